@@ -151,7 +151,7 @@ async fn main() -> anyhow::Result<()> {
     // 示例9: 数据删除操作
     println!("🗑️ 删除最后一个温度数据点...");
     let last_timestamp = 1609459200 + 60 * 60; // 1小时后的时间戳
-    let deleted = db.delete("temperature_sensor_1", Some(last_timestamp)).await?;
+    let deleted = db.delete("temperature_sensor_1", Some(last_timestamp), false).await?;
     
     if deleted {
         println!("✅ 数据点删除成功");
@@ -189,7 +189,7 @@ async fn main() -> anyhow::Result<()> {
 
     // 示例13: 批量删除操作
     println!("🗑️ 删除整个湿度传感器系列...");
-    let series_deleted = db.delete("humidity_sensor_1", None).await?;
+    let series_deleted = db.delete("humidity_sensor_1", None, false).await?;
     
     if series_deleted {
         println!("✅ 湿度传感器系列删除成功");
@@ -272,7 +272,7 @@ let cpu_series: Vec<String> = all_series.iter()
 
 for series_key in cpu_series {
     println!("  🗑️ 正在删除系列: {}", series_key);
-    match db.delete(&series_key, None).await {
+    match db.delete(&series_key, None, false).await {
         Ok(deleted) => {
             if deleted {
                 deleted_series += 1;
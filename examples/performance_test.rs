@@ -327,7 +327,7 @@ async fn main() -> anyhow::Result<()> {
     
     for i in 0..delete_count {
         let timestamp = start_timestamp + i;
-        let _deleted = db.delete("perf_single", Some(timestamp)).await?;
+        let _deleted = db.delete("perf_single", Some(timestamp), false).await?;
     }
     
     let duration = start.elapsed();
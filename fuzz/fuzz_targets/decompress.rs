@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use timeseries_db::db::compression::fuzz::check_decoder;
+
+fuzz_target!(|data: &[u8]| {
+    check_decoder(data);
+});
@@ -0,0 +1,341 @@
+use std::sync::Arc;
+
+use serde::ser::{self, Serialize, SerializeMap, SerializeStruct, Serializer};
+use serde_json::Value;
+
+use super::models::{SeriesFirstSeen, SeriesInfo, SeriesListResponse};
+use crate::db::{StaleSeries, StatsSummary};
+
+// 支持 ?fields= 的响应结构体。FIELDS 为序列化后的顶层字段名（已应用rename），
+// 结构体增加或改名字段时同步修改，测试中检查与序列化结果一致
+pub trait ResponseFields: Serialize {
+    const FIELDS: &'static [&'static str];
+}
+
+impl ResponseFields for StatsSummary {
+    const FIELDS: &'static [&'static str] = &[
+        "computed_at",
+        "total_series",
+        "total_points",
+        "memtable_points",
+        "total_compressed_bytes",
+        "sstable_count",
+        "ingested_points",
+        "new_series_today",
+        "top_series",
+        "sstables_updated_at",
+        "notes",
+    ];
+}
+
+impl ResponseFields for StaleSeries {
+    const FIELDS: &'static [&'static str] = &["series_key", "last_timestamp", "last_value"];
+}
+
+impl ResponseFields for SeriesFirstSeen {
+    const FIELDS: &'static [&'static str] = &["series_key", "first_seen"];
+}
+
+impl ResponseFields for SeriesInfo {
+    const FIELDS: &'static [&'static str] = &[
+        "series_key",
+        "count",
+        "min_timestamp",
+        "max_timestamp",
+        "last_timestamp",
+        "resolution",
+        "min_value",
+        "max_value",
+        "tags",
+    ];
+}
+
+impl ResponseFields for SeriesListResponse {
+    const FIELDS: &'static [&'static str] = &["series", "count"];
+}
+
+// 响应的字段选择，?fields=a,b 只返回列出的顶层字段，不指定时返回全部字段。
+// 序列化时直接跳过没有选择的字段，不会先生成完整的响应
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FieldSelection {
+    fields: Option<Arc<[String]>>,
+}
+
+impl FieldSelection {
+    // valid 为可选的字段名，包含未知字段时返回列出可选字段的错误
+    pub fn parse(fields: Option<&str>, valid: &[&str]) -> Result<Self, String> {
+        let Some(fields) = fields else {
+            return Ok(Self::default());
+        };
+        let mut selected: Vec<String> = Vec::new();
+        for field in fields.split(',').map(str::trim).filter(|field| !field.is_empty()) {
+            if !valid.contains(&field) {
+                return Err(format!("未知的字段 {:?}，可选字段: {}", field, valid.join(", ")));
+            }
+            if !selected.iter().any(|existing| existing == field) {
+                selected.push(field.to_string());
+            }
+        }
+        if selected.is_empty() {
+            return Err(format!("fields 不能为空，可选字段: {}", valid.join(", ")));
+        }
+        Ok(Self { fields: Some(selected.into()) })
+    }
+
+    // 可选字段为响应结构体 T 的 FIELDS
+    pub fn for_model<T: ResponseFields>(fields: Option<&str>) -> Result<Self, String> {
+        Self::parse(fields, T::FIELDS)
+    }
+
+    // 可选字段为JSON对象已有的键
+    pub fn for_object(fields: Option<&str>, object: &Value) -> Result<Self, String> {
+        let valid: Vec<&str> = object
+            .as_object()
+            .map(|object| object.keys().map(String::as_str).collect())
+            .unwrap_or_default();
+        Self::parse(fields, &valid)
+    }
+
+    // 序列化时只输出选择的字段，value 不是结构体或对象时原样输出
+    pub fn apply<T: Serialize>(&self, value: T) -> Selected<T> {
+        Selected { value, fields: self.fields.clone() }
+    }
+
+    pub fn apply_each<T: Serialize>(&self, items: Vec<T>) -> Vec<Selected<T>> {
+        items.into_iter().map(|item| self.apply(item)).collect()
+    }
+}
+
+// 按字段选择序列化的值
+#[derive(Debug)]
+pub struct Selected<T> {
+    value: T,
+    fields: Option<Arc<[String]>>,
+}
+
+impl<T: Serialize> Serialize for Selected<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match &self.fields {
+            Some(fields) => self.value.serialize(FieldFilter { inner: serializer, fields }),
+            None => self.value.serialize(serializer),
+        }
+    }
+}
+
+// 只过滤顶层结构体（或 flatten 后的映射、JSON对象）的字段，字段的值和其他类型原样交给内层
+struct FieldFilter<'a, S> {
+    inner: S,
+    fields: &'a [String],
+}
+
+impl<'a, S: Serializer> Serializer for FieldFilter<'a, S> {
+    type Ok = S::Ok;
+    type Error = S::Error;
+    type SerializeSeq = S::SerializeSeq;
+    type SerializeTuple = S::SerializeTuple;
+    type SerializeTupleStruct = S::SerializeTupleStruct;
+    type SerializeTupleVariant = S::SerializeTupleVariant;
+    type SerializeMap = FilteredMap<'a, S::SerializeMap>;
+    type SerializeStruct = FilteredStruct<'a, S::SerializeStruct>;
+    type SerializeStructVariant = S::SerializeStructVariant;
+
+    fn serialize_bool(self, v: bool) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_bool(v)
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_i8(v)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_i16(v)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_i32(v)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_i64(v)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_u8(v)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_u16(v)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_u32(v)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_u64(v)
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_f32(v)
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_f64(v)
+    }
+
+    fn serialize_char(self, v: char) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_char(v)
+    }
+
+    fn serialize_str(self, v: &str) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_str(v)
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_bytes(v)
+    }
+
+    fn serialize_none(self) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_none()
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_some(value)
+    }
+
+    fn serialize_unit(self) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_unit()
+    }
+
+    fn serialize_unit_struct(self, name: &'static str) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_unit_struct(name)
+    }
+
+    fn serialize_unit_variant(self, name: &'static str, index: u32, variant: &'static str) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_unit_variant(name, index, variant)
+    }
+
+    // serde(transparent) 之外的新类型包装同样按内层的字段过滤
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _name: &'static str, value: &T) -> Result<S::Ok, S::Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_newtype_variant(name, index, variant, value)
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<S::SerializeSeq, S::Error> {
+        self.inner.serialize_seq(len)
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<S::SerializeTuple, S::Error> {
+        self.inner.serialize_tuple(len)
+    }
+
+    fn serialize_tuple_struct(self, name: &'static str, len: usize) -> Result<S::SerializeTupleStruct, S::Error> {
+        self.inner.serialize_tuple_struct(name, len)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<S::SerializeTupleVariant, S::Error> {
+        self.inner.serialize_tuple_variant(name, index, variant, len)
+    }
+
+    // 带 flatten 字段的结构体和JSON对象按映射序列化，事先不知道保留几个键
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, S::Error> {
+        Ok(FilteredMap { inner: self.inner.serialize_map(None)?, fields: self.fields, skip_value: false })
+    }
+
+    fn serialize_struct(self, name: &'static str, len: usize) -> Result<Self::SerializeStruct, S::Error> {
+        Ok(FilteredStruct { inner: self.inner.serialize_struct(name, len.min(self.fields.len()))?, fields: self.fields })
+    }
+
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<S::SerializeStructVariant, S::Error> {
+        self.inner.serialize_struct_variant(name, index, variant, len)
+    }
+
+    fn is_human_readable(&self) -> bool {
+        self.inner.is_human_readable()
+    }
+}
+
+struct FilteredStruct<'a, S> {
+    inner: S,
+    fields: &'a [String],
+}
+
+impl<S: SerializeStruct> SerializeStruct for FilteredStruct<'_, S> {
+    type Ok = S::Ok;
+    type Error = S::Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), S::Error> {
+        if self.fields.iter().any(|field| field == key) {
+            self.inner.serialize_field(key, value)
+        } else {
+            self.inner.skip_field(key)
+        }
+    }
+
+    fn skip_field(&mut self, key: &'static str) -> Result<(), S::Error> {
+        self.inner.skip_field(key)
+    }
+
+    fn end(self) -> Result<S::Ok, S::Error> {
+        self.inner.end()
+    }
+}
+
+struct FilteredMap<'a, S> {
+    inner: S,
+    fields: &'a [String],
+    // 上一个键没有选择，跳过对应的值
+    skip_value: bool,
+}
+
+impl<S: SerializeMap> SerializeMap for FilteredMap<'_, S> {
+    type Ok = S::Ok;
+    type Error = S::Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), S::Error> {
+        let selected = match serde_json::to_value(key) {
+            Ok(Value::String(key)) => self.fields.contains(&key),
+            Ok(_) => false,
+            Err(e) => return Err(ser::Error::custom(e)),
+        };
+        self.skip_value = !selected;
+        if selected {
+            self.inner.serialize_key(key)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), S::Error> {
+        if self.skip_value {
+            Ok(())
+        } else {
+            self.inner.serialize_value(value)
+        }
+    }
+
+    fn end(self) -> Result<S::Ok, S::Error> {
+        self.inner.end()
+    }
+}
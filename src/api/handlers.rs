@@ -1,219 +1,2154 @@
 use axum::{
-    extract::{Path, Query, State},
-    response::Json,
+    body::{Body, Bytes},
+    extract::{Extension, Path, Query, Request, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware::Next,
+    response::{sse::{Event, KeepAlive, Sse}, IntoResponse, Json, Response},
 };
+use futures_util::StreamExt;
 use serde_json::Value;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
 
-use crate::db::{TimeSeriesDB, DataPoint};
+use crate::db::{
+    TimeSeriesDB, DataPoint, AggregateFn, TimeWindow, DEFAULT_HISTOGRAM_BOUNDS, DbError, DiskLevel, RetentionPolicy, RetentionReport, BoundsReport, ImportReport, BatchUpdateReport, BlockIngestReport, EncodedBlock, parse_relative_duration, parse_duration_expr, parse_time_bound, ResolvedRange, TimeRange, SSTableInfo, SSTableDetail,
+    CompactionOptions, CompactionReport, QuotaPolicy, QuotaReport, OperationInfo, CompressionReport, OverlapReport, SSTableRelocation, StaleSeries, StatsSummary, DownsamplePolicy, PrefixStats, PrefixDeleteReport, Annotation, NewAnnotation, JobInfo, OperationKind, ChangeEvent, ChangeFilter, TagFilter, parse_tag_condition, QueryResult,
+};
 use super::models::{
-    CreateDataPointRequest, UpdateDataPointRequest, QueryRequest, 
-    ApiResponse, DataPointResponse, SeriesListResponse, CompactRequest
+    CreateDataPointRequest, UpdateDataPointRequest, ReplaceSeriesRequest, ReplaceSeriesResponse, QueryRequest, 
+    ApiResponse, DataPointResponse, SeriesListResponse, CompactRequest,
+    AggregateAcrossResponse, WriteParams, TruncateRequest, StreamIngestReport, CrossingsRequest, CrossingsResponse,
+    DownsampleRequest, DownsampleResponse, DownsampleBuckets,
+    NewSeriesRequest, NewSeriesResponse, SeriesFirstSeen, StaleSeriesResponse, FieldsParams, SeriesInfo, SeriesInfoParams, SeriesListParams,
+    HistogramRequest, HistogramResponse, UpdateSeriesTagsRequest, GapsRequest, GapsResponse, ChunksRequest, ChunksResponse, DerivativeRequest, DerivativeResponse, DerivativePoint, IntegralRequest, IntegralResponse, WindowsRequest, WindowsResponse, BodyTooLarge, QuerySort,
+    BatchInsertResult, BatchInsertError, SeriesInsertCount, WarmupRequest, WarmupResponse,
+    MeasurementAggregateRequest, MeasurementAggregateResponse, ImportParams, BatchUpdateRequest, EnvelopeParams,
+    DeleteParams, PrefixParams, CreateAnnotationRequest, AnnotationsRequest, ProtectedSeriesRequest, TruncateResponse, AckLevel, PatchSeriesTagsRequest, RelocateRequest, SensitiveTagsRequest, SensitiveTagsResponse,
 };
+use super::redaction::{TagView, Visible, VisibleTags, REDACTED_TAG_VALUE};
+use super::fields::{FieldSelection, Selected};
+use super::tenant::{unscoped, Tenant};
+use crate::server_config::Config;
 
 pub type AppState = TimeSeriesDB;
 
-// 创建数据点
-pub async fn create_datapoint(
+// NDJSON流式写入时单行的最大长度，防止没有换行的请求体耗尽内存
+const MAX_NDJSON_LINE_BYTES: usize = 64 * 1024;
+
+const STREAM_ENDPOINT: &str = "/api/v1/datapoints/stream";
+
+// 批量写入响应中最多列出的失败元素数量，failed 仍为准确的总数
+const MAX_BATCH_ERRORS: usize = 100;
+
+// 导出归档时每个响应数据块的大小，以及最多缓冲的块数
+const EXPORT_CHUNK_BYTES: usize = 64 * 1024;
+const EXPORT_CHANNEL_CHUNKS: usize = 16;
+
+// 根据引擎错误类型选择HTTP状态码
+fn error_status(error: &std::io::Error) -> StatusCode {
+    match DbError::from_io(error) {
+        Some(DbError::DiskFull { .. }) => StatusCode::INSUFFICIENT_STORAGE,
+        Some(DbError::TagDrift { .. }) => StatusCode::CONFLICT,
+        Some(DbError::InvalidTimeRange { .. }) => StatusCode::BAD_REQUEST,
+        Some(DbError::DatabaseNotEmpty { .. }) => StatusCode::CONFLICT,
+        Some(DbError::QuotaExceeded { .. }) => StatusCode::TOO_MANY_REQUESTS,
+        Some(DbError::SeriesProtected { .. }) => StatusCode::FORBIDDEN,
+        Some(DbError::UnknownDataDir { .. }) => StatusCode::BAD_REQUEST,
+        Some(DbError::SSTableModified { .. }) => StatusCode::CONFLICT,
+        Some(DbError::ResultTooLarge { .. }) => StatusCode::PAYLOAD_TOO_LARGE,
+        Some(DbError::ClockBeforeEpoch { .. }) => StatusCode::INTERNAL_SERVER_ERROR,
+        Some(DbError::InvalidDataPoint { .. }) => StatusCode::BAD_REQUEST,
+        Some(DbError::InvalidAnnotation { .. }) => StatusCode::BAD_REQUEST,
+        Some(DbError::MaintenanceBusy { .. }) => StatusCode::CONFLICT,
+        None => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+// 将请求中的时间戳转换为数据库内部精度
+fn to_internal_timestamp(db: &AppState, timestamp: u64, params: &WriteParams) -> Result<u64, String> {
+    let precision = params.precision.unwrap_or(db.timestamp_precision());
+    precision
+        .convert(timestamp, db.timestamp_precision())
+        .ok_or_else(|| format!(
+            "时间戳 {}{} 转换为 {} 精度时溢出",
+            timestamp, precision, db.timestamp_precision()
+        ))
+}
+
+// ack=flushed 时等待本次请求之前的所有写入落盘
+async fn wait_for_ack(db: &AppState, params: &WriteParams) -> Result<(), String> {
+    if params.ack == AckLevel::Flushed {
+        db.wait_durable(db.write_sequence())
+            .await
+            .map_err(|e| format!("数据已写入内存表，但刷新到磁盘失败: {}", e))?;
+    }
+    Ok(())
+}
+
+// 创建数据点
+pub async fn create_datapoint(
+    State(db): State<AppState>,
+    Query(params): Query<WriteParams>,
+    headers: HeaderMap,
+    Json(request): Json<CreateDataPointRequest>,
+) -> (StatusCode, Json<ApiResponse<String>>) {
+    let view = TagView::from_headers(&db, &headers);
+    let series_key = Tenant::from_headers(&db, &headers).scope(&request.series_key);
+    let tags = request.tags.unwrap_or_default();
+    let timestamp = match to_internal_timestamp(&db, request.timestamp, &params) {
+        Ok(ts) => ts,
+        Err(e) => return (StatusCode::BAD_REQUEST, Json(ApiResponse::error(e))),
+    };
+    
+    let datapoint = DataPoint {
+        timestamp,
+        value: request.value,
+        tags,
+    };
+
+    if let Err(e) = db.insert(series_key.clone(), datapoint).await {
+        tracing::error!("创建数据点失败: {}", e);
+        return (error_status(&e), Json(ApiResponse::error(format!("创建数据点失败: {}", view.error_message(&db, &e)))));
+    }
+    match wait_for_ack(&db, &params).await {
+        Ok(_) => (StatusCode::OK, Json(ApiResponse {
+            point_count: params.return_count.then(|| db.series_point_count(&series_key)),
+            ..ApiResponse::success(format!(
+                "数据点已添加到系列: {} (时间戳: {})",
+                unscoped(&series_key), timestamp
+            ))
+        })),
+        Err(e) => {
+            tracing::error!("{}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ApiResponse::error(e)))
+        }
+    }
+}
+
+// 批量创建数据点：逐个解析JSON数组中的元素并立即写入，
+// 内存占用只与单个元素长度和内存表阈值有关，请求体总大小受 max_request_body_bytes 限制
+pub async fn create_datapoints_batch(
+    State(db): State<AppState>,
+    Query(params): Query<WriteParams>,
+    headers: HeaderMap,
+    body: Body,
+) -> Response {
+    let view = TagView::from_headers(&db, &headers);
+    let tenant = Tenant::from_headers(&db, &headers);
+    let max_body_bytes = db.config().max_request_body_bytes;
+    let mut splitter = JsonArraySplitter::default();
+    let mut stream = body.into_data_stream();
+    let mut received = 0usize;
+    let mut result = BatchInsertResult::default();
+    let mut per_series: BTreeMap<String, usize> = BTreeMap::new();
+
+    while let Some(chunk) = stream.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                tracing::error!("读取批量请求体失败: {}", e);
+                return (StatusCode::BAD_REQUEST, Json(ApiResponse::<String>::error(format!(
+                    "读取请求体失败: {}，已写入 {} 个", e, result.succeeded
+                )))).into_response();
+            }
+        };
+        received += chunk.len();
+        if received > max_body_bytes {
+            tracing::warn!("批量请求体超过 {} 字节，已写入 {} 个", max_body_bytes, result.succeeded);
+            return body_too_large(max_body_bytes);
+        }
+
+        let elements = match splitter.feed(&chunk) {
+            Ok(elements) => elements,
+            Err(e) => {
+                return (StatusCode::BAD_REQUEST, Json(ApiResponse::<String>::error(format!(
+                    "{}，已写入 {} 个", e, result.succeeded
+                )))).into_response();
+            }
+        };
+
+        for element in elements {
+            if db.disk_level() >= DiskLevel::Low {
+                // 磁盘空间不足时后续写入必然失败，直接返回已完成的部分
+                let message = format!(
+                    "磁盘空间不足，批量写入中止: 成功 {} 个，失败 {} 个",
+                    result.succeeded, result.failed
+                );
+                let response = ApiResponse {
+                    data: Some(finish_batch_result(result, per_series)),
+                    ..ApiResponse::error(message)
+                };
+                return (StatusCode::INSUFFICIENT_STORAGE, Json(response)).into_response();
+            }
+
+            let index = result.total;
+            result.total += 1;
+            match insert_json_datapoint(&db, &element, &params, view, &tenant).await {
+                Ok(series_key) => {
+                    result.succeeded += 1;
+                    *per_series.entry(series_key).or_default() += 1;
+                }
+                Err(e) => {
+                    tracing::error!("批量创建第 {} 个数据点失败: {}", index, e);
+                    result.failed += 1;
+                    if result.errors.len() < MAX_BATCH_ERRORS {
+                        result.errors.push(BatchInsertError { index, message: e });
+                    }
+                }
+            }
+        }
+    }
+
+    if !splitter.is_complete() {
+        return (StatusCode::BAD_REQUEST, Json(ApiResponse::<String>::error(format!(
+            "JSON数组不完整，已写入 {} 个", result.succeeded
+        )))).into_response();
+    }
+
+    if let Err(e) = wait_for_ack(&db, &params).await {
+        tracing::error!("{}", e);
+        let response = ApiResponse {
+            data: Some(finish_batch_result(result, per_series)),
+            ..ApiResponse::error(e)
+        };
+        return (StatusCode::INTERNAL_SERVER_ERROR, Json(response)).into_response();
+    }
+    let message = format!("批量创建完成: 成功 {} 个，失败 {} 个", result.succeeded, result.failed);
+    let response = ApiResponse {
+        message,
+        ..ApiResponse::success(finish_batch_result(result, per_series))
+    };
+    (StatusCode::OK, Json(response)).into_response()
+}
+
+fn finish_batch_result(mut result: BatchInsertResult, per_series: BTreeMap<String, usize>) -> BatchInsertResult {
+    result.per_series = per_series
+        .into_iter()
+        .map(|(series_key, count)| SeriesInsertCount { series_key, count })
+        .collect();
+    result
+}
+
+// 按顶层元素切分JSON数组，只跟踪字符串和嵌套层级，元素内容交给serde_json解析
+#[derive(Debug, Default)]
+struct JsonArraySplitter {
+    started: bool,
+    finished: bool,
+    depth: usize,
+    in_string: bool,
+    escaped: bool,
+    element: Vec<u8>,
+}
+
+impl JsonArraySplitter {
+    fn feed(&mut self, chunk: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+        let mut elements = Vec::new();
+        for &byte in chunk {
+            if !self.started || self.finished {
+                if byte.is_ascii_whitespace() {
+                    continue;
+                }
+                if !self.started && byte == b'[' {
+                    self.started = true;
+                    continue;
+                }
+                return Err("请求体必须是单个JSON数组".to_string());
+            }
+
+            if self.in_string {
+                if self.escaped {
+                    self.escaped = false;
+                } else if byte == b'\\' {
+                    self.escaped = true;
+                } else if byte == b'"' {
+                    self.in_string = false;
+                }
+            } else if self.depth == 0 && (byte == b',' || byte == b']') {
+                let element = std::mem::take(&mut self.element);
+                if byte == b']' {
+                    self.finished = true;
+                }
+                // "[]" 的结束符前没有元素
+                if byte == b',' || !element.trim_ascii().is_empty() {
+                    elements.push(element);
+                }
+                continue;
+            } else {
+                match byte {
+                    b'"' => self.in_string = true,
+                    b'{' | b'[' => self.depth += 1,
+                    b'}' | b']' => self.depth = self.depth.saturating_sub(1),
+                    _ => {}
+                }
+            }
+
+            self.element.push(byte);
+            if self.element.len() > MAX_NDJSON_LINE_BYTES {
+                return Err(format!("数组元素超过 {} 字节", MAX_NDJSON_LINE_BYTES));
+            }
+        }
+        Ok(elements)
+    }
+
+    fn is_complete(&self) -> bool {
+        self.finished
+    }
+}
+
+// 请求体过大时返回413，并提示改用流式写入接口
+fn body_too_large(max_body_bytes: usize) -> Response {
+    let message = format!(
+        "请求体超过 {} 字节上限，大批量数据请改用 POST {} 按NDJSON流式写入",
+        max_body_bytes, STREAM_ENDPOINT
+    );
+    let response = ApiResponse {
+        data: Some(BodyTooLarge {
+            max_body_bytes,
+            stream_endpoint: STREAM_ENDPOINT.to_string(),
+        }),
+        ..ApiResponse::error(message)
+    };
+    (StatusCode::PAYLOAD_TOO_LARGE, Json(response)).into_response()
+}
+
+// 中间件：写入接口的请求体大小限制。声明了 Content-Length 的请求在读取前直接拒绝，
+// 分块传输的请求由各接口在读取时检查；Json 等提取器超限时返回的纯文本413改为统一的响应结构
+pub async fn limit_request_body(
+    State(db): State<AppState>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let max_body_bytes = db.config().max_request_body_bytes;
+    let content_length = req
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<usize>().ok());
+    if content_length.is_some_and(|len| len > max_body_bytes) {
+        tracing::warn!("{} {} 请求体超过 {} 字节，已拒绝", req.method(), req.uri(), max_body_bytes);
+        return body_too_large(max_body_bytes);
+    }
+    let response = next.run(req).await;
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/json"));
+    if response.status() == StatusCode::PAYLOAD_TOO_LARGE && !is_json {
+        return body_too_large(max_body_bytes);
+    }
+    response
+}
+
+// 中间件：查询接口的 ?envelope=false，成功时只返回 data 部分，partial/next_cursor 改为响应头，stats 不再返回。
+// 失败时保留完整的错误结构；没有设置状态码的失败响应多为参数错误，改为 400，避免被当作数据
+pub async fn unwrap_envelope(req: Request, next: Next) -> Response {
+    let params = match Query::<EnvelopeParams>::try_from_uri(req.uri()) {
+        Ok(Query(params)) => params,
+        Err(e) => {
+            return (StatusCode::BAD_REQUEST, Json(ApiResponse::<()>::error(format!("envelope参数无效: {}", e)))).into_response();
+        }
+    };
+    let response = next.run(req).await;
+    if params.envelope {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let bytes = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            tracing::error!("读取响应体失败: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let Ok(mut envelope) = serde_json::from_slice::<Value>(&bytes) else {
+        return Response::from_parts(parts, Body::from(bytes));
+    };
+
+    if envelope["success"] != Value::Bool(true) {
+        if parts.status.is_success() {
+            parts.status = StatusCode::BAD_REQUEST;
+        }
+        return Response::from_parts(parts, Body::from(bytes));
+    }
+    let range = envelope.get("range").cloned().unwrap_or_default();
+    let headers = [
+        ("x-partial", envelope.get("partial")),
+        ("x-next-cursor", envelope.get("next_cursor")),
+        ("x-range-start", range.get("start_time")),
+        ("x-range-end", range.get("end_time")),
+    ];
+    for (name, value) in headers {
+        if let Some(value) = value.filter(|v| !v.is_null()) {
+            if let Ok(value) = header::HeaderValue::from_str(&value.to_string()) {
+                parts.headers.insert(name, value);
+            }
+        }
+    }
+    parts.headers.remove(header::CONTENT_LENGTH);
+    (parts, Json(envelope["data"].take())).into_response()
+}
+
+// 流式批量写入：逐行解析NDJSON请求体并立即写入，内存表满时自动刷新，
+// 内存占用只与单行长度和内存表阈值有关
+pub async fn create_datapoints_stream(
+    State(db): State<AppState>,
+    Query(params): Query<WriteParams>,
+    headers: HeaderMap,
+    body: Body,
+) -> (StatusCode, Json<ApiResponse<StreamIngestReport>>) {
+    let view = TagView::from_headers(&db, &headers);
+    let tenant = Tenant::from_headers(&db, &headers);
+    let mut report = StreamIngestReport::default();
+    let mut stream = body.into_data_stream();
+    let mut buffer: Vec<u8> = Vec::new();
+    let mut line_number = 0usize;
+
+    loop {
+        let chunk = match stream.next().await {
+            Some(Ok(chunk)) => Some(chunk),
+            Some(Err(e)) => {
+                tracing::error!("读取NDJSON请求体失败: {}", e);
+                return (StatusCode::BAD_REQUEST, Json(ApiResponse::error(format!(
+                    "读取请求体失败: {}，已写入 {} 个", e, report.inserted
+                ))));
+            }
+            None => None,
+        };
+        let finished = chunk.is_none();
+        if let Some(chunk) = chunk {
+            buffer.extend_from_slice(&chunk);
+        }
+
+        // 处理缓冲区中所有完整的行，请求体结束时最后一行可以没有换行
+        loop {
+            let line_end = match buffer.iter().position(|b| *b == b'\n') {
+                Some(pos) => pos + 1,
+                None if finished && !buffer.is_empty() => buffer.len(),
+                None => break,
+            };
+            let line: Vec<u8> = buffer.drain(..line_end).collect();
+            line_number += 1;
+
+            let line = line.trim_ascii();
+            if line.is_empty() {
+                continue;
+            }
+
+            if db.disk_level() >= DiskLevel::Low {
+                let message = format!(
+                    "磁盘空间不足，流式写入在第 {} 行中止: 成功 {} 个，失败 {} 个",
+                    line_number, report.inserted, report.failed
+                );
+                return (StatusCode::INSUFFICIENT_STORAGE, Json(ApiResponse::error(message)));
+            }
+
+            if let Err(e) = insert_json_datapoint(&db, line, &params, view, &tenant).await {
+                tracing::error!("流式写入第 {} 行失败: {}", line_number, e);
+                report.failed += 1;
+                report.first_error.get_or_insert_with(|| format!("第 {} 行: {}", line_number, e));
+            } else {
+                report.inserted += 1;
+            }
+        }
+
+        if buffer.len() > MAX_NDJSON_LINE_BYTES {
+            return (StatusCode::PAYLOAD_TOO_LARGE, Json(ApiResponse::error(format!(
+                "第 {} 行超过 {} 字节，已写入 {} 个",
+                line_number + 1, MAX_NDJSON_LINE_BYTES, report.inserted
+            ))));
+        }
+
+        if finished {
+            break;
+        }
+    }
+
+    if let Err(e) = wait_for_ack(&db, &params).await {
+        tracing::error!("{}", e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, Json(ApiResponse { data: Some(report), ..ApiResponse::error(e) }));
+    }
+    tracing::info!("流式写入完成: 成功 {} 个，失败 {} 个", report.inserted, report.failed);
+    (StatusCode::OK, Json(ApiResponse::success(report)))
+}
+
+// 解析并写入单个数据点，成功时返回系列键
+async fn insert_json_datapoint(
+    db: &AppState,
+    json: &[u8],
+    params: &WriteParams,
+    view: TagView,
+    tenant: &Tenant,
+) -> Result<String, String> {
+    let request: CreateDataPointRequest = serde_json::from_slice(json)
+        .map_err(|e| format!("无效的JSON: {}", e))?;
+    let timestamp = to_internal_timestamp(db, request.timestamp, params)?;
+    let datapoint = DataPoint {
+        timestamp,
+        value: request.value,
+        tags: request.tags.unwrap_or_default(),
+    };
+    let series_key = tenant.scope(&request.series_key);
+    db.insert(series_key.clone(), datapoint).await.map_err(|e| view.error_message(db, &e))?;
+    Ok(series_key)
+}
+
+// 查询参数中的时间范围。start_time/end_time 为时间戳，或 now、now-1h 这样相对服务端当前时间的表达式；
+// last 如 "15m"，相当于 start_time=now-15m、end_time=now，不能与 start_time/end_time 同时使用。
+// 返回换算后的范围，用到相对时间时该范围需要在响应中返回
+fn query_time_range(
+    db: &TimeSeriesDB,
+    start_time: Option<&str>,
+    end_time: Option<&str>,
+    last: Option<&str>,
+    all: bool,
+) -> Result<(ResolvedRange, Option<ResolvedRange>), String> {
+    let range = match last {
+        Some(_) if start_time.is_some() || end_time.is_some() => {
+            return Err("last 不能与 start_time/end_time 同时指定".to_string());
+        }
+        Some(last) => TimeRange::last(parse_duration_expr(last).map_err(|e| format!("last: {}", e))?),
+        None => {
+            let bound = |name: &str, expr: Option<&str>| {
+                expr.map(parse_time_bound).transpose().map_err(|e| format!("{}: {}", name, e))
+            };
+            TimeRange::new(bound("start_time", start_time)?, bound("end_time", end_time)?)
+        }
+    };
+    let range = if all { range } else { db.default_time_range(range) };
+    let resolved = db.resolve_time_range(&range);
+    Ok((resolved, range.is_relative().then_some(resolved)))
+}
+
+// 查询数据点
+pub async fn query_datapoints(
+    State(db): State<AppState>,
+    Path(series_key): Path<String>,
+    Query(query): Query<QueryRequest>,
+    headers: HeaderMap,
+) -> (StatusCode, Json<ApiResponse<Vec<DataPointResponse>>>) {
+    query_matching_datapoints(db, series_key, query, TagFilter::default(), headers).await
+}
+
+// 查询数据点的路由入口，tag=key:value（可重复，条件之间为与）只返回标签满足条件的数据点
+pub async fn query_filtered_datapoints(
+    State(db): State<AppState>,
+    Path(series_key): Path<String>,
+    Query(query): Query<QueryRequest>,
+    Query(params): Query<Vec<(String, String)>>,
+    headers: HeaderMap,
+) -> (StatusCode, Json<ApiResponse<Vec<DataPointResponse>>>) {
+    let mut filter = TagFilter::new();
+    for (_, condition) in params.iter().filter(|(key, _)| key == "tag") {
+        if let Err(e) = filter.add_condition(condition) {
+            return (StatusCode::BAD_REQUEST, Json(ApiResponse::error(e)));
+        }
+    }
+    // 看不到敏感标签值的调用方不能按它过滤，否则可以逐个尝试出真实的值
+    if !TagView::from_headers(&db, &headers).unredacted() {
+        let sensitive = db.sensitive_tag_keys(&series_key);
+        if let Some(key) = filter.keys().find(|key| sensitive.contains(*key)) {
+            return (StatusCode::FORBIDDEN, Json(ApiResponse::error(format!("标签 {} 为敏感标签，不能用于过滤", key))));
+        }
+    }
+    query_matching_datapoints(db, series_key, query, filter, headers).await
+}
+
+async fn query_matching_datapoints(
+    db: AppState,
+    series_key: String,
+    query: QueryRequest,
+    filter: TagFilter,
+    headers: HeaderMap,
+) -> (StatusCode, Json<ApiResponse<Vec<DataPointResponse>>>) {
+    let view = TagView::from_headers(&db, &headers);
+    let (range, relative) = match query_time_range(&db, query.start_time.as_deref(), query.end_time.as_deref(), query.last.as_deref(), query.all) {
+        Ok(range) => range,
+        Err(e) => return (StatusCode::BAD_REQUEST, Json(ApiResponse::error(e))),
+    };
+    if query.limit == Some(0) {
+        return (StatusCode::BAD_REQUEST, Json(ApiResponse::error("limit必须大于0，不限制数量时请省略该参数".to_string())));
+    }
+    let annotations = if query.include_annotations {
+        match series_annotations(&db, &series_key, range, &Tenant::from_headers(&db, &headers), view) {
+            Ok(annotations) => Some(annotations),
+            Err(e) => return (query_error_status(&e), Json(ApiResponse::error(format!("查询注释失败: {}", e)))),
+        }
+    } else {
+        None
+    };
+    if let (Some(max_wait_ms), QuerySort::Timestamp) = (query.max_wait_ms, query.sort) {
+        let (status, Json(response)) =
+            query_datapoints_within(&db, &series_key, range, &query, Duration::from_millis(max_wait_ms), &filter, view).await;
+        let annotations = annotations.filter(|_| response.success);
+        return (status, Json(ApiResponse { range: relative, annotations, ..response }));
+    }
+
+    match db.query_range_filtered(&series_key, range.start_time, range.end_time, &filter).await {
+        Ok(QueryResult { mut datapoints, stats, reservation }) => {
+            if query.sort == QuerySort::ValueDesc {
+                // 稳定排序，值相同的点保持时间顺序
+                datapoints.sort_by(|a, b| b.value.total_cmp(&a.value));
+            }
+            if let Some(limit) = query.limit {
+                datapoints.truncate(limit);
+            }
+
+            let response_data = match datapoint_responses(&db, &series_key, datapoints, query.include_ingest_time, view).await {
+                Ok(response_data) => response_data,
+                Err(e) => {
+                    tracing::error!("查询写入时间失败: {}", e);
+                    return (error_status(&e), Json(ApiResponse::error(format!("查询写入时间失败: {}", e))));
+                }
+            };
+            
+            tracing::info!("查询系列 {} 返回 {} 个数据点", series_key, response_data.len());
+            (StatusCode::OK, Json(ApiResponse {
+                stats: query.stats.then_some(stats),
+                range: relative,
+                annotations,
+                reservation: Some(reservation),
+                ..ApiResponse::success(response_data)
+            }))
+        }
+        Err(e) => {
+            tracing::error!("查询数据点失败: {}", e);
+            (error_status(&e), Json(ApiResponse::error(format!("查询数据点失败: {}", e))))
+        }
+    }
+}
+
+// 查询响应附带的注释：系列的注释和全局注释。租户只能看到自己系列的注释
+fn series_annotations(
+    db: &TimeSeriesDB,
+    series_key: &str,
+    range: ResolvedRange,
+    tenant: &Tenant,
+    view: TagView,
+) -> std::io::Result<Vec<Visible<Annotation>>> {
+    let annotations = db.annotations(Some(series_key), &TimeRange::between(range.start_time, range.end_time))?;
+    Ok(annotations
+        .into_iter()
+        .filter(|annotation| annotation_visible(tenant, annotation))
+        .map(|annotation| visible_annotation(db, view, annotation))
+        .collect())
+}
+
+// 注释是引擎中的类型，返回给租户前去掉系列键的前缀，标签按所属系列的敏感标签隐藏
+fn visible_annotation(db: &TimeSeriesDB, view: TagView, mut annotation: Annotation) -> Visible<Annotation> {
+    let series_key = annotation.series_key.clone();
+    if let Some(series_key) = &mut annotation.series_key {
+        *series_key = unscoped(series_key).to_string();
+    }
+    view.annotation(db, series_key.as_deref(), annotation)
+}
+
+fn annotation_visible(tenant: &Tenant, annotation: &Annotation) -> bool {
+    match &annotation.series_key {
+        Some(series_key) => tenant.owns(series_key),
+        None => tenant.prefix().is_none(),
+    }
+}
+
+// 限时查询：超时后返回已读出的数据，并给出继续查询的 next_cursor
+async fn query_datapoints_within(
+    db: &TimeSeriesDB,
+    series_key: &str,
+    range: ResolvedRange,
+    query: &QueryRequest,
+    budget: Duration,
+    filter: &TagFilter,
+    view: TagView,
+) -> (StatusCode, Json<ApiResponse<Vec<DataPointResponse>>>) {
+    if !db.series_matches(series_key, filter) {
+        return (StatusCode::OK, Json(ApiResponse { partial: Some(false), ..ApiResponse::success(Vec::new()) }));
+    }
+    match db.query_range_within(series_key, range.start_time, range.end_time, budget).await {
+        Ok(mut result) => {
+            // 已读满 limit 个点时结果完整，不再需要继续读取
+            if let Some(limit) = query.limit.filter(|limit| result.datapoints.len() >= *limit) {
+                result.datapoints.truncate(limit);
+                result.next_cursor = None;
+            }
+            let partial = result.next_cursor.is_some();
+            let response_data = match datapoint_responses(db, series_key, result.datapoints, query.include_ingest_time, view).await {
+                Ok(response_data) => response_data,
+                Err(e) => {
+                    tracing::error!("查询写入时间失败: {}", e);
+                    return (error_status(&e), Json(ApiResponse::error(format!("查询写入时间失败: {}", e))));
+                }
+            };
+
+            if partial {
+                tracing::warn!("查询系列 {} 超过 {:?} 时间预算，返回部分结果 {} 个数据点", series_key, budget, response_data.len());
+            }
+            (StatusCode::OK, Json(ApiResponse {
+                partial: Some(partial),
+                next_cursor: result.next_cursor,
+                reservation: Some(result.reservation),
+                ..ApiResponse::success(response_data)
+            }))
+        }
+        Err(e) => {
+            tracing::error!("查询数据点失败: {}", e);
+            (error_status(&e), Json(ApiResponse::error(format!("查询数据点失败: {}", e))))
+        }
+    }
+}
+
+// 转换为响应格式，include_ingest_time 时按返回的数据点的时间范围查出写入时间
+async fn datapoint_responses(
+    db: &TimeSeriesDB,
+    series_key: &str,
+    datapoints: Vec<DataPoint>,
+    include_ingest_time: bool,
+    view: TagView,
+) -> std::io::Result<Vec<DataPointResponse>> {
+    let ingest_times = match (datapoints.iter().map(|dp| dp.timestamp).min(), datapoints.iter().map(|dp| dp.timestamp).max()) {
+        (Some(start), Some(end)) if include_ingest_time => db.query_ingest_times(series_key, Some(start), Some(end)).await?,
+        _ => BTreeMap::new(),
+    };
+    Ok(view.datapoints(db, series_key, datapoints, &ingest_times))
+}
+
+// 查询失败的状态码，参数无效时为400
+fn query_error_status(error: &std::io::Error) -> StatusCode {
+    match error.kind() {
+        std::io::ErrorKind::InvalidInput => StatusCode::BAD_REQUEST,
+        _ => error_status(error),
+    }
+}
+
+// 单系列降采样，fn=envelope 时每个桶返回 min/max/avg/first/last
+pub async fn query_downsample(
+    State(db): State<AppState>,
+    Path(series_key): Path<String>,
+    Query(query): Query<DownsampleRequest>,
+) -> (StatusCode, Json<ApiResponse<DownsampleResponse>>) {
+    if query.interval == 0 {
+        return (StatusCode::BAD_REQUEST, Json(ApiResponse::error("interval参数必须为正整数".to_string())));
+    }
+    let (range, relative) = match query_time_range(&db, query.start_time.as_deref(), query.end_time.as_deref(), query.last.as_deref(), query.all) {
+        Ok(range) => range,
+        Err(e) => return (StatusCode::BAD_REQUEST, Json(ApiResponse::error(e))),
+    };
+
+    let result = if query.function.eq_ignore_ascii_case("envelope") {
+        db.downsample_envelope(&series_key, range.start_time, range.end_time, query.interval, query.fill)
+            .await
+            .map(DownsampleBuckets::Envelope)
+    } else {
+        let aggregate_fn = match query.function.parse::<AggregateFn>() {
+            Ok(f) => f,
+            Err(e) => return (StatusCode::BAD_REQUEST, Json(ApiResponse::error(e))),
+        };
+        db.downsample(&series_key, range.start_time, range.end_time, query.interval, aggregate_fn, query.fill)
+            .await
+            .map(DownsampleBuckets::Values)
+    };
+
+    match result {
+        Ok(buckets) => (StatusCode::OK, Json(ApiResponse {
+            range: relative,
+            ..ApiResponse::success(DownsampleResponse {
+                series_key,
+                function: query.function.to_ascii_lowercase(),
+                interval: query.interval,
+                fill: query.fill,
+                buckets,
+            })
+        })),
+        Err(e) => {
+            tracing::error!("降采样查询失败: {}", e);
+            (query_error_status(&e), Json(ApiResponse::error(format!("降采样查询失败: {}", e))))
+        }
+    }
+}
+
+// 累积直方图，输出格式兼容Prometheus的 histogram_quantile
+pub async fn query_histogram(
+    State(db): State<AppState>,
+    Path(series_key): Path<String>,
+    Query(query): Query<HistogramRequest>,
+) -> (StatusCode, Json<ApiResponse<HistogramResponse>>) {
+    let bounds = match &query.le {
+        Some(le) => match le.split(',').map(|b| b.trim().parse::<f64>()).collect::<Result<Vec<_>, _>>() {
+            Ok(bounds) => bounds,
+            Err(_) => return (StatusCode::BAD_REQUEST, Json(ApiResponse::error(format!("无效的le边界: {}", le)))),
+        },
+        None => DEFAULT_HISTOGRAM_BOUNDS.to_vec(),
+    };
+    let (range, relative) = match query_time_range(&db, query.start_time.as_deref(), query.end_time.as_deref(), query.last.as_deref(), query.all) {
+        Ok(range) => range,
+        Err(e) => return (StatusCode::BAD_REQUEST, Json(ApiResponse::error(e))),
+    };
+
+    match db.histogram(&series_key, range.start_time, range.end_time, bounds).await {
+        Ok(histogram) => (StatusCode::OK, Json(ApiResponse {
+            range: relative,
+            ..ApiResponse::success(HistogramResponse {
+                series_key,
+                buckets: histogram.buckets(),
+                sum: histogram.sum(),
+                count: histogram.count(),
+            })
+        })),
+        Err(e) => {
+            tracing::error!("直方图查询失败: {}", e);
+            (query_error_status(&e), Json(ApiResponse::error(format!("直方图查询失败: {}", e))))
+        }
+    }
+}
+
+// 阈值穿越查询，只返回穿越点及前一个点
+pub async fn query_crossings(
+    State(db): State<AppState>,
+    Path(series_key): Path<String>,
+    Query(query): Query<CrossingsRequest>,
+) -> (StatusCode, Json<ApiResponse<CrossingsResponse>>) {
+    let (range, relative) = match query_time_range(&db, query.start_time.as_deref(), query.end_time.as_deref(), query.last.as_deref(), query.all) {
+        Ok(range) => range,
+        Err(e) => return (StatusCode::BAD_REQUEST, Json(ApiResponse::error(e))),
+    };
+    match db
+        .find_crossings(&series_key, query.threshold, query.direction, range.start_time, range.end_time)
+        .await
+    {
+        Ok(crossings) => {
+            tracing::info!("查询系列 {} 阈值穿越 {} 次", series_key, crossings.len());
+            (StatusCode::OK, Json(ApiResponse {
+                range: relative,
+                ..ApiResponse::success(CrossingsResponse {
+                    series_key,
+                    threshold: query.threshold,
+                    direction: query.direction,
+                    crossings,
+                })
+            }))
+        }
+        Err(e) => {
+            tracing::error!("查询阈值穿越失败: {}", e);
+            (query_error_status(&e), Json(ApiResponse::error(format!("查询阈值穿越失败: {}", e))))
+        }
+    }
+}
+
+// 查询缺失数据的时间窗口
+pub async fn query_gaps(
+    State(db): State<AppState>,
+    Path(series_key): Path<String>,
+    Query(query): Query<GapsRequest>,
+) -> (StatusCode, Json<ApiResponse<GapsResponse>>) {
+    let (range, relative) = match query_time_range(&db, query.start_time.as_deref(), query.end_time.as_deref(), query.last.as_deref(), query.all) {
+        Ok(range) => range,
+        Err(e) => return (StatusCode::BAD_REQUEST, Json(ApiResponse::error(e))),
+    };
+    match db
+        .find_gaps(&series_key, query.expected_interval, range.start_time, range.end_time)
+        .await
+    {
+        Ok(gaps) => {
+            tracing::info!("查询系列 {} 发现 {} 个数据缺口", series_key, gaps.len());
+            (StatusCode::OK, Json(ApiResponse {
+                range: relative,
+                ..ApiResponse::success(GapsResponse {
+                    series_key,
+                    expected_interval: query.expected_interval,
+                    gaps,
+                })
+            }))
+        }
+        Err(e) => {
+            tracing::error!("查询数据缺口失败: {}", e);
+            (query_error_status(&e), Json(ApiResponse::error(format!("查询数据缺口失败: {}", e))))
+        }
+    }
+}
+
+// 按时间窗口汇总点数和首末时间戳，用于逐段加载长时间范围
+pub async fn query_chunks(
+    State(db): State<AppState>,
+    Path(series_key): Path<String>,
+    Query(query): Query<ChunksRequest>,
+) -> (StatusCode, Json<ApiResponse<ChunksResponse>>) {
+    let chunk = match parse_relative_duration(&query.chunk) {
+        Ok(duration) => db.timestamp_precision().from_duration(duration),
+        Err(e) => return (StatusCode::BAD_REQUEST, Json(ApiResponse::error(e))),
+    };
+    let (start_time, end_time, relative) =
+        match query_time_range(&db, query.start_time.as_deref(), query.end_time.as_deref(), query.last.as_deref(), query.all) {
+            Ok((ResolvedRange { start_time: Some(start), end_time: Some(end) }, relative)) => (start, end, relative),
+            Ok(_) => {
+                return (StatusCode::BAD_REQUEST, Json(ApiResponse::error("需要指定 start_time 和 end_time，或者 last".to_string())));
+            }
+            Err(e) => return (StatusCode::BAD_REQUEST, Json(ApiResponse::error(e))),
+        };
+    match db.series_chunks(&series_key, start_time, end_time, chunk).await {
+        Ok(chunks) => {
+            tracing::info!("系列 {} 划分为 {} 个时间窗口", series_key, chunks.len());
+            (StatusCode::OK, Json(ApiResponse {
+                range: relative,
+                ..ApiResponse::success(ChunksResponse { series_key, chunk, chunks })
+            }))
+        }
+        Err(e) => {
+            tracing::error!("查询时间窗口失败: {}", e);
+            (query_error_status(&e), Json(ApiResponse::error(format!("查询时间窗口失败: {}", e))))
+        }
+    }
+}
+
+// 逐点变化率查询
+pub async fn query_derivative(
+    State(db): State<AppState>,
+    Path(series_key): Path<String>,
+    Query(query): Query<DerivativeRequest>,
+) -> (StatusCode, Json<ApiResponse<DerivativeResponse>>) {
+    let (range, relative) = match query_time_range(&db, query.start_time.as_deref(), query.end_time.as_deref(), query.last.as_deref(), query.all) {
+        Ok(range) => range,
+        Err(e) => return (StatusCode::BAD_REQUEST, Json(ApiResponse::error(e))),
+    };
+    match db.derivative(&series_key, range.start_time, range.end_time).await {
+        Ok(points) => {
+            let points = points
+                .into_iter()
+                .map(|(timestamp, rate)| DerivativePoint { timestamp, rate })
+                .collect();
+            (StatusCode::OK, Json(ApiResponse {
+                range: relative,
+                ..ApiResponse::success(DerivativeResponse { series_key, points })
+            }))
+        }
+        Err(e) => {
+            tracing::error!("查询变化率失败: {}", e);
+            (query_error_status(&e), Json(ApiResponse::error(format!("查询变化率失败: {}", e))))
+        }
+    }
+}
+
+// 数值对时间的积分（曲线下面积），如由功率求能耗
+pub async fn query_integral(
+    State(db): State<AppState>,
+    Path(series_key): Path<String>,
+    Query(query): Query<IntegralRequest>,
+) -> (StatusCode, Json<ApiResponse<IntegralResponse>>) {
+    let (range, relative) = match query_time_range(&db, query.start_time.as_deref(), query.end_time.as_deref(), query.last.as_deref(), query.all) {
+        Ok(range) => range,
+        Err(e) => return (StatusCode::BAD_REQUEST, Json(ApiResponse::error(e))),
+    };
+    match db.integral(&series_key, range.start_time, range.end_time).await {
+        Ok(integral) => (StatusCode::OK, Json(ApiResponse {
+            range: relative,
+            ..ApiResponse::success(IntegralResponse { series_key, integral })
+        })),
+        Err(e) => {
+            tracing::error!("查询积分失败: {}", e);
+            (query_error_status(&e), Json(ApiResponse::error(format!("查询积分失败: {}", e))))
+        }
+    }
+}
+
+// 一次查询多个时间窗口，如比较多天中的同一时段。只扫描一遍覆盖所有窗口的范围
+pub async fn query_windows(
+    State(db): State<AppState>,
+    Path(series_key): Path<String>,
+    Json(request): Json<WindowsRequest>,
+) -> (StatusCode, Json<ApiResponse<WindowsResponse>>) {
+    let windows = request
+        .windows
+        .iter()
+        .map(|&(start_time, end_time)| TimeWindow { start_time, end_time })
+        .collect();
+    match db.query_windows(&series_key, windows, request.function).await {
+        Ok(windows) => (StatusCode::OK, Json(ApiResponse::success(WindowsResponse {
+            series_key,
+            function: request.function,
+            windows,
+        }))),
+        Err(e) => {
+            tracing::error!("多窗口查询失败: {}", e);
+            (query_error_status(&e), Json(ApiResponse::error(format!("多窗口查询失败: {}", e))))
+        }
+    }
+}
+
+// 跨系列聚合查询，参数: match[]=key=value（可重复）、interval、fn、start_time、end_time、last
+pub async fn aggregate_across(
+    State(db): State<AppState>,
+    Query(params): Query<Vec<(String, String)>>,
+) -> (StatusCode, Json<ApiResponse<AggregateAcrossResponse>>) {
+    let mut matchers = BTreeMap::new();
+    let mut interval = None;
+    let mut function = "avg".to_string();
+    let mut start_time = None;
+    let mut end_time = None;
+    let mut last = None;
+    let mut all = false;
+
+    for (key, value) in params {
+        match key.as_str() {
+            "match[]" | "match" => match value.split_once('=') {
+                Some((k, v)) => {
+                    matchers.insert(k.to_string(), v.to_string());
+                }
+                None => {
+                    return (StatusCode::BAD_REQUEST, Json(ApiResponse::error(format!("无效的匹配条件: {}", value))));
+                }
+            },
+            "interval" => interval = value.parse::<u64>().ok(),
+            "fn" => function = value,
+            "start_time" => start_time = Some(value),
+            "end_time" => end_time = Some(value),
+            "last" => last = Some(value),
+            "all" => all = value == "true",
+            _ => {}
+        }
+    }
+
+    let Some(interval) = interval.filter(|i| *i > 0) else {
+        return (StatusCode::BAD_REQUEST, Json(ApiResponse::error("interval参数必须为正整数".to_string())));
+    };
+    let aggregate_fn = match function.parse::<AggregateFn>() {
+        Ok(f) => f,
+        Err(e) => return (StatusCode::BAD_REQUEST, Json(ApiResponse::error(e))),
+    };
+    let (range, relative) = match query_time_range(&db, start_time.as_deref(), end_time.as_deref(), last.as_deref(), all) {
+        Ok(range) => range,
+        Err(e) => return (StatusCode::BAD_REQUEST, Json(ApiResponse::error(e))),
+    };
+
+    match db.aggregate_across(&matchers, range.start_time, range.end_time, interval, aggregate_fn).await {
+        Ok((series, buckets)) => (StatusCode::OK, Json(ApiResponse {
+            range: relative,
+            ..ApiResponse::success(AggregateAcrossResponse {
+                function,
+                interval,
+                series,
+                buckets,
+            })
+        })),
+        Err(e) => {
+            tracing::error!("跨系列聚合失败: {}", e);
+            (query_error_status(&e), Json(ApiResponse::error(format!("跨系列聚合失败: {}", e))))
+        }
+    }
+}
+
+// 按度量聚合：度量下的每个系列分别降采样，返回系列到桶的映射
+pub async fn aggregate_measurement(
+    State(db): State<AppState>,
+    Path(measurement): Path<String>,
+    Query(query): Query<MeasurementAggregateRequest>,
+) -> (StatusCode, Json<ApiResponse<MeasurementAggregateResponse>>) {
+    if query.interval == 0 {
+        return (StatusCode::BAD_REQUEST, Json(ApiResponse::error("interval参数必须为正整数".to_string())));
+    }
+    let aggregate_fn = match query.function.parse::<AggregateFn>() {
+        Ok(f) => f,
+        Err(e) => return (StatusCode::BAD_REQUEST, Json(ApiResponse::error(e))),
+    };
+    let (range, relative) = match query_time_range(&db, query.start_time.as_deref(), query.end_time.as_deref(), query.last.as_deref(), query.all) {
+        Ok(range) => range,
+        Err(e) => return (StatusCode::BAD_REQUEST, Json(ApiResponse::error(e))),
+    };
+
+    match db
+        .aggregate_measurement(&measurement, range.start_time, range.end_time, query.interval, aggregate_fn, query.fill)
+        .await
+    {
+        Ok(series) => {
+            tracing::info!("度量 {} 聚合了 {} 个系列", measurement, series.len());
+            (StatusCode::OK, Json(ApiResponse {
+                range: relative,
+                ..ApiResponse::success(MeasurementAggregateResponse {
+                    measurement,
+                    function: query.function.to_ascii_lowercase(),
+                    interval: query.interval,
+                    series,
+                })
+            }))
+        }
+        Err(e) => {
+            tracing::error!("度量聚合失败: {}", e);
+            (query_error_status(&e), Json(ApiResponse::error(format!("度量聚合失败: {}", e))))
+        }
+    }
+}
+
+// 更新数据点
+pub async fn update_datapoint(
+    State(db): State<AppState>,
+    Path((series_key, timestamp)): Path<(String, u64)>,
+    Json(request): Json<UpdateDataPointRequest>,
+) -> Json<ApiResponse<String>> {
+    match db.update(&series_key, timestamp, request.value).await {
+        Ok(updated) => {
+            if updated {
+                tracing::info!("数据点已更新: {} at {} -> {}", series_key, timestamp, request.value);
+                Json(ApiResponse::success(format!(
+                    "数据点已更新: {} at {} -> {}",
+                    unscoped(&series_key), timestamp, request.value
+                )))
+            } else {
+                Json(ApiResponse::error(
+                    "未找到指定的数据点".to_string()
+                ))
+            }
+        }
+        Err(e) => {
+            tracing::error!("更新数据点失败: {}", e);
+            Json(ApiResponse::error(format!("更新数据点失败: {}", e)))
+        }
+    }
+}
+
+// 用请求中的数据点替换系列的全部数据，替换完成前的查询仍然返回旧数据。datapoints 为空时删除系列
+pub async fn replace_series_datapoints(
+    State(db): State<AppState>,
+    Path(series_key): Path<String>,
+    Query(params): Query<WriteParams>,
+    headers: HeaderMap,
+    Json(request): Json<ReplaceSeriesRequest>,
+) -> (StatusCode, Json<ApiResponse<ReplaceSeriesResponse>>) {
+    let view = TagView::from_headers(&db, &headers);
+    let tags = request.tags.unwrap_or_default();
+    let mut datapoints = Vec::with_capacity(request.datapoints.len());
+    for point in request.datapoints {
+        let timestamp = match to_internal_timestamp(&db, point.timestamp, &params) {
+            Ok(ts) => ts,
+            Err(e) => return (StatusCode::BAD_REQUEST, Json(ApiResponse::error(e))),
+        };
+        datapoints.push(DataPoint { timestamp, value: point.value, tags: tags.clone() });
+    }
+
+    match db.replace_series(&series_key, datapoints).await {
+        Ok(()) => {
+            let points = db.series_point_count(&series_key);
+            (StatusCode::OK, Json(ApiResponse::success(ReplaceSeriesResponse { series_key, points })))
+        }
+        Err(e) => {
+            tracing::error!("替换系列 {} 失败: {}", series_key, e);
+            (error_status(&e), Json(ApiResponse::error(format!("替换系列失败: {}", view.error_message(&db, &e)))))
+        }
+    }
+}
+
+// 批量修改数据点的值，每个受影响的SSTable只重写一次
+pub async fn update_datapoints_batch(
+    State(db): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<BatchUpdateRequest>,
+) -> (StatusCode, Json<ApiResponse<BatchUpdateReport>>) {
+    let tenant = Tenant::from_headers(&db, &headers);
+    let updates = request
+        .updates
+        .into_iter()
+        .map(|item| (tenant.scope(&item.series_key), item.timestamp, item.value))
+        .collect();
+
+    match db.update_batch(updates).await {
+        Ok(report) => {
+            tracing::info!(
+                "批量更新完成: 更新 {} 个，未找到 {} 个，重写 {} 个SSTable文件",
+                report.updated, report.not_found, report.files_rewritten
+            );
+            (StatusCode::OK, Json(ApiResponse::success(report)))
+        }
+        Err(e) => {
+            tracing::error!("批量更新数据点失败: {}", e);
+            (error_status(&e), Json(ApiResponse::error(format!("批量更新数据点失败: {}", e))))
+        }
+    }
+}
+
+// 删除数据点，受保护的系列需要 ?confirm=<系列键>
+pub async fn delete_datapoint(
+    State(db): State<AppState>,
+    Path((series_key, timestamp)): Path<(String, u64)>,
+    Query(params): Query<DeleteParams>,
+    headers: HeaderMap,
+) -> (StatusCode, Json<ApiResponse<String>>) {
+    let tenant = Tenant::from_headers(&db, &headers);
+    let confirmed = params.confirm.is_some_and(|confirm| tenant.scope(&confirm) == series_key);
+    match db.delete(&series_key, Some(timestamp), confirmed).await {
+        Ok(deleted) => {
+            if deleted {
+                tracing::info!("数据点已删除: {} at {}", series_key, timestamp);
+                (StatusCode::OK, Json(ApiResponse::success(format!(
+                    "数据点已删除: {} at {}",
+                    unscoped(&series_key), timestamp
+                ))))
+            } else {
+                (StatusCode::OK, Json(ApiResponse::error(
+                    "未找到指定的数据点".to_string()
+                )))
+            }
+        }
+        Err(e) => {
+            tracing::error!("删除数据点失败: {}", e);
+            (error_status(&e), Json(ApiResponse::error(format!("删除数据点失败: {}", e))))
+        }
+    }
+}
+
+// 删除整个系列，受保护的系列需要 ?confirm=<系列键>
+pub async fn delete_series(
+    State(db): State<AppState>,
+    Path(series_key): Path<String>,
+    Query(params): Query<DeleteParams>,
+    headers: HeaderMap,
+) -> (StatusCode, Json<ApiResponse<String>>) {
+    let tenant = Tenant::from_headers(&db, &headers);
+    let confirmed = params.confirm.is_some_and(|confirm| tenant.scope(&confirm) == series_key);
+    match db.delete(&series_key, None, confirmed).await {
+        Ok(deleted) => {
+            if deleted && params.delete_annotations {
+                return match db.delete_series_annotations(&series_key).await {
+                    Ok(removed) => {
+                        tracing::info!("系列已删除: {}，同时删除 {} 条注释", series_key, removed);
+                        (StatusCode::OK, Json(ApiResponse::success(format!(
+                            "系列已删除: {} (同时删除 {} 条注释)",
+                            unscoped(&series_key), removed
+                        ))))
+                    }
+                    Err(e) => {
+                        tracing::error!("删除系列 {} 的注释失败: {}", series_key, e);
+                        (error_status(&e), Json(ApiResponse::error(format!("系列已删除，但删除注释失败: {}", e))))
+                    }
+                };
+            }
+            if deleted {
+                tracing::info!("系列已删除: {}", series_key);
+                (StatusCode::OK, Json(ApiResponse::success(format!(
+                    "系列已删除: {}",
+                    unscoped(&series_key)
+                ))))
+            } else {
+                (StatusCode::OK, Json(ApiResponse::error(
+                    "未找到指定的系列".to_string()
+                )))
+            }
+        }
+        Err(e) => {
+            tracing::error!("删除系列失败: {}", e);
+            (error_status(&e), Json(ApiResponse::error(format!("删除系列失败: {}", e))))
+        }
+    }
+}
+
+// 读取预压缩块的请求头
+fn block_header<T: std::str::FromStr>(headers: &HeaderMap, name: &str) -> Result<T, String> {
+    let value = headers
+        .get(name)
+        .ok_or_else(|| format!("缺少请求头 {}", name))?;
+    value
+        .to_str()
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .ok_or_else(|| format!("请求头 {} 的值无效", name))
+}
+
+fn parse_block_upload(headers: &HeaderMap, body: Bytes) -> Result<(EncodedBlock, BTreeMap<String, String>), String> {
+    let tags = match headers.get("x-block-tags") {
+        Some(value) => value
+            .to_str()
+            .ok()
+            .and_then(|v| serde_json::from_str(v).ok())
+            .ok_or_else(|| "请求头 x-block-tags 必须是字符串到字符串的JSON对象".to_string())?,
+        None => BTreeMap::new(),
+    };
+    let block = EncodedBlock {
+        data: body.to_vec(),
+        count: block_header(headers, "x-block-count")?,
+        min_timestamp: block_header(headers, "x-block-min-timestamp")?,
+        max_timestamp: block_header(headers, "x-block-max-timestamp")?,
+    };
+    Ok((block, tags))
+}
+
+// 上传客户端预先压缩的Gorilla块，校验通过后原样写入新的SSTable
+pub async fn upload_block(
+    State(db): State<AppState>,
+    Path(series_key): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> (StatusCode, Json<ApiResponse<BlockIngestReport>>) {
+    let view = TagView::from_headers(&db, &headers);
+    let (block, tags) = match parse_block_upload(&headers, body) {
+        Ok(upload) => upload,
+        Err(e) => return (StatusCode::BAD_REQUEST, Json(ApiResponse::error(e))),
+    };
+
+    match db.ingest_block(series_key, block, tags).await {
+        Ok(report) => (StatusCode::CREATED, Json(ApiResponse::success(report))),
+        Err(e) => {
+            tracing::error!("写入预压缩块失败: {}", e);
+            let status = match e.kind() {
+                std::io::ErrorKind::InvalidData => StatusCode::BAD_REQUEST,
+                _ => error_status(&e),
+            };
+            (status, Json(ApiResponse::error(format!("块校验或写入失败: {}", view.error_message(&db, &e)))))
+        }
+    }
+}
+
+// 修改系列标签，只更新元数据不重写数据文件
+pub async fn update_series_tags(
+    State(db): State<AppState>,
+    Path(series_key): Path<String>,
+    headers: HeaderMap,
+    Json(request): Json<UpdateSeriesTagsRequest>,
+) -> (StatusCode, Json<ApiResponse<VisibleTags>>) {
+    let view = TagView::from_headers(&db, &headers);
+    match db.update_series_tags(&series_key, request.tags.clone()) {
+        Ok(true) => {
+            tracing::info!("系列标签已更新: {}", series_key);
+            (StatusCode::OK, Json(ApiResponse::success(view.tags(&db, &series_key, request.tags))))
+        }
+        Ok(false) => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error("未找到指定的系列".to_string())),
+        ),
+        Err(e) => {
+            tracing::error!("更新系列标签失败: {}", e);
+            (error_status(&e), Json(ApiResponse::error(format!("更新系列标签失败: {}", e))))
+        }
+    }
+}
+
+// 合并修改系列标签：新增、覆盖或删除指定的标签键，其余标签保持不变
+pub async fn patch_series_tags(
+    State(db): State<AppState>,
+    Path(series_key): Path<String>,
+    headers: HeaderMap,
+    Json(request): Json<PatchSeriesTagsRequest>,
+) -> (StatusCode, Json<ApiResponse<VisibleTags>>) {
+    let view = TagView::from_headers(&db, &headers);
+    if let Some(key) = request.remove.iter().find(|key| request.set.contains_key(*key)) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error(format!("标签 {} 不能同时出现在 set 和 remove 中", key))),
+        );
+    }
+
+    match db.patch_series_tags(&series_key, request.set, &request.remove) {
+        Ok(Some(tags)) => {
+            tracing::info!("系列标签已更新: {}", series_key);
+            (StatusCode::OK, Json(ApiResponse::success(view.tags(&db, &series_key, tags))))
+        }
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error("未找到指定的系列".to_string())),
+        ),
+        Err(e) => {
+            tracing::error!("更新系列标签失败: {}", e);
+            (error_status(&e), Json(ApiResponse::error(format!("更新系列标签失败: {}", e))))
+        }
+    }
+}
+
+// 获取系列列表，?prefix= 只列出以该前缀开头的系列；租户只能看到自己的系列
+pub async fn list_series(
+    State(db): State<AppState>,
+    Query(params): Query<SeriesListParams>,
+    headers: HeaderMap,
+) -> (StatusCode, Json<ApiResponse<Selected<SeriesListResponse>>>) {
+    let fields = match FieldSelection::for_model::<SeriesListResponse>(params.fields.as_deref()) {
+        Ok(fields) => fields,
+        Err(e) => return (StatusCode::BAD_REQUEST, Json(ApiResponse::error(e))),
+    };
+    let series = match Tenant::from_headers(&db, &headers).scope_prefix(params.prefix.as_deref()) {
+        Some(prefix) => Ok(db.series_with_prefix(&prefix)),
+        None => db.get_all_series().await,
+    };
+    match series {
+        Ok(series) => {
+            let response = SeriesListResponse::new(series);
+            (StatusCode::OK, Json(ApiResponse::success(fields.apply(response))))
+        }
+        Err(e) => {
+            tracing::error!("获取系列列表失败: {}", e);
+            (error_status(&e), Json(ApiResponse::error(format!("获取系列列表失败: {}", e))))
+        }
+    }
+}
+
+// 删除键以 ?prefix= 开头的全部系列，受删除保护的系列保留并在结果中列出。
+// 租户的前缀相对于租户自己的前缀，不指定时删除租户的全部系列
+pub async fn delete_series_prefix(
+    State(db): State<AppState>,
+    Query(params): Query<PrefixParams>,
+    headers: HeaderMap,
+) -> (StatusCode, Json<ApiResponse<PrefixDeleteReport>>) {
+    let Some(prefix) = Tenant::from_headers(&db, &headers).scope_prefix(params.prefix.as_deref()) else {
+        return (StatusCode::BAD_REQUEST, Json(ApiResponse::error("缺少prefix参数".to_string())));
+    };
+    match db.delete_prefix(&prefix).await {
+        Ok(report) => {
+            let unscoped_all = |keys: Vec<String>| keys.iter().map(|key| unscoped(key).to_string()).collect();
+            let report = PrefixDeleteReport {
+                prefix: unscoped(&report.prefix).to_string(),
+                deleted: unscoped_all(report.deleted),
+                protected: unscoped_all(report.protected),
+            };
+            (StatusCode::OK, Json(ApiResponse::success(report)))
+        }
+        Err(e) => {
+            tracing::error!("按前缀删除系列失败: {}", e);
+            (query_error_status(&e), Json(ApiResponse::error(format!("按前缀删除系列失败: {}", e))))
+        }
+    }
+}
+
+// 键以 ?prefix= 开头的系列的点数统计，只读取内存中的计数和SSTable元数据。租户的前缀相对于租户自己的前缀
+pub async fn prefix_stats(
+    State(db): State<AppState>,
+    Query(params): Query<PrefixParams>,
+    headers: HeaderMap,
+) -> Json<ApiResponse<PrefixStats>> {
+    let prefix = Tenant::from_headers(&db, &headers).scope_prefix(params.prefix.as_deref()).unwrap_or_default();
+    let mut stats = db.prefix_stats(&prefix);
+    stats.prefix = unscoped(&stats.prefix).to_string();
+    for series in &mut stats.top_series {
+        series.series_key = unscoped(&series.series_key).to_string();
+    }
+    Json(ApiResponse::success(stats))
+}
+
+// 手动触发compaction
+pub async fn trigger_compaction(
+    State(db): State<AppState>,
+    Json(request): Json<CompactRequest>,
+) -> Json<ApiResponse<CompactionReport>> {
+    let options = CompactionOptions::window(request.start_time, request.end_time);
+    match db.compact_with(&options).await {
+        Ok(report) => {
+            tracing::info!(
+                "手动compaction执行完成: {:?}",
+                report.inputs.iter().map(|input| input.file_name.as_str()).collect::<Vec<_>>()
+            );
+            Json(ApiResponse {
+                message: "Compaction执行完成".to_string(),
+                ..ApiResponse::success(report)
+            })
+        }
+        Err(e) => {
+            tracing::error!("Compaction执行失败: {}", e);
+            Json(ApiResponse::error(format!("Compaction执行失败: {}", e)))
+        }
+    }
+}
+
+// 只合并一个系列的块
+pub async fn compact_series(
+    State(db): State<AppState>,
+    Path(series_key): Path<String>,
+) -> Json<ApiResponse<CompactionReport>> {
+    match db.compact_series(&series_key).await {
+        Ok(report) => {
+            tracing::info!(
+                "系列 {} 的compaction执行完成: {:?}",
+                series_key,
+                report.inputs.iter().map(|input| input.file_name.as_str()).collect::<Vec<_>>()
+            );
+            Json(ApiResponse {
+                message: "Compaction执行完成".to_string(),
+                ..ApiResponse::success(report)
+            })
+        }
+        Err(e) => {
+            tracing::error!("系列 {} 的compaction执行失败: {}", series_key, e);
+            Json(ApiResponse::error(format!("Compaction执行失败: {}", e)))
+        }
+    }
+}
+
+// 最近的compaction报告，最新的在前
+pub async fn compaction_history(
+    State(db): State<AppState>,
+) -> Json<ApiResponse<Vec<CompactionReport>>> {
+    Json(ApiResponse::success(db.compaction_history()))
+}
+
+// 清空全部数据（需要 confirm: true）
+pub async fn truncate_database(
+    State(db): State<AppState>,
+    Json(request): Json<TruncateRequest>,
+) -> (StatusCode, Json<ApiResponse<TruncateResponse>>) {
+    if !request.confirm {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error("清空数据需要在请求体中设置 \"confirm\": true".to_string())),
+        );
+    }
+
+    match db.truncate().await {
+        Ok(protected_skipped) => (StatusCode::OK, Json(ApiResponse {
+            message: if protected_skipped.is_empty() {
+                "数据库已清空".to_string()
+            } else {
+                format!("数据库已清空，保留 {} 个受删除保护的系列", protected_skipped.len())
+            },
+            ..ApiResponse::success(TruncateResponse { protected_skipped })
+        })),
+        Err(e) => {
+            tracing::error!("清空数据库失败: {}", e);
+            (error_status(&e), Json(ApiResponse::error(format!("清空数据库失败: {}", e))))
+        }
+    }
+}
+
+// 获取保留策略
+pub async fn get_retention_policy(
+    State(db): State<AppState>,
+) -> Json<ApiResponse<RetentionPolicy>> {
+    Json(ApiResponse::success(db.retention_policy()))
+}
+
+// 设置保留策略（规则按顺序匹配，持久化到manifest）
+pub async fn set_retention_policy(
+    State(db): State<AppState>,
+    Json(policy): Json<RetentionPolicy>,
+) -> Json<ApiResponse<RetentionPolicy>> {
+    match db.set_retention_policy(policy.clone()) {
+        Ok(_) => {
+            tracing::info!("保留策略已更新: {} 条规则", policy.rules.len());
+            Json(ApiResponse::success(policy))
+        }
+        Err(e) => {
+            tracing::error!("更新保留策略失败: {}", e);
+            Json(ApiResponse::error(format!("更新保留策略失败: {}", e)))
+        }
+    }
+}
+
+// 查看降采样策略
+pub async fn get_downsample_policy(
+    State(db): State<AppState>,
+) -> Json<ApiResponse<DownsamplePolicy>> {
+    Json(ApiResponse::success(db.downsample_policy()))
+}
+
+// 设置降采样策略（规则按顺序匹配，持久化到manifest，下一次compaction时生效）
+pub async fn set_downsample_policy(
+    State(db): State<AppState>,
+    Json(policy): Json<DownsamplePolicy>,
+) -> (StatusCode, Json<ApiResponse<DownsamplePolicy>>) {
+    match db.set_downsample_policy(policy.clone()) {
+        Ok(_) => {
+            tracing::info!("降采样策略已更新: {} 条规则", policy.rules.len());
+            (StatusCode::OK, Json(ApiResponse::success(policy)))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::InvalidInput => {
+            (StatusCode::BAD_REQUEST, Json(ApiResponse::error(e.to_string())))
+        }
+        Err(e) => {
+            tracing::error!("更新降采样策略失败: {}", e);
+            (error_status(&e), Json(ApiResponse::error(format!("更新降采样策略失败: {}", e))))
+        }
+    }
+}
+
+// 查看每条配额规则的当前用量
+pub async fn get_quotas(
     State(db): State<AppState>,
-    Json(request): Json<CreateDataPointRequest>,
-) -> Json<ApiResponse<String>> {
-    let tags = request.tags.unwrap_or_default();
-    
-    let datapoint = DataPoint {
-        timestamp: request.timestamp,
-        value: request.value,
-        tags,
-    };
+) -> Json<ApiResponse<QuotaReport>> {
+    Json(ApiResponse::success(db.quota_report()))
+}
 
-    match db.insert(request.series_key.clone(), datapoint).await {
-        Ok(_) => Json(ApiResponse::success(format!(
-            "数据点已添加到系列: {} (时间戳: {})",
-            request.series_key, request.timestamp
-        ))),
+// 设置存储配额（持久化到manifest，按新规则重新统计用量）
+pub async fn set_quotas(
+    State(db): State<AppState>,
+    Json(policy): Json<QuotaPolicy>,
+) -> (StatusCode, Json<ApiResponse<QuotaReport>>) {
+    match db.set_quota_policy(policy).await {
+        Ok(_) => {
+            let report = db.quota_report();
+            tracing::info!("存储配额已更新: {} 条规则", report.quotas.len());
+            (StatusCode::OK, Json(ApiResponse::success(report)))
+        }
         Err(e) => {
-            tracing::error!("创建数据点失败: {}", e);
-            Json(ApiResponse::error(format!("创建数据点失败: {}", e)))
+            tracing::error!("更新存储配额失败: {}", e);
+            (error_status(&e), Json(ApiResponse::error(format!("更新存储配额失败: {}", e))))
         }
     }
 }
 
-// 批量创建数据点
-pub async fn create_datapoints_batch(
+// 查看启动预热列表及本次启动的预热进度
+pub async fn get_warmup(
     State(db): State<AppState>,
-    Json(requests): Json<Vec<CreateDataPointRequest>>,
-) -> Json<ApiResponse<String>> {
-    let mut success_count = 0;
-    let mut error_count = 0;
-
-    for request in requests {
-        let tags = request.tags.unwrap_or_default();
-        
-        let datapoint = DataPoint {
-            timestamp: request.timestamp,
-            value: request.value,
-            tags,
-        };
+) -> Json<ApiResponse<WarmupResponse>> {
+    Json(ApiResponse::success(WarmupResponse {
+        series: db.warmup_series(),
+        status: db.warmup_status(),
+    }))
+}
 
-        match db.insert(request.series_key.clone(), datapoint).await {
-            Ok(_) => success_count += 1,
-            Err(e) => {
-                tracing::error!("批量创建数据点失败: {}", e);
-                error_count += 1;
-            }
+// 设置启动预热列表（持久化到manifest，下次启动时生效）
+pub async fn set_warmup(
+    State(db): State<AppState>,
+    Json(request): Json<WarmupRequest>,
+) -> Json<ApiResponse<WarmupResponse>> {
+    match db.set_warmup_series(request.series) {
+        Ok(_) => {
+            let series = db.warmup_series();
+            tracing::info!("预热列表已更新: {:?}", series);
+            Json(ApiResponse::success(WarmupResponse {
+                series,
+                status: db.warmup_status(),
+            }))
+        }
+        Err(e) => {
+            tracing::error!("更新预热列表失败: {}", e);
+            Json(ApiResponse::error(format!("更新预热列表失败: {}", e)))
         }
     }
+}
 
-    Json(ApiResponse::success(format!(
-        "批量创建完成: 成功 {} 个，失败 {} 个",
-        success_count, error_count
-    )))
+// 获取删除保护列表
+pub async fn get_protected_series(
+    State(db): State<AppState>,
+) -> Json<ApiResponse<Vec<String>>> {
+    Json(ApiResponse::success(db.protected_series()))
 }
 
-// 查询数据点
-pub async fn query_datapoints(
+// 设置删除保护列表（持久化到manifest，立即生效）
+pub async fn set_protected_series(
     State(db): State<AppState>,
-    Path(series_key): Path<String>,
-    Query(query): Query<QueryRequest>,
-) -> Json<ApiResponse<Vec<DataPointResponse>>> {
-    match db.query_range(&series_key, query.start_time, query.end_time).await {
-        Ok(datapoints) => {
-            let response_data: Vec<DataPointResponse> = datapoints
-                .into_iter()
-                .map(|dp| DataPointResponse {
-                    timestamp: dp.timestamp,
-                    value: dp.value,
-                    tags: dp.tags,
-                })
-                .collect();
-            
-            tracing::info!("查询系列 {} 返回 {} 个数据点", series_key, response_data.len());
-            Json(ApiResponse::success(response_data))
+    Json(request): Json<ProtectedSeriesRequest>,
+) -> (StatusCode, Json<ApiResponse<Vec<String>>>) {
+    match db.set_protected_series(request.series) {
+        Ok(_) => {
+            let series = db.protected_series();
+            tracing::info!("删除保护列表已更新: {:?}", series);
+            (StatusCode::OK, Json(ApiResponse::success(series)))
         }
         Err(e) => {
-            tracing::error!("查询数据点失败: {}", e);
-            Json(ApiResponse::error(format!("查询数据点失败: {}", e)))
+            tracing::error!("更新删除保护列表失败: {}", e);
+            (error_status(&e), Json(ApiResponse::error(format!("更新删除保护列表失败: {}", e))))
         }
     }
 }
 
-// 更新数据点
-pub async fn update_datapoint(
+// 立即执行一次保留策略
+pub async fn enforce_retention(
     State(db): State<AppState>,
-    Path((series_key, timestamp)): Path<(String, u64)>,
-    Json(request): Json<UpdateDataPointRequest>,
-) -> Json<ApiResponse<String>> {
-    match db.update(&series_key, timestamp, request.value).await {
-        Ok(updated) => {
-            if updated {
-                tracing::info!("数据点已更新: {} at {} -> {}", series_key, timestamp, request.value);
-                Json(ApiResponse::success(format!(
-                    "数据点已更新: {} at {} -> {}",
-                    series_key, timestamp, request.value
-                )))
-            } else {
-                Json(ApiResponse::error(
-                    "未找到指定的数据点".to_string()
-                ))
-            }
+) -> Json<ApiResponse<RetentionReport>> {
+    match db.enforce_retention().await {
+        Ok(report) => Json(ApiResponse::success(report)),
+        Err(e) => {
+            tracing::error!("执行保留策略失败: {}", e);
+            Json(ApiResponse::error(format!("执行保留策略失败: {}", e)))
         }
+    }
+}
+
+// 校验并修正系列块记录的时间范围
+pub async fn verify_series_bounds(
+    State(db): State<AppState>,
+) -> Json<ApiResponse<BoundsReport>> {
+    match db.verify_series_bounds().await {
+        Ok(report) => Json(ApiResponse::success(report)),
         Err(e) => {
-            tracing::error!("更新数据点失败: {}", e);
-            Json(ApiResponse::error(format!("更新数据点失败: {}", e)))
+            tracing::error!("校验系列时间范围失败: {}", e);
+            Json(ApiResponse::error(format!("校验系列时间范围失败: {}", e)))
         }
     }
 }
 
-// 删除数据点
-pub async fn delete_datapoint(
+// 列出所有SSTable文件及其元数据
+pub async fn list_sstables(
     State(db): State<AppState>,
-    Path((series_key, timestamp)): Path<(String, u64)>,
-) -> Json<ApiResponse<String>> {
-    match db.delete(&series_key, Some(timestamp)).await {
-        Ok(deleted) => {
-            if deleted {
-                tracing::info!("数据点已删除: {} at {}", series_key, timestamp);
-                Json(ApiResponse::success(format!(
-                    "数据点已删除: {} at {}",
-                    series_key, timestamp
-                )))
-            } else {
-                Json(ApiResponse::error(
-                    "未找到指定的数据点".to_string()
-                ))
-            }
+) -> Json<ApiResponse<Vec<SSTableInfo>>> {
+    Json(ApiResponse::success(db.sstable_infos()))
+}
+
+// 查看单个SSTable文件中的系列
+pub async fn get_sstable(
+    State(db): State<AppState>,
+    Path(file_name): Path<String>,
+) -> (StatusCode, Json<ApiResponse<SSTableDetail>>) {
+    match db.sstable_detail(&file_name) {
+        Ok(Some(detail)) => (StatusCode::OK, Json(ApiResponse::success(detail))),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error(format!("未找到SSTable文件: {}", file_name))),
+        ),
+        Err(e) => {
+            tracing::error!("读取SSTable元数据失败: {}", e);
+            (error_status(&e), Json(ApiResponse::error(format!("读取SSTable元数据失败: {}", e))))
         }
+    }
+}
+
+// 系列在SSTable中的压缩效果
+pub async fn get_series_compression(
+    State(db): State<AppState>,
+    Path(series_key): Path<String>,
+) -> (StatusCode, Json<ApiResponse<CompressionReport>>) {
+    match db.series_compression_report(&series_key) {
+        Ok(Some(report)) => (StatusCode::OK, Json(ApiResponse::success(report))),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error(format!("未找到系列: {}", series_key))),
+        ),
         Err(e) => {
-            tracing::error!("删除数据点失败: {}", e);
-            Json(ApiResponse::error(format!("删除数据点失败: {}", e)))
+            tracing::error!("统计系列压缩效果失败: {}", e);
+            (error_status(&e), Json(ApiResponse::error(format!("统计系列压缩效果失败: {}", e))))
         }
     }
 }
 
-// 删除整个系列
-pub async fn delete_series(
+// 系列的敏感标签键
+pub async fn get_sensitive_tags(
     State(db): State<AppState>,
     Path(series_key): Path<String>,
-) -> Json<ApiResponse<String>> {
-    match db.delete(&series_key, None).await {
-        Ok(deleted) => {
-            if deleted {
-                tracing::info!("系列已删除: {}", series_key);
-                Json(ApiResponse::success(format!(
-                    "系列已删除: {}",
-                    series_key
-                )))
-            } else {
-                Json(ApiResponse::error(
-                    "未找到指定的系列".to_string()
-                ))
-            }
+) -> Json<ApiResponse<SensitiveTagsResponse>> {
+    Json(ApiResponse::success(SensitiveTagsResponse {
+        tag_keys: db.series_sensitive_tags(&series_key),
+        global_tag_keys: db.config().sensitive_tag_keys.clone(),
+        series_key,
+    }))
+}
+
+// 替换系列单独标记的敏感标签键。取消标记会暴露标签值，只允许具有 unredacted 权限的调用方修改
+pub async fn set_sensitive_tags(
+    State(db): State<AppState>,
+    Path(series_key): Path<String>,
+    headers: HeaderMap,
+    Json(request): Json<SensitiveTagsRequest>,
+) -> (StatusCode, Json<ApiResponse<SensitiveTagsResponse>>) {
+    if !TagView::from_headers(&db, &headers).unredacted() {
+        return (StatusCode::FORBIDDEN, Json(ApiResponse::error("修改敏感标签需要具有 unredacted 权限的API key".to_string())));
+    }
+    match db.set_series_sensitive_tags(&series_key, request.tag_keys) {
+        Ok(true) => {
+            tracing::info!("系列 {} 的敏感标签已更新", series_key);
+            (StatusCode::OK, Json(ApiResponse::success(SensitiveTagsResponse {
+                tag_keys: db.series_sensitive_tags(&series_key),
+                global_tag_keys: db.config().sensitive_tag_keys.clone(),
+                series_key,
+            })))
         }
+        Ok(false) => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error("未找到指定的系列".to_string())),
+        ),
         Err(e) => {
-            tracing::error!("删除系列失败: {}", e);
-            Json(ApiResponse::error(format!("删除系列失败: {}", e)))
+            tracing::error!("更新敏感标签失败: {}", e);
+            (error_status(&e), Json(ApiResponse::error(format!("更新敏感标签失败: {}", e))))
         }
     }
 }
 
-// 获取所有系列列表
-pub async fn list_series(
+// SSTable之间时间范围的重叠情况，只读取块元数据，结果在刷新和compaction之前被缓存
+pub async fn get_overlap_report(
     State(db): State<AppState>,
-) -> Json<ApiResponse<SeriesListResponse>> {
-    match db.get_all_series().await {
-        Ok(series) => {
-            let response = SeriesListResponse::new(series);
-            Json(ApiResponse::success(response))
+) -> Json<ApiResponse<OverlapReport>> {
+    Json(ApiResponse::success(db.overlap_report()))
+}
+
+// 把SSTable移动到另一个数据目录，移动期间查询和写入照常进行
+pub async fn relocate_sstable(
+    State(db): State<AppState>,
+    Json(request): Json<RelocateRequest>,
+) -> (StatusCode, Json<ApiResponse<SSTableRelocation>>) {
+    match db.relocate_sstable(&request.file_name, &request.target_dir).await {
+        Ok(Some(relocation)) => (StatusCode::OK, Json(ApiResponse::success(relocation))),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error(format!("未找到SSTable文件: {}", request.file_name))),
+        ),
+        Err(e) => {
+            tracing::error!("移动SSTable失败: {}", e);
+            (error_status(&e), Json(ApiResponse::error(format!("移动SSTable失败: {}", e))))
+        }
+    }
+}
+
+// 服务启动时实际生效的配置（配置文件、环境变量和默认值合并后），不返回API key本身
+pub async fn get_config(
+    Extension(config): Extension<Arc<Config>>,
+) -> Json<ApiResponse<Config>> {
+    let mut config = config.as_ref().clone();
+    for key in &mut config.unredacted_api_keys {
+        *key = REDACTED_TAG_VALUE.to_string();
+    }
+    // 租户key替换为编号，保留对应的前缀
+    config.tenant_api_keys = std::mem::take(&mut config.tenant_api_keys)
+        .into_values()
+        .enumerate()
+        .map(|(index, prefix)| (format!("{}#{}", REDACTED_TAG_VALUE, index + 1), prefix))
+        .collect();
+    Json(ApiResponse::success(config))
+}
+
+// 进行中的管理操作（compaction、导出）及进度
+pub async fn list_operations(
+    State(db): State<AppState>,
+) -> Json<ApiResponse<Vec<OperationInfo>>> {
+    Json(ApiResponse::success(db.operations()))
+}
+
+// 新建注释（时间点或时间段），不指定 series_key 时为全局注释，租户只能为自己的系列添加注释
+pub async fn create_annotation(
+    State(db): State<AppState>,
+    Query(params): Query<WriteParams>,
+    headers: HeaderMap,
+    Json(request): Json<CreateAnnotationRequest>,
+) -> (StatusCode, Json<ApiResponse<Visible<Annotation>>>) {
+    let tenant = Tenant::from_headers(&db, &headers);
+    let view = TagView::from_headers(&db, &headers);
+    if tenant.prefix().is_some() && request.series_key.is_none() {
+        return (StatusCode::FORBIDDEN, Json(ApiResponse::error("租户API key只能为系列添加注释".to_string())));
+    }
+    let timestamps = to_internal_timestamp(&db, request.timestamp, &params).and_then(|timestamp| {
+        let end_timestamp = request.end_timestamp.map(|end| to_internal_timestamp(&db, end, &params)).transpose()?;
+        Ok((timestamp, end_timestamp))
+    });
+    let (timestamp, end_timestamp) = match timestamps {
+        Ok(timestamps) => timestamps,
+        Err(e) => return (StatusCode::BAD_REQUEST, Json(ApiResponse::error(e))),
+    };
+    let annotation = NewAnnotation {
+        series_key: request.series_key.map(|series_key| tenant.scope(&series_key)),
+        timestamp,
+        end_timestamp,
+        text: request.text,
+        tags: request.tags,
+    };
+    match db.add_annotation(annotation).await {
+        Ok(annotation) => (StatusCode::OK, Json(ApiResponse::success(visible_annotation(&db, view, annotation)))),
+        Err(e) => {
+            tracing::error!("添加注释失败: {}", e);
+            (error_status(&e), Json(ApiResponse::error(format!("添加注释失败: {}", e))))
+        }
+    }
+}
+
+// 查询注释，参数: series_key、start_time/end_time 或 last。指定系列时包括全局注释
+pub async fn list_annotations(
+    State(db): State<AppState>,
+    Query(query): Query<AnnotationsRequest>,
+    headers: HeaderMap,
+) -> (StatusCode, Json<ApiResponse<Vec<Visible<Annotation>>>>) {
+    let tenant = Tenant::from_headers(&db, &headers);
+    let view = TagView::from_headers(&db, &headers);
+    // 注释不扫描系列数据，不指定时间范围时返回全部注释
+    let (range, relative) = match query_time_range(&db, query.start_time.as_deref(), query.end_time.as_deref(), query.last.as_deref(), true) {
+        Ok(range) => range,
+        Err(e) => return (StatusCode::BAD_REQUEST, Json(ApiResponse::error(e))),
+    };
+    let series_key = query.series_key.as_deref().map(|series_key| tenant.scope(series_key));
+    match db.annotations(series_key.as_deref(), &TimeRange::between(range.start_time, range.end_time)) {
+        Ok(annotations) => {
+            let annotations = annotations
+                .into_iter()
+                .filter(|annotation| annotation_visible(&tenant, annotation))
+                .map(|annotation| visible_annotation(&db, view, annotation))
+                .collect();
+            (StatusCode::OK, Json(ApiResponse { range: relative, ..ApiResponse::success(annotations) }))
         }
+        Err(e) => (query_error_status(&e), Json(ApiResponse::error(format!("查询注释失败: {}", e)))),
+    }
+}
+
+pub async fn delete_annotation(
+    State(db): State<AppState>,
+    Path(id): Path<u64>,
+    headers: HeaderMap,
+) -> (StatusCode, Json<ApiResponse<String>>) {
+    let tenant = Tenant::from_headers(&db, &headers);
+    if !db.annotation(id).is_some_and(|annotation| annotation_visible(&tenant, &annotation)) {
+        return (StatusCode::NOT_FOUND, Json(ApiResponse::error(format!("注释 {} 不存在", id))));
+    }
+    match db.delete_annotation(id).await {
+        Ok(true) => (StatusCode::OK, Json(ApiResponse::success(format!("注释已删除: {}", id)))),
+        Ok(false) => (StatusCode::NOT_FOUND, Json(ApiResponse::error(format!("注释 {} 不存在", id)))),
         Err(e) => {
-            tracing::error!("获取系列列表失败: {}", e);
-            Json(ApiResponse::error(format!("获取系列列表失败: {}", e)))
+            tracing::error!("删除注释失败: {}", e);
+            (error_status(&e), Json(ApiResponse::error(format!("删除注释失败: {}", e))))
         }
     }
 }
 
-// 手动触发compaction
-pub async fn trigger_compaction(
+// 以SSE推送变更事件，参数: series_key、prefix、tag=key:value（可重复，条件之间为与）。
+// 事件名为 insert/update/delete，数据为事件的JSON；跟不上写入而丢失事件时推送 lagged，数据为丢失的条数
+pub async fn stream_changes(
     State(db): State<AppState>,
-    Json(_request): Json<CompactRequest>,
-) -> Json<ApiResponse<String>> {
-    match db.compact().await {
-        Ok(_) => {
-            tracing::info!("手动compaction执行完成");
-            Json(ApiResponse::success(
-                "Compaction执行完成".to_string()
-            ))
+    Query(params): Query<Vec<(String, String)>>,
+    headers: HeaderMap,
+) -> Response {
+    let mut filter = ChangeFilter::default();
+    for (key, value) in params {
+        match key.as_str() {
+            "series_key" => filter.series_key = Some(value),
+            "prefix" => filter.prefix = Some(value),
+            "tag" => match parse_tag_condition(&value) {
+                Ok((k, v)) => {
+                    filter.tags.insert(k, v);
+                }
+                Err(e) => return (StatusCode::BAD_REQUEST, Json(ApiResponse::<()>::error(e))).into_response(),
+            },
+            _ => {}
+        }
+    }
+
+    let view = TagView::from_headers(&db, &headers);
+    let receiver = db.subscribe();
+    let events = futures_util::stream::unfold((receiver, db, filter, view), |(mut receiver, db, filter, view)| async move {
+        loop {
+            let event = match receiver.recv().await {
+                Ok(event) => view.change(&db, event),
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
+                    tracing::warn!("变更流跟不上写入，丢失 {} 个事件", skipped);
+                    let lagged = Event::default().event("lagged").data(skipped.to_string());
+                    return Some((Ok::<_, std::convert::Infallible>(lagged), (receiver, db, filter, view)));
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => return None,
+            };
+            // 按调用方能看到的标签过滤，隐藏的敏感标签值不会被匹配
+            let series_tags;
+            let tags = match &*event {
+                ChangeEvent::Insert { tags, .. } => tags,
+                _ => {
+                    series_tags = view.tags(&db, event.series_key(), db.series_tags(event.series_key()).unwrap_or_default());
+                    &*series_tags
+                }
+            };
+            if !filter.matches(event.series_key(), tags) {
+                continue;
+            }
+            match Event::default().event(event.kind()).json_data(&event) {
+                Ok(sse) => return Some((Ok(sse), (receiver, db, filter, view))),
+                Err(e) => tracing::warn!("序列化变更事件失败: {}", e),
+            }
+        }
+    });
+    Sse::new(events).keep_alive(KeepAlive::default()).into_response()
+}
+
+// 请求取消进行中的操作，操作在处理下一个文件前停止
+pub async fn cancel_operation(
+    State(db): State<AppState>,
+    Path(id): Path<u64>,
+) -> (StatusCode, Json<ApiResponse<String>>) {
+    if db.cancel_operation(id) {
+        tracing::info!("已请求取消操作 {}", id);
+        (StatusCode::ACCEPTED, Json(ApiResponse::success(format!("已请求取消操作 {}", id))))
+    } else {
+        (StatusCode::NOT_FOUND, Json(ApiResponse::error(format!("操作 {} 不存在或已结束", id))))
+    }
+}
+
+// 在后台开始完整性校验，返回校验任务的编号和初始进度
+pub async fn start_verify(
+    State(db): State<AppState>,
+) -> (StatusCode, Json<ApiResponse<JobInfo>>) {
+    match db.start_verify().map(|id| db.job(id)) {
+        Ok(Some(job)) => {
+            tracing::info!("开始完整性校验: 操作 {}，{} 个文件", job.id, job.files_total);
+            (StatusCode::ACCEPTED, Json(ApiResponse::success(job)))
+        }
+        Ok(None) => (StatusCode::INTERNAL_SERVER_ERROR, Json(ApiResponse::error("校验任务已结束但未保留结果".to_string()))),
+        Err(e) => (error_status(&e), Json(ApiResponse::error(format!("开始完整性校验失败: {}", e)))),
+    }
+}
+
+// 校验任务的进度（已检查文件数、已发现的损坏文件），结束后附带完整的报告
+pub async fn get_verify_job(
+    State(db): State<AppState>,
+    Path(id): Path<u64>,
+) -> (StatusCode, Json<ApiResponse<JobInfo>>) {
+    match db.job(id).filter(|job| job.kind == OperationKind::Verify) {
+        Some(job) => (StatusCode::OK, Json(ApiResponse::success(job))),
+        None => (StatusCode::NOT_FOUND, Json(ApiResponse::error(format!("校验任务 {} 不存在", id)))),
+    }
+}
+
+// 把写归档的同步写入转成响应体的数据块，缓冲的块满后阻塞写入线程，直到客户端读走数据
+struct ChannelWriter {
+    sender: tokio::sync::mpsc::Sender<std::io::Result<axum::body::Bytes>>,
+    buffer: Vec<u8>,
+}
+
+impl ChannelWriter {
+    fn send_buffer(&mut self) -> std::io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let chunk = std::mem::replace(&mut self.buffer, Vec::with_capacity(EXPORT_CHUNK_BYTES));
+        self.sender
+            .blocking_send(Ok(chunk.into()))
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::BrokenPipe, "客户端已断开"))
+    }
+}
+
+impl std::io::Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= EXPORT_CHUNK_BYTES {
+            self.send_buffer()?;
         }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.send_buffer()
+    }
+}
+
+// 导出全部数据为tar归档，边打包边发送
+pub async fn export_database(
+    State(db): State<AppState>,
+    headers: HeaderMap,
+) -> Response {
+    let snapshot = match db.export_snapshot().await {
+        Ok(snapshot) => snapshot,
         Err(e) => {
-            tracing::error!("Compaction执行失败: {}", e);
-            Json(ApiResponse::error(format!("Compaction执行失败: {}", e)))
+            tracing::error!("导出数据失败: {}", e);
+            return (error_status(&e), Json(ApiResponse::<()>::error(format!("导出数据失败: {}", e)))).into_response();
+        }
+    };
+
+    // 先计算整个归档的校验和与长度，响应头要在响应体之前发出。数据块已经压缩过，不再按 Accept-Encoding 压缩，
+    // 代理和CDN缓存的字节与校验和一致
+    let view = TagView::from_headers(&db, &headers);
+    let checksum = tokio::task::spawn_blocking(move || {
+        if !view.can_export(&db, &snapshot)? {
+            return Ok(None);
+        }
+        snapshot.checksum().map(|checksum| Some((snapshot, checksum)))
+    })
+    .await;
+    let (snapshot, (content_length, checksum)) = match checksum.map_err(std::io::Error::other).and_then(|result| result) {
+        Ok(Some(result)) => result,
+        Ok(None) => {
+            return (
+                StatusCode::FORBIDDEN,
+                Json(ApiResponse::<()>::error("导出的数据包含敏感标签，需要具有 unredacted 权限的API key".to_string())),
+            )
+                .into_response();
+        }
+        Err(e) => {
+            tracing::error!("导出数据失败: {}", e);
+            return (error_status(&e), Json(ApiResponse::<()>::error(format!("导出数据失败: {}", e)))).into_response();
+        }
+    };
+
+    let (sender, receiver) = tokio::sync::mpsc::channel(EXPORT_CHANNEL_CHUNKS);
+    tokio::task::spawn_blocking(move || {
+        let writer = ChannelWriter {
+            sender: sender.clone(),
+            buffer: Vec::with_capacity(EXPORT_CHUNK_BYTES),
+        };
+        let result = snapshot.write_tar(writer).and_then(|(mut writer, export)| {
+            std::io::Write::flush(&mut writer)?;
+            Ok(export)
+        });
+        match result {
+            Ok(export) => tracing::info!("导出完成: {} 个SSTable文件，{} 个系列", export.files.len(), export.series_count),
+            Err(e) => {
+                // 响应头已发出，只能中断响应体，客户端据此判断归档不完整
+                tracing::error!("导出数据失败: {}", e);
+                let _ = sender.blocking_send(Err(e));
+            }
+        }
+    });
+
+    let stream = futures_util::stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|chunk| (chunk, receiver))
+    });
+    let checksum = format!("{:08x}", checksum);
+    (
+        [
+            (header::CONTENT_TYPE, "application/x-tar".to_string()),
+            (header::CONTENT_DISPOSITION, "attachment; filename=\"timeseries-export.tar\"".to_string()),
+            (header::CONTENT_LENGTH, content_length.to_string()),
+            (header::ETAG, format!("\"{}\"", checksum)),
+        ],
+        [("x-export-checksum", checksum)],
+        Body::from_stream(stream),
+    )
+        .into_response()
+}
+
+// 从导出的归档导入数据，数据库不为空时需要 ?force=true
+pub async fn import_database(
+    State(db): State<AppState>,
+    Query(params): Query<ImportParams>,
+    body: Body,
+) -> (StatusCode, Json<ApiResponse<ImportReport>>) {
+    let stream = body.into_data_stream().map(|chunk| chunk.map_err(std::io::Error::other));
+    let reader = tokio_util::io::SyncIoBridge::new(tokio_util::io::StreamReader::new(stream));
+
+    match db.import_archive(reader, params.force).await {
+        Ok(report) => (StatusCode::OK, Json(ApiResponse::success(report))),
+        Err(e) => {
+            tracing::error!("导入数据失败: {}", e);
+            let status = match e.kind() {
+                std::io::ErrorKind::InvalidData => StatusCode::BAD_REQUEST,
+                _ => error_status(&e),
+            };
+            (status, Json(ApiResponse::error(format!("导入数据失败: {}", e))))
         }
     }
 }
 
 // 健康检查
-pub async fn health_check() -> Json<Value> {
+pub async fn health_check(
+    State(db): State<AppState>,
+) -> Json<Value> {
+    let mut warnings = Vec::new();
+
+    // 内存表数据驻留时间超过上限两倍，说明刷新任务可能卡住了
+    if let (Some(max_age), Some(age)) = (db.config().max_memtable_age, db.oldest_memtable_age()) {
+        if age > max_age * 2 {
+            warnings.push(format!(
+                "内存表最早数据已驻留 {} 秒，超过上限 {} 秒的两倍，刷新任务可能已停止",
+                age.as_secs(),
+                max_age.as_secs()
+            ));
+        }
+    }
+
+    match db.disk_level() {
+        DiskLevel::Ok => {}
+        DiskLevel::Low => warnings.push(format!(
+            "磁盘剩余空间 {:?} 字节低于低水位线，已拒绝写入",
+            db.disk_free_bytes()
+        )),
+        DiskLevel::Critical => warnings.push(format!(
+            "磁盘剩余空间 {:?} 字节低于临界水位线，已拒绝写入并暂停compaction",
+            db.disk_free_bytes()
+        )),
+    }
+
+    // 后台任务最近一次执行失败，正在退避重试
+    let background_tasks = db.background_task_status();
+    for (name, task) in &background_tasks {
+        if task.consecutive_failures > 0 {
+            warnings.push(format!(
+                "后台任务 {} 已连续失败 {} 次: {}",
+                name,
+                task.consecutive_failures,
+                task.last_error.as_deref().unwrap_or_default()
+            ));
+        }
+    }
+
+    let status = if warnings.is_empty() { "healthy" } else { "degraded" };
+
     Json(serde_json::json!({
-        "status": "healthy",
+        "status": status,
+        "warnings": warnings,
+        "disk_free_bytes": db.disk_free_bytes(),
+        "disk_level": db.disk_level(),
+        // 预热在后台进行，state 为 running 时热点系列的首次查询可能较慢
+        "warmup": db.warmup_status(),
+        "background_tasks": background_tasks,
         "service": "时序数据库",
         "version": "1.0.0",
         "timestamp": chrono::Utc::now().timestamp(),
@@ -227,9 +2162,22 @@ pub async fn health_check() -> Json<Value> {
 }
 
 // 数据库统计信息
+// 只读取内存计数和SSTable元数据摘要的统计，不访问数据文件
+pub async fn stats_summary(
+    State(db): State<AppState>,
+    Query(params): Query<FieldsParams>,
+) -> (StatusCode, Json<ApiResponse<Selected<StatsSummary>>>) {
+    let fields = match FieldSelection::for_model::<StatsSummary>(params.fields.as_deref()) {
+        Ok(fields) => fields,
+        Err(e) => return (StatusCode::BAD_REQUEST, Json(ApiResponse::error(e))),
+    };
+    (StatusCode::OK, Json(ApiResponse::success(fields.apply(db.stats_summary()))))
+}
+
 pub async fn db_stats(
     State(db): State<AppState>,
-) -> Json<ApiResponse<Value>> {
+    Query(params): Query<FieldsParams>,
+) -> (StatusCode, Json<ApiResponse<Selected<Value>>>) {
     match db.get_stats().await {
         Ok(stats) => {
             let response = serde_json::json!({
@@ -238,26 +2186,141 @@ pub async fn db_stats(
                 "memory_mapping": "mmap零拷贝",
                 "status": "运行中",
                 "memtable_size": stats.memtable_size,
+                "memtable_bytes": stats.memtable_bytes,
+                "memtable_tag_sets": stats.memtable_tag_sets,
                 "sstable_count": stats.sstable_count,
                 "total_series": stats.total_series,
+                "oldest_memtable_age_secs": stats.oldest_memtable_age_secs,
+                "max_memtable_age_secs": stats.max_memtable_age_secs,
+                "disk_free_bytes": stats.disk_free_bytes,
+                "disk_level": stats.disk_level,
+                "tag_drift_counts": stats.tag_drift_counts,
+                "sstable_scans": stats.sstable_scans,
+                "coalesced_queries": stats.coalesced_queries,
+                "total_bytes": stats.total_bytes,
+                "max_total_bytes": stats.max_total_bytes,
+                "location_index_warm": stats.location_index_warm,
+                "location_index_hits": stats.location_index_hits,
+                "location_index_misses": stats.location_index_misses,
+                "sstable_files_visited": stats.sstable_files_visited,
+                "events": stats.events,
+                "data_dirs": stats.data_dirs,
+                "query_memory": stats.query_memory,
+                "decode_buffers": stats.decode_buffers,
+                "query_progress": stats.query_progress,
+                "background_tasks": stats.background_tasks,
                 "timestamp": chrono::Utc::now().timestamp()
             });
-            
-            Json(ApiResponse::success(response))
+
+            match FieldSelection::for_object(params.fields.as_deref(), &response) {
+                Ok(fields) => (StatusCode::OK, Json(ApiResponse::success(fields.apply(response)))),
+                Err(e) => (StatusCode::BAD_REQUEST, Json(ApiResponse::error(e))),
+            }
         }
         Err(e) => {
             tracing::error!("获取数据库统计信息失败: {}", e);
-            Json(ApiResponse::error(format!("获取数据库统计信息失败: {}", e)))
+            (error_status(&e), Json(ApiResponse::error(format!("获取数据库统计信息失败: {}", e))))
+        }
+    }
+}
+
+// 停止上报的系列，参数: threshold_secs、include_value、prefix、match[]=key=value（可重复）、fields
+pub async fn list_stale_series(
+    State(db): State<AppState>,
+    Query(params): Query<Vec<(String, String)>>,
+    headers: HeaderMap,
+) -> (StatusCode, Json<ApiResponse<StaleSeriesResponse<Selected<StaleSeries>>>>) {
+    let mut threshold_secs = None;
+    let mut include_value = false;
+    let mut prefix = None;
+    let mut matchers = BTreeMap::new();
+    let mut fields = None;
+
+    for (key, value) in params {
+        match key.as_str() {
+            "threshold_secs" => threshold_secs = value.parse::<u64>().ok(),
+            "include_value" => include_value = value == "true" || value == "1",
+            "prefix" => prefix = Some(value),
+            "fields" => fields = Some(value),
+            "match[]" | "match" => match value.split_once('=') {
+                Some((k, v)) => {
+                    matchers.insert(k.to_string(), v.to_string());
+                }
+                None => {
+                    return (StatusCode::BAD_REQUEST, Json(ApiResponse::error(format!("无效的匹配条件: {}", value))));
+                }
+            },
+            _ => {}
+        }
+    }
+
+    let Some(threshold_secs) = threshold_secs else {
+        return (StatusCode::BAD_REQUEST, Json(ApiResponse::error("threshold_secs参数必须为非负整数".to_string())));
+    };
+    let fields = match FieldSelection::for_model::<StaleSeries>(fields.as_deref()) {
+        Ok(fields) => fields,
+        Err(e) => return (StatusCode::BAD_REQUEST, Json(ApiResponse::error(e))),
+    };
+
+    let prefix = Tenant::from_headers(&db, &headers).scope_prefix(prefix.as_deref());
+    match db.stale_series(threshold_secs, prefix.as_deref(), &matchers, include_value).await {
+        Ok(mut series) => {
+            tracing::info!("查询到 {} 个超过 {} 秒未上报的系列", series.len(), threshold_secs);
+            for stale in &mut series {
+                stale.series_key = unscoped(&stale.series_key).to_string();
+            }
+            let series = fields.apply_each(series);
+            (StatusCode::OK, Json(ApiResponse::success(StaleSeriesResponse { threshold_secs, series })))
+        }
+        Err(e) => {
+            tracing::error!("查询停止上报的系列失败: {}", e);
+            (error_status(&e), Json(ApiResponse::error(format!("查询停止上报的系列失败: {}", e))))
         }
     }
 }
 
-// 获取系列详细信息
+// 首次写入时间晚于since的系列，用于发现新出现的指标
+pub async fn list_new_series(
+    State(db): State<AppState>,
+    Query(query): Query<NewSeriesRequest>,
+    headers: HeaderMap,
+) -> (StatusCode, Json<ApiResponse<NewSeriesResponse<Selected<SeriesFirstSeen>>>>) {
+    let tenant = Tenant::from_headers(&db, &headers);
+    let fields = match FieldSelection::for_model::<SeriesFirstSeen>(query.fields.as_deref()) {
+        Ok(fields) => fields,
+        Err(e) => return (StatusCode::BAD_REQUEST, Json(ApiResponse::error(e))),
+    };
+    let series: Vec<SeriesFirstSeen> = db
+        .series_created_since(query.since)
+        .into_iter()
+        .filter(|(series_key, _)| tenant.owns(series_key))
+        .map(|(series_key, first_seen)| SeriesFirstSeen { series_key, first_seen })
+        .collect();
+
+    tracing::info!("查询到 {} 个在 {} 之后出现的系列", series.len(), query.since);
+    (StatusCode::OK, Json(ApiResponse::success(NewSeriesResponse {
+        since: query.since,
+        series: fields.apply_each(series),
+    })))
+}
+
+// 获取系列详细信息，?fields= 只返回部分字段
 pub async fn get_series_info(
     State(db): State<AppState>,
     Path(series_key): Path<String>,
-) -> Json<ApiResponse<Value>> {
-    match db.query_range(&series_key, None, None).await {
+    Query(params): Query<SeriesInfoParams>,
+    headers: HeaderMap,
+) -> (StatusCode, Json<ApiResponse<Selected<SeriesInfo>>>) {
+    let view = TagView::from_headers(&db, &headers);
+    let fields = match FieldSelection::for_model::<SeriesInfo>(params.fields.as_deref()) {
+        Ok(fields) => fields,
+        Err(e) => return (StatusCode::BAD_REQUEST, Json(ApiResponse::error(e))),
+    };
+    let (range, relative) = match query_time_range(&db, None, None, None, params.all) {
+        Ok(range) => range,
+        Err(e) => return (StatusCode::BAD_REQUEST, Json(ApiResponse::error(e))),
+    };
+    match db.query_range(&series_key, range.start_time, range.end_time).await {
         Ok(datapoints) => {
             let count = datapoints.len();
             let min_timestamp = datapoints.iter().map(|dp| dp.timestamp).min();
@@ -265,25 +2328,27 @@ pub async fn get_series_info(
             let min_value = datapoints.iter().map(|dp| dp.value).fold(f64::INFINITY, f64::min);
             let max_value = datapoints.iter().map(|dp| dp.value).fold(f64::NEG_INFINITY, f64::max);
             
-            let info = serde_json::json!({
-                "series_key": series_key,
-                "count": count,
-                "min_timestamp": min_timestamp,
-                "max_timestamp": max_timestamp,
-                "min_value": if min_value.is_finite() { Some(min_value) } else { None },
-                "max_value": if max_value.is_finite() { Some(max_value) } else { None },
-                "tags": if !datapoints.is_empty() { 
-                    Some(&datapoints[0].tags) 
-                } else { 
-                    None 
-                }
-            });
-            
-            Json(ApiResponse::success(info))
+            let info = SeriesInfo {
+                count,
+                min_timestamp,
+                max_timestamp,
+                last_timestamp: db.last_timestamp(&series_key),
+                resolution: db.series_resolution(&series_key),
+                min_value: if min_value.is_finite() { Some(min_value) } else { None },
+                max_value: if max_value.is_finite() { Some(max_value) } else { None },
+                tags: if !datapoints.is_empty() {
+                    db.series_tags(&series_key).map(|tags| view.tags(&db, &series_key, tags))
+                } else {
+                    None
+                },
+                series_key,
+            };
+
+            (StatusCode::OK, Json(ApiResponse { range: relative, ..ApiResponse::success(fields.apply(info)) }))
         }
         Err(e) => {
             tracing::error!("获取系列信息失败: {}", e);
-            Json(ApiResponse::error(format!("获取系列信息失败: {}", e)))
+            (query_error_status(&e), Json(ApiResponse::error(format!("获取系列信息失败: {}", e))))
         }
     }
 }
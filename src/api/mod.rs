@@ -1,6 +1,14 @@
 pub mod handlers;
 pub mod models;
+pub mod routes;
+pub mod redaction;
+pub mod fields;
+pub mod tenant;
 
 pub use handlers::*;
 pub use models::*;
+pub use routes::*;
+pub use redaction::*;
+pub use fields::*;
+pub use tenant::*;
 
@@ -1,29 +1,611 @@
-use serde::{Deserialize, Serialize};
+use serde::{de, Deserialize, Deserializer, Serialize};
 use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::db::{
+    AggregateBucket, AggregateFn, HistogramBucket, StaleSeries, Crossing, CrossingDirection, DownsampleBucket, EnvelopeBucket, FillPolicy, Gap, ChunkSummary, QueryStats, ResolutionSegment, ResolvedRange, TimePrecision, WarmupStatus, WindowResult, Annotation, QueryReservation,
+};
+use super::redaction::{Visible, VisibleTags};
+use super::tenant::{serialize_series_key, serialize_series_keys};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CreateDataPointRequest {
     pub series_key: String,
     pub timestamp: u64,
+    #[serde(deserialize_with = "deserialize_exact_f64")]
     pub value: f64,
     pub tags: Option<BTreeMap<String, String>>,
 }
 
+// f64能精确表示的最大整数 2^53
+pub const MAX_EXACT_INTEGER: u64 = 1 << 53;
+
+// 数值以f64存储，超出 ±2^53 的JSON整数转换时会静默丢失精度，直接拒绝
+pub fn deserialize_exact_f64<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    struct ExactF64Visitor;
+
+    impl de::Visitor<'_> for ExactF64Visitor {
+        type Value = f64;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "数值（整数绝对值不超过 {}）", MAX_EXACT_INTEGER)
+        }
+
+        fn visit_f64<E: de::Error>(self, v: f64) -> Result<f64, E> {
+            Ok(v)
+        }
+
+        fn visit_u64<E: de::Error>(self, v: u64) -> Result<f64, E> {
+            if v > MAX_EXACT_INTEGER {
+                return Err(E::custom(format!(
+                    "整数 {} 超出f64可精确表示的范围 ±{}，存储后会丢失精度",
+                    v, MAX_EXACT_INTEGER
+                )));
+            }
+            Ok(v as f64)
+        }
+
+        fn visit_i64<E: de::Error>(self, v: i64) -> Result<f64, E> {
+            if v.unsigned_abs() > MAX_EXACT_INTEGER {
+                return Err(E::custom(format!(
+                    "整数 {} 超出f64可精确表示的范围 ±{}，存储后会丢失精度",
+                    v, MAX_EXACT_INTEGER
+                )));
+            }
+            Ok(v as f64)
+        }
+    }
+
+    deserializer.deserialize_f64(ExactF64Visitor)
+}
+
+// 写入接口何时响应
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AckLevel {
+    // 写入内存表后立即响应
+    #[default]
+    Received,
+    // 等待覆盖本次写入的内存表刷新完成后响应
+    Flushed,
+}
+
+// 写入接口的查询参数
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct WriteParams {
+    // 请求中时间戳的精度，默认与数据库内部精度一致
+    pub precision: Option<TimePrecision>,
+    // 单点写入接口在响应中附带系列写入后的数据点总数
+    #[serde(default)]
+    pub return_count: bool,
+    #[serde(default)]
+    pub ack: AckLevel,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UpdateDataPointRequest {
+    #[serde(deserialize_with = "deserialize_exact_f64")]
     pub value: f64,
 }
 
+// 替换系列全部数据的请求，所有数据点使用同一组标签
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReplaceSeriesRequest {
+    pub tags: Option<BTreeMap<String, String>>,
+    pub datapoints: Vec<ReplaceSeriesPoint>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReplaceSeriesPoint {
+    pub timestamp: u64,
+    #[serde(deserialize_with = "deserialize_exact_f64")]
+    pub value: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReplaceSeriesResponse {
+    #[serde(serialize_with = "serialize_series_key")]
+    pub series_key: String,
+    // 替换后系列的数据点数，重复的时间戳只计一次
+    pub points: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BatchUpdateItem {
+    pub series_key: String,
+    pub timestamp: u64,
+    #[serde(deserialize_with = "deserialize_exact_f64")]
+    pub value: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BatchUpdateRequest {
+    pub updates: Vec<BatchUpdateItem>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UpdateSeriesTagsRequest {
+    pub tags: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PatchSeriesTagsRequest {
+    // 新增或覆盖的标签
+    #[serde(default)]
+    pub set: BTreeMap<String, String>,
+    // 删除的标签键
+    #[serde(default)]
+    pub remove: Vec<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct QueryRequest {
-    pub start_time: Option<u64>,
-    pub end_time: Option<u64>,
+    // 时间戳，或 now、now-1h、now+5m 这样相对服务端当前时间的表达式，其他查询接口相同
+    pub start_time: Option<String>,
+    pub end_time: Option<String>,
+    // 相对时间范围，如 "1h"，相当于 start_time=now-1h、end_time=now，不能与 start_time/end_time 同时使用
+    #[serde(alias = "range")]
+    pub last: Option<String>,
+    // 为true时没有指定时间范围的查询扫描整个系列，不使用 default_query_range_secs
+    #[serde(default)]
+    pub all: bool,
     pub limit: Option<usize>,
+    // 为true时在响应中附带数据来源统计
+    #[serde(default)]
+    pub stats: bool,
+    // 查询时间预算（毫秒），超时后返回已读出的部分数据，不指定时读取全部
+    pub max_wait_ms: Option<u64>,
+    // 输出顺序，limit 在排序之后生效
+    #[serde(default)]
+    pub sort: QuerySort,
+    // 为true时每个数据点附带写入时间 ingest_timestamp，需要开启 record_ingest_time
+    #[serde(default)]
+    pub include_ingest_time: bool,
+    // 为true时在响应中附带该系列和全局的、与查询时间范围相交的注释
+    #[serde(default)]
+    pub include_annotations: bool,
+}
+
+// 查询结果的排序方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuerySort {
+    // 按时间戳升序
+    #[default]
+    Timestamp,
+    // 按值降序，值相同的按时间戳升序；需要读出整个范围后排序，忽略 max_wait_ms
+    ValueDesc,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DownsampleRequest {
+    pub interval: u64,
+    // 聚合函数，支持 avg/sum/min/max/count/first/last/median/pNN 和 envelope
+    #[serde(rename = "fn", default = "default_downsample_fn")]
+    pub function: String,
+    #[serde(default)]
+    pub fill: FillPolicy,
+    pub start_time: Option<String>,
+    pub end_time: Option<String>,
+    pub last: Option<String>,
+    #[serde(default)]
+    pub all: bool,
+}
+
+fn default_downsample_fn() -> String {
+    "avg".to_string()
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MeasurementAggregateRequest {
+    pub interval: u64,
+    #[serde(rename = "fn", default = "default_downsample_fn")]
+    pub function: String,
+    #[serde(default)]
+    pub fill: FillPolicy,
+    pub start_time: Option<String>,
+    pub end_time: Option<String>,
+    pub last: Option<String>,
+    #[serde(default)]
+    pub all: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MeasurementAggregateResponse {
+    pub measurement: String,
+    pub function: String,
+    pub interval: u64,
+    // 系列键 -> 该系列的降采样桶
+    pub series: BTreeMap<String, Vec<DownsampleBucket>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum DownsampleBuckets {
+    Values(Vec<DownsampleBucket>),
+    Envelope(Vec<EnvelopeBucket>),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DownsampleResponse {
+    #[serde(serialize_with = "serialize_series_key")]
+    pub series_key: String,
+    pub function: String,
+    pub interval: u64,
+    pub fill: FillPolicy,
+    pub buckets: DownsampleBuckets,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NewSeriesRequest {
+    pub since: u64,
+    // 逗号分隔，每个系列只返回这些字段
+    pub fields: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SeriesFirstSeen {
+    #[serde(serialize_with = "serialize_series_key")]
+    pub series_key: String,
+    pub first_seen: u64,
+}
+
+// 指定 fields 时 T 为只含所选字段的JSON对象
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NewSeriesResponse<T = SeriesFirstSeen> {
+    pub since: u64,
+    pub series: Vec<T>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StaleSeriesResponse<T = StaleSeries> {
+    pub threshold_secs: u64,
+    pub series: Vec<T>,
+}
+
+// 只返回部分字段，逗号分隔，如 ?fields=count,last_timestamp
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct FieldsParams {
+    pub fields: Option<String>,
+}
+
+// 系列信息的参数，统计范围与数据点查询相同：默认按 default_query_range_secs，all=true 时统计整个系列
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SeriesInfoParams {
+    pub fields: Option<String>,
+    #[serde(default)]
+    pub all: bool,
+}
+
+// 系列详细信息
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SeriesInfo {
+    #[serde(serialize_with = "serialize_series_key")]
+    pub series_key: String,
+    pub count: usize,
+    pub min_timestamp: Option<u64>,
+    pub max_timestamp: Option<u64>,
+    pub last_timestamp: Option<u64>,
+    pub resolution: Vec<ResolutionSegment>,
+    pub min_value: Option<f64>,
+    pub max_value: Option<f64>,
+    pub tags: Option<VisibleTags>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HistogramRequest {
+    // 逗号分隔的桶边界，如 "0.1,0.5,1"；不指定时使用Prometheus默认边界
+    pub le: Option<String>,
+    pub start_time: Option<String>,
+    pub end_time: Option<String>,
+    pub last: Option<String>,
+    #[serde(default)]
+    pub all: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HistogramResponse {
+    #[serde(serialize_with = "serialize_series_key")]
+    pub series_key: String,
+    pub buckets: Vec<HistogramBucket>,
+    pub sum: f64,
+    pub count: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CrossingsRequest {
+    pub threshold: f64,
+    #[serde(default)]
+    pub direction: CrossingDirection,
+    pub start_time: Option<String>,
+    pub end_time: Option<String>,
+    pub last: Option<String>,
+    #[serde(default)]
+    pub all: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CrossingsResponse {
+    #[serde(serialize_with = "serialize_series_key")]
+    pub series_key: String,
+    pub threshold: f64,
+    pub direction: CrossingDirection,
+    pub crossings: Vec<Crossing>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GapsRequest {
+    pub expected_interval: u64,
+    #[serde(alias = "start")]
+    pub start_time: Option<String>,
+    #[serde(alias = "end")]
+    pub end_time: Option<String>,
+    pub last: Option<String>,
+    #[serde(default)]
+    pub all: bool,
+}
+
+// chunk 为窗口长度，数字加单位 s/m/h/d/w，如 1h。需要指定 start_time 和 end_time，或者 last
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChunksRequest {
+    #[serde(alias = "start")]
+    pub start_time: Option<String>,
+    #[serde(alias = "end")]
+    pub end_time: Option<String>,
+    pub chunk: String,
+    pub last: Option<String>,
+    #[serde(default)]
+    pub all: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChunksResponse {
+    #[serde(serialize_with = "serialize_series_key")]
+    pub series_key: String,
+    // 窗口长度，与时间戳同单位
+    pub chunk: u64,
+    pub chunks: Vec<ChunkSummary>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GapsResponse {
+    #[serde(serialize_with = "serialize_series_key")]
+    pub series_key: String,
+    pub expected_interval: u64,
+    pub gaps: Vec<Gap>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DerivativeRequest {
+    #[serde(alias = "start")]
+    pub start_time: Option<String>,
+    #[serde(alias = "end")]
+    pub end_time: Option<String>,
+    pub last: Option<String>,
+    #[serde(default)]
+    pub all: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DerivativePoint {
+    pub timestamp: u64,
+    // 与前一个点之间每个时间戳单位的变化量
+    pub rate: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DerivativeResponse {
+    #[serde(serialize_with = "serialize_series_key")]
+    pub series_key: String,
+    pub points: Vec<DerivativePoint>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IntegralRequest {
+    #[serde(alias = "start")]
+    pub start_time: Option<String>,
+    #[serde(alias = "end")]
+    pub end_time: Option<String>,
+    pub last: Option<String>,
+    #[serde(default)]
+    pub all: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IntegralResponse {
+    #[serde(serialize_with = "serialize_series_key")]
+    pub series_key: String,
+    // 值×时间戳单位
+    pub integral: f64,
+}
+
+// 多窗口查询，每个窗口为 [start_time, end_time]，两端都包含
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WindowsRequest {
+    pub windows: Vec<(u64, u64)>,
+    // 不指定时返回每个窗口内的原始点
+    #[serde(rename = "fn", default)]
+    pub function: Option<AggregateFn>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WindowsResponse {
+    #[serde(serialize_with = "serialize_series_key")]
+    pub series_key: String,
+    #[serde(rename = "fn")]
+    pub function: Option<AggregateFn>,
+    pub windows: Vec<WindowResult>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CompactRequest {
     pub force: Option<bool>,
+    // 只合并与该时间窗口相交的SSTable和系列块
+    pub start_time: Option<u64>,
+    pub end_time: Option<u64>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct StreamIngestReport {
+    pub inserted: usize,
+    pub failed: usize,
+    // 第一个失败行的错误信息（含行号）
+    pub first_error: Option<String>,
+}
+
+// 批量写入结果
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct BatchInsertResult {
+    // 已解析的数组元素数量
+    pub total: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    // 每个系列成功写入的数量，按系列键排序
+    pub per_series: Vec<SeriesInsertCount>,
+    // 失败元素在数组中的下标（从0开始）及原因，最多返回100条
+    pub errors: Vec<BatchInsertError>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SeriesInsertCount {
+    #[serde(serialize_with = "serialize_series_key")]
+    pub series_key: String,
+    pub count: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BatchInsertError {
+    pub index: usize,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WarmupRequest {
+    // 系列键，以 '*' 结尾表示前缀
+    pub series: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WarmupResponse {
+    pub series: Vec<String>,
+    // 本次启动的预热进度
+    pub status: WarmupStatus,
+}
+
+// 请求体超过大小上限时返回的错误详情
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BodyTooLarge {
+    pub max_body_bytes: usize,
+    // 不受大小限制的流式写入接口
+    pub stream_endpoint: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TruncateRequest {
+    // 必须显式确认，防止误删全部数据
+    #[serde(default)]
+    pub confirm: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EnvelopeParams {
+    // false 时成功响应只返回 data 部分
+    #[serde(default = "default_envelope")]
+    pub envelope: bool,
+}
+
+fn default_envelope() -> bool {
+    true
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct DeleteParams {
+    // 删除受保护的系列时必须与系列键完全相同
+    #[serde(default)]
+    pub confirm: Option<String>,
+    // 为true时删除整个系列的同时删除该系列的注释
+    #[serde(default)]
+    pub delete_annotations: bool,
+}
+
+// 新建注释，timestamp/end_timestamp 的精度由 precision 参数指定。不指定 series_key 时为全局注释
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateAnnotationRequest {
+    #[serde(default)]
+    pub series_key: Option<String>,
+    pub timestamp: u64,
+    // 时间段注释的结束时间戳
+    #[serde(default)]
+    pub end_timestamp: Option<u64>,
+    pub text: String,
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+}
+
+// 查询注释的参数，时间范围的写法与数据点查询相同
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct AnnotationsRequest {
+    pub series_key: Option<String>,
+    pub start_time: Option<String>,
+    pub end_time: Option<String>,
+    #[serde(alias = "range")]
+    pub last: Option<String>,
+}
+
+// 按系列键前缀列出、删除系列或统计
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PrefixParams {
+    #[serde(default)]
+    pub prefix: Option<String>,
+}
+
+// 系列列表的参数，fields 只返回部分字段，如 ?fields=count
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SeriesListParams {
+    #[serde(default)]
+    pub prefix: Option<String>,
+    pub fields: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProtectedSeriesRequest {
+    // 系列键，以 '*' 结尾表示前缀
+    pub series: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SensitiveTagsRequest {
+    pub tag_keys: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SensitiveTagsResponse {
+    #[serde(serialize_with = "serialize_series_key")]
+    pub series_key: String,
+    // 系列单独标记的键
+    pub tag_keys: Vec<String>,
+    // 所有系列共用的键
+    pub global_tag_keys: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RelocateRequest {
+    pub file_name: String,
+    // 目标数据目录，必须是主数据目录或 EXTRA_DATA_DIRS 中的目录
+    pub target_dir: std::path::PathBuf,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TruncateResponse {
+    // 受删除保护而保留的系列
+    pub protected_skipped: Vec<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ImportParams {
+    // 数据库不为空时先清空再导入
+    #[serde(default)]
+    pub force: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -32,6 +614,25 @@ pub struct ApiResponse<T> {
     pub message: String,
     pub data: Option<T>,
     pub timestamp: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stats: Option<QueryStats>,
+    // 仅在请求指定 max_wait_ms 时出现
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub partial: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<u64>,
+    // 仅在写入时指定 return_count 时出现
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub point_count: Option<usize>,
+    // 仅在查询使用相对时间时出现，为换算后的时间范围
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub range: Option<ResolvedRange>,
+    // 仅在查询指定 include_annotations 时出现
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<Vec<Visible<Annotation>>>,
+    // 查询结果预留的内存，响应序列化之后随响应一起释放
+    #[serde(skip)]
+    pub reservation: Option<QueryReservation>,
 }
 
 impl<T> ApiResponse<T> {
@@ -41,6 +642,13 @@ impl<T> ApiResponse<T> {
             message: "操作成功".to_string(),
             data: Some(data),
             timestamp: chrono::Utc::now().timestamp(),
+            stats: None,
+            partial: None,
+            next_cursor: None,
+            point_count: None,
+            range: None,
+            annotations: None,
+            reservation: None,
         }
     }
 
@@ -50,6 +658,13 @@ impl<T> ApiResponse<T> {
             message,
             data: None,
             timestamp: chrono::Utc::now().timestamp(),
+            stats: None,
+            partial: None,
+            next_cursor: None,
+            point_count: None,
+            range: None,
+            annotations: None,
+            reservation: None,
         }
     }
 }
@@ -58,11 +673,15 @@ impl<T> ApiResponse<T> {
 pub struct DataPointResponse {
     pub timestamp: u64,
     pub value: f64,
-    pub tags: BTreeMap<String, String>,
+    pub tags: VisibleTags,
+    // 写入时的服务端时间，只在 include_ingest_time=true 且写入时记录了写入时间时返回
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ingest_timestamp: Option<u64>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SeriesListResponse {
+    #[serde(serialize_with = "serialize_series_keys")]
     pub series: Vec<String>,
     pub count: usize,
 }
@@ -96,3 +715,10 @@ impl ErrorResponse {
     }
 }
 
+#[derive(Debug, Serialize)]
+pub struct AggregateAcrossResponse {
+    pub function: String,
+    pub interval: u64,
+    pub series: Vec<String>,
+    pub buckets: Vec<AggregateBucket>,
+}
@@ -0,0 +1,137 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use axum::http::HeaderMap;
+use serde::{Deserialize, Serialize};
+
+use crate::db::{Annotation, ChangeEvent, DataPoint, DbError, ExportSnapshot, TimeSeriesDB};
+use super::models::DataPointResponse;
+
+// 调用方的API key
+pub const API_KEY_HEADER: &str = "x-api-key";
+// 敏感标签的值在响应中替换为该字符串，标签键保留
+pub const REDACTED_TAG_VALUE: &str = "[redacted]";
+
+// 已按调用方权限处理过的标签或带标签的数据，只能由 TagView 构造。
+// 响应模型中的标签字段都是这个类型，返回标签的接口不经过 TagView 就无法编译
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Visible<T>(T);
+
+impl<T> std::ops::Deref for Visible<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+pub type VisibleTags = Visible<BTreeMap<String, String>>;
+
+// 一次请求能看到的标签。所有返回系列标签的响应都经过这里构造，
+// 敏感标签（全局列表加系列单独标记的键）的值被替换，API key 具有 unredacted 权限时原样返回
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TagView {
+    unredacted: bool,
+}
+
+impl TagView {
+    pub fn from_headers(db: &TimeSeriesDB, headers: &HeaderMap) -> Self {
+        let unredacted = headers
+            .get(API_KEY_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|key| db.config().unredacted_api_keys.iter().any(|allowed| allowed == key));
+        Self { unredacted }
+    }
+
+    pub fn unredacted(&self) -> bool {
+        self.unredacted
+    }
+
+    // 系列的标签，隐藏敏感标签的值
+    pub fn tags(&self, db: &TimeSeriesDB, series_key: &str, mut tags: BTreeMap<String, String>) -> VisibleTags {
+        if !self.unredacted {
+            redact(&db.sensitive_tag_keys(series_key), &mut tags);
+        }
+        Visible(tags)
+    }
+
+    // 注释的标签。series_key 为引擎中的系列键，全局注释为None，只按全局列表隐藏
+    pub fn annotation(&self, db: &TimeSeriesDB, series_key: Option<&str>, mut annotation: Annotation) -> Visible<Annotation> {
+        if !self.unredacted {
+            let sensitive = match series_key {
+                Some(series_key) => db.sensitive_tag_keys(series_key),
+                None => db.config().sensitive_tag_keys.iter().cloned().collect(),
+            };
+            redact(&sensitive, &mut annotation.tags);
+        }
+        Visible(annotation)
+    }
+
+    // 变更流中的事件，插入事件携带的标签同样隐藏
+    pub fn change(&self, db: &TimeSeriesDB, event: ChangeEvent) -> Visible<ChangeEvent> {
+        match event {
+            ChangeEvent::Insert { series_key, timestamp, value, tags } => {
+                let Visible(tags) = self.tags(db, &series_key, tags);
+                Visible(ChangeEvent::Insert { series_key, timestamp, value, tags })
+            }
+            event => Visible(event),
+        }
+    }
+
+    // 转换为响应格式，同一系列的敏感标签只查一次
+    pub fn datapoints(
+        &self,
+        db: &TimeSeriesDB,
+        series_key: &str,
+        datapoints: Vec<DataPoint>,
+        ingest_times: &BTreeMap<u64, u64>,
+    ) -> Vec<DataPointResponse> {
+        let sensitive = if self.unredacted { BTreeSet::new() } else { db.sensitive_tag_keys(series_key) };
+        datapoints
+            .into_iter()
+            .map(|mut dp| {
+                redact(&sensitive, &mut dp.tags);
+                DataPointResponse {
+                    ingest_timestamp: ingest_times.get(&dp.timestamp).copied(),
+                    timestamp: dp.timestamp,
+                    value: dp.value,
+                    tags: Visible(dp.tags),
+                }
+            })
+            .collect()
+    }
+
+    // 错误信息。标签漂移的错误包含系列已有的标签，同样隐藏敏感标签的值
+    pub fn error_message(&self, db: &TimeSeriesDB, error: &std::io::Error) -> String {
+        match DbError::from_io(error) {
+            Some(DbError::TagDrift { series_key, expected, actual }) if !self.unredacted => DbError::TagDrift {
+                series_key: series_key.clone(),
+                expected: self.tags(db, series_key, expected.clone()).0,
+                actual: self.tags(db, series_key, actual.clone()).0,
+            }
+            .to_string(),
+            _ => error.to_string(),
+        }
+    }
+
+    // 能否导出归档。SSTable文件中也保存着原始标签，无法只替换manifest，
+    // 因此有系列带敏感标签时只有 unredacted 权限可以导出。旧格式的文件在块内保存标签，
+    // 要读取归档中每个文件的块列表，在阻塞线程上调用
+    pub fn can_export(&self, db: &TimeSeriesDB, snapshot: &ExportSnapshot) -> std::io::Result<bool> {
+        if self.unredacted {
+            return Ok(true);
+        }
+        Ok(snapshot.series_tag_keys()?.iter().all(|(series_key, keys)| {
+            let sensitive = db.sensitive_tag_keys(series_key);
+            keys.is_disjoint(&sensitive)
+        }))
+    }
+}
+
+fn redact(sensitive: &BTreeSet<String>, tags: &mut BTreeMap<String, String>) {
+    for (key, value) in tags.iter_mut() {
+        if sensitive.contains(key) {
+            *value = REDACTED_TAG_VALUE.to_string();
+        }
+    }
+}
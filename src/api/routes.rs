@@ -0,0 +1,126 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{DefaultBodyLimit, Extension},
+    routing::{get, post, put, delete},
+    Router,
+    middleware::{from_fn, from_fn_with_state},
+};
+
+use super::handlers::{
+    AppState, create_datapoint, create_datapoints_batch, create_datapoints_stream, query_filtered_datapoints,
+    update_datapoint, replace_series_datapoints, delete_datapoint, delete_series, list_series,
+    health_check, db_stats, get_series_info, trigger_compaction, compaction_history,
+    aggregate_across, get_retention_policy, set_retention_policy, enforce_retention,
+    truncate_database, query_crossings, query_downsample, list_new_series,
+    list_stale_series, query_histogram, update_series_tags, query_gaps, limit_request_body,
+    get_warmup, set_warmup, aggregate_measurement, verify_series_bounds, export_database, import_database,
+    list_sstables, get_sstable, update_datapoints_batch, upload_block, query_derivative, query_integral, query_windows, unwrap_envelope, get_quotas, set_quotas,
+    list_operations, cancel_operation, create_annotation, list_annotations, delete_annotation, start_verify, get_verify_job, stream_changes,
+    get_protected_series, set_protected_series, get_series_compression,
+    patch_series_tags, get_overlap_report, get_config, relocate_sstable, compact_series, query_chunks,
+    get_sensitive_tags, set_sensitive_tags, stats_summary, get_downsample_policy, set_downsample_policy,
+    delete_series_prefix, prefix_stats,
+};
+use super::tenant::scope_tenant;
+use crate::server_config::Config;
+
+// 全部API路由及请求体大小限制。日志、超时和CORS等与部署相关的中间件由调用方添加
+pub fn router(db: AppState) -> Router {
+    scope_tenants(db.clone(), routes(&db).with_state(db))
+}
+
+// 全部API路由，另外提供 /api/v1/admin/config 查看服务启动时生效的配置
+pub fn router_with_config(db: AppState, config: Config) -> Router {
+    let routes = routes(&db)
+        .route("/api/v1/admin/config", get(get_config))
+        .layer(Extension(Arc::new(config)))
+        .with_state(db.clone());
+    scope_tenants(db, routes)
+}
+
+// 租户请求的路径需要在路由之前改写，中间件包在完整的路由之外
+fn scope_tenants(db: AppState, routes: Router) -> Router {
+    Router::new()
+        .fallback_service(routes)
+        .layer(from_fn_with_state(db, scope_tenant))
+}
+
+fn routes(db: &AppState) -> Router<AppState> {
+    // 写入接口的请求体大小限制，流式写入接口不受限制
+    let max_request_body_bytes = db.config().max_request_body_bytes;
+    let body_limit = from_fn_with_state(db.clone(), limit_request_body);
+    // 查询接口支持 ?envelope=false 返回不带外层结构的数据
+    let envelope = from_fn(unwrap_envelope);
+    
+    Router::new()
+        // 健康检查和统计
+        .route("/health", get(health_check))
+        .route("/stats", get(db_stats))
+        .route("/api/v1/stats/summary", get(stats_summary))
+        .route("/api/v1/stats/prefix", get(prefix_stats))
+        
+        // 数据点CRUD操作
+        .route("/api/v1/datapoints", post(create_datapoint).route_layer(body_limit.clone()))
+        .route("/api/v1/datapoints/batch", post(create_datapoints_batch).route_layer(body_limit.clone()))
+        .route("/api/v1/datapoints/stream", post(create_datapoints_stream))
+        .route("/api/v1/datapoints/update_batch", post(update_datapoints_batch).route_layer(body_limit.clone()))
+        .route("/api/v1/series/:series_key/datapoints", get(query_filtered_datapoints).route_layer(envelope.clone()))
+        .route("/api/v1/series/:series_key/datapoints", put(replace_series_datapoints).route_layer(body_limit.clone()))
+        .route("/api/v1/series/:series_key/crossings", get(query_crossings).route_layer(envelope.clone()))
+        .route("/api/v1/series/:series_key/downsample", get(query_downsample).route_layer(envelope.clone()))
+        .route("/api/v1/series/:series_key/histogram", get(query_histogram).route_layer(envelope.clone()))
+        .route("/api/v1/series/:series_key/gaps", get(query_gaps).route_layer(envelope.clone()))
+        .route("/api/v1/series/:series_key/derivative", get(query_derivative).route_layer(envelope.clone()))
+        .route("/api/v1/series/:series_key/integral", get(query_integral).route_layer(envelope.clone()))
+        .route("/api/v1/series/:series_key/chunks", get(query_chunks).route_layer(envelope.clone()))
+        .route("/api/v1/series/:series_key/windows", post(query_windows).route_layer(envelope.clone()))
+        .route("/api/v1/series/:series_key/datapoints/:timestamp", put(update_datapoint).route_layer(body_limit.clone()))
+        .route("/api/v1/series/:series_key/datapoints/:timestamp", delete(delete_datapoint))
+        .route("/api/v1/series/:series_key/blocks", post(upload_block).route_layer(body_limit.clone()))
+        
+        // 查询
+        .route("/api/v1/query/aggregate_across", get(aggregate_across).route_layer(envelope.clone()))
+        .route("/api/v1/measurements/:measurement/aggregate", get(aggregate_measurement).route_layer(envelope))
+
+        // 注释
+        .route("/api/v1/annotations", post(create_annotation).route_layer(body_limit.clone()).get(list_annotations))
+        .route("/api/v1/annotations/:id", delete(delete_annotation))
+
+        // 变更订阅
+        .route("/api/v1/stream", get(stream_changes))
+        
+        // 系列管理
+        .route("/api/v1/series", get(list_series).delete(delete_series_prefix))
+        .route("/api/v1/series/new", get(list_new_series))
+        .route("/api/v1/series/stale", get(list_stale_series))
+        .route("/api/v1/series/:series_key", get(get_series_info))
+        .route("/api/v1/series/:series_key", delete(delete_series))
+        .route("/api/v1/series/:series_key/tags", put(update_series_tags).patch(patch_series_tags).route_layer(body_limit))
+        .route("/api/v1/series/:series_key/compression", get(get_series_compression))
+        .route("/api/v1/series/:series_key/sensitive-tags", get(get_sensitive_tags).put(set_sensitive_tags))
+        
+        // 数据库管理
+        .route("/api/v1/admin/compact", post(trigger_compaction))
+        .route("/api/v1/admin/compaction/history", get(compaction_history))
+        .route("/api/v1/admin/series/:series_key/compact", post(compact_series))
+        .route("/api/v1/admin/operations", get(list_operations))
+        .route("/api/v1/admin/operations/:id", delete(cancel_operation))
+        .route("/api/v1/admin/retention", get(get_retention_policy).put(set_retention_policy))
+        .route("/api/v1/admin/retention/enforce", post(enforce_retention))
+        .route("/api/v1/admin/downsampling", get(get_downsample_policy).put(set_downsample_policy))
+        .route("/api/v1/admin/quotas", get(get_quotas).put(set_quotas))
+        .route("/api/v1/admin/warmup", get(get_warmup).put(set_warmup))
+        .route("/api/v1/admin/protected-series", get(get_protected_series).put(set_protected_series))
+        .route("/api/v1/admin/verify", post(start_verify))
+        .route("/api/v1/admin/verify/:id", get(get_verify_job))
+        .route("/api/v1/admin/verify/bounds", post(verify_series_bounds))
+        .route("/api/v1/admin/sstables", get(list_sstables))
+        .route("/api/v1/admin/sstables/:file_name", get(get_sstable))
+        .route("/api/v1/admin/overlap_report", get(get_overlap_report))
+        .route("/api/v1/admin/relocate", post(relocate_sstable))
+        .route("/api/v1/admin/export", get(export_database))
+        .route("/api/v1/admin/import", post(import_database))
+        .route("/api/v1/admin/truncate", post(truncate_database))
+        .layer(DefaultBodyLimit::max(max_request_body_bytes))
+}
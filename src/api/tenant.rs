@@ -0,0 +1,157 @@
+use axum::{
+    extract::{Request, State},
+    http::{uri::PathAndQuery, HeaderMap, StatusCode, Uri},
+    middleware::Next,
+    response::{IntoResponse, Json, Response},
+};
+use serde::Serializer;
+
+use crate::db::TimeSeriesDB;
+use super::models::ApiResponse;
+use super::redaction::API_KEY_HEADER;
+
+// 租户可以访问的不带系列键的接口，其余接口只能由其他调用方访问
+const TENANT_PATHS: &[&str] = &[
+    "/health",
+    "/api/v1/datapoints",
+    "/api/v1/datapoints/batch",
+    "/api/v1/datapoints/stream",
+    "/api/v1/datapoints/update_batch",
+    "/api/v1/series",
+    "/api/v1/series/new",
+    "/api/v1/series/stale",
+    "/api/v1/stats/prefix",
+    "/api/v1/annotations",
+];
+
+const SERIES_PATH: &str = "/api/v1/series/";
+// 按编号删除注释，由接口检查注释属于租户的系列
+const ANNOTATION_PATH: &str = "/api/v1/annotations/";
+
+// 一次请求所属的租户。API key 绑定了前缀时只能访问以该前缀开头的系列，
+// 请求中的系列键自动加上前缀，响应中的系列键去掉前缀；其他调用方不受限制
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Tenant {
+    prefix: Option<String>,
+}
+
+impl Tenant {
+    pub fn from_headers(db: &TimeSeriesDB, headers: &HeaderMap) -> Self {
+        let prefix = headers
+            .get(API_KEY_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|key| db.config().tenant_api_keys.get(key))
+            .cloned();
+        Self { prefix }
+    }
+
+    pub fn prefix(&self) -> Option<&str> {
+        self.prefix.as_deref()
+    }
+
+    // 请求中的系列键对应的引擎中的键
+    pub fn scope(&self, series_key: &str) -> String {
+        match &self.prefix {
+            Some(prefix) => format!("{}{}", prefix, series_key),
+            None => series_key.to_string(),
+        }
+    }
+
+    // 请求中的键前缀对应的引擎中的前缀，租户不指定时为整个租户的前缀
+    pub fn scope_prefix(&self, prefix: Option<&str>) -> Option<String> {
+        match &self.prefix {
+            Some(_) => Some(self.scope(prefix.unwrap_or_default())),
+            None => prefix.map(str::to_string),
+        }
+    }
+
+    // 系列是否可以被该请求看到
+    pub fn owns(&self, series_key: &str) -> bool {
+        self.prefix.as_deref().is_none_or(|prefix| series_key.starts_with(prefix))
+    }
+
+    // 租户请求改写后的路径：路径中的系列键加上前缀；不能访问的接口返回None
+    fn scoped_path(&self, path: &str) -> Option<String> {
+        let prefix = self.prefix.as_deref()?;
+        if TENANT_PATHS.contains(&path) || path.strip_prefix(ANNOTATION_PATH).is_some_and(|id| !id.contains('/')) {
+            return Some(path.to_string());
+        }
+        let rest = path.strip_prefix(SERIES_PATH)?;
+        let (series_key, tail) = rest.find('/').map_or((rest, ""), |index| rest.split_at(index));
+        if series_key.is_empty() {
+            return None;
+        }
+        Some(format!("{}{}{}{}", SERIES_PATH, encode_path_segment(prefix), series_key, tail))
+    }
+}
+
+tokio::task_local! {
+    // 正在处理的租户请求的前缀，响应模型中的系列键字段序列化时去掉
+    static RESPONSE_PREFIX: String;
+}
+
+// 响应中的系列键：租户请求去掉前缀，其他请求原样返回
+pub fn unscoped(series_key: &str) -> &str {
+    RESPONSE_PREFIX
+        .try_with(|prefix| series_key.strip_prefix(prefix.as_str()).map(|rest| series_key.len() - rest.len()))
+        .ok()
+        .flatten()
+        .map_or(series_key, |start| &series_key[start..])
+}
+
+// 响应模型中系列键字段的序列化
+pub fn serialize_series_key<S: Serializer>(series_key: &str, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(unscoped(series_key))
+}
+
+pub fn serialize_series_keys<S: Serializer>(series_keys: &[String], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(series_keys.iter().map(|series_key| unscoped(series_key)))
+}
+
+// 路径中的前缀按百分号编码，前缀中的 '/' 等字符不会改变路由
+fn encode_path_segment(segment: &str) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+    for byte in segment.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~') {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+// 中间件：租户请求在路由之前改写路径中的系列键，不能访问的接口返回403，
+// 响应模型中的系列键字段去掉前缀。写入和列表接口的请求体和参数由各接口通过 Tenant 处理
+pub async fn scope_tenant(State(db): State<TimeSeriesDB>, mut req: Request, next: Next) -> Response {
+    let tenant = Tenant::from_headers(&db, req.headers());
+    if tenant.prefix().is_none() {
+        return next.run(req).await;
+    }
+
+    let Some(path) = tenant.scoped_path(req.uri().path()) else {
+        tracing::warn!("租户API key不能访问 {} {}", req.method(), req.uri().path());
+        return (StatusCode::FORBIDDEN, Json(ApiResponse::<()>::error("租户API key不能访问该接口".to_string()))).into_response();
+    };
+    let path_and_query = match req.uri().query() {
+        Some(query) => format!("{}?{}", path, query),
+        None => path,
+    };
+    let mut parts = req.uri().clone().into_parts();
+    let uri = path_and_query
+        .parse::<PathAndQuery>()
+        .map_err(|e| e.to_string())
+        .and_then(|path_and_query| {
+            parts.path_and_query = Some(path_and_query);
+            Uri::from_parts(parts).map_err(|e| e.to_string())
+        });
+    match uri {
+        Ok(uri) => *req.uri_mut() = uri,
+        Err(e) => {
+            return (StatusCode::BAD_REQUEST, Json(ApiResponse::<()>::error(format!("无效的请求路径: {}", e)))).into_response();
+        }
+    }
+
+    // 处理函数在该作用域内序列化响应，只有系列键字段去掉前缀；流式响应体不经过这里
+    RESPONSE_PREFIX.scope(tenant.prefix.unwrap_or_default(), next.run(req)).await
+}
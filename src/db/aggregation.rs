@@ -0,0 +1,437 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+// 填充空桶时最多生成的桶数量
+pub const MAX_FILL_BUCKETS: u64 = 100_000;
+
+// 聚合函数
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AggregateFn {
+    Avg,
+    Sum,
+    Min,
+    Max,
+    Count,
+    First,
+    Last,
+    // 百分位，取值范围 [0, 100]
+    Percentile(f64),
+}
+
+impl FromStr for AggregateFn {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "avg" | "mean" => Ok(Self::Avg),
+            "sum" => Ok(Self::Sum),
+            "min" => Ok(Self::Min),
+            "max" => Ok(Self::Max),
+            "count" => Ok(Self::Count),
+            "first" => Ok(Self::First),
+            "last" => Ok(Self::Last),
+            "median" => Ok(Self::Percentile(50.0)),
+            other => {
+                let quantile = other
+                    .strip_prefix('p')
+                    .and_then(|q| q.parse::<f64>().ok())
+                    .filter(|q| (0.0..=100.0).contains(q))
+                    .ok_or_else(|| format!("不支持的聚合函数: {}", s))?;
+                Ok(Self::Percentile(quantile))
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for AggregateFn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Avg => write!(f, "avg"),
+            Self::Sum => write!(f, "sum"),
+            Self::Min => write!(f, "min"),
+            Self::Max => write!(f, "max"),
+            Self::Count => write!(f, "count"),
+            Self::First => write!(f, "first"),
+            Self::Last => write!(f, "last"),
+            Self::Percentile(q) => write!(f, "p{}", q),
+        }
+    }
+}
+
+// 序列化为与查询参数相同的名称，如 "avg"、"p95"
+impl Serialize for AggregateFn {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for AggregateFn {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl AggregateFn {
+    // 对一组按时间排序的值计算聚合结果，空输入返回None
+    pub fn apply(&self, values: &[f64]) -> Option<f64> {
+        if values.is_empty() {
+            return None;
+        }
+        let result = match self {
+            Self::Avg => values.iter().sum::<f64>() / values.len() as f64,
+            Self::Sum => values.iter().sum(),
+            Self::Min => values.iter().copied().fold(f64::INFINITY, f64::min),
+            Self::Max => values.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+            Self::Count => values.len() as f64,
+            Self::First => values[0],
+            Self::Last => values[values.len() - 1],
+            Self::Percentile(q) => {
+                let mut sorted = values.to_vec();
+                return percentile(&mut sorted, *q);
+            }
+        };
+        Some(result)
+    }
+}
+
+// 线性插值百分位（与numpy默认算法一致），q取值 [0, 100]
+pub fn percentile(values: &mut [f64], q: f64) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(|a, b| a.total_cmp(b));
+
+    let rank = (q / 100.0).clamp(0.0, 1.0) * (values.len() - 1) as f64;
+    let lower = rank.floor() as usize;
+    let upper = rank.ceil() as usize;
+    let weight = rank - lower as f64;
+    Some(values[lower] + (values[upper] - values[lower]) * weight)
+}
+
+// 时间戳所属桶的起始时间
+pub fn bucket_start(timestamp: u64, interval: u64) -> u64 {
+    if interval == 0 {
+        return timestamp;
+    }
+    timestamp - timestamp % interval
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct AggregateBucket {
+    pub timestamp: u64,
+    pub value: f64,
+    // 参与该桶计算的系列数量
+    pub series_count: usize,
+}
+
+// 单个桶的增量聚合状态。除百分位外只保存常数大小的状态，不保留原始值，
+// 结果与对同一组值调用 AggregateFn::apply 一致
+#[derive(Debug, Clone)]
+pub struct BucketAccumulator {
+    function: AggregateFn,
+    count: usize,
+    sum: f64,
+    min: f64,
+    max: f64,
+    first: Option<f64>,
+    last: Option<f64>,
+    // 百分位需要全部值
+    values: Vec<f64>,
+}
+
+impl BucketAccumulator {
+    pub fn new(function: AggregateFn) -> Self {
+        Self {
+            function,
+            count: 0,
+            sum: 0.0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            first: None,
+            last: None,
+            values: Vec::new(),
+        }
+    }
+
+    // 按时间顺序加入一个值
+    pub fn push(&mut self, value: f64) {
+        self.count += 1;
+        match self.function {
+            AggregateFn::Avg | AggregateFn::Sum => self.sum += value,
+            AggregateFn::Min => self.min = self.min.min(value),
+            AggregateFn::Max => self.max = self.max.max(value),
+            AggregateFn::Count => {}
+            AggregateFn::First => {
+                self.first.get_or_insert(value);
+            }
+            AggregateFn::Last => self.last = Some(value),
+            AggregateFn::Percentile(_) => self.values.push(value),
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    // 为计算结果保留的原始值个数，只有百分位不为0
+    pub fn buffered(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn finish(self) -> Option<f64> {
+        if self.count == 0 {
+            return None;
+        }
+        match self.function {
+            AggregateFn::Avg => Some(self.sum / self.count as f64),
+            AggregateFn::Sum => Some(self.sum),
+            AggregateFn::Min => Some(self.min),
+            AggregateFn::Max => Some(self.max),
+            AggregateFn::Count => Some(self.count as f64),
+            AggregateFn::First => self.first,
+            AggregateFn::Last => self.last,
+            AggregateFn::Percentile(q) => {
+                let mut values = self.values;
+                percentile(&mut values, q)
+            }
+        }
+    }
+}
+
+// 跨系列按桶聚合的累加器：每个桶增量汇总所有系列的值
+#[derive(Debug)]
+pub struct CrossSeriesBuckets {
+    function: AggregateFn,
+    buckets: BTreeMap<u64, (BucketAccumulator, usize)>,
+}
+
+impl CrossSeriesBuckets {
+    pub fn new(function: AggregateFn) -> Self {
+        Self {
+            function,
+            buckets: BTreeMap::new(),
+        }
+    }
+
+    // 加入一个系列按时间排序的数据点，series_count按系列计数
+    pub fn add_series(&mut self, points: impl IntoIterator<Item = (u64, f64)>, interval: u64) {
+        let mut last_bucket = None;
+        for (timestamp, value) in points {
+            let bucket = bucket_start(timestamp, interval);
+            let entry = self
+                .buckets
+                .entry(bucket)
+                .or_insert_with(|| (BucketAccumulator::new(self.function), 0));
+            entry.0.push(value);
+            if last_bucket != Some(bucket) {
+                entry.1 += 1;
+                last_bucket = Some(bucket);
+            }
+        }
+    }
+
+    pub fn finish(self) -> Vec<AggregateBucket> {
+        self.buckets
+            .into_iter()
+            .filter_map(|(timestamp, (accumulator, series_count))| {
+                accumulator.finish().map(|value| AggregateBucket {
+                    timestamp,
+                    value,
+                    series_count,
+                })
+            })
+            .collect()
+    }
+}
+
+// 降采样时空桶的填充策略
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FillPolicy {
+    // 不输出空桶
+    #[default]
+    None,
+    // 输出空桶，值为null
+    Null,
+}
+
+impl FromStr for FillPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(Self::None),
+            "null" => Ok(Self::Null),
+            other => Err(format!("不支持的填充策略: {}，可选 none/null", other)),
+        }
+    }
+}
+
+pub trait TimeBucket {
+    fn bucket_ts(&self) -> u64;
+    fn empty(bucket_ts: u64) -> Self;
+}
+
+impl FillPolicy {
+    // 按策略补齐 [first, last] 之间的空桶，未指定时使用已有桶的首尾。buckets需按时间排序
+    pub fn apply<B: TimeBucket>(
+        self,
+        buckets: Vec<B>,
+        first: Option<u64>,
+        last: Option<u64>,
+        interval: u64,
+    ) -> Result<Vec<B>, String> {
+        if self == Self::None || interval == 0 {
+            return Ok(buckets);
+        }
+        let (Some(first), Some(last)) = (
+            first.or_else(|| buckets.first().map(|b| b.bucket_ts())),
+            last.or_else(|| buckets.last().map(|b| b.bucket_ts())),
+        ) else {
+            return Ok(buckets);
+        };
+        if first > last {
+            return Ok(Vec::new());
+        }
+        if (last - first) / interval >= MAX_FILL_BUCKETS {
+            return Err(format!("填充后的桶数量超过上限 {}，请缩小时间范围或增大interval", MAX_FILL_BUCKETS));
+        }
+
+        let mut filled = Vec::new();
+        let mut existing = buckets.into_iter().peekable();
+        let mut bucket_ts = first;
+        loop {
+            match existing.next_if(|b| b.bucket_ts() == bucket_ts) {
+                Some(bucket) => filled.push(bucket),
+                None => filled.push(B::empty(bucket_ts)),
+            }
+            if bucket_ts >= last {
+                break;
+            }
+            bucket_ts += interval;
+        }
+        Ok(filled)
+    }
+}
+
+// 单系列降采样的桶，空桶的value为null
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DownsampleBucket {
+    pub timestamp: u64,
+    pub value: Option<f64>,
+    pub count: usize,
+}
+
+impl TimeBucket for DownsampleBucket {
+    fn bucket_ts(&self) -> u64 {
+        self.timestamp
+    }
+
+    fn empty(bucket_ts: u64) -> Self {
+        Self { timestamp: bucket_ts, value: None, count: 0 }
+    }
+}
+
+// 包络降采样：每个桶保留最小、最大、平均、首、尾值，不会像平均值那样抹掉尖峰
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct EnvelopeBucket {
+    pub bucket_ts: u64,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub avg: Option<f64>,
+    pub first: Option<f64>,
+    pub last: Option<f64>,
+    pub count: usize,
+}
+
+impl EnvelopeBucket {
+    // 按时间顺序加入一个值
+    pub fn push(&mut self, value: f64) {
+        self.min = Some(self.min.map_or(value, |min| min.min(value)));
+        self.max = Some(self.max.map_or(value, |max| max.max(value)));
+        self.first.get_or_insert(value);
+        self.last = Some(value);
+        self.count += 1;
+        // 增量均值，不需要另外保存总和
+        let avg = self.avg.unwrap_or(0.0);
+        self.avg = Some(avg + (value - avg) / self.count as f64);
+    }
+}
+
+impl TimeBucket for EnvelopeBucket {
+    fn bucket_ts(&self) -> u64 {
+        self.bucket_ts
+    }
+
+    fn empty(bucket_ts: u64) -> Self {
+        Self { bucket_ts, min: None, max: None, avg: None, first: None, last: None, count: 0 }
+    }
+}
+
+// Prometheus客户端库的默认桶边界
+pub const DEFAULT_HISTOGRAM_BOUNDS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+// 累积直方图桶，le格式与Prometheus一致（最后一个为 "+Inf"）
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct HistogramBucket {
+    pub le: String,
+    pub count: u64,
+}
+
+// 累积直方图：每个桶统计 value <= le 的数量，输出可直接用于 histogram_quantile
+#[derive(Debug, Clone)]
+pub struct CumulativeHistogram {
+    bounds: Vec<f64>,
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl CumulativeHistogram {
+    // 边界需为严格递增的有限值，+Inf 桶自动追加
+    pub fn new(bounds: Vec<f64>) -> Result<Self, String> {
+        if bounds.iter().any(|b| !b.is_finite()) {
+            return Err("le边界必须是有限数值，+Inf桶会自动添加".to_string());
+        }
+        if bounds.windows(2).any(|w| w[0] >= w[1]) {
+            return Err("le边界必须严格递增".to_string());
+        }
+        let counts = vec![0; bounds.len()];
+        Ok(Self { bounds, counts, sum: 0.0, count: 0 })
+    }
+
+    pub fn observe(&mut self, value: f64) {
+        // 第一个 >= value 的边界，之后的桶都包含该值（累积在输出时计算）
+        let index = self.bounds.partition_point(|bound| *bound < value);
+        if index < self.counts.len() {
+            self.counts[index] += 1;
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    pub fn sum(&self) -> f64 {
+        self.sum
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn buckets(&self) -> Vec<HistogramBucket> {
+        let mut cumulative = 0;
+        let mut buckets: Vec<HistogramBucket> = self
+            .bounds
+            .iter()
+            .zip(&self.counts)
+            .map(|(bound, count)| {
+                cumulative += count;
+                HistogramBucket { le: bound.to_string(), count: cumulative }
+            })
+            .collect();
+        buckets.push(HistogramBucket { le: "+Inf".to_string(), count: self.count });
+        buckets
+    }
+}
@@ -0,0 +1,169 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Result, Write};
+use std::path::{Path, PathBuf};
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+pub const ANNOTATIONS_FILE: &str = "ANNOTATIONS.json";
+
+// 注释文本的最大长度（字节）
+pub const MAX_ANNOTATION_TEXT_BYTES: usize = 4096;
+
+// 与系列数据放在一起的事件注释（部署标记、故障说明等），时间戳为内部精度
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Annotation {
+    pub id: u64,
+    // 所属系列，None 为全局注释
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub series_key: Option<String>,
+    pub timestamp: u64,
+    // 时间段注释的结束时间戳，时间点注释为None
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end_timestamp: Option<u64>,
+    pub text: String,
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+}
+
+impl Annotation {
+    // 注释的时间段与 [start_time, end_time] 相交
+    pub fn overlaps(&self, start_time: Option<u64>, end_time: Option<u64>) -> bool {
+        let last = self.end_timestamp.unwrap_or(self.timestamp);
+        start_time.is_none_or(|start| last >= start) && end_time.is_none_or(|end| self.timestamp <= end)
+    }
+}
+
+// 新建注释的内容，编号由存储分配
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NewAnnotation {
+    pub series_key: Option<String>,
+    pub timestamp: u64,
+    pub end_timestamp: Option<u64>,
+    pub text: String,
+    pub tags: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct AnnotationFile {
+    next_id: u64,
+    // 按时间戳、编号排序
+    annotations: Vec<Annotation>,
+}
+
+// 注释不经过内存表和Gorilla压缩，全部保存在数据目录的 ANNOTATIONS.json 中，每次修改整体重写。
+// 适合数量不多的注释，查询时逐条比较
+#[derive(Debug)]
+pub struct AnnotationStore {
+    path: PathBuf,
+    state: Mutex<AnnotationFile>,
+    // 修改串行进行，写文件期间只持有这把锁，查询不等待文件写入
+    writer: Mutex<()>,
+}
+
+impl AnnotationStore {
+    pub fn open(data_dir: &Path) -> Result<Self> {
+        let path = data_dir.join(ANNOTATIONS_FILE);
+        let state = if path.exists() {
+            let content = fs::read(&path)?;
+            serde_json::from_slice(&content).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?
+        } else {
+            AnnotationFile { next_id: 1, annotations: Vec::new() }
+        };
+        Ok(Self { path, state: Mutex::new(state), writer: Mutex::new(()) })
+    }
+
+    // 先修改内存中的列表并序列化，写文件时不持有列表的锁，写入失败时撤销修改
+    pub fn add(&self, annotation: NewAnnotation) -> Result<Annotation> {
+        let _writer = self.writer.lock();
+        let (annotation, content) = {
+            let mut state = self.state.lock();
+            let annotation = Annotation {
+                id: state.next_id,
+                series_key: annotation.series_key,
+                timestamp: annotation.timestamp,
+                end_timestamp: annotation.end_timestamp,
+                text: annotation.text,
+                tags: annotation.tags,
+            };
+            let index = state
+                .annotations
+                .partition_point(|existing| (existing.timestamp, existing.id) <= (annotation.timestamp, annotation.id));
+            state.annotations.insert(index, annotation.clone());
+            state.next_id += 1;
+            (annotation, serialize(&state))
+        };
+        if let Err(e) = content.and_then(|content| self.save(&content)) {
+            let mut state = self.state.lock();
+            state.annotations.retain(|existing| existing.id != annotation.id);
+            state.next_id -= 1;
+            return Err(e);
+        }
+        Ok(annotation)
+    }
+
+    // 与时间范围相交的注释，按时间戳排序。指定系列时返回该系列的注释和全局注释
+    pub fn query(&self, series_key: Option<&str>, start_time: Option<u64>, end_time: Option<u64>) -> Vec<Annotation> {
+        self.state
+            .lock()
+            .annotations
+            .iter()
+            .filter(|annotation| {
+                series_key.is_none_or(|key| annotation.series_key.as_deref().is_none_or(|own| own == key))
+            })
+            .filter(|annotation| annotation.overlaps(start_time, end_time))
+            .cloned()
+            .collect()
+    }
+
+    pub fn get(&self, id: u64) -> Option<Annotation> {
+        self.state.lock().annotations.iter().find(|annotation| annotation.id == id).cloned()
+    }
+
+    // 删除满足条件的注释，返回删除的条数。只保留被删除的注释用于写入失败时放回，不复制整个列表
+    pub fn remove_where(&self, mut remove: impl FnMut(&Annotation) -> bool) -> Result<usize> {
+        let _writer = self.writer.lock();
+        let (removed, content) = {
+            let mut state = self.state.lock();
+            let (removed, kept): (Vec<Annotation>, Vec<Annotation>) =
+                std::mem::take(&mut state.annotations).into_iter().partition(|annotation| remove(annotation));
+            state.annotations = kept;
+            if removed.is_empty() {
+                return Ok(0);
+            }
+            (removed, serialize(&state))
+        };
+        if let Err(e) = content.and_then(|content| self.save(&content)) {
+            let mut state = self.state.lock();
+            state.annotations.extend(removed);
+            state.annotations.sort_by_key(|annotation| (annotation.timestamp, annotation.id));
+            return Err(e);
+        }
+        Ok(removed.len())
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().annotations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // 与manifest一样先写临时文件再重命名
+    fn save(&self, content: &[u8]) -> Result<()> {
+        let tmp_path = self.path.with_extension("json.tmp");
+
+        let mut file = fs::File::create(&tmp_path)?;
+        file.write_all(content)?;
+        file.sync_all()?;
+        drop(file);
+
+        fs::rename(&tmp_path, &self.path)
+    }
+}
+
+fn serialize(state: &AnnotationFile) -> Result<Vec<u8>> {
+    serde_json::to_vec_pretty(state).map_err(std::io::Error::other)
+}
@@ -0,0 +1,432 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File};
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::OwnedSemaphorePermit;
+
+use super::{Manifest, OperationHandle, SSTable, SeriesData, SeriesWalk, TimePrecision, MANIFEST_FILE, SSTABLE_FORMAT_VERSION};
+
+// 导出归档的结构:
+//   sstables/<文件名>  按SSTable列表顺序排列的数据文件，内容与数据目录中的文件相同
+//   MANIFEST.json      导出时数据目录的manifest（系列标签、首次写入时间、保留策略等）
+//   EXPORT.json        最后写入的导出清单，记录每个文件的大小和CRC32
+pub const EXPORT_FILE: &str = "EXPORT.json";
+pub const EXPORT_VERSION: u32 = 1;
+const SSTABLE_ENTRY_DIR: &str = "sstables";
+// 数据目录中导出快照和导入暂存目录的前缀，启动时清理残留
+pub(crate) const EXPORT_DIR_PREFIX: &str = "export_";
+pub(crate) const IMPORT_DIR_PREFIX: &str = "import_";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportedFile {
+    pub file_name: String,
+    pub size_bytes: u64,
+    pub checksum: u32,
+}
+
+// 导出清单
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExportManifest {
+    pub export_version: u32,
+    // 导出方支持的SSTable格式版本
+    pub format_version: u16,
+    pub timestamp_precision: TimePrecision,
+    pub files: Vec<ExportedFile>,
+    pub manifest_checksum: u32,
+    pub series_count: usize,
+    pub min_timestamp: Option<u64>,
+    pub max_timestamp: Option<u64>,
+}
+
+// 导入结果
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ImportReport {
+    pub files_imported: usize,
+    pub bytes_imported: u64,
+    pub series_count: usize,
+    pub min_timestamp: Option<u64>,
+    pub max_timestamp: Option<u64>,
+}
+
+fn invalid_archive(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("导入归档无效: {}", message))
+}
+
+// 读取时累计CRC32和字节数
+struct ChecksumReader<R> {
+    inner: R,
+    hasher: crc32fast::Hasher,
+    bytes: u64,
+}
+
+impl<R: Read> ChecksumReader<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            hasher: crc32fast::Hasher::new(),
+            bytes: 0,
+        }
+    }
+
+    fn finish(self) -> (u64, u32) {
+        (self.bytes, self.hasher.finalize())
+    }
+}
+
+impl<R: Read> Read for ChecksumReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.bytes += n as u64;
+        Ok(n)
+    }
+}
+
+// 只计算写入内容的CRC32和字节数，丢弃数据
+#[derive(Default)]
+struct ChecksumSink {
+    hasher: crc32fast::Hasher,
+    bytes: u64,
+}
+
+impl Write for ChecksumSink {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.hasher.update(buf);
+        self.bytes += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+// 快照中的系列数、时间范围和块内保存的标签键
+#[derive(Debug, Default)]
+struct SeriesSummary {
+    series_count: usize,
+    min_timestamp: Option<u64>,
+    max_timestamp: Option<u64>,
+    tag_keys: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl SeriesSummary {
+    // 用 SeriesWalk 对快照文件做一次遍历，只读取块列表，不解压
+    fn from_files(files: &[PathBuf]) -> Result<Self> {
+        let mut summary = Self::default();
+        for entry in SeriesWalk::from_files(files)? {
+            let mut has_points = false;
+            for block in entry.blocks() {
+                if !block.tags.is_empty() {
+                    summary.tag_keys.entry(entry.series_key.clone()).or_default().extend(block.tags.keys().cloned());
+                }
+                if block.count == 0 {
+                    continue;
+                }
+                has_points = true;
+                summary.min_timestamp = Some(summary.min_timestamp.map_or(block.min_timestamp, |ts| ts.min(block.min_timestamp)));
+                summary.max_timestamp = Some(summary.max_timestamp.map_or(block.max_timestamp, |ts| ts.max(block.max_timestamp)));
+            }
+            summary.series_count += usize::from(has_points);
+        }
+        Ok(summary)
+    }
+}
+
+fn append_entry<W: Write>(builder: &mut tar::Builder<W>, name: &str, size: u64, data: impl Read) -> Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(size);
+    header.set_mode(0o644);
+    header.set_mtime(0);
+    builder.append_data(&mut header, name, data)
+}
+
+// 导出时的一致性快照：在SSTable列表锁内把所有文件硬链接到临时目录，之后的compaction、
+// 删除等操作只会替换或删除数据目录中的文件，不影响快照。快照释放时删除临时目录。
+// 归档内容只取决于快照：条目按SSTable列表顺序排列，mtime、属主固定为0，manifest中的映射都是有序的，
+// 同样的数据导出两次得到完全相同的字节
+#[derive(Debug)]
+pub struct ExportSnapshot {
+    dir: PathBuf,
+    files: Vec<PathBuf>,
+    manifest: Manifest,
+    timestamp_precision: TimePrecision,
+    // 在操作登记表中的条目，写归档期间可以取消
+    operation: Option<OperationHandle>,
+    // 维护许可，快照丢弃时释放
+    _permit: Option<OwnedSemaphorePermit>,
+    // 第一次用到时遍历快照文件生成，权限检查、计算校验和与写归档共用
+    summary: OnceLock<SeriesSummary>,
+}
+
+impl ExportSnapshot {
+    pub(crate) fn create(dir: PathBuf, sstables: &[SSTable], manifest: Manifest, timestamp_precision: TimePrecision) -> Result<Self> {
+        fs::create_dir_all(&dir)?;
+        let mut snapshot = Self {
+            dir,
+            files: Vec::with_capacity(sstables.len()),
+            manifest,
+            timestamp_precision,
+            operation: None,
+            _permit: None,
+            summary: OnceLock::new(),
+        };
+        for sstable in sstables {
+            let target = snapshot.dir.join(sstable.file_name());
+            // 不支持硬链接的文件系统退回复制
+            if fs::hard_link(sstable.file_path(), &target).is_err() {
+                fs::copy(sstable.file_path(), &target)?;
+            }
+            snapshot.files.push(target);
+        }
+        Ok(snapshot)
+    }
+
+    pub(crate) fn with_operation(mut self, operation: OperationHandle) -> Self {
+        self.operation = Some(operation);
+        self
+    }
+
+    pub(crate) fn with_permit(mut self, permit: OwnedSemaphorePermit) -> Self {
+        self._permit = Some(permit);
+        self
+    }
+
+    pub fn file_count(&self) -> usize {
+        self.files.len()
+    }
+
+    // 归档中每个系列出现过的标签键：manifest 中的标签加上旧格式文件在块内保存的标签
+    pub fn series_tag_keys(&self) -> Result<BTreeMap<String, BTreeSet<String>>> {
+        let mut keys: BTreeMap<String, BTreeSet<String>> = self
+            .manifest
+            .series_tags
+            .iter()
+            .map(|(series_key, tags)| (series_key.clone(), tags.keys().cloned().collect()))
+            .collect();
+        for (series_key, tag_keys) in &self.summary()?.tag_keys {
+            keys.entry(series_key.clone()).or_default().extend(tag_keys.iter().cloned());
+        }
+        Ok(keys)
+    }
+
+    fn summary(&self) -> Result<&SeriesSummary> {
+        if let Some(summary) = self.summary.get() {
+            return Ok(summary);
+        }
+        let summary = SeriesSummary::from_files(&self.files)?;
+        Ok(self.summary.get_or_init(|| summary))
+    }
+
+    // 逐个文件写入tar，每次只缓冲一个tar块，内存占用与数据量无关
+    pub fn write_tar<W: Write>(&self, writer: W) -> Result<(W, ExportManifest)> {
+        self.write_tar_stage(writer, "writing")
+    }
+
+    // 预先生成一遍归档（不保存），返回 (字节数, CRC32)，与随后 write_tar 写出的内容一致
+    pub fn checksum(&self) -> Result<(u64, u32)> {
+        let (sink, _) = self.write_tar_stage(ChecksumSink::default(), "checksumming")?;
+        Ok((sink.bytes, sink.hasher.finalize()))
+    }
+
+    fn write_tar_stage<W: Write>(&self, writer: W, stage: &'static str) -> Result<(W, ExportManifest)> {
+        let mut builder = tar::Builder::new(writer);
+        let mut files = Vec::with_capacity(self.files.len());
+        let summary = self.summary()?;
+
+        if let Some(operation) = &self.operation {
+            operation.begin_stage(stage, self.files.len());
+        }
+        for path in &self.files {
+            // 取消后归档不完整，接收方会因缺少导出清单而拒绝导入
+            if let Some(operation) = &self.operation {
+                operation.check_cancelled()?;
+            }
+            let file_name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+            let size = fs::metadata(path)?.len();
+            let mut reader = ChecksumReader::new(File::open(path)?.take(size));
+            append_entry(&mut builder, &format!("{}/{}", SSTABLE_ENTRY_DIR, file_name), size, &mut reader)?;
+            let (size_bytes, checksum) = reader.finish();
+            files.push(ExportedFile { file_name, size_bytes, checksum });
+            if let Some(operation) = &self.operation {
+                operation.file_done();
+            }
+        }
+
+        let manifest = serde_json::to_vec_pretty(&self.manifest).map_err(Error::other)?;
+        append_entry(&mut builder, MANIFEST_FILE, manifest.len() as u64, manifest.as_slice())?;
+
+        let export = ExportManifest {
+            export_version: EXPORT_VERSION,
+            format_version: SSTABLE_FORMAT_VERSION,
+            timestamp_precision: self.timestamp_precision,
+            files,
+            manifest_checksum: crc32fast::hash(&manifest),
+            series_count: summary.series_count,
+            min_timestamp: summary.min_timestamp,
+            max_timestamp: summary.max_timestamp,
+        };
+        let content = serde_json::to_vec_pretty(&export).map_err(Error::other)?;
+        append_entry(&mut builder, EXPORT_FILE, content.len() as u64, content.as_slice())?;
+
+        let writer = builder.into_inner()?;
+        Ok((writer, export))
+    }
+}
+
+impl Drop for ExportSnapshot {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir_all(&self.dir) {
+            tracing::warn!("删除导出快照目录失败 {:?}: {}", self.dir, e);
+        }
+    }
+}
+
+// 解包并校验完成的归档，文件位于临时目录中，释放时删除临时目录
+#[derive(Debug)]
+pub struct ImportedArchive {
+    dir: PathBuf,
+    pub files: Vec<PathBuf>,
+    pub manifest: Manifest,
+    pub export: ExportManifest,
+    // 每个文件的系列块元数据，与 files 一一对应
+    pub blocks: Vec<Vec<SeriesData>>,
+}
+
+impl ImportedArchive {
+    pub fn report(&self) -> ImportReport {
+        ImportReport {
+            files_imported: self.files.len(),
+            bytes_imported: self.export.files.iter().map(|file| file.size_bytes).sum(),
+            series_count: self.export.series_count,
+            min_timestamp: self.export.min_timestamp,
+            max_timestamp: self.export.max_timestamp,
+        }
+    }
+}
+
+impl Drop for ImportedArchive {
+    fn drop(&mut self) {
+        if self.dir.exists() {
+            if let Err(e) = fs::remove_dir_all(&self.dir) {
+                tracing::warn!("删除导入临时目录失败 {:?}: {}", self.dir, e);
+            }
+        }
+    }
+}
+
+// SSTable条目只能是 sstables/ 下的单个 .data 文件名，拒绝路径穿越
+fn sstable_entry_name(path: &Path) -> Option<String> {
+    let mut components = path.components();
+    let dir = components.next()?;
+    let name = components.next()?;
+    if components.next().is_some() || dir.as_os_str() != SSTABLE_ENTRY_DIR {
+        return None;
+    }
+    let std::path::Component::Normal(name) = name else {
+        return None;
+    };
+    let name = name.to_str()?;
+    name.ends_with(".data").then(|| name.to_string())
+}
+
+// 把归档解包到临时目录，并按导出清单校验每个文件的大小和CRC32
+pub(crate) fn unpack_archive<R: Read>(reader: R, dir: PathBuf, timestamp_precision: TimePrecision) -> Result<ImportedArchive> {
+    fs::create_dir_all(&dir)?;
+    let mut archive = ImportedArchive {
+        dir,
+        files: Vec::new(),
+        manifest: Manifest::default(),
+        export: ExportManifest {
+            export_version: EXPORT_VERSION,
+            format_version: SSTABLE_FORMAT_VERSION,
+            timestamp_precision,
+            files: Vec::new(),
+            manifest_checksum: 0,
+            series_count: 0,
+            min_timestamp: None,
+            max_timestamp: None,
+        },
+        blocks: Vec::new(),
+    };
+
+    let mut unpacked: BTreeMap<String, (u64, u32)> = BTreeMap::new();
+    let mut manifest: Option<Vec<u8>> = None;
+    let mut export: Option<Vec<u8>> = None;
+
+    let mut tar = tar::Archive::new(reader);
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = entry.path()?.into_owned();
+        if path.as_os_str() == MANIFEST_FILE || path.as_os_str() == EXPORT_FILE {
+            let mut content = Vec::new();
+            entry.read_to_end(&mut content)?;
+            if path.as_os_str() == MANIFEST_FILE {
+                manifest = Some(content);
+            } else {
+                export = Some(content);
+            }
+            continue;
+        }
+        let Some(file_name) = sstable_entry_name(&path) else {
+            return Err(invalid_archive(format!("不支持的条目 {:?}", path)));
+        };
+        if unpacked.contains_key(&file_name) {
+            return Err(invalid_archive(format!("文件 {} 重复", file_name)));
+        }
+        let mut reader = ChecksumReader::new(&mut entry);
+        let mut file = File::create(archive.dir.join(&file_name))?;
+        std::io::copy(&mut reader, &mut file)?;
+        file.sync_all()?;
+        unpacked.insert(file_name, reader.finish());
+    }
+
+    let export = export.ok_or_else(|| invalid_archive(format!("缺少 {}", EXPORT_FILE)))?;
+    let export: ExportManifest = serde_json::from_slice(&export).map_err(|e| invalid_archive(format!("{} 解析失败: {}", EXPORT_FILE, e)))?;
+    if export.export_version > EXPORT_VERSION {
+        return Err(invalid_archive(format!("导出版本 {} 高于当前支持的 {}", export.export_version, EXPORT_VERSION)));
+    }
+    if export.format_version > SSTABLE_FORMAT_VERSION {
+        return Err(invalid_archive(format!("SSTable格式版本 {} 高于当前支持的 {}", export.format_version, SSTABLE_FORMAT_VERSION)));
+    }
+    if export.timestamp_precision != timestamp_precision {
+        return Err(invalid_archive(format!(
+            "归档的时间戳精度为 {}，与数据库的 {} 不一致",
+            export.timestamp_precision, timestamp_precision
+        )));
+    }
+
+    let manifest = manifest.ok_or_else(|| invalid_archive(format!("缺少 {}", MANIFEST_FILE)))?;
+    if crc32fast::hash(&manifest) != export.manifest_checksum {
+        return Err(invalid_archive(format!("{} 校验和不匹配", MANIFEST_FILE)));
+    }
+    archive.manifest = serde_json::from_slice(&manifest).map_err(|e| invalid_archive(format!("{} 解析失败: {}", MANIFEST_FILE, e)))?;
+
+    if unpacked.len() != export.files.len() {
+        return Err(invalid_archive(format!(
+            "包含 {} 个数据文件，导出清单记录了 {} 个",
+            unpacked.len(),
+            export.files.len()
+        )));
+    }
+    for file in &export.files {
+        match unpacked.get(&file.file_name) {
+            Some(&(size_bytes, checksum)) if size_bytes == file.size_bytes && checksum == file.checksum => {}
+            Some(_) => return Err(invalid_archive(format!("文件 {} 的大小或校验和不匹配", file.file_name))),
+            None => return Err(invalid_archive(format!("缺少文件 {}", file.file_name))),
+        }
+        let path = archive.dir.join(&file.file_name);
+        let blocks = SSTable::new(path.clone())?.load_series_list()?;
+        archive.files.push(path);
+        archive.blocks.push(blocks);
+    }
+    archive.export = export;
+
+    Ok(archive)
+}
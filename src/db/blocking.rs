@@ -0,0 +1,84 @@
+use std::io::Result;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use tokio::sync::Semaphore;
+
+// 解压、压缩等CPU密集任务的执行池。任务在tokio的阻塞线程上执行，同时执行的数量不超过 threads，
+// 大查询和compaction不会占满异步工作线程，也不会彼此抢占过多CPU。
+// 点数不超过 inline_points 的小任务直接在当前任务中执行，省去线程切换的开销
+#[derive(Debug)]
+pub struct BlockingPool {
+    permits: Arc<Semaphore>,
+    threads: usize,
+    inline_points: usize,
+    offloaded: AtomicU64,
+    inlined: AtomicU64,
+}
+
+// 执行池的配置和累计执行次数
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct BlockingPoolStats {
+    pub threads: usize,
+    pub inline_points: usize,
+    pub offloaded: u64,
+    pub inlined: u64,
+}
+
+impl BlockingPool {
+    pub fn new(threads: usize, inline_points: usize) -> Self {
+        let threads = threads.max(1);
+        Self {
+            permits: Arc::new(Semaphore::new(threads)),
+            threads,
+            inline_points,
+            offloaded: AtomicU64::new(0),
+            inlined: AtomicU64::new(0),
+        }
+    }
+
+    // 在阻塞线程上执行，线程都在忙时异步等待
+    pub async fn run<T, F>(&self, task: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        let permit = Arc::clone(&self.permits)
+            .acquire_owned()
+            .await
+            .map_err(std::io::Error::other)?;
+        self.offloaded.fetch_add(1, Ordering::Relaxed);
+        tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            task()
+        })
+        .await
+        .map_err(std::io::Error::other)
+    }
+
+    // 按任务要处理的数据点数决定内联执行还是交给阻塞线程
+    pub async fn run_sized<T, F>(&self, points: usize, task: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        if points <= self.inline_points {
+            self.inlined.fetch_add(1, Ordering::Relaxed);
+            return Ok(task());
+        }
+        self.run(task).await
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    pub fn stats(&self) -> BlockingPoolStats {
+        BlockingPoolStats {
+            threads: self.threads,
+            inline_points: self.inline_points,
+            offloaded: self.offloaded.load(Ordering::Relaxed),
+            inlined: self.inlined.load(Ordering::Relaxed),
+        }
+    }
+}
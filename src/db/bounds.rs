@@ -0,0 +1,22 @@
+use serde::Serialize;
+
+// 记录的时间范围与实际数据不一致的系列块
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BoundsMismatch {
+    pub file_name: String,
+    pub series_key: String,
+    pub stored_min_timestamp: u64,
+    pub stored_max_timestamp: u64,
+    pub actual_min_timestamp: u64,
+    pub actual_max_timestamp: u64,
+}
+
+// 一次时间范围校验的结果，repaired 中的块已修正并重写
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BoundsReport {
+    pub files_checked: usize,
+    // 无法读取而跳过的文件
+    pub files_failed: usize,
+    pub blocks_checked: usize,
+    pub repaired: Vec<BoundsMismatch>,
+}
@@ -0,0 +1,64 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use tokio_util::sync::{CancellationToken, DropGuard};
+
+// 查询的取消标记。发起查询的future被丢弃时（如客户端断开连接，axum丢弃处理请求的future）取消，
+// 在阻塞线程上解压、归并的任务在每个块、每批数据点之前检查，已取消时提前结束
+#[derive(Debug)]
+pub struct QueryCancellation {
+    token: CancellationToken,
+    _guard: DropGuard,
+}
+
+impl QueryCancellation {
+    pub fn new() -> Self {
+        let token = CancellationToken::new();
+        let guard = token.clone().drop_guard();
+        Self { token, _guard: guard }
+    }
+
+    // 交给阻塞任务检查的标记
+    pub fn token(&self) -> CancellationToken {
+        self.token.clone()
+    }
+}
+
+impl Default for QueryCancellation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// 查询的执行进度：已解压的SSTable块数，以及因发起方取消而提前结束的查询数
+#[derive(Debug, Default)]
+pub struct QueryProgress {
+    blocks_decoded: AtomicU64,
+    cancelled: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct QueryProgressStats {
+    pub blocks_decoded: u64,
+    pub cancelled: u64,
+}
+
+impl QueryProgress {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn block_decoded(&self) {
+        self.blocks_decoded.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn cancelled(&self) {
+        self.cancelled.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> QueryProgressStats {
+        QueryProgressStats {
+            blocks_decoded: self.blocks_decoded.load(Ordering::Relaxed),
+            cancelled: self.cancelled.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use std::collections::BTreeMap;
+
+use parking_lot::RwLock;
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+// 变更通道的默认容量
+pub const DEFAULT_CHANGE_FEED_CAPACITY: usize = 1024;
+
+// 写入、更新和删除产生的变更事件，时间戳为内部精度
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChangeEvent {
+    Insert {
+        series_key: String,
+        timestamp: u64,
+        value: f64,
+        tags: BTreeMap<String, String>,
+    },
+    Update {
+        series_key: String,
+        timestamp: u64,
+        value: f64,
+    },
+    // 删除 [start_time, end_time] 内的数据点，删除单个点时两端相同，删除整个系列时两端都为None
+    Delete {
+        series_key: String,
+        start_time: Option<u64>,
+        end_time: Option<u64>,
+    },
+}
+
+impl ChangeEvent {
+    pub fn series_key(&self) -> &str {
+        match self {
+            Self::Insert { series_key, .. } | Self::Update { series_key, .. } | Self::Delete { series_key, .. } => series_key,
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Insert { .. } => "insert",
+            Self::Update { .. } => "update",
+            Self::Delete { .. } => "delete",
+        }
+    }
+}
+
+// 订阅方按系列键、前缀和标签过滤事件，条件之间为与
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChangeFilter {
+    pub series_key: Option<String>,
+    pub prefix: Option<String>,
+    pub tags: BTreeMap<String, String>,
+}
+
+impl ChangeFilter {
+    // tags 为插入事件携带的标签，其他事件为系列的标签
+    pub fn matches(&self, series_key: &str, tags: &BTreeMap<String, String>) -> bool {
+        self.series_key.as_deref().is_none_or(|key| key == series_key)
+            && self.prefix.as_deref().is_none_or(|prefix| series_key.starts_with(prefix))
+            && self.tags.iter().all(|(key, value)| tags.get(key) == Some(value))
+    }
+}
+
+// 变更事件的广播通道。容量有限，跟不上的订阅方丢失最早的事件（接收时得到 Lagged），
+// 不会因为慢订阅方占用更多内存。没有订阅方时不构造事件
+#[derive(Debug)]
+pub struct ChangeFeed {
+    capacity: usize,
+    // 关闭后为None，已有的订阅方收完缓冲的事件后得到 Closed
+    sender: RwLock<Option<broadcast::Sender<ChangeEvent>>>,
+}
+
+impl ChangeFeed {
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        let (sender, _) = broadcast::channel(capacity);
+        Self { capacity, sender: RwLock::new(Some(sender)) }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ChangeEvent> {
+        match &*self.sender.read() {
+            Some(sender) => sender.subscribe(),
+            // 已关闭：返回立即结束的接收端
+            None => broadcast::channel(self.capacity).1,
+        }
+    }
+
+    pub fn has_subscribers(&self) -> bool {
+        self.sender.read().as_ref().is_some_and(|sender| sender.receiver_count() > 0)
+    }
+
+    pub fn publish(&self, event: impl FnOnce() -> ChangeEvent) {
+        if let Some(sender) = &*self.sender.read() {
+            if sender.receiver_count() > 0 {
+                let _ = sender.send(event());
+            }
+        }
+    }
+
+    pub fn publish_all(&self, events: impl FnOnce() -> Vec<ChangeEvent>) {
+        if let Some(sender) = &*self.sender.read() {
+            if sender.receiver_count() > 0 {
+                for event in events() {
+                    let _ = sender.send(event);
+                }
+            }
+        }
+    }
+
+    pub fn close(&self) {
+        self.sender.write().take();
+    }
+}
@@ -0,0 +1,131 @@
+use std::io::{Error, ErrorKind, Result};
+
+use serde::{Deserialize, Serialize};
+
+use super::compression::format::block_decoder;
+use super::{DbError, SeriesData};
+
+// 一次请求最多划分的时间窗口数
+pub const MAX_CHUNKS: u64 = 100_000;
+
+// 时间窗口 [start, end] 内数据点的概况，end 含在窗口内。没有数据点时 min/max 为None
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkSummary {
+    pub start: u64,
+    pub end: u64,
+    pub count: usize,
+    pub min_timestamp: Option<u64>,
+    pub max_timestamp: Option<u64>,
+}
+
+// 从 start 开始每 chunk 个时间单位一个窗口，最后一个窗口截止于 end
+pub fn chunk_windows(start: u64, end: u64, chunk: u64) -> Result<Vec<ChunkSummary>> {
+    if chunk == 0 {
+        return Err(Error::new(ErrorKind::InvalidInput, "chunk必须大于0"));
+    }
+    if end < start {
+        return Err(DbError::InvalidTimeRange { start_time: start, end_time: end }.into());
+    }
+    let count = (end - start) / chunk + 1;
+    if count > MAX_CHUNKS {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("时间窗口数量 {} 超过上限 {}，请缩小时间范围或增大chunk", count, MAX_CHUNKS),
+        ));
+    }
+    Ok((0..count)
+        .map(|index| {
+            let window_start = start + index * chunk;
+            ChunkSummary {
+                start: window_start,
+                end: window_start.saturating_add(chunk - 1).min(end),
+                count: 0,
+                min_timestamp: None,
+                max_timestamp: None,
+            }
+        })
+        .collect())
+}
+
+// 一个窗口的数据来源：完整落在窗口内的块只用元数据，其余的点按时间戳计入
+#[derive(Debug, Default)]
+struct WindowSources<'a> {
+    blocks: Vec<&'a SeriesData>,
+    timestamps: Vec<u64>,
+}
+
+impl WindowSources<'_> {
+    // 块之间以及块与其他点的时间范围都不相交时不可能有重复的时间戳，块的元数据就是准确的
+    fn metadata_exact(&mut self) -> bool {
+        self.blocks.sort_by_key(|block| block.min_timestamp);
+        let disjoint = self.blocks.windows(2).all(|pair| pair[0].max_timestamp < pair[1].min_timestamp);
+        disjoint
+            && !self.timestamps.iter().any(|&ts| {
+                let index = self.blocks.partition_point(|block| block.max_timestamp < ts);
+                self.blocks.get(index).is_some_and(|block| block.min_timestamp <= ts)
+            })
+    }
+}
+
+// 按窗口统计点数和首末时间戳。完整落在一个窗口内、且与其他来源不重叠的块只读元数据，
+// 跨窗口或与其他来源重叠的块才解压时间戳；重复的时间戳只计一次
+pub fn summarize_chunks(
+    mut windows: Vec<ChunkSummary>,
+    chunk: u64,
+    blocks: &[SeriesData],
+    memtable_timestamps: &[u64],
+) -> Result<Vec<ChunkSummary>> {
+    let (Some(first), Some(last)) = (windows.first(), windows.last()) else {
+        return Ok(windows);
+    };
+    let (start, end) = (first.start, last.end);
+    let index_of = |ts: u64| ((ts - start) / chunk) as usize;
+    let mut sources: Vec<WindowSources> = windows.iter().map(|_| WindowSources::default()).collect();
+
+    for &ts in memtable_timestamps.iter().filter(|ts| (start..=end).contains(*ts)) {
+        sources[index_of(ts)].timestamps.push(ts);
+    }
+    for block in blocks {
+        if block.count == 0 || block.max_timestamp < start || block.min_timestamp > end {
+            continue;
+        }
+        if block.min_timestamp >= start && block.max_timestamp <= end && index_of(block.min_timestamp) == index_of(block.max_timestamp) {
+            sources[index_of(block.min_timestamp)].blocks.push(block);
+        } else {
+            for ts in decode_timestamps(block)?.into_iter().filter(|ts| (start..=end).contains(ts)) {
+                sources[index_of(ts)].timestamps.push(ts);
+            }
+        }
+    }
+
+    for (window, mut source) in windows.iter_mut().zip(sources) {
+        if !source.metadata_exact() {
+            for block in std::mem::take(&mut source.blocks) {
+                source.timestamps.extend(decode_timestamps(block)?);
+            }
+        }
+        source.timestamps.sort_unstable();
+        source.timestamps.dedup();
+
+        window.count = source.timestamps.len() + source.blocks.iter().map(|block| block.count).sum::<usize>();
+        window.min_timestamp = source
+            .timestamps
+            .first()
+            .copied()
+            .into_iter()
+            .chain(source.blocks.iter().map(|block| block.min_timestamp))
+            .min();
+        window.max_timestamp = source
+            .timestamps
+            .last()
+            .copied()
+            .into_iter()
+            .chain(source.blocks.iter().map(|block| block.max_timestamp))
+            .max();
+    }
+    Ok(windows)
+}
+
+fn decode_timestamps(block: &SeriesData) -> Result<Vec<u64>> {
+    Ok(block_decoder(block.compressed_data.clone())?.take(block.count).map(|(ts, _)| ts).collect())
+}
@@ -0,0 +1,67 @@
+use std::fmt::Debug;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use parking_lot::Mutex;
+
+use super::DbError;
+
+// 时钟抽象，便于在测试中控制时间
+pub trait Clock: Send + Sync + Debug {
+    fn now(&self) -> SystemTime;
+
+    fn now_secs(&self) -> u64 {
+        self.now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+    }
+
+    // 当前时间距Unix纪元的时长，时钟早于纪元时返回错误而不是按0处理
+    fn since_epoch(&self) -> std::io::Result<Duration> {
+        self.now().duration_since(UNIX_EPOCH).map_err(|e| {
+            DbError::ClockBeforeEpoch { behind_secs: e.duration().as_secs() }.into()
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+// 手动推进的时钟，用于测试
+#[derive(Debug)]
+pub struct MockClock {
+    now: Mutex<SystemTime>,
+}
+
+impl MockClock {
+    pub fn new(start: SystemTime) -> Self {
+        Self {
+            now: Mutex::new(start),
+        }
+    }
+
+    pub fn from_secs(secs: u64) -> Self {
+        Self::new(UNIX_EPOCH + Duration::from_secs(secs))
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        let mut now = self.now.lock();
+        *now += duration;
+    }
+
+    pub fn set(&self, time: SystemTime) {
+        *self.now.lock() = time;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> SystemTime {
+        *self.now.lock()
+    }
+}
@@ -0,0 +1,89 @@
+use serde::Serialize;
+
+use super::SeriesData;
+
+// compaction过滤条件，未指定时间窗口和系列时合并全部SSTable
+#[derive(Debug, Clone, Default)]
+pub struct CompactionOptions {
+    pub start_time: Option<u64>,
+    pub end_time: Option<u64>,
+    // 只合并该系列的块，其他系列的块保留在原文件中
+    pub series_key: Option<String>,
+    // 只有这些文件参与合并，未指定时所有文件参与
+    pub files: Option<Vec<String>>,
+}
+
+impl CompactionOptions {
+    pub fn window(start_time: Option<u64>, end_time: Option<u64>) -> Self {
+        Self { start_time, end_time, ..Self::default() }
+    }
+
+    pub fn series(series_key: &str) -> Self {
+        Self { series_key: Some(series_key.to_string()), ..Self::default() }
+    }
+
+    pub fn files(files: Vec<String>) -> Self {
+        Self { files: Some(files), ..Self::default() }
+    }
+
+    // 文件是否参与合并
+    pub fn includes_file(&self, file_name: &str) -> bool {
+        self.files.as_ref().is_none_or(|files| files.iter().any(|file| file == file_name))
+    }
+
+    pub fn is_windowed(&self) -> bool {
+        self.start_time.is_some() || self.end_time.is_some()
+    }
+
+    // 数据块的时间范围是否与窗口相交
+    pub fn overlaps(&self, min_timestamp: u64, max_timestamp: u64) -> bool {
+        self.start_time.is_none_or(|start| max_timestamp >= start)
+            && self.end_time.is_none_or(|end| min_timestamp <= end)
+    }
+
+    // 数据块是否参与合并
+    pub fn selects(&self, series: &SeriesData) -> bool {
+        self.series_key.as_ref().is_none_or(|key| *key == series.series_key)
+            && self.overlaps(series.min_timestamp, series.max_timestamp)
+    }
+}
+
+// 引擎保留的最近compaction报告数量
+pub const COMPACTION_HISTORY_LIMIT: usize = 50;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CompactionInput {
+    pub file_name: String,
+    // 合并前的文件大小
+    pub size_bytes: u64,
+    pub series_count: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CompactionOutput {
+    pub file_name: String,
+    pub size_bytes: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CompactionReport {
+    // 开始时间（Unix秒）和耗时
+    pub started_at: u64,
+    pub duration_ms: u64,
+    // 参与合并的SSTable文件
+    pub inputs: Vec<CompactionInput>,
+    // 合并输出的新文件
+    pub output: Option<CompactionOutput>,
+    // 写入的字节数（新文件以及部分保留后重写的文件）
+    pub bytes_rewritten: u64,
+    pub series_merged: usize,
+    // 因包含热数据窗口内的数据而未参与合并的文件数
+    pub hot_files_skipped: usize,
+    // 从输入文件读出的数据点数，以及其中重复时间戳被去掉的数量
+    pub points_merged: usize,
+    pub duplicates_removed: usize,
+    // 按降采样策略聚合掉的数据点数
+    pub points_downsampled: usize,
+    // 未执行时的原因
+    pub skipped_reason: Option<String>,
+}
@@ -1,5 +1,14 @@
 use serde::{Serialize, Deserialize};
 
+pub mod format;
+pub mod scratch;
+#[cfg(any(test, feature = "fuzz"))]
+pub mod fuzz;
+
+// 12位delta-of-delta中保留的转义值，其后跟随64位原始delta-of-delta。
+// v1 位流没有转义，0x800 表示 -2048，按 v1 解码的块不识别转义
+const DOD_ESCAPE: u64 = 0x800;
+
 #[derive(Debug, Clone)]
 pub struct GorillaBitWriter {
     buffer: Vec<u8>,
@@ -20,23 +29,15 @@ impl GorillaBitWriter {
         }
         
         for i in (0..num_bits).rev() {
-            let bit = (value >> i) & 1;
-            
-            // 使用 wrapping_div 和 wrapping_rem 避免溢出
-            if self.bit_pos.wrapping_rem(8) == 0 {
+            if self.bit_pos.is_multiple_of(8) {
                 self.buffer.push(0);
             }
-            
-            let byte_index = self.bit_pos.wrapping_div(8);
-            let bit_index = 7_usize.wrapping_sub(self.bit_pos.wrapping_rem(8));
-            
-            // 确保不越界
-            if byte_index < self.buffer.len() && bit == 1 {
-                self.buffer[byte_index] |= 1 << bit_index;
+            if (value >> i) & 1 == 1 {
+                // bit_pos 所在的字节就是刚写入或之前写入的最后一个字节
+                let last = self.buffer.len() - 1;
+                self.buffer[last] |= 1 << (7 - self.bit_pos % 8);
             }
-            
-            // 使用 wrapping_add 避免溢出
-            self.bit_pos = self.bit_pos.wrapping_add(1);
+            self.bit_pos += 1;
         }
     }
 
@@ -59,38 +60,48 @@ impl GorillaBitReader {
         }
     }
 
-    pub fn read_bits(&mut self, num_bits: usize) -> Option<u64> {
-        if num_bits == 0 || num_bits > 64 {
-            return Some(0);
+    // 从第 offset 个字节开始读取，跳过的字节不复制
+    pub fn with_offset(data: Vec<u8>, offset: usize) -> Self {
+        Self {
+            bit_pos: offset.min(data.len()) * 8,
+            buffer: data,
         }
-        
-        // 使用 wrapping_add 和 wrapping_mul 避免溢出
-        let total_bits = self.buffer.len().wrapping_mul(8);
-        if self.bit_pos.wrapping_add(num_bits) > total_bits {
+    }
+
+    // 读取 num_bits 位（最多64位），剩余的位不足或位数无效时返回None且不移动读取位置
+    pub fn read_bits(&mut self, num_bits: usize) -> Option<u64> {
+        if num_bits > 64 || num_bits > self.remaining_bits() {
             return None;
         }
 
         let mut result = 0u64;
-        
         for _ in 0..num_bits {
-            let byte_index = self.bit_pos.wrapping_div(8);
-            let bit_index = 7_usize.wrapping_sub(self.bit_pos.wrapping_rem(8));
-            
-            if byte_index >= self.buffer.len() {
-                return None;
-            }
-            
-            let bit = (self.buffer[byte_index] >> bit_index) & 1;
+            let bit = (self.buffer[self.bit_pos / 8] >> (7 - self.bit_pos % 8)) & 1;
             result = (result << 1) | (bit as u64);
-            self.bit_pos = self.bit_pos.wrapping_add(1);
+            self.bit_pos += 1;
         }
         
         Some(result)
     }
 
+    // 读取 num_bits 位但不移动读取位置
+    pub fn peek_bits(&mut self, num_bits: usize) -> Option<u64> {
+        let bit_pos = self.bit_pos;
+        let result = self.read_bits(num_bits);
+        self.bit_pos = bit_pos;
+        result
+    }
+
     pub fn has_more_data(&self) -> bool {
-        let total_bits = self.buffer.len().wrapping_mul(8);
-        self.bit_pos < total_bits
+        self.remaining_bits() > 0
+    }
+
+    pub fn remaining_bits(&self) -> usize {
+        self.buffer.len().saturating_mul(8).saturating_sub(self.bit_pos)
+    }
+
+    pub fn bit_pos(&self) -> usize {
+        self.bit_pos
     }
 }
 
@@ -137,11 +148,15 @@ impl GorillaCompressor {
     fn compress_timestamp(&mut self, delta: i64) {
         match self.prev_delta {
             None => {
-                // 第一个delta
-                self.writer.write_bits(0b10, 2);
-                // 确保delta在有效范围内
-                let clamped_delta = delta.clamp(-8191, 8191);
-                self.writer.write_bits(clamped_delta as u64, 14);
+                // 第一个delta: '10'+14位，超出范围时 '11'+64位zigzag编码。
+                // 绝对值接近2^63时可能以8个1开头，解码时靠其后剩余的位数与结束标记区分
+                if (-8191..=8191).contains(&delta) {
+                    self.writer.write_bits(0b10, 2);
+                    self.writer.write_bits(delta as u64 & 0x3FFF, 14);
+                } else {
+                    self.writer.write_bits(0b11, 2);
+                    self.writer.write_bits(((delta << 1) ^ (delta >> 63)) as u64, 64);
+                }
                 self.prev_delta = Some(delta);
             }
             Some(prev_delta) => {
@@ -150,7 +165,7 @@ impl GorillaCompressor {
                 
                 if delta_of_delta == 0 {
                     self.writer.write_bits(0b0, 1);
-                } else if (-63..=64).contains(&delta_of_delta) {
+                } else if (-64..=63).contains(&delta_of_delta) {
                     self.writer.write_bits(0b10, 2);
                     // 7位有符号整数编码，使用 wrapping_add
                     let encoded = if delta_of_delta < 0 {
@@ -159,7 +174,7 @@ impl GorillaCompressor {
                         delta_of_delta as u64
                     };
                     self.writer.write_bits(encoded, 7);
-                } else {
+                } else if (-2047..=2047).contains(&delta_of_delta) {
                     self.writer.write_bits(0b11, 2);
                     // 12位有符号整数编码，使用 wrapping_add
                     let encoded = if delta_of_delta < 0 {
@@ -168,6 +183,11 @@ impl GorillaCompressor {
                         delta_of_delta as u64
                     };
                     self.writer.write_bits(encoded, 12);
+                } else {
+                    // 超出12位范围: '11' + 转义值(-2048) + 64位原值
+                    self.writer.write_bits(0b11, 2);
+                    self.writer.write_bits(DOD_ESCAPE, 12);
+                    self.writer.write_bits(delta_of_delta as u64, 64);
                 }
                 
                 self.prev_delta = Some(delta);
@@ -196,17 +216,11 @@ impl GorillaCompressor {
                     // 使用 saturating_sub 避免溢出
                     let meaningful_bits = 64_usize.saturating_sub(leading_zeros).saturating_sub(trailing_zeros);
                     
-                    if meaningful_bits > 0 && meaningful_bits <= 64 {
-                        self.writer.write_bits(leading_zeros.min(63) as u64, 6);
-                        self.writer.write_bits(meaningful_bits.min(64) as u64, 6);
-                        let meaningful_value = xor_result >> trailing_zeros.min(63);
-                        self.writer.write_bits(meaningful_value, meaningful_bits.min(64));
-                    } else {
-                        // 如果没有有意义的位，存储完整值
-                        self.writer.write_bits(0, 6);
-                        self.writer.write_bits(64, 6);
-                        self.writer.write_bits(current_bits, 64);
-                    }
+                    // 有效位数用6位存储，64位时写作0（xor非零时有效位数不可能为0）
+                    self.writer.write_bits(leading_zeros.min(63) as u64, 6);
+                    self.writer.write_bits((meaningful_bits % 64) as u64, 6);
+                    let meaningful_value = xor_result >> trailing_zeros.min(63);
+                    self.writer.write_bits(meaningful_value, meaningful_bits);
                 }
             }
         }
@@ -219,6 +233,9 @@ impl GorillaCompressor {
     }
 }
 
+// 解码失败的原因：位流在数据点中间结束、控制位无效、缺少结束标记，或一次解码没有读取任何位
+const TRUNCATED: &str = "位流在数据点中间结束";
+
 #[derive(Debug)]
 pub struct GorillaDecompressor {
     reader: GorillaBitReader,
@@ -226,9 +243,14 @@ pub struct GorillaDecompressor {
     prev_delta: Option<i64>,
     prev_value: Option<f64>,
     finished: bool,
+    // 解码失败的原因；失败之后不再输出数据点
+    error: Option<&'static str>,
+    // 按 v1 位流解码：没有第一个delta和delta-of-delta的转义
+    legacy: bool,
 }
 
 impl GorillaDecompressor {
+    // 按当前编码（v2 位流）解码
     pub fn new(data: Vec<u8>) -> Self {
         Self {
             reader: GorillaBitReader::new(data),
@@ -236,134 +258,196 @@ impl GorillaDecompressor {
             prev_delta: None,
             prev_value: None,
             finished: false,
+            error: None,
+            legacy: false,
+        }
+    }
+
+    // 按块的版本解码，版本需已由 format::check_version 检查
+    pub fn for_version(data: Vec<u8>, version: u8) -> Self {
+        Self {
+            legacy: version == format::BLOCK_FORMAT_V1,
+            ..Self::new(data)
+        }
+    }
+
+    // 解码完整的块（第一个字节为版本，需已由 format::check_version 检查），直接使用块的数据不复制
+    pub fn for_block(block: Vec<u8>, version: u8) -> Self {
+        Self {
+            reader: GorillaBitReader::with_offset(block, 1),
+            ..Self::for_version(Vec::new(), version)
         }
     }
 
+    // 返回None之后不会再输出数据点：遇到结束标记正常结束，否则记录失败原因（见 error）。
+    // 每个数据点至少读取2位，没有读取任何位时按失败处理，任意输入都能在有限步内结束
     pub fn decompress_next(&mut self) -> Option<(u64, f64)> {
-        if self.finished || !self.reader.has_more_data() {
+        if self.finished {
             return None;
         }
+        let start = self.reader.bit_pos();
+        let result = match self.decode_point() {
+            Ok(Some(_)) if self.reader.bit_pos() <= start => Err("解码没有读取任何位"),
+            result => result,
+        };
+        match result {
+            Ok(Some(point)) => Some(point),
+            Ok(None) => {
+                self.finished = true;
+                None
+            }
+            Err(error) => {
+                self.finished = true;
+                self.error = Some(error);
+                None
+            }
+        }
+    }
+
+    // 解码失败的原因，正常结束或尚未结束时为None
+    pub fn error(&self) -> Option<&'static str> {
+        self.error
+    }
+
+    // 解码失败时返回 InvalidData 错误
+    pub fn check(&self) -> std::io::Result<()> {
+        match self.error {
+            Some(error) => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("块数据损坏: {}", error),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    fn read(&mut self, num_bits: usize) -> Result<u64, &'static str> {
+        self.reader.read_bits(num_bits).ok_or(TRUNCATED)
+    }
+
+    fn decode_point(&mut self) -> Result<Option<(u64, f64)>, &'static str> {
+        // 检查是否遇到结束标记
+        // 结束标记之后只剩补齐的位；很大的第一个delta（'11'+zigzag）也可能以8个1开头
+        if self.reader.peek_bits(8) == Some(0b11111111) && self.reader.remaining_bits() < 16 {
+            return Ok(None);
+        }
+        if self.reader.remaining_bits() < 8 {
+            return Err("位流缺少结束标记");
+        }
 
         match self.prev_timestamp {
             None => {
                 // 第一个数据点
-                let timestamp = self.reader.read_bits(64)?;
-                let value_bits = self.reader.read_bits(64)?;
-                let value = f64::from_bits(value_bits);
+                let timestamp = self.read(64)?;
+                let value = f64::from_bits(self.read(64)?);
                 
                 self.prev_timestamp = Some(timestamp);
                 self.prev_value = Some(value);
                 
-                Some((timestamp, value))
+                Ok(Some((timestamp, value)))
             }
             Some(prev_ts) => {
-                // 检查是否遇到结束标记
-                if let Some(end_marker) = self.reader.read_bits(8) {
-                    if end_marker == 0b11111111 {
-                        self.finished = true;
-                        return None;
-                    }
-                    // 回退8位
-                    self.reader.bit_pos = self.reader.bit_pos.saturating_sub(8);
-                }
-                
                 let timestamp = self.decompress_timestamp(prev_ts)?;
                 let value = self.decompress_value()?;
                 
                 self.prev_timestamp = Some(timestamp);
                 self.prev_value = Some(value);
                 
-                Some((timestamp, value))
+                Ok(Some((timestamp, value)))
             }
         }
     }
 
-    fn decompress_timestamp(&mut self, prev_timestamp: u64) -> Option<u64> {
+    fn decompress_timestamp(&mut self, prev_timestamp: u64) -> Result<u64, &'static str> {
         match self.prev_delta {
             None => {
-                let control_bits = self.reader.read_bits(2)?;
-                if control_bits == 0b10 {
-                    let delta = self.reader.read_bits(14)? as i64;
-                    let signed_delta = if delta > 8191 { 
-                        delta.wrapping_sub(16384) 
-                    } else { 
-                        delta 
-                    };
-                    self.prev_delta = Some(signed_delta);
-                    // 使用 wrapping_add 避免溢出
-                    Some((prev_timestamp as i64).wrapping_add(signed_delta) as u64)
-                } else {
-                    None
-                }
+                let control_bits = self.read(2)?;
+                let signed_delta = match control_bits {
+                    0b10 => {
+                        let delta = self.read(14)? as i64;
+                        if delta > 8191 {
+                            delta - 16384
+                        } else {
+                            delta
+                        }
+                    }
+                    0b11 if !self.legacy => {
+                        let zigzag = self.read(64)?;
+                        ((zigzag >> 1) as i64) ^ -((zigzag & 1) as i64)
+                    }
+                    _ => return Err("第一个delta的控制位无效"),
+                };
+                self.prev_delta = Some(signed_delta);
+                // 使用 wrapping_add 避免溢出
+                Ok((prev_timestamp as i64).wrapping_add(signed_delta) as u64)
             }
             Some(prev_delta) => {
-                let control_bit = self.reader.read_bits(1)?;
+                let control_bit = self.read(1)?;
                 
                 if control_bit == 0 {
                     // 使用 wrapping_add 避免溢出
-                    Some((prev_timestamp as i64).wrapping_add(prev_delta) as u64)
+                    Ok((prev_timestamp as i64).wrapping_add(prev_delta) as u64)
                 } else {
-                    let second_bit = self.reader.read_bits(1)?;
+                    let second_bit = self.read(1)?;
                     
                     let delta_of_delta = if second_bit == 0 {
-                        let value = self.reader.read_bits(7)? as i64;
+                        let value = self.read(7)? as i64;
                         if value > 63 { 
-                            value.wrapping_sub(128) 
+                            value - 128
                         } else { 
                             value 
                         }
                     } else {
-                        let value = self.reader.read_bits(12)? as i64;
-                        if value > 2047 { 
-                            value.wrapping_sub(4096) 
-                        } else { 
-                            value 
+                        let value = self.read(12)?;
+                        if value == DOD_ESCAPE && !self.legacy {
+                            self.read(64)? as i64
+                        } else if value > 2047 {
+                            value as i64 - 4096
+                        } else {
+                            value as i64
                         }
                     };
                     
                     // 使用 wrapping_add 避免溢出
                     let new_delta = prev_delta.wrapping_add(delta_of_delta);
                     self.prev_delta = Some(new_delta);
-                    Some((prev_timestamp as i64).wrapping_add(new_delta) as u64)
+                    Ok((prev_timestamp as i64).wrapping_add(new_delta) as u64)
                 }
             }
         }
     }
 
-    fn decompress_value(&mut self) -> Option<f64> {
+    fn decompress_value(&mut self) -> Result<f64, &'static str> {
         match self.prev_value {
-            None => {
-                let value_bits = self.reader.read_bits(64)?;
-                let value = f64::from_bits(value_bits);
-                Some(value)
-            }
+            None => Ok(f64::from_bits(self.read(64)?)),
             Some(prev_value) => {
-                let control_bit = self.reader.read_bits(1)?;
+                let control_bit = self.read(1)?;
                 
                 if control_bit == 0 {
-                    Some(prev_value)
+                    Ok(prev_value)
                 } else {
-                    let leading_zeros = self.reader.read_bits(6)? as usize;
-                    let meaningful_bits = self.reader.read_bits(6)? as usize;
-                    
-                    if meaningful_bits == 0 || meaningful_bits > 64 {
-                        return Some(prev_value);
-                    }
+                    let leading_zeros = self.read(6)? as usize;
+                    // 0表示64个有效位
+                    let meaningful_bits = match self.read(6)? as usize {
+                        0 => 64,
+                        bits => bits,
+                    };
                     
-                    let meaningful_value = self.reader.read_bits(meaningful_bits.min(64))?;
-                    // 使用 saturating_sub 避免溢出
+                    let meaningful_value = self.read(meaningful_bits)?;
+                    // 损坏的输入中前导零与有效位数之和可能超过64，按0个尾随零处理
                     let trailing_zeros = 64_usize.saturating_sub(leading_zeros).saturating_sub(meaningful_bits);
                     
-                    let xor_result = meaningful_value << trailing_zeros.min(63);
-                    let prev_bits = prev_value.to_bits();
-                    let current_bits = prev_bits ^ xor_result;
-                    
-                    Some(f64::from_bits(current_bits))
+                    let xor_result = meaningful_value << trailing_zeros;
+                    Ok(f64::from_bits(prev_value.to_bits() ^ xor_result))
                 }
             }
         }
     }
 
+    // 取回块数据的缓冲区，供下一次解压复用
+    pub fn into_buffer(self) -> Vec<u8> {
+        self.reader.buffer
+    }
+
     pub fn decompress_all(mut self) -> Vec<(u64, f64)> {
         let mut results = Vec::new();
         
@@ -375,6 +459,14 @@ impl GorillaDecompressor {
     }
 }
 
+impl Iterator for GorillaDecompressor {
+    type Item = (u64, f64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.decompress_next()
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DataPoint {
     pub timestamp: u64,
@@ -382,14 +474,70 @@ pub struct DataPoint {
     pub tags: std::collections::BTreeMap<String, String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct SeriesData {
     pub series_key: String,
     pub compressed_data: Vec<u8>,
+    // 旧格式的文件在块内保存标签；新写入的块为空，标签保存在manifest中
     pub tags: std::collections::BTreeMap<String, String>,
     pub min_timestamp: u64,
     pub max_timestamp: u64,
     pub count: usize,
+    // 与数据点并行的写入时间流（见 format::encode_ingest_times），未记录写入时间时为空
+    pub ingest_data: Vec<u8>,
+}
+
+// 客户端预先压缩的块，与SSTable中的块格式相同（带版本字节，见 format 模块），
+// 可以通过 POST /api/v1/series/:series_key/blocks 直接上传
+#[derive(Debug, Clone, PartialEq)]
+pub struct EncodedBlock {
+    pub data: Vec<u8>,
+    pub count: usize,
+    pub min_timestamp: u64,
+    pub max_timestamp: u64,
+}
+
+impl EncodedBlock {
+    // 数据点必须按时间戳严格递增，时间戳使用服务端的存储精度
+    pub fn encode(points: &[(u64, f64)]) -> Self {
+        Self {
+            data: format::encode_block_v2(points),
+            count: points.len(),
+            min_timestamp: points.first().map_or(0, |p| p.0),
+            max_timestamp: points.last().map_or(0, |p| p.0),
+        }
+    }
+
+    // 完整解压一遍，核对数据点数量、顺序和声明的时间范围，返回解压出的数据点
+    pub fn validate(&self) -> Result<Vec<(u64, f64)>, String> {
+        if self.count == 0 {
+            return Err("块不能为空".to_string());
+        }
+        // 最多解压 count+1 个点就能判断数量是否一致，很小的块不会解压出大量数据点
+        let mut decoder = format::block_decoder(self.data.clone()).map_err(|e| e.to_string())?;
+        let points: Vec<(u64, f64)> = decoder.by_ref().take(self.count.saturating_add(1)).collect();
+        decoder.check().map_err(|e| e.to_string())?;
+        if points.len() > self.count {
+            return Err(format!("声明 {} 个数据点，解压得到的数据点更多", self.count));
+        }
+        if points.len() != self.count {
+            return Err(format!("声明 {} 个数据点，解压得到 {} 个", self.count, points.len()));
+        }
+        if let Some(i) = points.windows(2).position(|pair| pair[0].0 >= pair[1].0) {
+            return Err(format!(
+                "第 {} 个数据点的时间戳 {} 不大于前一个 {}，块内时间戳必须严格递增",
+                i + 2, points[i + 1].0, points[i].0
+            ));
+        }
+        let (first, last) = (points[0].0, points[points.len() - 1].0);
+        if (first, last) != (self.min_timestamp, self.max_timestamp) {
+            return Err(format!(
+                "声明的时间范围 [{}, {}] 与解压得到的 [{}, {}] 不一致",
+                self.min_timestamp, self.max_timestamp, first, last
+            ));
+        }
+        Ok(points)
+    }
 }
 
 // 为 GorillaBitWriter 添加 Default 实现
@@ -0,0 +1,90 @@
+use std::io::{Error, ErrorKind, Result};
+
+use super::{GorillaCompressor, GorillaDecompressor};
+
+// Gorilla块的编码格式。每个块以一个版本字节开头，后面是该版本的位流。
+// 已发布版本的位流不再修改，编码方式的任何变化（标记、取值范围、截断规则）都必须使用新的版本号，
+// 外部工具可以据此判断能否解码。SSTable格式 v3 之前的文件中的块没有版本字节，是 v1 位流
+//
+// v1 位流：最初的编码，与 v2 的区别是没有转义。第一个delta只有 '10' + 14位补码（写入时超出 ±8191 的值被截断），
+//   delta-of-delta 的 '11' + 12位补码中 0x800 表示 -2048（写入时超出12位的值被截断）
+pub const BLOCK_FORMAT_V1: u8 = 1;
+
+// v2 位流，按位从高到低写入:
+//   第一个点: 64位时间戳 + 64位数值
+//   第一个delta: '10' + 14位补码，超出 ±8191 时 '11' + 64位zigzag
+//   之后的delta-of-delta: '0' 表示不变，'10' + 7位补码，'11' + 12位补码，超出 ±2047 时 '11' + 0x800 + 64位原值
+//   之后的数值: '0' 表示与前值相同，否则 '1' + 6位前导零个数 + 6位有效位数（0表示64）+ 有效位
+//   结束标记: 8个1，最后一个字节不足8位时补0
+//   解码时8个1之后剩余不足8位才是结束标记，否则按数据点解码（很大的第一个delta也可能以8个1开头）
+pub const BLOCK_FORMAT_V2: u8 = 2;
+pub const CURRENT_BLOCK_FORMAT: u8 = BLOCK_FORMAT_V2;
+
+// 编码为带版本字节的 v2 块
+pub fn encode_block_v2(points: &[(u64, f64)]) -> Vec<u8> {
+    let mut compressor = GorillaCompressor::new();
+    for &(timestamp, value) in points {
+        compressor.compress_datapoint(timestamp, value);
+    }
+    let stream = compressor.finish();
+    let mut block = Vec::with_capacity(stream.len() + 1);
+    block.push(BLOCK_FORMAT_V2);
+    block.extend_from_slice(&stream);
+    block
+}
+
+// 写入时间流：与数据块并行的 v2 块，每个点是 (事件时间戳, 写入时间戳的位模式)，按事件时间戳对应块内的数据点。
+// 没有记录写入时间时为空
+pub fn encode_ingest_times(times: &[(u64, u64)]) -> Vec<u8> {
+    if times.is_empty() {
+        return Vec::new();
+    }
+    let points: Vec<(u64, f64)> = times.iter().map(|&(timestamp, ingest)| (timestamp, f64::from_bits(ingest))).collect();
+    encode_block_v2(&points)
+}
+
+pub fn decode_ingest_times(block: &[u8]) -> Result<Vec<(u64, u64)>> {
+    if block.is_empty() {
+        return Ok(Vec::new());
+    }
+    Ok(decode_versioned(block)?.into_iter().map(|(timestamp, ingest)| (timestamp, ingest.to_bits())).collect())
+}
+
+// 按指定版本解码不带版本字节的位流
+pub fn decode_block(version: u8, bytes: &[u8]) -> Result<Vec<(u64, f64)>> {
+    check_version(version)?;
+    let mut decoder = GorillaDecompressor::for_version(bytes.to_vec(), version);
+    let points = decoder.by_ref().collect();
+    decoder.check()?;
+    Ok(points)
+}
+
+// 读取块开头的版本字节并解码整个块
+pub fn decode_versioned(block: &[u8]) -> Result<Vec<(u64, f64)>> {
+    let (&version, bytes) = block
+        .split_first()
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "块数据为空"))?;
+    decode_block(version, bytes)
+}
+
+// 逐点解码带版本字节的块，不复制块数据。位流损坏时提前结束，原因见 GorillaDecompressor::error
+pub fn block_decoder(block: Vec<u8>) -> Result<GorillaDecompressor> {
+    let version = *block
+        .first()
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "块数据为空"))?;
+    check_version(version)?;
+    let mut decoder = GorillaDecompressor::for_version(block, version);
+    decoder.reader.bit_pos = 8;
+    Ok(decoder)
+}
+
+pub(crate) fn check_version(version: u8) -> Result<()> {
+    if (BLOCK_FORMAT_V1..=CURRENT_BLOCK_FORMAT).contains(&version) {
+        Ok(())
+    } else {
+        Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("不支持的块编码版本 {}，当前支持 {}-{}", version, BLOCK_FORMAT_V1, CURRENT_BLOCK_FORMAT),
+        ))
+    }
+}
@@ -0,0 +1,119 @@
+use super::format::{block_decoder, decode_versioned, encode_block_v2, BLOCK_FORMAT_V2};
+use super::scratch::{DecodeScratch, DecodeScratchCounters};
+use super::{EncodedBlock, GorillaDecompressor};
+
+// 随机输入测试的检查函数，供 fuzz/ 目录下的 cargo-fuzz 目标（需要 fuzz 特性）和库内的随机测试共用。
+// 不满足约定时直接panic，fuzz 工具据此保存触发问题的输入
+
+// stream_bytes 字节的位流最多能解码出的数据点数：第一个点128位，之后每个点至少2位
+pub fn max_points(stream_bytes: usize) -> usize {
+    match stream_bytes.saturating_mul(8).checked_sub(128) {
+        Some(bits) => 1 + bits / 2,
+        None => 0,
+    }
+}
+
+// 把任意字节当作 v2 位流解码：必须结束且不panic，点数不超过 max_points，结束后不再输出数据点；
+// 整块解码、逐点解码、复用缓冲区解码和上传块校验的结果一致
+pub fn check_decoder(stream: &[u8]) {
+    let limit = max_points(stream.len());
+    let mut decoder = GorillaDecompressor::new(stream.to_vec());
+    let mut points = Vec::new();
+    while let Some(point) = decoder.decompress_next() {
+        points.push(point);
+        assert!(points.len() <= limit, "{} 字节的位流解码出超过 {} 个数据点", stream.len(), limit);
+    }
+    assert_eq!(decoder.decompress_next(), None, "解码结束后又输出了数据点");
+    let corrupted = decoder.error().is_some();
+
+    let mut block = Vec::with_capacity(stream.len() + 1);
+    block.push(BLOCK_FORMAT_V2);
+    block.extend_from_slice(stream);
+
+    match decode_versioned(&block) {
+        Ok(decoded) => {
+            assert!(!corrupted, "损坏的位流整块解码成功");
+            assert_same(&decoded, &points);
+        }
+        Err(_) => assert!(corrupted, "完整的位流整块解码失败"),
+    }
+    let streamed: Vec<(u64, f64)> = block_decoder(block.clone()).expect("版本字节有效").collect();
+    assert_same(&streamed, &points);
+    match DecodeScratch::new().decode(block.clone(), usize::MAX, &DecodeScratchCounters::new(), |_| true) {
+        Ok(decoded) => assert_same(decoded, &points),
+        Err(_) => assert!(corrupted, "完整的位流复用缓冲区解码失败"),
+    }
+
+    // 声明的点数与实际不符的块必须被拒绝，校验通过时返回的就是解码出的数据点
+    let (min_timestamp, max_timestamp) = (points.first().map_or(0, |p| p.0), points.last().map_or(0, |p| p.0));
+    for count in [0, 1, points.len(), points.len() + 1] {
+        let encoded = EncodedBlock { data: block.clone(), count, min_timestamp, max_timestamp };
+        if let Ok(validated) = encoded.validate() {
+            assert!(!corrupted && count == points.len(), "声明 {} 个点的块校验通过", count);
+            assert_same(&validated, &points);
+        }
+    }
+}
+
+// 任意数据点序列（时间戳可以乱序、回绕，数值可以是NaN）编码后解码，按位还原
+pub fn check_round_trip(points: &[(u64, f64)]) {
+    let block = encode_block_v2(points);
+    let decoded = decode_versioned(&block).expect("编码的块可以解码");
+    assert_same(&decoded, points);
+    let streamed: Vec<(u64, f64)> = block_decoder(block.clone()).expect("版本字节有效").collect();
+    assert_same(&streamed, points);
+
+    if !points.is_empty() && points.windows(2).all(|pair| pair[0].0 < pair[1].0) {
+        let validated = EncodedBlock::encode(points).validate().expect("时间戳递增的块校验通过");
+        assert_same(&validated, points);
+    }
+}
+
+// 由任意字节生成数据点序列。每个点使用一个控制字节和最多8个字节的参数，
+// 控制字节的低3位选择时间戳的变化，高位选择数值的变化，覆盖各种delta、delta-of-delta和异或宽度
+pub fn points_from_bytes(data: &[u8]) -> Vec<(u64, f64)> {
+    const SPECIAL: [f64; 8] = [f64::NAN, f64::INFINITY, f64::NEG_INFINITY, -0.0, 0.0, f64::MIN_POSITIVE, f64::MAX, f64::MIN];
+
+    let mut points: Vec<(u64, f64)> = Vec::new();
+    let (mut delta, mut timestamp, mut value) = (0i64, 0u64, 0f64);
+    for chunk in data.chunks(9) {
+        let control = chunk[0];
+        let mut arg_bytes = [0u8; 8];
+        arg_bytes[..chunk.len() - 1].copy_from_slice(&chunk[1..]);
+        let arg = u64::from_le_bytes(arg_bytes);
+
+        if points.is_empty() {
+            timestamp = arg;
+            value = f64::from_bits(arg.rotate_left(control as u32));
+        } else {
+            match control & 0b111 {
+                0 => {}
+                1 => delta = delta.wrapping_add(arg as i8 as i64),
+                2 => delta = delta.wrapping_add(arg as i16 as i64 % 4096),
+                3 => delta = arg as i64,
+                4 => delta = (arg as u32) as i64,
+                5 => delta = arg.wrapping_sub(timestamp) as i64,
+                6 => delta = (u64::MAX - (arg & 0xff)).wrapping_sub(timestamp) as i64,
+                _ => delta = 1 + (arg % 1000) as i64,
+            }
+            timestamp = timestamp.wrapping_add(delta as u64);
+            value = match (control >> 3) & 0b111 {
+                0 => value,
+                1 => value + (arg & 0xff) as f64,
+                2 => f64::from_bits(arg),
+                3 => f64::from_bits(value.to_bits() ^ (arg & 0xffff)),
+                4 => SPECIAL[(arg % 8) as usize],
+                5 => f64::from_bits(value.to_bits() ^ arg.rotate_left(control as u32)),
+                _ => (arg % 1000) as f64,
+            };
+        }
+        points.push((timestamp, value));
+    }
+    points
+}
+
+// 按位比较，NaN 与 -0.0 也必须原样还原
+fn assert_same(actual: &[(u64, f64)], expected: &[(u64, f64)]) {
+    let bits = |points: &[(u64, f64)]| points.iter().map(|&(ts, v)| (ts, v.to_bits())).collect::<Vec<_>>();
+    assert_eq!(bits(actual), bits(expected), "解码结果不一致");
+}
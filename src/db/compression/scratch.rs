@@ -0,0 +1,114 @@
+use std::cell::RefCell;
+use std::io::Result;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::format::check_version;
+use super::GorillaDecompressor;
+
+// 解压整块时使用的临时缓冲区，存放解压出的数据点。每个线程保留一份，
+// 同一线程上依次进行的解压复用已分配的内存，不再每个块都重新分配
+#[derive(Debug, Default)]
+pub struct DecodeScratch {
+    points: Vec<(u64, f64)>,
+}
+
+impl DecodeScratch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // 解码带版本字节的块，块数据交给解码器不再复制。只保留前 limit 个点中 keep 为true的点，
+    // 结果在下次解码前有效。缓冲区容量不足时才分配，计入 counters
+    pub fn decode(
+        &mut self,
+        block: Vec<u8>,
+        limit: usize,
+        counters: &DecodeScratchCounters,
+        mut keep: impl FnMut(&(u64, f64)) -> bool,
+    ) -> Result<&mut Vec<(u64, f64)>> {
+        let version = *block
+            .first()
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "块数据为空"))?;
+        check_version(version)?;
+        let capacity = self.points.capacity();
+
+        let mut decoder = GorillaDecompressor::for_block(block, version);
+        self.points.clear();
+        let mut decoded = 0;
+        while decoded < limit {
+            let Some(point) = decoder.decompress_next() else {
+                break;
+            };
+            decoded += 1;
+            if keep(&point) {
+                self.points.push(point);
+            }
+        }
+        decoder.check()?;
+
+        if self.points.capacity() == capacity {
+            counters.reused.fetch_add(1, Ordering::Relaxed);
+        } else {
+            counters.allocated.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(&mut self.points)
+    }
+
+    // 保留的数据点容量，用于判断缓冲区是否被复用
+    pub fn capacity(&self) -> usize {
+        self.points.capacity()
+    }
+
+    // 大块解压后不长期占用内存，保留的容量超过 max_points 时释放多余部分
+    fn trim(&mut self, max_points: usize) {
+        if self.points.capacity() > max_points {
+            self.points = Vec::with_capacity(max_points);
+        }
+    }
+}
+
+thread_local! {
+    static SCRATCH: RefCell<DecodeScratch> = RefCell::new(DecodeScratch::new());
+}
+
+// 在当前线程的解压缓冲区上执行 f，结束后最多保留 max_points 个点的容量。
+// max_points 为0或缓冲区正被同一线程上的外层调用使用时，改用临时的缓冲区
+pub fn with_decode_scratch<R>(max_points: usize, f: impl FnOnce(&mut DecodeScratch) -> R) -> R {
+    if max_points == 0 {
+        return f(&mut DecodeScratch::new());
+    }
+    SCRATCH.with(|scratch| match scratch.try_borrow_mut() {
+        Ok(mut scratch) => {
+            let result = f(&mut scratch);
+            scratch.trim(max_points);
+            result
+        }
+        Err(_) => f(&mut DecodeScratch::new()),
+    })
+}
+
+// 解压缓冲区的使用次数：直接复用已有容量的次数，以及需要重新分配的次数
+#[derive(Debug, Default)]
+pub struct DecodeScratchCounters {
+    reused: AtomicU64,
+    allocated: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct DecodeScratchStats {
+    pub reused: u64,
+    pub allocated: u64,
+}
+
+impl DecodeScratchCounters {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn snapshot(&self) -> DecodeScratchStats {
+        DecodeScratchStats {
+            reused: self.reused.load(Ordering::Relaxed),
+            allocated: self.allocated.load(Ordering::Relaxed),
+        }
+    }
+}
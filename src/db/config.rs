@@ -0,0 +1,348 @@
+use std::collections::BTreeMap;
+use std::io::Result;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::{BlockCompression, DEFAULT_CHANGE_FEED_CAPACITY, Clock, SSTableEncoding, DiskSpace, EngineObserver, ObserverSet, SystemClock, SystemDiskSpace, TagDriftMode, TimePrecision, TimeSeriesDB};
+
+#[derive(Debug, Clone)]
+pub struct DbConfig {
+    // 内存表数据点数量阈值，达到后刷新到SSTable
+    pub memtable_threshold: usize,
+    // 内存表中最早数据点的最大驻留时间，超过后无论大小都会刷新
+    pub max_memtable_age: Option<Duration>,
+    // 超过该秒数没有新的写入时刷新内存表，写入停止后最后一批数据也能及时落盘
+    pub idle_flush_secs: Option<u64>,
+    // 可用磁盘空间低于该值时拒绝写入
+    pub disk_low_watermark_bytes: Option<u64>,
+    // 可用磁盘空间低于该值时同时暂停compaction
+    pub disk_critical_watermark_bytes: Option<u64>,
+    // 磁盘空间检查周期
+    pub disk_check_interval: Duration,
+    // 内部存储的时间戳精度，数据目录创建后不可更改
+    pub timestamp_precision: TimePrecision,
+    // 标签漂移检测模式
+    pub tag_drift_mode: TagDriftMode,
+    // 合并并发的相同范围查询，只解压一次SSTable
+    pub query_coalescing: bool,
+    // 热数据窗口：最新数据点在窗口内的SSTable不参与compaction，保持小文件便于查询近期数据
+    pub hot_window_secs: Option<u64>,
+    // 数据点时间戳最多比当前时间晚多少秒，超过时拒绝写入；未设置时不检查
+    pub max_future_skew_secs: Option<u64>,
+    // 严格校验写入的数据：系列键不超过 MAX_SERIES_KEY_BYTES，标签键非空且键和值不含控制字符，值为有限的数。
+    // 所有写入路径使用相同的规则；默认关闭，与之前的写入行为一致
+    pub strict_validation: bool,
+    // HTTP查询（包括系列详细信息）没有指定时间范围时只查询最近这么多秒，必须大于0，显式指定 all=true 才扫描整个系列；未设置时不限制
+    pub default_query_range_secs: Option<u64>,
+    // 删除后某个SSTable中已删除的点数占比超过该值（0到1之间）时，在后台合并超过该比例的文件回收空间
+    pub compaction_deleted_ratio: Option<f64>,
+    // SSTable文件总大小上限，刷新或compaction后超过时从最旧的数据开始淘汰
+    pub max_total_bytes: Option<u64>,
+    // HTTP写入接口的请求体大小上限（字节），NDJSON流式写入不受限制
+    pub max_request_body_bytes: usize,
+    // 两次按阈值自动刷新之间的最短间隔，期间内存表可以暂时超过阈值；按驻留时间的刷新不受影响
+    pub min_flush_interval: Option<Duration>,
+    // 刷新被抑制时内存表数据点数量的硬上限，未指定时为阈值的4倍
+    pub memtable_hard_limit: Option<usize>,
+    // 启动预热的系列列表（以 '*' 结尾表示前缀）。指定时替换数据目录中保存的列表，否则沿用保存的列表
+    pub warmup_series: Option<Vec<String>>,
+    // 同时执行解压、压缩和compaction的阻塞线程数
+    pub blocking_threads: usize,
+    // 数据点数不超过该值的查询直接在异步任务中解压，不交给阻塞线程；内联的查询不会与并发的相同查询合并
+    pub inline_query_points: usize,
+    // 每个线程为解压保留的缓冲区最多可容纳的数据点数，同一线程上的查询复用这块内存；0表示不复用
+    pub decode_scratch_points: usize,
+    // 变更事件通道的容量，跟不上的订阅方丢失最早的事件
+    pub change_feed_capacity: usize,
+    // 刷新和compaction写出的SSTable使用的通用压缩算法。压缩的文件更小，但每次读取文件元数据都要整体解压，
+    // 适合较少查询的冷数据，通常只对compaction输出启用
+    pub flush_compression: BlockCompression,
+    pub compaction_compression: BlockCompression,
+    // 新写入的SSTable中块的序列化方式。tagged 的每个字段带编号，以后增加块字段时新旧版本可以互读；
+    // 已有文件改写时保持原来的方式，两种文件可以混用
+    pub sstable_encoding: SSTableEncoding,
+    // 同时保持内存映射的SSTable数量上限，超过时淘汰最近最少使用且未在读取中的映射
+    pub max_mapped_sstables: usize,
+    // 为每个数据点记录写入时的服务端时间，查询时可以与事件时间戳一起返回，用于分析迟到的数据。
+    // 每个块多保存一个压缩的时间流，预先压缩上传的块不记录
+    pub record_ingest_time: bool,
+    // 数据目录之外的其他数据目录（如新增的磁盘）。新的SSTable写入可用空间最多的目录，
+    // 启动时扫描所有目录；manifest始终保存在主数据目录中
+    pub extra_data_dirs: Vec<PathBuf>,
+    // 单次范围查询结果的预估内存上限（字节），超过时查询失败；按点数和标签估算，标签多的点占用更多。
+    // 限时分页查询（max_wait_ms）达到上限时返回部分结果
+    pub max_query_memory_bytes: Option<usize>,
+    // 所有并发范围查询预留内存的总预算（字节），预算不足时查询排队等待
+    pub query_memory_budget_bytes: Option<usize>,
+    // 所有系列的敏感标签键（如设备序列号），HTTP响应中隐藏其值；每个系列还可以单独标记。引擎API不受影响
+    pub sensitive_tag_keys: Vec<String>,
+    // 请求头 x-api-key 为其中之一的调用方具有 unredacted 权限，可以看到敏感标签
+    pub unredacted_api_keys: Vec<String>,
+    // 租户API key到系列键前缀的映射。使用这些key的HTTP请求只能访问以对应前缀开头的系列，
+    // 请求和响应中的系列键不带前缀。引擎API不受影响
+    pub tenant_api_keys: BTreeMap<String, String>,
+}
+
+impl Default for DbConfig {
+    fn default() -> Self {
+        Self {
+            memtable_threshold: 1000,
+            max_memtable_age: None,
+            idle_flush_secs: None,
+            disk_low_watermark_bytes: None,
+            disk_critical_watermark_bytes: None,
+            disk_check_interval: Duration::from_secs(10),
+            timestamp_precision: TimePrecision::Seconds,
+            tag_drift_mode: TagDriftMode::Off,
+            query_coalescing: true,
+            hot_window_secs: None,
+            max_future_skew_secs: None,
+            strict_validation: false,
+            default_query_range_secs: None,
+            compaction_deleted_ratio: None,
+            max_total_bytes: None,
+            max_request_body_bytes: 16 * 1024 * 1024,
+            min_flush_interval: None,
+            memtable_hard_limit: None,
+            warmup_series: None,
+            blocking_threads: std::thread::available_parallelism().map(|n| n.get()).unwrap_or(4),
+            inline_query_points: 4096,
+            decode_scratch_points: 65536,
+            change_feed_capacity: DEFAULT_CHANGE_FEED_CAPACITY,
+            flush_compression: BlockCompression::None,
+            compaction_compression: BlockCompression::None,
+            sstable_encoding: SSTableEncoding::Bincode,
+            max_mapped_sstables: 1024,
+            record_ingest_time: false,
+            extra_data_dirs: Vec::new(),
+            max_query_memory_bytes: None,
+            query_memory_budget_bytes: None,
+            sensitive_tag_keys: Vec::new(),
+            unredacted_api_keys: Vec::new(),
+            tenant_api_keys: BTreeMap::new(),
+        }
+    }
+}
+
+impl DbConfig {
+    // 达到该数量时无论距上次刷新多久都会刷新
+    pub fn effective_memtable_hard_limit(&self) -> usize {
+        self.memtable_hard_limit
+            .unwrap_or_else(|| self.memtable_threshold.saturating_mul(4))
+            .max(self.memtable_threshold)
+    }
+}
+
+#[derive(Debug)]
+pub struct TimeSeriesDBBuilder {
+    data_dir: PathBuf,
+    config: DbConfig,
+    clock: Arc<dyn Clock>,
+    disk_space: Arc<dyn DiskSpace>,
+    observers: Vec<Arc<dyn EngineObserver>>,
+}
+
+impl TimeSeriesDBBuilder {
+    pub fn new<P: AsRef<Path>>(data_dir: P) -> Self {
+        Self {
+            data_dir: data_dir.as_ref().to_path_buf(),
+            config: DbConfig::default(),
+            clock: Arc::new(SystemClock),
+            disk_space: Arc::new(SystemDiskSpace),
+            observers: Vec::new(),
+        }
+    }
+
+    pub fn config(mut self, config: DbConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn memtable_threshold(mut self, threshold: usize) -> Self {
+        self.config.memtable_threshold = threshold;
+        self
+    }
+
+    pub fn max_memtable_age(mut self, age: Duration) -> Self {
+        self.config.max_memtable_age = Some(age);
+        self
+    }
+
+    pub fn idle_flush_secs(mut self, secs: u64) -> Self {
+        self.config.idle_flush_secs = Some(secs);
+        self
+    }
+
+    pub fn disk_watermarks(mut self, low_bytes: u64, critical_bytes: u64) -> Self {
+        self.config.disk_low_watermark_bytes = Some(low_bytes);
+        self.config.disk_critical_watermark_bytes = Some(critical_bytes);
+        self
+    }
+
+    pub fn disk_check_interval(mut self, interval: Duration) -> Self {
+        self.config.disk_check_interval = interval;
+        self
+    }
+
+    pub fn timestamp_precision(mut self, precision: TimePrecision) -> Self {
+        self.config.timestamp_precision = precision;
+        self
+    }
+
+    pub fn tag_drift_mode(mut self, mode: TagDriftMode) -> Self {
+        self.config.tag_drift_mode = mode;
+        self
+    }
+
+    pub fn query_coalescing(mut self, enabled: bool) -> Self {
+        self.config.query_coalescing = enabled;
+        self
+    }
+
+    pub fn hot_window_secs(mut self, secs: u64) -> Self {
+        self.config.hot_window_secs = Some(secs);
+        self
+    }
+
+    pub fn max_future_skew_secs(mut self, secs: u64) -> Self {
+        self.config.max_future_skew_secs = Some(secs);
+        self
+    }
+
+    pub fn strict_validation(mut self, enabled: bool) -> Self {
+        self.config.strict_validation = enabled;
+        self
+    }
+
+    pub fn default_query_range_secs(mut self, secs: u64) -> Self {
+        self.config.default_query_range_secs = Some(secs);
+        self
+    }
+
+    pub fn compaction_deleted_ratio(mut self, ratio: f64) -> Self {
+        self.config.compaction_deleted_ratio = Some(ratio);
+        self
+    }
+
+    pub fn max_total_bytes(mut self, bytes: u64) -> Self {
+        self.config.max_total_bytes = Some(bytes);
+        self
+    }
+
+    pub fn max_request_body_bytes(mut self, bytes: usize) -> Self {
+        self.config.max_request_body_bytes = bytes;
+        self
+    }
+
+    pub fn min_flush_interval(mut self, interval: Duration) -> Self {
+        self.config.min_flush_interval = Some(interval);
+        self
+    }
+
+    pub fn memtable_hard_limit(mut self, points: usize) -> Self {
+        self.config.memtable_hard_limit = Some(points);
+        self
+    }
+
+    pub fn warmup_series(mut self, patterns: Vec<String>) -> Self {
+        self.config.warmup_series = Some(patterns);
+        self
+    }
+
+    pub fn blocking_threads(mut self, threads: usize) -> Self {
+        self.config.blocking_threads = threads;
+        self
+    }
+
+    pub fn inline_query_points(mut self, points: usize) -> Self {
+        self.config.inline_query_points = points;
+        self
+    }
+
+    pub fn decode_scratch_points(mut self, points: usize) -> Self {
+        self.config.decode_scratch_points = points;
+        self
+    }
+
+    pub fn change_feed_capacity(mut self, capacity: usize) -> Self {
+        self.config.change_feed_capacity = capacity;
+        self
+    }
+
+    pub fn flush_compression(mut self, compression: BlockCompression) -> Self {
+        self.config.flush_compression = compression;
+        self
+    }
+
+    pub fn compaction_compression(mut self, compression: BlockCompression) -> Self {
+        self.config.compaction_compression = compression;
+        self
+    }
+
+    pub fn sstable_encoding(mut self, encoding: SSTableEncoding) -> Self {
+        self.config.sstable_encoding = encoding;
+        self
+    }
+
+    pub fn max_mapped_sstables(mut self, count: usize) -> Self {
+        self.config.max_mapped_sstables = count;
+        self
+    }
+
+    pub fn record_ingest_time(mut self, enabled: bool) -> Self {
+        self.config.record_ingest_time = enabled;
+        self
+    }
+
+    pub fn extra_data_dirs(mut self, dirs: Vec<PathBuf>) -> Self {
+        self.config.extra_data_dirs = dirs;
+        self
+    }
+
+    pub fn max_query_memory_bytes(mut self, bytes: usize) -> Self {
+        self.config.max_query_memory_bytes = Some(bytes);
+        self
+    }
+
+    pub fn query_memory_budget_bytes(mut self, bytes: usize) -> Self {
+        self.config.query_memory_budget_bytes = Some(bytes);
+        self
+    }
+
+    pub fn sensitive_tag_keys(mut self, keys: Vec<String>) -> Self {
+        self.config.sensitive_tag_keys = keys;
+        self
+    }
+
+    pub fn unredacted_api_keys(mut self, keys: Vec<String>) -> Self {
+        self.config.unredacted_api_keys = keys;
+        self
+    }
+
+    pub fn tenant_api_keys(mut self, keys: BTreeMap<String, String>) -> Self {
+        self.config.tenant_api_keys = keys;
+        self
+    }
+
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    // 数据目录可用空间的来源，默认读取文件系统
+    pub fn disk_space(mut self, disk_space: Arc<dyn DiskSpace>) -> Self {
+        self.disk_space = disk_space;
+        self
+    }
+
+    // 注册引擎事件观察者，可多次调用，按注册顺序通知
+    pub fn with_observer(mut self, observer: Arc<dyn EngineObserver>) -> Self {
+        self.observers.push(observer);
+        self
+    }
+
+    pub fn build(self) -> Result<TimeSeriesDB> {
+        TimeSeriesDB::open(self.data_dir, self.config, self.clock, self.disk_space, ObserverSet::new(self.observers))
+    }
+}
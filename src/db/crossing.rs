@@ -0,0 +1,80 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CrossingDirection {
+    // 从低于阈值变为大于等于阈值
+    #[default]
+    Above,
+    // 从大于等于阈值变为低于阈值
+    Below,
+    Both,
+}
+
+impl FromStr for CrossingDirection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "above" => Ok(Self::Above),
+            "below" => Ok(Self::Below),
+            "both" => Ok(Self::Both),
+            other => Err(format!("不支持的穿越方向: {}，可选 above/below/both", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Crossing {
+    pub timestamp: u64,
+    pub value: f64,
+    pub previous_timestamp: u64,
+    pub previous_value: f64,
+    // 本次穿越的方向（above 或 below）
+    pub direction: CrossingDirection,
+}
+
+// 逐点检测阈值穿越，只保存上一个点
+#[derive(Debug, Clone)]
+pub struct CrossingDetector {
+    threshold: f64,
+    direction: CrossingDirection,
+    previous: Option<(u64, f64)>,
+}
+
+impl CrossingDetector {
+    pub fn new(threshold: f64, direction: CrossingDirection) -> Self {
+        Self {
+            threshold,
+            direction,
+            previous: None,
+        }
+    }
+
+    pub fn push(&mut self, timestamp: u64, value: f64) -> Option<Crossing> {
+        // 范围内的第一个点没有前一个点，不算穿越
+        let (previous_timestamp, previous_value) = self.previous.replace((timestamp, value))?;
+
+        let was_above = previous_value >= self.threshold;
+        let is_above = value >= self.threshold;
+        let crossed = match (was_above, is_above) {
+            (false, true) => CrossingDirection::Above,
+            (true, false) => CrossingDirection::Below,
+            _ => return None,
+        };
+
+        if self.direction != CrossingDirection::Both && self.direction != crossed {
+            return None;
+        }
+
+        Some(Crossing {
+            timestamp,
+            value,
+            previous_timestamp,
+            previous_value,
+            direction: crossed,
+        })
+    }
+}
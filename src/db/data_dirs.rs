@@ -0,0 +1,114 @@
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::Serialize;
+
+use super::DiskSpace;
+
+// 数据库使用的数据目录。主目录保存manifest，SSTable可以分布在所有目录中，
+// 新的SSTable写入可用空间最多的目录
+#[derive(Debug, Clone)]
+pub struct DataDirs {
+    // 主目录在最前
+    dirs: Vec<PathBuf>,
+    // 与 dirs 一一对应，用于比较调用方传入的路径
+    canonical: Vec<PathBuf>,
+    space: Arc<dyn DiskSpace>,
+}
+
+impl DataDirs {
+    // 创建不存在的目录，同一目录不能重复出现
+    pub fn new(primary: PathBuf, extra: &[PathBuf], space: Arc<dyn DiskSpace>) -> Result<Self> {
+        let mut dirs = Vec::with_capacity(extra.len() + 1);
+        let mut canonical: Vec<PathBuf> = Vec::with_capacity(extra.len() + 1);
+        for dir in std::iter::once(&primary).chain(extra) {
+            fs::create_dir_all(dir)?;
+            let resolved = fs::canonicalize(dir)?;
+            if canonical.contains(&resolved) {
+                return Err(Error::new(ErrorKind::InvalidInput, format!("数据目录重复: {:?}", dir)));
+            }
+            dirs.push(dir.clone());
+            canonical.push(resolved);
+        }
+        Ok(Self { dirs, canonical, space })
+    }
+
+    pub fn primary(&self) -> &Path {
+        &self.dirs[0]
+    }
+
+    pub fn all(&self) -> &[PathBuf] {
+        &self.dirs
+    }
+
+    pub fn space(&self) -> &Arc<dyn DiskSpace> {
+        &self.space
+    }
+
+    // 调用方给出的路径对应的已配置目录
+    pub fn find(&self, dir: &Path) -> Option<&Path> {
+        let resolved = fs::canonicalize(dir).ok()?;
+        self.canonical
+            .iter()
+            .position(|candidate| *candidate == resolved)
+            .map(|index| self.dirs[index].as_path())
+    }
+
+    // 文件所在的已配置目录。SSTable的路径总是由配置的目录和文件名拼成
+    pub fn dir_of(&self, file_path: &Path) -> Option<&Path> {
+        let parent = file_path.parent()?;
+        self.dirs.iter().map(PathBuf::as_path).find(|dir| *dir == parent)
+    }
+
+    // 新SSTable的目录：可用空间最多的目录，空间相同或无法检查时靠前的优先
+    pub fn pick_for_write(&self) -> &Path {
+        if self.dirs.len() == 1 {
+            return self.primary();
+        }
+        let mut best: Option<(&Path, u64)> = None;
+        for dir in &self.dirs {
+            match self.space.available_space(dir) {
+                Ok(free) if best.is_none_or(|(_, best_free)| free > best_free) => best = Some((dir, free)),
+                Ok(_) => {}
+                Err(e) => tracing::warn!("检查数据目录可用空间失败 {:?}: {}", dir, e),
+            }
+        }
+        best.map_or(self.primary(), |(dir, _)| dir)
+    }
+}
+
+// 把目录项的修改（新建、重命名、删除文件）持久化
+pub fn sync_dir(dir: &Path) -> Result<()> {
+    #[cfg(unix)]
+    {
+        fs::File::open(dir)?.sync_all()
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = dir;
+        Ok(())
+    }
+}
+
+// 单个数据目录的用量
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DataDirUsage {
+    pub path: PathBuf,
+    pub primary: bool,
+    pub sstable_count: usize,
+    pub sstable_bytes: u64,
+    // 所在文件系统的可用空间，检查失败时为None
+    pub free_bytes: Option<u64>,
+}
+
+// 在数据目录之间移动一个SSTable的结果，文件已在目标目录时 moved 为false
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SSTableRelocation {
+    pub file_name: String,
+    pub from: PathBuf,
+    pub to: PathBuf,
+    pub size_bytes: u64,
+    pub moved: bool,
+}
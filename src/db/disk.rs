@@ -0,0 +1,143 @@
+use std::fmt::Debug;
+use std::io::Result;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use serde::Serialize;
+
+// 数据目录所在文件系统的可用空间（字节）
+#[cfg(unix)]
+pub fn available_space(path: &Path) -> Result<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    let ret = unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) };
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok((stat.f_bavail as u64).saturating_mul(stat.f_frsize as u64))
+}
+
+#[cfg(not(unix))]
+pub fn available_space(_path: &Path) -> Result<u64> {
+    Ok(u64::MAX)
+}
+
+// 可用空间的来源，便于在测试中模拟磁盘将满
+pub trait DiskSpace: Send + Sync + Debug {
+    fn available_space(&self, path: &Path) -> Result<u64>;
+}
+
+#[derive(Debug, Default)]
+pub struct SystemDiskSpace;
+
+impl DiskSpace for SystemDiskSpace {
+    fn available_space(&self, path: &Path) -> Result<u64> {
+        available_space(path)
+    }
+}
+
+// 所有目录都返回手动设置的可用空间，用于测试
+#[derive(Debug)]
+pub struct MockDiskSpace {
+    free_bytes: AtomicU64,
+}
+
+impl MockDiskSpace {
+    pub fn new(free_bytes: u64) -> Self {
+        Self {
+            free_bytes: AtomicU64::new(free_bytes),
+        }
+    }
+
+    pub fn set(&self, free_bytes: u64) {
+        self.free_bytes.store(free_bytes, Ordering::Relaxed);
+    }
+}
+
+impl DiskSpace for MockDiskSpace {
+    fn available_space(&self, _path: &Path) -> Result<u64> {
+        Ok(self.free_bytes.load(Ordering::Relaxed))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiskLevel {
+    Ok,
+    // 低于低水位线：拒绝写入，但允许删除、保留策略和compaction
+    Low,
+    // 低于临界水位线：额外暂停需要临时空间的compaction
+    Critical,
+}
+
+// 缓存最近一次的磁盘空间检查结果，写入路径只读原子变量。
+// 有多个数据目录时取可用空间最多的目录，新的SSTable总是写入该目录
+#[derive(Debug)]
+pub struct DiskMonitor {
+    paths: Vec<PathBuf>,
+    space: Arc<dyn DiskSpace>,
+    low_watermark: Option<u64>,
+    critical_watermark: Option<u64>,
+    free_bytes: AtomicU64,
+}
+
+impl DiskMonitor {
+    pub fn new(paths: Vec<PathBuf>, space: Arc<dyn DiskSpace>, low_watermark: Option<u64>, critical_watermark: Option<u64>) -> Self {
+        Self {
+            paths,
+            space,
+            low_watermark,
+            critical_watermark,
+            free_bytes: AtomicU64::new(u64::MAX),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.low_watermark.is_some() || self.critical_watermark.is_some()
+    }
+
+    pub fn refresh(&self) -> Result<u64> {
+        let mut free = self.space.available_space(&self.paths[0])?;
+        for path in &self.paths[1..] {
+            match self.space.available_space(path) {
+                Ok(space) => free = free.max(space),
+                Err(e) => tracing::warn!("检查数据目录可用空间失败 {:?}: {}", path, e),
+            }
+        }
+        self.free_bytes.store(free, Ordering::Relaxed);
+        Ok(free)
+    }
+
+    pub fn free_bytes(&self) -> Option<u64> {
+        match self.free_bytes.load(Ordering::Relaxed) {
+            u64::MAX => None,
+            free => Some(free),
+        }
+    }
+
+    pub fn low_watermark(&self) -> Option<u64> {
+        self.low_watermark
+    }
+
+    pub fn critical_watermark(&self) -> Option<u64> {
+        self.critical_watermark
+    }
+
+    pub fn level(&self) -> DiskLevel {
+        let Some(free) = self.free_bytes() else {
+            return DiskLevel::Ok;
+        };
+        if self.critical_watermark.is_some_and(|w| free < w) {
+            DiskLevel::Critical
+        } else if self.low_watermark.is_some_and(|w| free < w) {
+            DiskLevel::Low
+        } else {
+            DiskLevel::Ok
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::{bucket_start, AggregateFn, BucketAccumulator, DataPoint, SeriesMatcher};
+
+// 按年龄降低分辨率：早于 after_secs 的数据在compaction时每 interval_secs 聚合为一个点
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DownsampleRule {
+    #[serde(rename = "match")]
+    pub matcher: SeriesMatcher,
+    pub after_secs: u64,
+    pub interval_secs: u64,
+    pub function: AggregateFn,
+}
+
+// 降采样策略：规则按顺序匹配，第一个命中的规则生效，未命中的系列保持原始分辨率
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DownsamplePolicy {
+    #[serde(default)]
+    pub rules: Vec<DownsampleRule>,
+}
+
+impl DownsamplePolicy {
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn rule_for(&self, series_key: &str, tags: &BTreeMap<String, String>) -> Option<&DownsampleRule> {
+        self.rules.iter().find(|rule| rule.matcher.matches(series_key, tags))
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        for (index, rule) in self.rules.iter().enumerate() {
+            if rule.interval_secs == 0 {
+                return Err(format!("第 {} 条规则的 interval_secs 必须大于0", index + 1));
+            }
+        }
+        Ok(())
+    }
+}
+
+// 系列中已降低分辨率的时间范围 [start, end)，时间戳和间隔均为内部精度。
+// end 之前的数据不会再次聚合，之后写入的更早数据保持原始分辨率
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResolutionSegment {
+    pub start: u64,
+    pub end: u64,
+    pub interval: u64,
+    pub function: AggregateFn,
+}
+
+// 追加一段降采样范围，与上一段首尾相接且分辨率相同时合并
+pub fn record_segment(segments: &mut Vec<ResolutionSegment>, segment: ResolutionSegment) {
+    match segments.last_mut() {
+        Some(last) if last.end == segment.start && last.interval == segment.interval && last.function == segment.function => {
+            last.end = segment.end;
+        }
+        _ => segments.push(segment),
+    }
+}
+
+// 把 [from, before) 内的点按 interval 聚合，每个桶输出一个时间戳为桶起点的点，其余点原样保留。
+// points 需按时间排序且没有重复的时间戳
+pub fn downsample_points(points: Vec<DataPoint>, from: u64, before: u64, interval: u64, function: AggregateFn) -> Vec<DataPoint> {
+    let mut output = Vec::with_capacity(points.len());
+    let mut bucket: Option<(u64, BTreeMap<String, String>, BucketAccumulator)> = None;
+    for point in points {
+        if point.timestamp < from || point.timestamp >= before {
+            if let Some(finished) = bucket.take() {
+                output.extend(finish_bucket(finished));
+            }
+            output.push(point);
+            continue;
+        }
+        let start = bucket_start(point.timestamp, interval);
+        if bucket.as_ref().is_some_and(|(current, _, _)| *current != start) {
+            output.extend(bucket.take().and_then(finish_bucket));
+        }
+        bucket
+            .get_or_insert_with(|| (start, point.tags, BucketAccumulator::new(function)))
+            .2
+            .push(point.value);
+    }
+    output.extend(bucket.and_then(finish_bucket));
+    output
+}
+
+fn finish_bucket((timestamp, tags, accumulator): (u64, BTreeMap<String, String>, BucketAccumulator)) -> Option<DataPoint> {
+    accumulator.finish().map(|value| DataPoint { timestamp, value, tags })
+}
@@ -1,421 +1,4297 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::{File, OpenOptions};
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, Mutex, RwLock};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 use std::io::Result;
+use std::ops::ControlFlow;
 
-use super::{DataPoint, Memtable, SSTable, GorillaCompressor, SeriesData};
+use parking_lot::{Mutex, RwLock};
+use tokio_util::sync::CancellationToken;
+
+use super::{
+    AggregateBucket, AggregateFn, bucket_start, CumulativeHistogram, Clock, DownsampleBucket, EnvelopeBucket, FillPolicy, TimeBucket, Crossing, CrossingDetector, CrossingDirection, Gap, GapDetector, MergedPoints, CompactionInput, CompactionOptions, CompactionOutput, CompactionReport, COMPACTION_HISTORY_LIMIT, BucketAccumulator, CrossSeriesBuckets, DataPoint, DbError, DiskLevel,
+    DiskMonitor, EngineMetrics, FlushEvent, Manifest, ObserverSet, RetentionPolicy, RetentionReport, TagDriftDetector, TimePrecision, DbConfig, EncodedBlock, FreshnessMap, StaleSeries, SeriesLocationIndex, Memtable, MemtableView, BackgroundTasks, TaskStatus, MAX_POINTS_PER_BLOCK, SeriesData, SingleFlight, SSTable,
+    SystemClock, SystemDiskSpace, TimeWindow, WindowCollector, WindowPoint, WindowResult, MAX_QUERY_WINDOWS, DiskSpace, TimeSeriesDBBuilder, BlockCompression, SSTableEncoding, BlockingPool, BlockingPoolStats, MappingCache, MappingStats, OperationHandle, OperationInfo, OperationKind, OperationRegistry, OverlapAnalysis, OverlapCache, OverlapReport, sync_dir, DataDirs, DataDirUsage, SSTableRelocation, QueryMemory, QueryMemoryStats, QueryReservation, ReplacePlan, staged_path, STAGED_SUFFIX, chunk_windows, summarize_chunks, ChunkSummary, IngestRate, IngestedPoints, DownsamplePolicy, ResolutionSegment, downsample_points, record_segment, StatsSummary, ResolvedRange, TimeRange, top_series, SUMMARY_TOP_SERIES, QuotaAction, QuotaPolicy, QuotaReport, QuotaRule, QuotaStatus, QuotaTracker, QuotaUsage, BoundsReport, CompressionReport, SSTableDetail, SSTableInfo, series_summaries, ExportManifest, ExportSnapshot, ImportReport, ImportedArchive, unpack_archive, EXPORT_DIR_PREFIX, IMPORT_DIR_PREFIX, warmup_matches, WarmupProgress, WarmupState, WarmupStatus, prefix_range, PrefixStats, PrefixDeleteReport, ValidationError, validate_series_key, validate_tags, QueryCancellation, QueryProgress, QueryProgressStats, Annotation, AnnotationStore, NewAnnotation, validate_annotation, FileCheck, FileOutcome, JobInfo, SSTableVerifier, VerifyReport, VERIFY_CHUNK_FILES, ChangeEvent, ChangeFeed, TagFilter,
+};
+use super::series_walk::{SeriesMetadata, SeriesWalk};
+
+use super::compression::format::{block_decoder, decode_ingest_times, encode_block_v2, encode_ingest_times};
+use super::compression::scratch::{with_decode_scratch, DecodeScratch, DecodeScratchCounters, DecodeScratchStats};
+
+type SSTableQueryKey = (String, Option<u64>, Option<u64>);
+type SSTableQueryResult = std::result::Result<Arc<SSTableScan>, String>;
+// 文件路径和当时的大小、修改时间，用于判断文件是否在锁外读取期间被改写
+type FileVersion = (PathBuf, Option<(u64, Option<std::time::SystemTime>)>);
+
+// 一次SSTable查询的结果，合并的查询共享同一份
+#[derive(Debug, Default)]
+struct SSTableScan {
+    // 每个块中范围内的点，块内按时间排序
+    blocks: Vec<Vec<(u64, f64)>>,
+    files_scanned: usize,
+}
+
+// 单次查询的数据来源统计，去重后内存表与SSTable的点数之和等于返回的点数。
+// sources_merged 为参与归并的非空有序数据源个数（内存表和每个相交的块），
+// duplicates_resolved 为归并时因时间戳重复而丢弃的点数
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct QueryStats {
+    pub memtable_points: usize,
+    pub sstable_points: usize,
+    pub files_scanned: usize,
+    pub sources_merged: usize,
+    pub duplicates_resolved: usize,
+}
 
 #[derive(Debug)]
 pub struct TimeSeriesDB {
     memtable: Arc<RwLock<Memtable>>,
+    // 正在刷新的内存表，对应的SSTable加入之前查询仍然读取其中的数据
+    flushing_memtable: Arc<RwLock<Option<Arc<Memtable>>>>,
     sstables: Arc<Mutex<Vec<SSTable>>>,
     data_dir: PathBuf,
-    memtable_threshold: usize,
+    // 主数据目录和其他数据目录，SSTable可以位于其中任一目录
+    data_dirs: Arc<DataDirs>,
+    config: Arc<DbConfig>,
+    clock: Arc<dyn Clock>,
+    disk: Arc<DiskMonitor>,
+    manifest: Arc<RwLock<Manifest>>,
+    tag_drift: Arc<TagDriftDetector>,
+    last_timestamps: Arc<FreshnessMap>,
+    locations: Arc<SeriesLocationIndex>,
+    // 系列的首次写入时间或标签尚未持久化到manifest
+    series_meta_dirty: Arc<AtomicBool>,
+    sstable_queries: Arc<SingleFlight<SSTableQueryKey, SSTableQueryResult>>,
+    sstable_scans: Arc<AtomicU64>,
+    // 最近一小时每秒写入的点数
+    ingest_rate: Arc<IngestRate>,
+    compaction_history: Arc<Mutex<VecDeque<CompactionReport>>>,
+    // 按删除比例触发的compaction正在执行
+    deleted_compaction_running: Arc<AtomicBool>,
+    observers: Arc<ObserverSet>,
+    // 上一次刷新内存表的时间
+    last_flush: Arc<Mutex<Option<SystemTime>>>,
+    warmup: Arc<WarmupProgress>,
+    // 下一个新SSTable文件的序号
+    next_sequence: Arc<AtomicU64>,
+    // 解压、压缩和compaction的执行池
+    blocking: Arc<BlockingPool>,
+    // 所有SSTable共用的内存映射缓存
+    mappings: Arc<MappingCache>,
+    // 范围查询结果的内存记账和全局预算
+    query_memory: Arc<QueryMemory>,
+    // 解压缓冲区复用和重新分配的次数
+    decode_scratch: Arc<DecodeScratchCounters>,
+    query_progress: Arc<QueryProgress>,
+    annotations: Arc<AnnotationStore>,
+    // 按前缀的存储配额及缓存的用量
+    quota: Arc<QuotaTracker>,
+    // 进行中的compaction、导出等可取消的操作
+    operations: Arc<OperationRegistry>,
+    // compaction、导出和完整性校验共用的维护许可，同一时间只有一个在执行
+    maintenance: Arc<tokio::sync::Semaphore>,
+    // 写入、更新和删除的变更事件
+    changes: Arc<ChangeFeed>,
+    // 缓存的SSTable时间范围重叠分析
+    overlap: Arc<OverlapCache>,
+    // 每次写入内存表分配的递增序号，以及已刷新到SSTable的最大序号
+    write_sequence: Arc<AtomicU64>,
+    durable_sequence: Arc<tokio::sync::watch::Sender<u64>>,
+    // 刷新内存表串行执行，刷新完成时所有不大于其序号的写入都已落盘
+    flush_lock: Arc<tokio::sync::Mutex<()>>,
+    // 替换系列数据时持有写锁，扫描在读锁内取内存表和SSTable的快照；
+    // 分两步读取的范围查询按替换计数判断期间是否发生了替换
+    replace_gate: Arc<RwLock<()>>,
+    replace_generation: Arc<AtomicU64>,
+    shutdown: CancellationToken,
+    background_tasks: Arc<BackgroundTasks>,
+    dir_lock: Arc<Mutex<Vec<File>>>,
 }
 
 impl TimeSeriesDB {
     pub fn new<P: AsRef<Path>>(data_dir: P, memtable_threshold: usize) -> Result<Self> {
+        let config = DbConfig {
+            memtable_threshold,
+            ..DbConfig::default()
+        };
+        Self::open(data_dir, config, Arc::new(SystemClock), Arc::new(SystemDiskSpace), ObserverSet::new(Vec::new()))
+    }
+
+    pub fn builder<P: AsRef<Path>>(data_dir: P) -> TimeSeriesDBBuilder {
+        TimeSeriesDBBuilder::new(data_dir)
+    }
+
+    pub(crate) fn open<P: AsRef<Path>>(
+        data_dir: P,
+        config: DbConfig,
+        clock: Arc<dyn Clock>,
+        space: Arc<dyn DiskSpace>,
+        observers: ObserverSet,
+    ) -> Result<Self> {
         let data_dir = data_dir.as_ref().to_path_buf();
-        std::fs::create_dir_all(&data_dir)?;
+        let data_dirs = DataDirs::new(data_dir.clone(), &config.extra_data_dirs, Arc::clone(&space))?;
+
+        // 独占所有数据目录，防止多个实例同时写入同一目录
+        let mut lock_files = Vec::new();
+        for dir in data_dirs.all() {
+            let lock_file = OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(dir.join("LOCK"))?;
+            if lock_file.try_lock().is_err() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::WouldBlock,
+                    format!("数据目录已被其他实例占用: {:?}", dir),
+                ));
+            }
+            lock_files.push(lock_file);
+        }
+
+        Self::finish_pending_replace(&data_dir, &data_dirs)?;
+        let mappings = Arc::new(MappingCache::new(config.max_mapped_sstables));
+        let mut sstables = Vec::new();
+        for dir in data_dirs.all() {
+            let Ok(entries) = std::fs::read_dir(dir) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.extension().and_then(|s| s.to_str()) == Some("data") {
+                    sstables.push(SSTable::new(path)?.with_mapping_cache(Arc::clone(&mappings)));
+                } else if Self::is_leftover_temp(&path) {
+                    // 上次运行中断时残留的临时文件和导入导出目录
+                    let removed = if path.is_dir() { std::fs::remove_dir_all(&path) } else { std::fs::remove_file(&path) };
+                    if let Err(e) = removed {
+                        tracing::warn!("清理残留临时文件失败 {:?}: {}", path, e);
+                    }
+                }
+            }
+        }
+        let mut sstables = Self::drop_relocated_duplicates(sstables)?;
+        // 按序号恢复文件的创建顺序，不依赖目录遍历顺序。相同时间戳的数据点在多个文件中时，
+        // 查询保留列表中靠前（较早）文件的值，启动前后结果一致
+        sstables.sort_by_key(SSTable::order_key);
+        let next_sequence = sstables.iter().filter_map(SSTable::sequence).max().map_or(1, |seq| seq + 1);
+
+        let disk = DiskMonitor::new(
+            data_dirs.all().to_vec(),
+            space,
+            config.disk_low_watermark_bytes,
+            config.disk_critical_watermark_bytes,
+        );
+        if let Err(e) = disk.refresh() {
+            tracing::warn!("检查磁盘空间失败: {}", e);
+        }
+
+        let mut manifest = Manifest::load(&data_dir)?;
+        let annotations = AnnotationStore::open(&data_dir)?;
+        match manifest.timestamp_precision {
+            Some(precision) if precision != config.timestamp_precision => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!(
+                        "数据目录的时间戳精度为 {}，与配置的 {} 不一致",
+                        precision, config.timestamp_precision
+                    ),
+                ));
+            }
+            Some(_) => {}
+            None => {
+                manifest.timestamp_precision = Some(config.timestamp_precision);
+                manifest.save(&data_dir)?;
+            }
+        }
+
+        // 从SSTable元数据建立每个系列的最新时间戳和标签缓存，不解压数据
+        let tag_drift = TagDriftDetector::new(config.tag_drift_mode);
+        let last_timestamps = FreshnessMap::new();
+        let locations = SeriesLocationIndex::new();
+        let mut locations_complete = true;
+        let mut legacy_first_seen: BTreeMap<String, u64> = BTreeMap::new();
+        // 旧格式的SSTable块中带有标签，取每个系列最早的块迁移到manifest
+        let mut legacy_tags: BTreeMap<String, (u64, BTreeMap<String, String>)> = BTreeMap::new();
+        let mut stored_usage: BTreeMap<String, QuotaUsage> = BTreeMap::new();
+        for sstable in sstables.iter_mut() {
+            let blocks = match sstable.load_series_list() {
+                Ok(blocks) => blocks,
+                Err(e) => {
+                    tracing::warn!("读取SSTable元数据失败 {:?}: {}", sstable.file_path(), e);
+                    observers.sstable_read_failed(sstable.file_path(), &e);
+                    sstable.record_read_error(&e);
+                    locations_complete = false;
+                    continue;
+                }
+            };
+            sstable.record_blocks(&blocks);
+            locations.add_file(&sstable.file_name(), blocks.iter().map(|block| block.series_key.as_str()));
+            for block in blocks {
+                last_timestamps.observe(&block.series_key, block.max_timestamp);
+                let usage = stored_usage.entry(block.series_key.clone()).or_default();
+                usage.points += block.count as u64;
+                usage.bytes += block.compressed_data.len() as u64;
+                match legacy_tags.get(&block.series_key) {
+                    Some((min_timestamp, _)) if *min_timestamp <= block.min_timestamp => {}
+                    _ => {
+                        legacy_tags.insert(block.series_key.clone(), (block.min_timestamp, block.tags.clone()));
+                    }
+                }
+                legacy_first_seen
+                    .entry(block.series_key)
+                    .and_modify(|ts: &mut u64| *ts = (*ts).min(block.min_timestamp))
+                    .or_insert(block.min_timestamp);
+            }
+        }
+
+        // 首个数据点的时间戳不晚于各文件中最早的点：没有记录的旧系列以及旧版本按写入时的
+        // 时钟记录的值都以最早的数据点为准，已不存在的系列（删除后或刷新前崩溃）从manifest中移除
+        let known = manifest.series_first_seen.len();
+        manifest.series_first_seen.retain(|key, _| legacy_first_seen.contains_key(key));
+        let mut manifest_changed = manifest.series_first_seen.len() != known;
+        for (series_key, earliest) in legacy_first_seen {
+            let first_seen = manifest.series_first_seen.entry(series_key).or_insert(u64::MAX);
+            if *first_seen > earliest {
+                *first_seen = earliest;
+                manifest_changed = true;
+            }
+        }
+        let known = manifest.series_tags.len();
+        manifest.series_tags.retain(|key, _| legacy_tags.contains_key(key));
+        manifest_changed |= manifest.series_tags.len() != known;
+        for (series_key, (_, tags)) in legacy_tags {
+            if let std::collections::btree_map::Entry::Vacant(entry) = manifest.series_tags.entry(series_key) {
+                entry.insert(tags);
+                manifest_changed = true;
+            }
+        }
+        for (series_key, tags) in &manifest.series_tags {
+            tag_drift.seed(series_key, tags);
+        }
+        if let Some(patterns) = &config.warmup_series {
+            if *patterns != manifest.warmup_series {
+                manifest.warmup_series = patterns.clone();
+                manifest_changed = true;
+            }
+        }
+        // 恢复各文件已删除的点数，已不存在的文件（compaction或保留策略删除后）的记录移除
+        let known = manifest.sstable_deleted_points.len();
+        manifest
+            .sstable_deleted_points
+            .retain(|file_name, _| sstables.iter().any(|sstable| sstable.file_name() == *file_name));
+        manifest_changed |= manifest.sstable_deleted_points.len() != known;
+        for sstable in sstables.iter_mut() {
+            if let Some(&deleted_points) = manifest.sstable_deleted_points.get(&sstable.file_name()) {
+                sstable.set_deleted_points(deleted_points);
+            }
+            sstable.set_created_at(manifest.sstable_created_at.get(&sstable.file_name()).copied());
+        }
+        // 没有记录的文件按修改时间补上，不存在的文件的记录移除
+        let created_at = Self::sstable_created_at(&sstables);
+        if manifest.sstable_created_at != created_at {
+            manifest.sstable_created_at = created_at;
+            manifest_changed = true;
+        }
+        if manifest_changed {
+            manifest.save(&data_dir)?;
+        }
+        // 有文件读取失败时索引保持不完整，首次按系列访问时再重建
+        if locations_complete {
+            locations.mark_warm();
+        }
+        let quota = QuotaTracker::default();
+        quota.reset(
+            Some(manifest.quotas.clone()),
+            stored_usage.iter().map(|(series_key, usage)| (series_key.as_str(), *usage, 0)),
+        );
+
+        let background_tasks = Arc::new(BackgroundTasks::new(Arc::clone(&clock)));
+        let db = Self {
+            tag_drift: Arc::new(tag_drift),
+            last_timestamps: Arc::new(last_timestamps),
+            locations: Arc::new(locations),
+            series_meta_dirty: Arc::new(AtomicBool::new(false)),
+            sstable_queries: Arc::new(SingleFlight::new()),
+            sstable_scans: Arc::new(AtomicU64::new(0)),
+            ingest_rate: Arc::new(IngestRate::new()),
+            compaction_history: Arc::new(Mutex::new(VecDeque::new())),
+            deleted_compaction_running: Arc::new(AtomicBool::new(false)),
+            observers: Arc::new(observers),
+            last_flush: Arc::new(Mutex::new(None)),
+            warmup: Arc::new(WarmupProgress::new()),
+            next_sequence: Arc::new(AtomicU64::new(next_sequence)),
+            blocking: Arc::new(BlockingPool::new(config.blocking_threads, config.inline_query_points)),
+            mappings,
+            query_memory: Arc::new(QueryMemory::new(config.max_query_memory_bytes, config.query_memory_budget_bytes)),
+            decode_scratch: Arc::new(DecodeScratchCounters::new()),
+            query_progress: Arc::new(QueryProgress::new()),
+            annotations: Arc::new(annotations),
+            quota: Arc::new(quota),
+            operations: Arc::new(OperationRegistry::new()),
+            maintenance: Arc::new(tokio::sync::Semaphore::new(1)),
+            changes: Arc::new(ChangeFeed::new(config.change_feed_capacity)),
+            overlap: Arc::new(OverlapCache::new()),
+            write_sequence: Arc::new(AtomicU64::new(0)),
+            durable_sequence: Arc::new(tokio::sync::watch::Sender::new(0)),
+            flush_lock: Arc::new(tokio::sync::Mutex::new(())),
+            replace_gate: Arc::new(RwLock::new(())),
+            replace_generation: Arc::new(AtomicU64::new(0)),
+            manifest: Arc::new(RwLock::new(manifest)),
+            memtable: Arc::new(RwLock::new(Memtable::new(config.memtable_threshold))),
+            flushing_memtable: Arc::new(RwLock::new(None)),
+            sstables: Arc::new(Mutex::new(sstables)),
+            disk: Arc::new(disk),
+            data_dir,
+            data_dirs: Arc::new(data_dirs),
+            config: Arc::new(config),
+            clock,
+            shutdown: CancellationToken::new(),
+            background_tasks,
+            dir_lock: Arc::new(Mutex::new(lock_files)),
+        };
+
+        // 低频写入或写入停止时也需要按时间刷新，因此需要后台检查
+        let flush_limits = [db.config.max_memtable_age, db.config.idle_flush_secs.map(Duration::from_secs)];
+        if let Some(shortest) = flush_limits.into_iter().flatten().min() {
+            if tokio::runtime::Handle::try_current().is_ok() {
+                db.spawn_timed_flush_task(shortest);
+            } else {
+                tracing::warn!("未检测到tokio运行时，内存表按时间刷新仅在写入时检查，空闲刷新不会执行");
+            }
+        }
+
+        if db.disk.enabled() && tokio::runtime::Handle::try_current().is_ok() {
+            db.spawn_disk_check_task(db.config.disk_check_interval);
+        }
+
+        Ok(db)
+    }
+
+    // 在目录之间移动SSTable时，新文件就位后、删除旧文件前中断会在两个目录中留下同名的文件。
+    // 两者内容相同，保留先扫描到的（主目录在前）；内容不同时无法判断哪个有效，拒绝启动
+    fn drop_relocated_duplicates(sstables: Vec<SSTable>) -> Result<Vec<SSTable>> {
+        let mut kept: Vec<SSTable> = Vec::with_capacity(sstables.len());
+        let mut by_name: BTreeMap<String, usize> = BTreeMap::new();
+        for sstable in sstables {
+            let Some(&index) = by_name.get(&sstable.file_name()) else {
+                by_name.insert(sstable.file_name(), kept.len());
+                kept.push(sstable);
+                continue;
+            };
+            let original = kept[index].file_path();
+            if std::fs::read(original)? != std::fs::read(sstable.file_path())? {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("SSTable {:?} 和 {:?} 同名但内容不同", original, sstable.file_path()),
+                ));
+            }
+            tracing::warn!("删除移动中断后残留的重复SSTable {:?}", sstable.file_path());
+            sstable.delete_file()?;
+        }
+        Ok(kept)
+    }
+
+    fn is_leftover_temp(path: &Path) -> bool {
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            return false;
+        };
+        if path.is_dir() {
+            name.starts_with(EXPORT_DIR_PREFIX) || name.starts_with(IMPORT_DIR_PREFIX)
+        } else {
+            name.ends_with(".data.tmp") || name.strip_suffix(STAGED_SUFFIX).is_some_and(|name| name.ends_with(".data"))
+        }
+    }
+
+    // 定期刷新磁盘可用空间
+    fn spawn_disk_check_task(&self, period: Duration) {
+        let disk = Arc::clone(&self.disk);
+        let mut last_level = disk.level();
+        self.background_tasks.spawn_periodic("disk_check", period, self.shutdown.clone(), move || {
+            let result = disk.refresh().map(|_| {
+                let level = disk.level();
+                if level != last_level {
+                    tracing::warn!("磁盘水位变化: {:?} -> {:?}, 剩余 {:?} 字节", last_level, level, disk.free_bytes());
+                    last_level = level;
+                }
+            });
+            std::future::ready(result)
+        });
+    }
+
+    pub fn disk_level(&self) -> DiskLevel {
+        self.disk.level()
+    }
+
+    pub fn disk_free_bytes(&self) -> Option<u64> {
+        self.disk.free_bytes()
+    }
+
+    fn check_disk_for_write(&self) -> Result<()> {
+        if self.disk.level() >= DiskLevel::Low {
+            return Err(DbError::DiskFull {
+                free_bytes: self.disk.free_bytes().unwrap_or(0),
+                watermark_bytes: self
+                    .disk
+                    .low_watermark()
+                    .or(self.disk.critical_watermark())
+                    .unwrap_or(0),
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    pub fn config(&self) -> &DbConfig {
+        &self.config
+    }
+
+    pub fn timestamp_precision(&self) -> TimePrecision {
+        self.config.timestamp_precision
+    }
+
+    // 当前时间，使用内部时间戳精度
+    pub fn now_timestamp(&self) -> u64 {
+        self.config.timestamp_precision.timestamp_of(self.clock.now())
+    }
+
+    // 按引擎时钟的当前时间把相对时间范围换算为时间戳
+    pub fn resolve_time_range(&self, range: &TimeRange) -> ResolvedRange {
+        range.resolve(self.now_timestamp(), self.timestamp_precision())
+    }
+
+    // 两端都没有指定的范围按 default_query_range_secs 换成最近一段时间，未配置时原样返回
+    pub fn default_time_range(&self, range: TimeRange) -> TimeRange {
+        match self.config.default_query_range_secs {
+            Some(secs) if range.start.is_none() && range.end.is_none() => TimeRange::last(Duration::from_secs(secs)),
+            _ => range,
+        }
+    }
+
+    // 按内存表驻留时间和空闲时间刷新的后台任务，shortest 为两者中较短的上限
+    fn spawn_timed_flush_task(&self, shortest: Duration) {
+        let db = self.clone();
+        let check_period = (shortest / 4).max(Duration::from_secs(1));
+        self.background_tasks.spawn_periodic("timed_flush", check_period, self.shutdown.clone(), move || {
+            let db = db.clone();
+            async move {
+                if db.memtable_expired() {
+                    db.flush_memtable().await
+                } else if db.memtable_idle() {
+                    tracing::debug!("内存表 {:?} 内没有新的写入，空闲刷新", db.memtable_idle_time().unwrap_or_default());
+                    db.flush_memtable().await
+                } else {
+                    Ok(())
+                }
+            }
+        });
+    }
+
+    // 内存表中最早数据的驻留时长
+    pub fn oldest_memtable_age(&self) -> Option<Duration> {
+        let oldest = self.memtable.read().oldest_insert_time()?;
+        Some(self.clock.now().duration_since(oldest).unwrap_or_default())
+    }
+
+    // 距内存表最近一次写入的时间，内存表为空时为None
+    pub fn memtable_idle_time(&self) -> Option<Duration> {
+        let newest = self.memtable.read().newest_insert_time()?;
+        Some(self.clock.now().duration_since(newest).unwrap_or_default())
+    }
+
+    // 距上次刷新不足 min_flush_interval 且未达到硬上限时，推迟按阈值的刷新
+    fn flush_suppressed(&self, points: usize, now: SystemTime) -> bool {
+        let Some(min_interval) = self.config.min_flush_interval else {
+            return false;
+        };
+        if points >= self.config.effective_memtable_hard_limit() {
+            return false;
+        }
+        self.last_flush
+            .lock()
+            .is_some_and(|last| now.duration_since(last).unwrap_or_default() < min_interval)
+    }
+
+    fn memtable_expired(&self) -> bool {
+        match (self.config.max_memtable_age, self.oldest_memtable_age()) {
+            (Some(max_age), Some(age)) => age >= max_age,
+            _ => false,
+        }
+    }
+
+    fn memtable_idle(&self) -> bool {
+        match (self.config.idle_flush_secs, self.memtable_idle_time()) {
+            (Some(secs), Some(idle)) => idle >= Duration::from_secs(secs),
+            _ => false,
+        }
+    }
+
+    // 启动定期compaction后台任务，任务会在close()时停止
+    pub fn spawn_compaction_task(&self, period: Duration) {
+        let db = self.clone();
+        self.spawn_periodic_task("compaction", period, move || {
+            let db = db.clone();
+            async move {
+                // 优先合并时间范围重叠最多的一组文件，没有重叠或这组文件不能合并时合并全部文件
+                match db.overlap_compaction_window() {
+                    Some(window) => match db.compact_with(&window).await {
+                        Ok(report) if report.skipped_reason.is_some() => db.compact().await,
+                        result => result,
+                    },
+                    None => db.compact().await,
+                }?;
+                tracing::info!("定期compaction执行完成");
+                Ok(())
+            }
+        });
+    }
+
+    // 在任务监督下每隔 period 执行一次 task，第一次立即执行，close()时停止。
+    // 返回错误或panic时记录在任务状态中，退避后重新执行；嵌入方也可以用它运行自己的维护任务
+    pub fn spawn_periodic_task<F, Fut>(&self, name: &str, period: Duration, task: F)
+    where
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: std::future::Future<Output = Result<()>> + Send,
+    {
+        self.background_tasks.spawn_periodic(name, period, self.shutdown.clone(), task);
+    }
+
+    // 每个定期后台任务的执行次数、最近的错误和重启次数
+    pub fn background_task_status(&self) -> BTreeMap<String, TaskStatus> {
+        self.background_tasks.statuses()
+    }
+
+    // 仍在运行的后台任务数量
+    pub fn background_task_count(&self) -> usize {
+        self.background_tasks.running()
+    }
+
+    // 关闭数据库：停止后台任务并等待其退出，刷新内存表，释放数据目录锁。
+    // 其他clone出的句柄在close之后不应再用于写入。
+    pub async fn close(self) -> Result<()> {
+        self.shutdown.cancel();
+        self.changes.close();
+
+        let handles = self.background_tasks.take_handles();
+        for handle in handles {
+            if let Err(e) = handle.await {
+                tracing::warn!("后台任务退出异常: {}", e);
+            }
+        }
+
+        self.flush_memtable().await?;
+        self.persist_series_meta()?;
+
+        for lock_file in self.dir_lock.lock().drain(..) {
+            lock_file.unlock()?;
+        }
+
+        tracing::info!("数据库已关闭: {:?}", self.data_dir);
+        Ok(())
+    }
+
+    // 订阅写入、更新和删除的变更事件，只收到订阅之后的变更。通道容量为 change_feed_capacity，
+    // 接收跟不上时丢失最早的事件并在下一次接收时得到 Lagged；关闭数据库后收完缓冲的事件得到 Closed。
+    // 保留策略和容量上限的淘汰按删除产生事件，导入归档和compaction不产生事件
+    pub fn subscribe(&self) -> tokio::sync::broadcast::Receiver<ChangeEvent> {
+        self.changes.subscribe()
+    }
+
+    // 结束所有订阅，HTTP服务在等待连接关闭之前调用，否则变更流会一直保持连接。之后的订阅立即结束
+    pub fn close_change_feed(&self) {
+        self.changes.close();
+    }
+
+    pub async fn insert(&self, series_key: String, datapoint: DataPoint) -> Result<()> {
+        if self.shutdown.is_cancelled() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::BrokenPipe,
+                "数据库已关闭",
+            ));
+        }
+        self.check_disk_for_write()?;
+        // 标签漂移由检测器返回，同时记录漂移计数
+        self.validate_write(&series_key, Some(&datapoint.tags), [(datapoint.timestamp, datapoint.value)])
+            .map_err(|e| e.into_db_error(&series_key))?;
+        self.tag_drift.check(&series_key, &datapoint.tags)?;
+
+        // 检查是否需要flush，在锁外进行
+        let now = self.clock.now();
+        // 记录写入时间时时钟不可用则拒绝写入，不写入为0的写入时间
+        let ingest_timestamp = if self.config.record_ingest_time {
+            Some(self.config.timestamp_precision.from_duration(self.clock.since_epoch()?))
+        } else {
+            None
+        };
+        // 占用配额是写入内存表之前最后一个可能失败的步骤，失败的写入不会留下占用
+        self.quota.reserve(&series_key, 1)?;
+        self.record_series_start(&series_key, &datapoint.tags, datapoint.timestamp);
+        self.last_timestamps.observe(&series_key, datapoint.timestamp);
+        let event_key = self.changes.has_subscribers().then(|| series_key.clone());
+        let (is_full, points) = {
+            let mut memtable = self.memtable.write();
+            if let Some(ingest_timestamp) = ingest_timestamp {
+                memtable.record_ingest_time(&series_key, datapoint.timestamp, ingest_timestamp);
+            }
+            memtable.insert(series_key, datapoint.timestamp, datapoint.value, &datapoint.tags);
+            memtable.record_insert_time(now);
+            self.ingest_rate.record(self.clock.now_secs(), 1);
+            // 在内存表锁内分配序号，刷新时取走的数据正好是不大于当时序号的写入
+            self.write_sequence.fetch_add(1, Ordering::Relaxed);
+            (memtable.is_full(), memtable.size())
+        };
+        if let Some(series_key) = event_key {
+            let DataPoint { timestamp, value, tags } = datapoint;
+            self.changes.publish(|| ChangeEvent::Insert { series_key, timestamp, value, tags });
+        }
+        let flush_by_threshold = is_full && !self.flush_suppressed(points, now);
+        if flush_by_threshold {
+            let threshold = self.config.memtable_threshold;
+            self.observers.notify("memtable_threshold_crossed", |observer| {
+                observer.on_memtable_threshold_crossed(points, threshold)
+            });
+        }
+        let should_flush = flush_by_threshold || self.memtable_expired();
+
+        if should_flush {
+            self.flush_memtable().await?;
+        }
+
+        Ok(()) // 修复：添加 () 参数
+    }
+
+    // insert 对数据点的全部校验规则，不写入也不改变任何状态，供调用方在写入前预先检查：
+    // validate_write 的规则加上reject模式下的标签漂移。磁盘空间和配额与数据点本身无关，不在这里检查
+    pub fn validate_datapoint(&self, series_key: &str, datapoint: &DataPoint) -> std::result::Result<(), ValidationError> {
+        self.validate_write(series_key, Some(&datapoint.tags), [(datapoint.timestamp, datapoint.value)])?;
+        if let Some(expected) = self.tag_drift.rejected(series_key, &datapoint.tags) {
+            return Err(ValidationError::TagDrift { expected, actual: datapoint.tags.clone() });
+        }
+        Ok(())
+    }
+
+    // 所有写入路径共用的校验：insert、更新、预压缩块、替换系列和导入在写入前调用。
+    // 时间戳不超过当前时间加 max_future_skew_secs；开启 strict_validation 时还检查系列键和标签的规则、值为有限的数。
+    // tags 为 None 时只修改已有点的值，不检查标签；两项都不需要时不遍历 points，调用方可以传入惰性解压的迭代器
+    pub(crate) fn validate_write<I>(
+        &self,
+        series_key: &str,
+        tags: Option<&BTreeMap<String, String>>,
+        points: I,
+    ) -> std::result::Result<(), ValidationError>
+    where
+        I: IntoIterator<Item = (u64, f64)>,
+    {
+        let strict = self.config.strict_validation;
+        if strict {
+            validate_series_key(series_key)?;
+            if let Some(tags) = tags {
+                validate_tags(tags)?;
+            }
+        }
+        // 时钟早于纪元时无法判断偏差，该错误在需要写入时间时才返回
+        let max_timestamp = match (self.config.max_future_skew_secs, self.clock.since_epoch()) {
+            (Some(skew), Ok(now)) => Some(self.config.timestamp_precision.from_duration(now.saturating_add(Duration::from_secs(skew)))),
+            _ => None,
+        };
+        if !strict && max_timestamp.is_none() {
+            return Ok(());
+        }
+        for (timestamp, value) in points {
+            if strict && !value.is_finite() {
+                return Err(ValidationError::NonFiniteValue { value });
+            }
+            if let Some(max_timestamp) = max_timestamp.filter(|&max_timestamp| timestamp > max_timestamp) {
+                return Err(ValidationError::TimestampTooFarAhead { timestamp, max_timestamp });
+            }
+        }
+        Ok(())
+    }
+
+    // 写入客户端预先压缩的块：完整解压校验后原样写入一个新的SSTable，不经过内存表也不重新压缩
+    pub async fn ingest_block(
+        &self,
+        series_key: String,
+        block: EncodedBlock,
+        tags: BTreeMap<String, String>,
+    ) -> Result<BlockIngestReport> {
+        if self.shutdown.is_cancelled() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::BrokenPipe,
+                "数据库已关闭",
+            ));
+        }
+        self.check_disk_for_write()?;
+        if block.count > MAX_POINTS_PER_BLOCK {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("块包含 {} 个数据点，超过上限 {}", block.count, MAX_POINTS_PER_BLOCK),
+            ));
+        }
+        self.tag_drift.check(&series_key, &tags)?;
+
+        // 解压校验在阻塞线程池中执行
+        let (block, points) = self
+            .blocking
+            .run_sized(block.count, move || block.validate().map(|points| (block, points)))
+            .await?
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        self.validate_write(&series_key, Some(&tags), points.iter().copied())
+            .map_err(|e| e.into_db_error(&series_key))?;
+        // 块中不带标签，系列标签保存失败时不写入数据
+        self.record_series_start(&series_key, &tags, block.min_timestamp);
+        self.persist_series_meta()?;
+        self.quota.reserve(&series_key, block.count as u64)?;
+        self.ingest_rate.record(self.clock.now_secs(), block.count as u64);
+        self.last_timestamps.observe(&series_key, block.max_timestamp);
+
+        let report = BlockIngestReport {
+            series_key: series_key.clone(),
+            points: block.count,
+            min_timestamp: block.min_timestamp,
+            max_timestamp: block.max_timestamp,
+            sstable: String::new(),
+        };
+        let series_data = SeriesData {
+            series_key,
+            compressed_data: block.data,
+            tags: BTreeMap::new(),
+            min_timestamp: block.min_timestamp,
+            max_timestamp: block.max_timestamp,
+            count: block.count,
+            ingest_data: Vec::new(),
+        };
+        let usage = QuotaUsage {
+            points: series_data.count as u64,
+            bytes: series_data.compressed_data.len() as u64,
+        };
+        let path = self.next_sstable_path("sstable");
+        let compression = self.config.flush_compression;
+        let encoding = self.config.sstable_encoding;
+        let mappings = Arc::clone(&self.mappings);
+        let written = self
+            .blocking
+            .run_sized(series_data.count, move || {
+                let mut sstable = SSTable::new(path)?
+                    .with_compression(compression)
+                    .with_encoding(encoding)
+                    .with_mapping_cache(mappings);
+                sstable.write_data(std::slice::from_ref(&series_data)).map(|_| sstable)
+            })
+            .await
+            .and_then(|result| result);
+        let sstable = match written {
+            Ok(sstable) => sstable,
+            Err(e) => {
+                // 写入失败时释放占用的配额
+                self.quota.release(&report.series_key, usage.points);
+                return Err(e);
+            }
+        };
+
+        let report = BlockIngestReport { sstable: sstable.file_name(), ..report };
+        {
+            let mut sstables = self.sstables.lock();
+            self.locations.add_file(&report.sstable, std::iter::once(report.series_key.as_str()));
+            Self::add_sstable(&mut sstables, sstable);
+            self.overlap.invalidate();
+            self.record_sstable_meta(&sstables);
+        }
+        self.quota.record_flushed(&report.series_key, usage.points, usage);
+        tracing::info!("已写入预压缩块: {} ({} 个数据点)", report.series_key, report.points);
+        self.changes.publish_all(|| {
+            points
+                .into_iter()
+                .map(|(timestamp, value)| ChangeEvent::Insert {
+                    series_key: report.series_key.clone(),
+                    timestamp,
+                    value,
+                    tags: tags.clone(),
+                })
+                .collect()
+        });
+
+        self.enforce_quotas().await?;
+        self.enforce_size_limit().await?;
+        Ok(report)
+    }
+
+    pub async fn update(&self, series_key: &str, timestamp: u64, new_value: f64) -> Result<bool> {
+        self.validate_write(series_key, None, [(timestamp, new_value)]).map_err(|e| e.into_db_error(series_key))?;
+        // 正在刷新的点只能在新SSTable加入后更新，等待刷新完成
+        let _flush_guard = self.flush_lock.lock().await;
+        let updated = self.update_point(series_key, timestamp, new_value)?;
+        if updated {
+            self.changes.publish(|| ChangeEvent::Update { series_key: series_key.to_string(), timestamp, value: new_value });
+        }
+        Ok(updated)
+    }
+
+    fn update_point(&self, series_key: &str, timestamp: u64, new_value: f64) -> Result<bool> {
+        // 首先尝试在内存表中更新
+        let updated_in_memtable = {
+            let mut memtable = self.memtable.write();
+            memtable.update(series_key, timestamp, new_value)
+        };
+
+        if updated_in_memtable {
+            return Ok(true);
+        }
+
+        // 在SSTable中查找并更新，避免跨await持有锁
+        let mut sstables = self.sstables.lock();
+        for sstable in self.locations.locate(&mut sstables, series_key) {
+            if sstable.update_datapoint(series_key, timestamp, new_value)? {
+                self.record_sstable_meta(&sstables);
+                return Ok(true);
+            }
+        }
+        drop(sstables);
+
+        Ok(false)
+    }
+
+    // 批量更新已有的数据点。内存表中的点直接修改，其余按所在的SSTable分组，
+    // 每个受影响的文件只重写一次；同一个点出现多次时以最后一次为准
+    pub async fn update_batch(&self, updates: Vec<(String, u64, f64)>) -> Result<BatchUpdateReport> {
+        // 任一更新不满足写入规则时整批拒绝，不修改任何点
+        for (series_key, timestamp, value) in &updates {
+            self.validate_write(series_key, None, [(*timestamp, *value)]).map_err(|e| e.into_db_error(series_key))?;
+        }
+        let mut report = BatchUpdateReport {
+            total: updates.len(),
+            ..BatchUpdateReport::default()
+        };
+        let mut pending: BTreeMap<String, BTreeMap<u64, f64>> = BTreeMap::new();
+        for (series_key, timestamp, value) in updates {
+            pending.entry(series_key).or_default().insert(timestamp, value);
+        }
+        // 有订阅方时保留全部请求，结束后不在 pending 中的即为已更新的点
+        let requested = self.changes.has_subscribers().then(|| pending.clone());
+        let _flush_guard = self.flush_lock.lock().await;
+
+        {
+            let mut memtable = self.memtable.write();
+            pending.retain(|series_key, points| {
+                points.retain(|timestamp, value| {
+                    let updated = memtable.update(series_key, *timestamp, *value);
+                    report.updated += updated as usize;
+                    !updated
+                });
+                !points.is_empty()
+            });
+        }
+
+        if !pending.is_empty() {
+            let mut sstables = self.sstables.lock();
+            let keys: Vec<String> = pending.keys().cloned().collect();
+            for sstable in self.locations.locate_any(&mut sstables, keys.iter().map(String::as_str)) {
+                if pending.is_empty() {
+                    break;
+                }
+                let updated = sstable.update_datapoints(&mut pending)?;
+                if updated > 0 {
+                    report.updated += updated;
+                    report.files_rewritten += 1;
+                }
+            }
+            if report.files_rewritten > 0 {
+                self.record_sstable_meta(&sstables);
+            }
+        }
+
+        report.not_found = pending.values().map(BTreeMap::len).sum();
+        if let Some(requested) = requested {
+            self.changes.publish_all(|| {
+                requested
+                    .into_iter()
+                    .flat_map(|(series_key, points)| points.into_iter().map(move |point| (series_key.clone(), point)))
+                    .filter(|(series_key, (timestamp, _))| !pending.get(series_key).is_some_and(|left| left.contains_key(timestamp)))
+                    .map(|(series_key, (timestamp, value))| ChangeEvent::Update { series_key, timestamp, value })
+                    .collect()
+            });
+        }
+        Ok(report)
+    }
+
+    // 删除数据点（timestamp 为None时删除整个系列）。受删除保护的系列需要 force 为true
+    pub async fn delete(&self, series_key: &str, timestamp: Option<u64>, force: bool) -> Result<bool> {
+        self.check_deletable(series_key, force)?;
+        // 正在刷新的点只能在新SSTable加入后删除，等待刷新完成
+        let _flush_guard = self.flush_lock.lock().await;
+        // 首先尝试在内存表中删除
+        let deleted_from_memtable = {
+            let mut memtable = self.memtable.write();
+            memtable.delete(series_key, timestamp)
+        };
+
+        // 在SSTable中删除
+        let mut deleted_from_sstable = false;
+        {
+            let mut sstables = self.sstables.lock();
+            
+            for sstable in self.locations.locate(&mut sstables, series_key) {
+                if sstable.delete_datapoint(series_key, timestamp)? {
+                    deleted_from_sstable = true;
+                }
+            }
+            if timestamp.is_none() {
+                self.locations.remove_series(series_key);
+            }
+            if deleted_from_sstable {
+                self.overlap.invalidate();
+            }
+
+            self.remove_empty_sstables(&mut sstables);
+            if deleted_from_sstable {
+                self.after_points_deleted(&sstables);
+            }
+        }
+        if deleted_from_memtable || deleted_from_sstable {
+            self.quota.mark_stale();
+        }
+
+        match timestamp {
+            None => {
+                self.tag_drift.forget(series_key);
+                self.last_timestamps.set(series_key, None);
+                self.forget_series_meta(series_key);
+            }
+            Some(ts) if self.last_timestamps.get(series_key) == Some(ts) => {
+                self.refresh_last_timestamp(series_key);
+            }
+            Some(_) => {}
+        }
+
+        let deleted = deleted_from_memtable || deleted_from_sstable;
+        if deleted {
+            self.changes.publish(|| ChangeEvent::Delete { series_key: series_key.to_string(), start_time: timestamp, end_time: timestamp });
+        }
+        Ok(deleted)
+    }
+
+    // 用 points 原子地替换系列的全部数据：删除内存表和所有SSTable中已有的点，新数据写入一个新的SSTable。
+    // 替换期间的查询等到替换完成后再读取，不会看到只替换了一部分的数据。重复的时间戳保留先出现的值，
+    // 所有点的标签必须相同，系列标签替换为新的标签，首次写入时间不变；points 为空时等同删除系列
+    pub async fn replace_series(&self, series_key: &str, points: Vec<DataPoint>) -> Result<()> {
+        if self.shutdown.is_cancelled() {
+            return Err(std::io::Error::new(std::io::ErrorKind::BrokenPipe, "数据库已关闭"));
+        }
+        self.check_deletable(series_key, false)?;
+        if points.is_empty() {
+            self.delete(series_key, None, false).await?;
+            return Ok(());
+        }
+        self.check_disk_for_write()?;
+        let tags = points[0].tags.clone();
+        if points.iter().any(|dp| dp.tags != tags) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("替换系列 {} 时所有数据点的标签必须相同", series_key),
+            ));
+        }
+
+        let mut values: Vec<(u64, f64)> = points.iter().map(|dp| (dp.timestamp, dp.value)).collect();
+        self.validate_write(series_key, Some(&tags), values.iter().copied()).map_err(|e| e.into_db_error(series_key))?;
+        values.sort_by_key(|(ts, _)| *ts);
+        values.dedup_by_key(|(ts, _)| *ts);
+        let last_timestamp = values.last().map(|(ts, _)| *ts);
+        let count = values.len();
+
+        // 维护许可和刷新锁保证替换期间compaction、校验、刷新、删除和更新都不会改写涉及的文件，
+        // 刷新也不会把内存表中的旧数据写成新的SSTable
+        let _permit = Arc::clone(&self.maintenance).acquire_owned().await.map_err(std::io::Error::other)?;
+        let _flush_guard = self.flush_lock.lock().await;
+        let sstable = SSTable::new(self.next_sstable_path("sstable"))?
+            .with_compression(self.config.flush_compression)
+            .with_encoding(self.config.sstable_encoding)
+            .with_mapping_cache(Arc::clone(&self.mappings));
+        let existing: Vec<PathBuf> = {
+            let mut sstables = self.sstables.lock();
+            self.locations.locate(&mut sstables, series_key).into_iter().map(|sstable| sstable.file_path().to_path_buf()).collect()
+        };
+
+        // 新数据和去掉该系列后的旧文件先写成暂存文件，不持有任何锁，查询照常读取旧数据
+        let blocks = Self::encode_series_blocks(series_key.to_string(), &values, None);
+        let staged_key = series_key.to_string();
+        let plan = self
+            .blocking
+            .run_sized(count, move || ReplacePlan::stage(staged_key, sstable, blocks, existing))
+            .await??;
+
+        // 提交记录保存之后替换才生效，之后中断时启动过程据此完成替换
+        self.manifest.write().pending_replace = Some(plan.pending());
+        if let Err(e) = self.save_manifest().await {
+            self.abandon_replace(plan).await;
+            return Err(e);
+        }
+
+        // 在替换写锁内确认旧文件没有变化，再把暂存文件改名生效并切换内存中的SSTable列表
+        let swapped = {
+            let _gate = self.replace_gate.write();
+            let mut memtable = self.memtable.write();
+            let mut sstables = self.sstables.lock();
+            match plan.modified_file(&sstables) {
+                Some(file_name) => Err((plan, file_name)),
+                None => {
+                    self.replace_generation.fetch_add(1, Ordering::AcqRel);
+                    memtable.delete(series_key, None);
+                    let result = self.swap_series_data(&mut sstables, plan, &tags);
+                    self.replace_generation.fetch_add(1, Ordering::AcqRel);
+                    Ok(result)
+                }
+            }
+        };
+        let removed = match swapped {
+            Ok(result) => result?,
+            Err((plan, file_name)) => {
+                self.abandon_replace(plan).await;
+                return Err(DbError::SSTableModified { file_name }.into());
+            }
+        };
+
+        // 改名落盘、删除只含该系列的旧文件之后清除提交记录，与系列元数据一起保存
+        let dirs = self.data_dirs.all().to_vec();
+        self.blocking
+            .run(move || -> Result<()> {
+                for sstable in removed {
+                    sstable.delete_file()?;
+                }
+                dirs.iter().try_for_each(|dir| sync_dir(dir))
+            })
+            .await??;
+        self.manifest.write().pending_replace = None;
+        self.save_manifest().await?;
+
+        self.last_timestamps.set(series_key, last_timestamp);
+        self.tag_drift.forget(series_key);
+        self.tag_drift.check(series_key, &tags)?;
+        self.ingest_rate.record(self.clock.now_secs(), count as u64);
+        self.quota.mark_stale();
+        self.changes.publish_all(|| {
+            let delete = ChangeEvent::Delete { series_key: series_key.to_string(), start_time: None, end_time: None };
+            let inserts = values.iter().map(|&(timestamp, value)| ChangeEvent::Insert {
+                series_key: series_key.to_string(),
+                timestamp,
+                value,
+                tags: tags.clone(),
+            });
+            std::iter::once(delete).chain(inserts).collect()
+        });
+        tracing::info!("系列 {} 已替换为 {} 个数据点", series_key, count);
+        Ok(())
+    }
+
+    // 在替换写锁内把暂存文件改名为正式文件并切换SSTable列表，返回只含该系列、需要删除的旧文件。
+    // 改名失败时替换已经提交，重启后由启动过程完成
+    fn swap_series_data(&self, sstables: &mut Vec<SSTable>, plan: ReplacePlan, tags: &BTreeMap<String, String>) -> Result<Vec<SSTable>> {
+        let series_key = plan.series_key.as_str();
+        let mut removed = Vec::new();
+        let rewritten = !plan.rewrites.is_empty();
+        for rewrite in &plan.rewrites {
+            let Some(index) = sstables.iter().position(|sstable| sstable.file_name() == rewrite.file_name()) else {
+                continue;
+            };
+            if rewrite.retained.is_empty() {
+                let sstable = sstables.remove(index);
+                self.locations.remove_file(&sstable.file_name());
+                removed.push(sstable);
+            } else if let Err(e) = sstables[index].install_staged(&rewrite.retained, rewrite.removed_points) {
+                tracing::error!("替换系列 {} 时改名 {:?} 失败，重启后完成替换: {}", series_key, rewrite.path, e);
+                self.overlap.invalidate();
+                return Err(e);
+            }
+        }
+        self.locations.remove_series(series_key);
+        let mut sstable = plan.sstable;
+        sstable.install_staged(&plan.blocks, 0)?;
+        self.locations.add_file(&sstable.file_name(), [series_key]);
+        Self::add_sstable(sstables, sstable);
+        self.overlap.invalidate();
+        self.record_sstable_meta(sstables);
+        if rewritten {
+            self.after_points_deleted(sstables);
+        }
+
+        let mut manifest = self.manifest.write();
+        // 替换后的系列只有新的数据，首个数据点随之改变
+        match plan.blocks.iter().map(|block| block.min_timestamp).min() {
+            Some(earliest) => manifest.series_first_seen.insert(series_key.to_string(), earliest),
+            None => manifest.series_first_seen.remove(series_key),
+        };
+        manifest.series_tags.insert(series_key.to_string(), tags.clone());
+        manifest.series_resolution.remove(series_key);
+        self.series_meta_dirty.store(true, Ordering::Relaxed);
+        Ok(removed)
+    }
+
+    // 放弃没有生效的替换：删除暂存文件并清除提交记录
+    async fn abandon_replace(&self, plan: ReplacePlan) {
+        if let Err(e) = self.blocking.run(move || plan.discard()).await {
+            tracing::warn!("删除替换的暂存文件失败: {}", e);
+        }
+        self.manifest.write().pending_replace = None;
+        if let Err(e) = self.save_manifest().await {
+            tracing::warn!("清除替换的提交记录失败: {}", e);
+        }
+    }
+
+    // 在阻塞线程池中保存manifest，其中的系列元数据随之落盘
+    async fn save_manifest(&self) -> Result<()> {
+        let manifest = Arc::clone(&self.manifest);
+        let data_dir = self.data_dir.clone();
+        let dirty = self.series_meta_dirty.swap(false, Ordering::Relaxed);
+        let result = self.blocking.run(move || manifest.read().save(&data_dir)).await.and_then(|saved| saved);
+        if result.is_err() && dirty {
+            self.series_meta_dirty.store(true, Ordering::Relaxed);
+        }
+        result
+    }
+
+    // 上次运行在系列替换提交之后中断时完成替换：暂存文件改名为正式文件，删除被替换的旧文件。
+    // 新系列的文件（暂存的或已改名的）都不存在说明替换已被放弃，只清除记录，残留的暂存文件随后清理
+    fn finish_pending_replace(data_dir: &Path, data_dirs: &DataDirs) -> Result<()> {
+        let mut manifest = Manifest::load(data_dir)?;
+        let Some(pending) = manifest.pending_replace.take() else {
+            return Ok(());
+        };
+        let exists = |file_name: &str| {
+            data_dirs.all().iter().any(|dir| dir.join(file_name).exists() || staged_path(&dir.join(file_name)).exists())
+        };
+        if pending.staged.first().is_some_and(|file_name| exists(file_name)) {
+            for dir in data_dirs.all() {
+                for file_name in &pending.staged {
+                    let target = dir.join(file_name);
+                    let staged = staged_path(&target);
+                    if staged.exists() {
+                        std::fs::rename(&staged, &target)?;
+                    }
+                }
+                for file_name in &pending.removed {
+                    let path = dir.join(file_name);
+                    if path.exists() {
+                        std::fs::remove_file(&path)?;
+                    }
+                }
+                sync_dir(dir)?;
+            }
+            tracing::warn!("完成上次中断的系列替换: {}", pending.series_key);
+        } else {
+            tracing::warn!("放弃上次没有生效的系列替换: {}", pending.series_key);
+        }
+        manifest.save(data_dir)
+    }
+
+    pub fn protected_series(&self) -> Vec<String> {
+        self.manifest.read().protected_series.clone()
+    }
+
+    // 更新删除保护列表并持久化到manifest，立即生效
+    pub fn set_protected_series(&self, patterns: Vec<String>) -> Result<()> {
+        let mut manifest = self.manifest.write();
+        let previous = std::mem::replace(&mut manifest.protected_series, patterns);
+        if let Err(e) = manifest.save(&self.data_dir) {
+            manifest.protected_series = previous;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    // 保护列表的匹配规则与预热列表相同
+    pub fn is_protected(&self, series_key: &str) -> bool {
+        self.manifest
+            .read()
+            .protected_series
+            .iter()
+            .any(|pattern| warmup_matches(pattern, series_key))
+    }
+
+    fn check_deletable(&self, series_key: &str, force: bool) -> Result<()> {
+        if !force && self.is_protected(series_key) {
+            return Err(DbError::SeriesProtected { series_key: series_key.to_string() }.into());
+        }
+        Ok(())
+    }
+
+    // 清理空的SSTable文件
+    fn remove_empty_sstables(&self, sstables: &mut Vec<SSTable>) {
+        let mut indices_to_remove = Vec::new();
+        for (index, sstable) in sstables.iter_mut().enumerate() {
+            match sstable.get_all_series_keys() {
+                Ok(keys) => {
+                    if keys.is_empty() {
+                        indices_to_remove.push(index);
+                    }
+                }
+                Err(_) => {
+                    // 保留无法读取的文件
+                }
+            }
+        }
+
+        // 从后往前删除，避免索引错乱
+        for &index in indices_to_remove.iter().rev() {
+            let sstable = sstables.remove(index);
+            self.locations.remove_file(&sstable.file_name());
+            self.overlap.invalidate();
+        }
+    }
+
+    // SSTable中的点被删除后记录各文件已删除的点数。有文件已删除的点数占比超过
+    // compaction_deleted_ratio 时在后台合并这些文件，上一次触发的compaction结束前不重复触发
+    fn after_points_deleted(&self, sstables: &[SSTable]) {
+        self.record_sstable_meta(sstables);
+        let Some(ratio) = self.config.compaction_deleted_ratio else {
+            return;
+        };
+        let files: Vec<String> = sstables
+            .iter()
+            .filter(|sstable| sstable.info().deleted_ratio() > ratio)
+            .map(SSTable::file_name)
+            .collect();
+        if files.is_empty() || self.deleted_compaction_running.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        tracing::info!("SSTable {:?} 中已删除的点数占比超过 {}，触发compaction", files, ratio);
+        let db = self.clone();
+        let handle = tokio::spawn(async move {
+            let result = db.compact_with(&CompactionOptions::files(files)).await;
+            db.deleted_compaction_running.store(false, Ordering::Release);
+            if let Err(e) = result {
+                tracing::error!("按删除比例触发的compaction失败: {}", e);
+            }
+        });
+        self.background_tasks.push(handle);
+    }
+
+    // 把各SSTable已删除的点数和写入时间记入manifest，与系列元数据一起在下次刷新或关闭时保存，
+    // 重启后恢复。添加、改写或删除文件后调用
+    fn record_sstable_meta(&self, sstables: &[SSTable]) {
+        let counts: BTreeMap<String, usize> = sstables
+            .iter()
+            .map(SSTable::info)
+            .filter(|info| info.deleted_points > 0)
+            .map(|info| (info.file_name.clone(), info.deleted_points))
+            .collect();
+        let created_at = Self::sstable_created_at(sstables);
+        let mut manifest = self.manifest.write();
+        if manifest.sstable_deleted_points != counts || manifest.sstable_created_at != created_at {
+            manifest.sstable_deleted_points = counts;
+            manifest.sstable_created_at = created_at;
+            self.series_meta_dirty.store(true, Ordering::Relaxed);
+        }
+    }
+
+    fn sstable_created_at(sstables: &[SSTable]) -> BTreeMap<String, u64> {
+        sstables
+            .iter()
+            .map(SSTable::info)
+            .filter_map(|info| Some((info.file_name.clone(), info.created_at?)))
+            .collect()
+    }
+
+    // 删除系列在 [start_time, end_time] 范围内的数据点，返回删除的数量
+    pub async fn delete_range(&self, series_key: &str, start_time: Option<u64>, end_time: Option<u64>, force: bool) -> Result<usize> {
+        Self::check_time_range(start_time, end_time)?;
+        self.check_deletable(series_key, force)?;
+        let _flush_guard = self.flush_lock.lock().await;
+        let mut removed = {
+            let mut memtable = self.memtable.write();
+            memtable.delete_range(series_key, start_time, end_time)
+        };
+
+        {
+            let mut sstables = self.sstables.lock();
+            let mut removed_from_sstables = 0;
+            for sstable in self.locations.locate(&mut sstables, series_key) {
+                removed_from_sstables += sstable.delete_range(series_key, start_time, end_time)?;
+            }
+            if removed_from_sstables > 0 {
+                self.overlap.invalidate();
+            }
+            removed += removed_from_sstables;
+            self.remove_empty_sstables(&mut sstables);
+            if removed_from_sstables > 0 {
+                self.after_points_deleted(&sstables);
+            }
+        }
+
+        if removed > 0 {
+            self.quota.mark_stale();
+        }
+        let last = self.last_timestamps.get(series_key);
+        if removed > 0 && last.is_some_and(|last| end_time.is_none_or(|end| last <= end)) {
+            self.refresh_last_timestamp(series_key);
+        }
+        if removed > 0 {
+            self.changes.publish(|| ChangeEvent::Delete { series_key: series_key.to_string(), start_time, end_time });
+        }
+
+        Ok(removed)
+    }
+
+    // 系列最新的时间戳，直接读内存中的映射
+    pub fn last_timestamp(&self, series_key: &str) -> Option<u64> {
+        self.last_timestamps.get(series_key)
+    }
+
+    // 系列的数据点总数：内存表中的点数加上各SSTable块记录的点数，不解压数据。
+    // 覆盖写入同一时间戳的点在compaction去重之前会重复计数
+    pub fn series_point_count(&self, series_key: &str) -> usize {
+        let mut count = self.memtables().series_points(series_key).count();
+
+        let mut sstables = self.sstables.lock();
+        for sstable in self.locations.locate(&mut sstables, series_key) {
+            match sstable.load_series_list() {
+                Ok(blocks) => {
+                    count += blocks
+                        .iter()
+                        .filter(|block| block.series_key == series_key)
+                        .map(|block| block.count)
+                        .sum::<usize>();
+                }
+                Err(e) => {
+                    tracing::warn!("读取SSTable元数据失败 {:?}: {}", sstable.file_path(), e);
+                    self.observers.sstable_read_failed(sstable.file_path(), &e);
+                }
+            }
+        }
+        count
+    }
+
+    // 按SSTable中的块元数据统计系列的压缩效果，不解压也不重新编码。系列不存在时返回None
+    pub fn series_compression_report(&self, series_key: &str) -> Result<Option<CompressionReport>> {
+        if self.last_timestamps.get(series_key).is_none() {
+            return Ok(None);
+        }
+        let mut report = CompressionReport::new(series_key);
+        let mut sstables = self.sstables.lock();
+        for sstable in self.locations.locate(&mut sstables, series_key) {
+            for block in sstable.load_series_list()?.iter().filter(|block| block.series_key == series_key) {
+                report.add_block(block);
+            }
+        }
+        Ok(Some(report))
+    }
+
+    // 删除后根据内存表和SSTable块元数据重新计算最新时间戳，不解压数据
+    fn refresh_last_timestamp(&self, series_key: &str) {
+        let mut last = self.memtables().series_points(series_key).map(|dp| dp.timestamp).max();
+
+        let mut sstables = self.sstables.lock();
+        for sstable in self.locations.locate(&mut sstables, series_key) {
+            match sstable.load_series_list() {
+                Ok(blocks) => {
+                    for block in blocks.iter().filter(|block| block.series_key == series_key) {
+                        last = Some(last.map_or(block.max_timestamp, |ts| ts.max(block.max_timestamp)));
+                    }
+                }
+                Err(e) => {
+                    tracing::warn!("读取SSTable元数据失败 {:?}: {}", sstable.file_path(), e);
+                    self.observers.sstable_read_failed(sstable.file_path(), &e);
+                }
+            }
+        }
+        drop(sstables);
+
+        self.last_timestamps.set(series_key, last);
+        if last.is_none() {
+            self.forget_series_meta(series_key);
+        }
+    }
+
+    // 记录系列最早的数据点时间戳（写入更早的点时随之提前）和新系列的标签，随下一次刷新一起持久化。
+    // 已有记录的系列只取读锁
+    fn record_series_start(&self, series_key: &str, tags: &BTreeMap<String, String>, timestamp: u64) {
+        {
+            let manifest = self.manifest.read();
+            let started = manifest.series_first_seen.get(series_key).is_some_and(|first_seen| *first_seen <= timestamp);
+            if started && manifest.series_tags.contains_key(series_key) {
+                return;
+            }
+        }
+        let mut manifest = self.manifest.write();
+        let first_seen = manifest.series_first_seen.entry(series_key.to_string()).or_insert(u64::MAX);
+        if *first_seen > timestamp {
+            *first_seen = timestamp;
+            self.series_meta_dirty.store(true, Ordering::Relaxed);
+        }
+        if !manifest.series_tags.contains_key(series_key) {
+            manifest.series_tags.insert(series_key.to_string(), tags.clone());
+            self.series_meta_dirty.store(true, Ordering::Relaxed);
+        }
+    }
+
+    fn forget_series_meta(&self, series_key: &str) {
+        let mut manifest = self.manifest.write();
+        let mut removed = manifest.series_first_seen.remove(series_key).is_some();
+        removed |= manifest.series_resolution.remove(series_key).is_some();
+        if manifest.series_tags.remove(series_key).is_some() || removed {
+            self.series_meta_dirty.store(true, Ordering::Relaxed);
+        }
+    }
+
+    pub fn series_tags(&self, series_key: &str) -> Option<BTreeMap<String, String>> {
+        self.manifest.read().series_tags.get(series_key).cloned()
+    }
+
+    // 系列的敏感标签键：所有系列共用的列表加上系列单独标记的键
+    pub fn sensitive_tag_keys(&self, series_key: &str) -> std::collections::BTreeSet<String> {
+        let mut keys: std::collections::BTreeSet<String> = self.config.sensitive_tag_keys.iter().cloned().collect();
+        if let Some(series_keys) = self.manifest.read().sensitive_tags.get(series_key) {
+            keys.extend(series_keys.iter().cloned());
+        }
+        keys
+    }
+
+    // 系列单独标记的敏感标签键
+    pub fn series_sensitive_tags(&self, series_key: &str) -> Vec<String> {
+        self.manifest.read().sensitive_tags.get(series_key).cloned().unwrap_or_default()
+    }
+
+    // 替换系列单独标记的敏感标签键并持久化，空列表表示取消标记。系列不存在时返回false。
+    // 删除系列的数据不清除标记，之后重新写入的同名系列仍然隐藏这些标签
+    pub fn set_series_sensitive_tags(&self, series_key: &str, mut keys: Vec<String>) -> Result<bool> {
+        keys.sort();
+        keys.dedup();
+        let mut manifest = self.manifest.write();
+        if !manifest.series_tags.contains_key(series_key) {
+            return Ok(false);
+        }
+        let previous = if keys.is_empty() {
+            manifest.sensitive_tags.remove(series_key)
+        } else {
+            manifest.sensitive_tags.insert(series_key.to_string(), keys)
+        };
+        if let Err(e) = manifest.save(&self.data_dir) {
+            match previous {
+                Some(previous) => manifest.sensitive_tags.insert(series_key.to_string(), previous),
+                None => manifest.sensitive_tags.remove(series_key),
+            };
+            return Err(e);
+        }
+        Ok(true)
+    }
+
+    // 修改系列标签：只重写manifest，不触碰SSTable中的压缩数据。系列不存在时返回false
+    pub fn update_series_tags(&self, series_key: &str, tags: BTreeMap<String, String>) -> Result<bool> {
+        Ok(self.modify_series_tags(series_key, |current| *current = tags)?.is_some())
+    }
+
+    // 合并修改系列标签：先删除 remove 中的键，再写入 set 中的键值（已有的键被覆盖）。
+    // 返回修改后的标签，系列不存在时返回None
+    pub fn patch_series_tags(
+        &self,
+        series_key: &str,
+        set: BTreeMap<String, String>,
+        remove: &[String],
+    ) -> Result<Option<BTreeMap<String, String>>> {
+        self.modify_series_tags(series_key, |tags| {
+            for key in remove {
+                tags.remove(key);
+            }
+            tags.extend(set);
+        })
+    }
+
+    fn modify_series_tags(
+        &self,
+        series_key: &str,
+        modify: impl FnOnce(&mut BTreeMap<String, String>),
+    ) -> Result<Option<BTreeMap<String, String>>> {
+        let mut manifest = self.manifest.write();
+        let Some(current) = manifest.series_tags.get_mut(series_key) else {
+            return Ok(None);
+        };
+        let previous = current.clone();
+        modify(current);
+        let tags = current.clone();
+        if let Err(e) = manifest.save(&self.data_dir) {
+            manifest.series_tags.insert(series_key.to_string(), previous);
+            return Err(e);
+        }
+        drop(manifest);
+
+        // 新标签成为标签漂移检测的基准
+        self.tag_drift.forget(series_key);
+        self.tag_drift.seed(series_key, &tags);
+        Ok(Some(tags))
+    }
+
+    fn persist_series_meta(&self) -> Result<()> {
+        if self.series_meta_dirty.swap(false, Ordering::Relaxed) {
+            if let Err(e) = self.manifest.read().save(&self.data_dir) {
+                self.series_meta_dirty.store(true, Ordering::Relaxed);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn first_seen(&self, series_key: &str) -> Option<u64> {
+        self.manifest.read().series_first_seen.get(series_key).copied()
+    }
+
+    // 首个数据点晚于since的系列，按首个数据点的时间戳排序
+    pub fn series_created_since(&self, since: u64) -> Vec<(String, u64)> {
+        let manifest = self.manifest.read();
+        let mut series: Vec<(String, u64)> = manifest
+            .series_first_seen
+            .iter()
+            .filter(|(_, first_seen)| **first_seen > since)
+            .map(|(key, first_seen)| (key.clone(), *first_seen))
+            .collect();
+        series.sort_by_key(|(key, first_seen)| (*first_seen, key.clone()));
+        series
+    }
+
+    // 键以 prefix 开头的系列，按键排序。manifest中的系列键有序，只访问前缀范围内的键，
+    // 不像 get_all_series 那样读取每个SSTable的全部系列键
+    pub fn series_with_prefix(&self, prefix: &str) -> Vec<String> {
+        let manifest = self.manifest.read();
+        prefix_range(&manifest.series_first_seen, prefix).map(|(key, _)| key.clone()).collect()
+    }
+
+    // 前缀下系列的点数统计，只读取内存表和SSTable元数据中的计数
+    pub fn prefix_stats(&self, prefix: &str) -> PrefixStats {
+        let mut counts: BTreeMap<String, u64> = BTreeMap::new();
+        let mut memtable_points = 0;
+        {
+            let memtables = self.memtables();
+            for memtable in memtables.tables() {
+                for (series_key, points) in prefix_range(memtable.get_data(), prefix) {
+                    memtable_points += points.len();
+                    *counts.entry(series_key.clone()).or_default() += points.len() as u64;
+                }
+            }
+        }
+
+        let mut sstable_count = 0;
+        {
+            let sstables = self.sstables.lock();
+            for info in sstables.iter().map(SSTable::info) {
+                let mut found = false;
+                for (series_key, points) in prefix_range(&info.series_points, prefix) {
+                    found = true;
+                    *counts.entry(series_key.clone()).or_default() += *points as u64;
+                }
+                sstable_count += usize::from(found);
+            }
+        }
+
+        PrefixStats {
+            prefix: prefix.to_string(),
+            series: self.series_with_prefix(prefix).len(),
+            total_points: counts.values().sum(),
+            memtable_points,
+            sstable_count,
+            top_series: top_series(counts, SUMMARY_TOP_SERIES),
+        }
+    }
+
+    // 删除键以 prefix 开头的全部系列，受删除保护的系列保留并在结果中列出。
+    // prefix 不能为空，清空全部数据使用 truncate
+    pub async fn delete_prefix(&self, prefix: &str) -> Result<PrefixDeleteReport> {
+        if prefix.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "前缀不能为空，清空全部数据请使用 truncate",
+            ));
+        }
+        let (protected, deletable): (Vec<String>, Vec<String>) =
+            self.series_with_prefix(prefix).into_iter().partition(|series_key| self.is_protected(series_key));
+        let mut deleted = Vec::with_capacity(deletable.len());
+        for series_key in deletable {
+            if self.delete(&series_key, None, false).await? {
+                deleted.push(series_key);
+            }
+        }
+        if !deleted.is_empty() {
+            self.persist_series_meta()?;
+        }
+        tracing::info!("按前缀 {} 删除 {} 个系列，保留 {} 个受保护的系列", prefix, deleted.len(), protected.len());
+        Ok(PrefixDeleteReport {
+            prefix: prefix.to_string(),
+            deleted,
+            protected,
+        })
+    }
+
+    // 添加一条注释，写入 ANNOTATIONS.json 后返回分配了编号的注释。写文件在阻塞线程池中进行
+    pub async fn add_annotation(&self, annotation: NewAnnotation) -> Result<Annotation> {
+        validate_annotation(&annotation).map_err(|error| DbError::InvalidAnnotation { error })?;
+        let store = Arc::clone(&self.annotations);
+        let annotation = self.blocking.run(move || store.add(annotation)).await??;
+        tracing::info!("添加注释 {} (时间戳: {})", annotation.id, annotation.timestamp);
+        Ok(annotation)
+    }
+
+    // 与时间范围相交的注释，按时间戳排序。指定系列时返回该系列的注释和全局注释
+    pub fn annotations(&self, series_key: Option<&str>, range: &TimeRange) -> Result<Vec<Annotation>> {
+        let resolved = self.resolve_time_range(range);
+        Self::check_time_range(resolved.start_time, resolved.end_time)?;
+        Ok(self.annotations.query(series_key, resolved.start_time, resolved.end_time))
+    }
+
+    pub fn annotation(&self, id: u64) -> Option<Annotation> {
+        self.annotations.get(id)
+    }
+
+    pub async fn delete_annotation(&self, id: u64) -> Result<bool> {
+        Ok(self.remove_annotations(move |annotation| annotation.id == id).await? > 0)
+    }
+
+    // 删除系列的全部注释（删除系列时可选），返回删除的条数，全局注释不受影响
+    pub async fn delete_series_annotations(&self, series_key: &str) -> Result<usize> {
+        let series_key = series_key.to_string();
+        self.remove_annotations(move |annotation| annotation.series_key.as_deref() == Some(series_key.as_str())).await
+    }
+
+    // 在阻塞线程池中删除满足条件的注释并重写 ANNOTATIONS.json
+    async fn remove_annotations(&self, remove: impl FnMut(&Annotation) -> bool + Send + 'static) -> Result<usize> {
+        let store = Arc::clone(&self.annotations);
+        self.blocking.run(move || store.remove_where(remove)).await?
+    }
+
+    // 清空全部数据：内存表和所有SSTable文件。保留策略等manifest配置不受影响。
+    // 有受删除保护的系列时逐个删除其余系列，保留受保护的系列并返回它们的键。
+    // 系列的注释一并删除，受保护系列的注释和全局注释保留
+    pub async fn truncate(&self) -> Result<Vec<String>> {
+        if self.shutdown.is_cancelled() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::BrokenPipe,
+                "数据库已关闭，无法清空数据",
+            ));
+        }
+
+        let (protected, unprotected): (Vec<String>, Vec<String>) =
+            self.get_all_series().await?.into_iter().partition(|series_key| self.is_protected(series_key));
+        let kept = protected.clone();
+        self.remove_annotations(move |annotation| {
+            annotation.series_key.as_ref().is_some_and(|series_key| !kept.contains(series_key))
+        })
+        .await?;
+        if !protected.is_empty() {
+            for series_key in &unprotected {
+                self.delete(series_key, None, true).await?;
+            }
+            self.persist_series_meta()?;
+            tracing::warn!("数据库已清空: 删除 {} 个系列，保留 {} 个受保护的系列", unprotected.len(), protected.len());
+            return Ok(protected);
+        }
+
+        // 等待正在进行的刷新，刷新写出的SSTable随后一起删除
+        let _flush_guard = self.flush_lock.lock().await;
+        self.memtable.write().clear();
+
+        let removed = {
+            let mut sstables = self.sstables.lock();
+            for sstable in sstables.iter() {
+                sstable.delete_file()?;
+            }
+            let removed = sstables.len();
+            sstables.clear();
+            self.locations.clear();
+            self.overlap.invalidate();
+            removed
+        };
+
+        // 清理目录中残留的未被加载的数据文件
+        for dir in self.data_dirs.all() {
+            for entry in std::fs::read_dir(dir)? {
+                let path = entry?.path();
+                if path.extension().and_then(|s| s.to_str()) == Some("data") {
+                    std::fs::remove_file(&path)?;
+                }
+            }
+        }
+
+        self.tag_drift.clear();
+        self.last_timestamps.clear();
+        self.quota.reset(None, std::iter::empty());
+        {
+            let mut manifest = self.manifest.write();
+            manifest.series_first_seen.clear();
+            manifest.series_tags.clear();
+            manifest.sensitive_tags.clear();
+            manifest.series_resolution.clear();
+        }
+        self.series_meta_dirty.store(true, Ordering::Relaxed);
+        self.persist_series_meta()?;
+        self.changes.publish_all(|| {
+            unprotected
+                .into_iter()
+                .map(|series_key| ChangeEvent::Delete { series_key, start_time: None, end_time: None })
+                .collect()
+        });
+
+        tracing::warn!("数据库已清空: 删除 {} 个SSTable文件", removed);
+        Ok(Vec::new())
+    }
+
+    // 导出全部数据为tar归档：先刷新内存表，再在SSTable列表锁内为所有文件建立硬链接快照，
+    // 写归档期间的compaction和删除不影响导出的内容
+    pub async fn export_archive<W: std::io::Write + Send + 'static>(&self, writer: W) -> Result<(W, ExportManifest)> {
+        let snapshot = self.export_snapshot().await?;
+        // 写归档的速度取决于接收方，不占用执行池的名额
+        tokio::task::spawn_blocking(move || snapshot.write_tar(writer))
+            .await
+            .map_err(std::io::Error::other)?
+    }
+
+    // 刷新内存表并建立导出快照，由调用方决定在哪里写出归档。
+    // 快照持有维护许可，归档写完或快照被丢弃前compaction和校验等待
+    pub async fn export_snapshot(&self) -> Result<ExportSnapshot> {
+        let permit = Arc::clone(&self.maintenance).acquire_owned().await.map_err(std::io::Error::other)?;
+        self.flush_memtable().await?;
+        let sstables = self.sstables.lock();
+        let manifest = self.manifest.read().clone();
+        let dir = self.data_dir.join(format!("{}{}", EXPORT_DIR_PREFIX, uuid::Uuid::new_v4().simple()));
+        let snapshot = ExportSnapshot::create(dir, &sstables, manifest, self.config.timestamp_precision)?
+            .with_operation(self.operations.start(OperationKind::Snapshot, self.clock.now_secs()))
+            .with_permit(permit);
+        tracing::info!("开始导出: {} 个SSTable文件", snapshot.file_count());
+        Ok(snapshot)
+    }
+
+    // 从 export_archive 生成的归档导入数据。数据库不为空时拒绝，force 为true时先清空再导入。
+    // 保留策略和预热列表沿用本库的配置，不随归档导入
+    pub async fn import_archive<R: std::io::Read + Send + 'static>(&self, reader: R, force: bool) -> Result<ImportReport> {
+        if self.shutdown.is_cancelled() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::BrokenPipe,
+                "数据库已关闭，无法导入数据",
+            ));
+        }
+
+        let series_count = self.get_all_series().await?.len();
+        let sstable_count = self.sstables.lock().len();
+        let replace = series_count > 0 || sstable_count > 0;
+        if replace {
+            if !force {
+                return Err(DbError::DatabaseNotEmpty { series_count, sstable_count }.into());
+            }
+            // 覆盖导入不能保留部分系列，有受保护的系列时拒绝
+            if let Some(series_key) = self.get_all_series().await?.into_iter().find(|key| self.is_protected(key)) {
+                return Err(DbError::SeriesProtected { series_key }.into());
+            }
+        }
+
+        // 先完整解包并校验到导入目录，归档有问题时现有数据保持不变
+        let dir = self.data_dir.join(format!("{}{}", IMPORT_DIR_PREFIX, uuid::Uuid::new_v4().simple()));
+        let precision = self.config.timestamp_precision;
+        let db = self.clone();
+        let mut archive = tokio::task::spawn_blocking(move || {
+            let archive = unpack_archive(reader, dir, precision)?;
+            db.validate_archive(&archive)?;
+            Ok::<_, std::io::Error>(archive)
+        })
+        .await
+        .map_err(std::io::Error::other)??;
+        let report = archive.report();
+        // 归档校验通过后才清空旧数据；导入目录是子目录，不受清空影响
+        if replace {
+            self.truncate().await?;
+        }
+        self.install_archive(&mut archive)?;
+        self.refresh_quota_usage().await?;
+
+        tracing::info!("导入完成: {} 个SSTable文件，{} 个系列", report.files_imported, report.series_count);
+        Ok(report)
+    }
+
+    // 导入的数据点与其他写入使用相同的规则，任一点不满足时拒绝整个归档。只在需要逐点检查时解压
+    fn validate_archive(&self, archive: &ImportedArchive) -> Result<()> {
+        let no_tags = BTreeMap::new();
+        for block in archive.blocks.iter().flatten() {
+            let tags = archive.manifest.series_tags.get(&block.series_key).unwrap_or(&no_tags);
+            let points = block_decoder(block.compressed_data.clone())?.take(block.count);
+            self.validate_write(&block.series_key, Some(tags), points)
+                .map_err(|e| e.into_db_error(&block.series_key))?;
+        }
+        Ok(())
+    }
+
+    // 把校验过的文件按归档中的顺序移入数据目录，并登记系列元数据
+    fn install_archive(&self, archive: &mut ImportedArchive) -> Result<()> {
+        let mut imported = std::collections::BTreeSet::new();
+        {
+            let mut sstables = self.sstables.lock();
+            for (path, blocks) in archive.files.iter().zip(&archive.blocks) {
+                // 归档解包在主数据目录中，重命名不能跨文件系统
+                let target = self.next_sstable_path_in(&self.data_dir, "sstable");
+                std::fs::rename(path, &target)?;
+                let mut sstable = SSTable::new(target)?.with_mapping_cache(Arc::clone(&self.mappings));
+                sstable.record_blocks(blocks);
+                sstable.set_created_at(None);
+                self.locations.add_file(&sstable.file_name(), blocks.iter().map(|block| block.series_key.as_str()));
+                self.overlap.invalidate();
+                for block in blocks {
+                    self.last_timestamps.observe(&block.series_key, block.max_timestamp);
+                    imported.insert(block.series_key.clone());
+                }
+                Self::add_sstable(&mut sstables, sstable);
+            }
+            self.record_sstable_meta(&sstables);
+        }
+
+        let mut manifest = self.manifest.write();
+        for series_key in &imported {
+            if let Some(first_seen) = archive.manifest.series_first_seen.get(series_key) {
+                manifest.series_first_seen.insert(series_key.clone(), *first_seen);
+            }
+            if let Some(tags) = archive.manifest.series_tags.remove(series_key) {
+                self.tag_drift.forget(series_key);
+                self.tag_drift.seed(series_key, &tags);
+                manifest.series_tags.insert(series_key.clone(), tags);
+            }
+            if let Some(keys) = archive.manifest.sensitive_tags.remove(series_key) {
+                manifest.sensitive_tags.insert(series_key.clone(), keys);
+            }
+            if let Some(segments) = archive.manifest.series_resolution.remove(series_key) {
+                manifest.series_resolution.insert(series_key.clone(), segments);
+            }
+        }
+        manifest.save(&self.data_dir)
+    }
+
+    // 所有SSTable文件的元数据摘要，按文件顺序排列，不读取文件
+    pub fn sstable_infos(&self) -> Vec<SSTableInfo> {
+        self.sstables.lock().iter().map(|sstable| sstable.info().clone()).collect()
+    }
+
+    // 单个SSTable文件中每个系列的块统计，只读取块元数据。文件不存在时返回None
+    pub fn sstable_detail(&self, file_name: &str) -> Result<Option<SSTableDetail>> {
+        let mut sstables = self.sstables.lock();
+        let Some(sstable) = sstables.iter_mut().find(|sstable| sstable.file_name() == file_name) else {
+            return Ok(None);
+        };
+        let blocks = sstable.load_series_list()?;
+        Ok(Some(SSTableDetail {
+            info: sstable.info().clone(),
+            series: series_summaries(&blocks),
+        }))
+    }
+
+    // SSTable之间时间范围重叠的分析，只读取块元数据不解压。结果被缓存，
+    // 刷新、compaction和删除改变SSTable后下一次调用时重新计算
+    pub fn overlap_analysis(&self) -> Arc<OverlapAnalysis> {
+        if let Some(analysis) = self.overlap.get() {
+            return analysis;
+        }
+        let generation = self.overlap.generation();
+        let mut files = Vec::new();
+        {
+            let mut sstables = self.sstables.lock();
+            for sstable in sstables.iter_mut() {
+                match sstable.load_series_list() {
+                    Ok(blocks) => files.push((sstable.file_name(), series_summaries(&blocks))),
+                    Err(e) => {
+                        tracing::warn!("读取SSTable元数据失败 {:?}: {}", sstable.file_path(), e);
+                        self.observers.sstable_read_failed(sstable.file_path(), &e);
+                    }
+                }
+            }
+        }
+        self.overlap.store(generation, OverlapAnalysis::compute(&files))
+    }
+
+    pub fn overlap_report(&self) -> OverlapReport {
+        self.overlap_analysis().report.clone()
+    }
+
+    // 自动compaction优先合并的时间窗口：重叠最严重的系列中文件最多的一组相交文件的时间范围。
+    // 没有重叠时返回None
+    pub fn overlap_compaction_window(&self) -> Option<CompactionOptions> {
+        self.overlap_analysis()
+            .series
+            .iter()
+            .find_map(|series| series.worst_group.as_ref())
+            .map(|group| CompactionOptions::window(Some(group.start_time), Some(group.end_time)))
+    }
+
+    pub fn retention_policy(&self) -> RetentionPolicy {
+        self.manifest.read().retention.clone()
+    }
+
+    // 更新保留策略并持久化到manifest
+    pub fn set_retention_policy(&self, policy: RetentionPolicy) -> Result<()> {
+        let mut manifest = self.manifest.write();
+        let previous = std::mem::replace(&mut manifest.retention, policy);
+        if let Err(e) = manifest.save(&self.data_dir) {
+            manifest.retention = previous;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    pub fn downsample_policy(&self) -> DownsamplePolicy {
+        self.manifest.read().downsampling.clone()
+    }
+
+    // 更新降采样策略并持久化到manifest，下一次compaction时生效
+    pub fn set_downsample_policy(&self, policy: DownsamplePolicy) -> Result<()> {
+        policy
+            .validate()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        let mut manifest = self.manifest.write();
+        let previous = std::mem::replace(&mut manifest.downsampling, policy);
+        if let Err(e) = manifest.save(&self.data_dir) {
+            manifest.downsampling = previous;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    // 系列中已降低分辨率的时间范围，按时间排列
+    pub fn series_resolution(&self, series_key: &str) -> Vec<ResolutionSegment> {
+        self.manifest.read().series_resolution.get(series_key).cloned().unwrap_or_default()
+    }
+
+    // 进行中的compaction和导出
+    pub fn operations(&self) -> Vec<OperationInfo> {
+        self.operations.list()
+    }
+
+    // 请求取消进行中的操作，操作不存在或已结束时返回false
+    pub fn cancel_operation(&self, id: u64) -> bool {
+        self.operations.cancel(id)
+    }
+
+    pub fn quota_policy(&self) -> QuotaPolicy {
+        self.manifest.read().quotas.clone()
+    }
+
+    // 更新配额策略并持久化到manifest，然后按新规则重新统计用量
+    pub async fn set_quota_policy(&self, policy: QuotaPolicy) -> Result<()> {
+        {
+            let mut manifest = self.manifest.write();
+            let previous = std::mem::replace(&mut manifest.quotas, policy.clone());
+            if let Err(e) = manifest.save(&self.data_dir) {
+                manifest.quotas = previous;
+                return Err(e);
+            }
+        }
+        self.quota.reset(Some(policy), std::iter::empty());
+        self.refresh_quota_usage().await
+    }
+
+    // 每条配额规则的当前用量
+    pub fn quota_report(&self) -> QuotaReport {
+        QuotaReport {
+            stale: self.quota.is_stale(),
+            quotas: self.quota.status(),
+        }
+    }
+
+    // 从SSTable块元数据和内存表重新统计配额用量，没有配额规则时不读取文件
+    async fn refresh_quota_usage(&self) -> Result<()> {
+        if self.quota.is_empty() {
+            return Ok(());
+        }
+        let db = self.clone();
+        self.blocking.run(move || db.run_refresh_quota_usage()).await?
+    }
+
+    // 锁内只记下内存表的点数和文件列表，块列表在锁外读取。读取期间文件列表有变化（刷新、compaction、删除改写）时
+    // 重新统计；重试 QUOTA_REFRESH_ATTEMPTS 次仍在变化时采用最后一次的结果，并标记用量需要再次统计
+    fn run_refresh_quota_usage(&self) -> Result<()> {
+        for attempt in 1..=QUOTA_REFRESH_ATTEMPTS {
+            let (pending, files) = self.quota_snapshot();
+            let mut usage: BTreeMap<String, (QuotaUsage, u64)> = BTreeMap::new();
+            let mut changed = false;
+            for (path, _) in &files {
+                let blocks = match SSTable::new(path.clone())?.with_mapping_cache(Arc::clone(&self.mappings)).load_series_list() {
+                    Ok(blocks) => blocks,
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                        changed = true;
+                        break;
+                    }
+                    Err(e) => return Err(e),
+                };
+                for block in blocks {
+                    let (stored, _) = usage.entry(block.series_key).or_default();
+                    stored.points += block.count as u64;
+                    stored.bytes += block.compressed_data.len() as u64;
+                }
+            }
+            changed = changed || self.quota_snapshot().1 != files;
+            if changed && attempt < QUOTA_REFRESH_ATTEMPTS {
+                continue;
+            }
+            for (series_key, points) in pending {
+                usage.entry(series_key).or_default().1 += points;
+            }
+            self.quota.reset(
+                None,
+                usage.iter().map(|(series_key, (stored, pending))| (series_key.as_str(), *stored, *pending)),
+            );
+            if changed {
+                self.quota.mark_stale();
+            }
+            break;
+        }
+        Ok(())
+    }
+
+    // 内存表中每个系列的点数，和每个SSTable的路径及当时的大小、修改时间
+    fn quota_snapshot(&self) -> (Vec<(String, u64)>, Vec<FileVersion>) {
+        let memtables = self.memtables();
+        let sstables = self.sstables.lock();
+        let pending = memtables.series().into_iter().map(|(series_key, points)| (series_key.clone(), points.len() as u64)).collect();
+        let files = sstables
+            .iter()
+            .map(|sstable| {
+                let metadata = std::fs::metadata(sstable.file_path()).ok().map(|m| (m.len(), m.modified().ok()));
+                (sstable.file_path().to_path_buf(), metadata)
+            })
+            .collect();
+        (pending, files)
+    }
+
+    // 对超出配额的 evict 规则，从该前缀所有系列中最旧的数据点开始删除，直到回到上限以内。
+    // 字节数按前缀的平均每点字节数换算为点数
+    pub async fn enforce_quotas(&self) -> Result<RetentionReport> {
+        let mut report = RetentionReport::default();
+        let exceeded: Vec<QuotaStatus> = self
+            .quota
+            .status()
+            .into_iter()
+            .filter(|status| status.exceeded && status.rule.action == QuotaAction::Evict)
+            .collect();
+        if exceeded.is_empty() {
+            return Ok(report);
+        }
+
+        // 每条规则要淘汰的点数和匹配的系列，一次遍历收集所有规则需要的时间戳
+        struct Eviction {
+            rule: QuotaRule,
+            excess: u64,
+            series: Vec<String>,
+            timestamps: Vec<u64>,
+        }
+        let mut evictions = Vec::new();
+        for status in exceeded {
+            let QuotaStatus { rule, usage, .. } = status;
+            let mut excess = rule.max_points.map_or(0, |max| usage.points.saturating_sub(max));
+            if let Some(max_bytes) = rule.max_bytes {
+                if usage.bytes > max_bytes && usage.points > 0 {
+                    let bytes_per_point = (usage.bytes as f64 / usage.points as f64).max(f64::MIN_POSITIVE);
+                    excess = excess.max(((usage.bytes - max_bytes) as f64 / bytes_per_point).ceil() as u64);
+                }
+            }
+            if excess > 0 {
+                evictions.push(Eviction { rule, excess, series: Vec::new(), timestamps: Vec::new() });
+            }
+        }
+        self.for_each_series(|series_key, _, points| {
+            let matched: Vec<&mut Eviction> =
+                evictions.iter_mut().filter(|eviction| eviction.rule.matches(series_key)).collect();
+            if matched.is_empty() {
+                return ControlFlow::Continue(());
+            }
+            if self.is_protected(series_key) {
+                report.protected_skipped.push(series_key.to_string());
+                return ControlFlow::Continue(());
+            }
+            let timestamps: Vec<u64> = points.map(|(timestamp, _)| timestamp).collect();
+            for eviction in matched {
+                eviction.series.push(series_key.to_string());
+                eviction.timestamps.extend_from_slice(&timestamps);
+            }
+            ControlFlow::Continue(())
+        })?;
+
+        let mut trimmed = std::collections::HashSet::new();
+        for Eviction { rule, excess, series, mut timestamps } in evictions {
+            if timestamps.is_empty() {
+                continue;
+            }
+            // 第 excess 旧的时间戳作为淘汰边界，与边界相同的点一并删除
+            let index = (excess as usize).min(timestamps.len()) - 1;
+            let (_, horizon, _) = timestamps.select_nth_unstable(index);
+            let horizon = *horizon;
+
+            let mut removed = 0;
+            for series_key in series {
+                let count = self.delete_range(&series_key, None, Some(horizon), true).await?;
+                if count > 0 {
+                    trimmed.insert(series_key);
+                    removed += count;
+                }
+            }
+            tracing::warn!(
+                "前缀 \"{}\" 超出存储配额，淘汰最旧的 {} 个数据点（时间戳不晚于 {}）",
+                rule.prefix,
+                removed,
+                horizon
+            );
+            report.points_removed += removed;
+        }
+        report.series_trimmed = trimmed.len();
+        report.protected_skipped.sort();
+        report.protected_skipped.dedup();
+
+        if report.points_removed > 0 {
+            self.refresh_quota_usage().await?;
+        }
+        Ok(report)
+    }
+
+    pub fn warmup_series(&self) -> Vec<String> {
+        self.manifest.read().warmup_series.clone()
+    }
+
+    // 更新启动预热列表并持久化到manifest，下次启动时生效
+    pub fn set_warmup_series(&self, patterns: Vec<String>) -> Result<()> {
+        let mut manifest = self.manifest.write();
+        let previous = std::mem::replace(&mut manifest.warmup_series, patterns);
+        if let Err(e) = manifest.save(&self.data_dir) {
+            manifest.warmup_series = previous;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    pub fn warmup_status(&self) -> WarmupStatus {
+        self.warmup.status()
+    }
+
+    // 预热列表中系列所在的SSTable：建立内存映射并把文件内容读入页缓存，
+    // 避免重启后第一次查询时才逐个映射和读取文件。列表为空时不做任何事
+    pub async fn warm_up(&self) -> Result<WarmupStatus> {
+        if self.warmup_series().is_empty() {
+            return Ok(self.warmup_status());
+        }
+        let db = self.clone();
+        let started = Instant::now();
+        let result = self.blocking.run(move || db.run_warmup()).await.and_then(|result| result);
+        let state = if result.is_ok() { WarmupState::Done } else { WarmupState::Failed };
+        self.warmup.finish(state, started.elapsed().as_millis() as u64);
+        result?;
+
+        let status = self.warmup_status();
+        tracing::info!(
+            "预热完成: {} 个系列，{}/{} 个SSTable文件，{} 字节，耗时 {} ms",
+            status.series_matched, status.files_loaded, status.files_total, status.bytes_loaded, status.duration_ms
+        );
+        Ok(status)
+    }
+
+    fn run_warmup(&self) -> Result<()> {
+        let patterns = self.warmup_series();
+        let matched: Vec<String> = self
+            .manifest
+            .read()
+            .series_first_seen
+            .keys()
+            .filter(|key| patterns.iter().any(|pattern| warmup_matches(pattern, key)))
+            .cloned()
+            .collect();
+
+        if !self.locations.is_warm() {
+            let mut sstables = self.sstables.lock();
+            self.locations.rebuild(&mut sstables);
+        }
+        let files: std::collections::BTreeSet<String> = matched
+            .iter()
+            .filter_map(|key| self.locations.lookup(key))
+            .flatten()
+            .collect();
+        self.warmup.start(matched.len() as u64, files.len() as u64);
+
+        // 逐个文件加锁，预热期间查询和写入可以穿插执行
+        for file_name in files {
+            if self.shutdown.is_cancelled() {
+                break;
+            }
+            let mut sstables = self.sstables.lock();
+            let Some(sstable) = sstables.iter_mut().find(|sstable| sstable.file_name() == file_name) else {
+                continue;
+            };
+            match sstable.prefetch() {
+                Ok(bytes) => self.warmup.file_loaded(bytes),
+                Err(e) => tracing::warn!("预热SSTable失败 {:?}: {}", sstable.file_path(), e),
+            }
+        }
+        Ok(())
+    }
+
+    // 在后台执行预热，进度见 warmup_status
+    pub fn spawn_warmup(&self) {
+        let db = self.clone();
+        let handle = tokio::spawn(async move {
+            if let Err(e) = db.warm_up().await {
+                tracing::error!("预热失败: {}", e);
+            }
+        });
+        self.background_tasks.push(handle);
+    }
+
+    // 校验并修正SSTable中每个系列块记录的时间范围。范围查询按记录的范围跳过块，
+    // 记录错误的块会被错误地跳过；修正后重新计算相关系列的最新时间戳
+    pub async fn verify_series_bounds(&self) -> Result<BoundsReport> {
+        let db = self.clone();
+        let report = self.blocking.run(move || db.run_verify_series_bounds()).await?;
+
+        let repaired: std::collections::BTreeSet<&str> =
+            report.repaired.iter().map(|mismatch| mismatch.series_key.as_str()).collect();
+        for series_key in repaired {
+            self.refresh_last_timestamp(series_key);
+        }
+        if !report.repaired.is_empty() {
+            tracing::warn!("修正了 {} 个时间范围错误的系列块", report.repaired.len());
+        }
+        Ok(report)
+    }
+
+    fn run_verify_series_bounds(&self) -> BoundsReport {
+        let mut report = BoundsReport::default();
+        let mut sstables = self.sstables.lock();
+        for sstable in sstables.iter_mut() {
+            match sstable.repair_series_bounds() {
+                Ok((blocks, mismatches)) => {
+                    report.files_checked += 1;
+                    report.blocks_checked += blocks;
+                    report.repaired.extend(mismatches);
+                }
+                Err(e) => {
+                    tracing::warn!("校验SSTable时间范围失败 {:?}: {}", sstable.file_path(), e);
+                    self.observers.sstable_read_failed(sstable.file_path(), &e);
+                    report.files_failed += 1;
+                }
+            }
+        }
+        if !report.repaired.is_empty() {
+            self.record_sstable_meta(&sstables);
+        }
+        report
+    }
+
+    // 完整性校验：逐个解压所有SSTable中的块，报告损坏的文件，不修改任何文件。
+    // 与 start_verify 共用维护许可，已有校验在执行时等待其结束
+    pub async fn verify(&self) -> Result<VerifyReport> {
+        let _permit = Arc::clone(&self.maintenance).acquire_owned().await.map_err(std::io::Error::other)?;
+        let operation = self.operations.start(OperationKind::Verify, self.clock.now_secs());
+        let verifier = self.begin_verify(&operation);
+        self.run_verify(&operation, verifier).await
+    }
+
+    // 在后台执行完整性校验，立即返回操作编号，进度和结果通过 job 查询。
+    // 已有维护任务（校验、compaction或导出）在执行时返回 MaintenanceBusy
+    pub fn start_verify(&self) -> Result<u64> {
+        let Ok(permit) = Arc::clone(&self.maintenance).try_acquire_owned() else {
+            // 维护任务先取得许可再登记操作，登记表中进行中的操作就是持有许可的任务
+            let operation_id = self.operations.list().first().map(|operation| operation.id);
+            return Err(DbError::MaintenanceBusy { operation_id }.into());
+        };
+        let operation = self.operations.start(OperationKind::Verify, self.clock.now_secs());
+        let id = operation.id();
+        let verifier = self.begin_verify(&operation);
+        let db = self.clone();
+        let handle = tokio::spawn(async move {
+            let _permit = permit;
+            let outcome = match db.run_verify(&operation, verifier).await {
+                Ok(report) => serde_json::to_value(&report).map_err(|e| e.to_string()),
+                Err(e) => {
+                    tracing::error!("完整性校验 {} 失败: {}", id, e);
+                    Err(e.to_string())
+                }
+            };
+            operation.finish(db.clock.now_secs(), outcome);
+        });
+        self.background_tasks.push(handle);
+        Ok(id)
+    }
+
+    // 进行中或最近结束的后台操作，进程重启后不保留
+    pub fn job(&self, id: u64) -> Option<JobInfo> {
+        self.operations.job(id)
+    }
+
+    // 校验开始时的SSTable列表，之后由flush、compaction生成的文件不在本次校验范围内
+    fn begin_verify(&self, operation: &OperationHandle) -> SSTableVerifier {
+        let files: Vec<PathBuf> = self.sstables.lock().iter().map(|sstable| sstable.file_path().to_path_buf()).collect();
+        let verifier = SSTableVerifier::new(files, operation.cancel_token());
+        operation.begin_stage("verifying", verifier.files_total());
+        verifier
+    }
+
+    // 分批校验，每批在阻塞线程池中执行，两批之间更新进度、检查取消和关闭
+    async fn run_verify(&self, operation: &OperationHandle, mut verifier: SSTableVerifier) -> Result<VerifyReport> {
+        let mut report = VerifyReport::default();
+        while !verifier.is_done() {
+            operation.check_cancelled()?;
+            if self.shutdown.is_cancelled() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::BrokenPipe,
+                    format!("数据库已关闭，校验在第 {}/{} 个文件处停止", verifier.position(), verifier.files_total()),
+                ));
+            }
+            let (rest, checks) = self
+                .blocking
+                .run(move || {
+                    let checks: Vec<FileCheck> = verifier.by_ref().take(VERIFY_CHUNK_FILES).collect();
+                    (verifier, checks)
+                })
+                .await?;
+            verifier = rest;
+            for check in &checks {
+                if let FileOutcome::Corrupt(error) = &check.outcome {
+                    tracing::warn!("SSTable {} 校验失败: {}", check.file_name, error);
+                    operation.record_finding(format!("{}: {}", check.file_name, error));
+                }
+                report.record(check);
+                operation.file_done();
+            }
+        }
+
+        tracing::info!(
+            "完整性校验完成: 检查 {} 个文件、{} 个块，{} 个文件损坏",
+            report.files_checked,
+            report.blocks_checked,
+            report.corrupt.len()
+        );
+        Ok(report)
+    }
+
+    // 按保留策略删除每个系列超出保留期的数据
+    pub async fn enforce_retention(&self) -> Result<RetentionReport> {
+        let policy = self.retention_policy();
+        let mut report = RetentionReport::default();
+        if policy.is_empty() {
+            return Ok(report);
+        }
+
+        let now = self.now_timestamp();
+        let precision = self.timestamp_precision();
+        for (series_key, tags) in self.get_series_tags().await? {
+            let Some(max_age) = policy.max_age_for(&series_key, &tags) else {
+                continue;
+            };
+            let horizon = now.saturating_sub(precision.from_secs(max_age));
+            if horizon == 0 {
+                continue;
+            }
+            if self.is_protected(&series_key) {
+                report.protected_skipped.push(series_key);
+                continue;
+            }
+            let removed = self.delete_range(&series_key, None, Some(horizon - 1), true).await?;
+            if removed > 0 {
+                report.series_trimmed += 1;
+                report.points_removed += removed;
+            }
+        }
+
+        if report.points_removed > 0 {
+            tracing::info!(
+                "保留策略执行完成: 清理 {} 个系列中的 {} 个数据点",
+                report.series_trimmed,
+                report.points_removed
+            );
+        }
+        Ok(report)
+    }
+
+    // 每个数据目录中的SSTable数量、大小和可用空间
+    pub fn data_dir_usage(&self) -> Vec<DataDirUsage> {
+        let dirs = self.data_dirs.all();
+        let mut usage: Vec<DataDirUsage> = dirs
+            .iter()
+            .enumerate()
+            .map(|(index, dir)| DataDirUsage {
+                path: dir.clone(),
+                primary: index == 0,
+                sstable_count: 0,
+                sstable_bytes: 0,
+                free_bytes: self.data_dirs.space().available_space(dir).ok(),
+            })
+            .collect();
+        for sstable in self.sstables.lock().iter() {
+            let Some(index) = dirs.iter().position(|dir| Some(dir.as_path()) == sstable.file_path().parent()) else {
+                continue;
+            };
+            usage[index].sstable_count += 1;
+            usage[index].sstable_bytes += sstable.file_size();
+        }
+        usage
+    }
+
+    // 把SSTable移动到另一个数据目录：先复制为目标目录中的临时文件并fsync，再在锁内确认复制期间
+    // 文件没有被改写，重命名为正式文件名并切换到新路径，最后删除旧文件。复制时不持有SSTable列表的锁，
+    // 查询和写入照常进行。文件不存在时返回None
+    pub async fn relocate_sstable(&self, file_name: &str, target_dir: &Path) -> Result<Option<SSTableRelocation>> {
+        let Some(target_dir) = self.data_dirs.find(target_dir).map(Path::to_path_buf) else {
+            return Err(DbError::UnknownDataDir { path: target_dir.to_path_buf() }.into());
+        };
+        let source = {
+            let sstables = self.sstables.lock();
+            match sstables.iter().find(|sstable| sstable.file_name() == file_name) {
+                Some(sstable) => sstable.file_path().to_path_buf(),
+                None => return Ok(None),
+            }
+        };
+        let source_dir = source.parent().map(Path::to_path_buf).unwrap_or_default();
+        let mut relocation = SSTableRelocation {
+            file_name: file_name.to_string(),
+            from: source_dir.clone(),
+            to: target_dir.clone(),
+            size_bytes: 0,
+            moved: false,
+        };
+        if source_dir == target_dir {
+            relocation.size_bytes = std::fs::metadata(&source)?.len();
+            return Ok(Some(relocation));
+        }
+
+        let before = std::fs::metadata(&source)?;
+        let target = target_dir.join(file_name);
+        let mut tmp_name = target.as_os_str().to_owned();
+        tmp_name.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_name);
+        let (from, to) = (source.clone(), tmp_path.clone());
+        let copied = self
+            .blocking
+            .run(move || -> Result<u64> {
+                let bytes = std::fs::copy(&from, &to)?;
+                File::open(&to)?.sync_all()?;
+                Ok(bytes)
+            })
+            .await
+            .and_then(|copied| copied);
+        let size_bytes = match copied {
+            Ok(size_bytes) => size_bytes,
+            Err(e) => {
+                let _ = std::fs::remove_file(&tmp_path);
+                return Err(e);
+            }
+        };
+
+        let registered = {
+            let mut sstables = self.sstables.lock();
+            match sstables.iter_mut().find(|sstable| sstable.file_name() == file_name) {
+                None => Ok(false),
+                Some(sstable) => {
+                    let unchanged = sstable.file_path() == source
+                        && std::fs::metadata(&source)
+                            .is_ok_and(|now| now.len() == before.len() && now.modified().ok() == before.modified().ok());
+                    if !unchanged {
+                        Err(DbError::SSTableModified { file_name: file_name.to_string() }.into())
+                    } else {
+                        std::fs::rename(&tmp_path, &target)
+                            .and_then(|_| sync_dir(&target_dir))
+                            .map(|_| {
+                                sstable.set_file_path(target.clone());
+                                true
+                            })
+                    }
+                }
+            }
+        };
+        match registered {
+            Ok(true) => {}
+            Ok(false) => {
+                let _ = std::fs::remove_file(&tmp_path);
+                return Ok(None);
+            }
+            Err(e) => {
+                let _ = std::fs::remove_file(&tmp_path);
+                return Err(e);
+            }
+        }
+
+        // 新路径已生效；删除旧文件失败时留下的重复文件在下次启动时清理
+        if let Err(e) = std::fs::remove_file(&source).and_then(|_| sync_dir(&source_dir)) {
+            tracing::warn!("删除移动前的SSTable {:?} 失败: {}", source, e);
+        }
+        tracing::info!("SSTable {} 已从 {:?} 移动到 {:?}", file_name, source_dir, target_dir);
+        relocation.size_bytes = size_bytes;
+        relocation.moved = true;
+        Ok(Some(relocation))
+    }
+
+    // SSTable文件的总字节数
+    pub fn total_sstable_bytes(&self) -> u64 {
+        self.sstables.lock().iter().map(|sstable| sstable.file_size()).sum()
+    }
+
+    // 总大小超过 max_total_bytes 时，按时间从旧到新整块淘汰所有系列的数据，
+    // 直到回到上限以内。包含最新数据点的块不会被淘汰
+    pub async fn enforce_size_limit(&self) -> Result<RetentionReport> {
+        let mut report = RetentionReport::default();
+        let Some(max_total_bytes) = self.config.max_total_bytes else {
+            return Ok(report);
+        };
+
+        let mut trimmed = std::collections::HashSet::new();
+        let mut protected_skipped = std::collections::BTreeSet::new();
+        while self.total_sstable_bytes() > max_total_bytes {
+            let Some((horizon, newest, series)) = self.eviction_candidates()? else {
+                break;
+            };
+            if horizon >= newest {
+                tracing::warn!("SSTable总大小超过上限 {} 字节，但只剩最新的数据，停止淘汰", max_total_bytes);
+                break;
+            }
+
+            let mut removed = 0;
+            for series_key in series {
+                if self.is_protected(&series_key) {
+                    protected_skipped.insert(series_key);
+                    continue;
+                }
+                let count = self.delete_range(&series_key, None, Some(horizon), true).await?;
+                if count > 0 {
+                    trimmed.insert(series_key);
+                    removed += count;
+                }
+            }
+            if removed == 0 {
+                break;
+            }
+            report.points_removed += removed;
+        }
+        report.series_trimmed = trimmed.len();
+        report.protected_skipped = protected_skipped.into_iter().collect();
+
+        if report.points_removed > 0 {
+            tracing::warn!(
+                "SSTable总大小超过上限 {} 字节，淘汰 {} 个系列中最旧的 {} 个数据点，当前 {} 字节",
+                max_total_bytes,
+                report.series_trimmed,
+                report.points_removed,
+                self.total_sstable_bytes()
+            );
+        }
+        Ok(report)
+    }
+
+    // 所有块中最早结束的块的结束时间作为本轮的淘汰边界，返回边界、最新的时间戳和包含边界之前数据的系列。
+    // 受删除保护的系列不参与边界的计算，但同样会出现在返回的系列中，由调用方跳过
+    fn eviction_candidates(&self) -> Result<Option<(u64, u64, std::collections::BTreeSet<String>)>> {
+        let mut sstables = self.sstables.lock();
+        let mut bounds: Option<(u64, u64)> = None;
+        let mut blocks = Vec::new();
+        for sstable in sstables.iter_mut() {
+            for block in sstable.load_series_list()? {
+                if !self.is_protected(&block.series_key) {
+                    bounds = Some(bounds.map_or((block.max_timestamp, block.max_timestamp), |(horizon, newest)| {
+                        (horizon.min(block.max_timestamp), newest.max(block.max_timestamp))
+                    }));
+                }
+                blocks.push((block.series_key, block.min_timestamp));
+            }
+        }
+
+        Ok(bounds.map(|(horizon, newest)| {
+            let series = blocks
+                .into_iter()
+                .filter(|(_, min_timestamp)| *min_timestamp <= horizon)
+                .map(|(series_key, _)| series_key)
+                .collect();
+            (horizon, newest, series)
+        }))
+    }
+
+    // 定期执行保留策略的后台任务
+    pub fn spawn_retention_task(&self, period: Duration) {
+        let db = self.clone();
+        self.spawn_periodic_task("retention", period, move || {
+            let db = db.clone();
+            async move { db.enforce_retention().await.map(|_| ()) }
+        });
+    }
+
+    pub async fn query_range(&self, series_key: &str, start_time: Option<u64>, end_time: Option<u64>) -> Result<Vec<DataPoint>> {
+        self.query_range_with_stats(series_key, start_time, end_time)
+            .await
+            .map(|(results, _)| results)
+    }
+
+    // 按时间范围查询，如 TimeRange::last(Duration::from_secs(900)) 查询最近15分钟，范围在调用时按当前时间换算
+    pub async fn query_time_range(&self, series_key: &str, range: &TimeRange) -> Result<Vec<DataPoint>> {
+        let resolved = self.resolve_time_range(range);
+        self.query_range(series_key, resolved.start_time, resolved.end_time).await
+    }
+
+    // 范围查询，同时返回内存表和SSTable各自贡献的点数。内存表和SSTable分两步读取，
+    // 期间有系列被替换时重新查询，结果不会混合替换前后的数据
+    pub async fn query_range_with_stats(
+        &self,
+        series_key: &str,
+        start_time: Option<u64>,
+        end_time: Option<u64>,
+    ) -> Result<(Vec<DataPoint>, QueryStats)> {
+        let result = self.query_range_reserved(series_key, start_time, end_time).await?;
+        Ok((result.datapoints, result.stats))
+    }
+
+    // 范围查询，结果带着预留的查询内存，序列化响应时仍计入全局预算
+    async fn query_range_reserved(&self, series_key: &str, start_time: Option<u64>, end_time: Option<u64>) -> Result<QueryResult> {
+        loop {
+            let generation = self.replace_generation();
+            let result = self.query_range_once(series_key, start_time, end_time).await?;
+            if self.replace_generation.load(Ordering::Acquire) == generation {
+                return Ok(result);
+            }
+        }
+    }
+
+    // 按标签过滤的范围查询。数据点的标签为系列的标签，因此整个系列要么全部返回要么都不返回，
+    // 标签不满足条件时不读取数据
+    pub async fn query_range_filtered(
+        &self,
+        series_key: &str,
+        start_time: Option<u64>,
+        end_time: Option<u64>,
+        filter: &TagFilter,
+    ) -> Result<QueryResult> {
+        Self::check_time_range(start_time, end_time)?;
+        if !self.series_matches(series_key, filter) {
+            let reservation = self.query_memory.reserve(0).await?;
+            return Ok(QueryResult { datapoints: Vec::new(), stats: QueryStats::default(), reservation });
+        }
+        self.query_range_reserved(series_key, start_time, end_time).await
+    }
+
+    // 系列当前的标签是否满足过滤条件，没有标签的系列只满足空条件
+    pub fn series_matches(&self, series_key: &str, filter: &TagFilter) -> bool {
+        filter.is_empty() || filter.matches(&self.series_tags(series_key).unwrap_or_default())
+    }
+
+    // 当前的替换计数，有替换正在进行时等待其完成
+    fn replace_generation(&self) -> u64 {
+        let _gate = self.replace_gate.read();
+        self.replace_generation.load(Ordering::Acquire)
+    }
+
+    async fn query_range_once(
+        &self,
+        series_key: &str,
+        start_time: Option<u64>,
+        end_time: Option<u64>,
+    ) -> Result<QueryResult> {
+    Self::check_time_range(start_time, end_time)?;
+
+    // 查询内存表，按时间稳定排序一次，重复的时间戳保留先写入的值。
+    // 内存表先于SSTable读取，期间刷新的点至少出现在其中一边
+    let mut memtable_results: Vec<(u64, f64)> = self
+        .memtables()
+        .query(series_key, start_time, end_time)
+        .into_iter()
+        .map(|dp| (dp.timestamp, dp.value))
+        .collect();
+    tracing::debug!("🔍 内存表查询: {} 个数据点", memtable_results.len());
+    memtable_results.sort_by_key(|(timestamp, _)| *timestamp);
+    let memtable_points = {
+        let mut timestamps: Vec<u64> = memtable_results.iter().map(|(timestamp, _)| *timestamp).collect();
+        timestamps.dedup();
+        timestamps.len()
+    };
+
+    // 所有数据点统一使用系列当前的标签。解压之前按相交块的点数预留内存，预算不足时排队而不是先解压再等待
+    let tags = self.series_tags(series_key).unwrap_or_default();
+    let point_bytes = QueryMemory::point_bytes(&tags);
+    let (blocks, files_scanned) = self.locate_series_blocks(series_key, start_time, end_time);
+    let upper_bound = memtable_points + blocks.iter().map(|block| block.count).sum::<usize>();
+    let reservation = self.query_memory.reserve(upper_bound.saturating_mul(point_bytes)).await?;
+
+    // 解压SSTable中的块，并发的相同查询共享一次解压
+    let sstable_results = if self.config.query_coalescing {
+        let key = (series_key.to_string(), start_time, end_time);
+        self.sstable_queries
+            .run(key, || self.query_sstables(blocks, files_scanned, start_time, end_time))
+            .await
+    } else {
+        self.query_sstables(blocks, files_scanned, start_time, end_time).await
+    };
+    let sstable_results = sstable_results.map_err(std::io::Error::other)?;
+
+    // 多路归并各个有序数据源，内存表排在最前，重复的时间戳保留内存表中的值。收集时超过单次查询上限则失败
+    let mut merged = MergedPoints::new();
+    merged.push_source(memtable_results.into_iter());
+    for index in 0..sstable_results.blocks.len() {
+        let scan = Arc::clone(&sstable_results);
+        merged.push_source((0..scan.blocks[index].len()).map(move |i| scan.blocks[index][i]));
+    }
+    let mut results: Vec<DataPoint> = Vec::new();
+    for (timestamp, value) in merged.by_ref() {
+        let bytes = (results.len() + 1).saturating_mul(point_bytes);
+        if self.query_memory.exceeds(bytes) {
+            tracing::warn!("系列 {} 的查询结果超过单次查询内存上限", series_key);
+            return Err(self.query_memory.too_large(bytes));
+        }
+        results.push(DataPoint { timestamp, value, tags: tags.clone() });
+    }
+
+    if merged.duplicates_resolved() > 0 {
+        tracing::debug!("🔄 去重: 丢弃 {} 个重复的数据点", merged.duplicates_resolved());
+    }
+    tracing::debug!("📊 最终查询结果: {} 个数据点", results.len());
+
+    let stats = QueryStats {
+        memtable_points,
+        sstable_points: results.len() - memtable_points,
+        files_scanned: sstable_results.files_scanned,
+        sources_merged: merged.sources_merged(),
+        duplicates_resolved: merged.duplicates_resolved(),
+    };
+    Ok(QueryResult { datapoints: results, stats, reservation })
+    }
+
+
+    // 在时间预算内流式读取范围数据，超时后返回已读出的部分和继续读取的起始时间戳。
+    // 每批至少读出 PARTIAL_CHECK_INTERVAL 个点再检查时间，保证分批读取总能前进
+    pub async fn query_range_within(
+        &self,
+        series_key: &str,
+        start_time: Option<u64>,
+        end_time: Option<u64>,
+        budget: Duration,
+    ) -> Result<PartialQuery> {
+        let deadline = Instant::now() + budget;
+        let (mut scan, tags) = self.scan_series_tagged(series_key, start_time, end_time)?;
+        // 读满预留的内存（单次查询上限或全局预算）时与超时一样返回部分结果，至少读出一批
+        let point_bytes = QueryMemory::point_bytes(&tags);
+        let reservation = self.query_memory.reserve(scan.estimated_len().saturating_mul(point_bytes)).await?;
+        let max_points = (reservation.bytes() / point_bytes).max(PARTIAL_CHECK_INTERVAL);
+
+        let cancellation = QueryCancellation::new();
+        let cancelled = cancellation.token();
+        let progress = Arc::clone(&self.query_progress);
+
+        self.blocking.run_sized(scan.estimated_len(), move || {
+            let mut datapoints = Vec::new();
+            loop {
+                // 发起方已断开时不再读取，结果不会被使用
+                if cancelled.is_cancelled() {
+                    progress.cancelled();
+                    return PartialQuery { datapoints, next_cursor: None, reservation };
+                }
+                let before = datapoints.len();
+                let batch = PARTIAL_CHECK_INTERVAL.min(max_points - before);
+                datapoints.extend(
+                    scan.by_ref()
+                        .take(batch)
+                        .map(|(timestamp, value)| DataPoint { timestamp, value, tags: tags.clone() }),
+                );
+                if datapoints.len() - before < batch {
+                    return PartialQuery { datapoints, next_cursor: None, reservation };
+                }
+                if Instant::now() >= deadline || datapoints.len() >= max_points {
+                    let next_cursor = datapoints.last().and_then(|dp| dp.timestamp.checked_add(1));
+                    return PartialQuery { datapoints, next_cursor, reservation };
+                }
+            }
+        })
+        .await
+    }
+
+    // 流式归并内存表和SSTable中的数据点，按时间顺序逐点解压
+    fn scan_series(&self, series_key: &str, start_time: Option<u64>, end_time: Option<u64>) -> Result<MergedPoints> {
+        self.scan_series_tagged(series_key, start_time, end_time).map(|(merged, _)| merged)
+    }
+
+    // 同时返回系列的标签
+    fn scan_series_tagged(
+        &self,
+        series_key: &str,
+        start_time: Option<u64>,
+        end_time: Option<u64>,
+    ) -> Result<(MergedPoints, BTreeMap<String, String>)> {
+        Self::check_time_range(start_time, end_time)?;
+        let _gate = self.replace_gate.read();
+        let mut merged = MergedPoints::new();
+
+        {
+            let mut points: Vec<(u64, f64)> = self
+                .memtables()
+                .query(series_key, start_time, end_time)
+                .into_iter()
+                .map(|dp| (dp.timestamp, dp.value))
+                .collect();
+            points.sort_by_key(|(ts, _)| *ts);
+            merged.push_source(points.into_iter());
+        }
+
+        let mut sstables = self.sstables.lock();
+        for sstable in self.locations.locate(&mut sstables, series_key) {
+            for block in sstable.series_blocks(series_key, start_time, end_time)? {
+                merged.push_block(block, start_time, end_time);
+            }
+        }
+
+        Ok((merged, self.series_tags(series_key).unwrap_or_default()))
+    }
+
+    // 按系列键顺序遍历所有系列，回调返回 ControlFlow::Break 时提前停止，返回访问过的系列数。
+    // 每个SSTable只读取一次，点在回调消费时才逐块解压
+    pub fn for_each_series<F>(&self, mut visit: F) -> Result<usize>
+    where
+        F: FnMut(&str, &SeriesMetadata, &mut MergedPoints) -> ControlFlow<()>,
+    {
+        let mut visited = 0;
+        for entry in self.series_walk()? {
+            let metadata = SeriesMetadata {
+                tags: self.series_tags(&entry.series_key).unwrap_or_default(),
+                first_seen: self.first_seen(&entry.series_key),
+                last_timestamp: self.last_timestamp(&entry.series_key),
+                blocks: entry.block_count(),
+            };
+            let series_key = entry.series_key.clone();
+            let mut points = entry.points();
+            visited += 1;
+            if visit(&series_key, &metadata, &mut points).is_break() {
+                break;
+            }
+        }
+        Ok(visited)
+    }
+
+    // 复制内存表，并读取每个SSTable的块列表（不解压）
+    fn series_walk(&self) -> Result<SeriesWalk> {
+        let memtable = {
+            let memtables = self.memtables();
+            memtables
+                .series()
+                .into_iter()
+                .map(|(series_key, points)| {
+                    let mut points: Vec<(u64, f64)> = points.iter().map(|dp| (dp.timestamp, dp.value)).collect();
+                    points.sort_by_key(|(ts, _)| *ts);
+                    (series_key.clone(), points)
+                })
+                .collect()
+        };
+
+        let mut walk = SeriesWalk::new(memtable);
+        let mut sstables = self.sstables.lock();
+        for sstable in sstables.iter_mut() {
+            walk.add_blocks(sstable.load_series_list()?);
+        }
+        Ok(walk)
+    }
+
+    // 按时间顺序把系列的每个点交给回调，边解压边回调，不生成完整的结果，返回回调的点数。
+    // 解压在调用方线程上进行，在异步任务中处理大范围时请放到 spawn_blocking 中调用
+    pub fn for_each_point(
+        &self,
+        series_key: &str,
+        start_time: Option<u64>,
+        end_time: Option<u64>,
+        mut f: impl FnMut(u64, f64),
+    ) -> Result<usize> {
+        let mut points = 0;
+        for (timestamp, value) in self.scan_series(series_key, start_time, end_time)? {
+            f(timestamp, value);
+            points += 1;
+        }
+        Ok(points)
+    }
+
+    // 扫描系列并消费归并后的数据点。点数超过内联阈值时在阻塞线程池中解压，大查询不占用异步工作线程
+    async fn consume_series<T, F>(
+        &self,
+        series_key: &str,
+        start_time: Option<u64>,
+        end_time: Option<u64>,
+        consume: F,
+    ) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(MergedPoints) -> T + Send + 'static,
+    {
+        let scan = self.scan_series(series_key, start_time, end_time)?;
+        self.blocking.run_sized(scan.estimated_len(), move || consume(scan)).await
+    }
+
+    // 查找数值穿越阈值的时间点，只输出穿越点及其前一个点
+    pub async fn find_crossings(
+        &self,
+        series_key: &str,
+        threshold: f64,
+        direction: CrossingDirection,
+        start_time: Option<u64>,
+        end_time: Option<u64>,
+    ) -> Result<Vec<Crossing>> {
+        let mut detector = CrossingDetector::new(threshold, direction);
+        self.consume_series(series_key, start_time, end_time, move |scan| {
+            scan.filter_map(|(ts, value)| detector.push(ts, value)).collect()
+        })
+        .await
+    }
+
+    // 查找相邻两点间隔超过预期采样间隔的缺失窗口
+    pub async fn find_gaps(
+        &self,
+        series_key: &str,
+        expected_interval: u64,
+        start_time: Option<u64>,
+        end_time: Option<u64>,
+    ) -> Result<Vec<Gap>> {
+        if expected_interval == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "expected_interval必须大于0",
+            ));
+        }
+
+        let mut detector = GapDetector::new(expected_interval);
+        self.consume_series(series_key, start_time, end_time, move |scan| {
+            scan.filter_map(|(ts, _)| detector.push(ts)).collect()
+        })
+        .await
+    }
+
+    // 相邻原始数据点之间的变化率 (v_i - v_{i-1}) / (t_i - t_{i-1})，单位为每个时间戳单位。
+    // 每个结果的时间戳为后一个点，时间戳相同的相邻点不产生结果
+    pub async fn derivative(
+        &self,
+        series_key: &str,
+        start_time: Option<u64>,
+        end_time: Option<u64>,
+    ) -> Result<Vec<(u64, f64)>> {
+        self.consume_series(series_key, start_time, end_time, |scan| {
+            let mut previous: Option<(u64, f64)> = None;
+            scan.filter_map(|(ts, value)| {
+                let (prev_ts, prev_value) = previous.replace((ts, value))?;
+                (ts > prev_ts).then(|| (ts, (value - prev_value) / (ts - prev_ts) as f64))
+            })
+            .collect()
+        })
+        .await
+    }
+
+    // 按梯形法计算范围内数值对时间的积分，面积单位为 值×时间戳单位。只计算范围内第一个点到最后一个点之间的面积，
+    // 范围两端到最近的点之间不插值；少于两个点时为0
+    pub async fn integral(&self, series_key: &str, start_time: Option<u64>, end_time: Option<u64>) -> Result<f64> {
+        self.consume_series(series_key, start_time, end_time, |scan| {
+            let mut previous: Option<(u64, f64)> = None;
+            scan.map(|(ts, value)| match previous.replace((ts, value)) {
+                Some((prev_ts, prev_value)) => (prev_value + value) / 2.0 * (ts - prev_ts) as f64,
+                None => 0.0,
+            })
+            .sum()
+        })
+        .await
+    }
+
+    // 一次查询多个时间窗口：只扫描一遍覆盖所有窗口的范围，逐点分配到包含它的窗口，
+    // 结果与分别查询每个窗口相同。function 为 None 时返回每个窗口内的点，否则返回聚合值
+    pub async fn query_windows(
+        &self,
+        series_key: &str,
+        windows: Vec<TimeWindow>,
+        function: Option<AggregateFn>,
+    ) -> Result<Vec<WindowResult>> {
+        if windows.is_empty() || windows.len() > MAX_QUERY_WINDOWS {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("时间窗口数量必须在 1 到 {} 之间，实际为 {}", MAX_QUERY_WINDOWS, windows.len()),
+            ));
+        }
+        for window in &windows {
+            Self::check_time_range(Some(window.start_time), Some(window.end_time))?;
+        }
+        let start_time = windows.iter().map(|window| window.start_time).min();
+        let end_time = windows.iter().map(|window| window.end_time).max();
+
+        // 返回原始点时按单次查询内存上限检查保存的点数
+        let query_memory = Arc::clone(&self.query_memory);
+        let point_bytes = std::mem::size_of::<WindowPoint>();
+        let mut collector = WindowCollector::new(windows, function);
+        self.consume_series(series_key, start_time, end_time, move |scan| {
+            for (timestamp, value) in scan {
+                collector.push(timestamp, value);
+                let bytes = collector.collected().saturating_mul(point_bytes);
+                if query_memory.exceeds(bytes) {
+                    return Err(query_memory.too_large(bytes));
+                }
+            }
+            Ok(collector.finish())
+        })
+        .await?
+    }
+
+    // 解压 locate_series_blocks 取出的块；块中的点数超过内联阈值时在阻塞线程池中解压
+    async fn query_sstables(
+        &self,
+        blocks: Vec<SeriesData>,
+        files_scanned: usize,
+        start_time: Option<u64>,
+        end_time: Option<u64>,
+    ) -> SSTableQueryResult {
+        self.sstable_scans.fetch_add(1, Ordering::Relaxed);
+        let points = blocks.iter().map(|block| block.count).sum();
+        let scratch_points = self.config.decode_scratch_points;
+        let counters = Arc::clone(&self.decode_scratch);
+        // 等待结果的future被丢弃（客户端断开连接）时取消，解压线程不再继续解压剩余的块。
+        // 合并查询中领头的调用被取消时，由等待者之一重新执行
+        let cancellation = QueryCancellation::new();
+        let cancelled = cancellation.token();
+        let progress = Arc::clone(&self.query_progress);
+        let observers = Arc::clone(&self.observers);
+
+        self.blocking
+            .run_sized(points, move || with_decode_scratch(scratch_points, |scratch| {
+                let in_range = |(ts, _): &(u64, f64)| {
+                    start_time.is_none_or(|start| *ts >= start) && end_time.is_none_or(|end| *ts <= end)
+                };
+                let mut results = Vec::new();
+                for block in blocks {
+                    if cancelled.is_cancelled() {
+                        progress.cancelled();
+                        tracing::debug!("查询已取消，停止解压系列 {} 的剩余块", block.series_key);
+                        break;
+                    }
+                    observers.notify("query_block", |observer| observer.on_query_block(&block.series_key));
+                    progress.block_decoded();
+                    // 整块解压到复用的缓冲区，只留下范围内的点，再按其点数一次分配结果
+                    let decoded = match scratch.decode(block.compressed_data, block.count, &counters, in_range) {
+                        Ok(decoded) => decoded,
+                        Err(e) => {
+                            tracing::warn!("跳过无法解码的块 {}: {}", block.series_key, e);
+                            continue;
+                        }
+                    };
+                    if !decoded.is_empty() {
+                        results.push(decoded.to_vec());
+                    }
+                }
+                Arc::new(SSTableScan { blocks: results, files_scanned })
+            }))
+            .await
+            .map_err(|e| format!("SSTable查询任务失败: {}", e))
+    }
+
+    // 系列在时间范围内数据点的写入时间，按事件时间戳索引，没有记录写入时间的数据点不在结果中。
+    // 重复的时间戳与范围查询一样以内存表和先读到的块为准
+    pub async fn query_ingest_times(&self, series_key: &str, start_time: Option<u64>, end_time: Option<u64>) -> Result<BTreeMap<u64, u64>> {
+        Self::check_time_range(start_time, end_time)?;
+        let in_range = move |ts: u64| start_time.is_none_or(|start| ts >= start) && end_time.is_none_or(|end| ts <= end);
+
+        let mut times: BTreeMap<u64, u64> = self.memtables().ingest_times(series_key);
+        times.retain(|ts, _| in_range(*ts));
+
+        let (blocks, _) = self.locate_series_blocks(series_key, start_time, end_time);
+        let blocks: Vec<SeriesData> = blocks.into_iter().filter(|block| !block.ingest_data.is_empty()).collect();
+        let points = blocks.iter().map(|block| block.count).sum();
+        let sstable_times = self
+            .blocking
+            .run_sized(points, move || {
+                blocks
+                    .iter()
+                    .map(|block| decode_ingest_times(&block.ingest_data))
+                    .collect::<Result<Vec<_>>>()
+            })
+            .await??;
+        for (ts, ingest) in sstable_times.into_iter().flatten().filter(|(ts, _)| in_range(*ts)) {
+            times.entry(ts).or_insert(ingest);
+        }
+        Ok(times)
+    }
+
+    // 按固定长度的时间窗口汇总系列的点数和首末时间戳，不返回数据点，客户端据此决定逐段加载哪些窗口。
+    // 完整落在一个窗口内的块只读元数据，只有跨窗口或时间范围重叠的块才解压
+    pub async fn series_chunks(&self, series_key: &str, start: u64, end: u64, chunk: u64) -> Result<Vec<ChunkSummary>> {
+        let windows = chunk_windows(start, end, chunk)?;
+        let memtable_timestamps: Vec<u64> =
+            self.memtables().query(series_key, Some(start), Some(end)).into_iter().map(|dp| dp.timestamp).collect();
+        let (blocks, _) = self.locate_series_blocks(series_key, Some(start), Some(end));
+        let points = blocks.iter().map(|block| block.count).sum();
+        self.blocking
+            .run_sized(points, move || summarize_chunks(windows, chunk, &blocks, &memtable_timestamps))
+            .await?
+    }
+
+    // 返回相交的系列块（不解压）和访问的文件数
+    fn locate_series_blocks(&self, series_key: &str, start_time: Option<u64>, end_time: Option<u64>) -> (Vec<SeriesData>, usize) {
+        let mut sstables = self.sstables.lock();
+        let total = sstables.len();
+        let located = self.locations.locate(&mut sstables, series_key);
+        let files_scanned = located.len();
+        tracing::debug!("🗄️ 检查 {}/{} 个SSTable文件", files_scanned, total);
+
+        let mut blocks = Vec::new();
+        for (i, sstable) in located.into_iter().enumerate() {
+            match sstable.series_blocks(series_key, start_time, end_time) {
+                Ok(found) => blocks.extend(found),
+                Err(e) => tracing::warn!("SSTable {} 查询失败: {}", i, e),
+            }
+        }
+        (blocks, files_scanned)
+    }
+
+    // 内存表和SSTable中的所有系列键，按字节序升序排列、没有重复。
+    // 相同数据下结果与调用次数、写入顺序和文件顺序无关；新写入的系列出现在其排序位置上，
+    // 已有系列的相对顺序不变，可以用系列键作为分页游标
+    pub async fn get_all_series(&self) -> Result<Vec<String>> {
+        let mut series_keys = std::collections::BTreeSet::new();
+
+        // 获取内存表中的系列
+        {
+            let memtables = self.memtables();
+            for memtable in memtables.tables() {
+                series_keys.extend(memtable.get_data().keys().cloned());
+            }
+        }
+
+        // 获取SSTable中的系列
+        {
+            let mut sstables = self.sstables.lock();
+            for sstable in sstables.iter_mut() {
+                match sstable.get_all_series_keys() {
+                    Ok(keys) => {
+                        for key in keys {
+                            series_keys.insert(key);
+                        }
+                    }
+                    Err(e) => {
+                        tracing::warn!("读取SSTable系列键失败: {}", e);
+                    }
+                }
+            }
+        }
+
+        Ok(series_keys.into_iter().collect())
+    }
 
-        let mut sstables = Vec::new();
-        if let Ok(entries) = std::fs::read_dir(&data_dir) {
-            // for entry in entries {
-            //     if let Ok(entry) = entry {
-            //         let path = entry.path();
-            //         if path.extension().and_then(|s| s.to_str()) == Some("data") {
-            //             sstables.push(SSTable::new(path)?);
-            //         }
-            //     }
-            // }
-            for entry in entries.flatten() {
-    let path = entry.path();
-    if path.extension().and_then(|s| s.to_str()) == Some("data") {
-        sstables.push(SSTable::new(path)?);
+    // 每个系列的标签，直接读manifest，按系列键排序
+    pub async fn get_series_tags(&self) -> Result<BTreeMap<String, BTreeMap<String, String>>> {
+        Ok(self.manifest.read().series_tags.clone())
     }
-}
-        }
 
-        Ok(Self {
-            memtable: Arc::new(RwLock::new(Memtable::new(memtable_threshold))),
-            sstables: Arc::new(Mutex::new(sstables)),
-            data_dir,
-            memtable_threshold,
-        })
+    // 标签全部匹配的系列键，按字节序升序排列
+    pub async fn find_series_by_tags(&self, matchers: &BTreeMap<String, String>) -> Result<Vec<String>> {
+        let series_tags = self.get_series_tags().await?;
+        Ok(series_tags
+            .into_iter()
+            .filter(|(_, tags)| matchers.iter().all(|(k, v)| tags.get(k) == Some(v)))
+            .map(|(key, _)| key)
+            .collect())
     }
 
-    pub async fn insert(&self, series_key: String, datapoint: DataPoint) -> Result<()> {
-        // 检查是否需要flush，在锁外进行
-        let should_flush = {
-            let mut memtable = self.memtable.write().unwrap();
-            memtable.insert(series_key, datapoint);
-            memtable.is_full()
-        };
+    // 最新时间戳早于 now - threshold_secs 的系列，按最新时间戳排序。
+    // 只读内存中的最新时间戳映射；按标签过滤时读取SSTable元数据，include_value时才解压
+    pub async fn stale_series(
+        &self,
+        threshold_secs: u64,
+        prefix: Option<&str>,
+        matchers: &BTreeMap<String, String>,
+        include_value: bool,
+    ) -> Result<Vec<StaleSeries>> {
+        let threshold = self.timestamp_precision().from_secs(threshold_secs);
+        let cutoff = self.now_timestamp().saturating_sub(threshold);
 
-        if should_flush {
-            self.flush_memtable().await?;
+        let mut stale: Vec<(String, u64)> = self
+            .last_timestamps
+            .snapshot()
+            .into_iter()
+            .filter(|(key, last)| *last < cutoff && prefix.is_none_or(|p| key.starts_with(p)))
+            .collect();
+
+        if !matchers.is_empty() {
+            let series_tags = self.get_series_tags().await?;
+            stale.retain(|(key, _)| {
+                series_tags
+                    .get(key)
+                    .is_some_and(|tags| matchers.iter().all(|(k, v)| tags.get(k) == Some(v)))
+            });
         }
+        stale.sort_by_key(|(key, last)| (*last, key.clone()));
 
-        Ok(()) // 修复：添加 () 参数
+        let mut result = Vec::with_capacity(stale.len());
+        for (series_key, last_timestamp) in stale {
+            let last_value = if include_value {
+                self.value_at(&series_key, last_timestamp)?
+            } else {
+                None
+            };
+            result.push(StaleSeries { series_key, last_timestamp, last_value });
+        }
+        Ok(result)
     }
 
-    pub async fn update(&self, series_key: &str, timestamp: u64, new_value: f64) -> Result<bool> {
-        // 首先尝试在内存表中更新
-        let updated_in_memtable = {
-            let mut memtable = self.memtable.write().unwrap();
-            memtable.update(series_key, timestamp, new_value)
-        };
-
-        if updated_in_memtable {
-            return Ok(true);
+    // 读取指定时间戳的值，内存表优先，SSTable只解压包含该时间戳的块
+    fn value_at(&self, series_key: &str, timestamp: u64) -> Result<Option<f64>> {
+        if let Some(dp) = self.memtables().query(series_key, Some(timestamp), Some(timestamp)).first() {
+            return Ok(Some(dp.value));
         }
 
-        // 在SSTable中查找并更新，避免跨await持有锁
-        let mut sstables = self.sstables.lock().unwrap();
-        for sstable in sstables.iter_mut() {
-            if sstable.update_datapoint(series_key, timestamp, new_value)? {
-                return Ok(true);
+        let mut sstables = self.sstables.lock();
+        for sstable in self.locations.locate(&mut sstables, series_key) {
+            if let Some(dp) = sstable.query_series(series_key, Some(timestamp), Some(timestamp))?.first() {
+                return Ok(Some(dp.value));
             }
         }
-        drop(sstables);
-
-        Ok(false)
+        Ok(None)
     }
 
-    pub async fn delete(&self, series_key: &str, timestamp: Option<u64>) -> Result<bool> {
-        // 首先尝试在内存表中删除
-        let deleted_from_memtable = {
-            let mut memtable = self.memtable.write().unwrap();
-            memtable.delete(series_key, timestamp)
-        };
+    // 跨系列聚合：每个时间桶内汇总所有匹配系列的值后再计算聚合函数（如所有主机CPU的p95）
+    pub async fn aggregate_across(
+        &self,
+        matchers: &BTreeMap<String, String>,
+        start_time: Option<u64>,
+        end_time: Option<u64>,
+        interval: u64,
+        function: AggregateFn,
+    ) -> Result<(Vec<String>, Vec<AggregateBucket>)> {
+        Self::check_interval(interval)?;
+        Self::check_time_range(start_time, end_time)?;
 
-        // 在SSTable中删除
-        let mut deleted_from_sstable = false;
-        {
-            let mut sstables = self.sstables.lock().unwrap();
-            
-            for sstable in sstables.iter_mut() {
-                if sstable.delete_datapoint(series_key, timestamp)? {
-                    deleted_from_sstable = true;
-                }
+        // 逐个系列流式扫描，内存只与桶的数量有关
+        let series_keys = self.find_series_by_tags(matchers).await?;
+        let mut buckets = CrossSeriesBuckets::new(function);
+        for series_key in &series_keys {
+            buckets = self
+                .consume_series(series_key, start_time, end_time, move |scan| {
+                    buckets.add_series(scan, interval);
+                    buckets
+                })
+                .await?;
+        }
+
+        Ok((series_keys, buckets.finish()))
+    }
+
+    // 对整个时间范围计算一个聚合值，流式扫描且不保留原始值（百分位除外），没有数据点时返回None
+    pub async fn aggregate_range(
+        &self,
+        series_key: &str,
+        start_time: Option<u64>,
+        end_time: Option<u64>,
+        function: AggregateFn,
+    ) -> Result<Option<f64>> {
+        Self::check_time_range(start_time, end_time)?;
+        self.consume_series(series_key, start_time, end_time, move |scan| {
+            let mut accumulator = BucketAccumulator::new(function);
+            for (_, value) in scan {
+                accumulator.push(value);
             }
+            accumulator.finish()
+        })
+        .await
+    }
 
-            // 清理空的SSTable文件
-            let mut indices_to_remove = Vec::new();
-            for (index, sstable) in sstables.iter_mut().enumerate() {
-                match sstable.get_all_series_keys() {
-                    Ok(keys) => {
-                        if keys.is_empty() {
-                            indices_to_remove.push(index);
+    // 单系列按时间桶降采样，流式扫描数据点，一次只保留当前桶的聚合状态
+    pub async fn downsample(
+        &self,
+        series_key: &str,
+        start_time: Option<u64>,
+        end_time: Option<u64>,
+        interval: u64,
+        function: AggregateFn,
+        fill: FillPolicy,
+    ) -> Result<Vec<DownsampleBucket>> {
+        Self::check_interval(interval)?;
+
+        let buckets = self
+            .consume_series(series_key, start_time, end_time, move |scan| {
+                let finish = |ts: u64, accumulator: BucketAccumulator| DownsampleBucket {
+                    timestamp: ts,
+                    count: accumulator.count(),
+                    value: accumulator.finish(),
+                };
+                let mut buckets = Vec::new();
+                let mut current: Option<(u64, BucketAccumulator)> = None;
+                for (timestamp, value) in scan {
+                    let bucket_ts = bucket_start(timestamp, interval);
+                    if current.as_ref().is_none_or(|(ts, _)| *ts != bucket_ts) {
+                        if let Some((ts, accumulator)) = current.replace((bucket_ts, BucketAccumulator::new(function))) {
+                            buckets.push(finish(ts, accumulator));
                         }
                     }
-                    Err(_) => {
-                        // 保留无法读取的文件
+                    current.as_mut().unwrap().1.push(value);
+                }
+                if let Some((ts, accumulator)) = current {
+                    buckets.push(finish(ts, accumulator));
+                }
+                buckets
+            })
+            .await?;
+
+        Self::fill_buckets(buckets, start_time, end_time, interval, fill)
+    }
+
+    // 度量下的系列：系列键以 measurement 开头，或 measurement 标签等于该值
+    pub async fn measurement_series(&self, measurement: &str) -> Result<Vec<String>> {
+        Ok(self
+            .get_series_tags()
+            .await?
+            .into_iter()
+            .filter(|(key, tags)| {
+                in_measurement(key, measurement) || tags.get(MEASUREMENT_TAG).is_some_and(|m| m == measurement)
+            })
+            .map(|(key, _)| key)
+            .collect())
+    }
+
+    // 对度量下的每个系列分别降采样。各系列并发计算，同时进行的系列数不超过执行池的线程数，
+    // 系列很多时不会一次占用大量查询内存
+    pub async fn aggregate_measurement(
+        &self,
+        measurement: &str,
+        start_time: Option<u64>,
+        end_time: Option<u64>,
+        interval: u64,
+        function: AggregateFn,
+        fill: FillPolicy,
+    ) -> Result<BTreeMap<String, Vec<DownsampleBucket>>> {
+        Self::check_interval(interval)?;
+        Self::check_time_range(start_time, end_time)?;
+
+        let concurrency = self.blocking.threads();
+        let mut tasks = tokio::task::JoinSet::new();
+        let mut results = BTreeMap::new();
+        for series_key in self.measurement_series(measurement).await? {
+            if tasks.len() >= concurrency {
+                if let Some(joined) = tasks.join_next().await {
+                    let (series_key, buckets) = joined.map_err(std::io::Error::other)?;
+                    results.insert(series_key, buckets?);
+                }
+            }
+            let db = self.clone();
+            tasks.spawn(async move {
+                let buckets = db.downsample(&series_key, start_time, end_time, interval, function, fill).await;
+                (series_key, buckets)
+            });
+        }
+
+        while let Some(joined) = tasks.join_next().await {
+            let (series_key, buckets) = joined.map_err(std::io::Error::other)?;
+            results.insert(series_key, buckets?);
+        }
+        Ok(results)
+    }
+
+    // 包络降采样，每个桶单次遍历计算 min/max/avg/first/last
+    pub async fn downsample_envelope(
+        &self,
+        series_key: &str,
+        start_time: Option<u64>,
+        end_time: Option<u64>,
+        interval: u64,
+        fill: FillPolicy,
+    ) -> Result<Vec<EnvelopeBucket>> {
+        Self::check_interval(interval)?;
+
+        let buckets = self
+            .consume_series(series_key, start_time, end_time, move |scan| {
+                let mut buckets: Vec<EnvelopeBucket> = Vec::new();
+                for (timestamp, value) in scan {
+                    let bucket_ts = bucket_start(timestamp, interval);
+                    if buckets.last().is_none_or(|bucket| bucket.bucket_ts != bucket_ts) {
+                        buckets.push(EnvelopeBucket::empty(bucket_ts));
                     }
+                    buckets.last_mut().unwrap().push(value);
                 }
+                buckets
+            })
+            .await?;
+
+        Self::fill_buckets(buckets, start_time, end_time, interval, fill)
+    }
+
+    // 将范围内的数值统计为累积直方图，流式扫描不保留原始数据
+    pub async fn histogram(
+        &self,
+        series_key: &str,
+        start_time: Option<u64>,
+        end_time: Option<u64>,
+        bounds: Vec<f64>,
+    ) -> Result<CumulativeHistogram> {
+        let mut histogram = CumulativeHistogram::new(bounds)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        self.consume_series(series_key, start_time, end_time, move |scan| {
+            for (_, value) in scan {
+                histogram.observe(value);
             }
-            
-            // 从后往前删除，避免索引错乱
-            for &index in indices_to_remove.iter().rev() {
-                sstables.remove(index);
+            histogram
+        })
+        .await
+    }
+
+    // 上下界可以相等（只查单个时间戳），颠倒时报错而不是返回空结果
+    fn check_time_range(start_time: Option<u64>, end_time: Option<u64>) -> Result<()> {
+        match (start_time, end_time) {
+            (Some(start_time), Some(end_time)) if end_time < start_time => {
+                Err(DbError::InvalidTimeRange { start_time, end_time }.into())
             }
+            _ => Ok(()),
         }
+    }
 
-        Ok(deleted_from_memtable || deleted_from_sstable)
+    fn check_interval(interval: u64) -> Result<()> {
+        if interval == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "interval必须大于0",
+            ));
+        }
+        Ok(())
     }
 
-    pub async fn query_range(&self, series_key: &str, start_time: Option<u64>, end_time: Option<u64>) -> Result<Vec<DataPoint>> {
-    let mut results = Vec::new();
+    fn fill_buckets<B: TimeBucket>(
+        buckets: Vec<B>,
+        start_time: Option<u64>,
+        end_time: Option<u64>,
+        interval: u64,
+        fill: FillPolicy,
+    ) -> Result<Vec<B>> {
+        fill.apply(
+            buckets,
+            start_time.map(|start| bucket_start(start, interval)),
+            end_time.map(|end| bucket_start(end, interval)),
+            interval,
+        )
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))
+    }
 
-    // 查询内存表
-    {
-        let memtable = self.memtable.read().unwrap();
-        let memtable_results = memtable.query(series_key, start_time, end_time);
-        println!("🔍 内存表查询: {} 个数据点", memtable_results.len());
-        results.extend(memtable_results);
+    // 生成新的SSTable路径，文件名带递增的序号，启动时按序号恢复文件顺序。
+    // 新文件写入可用空间最多的数据目录
+    fn next_sstable_path(&self, prefix: &str) -> PathBuf {
+        self.next_sstable_path_in(self.data_dirs.pick_for_write(), prefix)
     }
 
-    // 查询SSTable文件
-    {
-        let mut sstables = self.sstables.lock().unwrap();
-        println!("🗄️ 检查 {} 个SSTable文件", sstables.len());
-        
-        for (i, sstable) in sstables.iter_mut().enumerate() {
-            match sstable.query_series(series_key, start_time, end_time) {
-                Ok(sstable_results) => {
-                    println!("  SSTable {}: {} 个数据点", i, sstable_results.len());
-                    results.extend(sstable_results);
-                }
-                Err(e) => {
-                    println!("  SSTable {} 查询失败: {}", i, e);
-                    continue;
-                }
+    // 文件名在所有数据目录中唯一
+    fn next_sstable_path_in(&self, dir: &Path, prefix: &str) -> PathBuf {
+        let timestamp = self.clock.now_secs();
+        loop {
+            let seq = self.next_sequence.fetch_add(1, Ordering::Relaxed);
+            let file_name = format!("{}_{}.{:06}.data", prefix, timestamp, seq);
+            if self.data_dirs.all().iter().all(|dir| !dir.join(&file_name).exists()) {
+                return dir.join(file_name);
             }
         }
     }
 
-    // 按时间戳排序并去重
-    results.sort_by_key(|dp| dp.timestamp);
-    let before_dedup = results.len();
-    results.dedup_by_key(|dp| dp.timestamp);
-    
-    if before_dedup != results.len() {
-        println!("🔄 去重: {} -> {} 个数据点", before_dedup, results.len());
+    // 按序号插入SSTable列表，保持运行中的顺序与重启后一致
+    fn add_sstable(sstables: &mut Vec<SSTable>, sstable: SSTable) {
+        let key = sstable.order_key();
+        let index = sstables.partition_point(|existing| existing.order_key() <= key);
+        sstables.insert(index, sstable);
     }
-    
-    println!("📊 最终查询结果: {} 个数据点", results.len());
-    Ok(results)
+
+    // 将一个系列的数据点按 MAX_POINTS_PER_BLOCK 切分并压缩为多个块，
+    // 每个块有独立的时间范围，范围查询只需解压相交的块
+    fn encode_series_blocks(series_key: String, points: &[(u64, f64)], ingest_times: Option<&BTreeMap<u64, u64>>) -> Vec<SeriesData> {
+        points
+            .chunks(MAX_POINTS_PER_BLOCK)
+            .map(|chunk| Self::encode_series(series_key.clone(), chunk, ingest_times))
+            .collect()
     }
 
+    // 将一段数据点压缩为一个SeriesData块。标签保存在manifest中，块内不再写入
+    fn encode_series(series_key: String, points: &[(u64, f64)], ingest_times: Option<&BTreeMap<u64, u64>>) -> SeriesData {
+        let min_timestamp = points.iter().map(|(ts, _)| *ts).min().unwrap_or(u64::MAX);
+        let max_timestamp = points.iter().map(|(ts, _)| *ts).max().unwrap_or(0);
+        let ingest: Vec<(u64, u64)> = ingest_times.map_or_else(Vec::new, |times| {
+            points.iter().filter_map(|(ts, _)| times.get(ts).map(|ingest| (*ts, *ingest))).collect()
+        });
 
-    pub async fn get_all_series(&self) -> Result<Vec<String>> {
-        let mut series_keys = std::collections::HashSet::new();
+        SeriesData {
+            series_key,
+            compressed_data: encode_block_v2(points),
+            tags: BTreeMap::new(),
+            min_timestamp,
+            max_timestamp,
+            count: points.len(),
+            ingest_data: encode_ingest_times(&ingest),
+        }
+    }
 
-        // 获取内存表中的系列
-        {
-            let memtable = self.memtable.read().unwrap();
-            for key in memtable.get_data().keys() {
-                series_keys.insert(key.clone());
+    // 按系列合并数据点并排序去重后写入新的SSTable
+    fn write_merged_sstable(
+        &self,
+        prefix: &str,
+        merged: BTreeMap<String, Vec<DataPoint>>,
+        ingest_times: &BTreeMap<String, BTreeMap<u64, u64>>,
+    ) -> Result<Option<SSTable>> {
+        let mut series_data_list = Vec::new();
+        for (series_key, mut datapoints) in merged {
+            datapoints.sort_by_key(|dp| dp.timestamp);
+            datapoints.dedup_by_key(|dp| dp.timestamp);
+            if datapoints.is_empty() {
+                continue;
             }
+            let times = ingest_times.get(&series_key);
+            let points: Vec<(u64, f64)> = datapoints.iter().map(|dp| (dp.timestamp, dp.value)).collect();
+            series_data_list.extend(Self::encode_series_blocks(series_key, &points, times));
         }
 
-        // 获取SSTable中的系列
-        {
-            let mut sstables = self.sstables.lock().unwrap();
-            for sstable in sstables.iter_mut() {
-                match sstable.get_all_series_keys() {
-                    Ok(keys) => {
-                        for key in keys {
-                            series_keys.insert(key);
-                        }
-                    }
-                    Err(e) => {
-                        tracing::warn!("读取SSTable系列键失败: {}", e);
-                    }
-                }
-            }
+        if series_data_list.is_empty() {
+            return Ok(None);
         }
 
-        Ok(series_keys.into_iter().collect())
+        let mut sstable = SSTable::new(self.next_sstable_path(prefix))?
+            .with_compression(self.config.compaction_compression)
+            .with_encoding(self.config.sstable_encoding)
+            .with_mapping_cache(Arc::clone(&self.mappings));
+        sstable.write_data(&series_data_list)?;
+        Ok(Some(sstable))
     }
 
-    async fn flush_memtable(&self) -> Result<()> {
-        // 获取数据并清空内存表，确保锁不跨越await
-        let data = {
-            let mut memtable = self.memtable.write().unwrap();
-            let data = memtable.get_data().clone();
-            memtable.clear();
-            data
-        };
+    // 最近一次写入内存表的序号，从0开始，重启后重新计数
+    pub fn write_sequence(&self) -> u64 {
+        self.write_sequence.load(Ordering::Relaxed)
+    }
+
+    // 已刷新到SSTable的最大写入序号
+    pub fn durable_sequence(&self) -> u64 {
+        *self.durable_sequence.borrow()
+    }
+
+    // 每次刷新完成后收到新的 durable_sequence
+    pub fn subscribe_durable(&self) -> tokio::sync::watch::Receiver<u64> {
+        self.durable_sequence.subscribe()
+    }
 
-        if data.is_empty() {
-            return Ok(()); // 修复：这里就是第196行，需要添加 () 参数
+    // 等待序号不大于 sequence 的写入落盘。尚未落盘时立即刷新内存表，
+    // 并发的等待者排队在同一次刷新之后，已被覆盖的直接返回
+    pub async fn wait_durable(&self, sequence: u64) -> Result<()> {
+        if self.durable_sequence() >= sequence {
+            return Ok(());
         }
+        self.flush_memtable_covering(Some(sequence)).await
+    }
 
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
-        
-        let sstable_path = self.data_dir.join(format!("sstable_{}.data", timestamp));
-        let mut sstable = SSTable::new(sstable_path)?;
+    fn mark_durable(&self, covered: u64) {
+        self.durable_sequence.send_if_modified(|durable| {
+            let advanced = covered > *durable;
+            *durable = (*durable).max(covered);
+            advanced
+        });
+    }
 
-        let mut series_data_list = Vec::new();
-        
-        for (series_key, datapoints) in data {
-            if datapoints.is_empty() {
-                continue;
-            }
+    // 当前内存表和正在刷新的内存表。先取当前内存表的读锁，取走内存表时两者在同一个写锁内交换
+    fn memtables(&self) -> MemtableView<'_> {
+        let active = self.memtable.read();
+        let flushing = self.flushing_memtable.read().clone();
+        MemtableView::new(flushing, active)
+    }
+
+    async fn flush_memtable(&self) -> Result<()> {
+        self.flush_memtable_covering(None).await
+    }
 
-            let mut compressor = GorillaCompressor::new();
-            let mut min_timestamp = u64::MAX;
-            let mut max_timestamp = 0u64;
-            let mut tags = BTreeMap::new();
+    // sequence 为Some时，拿到刷新锁后发现已被之前的刷新覆盖则不再刷新
+    async fn flush_memtable_covering(&self, sequence: Option<u64>) -> Result<()> {
+        let flush_guard = self.flush_lock.lock().await;
+        if sequence.is_some_and(|sequence| self.durable_sequence() >= sequence) {
+            return Ok(());
+        }
 
-            for datapoint in &datapoints {
-                compressor.compress_datapoint(datapoint.timestamp, datapoint.value);
-                min_timestamp = min_timestamp.min(datapoint.timestamp);
-                max_timestamp = max_timestamp.max(datapoint.timestamp);
-                
-                if tags.is_empty() {
-                    tags = datapoint.tags.clone();
-                }
+        // 取走内存表，确保锁不跨越await。取走的内存表在新SSTable加入之前对查询仍然可见
+        let (frozen, covered) = {
+            let mut memtable = self.memtable.write();
+            if memtable.size() == 0 {
+                // 之前的写入已刷新或被删除
+                self.mark_durable(self.write_sequence.load(Ordering::Relaxed));
+                return Ok(()); // 修复：这里就是第196行，需要添加 () 参数
             }
-            
-            let compressed_data = compressor.finish();
-            
-            let series_data = SeriesData {
-                series_key,
-                compressed_data,
-                tags,
-                min_timestamp,
-                max_timestamp,
-                count: datapoints.len(),
-            };
+            let frozen = Arc::new(memtable.freeze());
+            *self.flushing_memtable.write() = Some(Arc::clone(&frozen));
+            (frozen, self.write_sequence.load(Ordering::Relaxed))
+        };
+        *self.last_flush.lock() = Some(self.clock.now());
 
-            series_data_list.push(series_data);
-        }
+        let points = frozen.size();
+        let sstable_path = self.next_sstable_path("sstable");
+        let written = match self.persist_series_meta() {
+            // 压缩和写文件在阻塞线程池中执行。新块不带标签，先保存manifest中的系列标签
+            Ok(()) => {
+                let path = sstable_path.clone();
+                let compression = self.config.flush_compression;
+                let encoding = self.config.sstable_encoding;
+                let mappings = Arc::clone(&self.mappings);
+                let data = Arc::clone(&frozen);
+                let observers = Arc::clone(&self.observers);
+                self.blocking
+                    .run_sized(points, move || {
+                        observers.notify("flush_writing", |observer| observer.on_flush_writing(points));
+                        Self::write_flushed_sstable(path, &data, compression, encoding, mappings)
+                    })
+                    .await
+                    .and_then(|result| result)
+            }
+            Err(e) => Err(e),
+        };
+        let (sstable, blocks) = match written {
+            Ok(written) => written,
+            Err(e) => {
+                // 数据放回内存表，下次刷新重试
+                let mut memtable = self.memtable.write();
+                memtable.restore(&frozen);
+                *self.flushing_memtable.write() = None;
+                return Err(e);
+            }
+        };
 
-        sstable.write_data(&series_data_list)?;
-        
-        // 添加新的SSTable，锁的作用域很小
+        // 添加新的SSTable之后再移除刷新的内存表，锁的作用域很小
         {
-            let mut sstables = self.sstables.lock().unwrap();
-            sstables.push(sstable);
+            let mut sstables = self.sstables.lock();
+            self.locations.add_file(&sstable.file_name(), blocks.iter().map(|(series_key, _)| series_key.as_str()));
+            Self::add_sstable(&mut sstables, sstable);
+            self.overlap.invalidate();
+            self.record_sstable_meta(&sstables);
+            *self.flushing_memtable.write() = None;
         }
+        self.mark_durable(covered);
+        drop(flush_guard);
+        let block_keys: Vec<String> = if self.quota.is_stale() {
+            self.refresh_quota_usage().await?;
+            blocks.into_iter().map(|(series_key, _)| series_key).collect()
+        } else {
+            blocks
+                .into_iter()
+                .map(|(series_key, usage)| {
+                    self.quota.record_flushed(&series_key, usage.points, usage);
+                    series_key
+                })
+                .collect()
+        };
+
+        tracing::info!("内存表已刷新到SSTable，包含 {} 个系列块", block_keys.len());
+        let event = FlushEvent {
+            sstable_path,
+            series_blocks: block_keys.len(),
+            points,
+        };
+        self.observers.notify("flush_completed", |observer| observer.on_flush_completed(&event));
 
-        tracing::info!("内存表已刷新到SSTable，包含 {} 个系列", series_data_list.len());
+        self.enforce_quotas().await?;
+        self.enforce_size_limit().await?;
 
         Ok(()) // 修复：添加 () 参数
     }
 
-    pub async fn compact(&self) -> Result<()> {
+    // 压缩内存表数据写入新的SSTable，返回文件和每个块的系列键及用量
+    fn write_flushed_sstable(
+        path: PathBuf,
+        memtable: &Memtable,
+        compression: BlockCompression,
+        encoding: SSTableEncoding,
+        mappings: Arc<MappingCache>,
+    ) -> Result<(SSTable, Vec<(String, QuotaUsage)>)> {
+        let series_data_list: Vec<SeriesData> = memtable
+            .get_data()
+            .iter()
+            .filter(|(_, datapoints)| !datapoints.is_empty())
+            .flat_map(|(series_key, datapoints)| {
+                // 块内按时间排序，流式扫描依赖块内有序。标签保存在manifest中，这里只取时间戳和值
+                let mut points: Vec<(u64, f64)> = datapoints.iter().map(|dp| (dp.timestamp, dp.value)).collect();
+                points.sort_by_key(|(ts, _)| *ts);
+                let times = memtable.ingest_times().get(series_key);
+                Self::encode_series_blocks(series_key.clone(), &points, times)
+            })
+            .collect();
+
+        let mut sstable = SSTable::new(path)?
+            .with_compression(compression)
+            .with_encoding(encoding)
+            .with_mapping_cache(mappings);
+        sstable.write_data(&series_data_list)?;
+        let blocks = series_data_list
+            .into_iter()
+            .map(|series| {
+                let usage = QuotaUsage {
+                    points: series.count as u64,
+                    bytes: series.compressed_data.len() as u64,
+                };
+                (series.series_key, usage)
+            })
+            .collect();
+        Ok((sstable, blocks))
+    }
+
+    pub async fn compact(&self) -> Result<CompactionReport> {
+        self.compact_with(&CompactionOptions::default()).await
+    }
+
+    // 只合并一个系列分散在各SSTable中的块，其他系列的块保留在原文件中，只含其他系列的文件不会被改写
+    pub async fn compact_series(&self, series_key: &str) -> Result<CompactionReport> {
+        self.compact_with(&CompactionOptions::series(series_key)).await
+    }
+
+    // 最近执行过的compaction报告，最新的在前。跳过的执行不记录
+    pub fn compaction_history(&self) -> Vec<CompactionReport> {
+        self.compaction_history.lock().iter().rev().cloned().collect()
+    }
+
+    fn record_compaction(&self, report: &CompactionReport) {
+        let mut history = self.compaction_history.lock();
+        if history.len() >= COMPACTION_HISTORY_LIMIT {
+            history.pop_front();
+        }
+        history.push_back(report.clone());
+    }
+
+    // 按条件执行compaction。指定时间窗口或系列时只有包含相应块的SSTable参与，
+    // 且只合并其中与窗口相交（属于该系列）的系列块，其余块保留在原文件中。
+    // 与校验、导出共用维护许可，有维护任务在执行时等待其结束
+    pub async fn compact_with(&self, options: &CompactionOptions) -> Result<CompactionReport> {
+        let _permit = Arc::clone(&self.maintenance).acquire_owned().await.map_err(std::io::Error::other)?;
+        let operation = self.operations.start(OperationKind::Compact, self.clock.now_secs());
+        self.observers.notify("compaction_started", |observer| observer.on_compaction_started(options));
+        let db = self.clone();
+        let compaction_options = options.clone();
+        let report = self.blocking.run(move || db.run_compaction(&compaction_options, &operation)).await??;
+        self.observers.notify("compaction_finished", |observer| observer.on_compaction_finished(&report));
+        if report.skipped_reason.is_none() {
+            // 合并去重后重新统计配额用量
+            self.refresh_quota_usage().await?;
+            self.enforce_size_limit().await?;
+        }
+        Ok(report)
+    }
+
+    fn run_compaction(&self, options: &CompactionOptions, operation: &OperationHandle) -> Result<CompactionReport> {
         tracing::info!("开始执行compaction操作");
-        
-        // 获取所有SSTable数据，避免长时间持有锁
-        let all_series_data = {
-            let mut sstables = self.sstables.lock().unwrap();
-            
-            if sstables.len() < 2 {
-                tracing::info!("SSTable数量不足，跳过compaction");
-                return Ok(()); // 修复：添加 () 参数
+        let started = Instant::now();
+        let started_at = self.clock.now_secs();
+
+        // compaction需要临时的额外空间，临界水位下暂停
+        if self.disk.level() >= DiskLevel::Critical {
+            tracing::warn!("磁盘空间低于临界水位线，暂停compaction");
+            return Ok(CompactionReport {
+                skipped_reason: Some("磁盘空间低于临界水位线".to_string()),
+                ..CompactionReport::default()
+            });
+        }
+
+        // 最新数据点不早于该时间的文件属于热数据，保持原样
+        let hot_horizon = self
+            .config
+            .hot_window_secs
+            .map(|secs| self.now_timestamp().saturating_sub(self.timestamp_precision().from_secs(secs)));
+
+        let mut sstables = self.sstables.lock();
+
+        // 写入新文件之前都可以取消，此时没有修改任何文件
+        let cancelled = || {
+            tracing::info!("compaction已取消（操作 {}）", operation.id());
+            Ok(CompactionReport {
+                skipped_reason: Some(format!("已取消（操作 {}）", operation.id())),
+                ..CompactionReport::default()
+            })
+        };
+
+        // 选出参与合并的文件，以及每个文件中需要合并/保留的系列块
+        let mut candidates = Vec::new();
+        let mut hot_files_skipped = 0;
+        operation.begin_stage("selecting", sstables.len());
+        for (index, sstable) in sstables.iter_mut().enumerate() {
+            if operation.is_cancelled() {
+                return cancelled();
+            }
+            operation.file_done();
+            if !options.includes_file(&sstable.file_name()) {
+                continue;
+            }
+            let series_list = match sstable.load_series_list() {
+                Ok(list) => list,
+                Err(e) => {
+                    tracing::warn!("读取SSTable失败: {}", e);
+                    self.observers.sstable_read_failed(sstable.file_path(), &e);
+                    continue;
+                }
+            };
+            let file_max = series_list.iter().map(|series| series.max_timestamp).max();
+            if hot_horizon.is_some_and(|horizon| file_max.is_some_and(|max| max >= horizon)) {
+                hot_files_skipped += 1;
+                continue;
+            }
+            let series_count = series_list
+                .iter()
+                .map(|series| series.series_key.as_str())
+                .collect::<std::collections::HashSet<_>>()
+                .len();
+            let input = CompactionInput {
+                file_name: sstable.file_name(),
+                size_bytes: sstable.file_size(),
+                series_count,
+            };
+            let (selected, retained): (Vec<SeriesData>, Vec<SeriesData>) = series_list
+                .into_iter()
+                .partition(|series| options.selects(series));
+            if !selected.is_empty() {
+                candidates.push((index, input, selected, retained));
             }
+        }
 
-            let mut all_series_data = BTreeMap::new();
+        // 指定了文件时（按删除比例触发）单个文件同样改写，以回收已删除的点占用的空间
+        if candidates.is_empty() || (candidates.len() < 2 && options.files.is_none()) {
+            tracing::info!("SSTable数量不足，跳过compaction");
+            return Ok(CompactionReport {
+                skipped_reason: Some("参与合并的SSTable少于2个".to_string()),
+                hot_files_skipped,
+                ..CompactionReport::default()
+            });
+        }
 
-            // 读取所有SSTable中的数据
-            for sstable in sstables.iter_mut() {
-                match sstable.get_all_series_keys() {
-                    Ok(series_keys) => {
-                        for series_key in series_keys {
-                            match sstable.query_series(&series_key, None, None) {
-                                Ok(datapoints) => {
-                                    let entry = all_series_data.entry(series_key).or_insert_with(Vec::new);
-                                    entry.extend(datapoints);
-                                }
-                                Err(e) => {
-                                    tracing::warn!("读取系列数据失败: {}", e);
-                                }
-                            }
-                        }
+        let mut report = CompactionReport {
+            started_at,
+            hot_files_skipped,
+            ..CompactionReport::default()
+        };
+        let mut merged: BTreeMap<String, Vec<DataPoint>> = BTreeMap::new();
+        // 与数据点去重一样，同一时间戳保留最先读到的写入时间
+        let mut ingest_times: BTreeMap<String, BTreeMap<u64, u64>> = BTreeMap::new();
+        operation.begin_stage("merging", candidates.len());
+        let mut scratch = DecodeScratch::new();
+        for (_, input, selected, _) in candidates.iter_mut() {
+            if operation.is_cancelled() {
+                return cancelled();
+            }
+            operation.file_done();
+            report.inputs.push(input.clone());
+            // 合并后不再需要原来的块，块数据直接交给解码器
+            for series in std::mem::take(selected) {
+                let points = scratch.decode(series.compressed_data, series.count, &self.decode_scratch, |_| true)?;
+                report.points_merged += points.len();
+                merged.entry(series.series_key.clone()).or_default().extend(
+                    points.iter().map(|&(timestamp, value)| DataPoint {
+                        timestamp,
+                        value,
+                        tags: series.tags.clone(),
+                    }),
+                );
+                if !series.ingest_data.is_empty() {
+                    let times = ingest_times.entry(series.series_key.clone()).or_default();
+                    for (timestamp, ingest) in decode_ingest_times(&series.ingest_data)? {
+                        times.entry(timestamp).or_insert(ingest);
                     }
-                    Err(e) => {
-                        tracing::warn!("读取SSTable失败: {}", e);
+                }
+            }
+        }
+
+        // 先写入新文件，再处理旧文件，避免中途失败丢数据
+        report.series_merged = merged.len();
+        let mut points_written = 0;
+        for datapoints in merged.values_mut() {
+            datapoints.sort_by_key(|dp| dp.timestamp);
+            datapoints.dedup_by_key(|dp| dp.timestamp);
+            points_written += datapoints.len();
+        }
+        report.duplicates_removed = report.points_merged - points_written;
+        let resolution_changes = if options.is_windowed() {
+            // 窗口compaction只看到系列的一部分块，不降低分辨率
+            Vec::new()
+        } else {
+            self.downsample_merged(&mut merged, &mut ingest_times)
+        };
+        report.points_downsampled = points_written - merged.values().map(Vec::len).sum::<usize>();
+        let merged_keys: Vec<String> = merged.keys().cloned().collect();
+        let prefix = if options.series_key.is_some() {
+            "compacted_series"
+        } else if options.is_windowed() {
+            "compacted_window"
+        } else {
+            "compacted"
+        };
+        if operation.is_cancelled() {
+            return cancelled();
+        }
+        operation.begin_stage("writing", candidates.len());
+        let new_sstable = self.write_merged_sstable(prefix, merged, &ingest_times)?;
+
+        // 降采样的分辨率范围在新文件生效之前保存。保存失败时删除新文件，旧文件保持不变，
+        // 否则重启后会有降采样过的数据却没有对应的分辨率记录
+        if !resolution_changes.is_empty() {
+            let previous: Vec<(String, Option<Vec<ResolutionSegment>>)> = {
+                let mut manifest = self.manifest.write();
+                let previous = resolution_changes
+                    .iter()
+                    .map(|(series_key, _)| (series_key.clone(), manifest.series_resolution.get(series_key).cloned()))
+                    .collect();
+                for (series_key, segment) in resolution_changes {
+                    record_segment(manifest.series_resolution.entry(series_key).or_default(), segment);
+                }
+                previous
+            };
+            self.series_meta_dirty.store(true, Ordering::Relaxed);
+            if let Err(e) = self.persist_series_meta() {
+                tracing::error!("保存降采样范围失败，放弃本次compaction: {}", e);
+                let mut manifest = self.manifest.write();
+                for (series_key, segments) in previous {
+                    match segments {
+                        Some(segments) => manifest.series_resolution.insert(series_key, segments),
+                        None => manifest.series_resolution.remove(&series_key),
+                    };
+                }
+                drop(manifest);
+                if let Some(sstable) = &new_sstable {
+                    if let Err(e) = sstable.delete_file() {
+                        tracing::warn!("删除未生效的SSTable文件失败: {}", e);
                     }
                 }
+                return Err(e);
             }
+        }
 
-            // 删除旧的SSTable文件
-            for sstable in sstables.iter() {
+        if let Some(sstable) = &new_sstable {
+            let output = CompactionOutput {
+                file_name: sstable.file_name(),
+                size_bytes: sstable.file_size(),
+            };
+            report.bytes_rewritten += output.size_bytes;
+            report.output = Some(output);
+        }
+
+        let mut removed_indices = Vec::new();
+        for (index, _, _, mut retained) in candidates {
+            operation.file_done();
+            let sstable = &mut sstables[index];
+            if retained.is_empty() {
                 if let Err(e) = sstable.delete_file() {
                     tracing::warn!("删除旧SSTable文件失败: {}", e);
                 }
+                removed_indices.push(index);
+            } else {
+                // 重写时顺带去掉旧格式块中的标签
+                for block in retained.iter_mut() {
+                    block.tags.clear();
+                }
+                sstable.write_data(&retained)?;
+                sstable.reset_deleted_points();
+                report.bytes_rewritten += sstable.file_size();
             }
-            sstables.clear();
-
-            all_series_data
-        };
+        }
+        for index in removed_indices.into_iter().rev() {
+            let sstable = sstables.remove(index);
+            self.locations.remove_file(&sstable.file_name());
+        }
+        if let Some(sstable) = new_sstable {
+            self.locations.add_file(&sstable.file_name(), merged_keys.iter().map(String::as_str));
+            Self::add_sstable(&mut sstables, sstable);
+        }
+        self.overlap.invalidate();
+        self.record_sstable_meta(&sstables);
 
-        // 创建新的compacted SSTable
-        if !all_series_data.is_empty() {
-            let timestamp = SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_secs();
-            
-            let sstable_path = self.data_dir.join(format!("compacted_{}.data", timestamp));
-            let mut new_sstable = SSTable::new(sstable_path)?;
+        drop(sstables);
 
-            let mut series_data_list = Vec::new();
+        report.duration_ms = started.elapsed().as_millis() as u64;
+        tracing::info!(
+            "Compaction完成，合并了 {} 个文件中的 {} 个系列，{} 个数据点（去重 {} 个），耗时 {} ms",
+            report.inputs.len(),
+            report.series_merged,
+            report.points_merged,
+            report.duplicates_removed,
+            report.duration_ms
+        );
+        self.record_compaction(&report);
 
-            for (series_key, mut datapoints) in all_series_data {
-                // 按时间戳排序并去重
-                datapoints.sort_by_key(|dp| dp.timestamp);
-                datapoints.dedup_by_key(|dp| dp.timestamp);
+        Ok(report)
+    }
 
-                if datapoints.is_empty() {
-                    continue;
-                }
+    // 按降采样策略聚合合并结果中足够旧的数据，返回每个系列新降采样的范围。
+    // 只处理上次降采样范围之后、早于年龄阈值的数据，重复执行不会再次聚合已聚合的点，阈值之后的数据保持不变
+    fn downsample_merged(
+        &self,
+        merged: &mut BTreeMap<String, Vec<DataPoint>>,
+        ingest_times: &mut BTreeMap<String, BTreeMap<u64, u64>>,
+    ) -> Vec<(String, ResolutionSegment)> {
+        let manifest = self.manifest.read();
+        if manifest.downsampling.is_empty() {
+            return Vec::new();
+        }
+        let precision = self.timestamp_precision();
+        let now = self.now_timestamp();
+        let mut changes = Vec::new();
+        for (series_key, datapoints) in merged.iter_mut() {
+            let tags = manifest.series_tags.get(series_key).cloned().unwrap_or_default();
+            let Some(rule) = manifest.downsampling.rule_for(series_key, &tags) else {
+                continue;
+            };
+            let interval = precision.from_secs(rule.interval_secs).max(1);
+            let before = bucket_start(now.saturating_sub(precision.from_secs(rule.after_secs)), interval);
+            let from = manifest
+                .series_resolution
+                .get(series_key)
+                .and_then(|segments| segments.last())
+                .map_or(0, |segment| segment.end);
+            if before <= from {
+                continue;
+            }
+            *datapoints = downsample_points(std::mem::take(datapoints), from, before, interval, rule.function);
+            if let Some(times) = ingest_times.get_mut(series_key) {
+                times.retain(|timestamp, _| !(from..before).contains(timestamp));
+            }
+            changes.push((
+                series_key.clone(),
+                ResolutionSegment { start: from, end: before, interval, function: rule.function },
+            ));
+        }
+        changes
+    }
 
-                let mut compressor = GorillaCompressor::new();
-                let mut min_timestamp = u64::MAX;
-                let mut max_timestamp = 0u64;
-                let mut tags = BTreeMap::new();
+    // 只读取内存中的计数和SSTable元数据摘要的统计，不访问数据文件，适合频繁刷新的看板
+    pub fn stats_summary(&self) -> StatsSummary {
+        let now = self.clock.now();
+        let now_secs = self.clock.now_secs();
+        let mut counts: BTreeMap<String, u64> = BTreeMap::new();
+        let mut notes = Vec::new();
 
-                for datapoint in &datapoints {
-                    compressor.compress_datapoint(datapoint.timestamp, datapoint.value);
-                    min_timestamp = min_timestamp.min(datapoint.timestamp);
-                    max_timestamp = max_timestamp.max(datapoint.timestamp);
-                    
-                    if tags.is_empty() {
-                        tags = datapoint.tags.clone();
-                    }
+        let memtable_points = {
+            let memtables = self.memtables();
+            for memtable in memtables.tables() {
+                for (series_key, points) in memtable.get_data() {
+                    *counts.entry(series_key.clone()).or_default() += points.len() as u64;
                 }
-
-                let compressed_data = compressor.finish();
-                
-                let series_data = SeriesData {
-                    series_key,
-                    compressed_data,
-                    tags,
-                    min_timestamp,
-                    max_timestamp,
-                    count: datapoints.len(),
-                };
-
-                series_data_list.push(series_data);
             }
+            memtables.tables().map(Memtable::size).sum()
+        };
 
-            new_sstable.write_data(&series_data_list)?;
-            
-            {
-                let mut sstables = self.sstables.lock().unwrap();
-                sstables.push(new_sstable);
+        let (sstable_count, mut total_points, total_compressed_bytes, sstables_updated_at, unreadable) = {
+            let sstables = self.sstables.lock();
+            let mut total_points = 0u64;
+            let mut total_bytes = 0u64;
+            let mut updated_at: Option<u64> = None;
+            let mut unreadable = 0;
+            for info in sstables.iter().map(SSTable::info) {
+                total_points += info.total_points as u64;
+                total_bytes += info.size_bytes;
+                updated_at = updated_at.max(info.created_at);
+                if info.read_error.is_some() {
+                    unreadable += 1;
+                }
+                for (series_key, points) in &info.series_points {
+                    *counts.entry(series_key.clone()).or_default() += *points as u64;
+                }
             }
+            (sstables.len(), total_points, total_bytes, updated_at, unreadable)
+        };
+        total_points += memtable_points as u64;
 
-            tracing::info!("Compaction完成，合并了 {} 个系列", series_data_list.len());
+        if sstable_count + usize::from(memtable_points > 0) > 1 {
+            notes.push("点数按各SSTable和内存表累加，同一时间戳在compaction合并之前可能被重复计数".to_string());
+        }
+        if unreadable > 0 {
+            notes.push(format!("{} 个SSTable无法读取元数据，未计入统计", unreadable));
         }
 
-        Ok(()) // 修复：添加 () 参数
+        let day_start = now
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|since_epoch| since_epoch.as_secs() / 86400 * 86400)
+            .unwrap_or_default();
+        let today = self
+            .config
+            .timestamp_precision
+            .from_duration(Duration::from_secs(day_start));
+        let (total_series, new_series_today) = {
+            let manifest = self.manifest.read();
+            let first_seen = &manifest.series_first_seen;
+            (first_seen.len(), first_seen.values().filter(|first_seen| **first_seen >= today).count())
+        };
+
+        StatsSummary {
+            computed_at: now_secs,
+            total_series,
+            total_points,
+            memtable_points,
+            total_compressed_bytes,
+            sstable_count,
+            ingested_points: IngestedPoints::from_rate(&self.ingest_rate, now_secs),
+            new_series_today,
+            top_series: top_series(counts, SUMMARY_TOP_SERIES),
+            sstables_updated_at,
+            notes,
+        }
     }
 
     pub async fn get_stats(&self) -> Result<DatabaseStats> {
-        let memtable_size = {
-            let memtable = self.memtable.read().unwrap();
-            memtable.get_data().len()
+        let (memtable_size, memtable_bytes, memtable_tag_sets) = {
+            let memtables = self.memtables();
+            let bytes = memtables.tables().map(Memtable::estimated_bytes).sum();
+            (memtables.series().len(), bytes, memtables.tables().map(Memtable::tag_sets).sum())
         };
 
         let sstable_count = {
-            let sstables = self.sstables.lock().unwrap();
+            let sstables = self.sstables.lock();
             sstables.len()
         };
 
         let all_series = self.get_all_series().await?;
         let total_series = all_series.len();
 
+        if let Err(e) = self.disk.refresh() {
+            tracing::warn!("检查磁盘空间失败: {}", e);
+        }
+
         Ok(DatabaseStats {
             memtable_size,
+            memtable_bytes,
+            memtable_tag_sets,
             sstable_count,
             total_series,
+            oldest_memtable_age_secs: self.oldest_memtable_age().map(|age| age.as_secs()),
+            max_memtable_age_secs: self.config.max_memtable_age.map(|age| age.as_secs()),
+            disk_free_bytes: self.disk.free_bytes(),
+            disk_level: self.disk.level(),
+            tag_drift_counts: self.tag_drift.drift_counts(),
+            sstable_scans: self.sstable_scans.load(Ordering::Relaxed),
+            coalesced_queries: self.sstable_queries.shared_count(),
+            total_bytes: self.total_sstable_bytes(),
+            max_total_bytes: self.config.max_total_bytes,
+            location_index_warm: self.locations.is_warm(),
+            location_index_hits: self.locations.hits(),
+            location_index_misses: self.locations.misses(),
+            sstable_files_visited: self.locations.files_visited(),
+            events: self.observers.metrics(),
+            blocking: self.blocking.stats(),
+            mappings: self.mappings.stats(),
+            query_memory: self.query_memory.stats(),
+            decode_buffers: self.decode_scratch.snapshot(),
+            query_progress: self.query_progress.snapshot(),
+            data_dirs: self.data_dir_usage(),
+            background_tasks: self.background_task_status(),
         })
     }
 }
 
+// 标记系列所属度量的标签名
+pub const MEASUREMENT_TAG: &str = "measurement";
+// 系列键中度量名之后的分隔符，cpu 包含 cpu.load、cpu_host1，不包含 cpu2_host
+const MEASUREMENT_SEPARATORS: &[char] = &['.', '_', ':'];
+
+// 系列键等于度量名，或以度量名加分隔符开头
+fn in_measurement(series_key: &str, measurement: &str) -> bool {
+    series_key
+        .strip_prefix(measurement)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with(MEASUREMENT_SEPARATORS))
+}
+
+// 限时查询每读出这么多个点检查一次是否超时
+const PARTIAL_CHECK_INTERVAL: usize = 1024;
+// 重新统计配额用量时，文件列表在读取期间变化的最大重试次数
+const QUOTA_REFRESH_ATTEMPTS: usize = 3;
+
+// 限时查询的结果，next_cursor 不为空表示超时返回了部分数据，
+// 以它作为 start_time 继续查询即可读取剩余数据
+#[derive(Debug)]
+pub struct PartialQuery {
+    pub datapoints: Vec<DataPoint>,
+    pub next_cursor: Option<u64>,
+    // 为结果预留的查询内存，丢弃时归还
+    pub reservation: QueryReservation,
+}
+
+// 范围查询的结果，reservation 为结果预留的查询内存，在结果写出之前保留即可让它一直计入全局预算
+#[derive(Debug)]
+pub struct QueryResult {
+    pub datapoints: Vec<DataPoint>,
+    pub stats: QueryStats,
+    pub reservation: QueryReservation,
+}
+
+// 预压缩块的写入结果
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct BlockIngestReport {
+    pub series_key: String,
+    pub points: usize,
+    pub min_timestamp: u64,
+    pub max_timestamp: u64,
+    // 块所在的新SSTable文件名
+    pub sstable: String,
+}
+
+// 批量更新的结果，同一个点重复出现时只计一次 updated 或 not_found
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize)]
+pub struct BatchUpdateReport {
+    pub total: usize,
+    pub updated: usize,
+    // 不存在的点，不会被创建
+    pub not_found: usize,
+    pub files_rewritten: usize,
+}
+
 #[derive(Debug, serde::Serialize)]
 pub struct DatabaseStats {
     pub memtable_size: usize,
+    // 内存表占用的内存估算，以及数据点共享的不同标签集合数
+    pub memtable_bytes: usize,
+    pub memtable_tag_sets: usize,
     pub sstable_count: usize,
     pub total_series: usize,
+    pub oldest_memtable_age_secs: Option<u64>,
+    pub max_memtable_age_secs: Option<u64>,
+    pub disk_free_bytes: Option<u64>,
+    pub disk_level: DiskLevel,
+    // 每个系列检测到的标签漂移次数
+    pub tag_drift_counts: BTreeMap<String, u64>,
+    // 实际执行的SSTable查询次数，以及通过合并省掉的次数
+    pub sstable_scans: u64,
+    pub coalesced_queries: u64,
+    // SSTable文件总字节数及其上限
+    pub total_bytes: u64,
+    pub max_total_bytes: Option<u64>,
+    // 系列位置索引：是否完整、命中/退回全量扫描的次数，以及按系列访问的SSTable文件总数
+    pub location_index_warm: bool,
+    pub location_index_hits: u64,
+    pub location_index_misses: u64,
+    pub sstable_files_visited: u64,
+    // 引擎事件计数
+    pub events: EngineMetrics,
+    // 阻塞线程池的配置与内联/转交执行的次数
+    pub blocking: BlockingPoolStats,
+    // SSTable内存映射缓存的容量、当前映射数和累计映射/淘汰次数
+    pub mappings: MappingStats,
+    // 范围查询结果的内存上限、当前预留量、排队和超限失败的查询数
+    pub query_memory: QueryMemoryStats,
+    // 解压缓冲区直接复用和需要重新分配的次数
+    pub decode_buffers: DecodeScratchStats,
+    // SSTable查询解压的块数，以及发起方断开（future被丢弃）后提前结束的查询数
+    pub query_progress: QueryProgressStats,
+    // 每个数据目录的用量，主数据目录在最前
+    pub data_dirs: Vec<DataDirUsage>,
+    // 每个定期后台任务的运行情况
+    pub background_tasks: BTreeMap<String, TaskStatus>,
 }
 
 impl Clone for TimeSeriesDB {
     fn clone(&self) -> Self {
         Self {
             memtable: Arc::clone(&self.memtable),
+            flushing_memtable: Arc::clone(&self.flushing_memtable),
             sstables: Arc::clone(&self.sstables),
             data_dir: self.data_dir.clone(),
-            memtable_threshold: self.memtable_threshold,
+            data_dirs: Arc::clone(&self.data_dirs),
+            config: Arc::clone(&self.config),
+            clock: Arc::clone(&self.clock),
+            disk: Arc::clone(&self.disk),
+            manifest: Arc::clone(&self.manifest),
+            tag_drift: Arc::clone(&self.tag_drift),
+            last_timestamps: Arc::clone(&self.last_timestamps),
+            locations: Arc::clone(&self.locations),
+            series_meta_dirty: Arc::clone(&self.series_meta_dirty),
+            sstable_queries: Arc::clone(&self.sstable_queries),
+            sstable_scans: Arc::clone(&self.sstable_scans),
+            ingest_rate: Arc::clone(&self.ingest_rate),
+            compaction_history: Arc::clone(&self.compaction_history),
+            deleted_compaction_running: Arc::clone(&self.deleted_compaction_running),
+            observers: Arc::clone(&self.observers),
+            last_flush: Arc::clone(&self.last_flush),
+            warmup: Arc::clone(&self.warmup),
+            next_sequence: Arc::clone(&self.next_sequence),
+            blocking: Arc::clone(&self.blocking),
+            mappings: Arc::clone(&self.mappings),
+            query_memory: Arc::clone(&self.query_memory),
+            decode_scratch: Arc::clone(&self.decode_scratch),
+            query_progress: Arc::clone(&self.query_progress),
+            annotations: Arc::clone(&self.annotations),
+            quota: Arc::clone(&self.quota),
+            operations: Arc::clone(&self.operations),
+            maintenance: Arc::clone(&self.maintenance),
+            changes: Arc::clone(&self.changes),
+            overlap: Arc::clone(&self.overlap),
+            write_sequence: Arc::clone(&self.write_sequence),
+            durable_sequence: Arc::clone(&self.durable_sequence),
+            flush_lock: Arc::clone(&self.flush_lock),
+            replace_gate: Arc::clone(&self.replace_gate),
+            replace_generation: Arc::clone(&self.replace_generation),
+            shutdown: self.shutdown.clone(),
+            background_tasks: Arc::clone(&self.background_tasks),
+            dir_lock: Arc::clone(&self.dir_lock),
         }
     }
 }
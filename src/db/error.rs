@@ -0,0 +1,141 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use super::{QuotaUsage, ValidationError};
+
+// 引擎的类型化错误，通过 io::Error 传递以保持现有接口不变，
+// 调用方可以用 DbError::from_io 取回具体类型
+#[derive(Debug, Clone, PartialEq)]
+pub enum DbError {
+    // 磁盘剩余空间低于水位线，拒绝写入
+    DiskFull { free_bytes: u64, watermark_bytes: u64 },
+    // 写入的标签与系列已有标签不一致（reject模式）
+    TagDrift {
+        series_key: String,
+        expected: BTreeMap<String, String>,
+        actual: BTreeMap<String, String>,
+    },
+    // 查询或删除的时间范围上下界颠倒
+    InvalidTimeRange { start_time: u64, end_time: u64 },
+    // 导入要求空数据库
+    DatabaseNotEmpty { series_count: usize, sstable_count: usize },
+    // 写入会使前缀的存储用量超过配额（reject模式）
+    QuotaExceeded {
+        prefix: String,
+        usage: QuotaUsage,
+        max_points: Option<u64>,
+        max_bytes: Option<u64>,
+    },
+    // 系列受删除保护，未指定 force
+    SeriesProtected { series_key: String },
+    // 移动SSTable的目标不是已配置的数据目录
+    UnknownDataDir { path: PathBuf },
+    // 移动SSTable期间文件被删除、compaction等改写
+    SSTableModified { file_name: String },
+    // 查询结果的预估内存超过单次查询上限
+    ResultTooLarge { estimated_bytes: usize, limit_bytes: usize },
+    // 系统时钟早于Unix纪元，无法得到写入时间
+    ClockBeforeEpoch { behind_secs: u64 },
+    // 写入的数据点不满足校验规则
+    InvalidDataPoint { series_key: String, error: ValidationError },
+    // 新建的注释不满足校验规则
+    InvalidAnnotation { error: ValidationError },
+    // 同一时间只能执行一个校验等维护任务
+    MaintenanceBusy { operation_id: Option<u64> },
+}
+
+impl DbError {
+    pub fn kind(&self) -> io::ErrorKind {
+        match self {
+            Self::DiskFull { .. } => io::ErrorKind::StorageFull,
+            Self::TagDrift { .. } => io::ErrorKind::InvalidInput,
+            Self::InvalidTimeRange { .. } => io::ErrorKind::InvalidInput,
+            Self::DatabaseNotEmpty { .. } => io::ErrorKind::AlreadyExists,
+            Self::QuotaExceeded { .. } => io::ErrorKind::QuotaExceeded,
+            Self::SeriesProtected { .. } => io::ErrorKind::PermissionDenied,
+            Self::UnknownDataDir { .. } => io::ErrorKind::InvalidInput,
+            Self::SSTableModified { .. } => io::ErrorKind::ResourceBusy,
+            Self::ResultTooLarge { .. } => io::ErrorKind::OutOfMemory,
+            Self::ClockBeforeEpoch { .. } => io::ErrorKind::Other,
+            Self::InvalidDataPoint { .. } => io::ErrorKind::InvalidInput,
+            Self::InvalidAnnotation { .. } => io::ErrorKind::InvalidInput,
+            Self::MaintenanceBusy { .. } => io::ErrorKind::ResourceBusy,
+        }
+    }
+
+    pub fn from_io(error: &io::Error) -> Option<&DbError> {
+        error.get_ref().and_then(|e| e.downcast_ref::<DbError>())
+    }
+}
+
+impl fmt::Display for DbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DiskFull { free_bytes, watermark_bytes } => write!(
+                f,
+                "磁盘空间不足: 剩余 {} 字节，低于水位线 {} 字节",
+                free_bytes, watermark_bytes
+            ),
+            Self::TagDrift { series_key, expected, actual } => write!(
+                f,
+                "系列 {} 的标签与已有标签不一致: 已有 {:?}，写入 {:?}",
+                series_key, expected, actual
+            ),
+            Self::InvalidTimeRange { start_time, end_time } => write!(
+                f,
+                "时间范围无效: end_time {} 早于 start_time {}，请检查参数是否颠倒",
+                end_time, start_time
+            ),
+            Self::DatabaseNotEmpty { series_count, sstable_count } => write!(
+                f,
+                "数据库不为空: 已有 {} 个系列、{} 个SSTable文件，覆盖导入需要指定 force=true",
+                series_count, sstable_count
+            ),
+            Self::QuotaExceeded { prefix, usage, max_points, max_bytes } => write!(
+                f,
+                "超出前缀 \"{}\" 的存储配额: 已用 {} 个点、{} 字节，上限 {} 个点、{} 字节",
+                prefix,
+                usage.points,
+                usage.bytes,
+                max_points.map_or("不限".to_string(), |v| v.to_string()),
+                max_bytes.map_or("不限".to_string(), |v| v.to_string())
+            ),
+            Self::SeriesProtected { series_key } => write!(
+                f,
+                "系列 {} 受删除保护，需要确认后才能删除",
+                series_key
+            ),
+            Self::UnknownDataDir { path } => write!(f, "{:?} 不是已配置的数据目录", path),
+            Self::SSTableModified { file_name } => write!(
+                f,
+                "SSTable {} 在移动期间被改写，请重试",
+                file_name
+            ),
+            Self::ResultTooLarge { estimated_bytes, limit_bytes } => write!(
+                f,
+                "查询结果过大: 已超过 {} 字节，单次查询上限 {} 字节。请缩小时间范围，\
+                 或改用 max_wait_ms 分页查询（按 next_cursor 继续）、降采样/聚合等逐点处理的接口，导出全部数据请使用 /api/v1/admin/export",
+                estimated_bytes, limit_bytes
+            ),
+            Self::ClockBeforeEpoch { behind_secs } => write!(
+                f,
+                "系统时钟早于Unix纪元 {} 秒，请检查服务器时间",
+                behind_secs
+            ),
+            Self::InvalidDataPoint { series_key, error } => write!(f, "系列 {} 的数据点无效: {}", series_key, error),
+            Self::InvalidAnnotation { error } => write!(f, "注释无效: {}", error),
+            Self::MaintenanceBusy { operation_id: Some(id) } => write!(f, "已有维护任务在执行: 操作 {}", id),
+            Self::MaintenanceBusy { operation_id: None } => write!(f, "已有维护任务在执行"),
+        }
+    }
+}
+
+impl std::error::Error for DbError {}
+
+impl From<DbError> for io::Error {
+    fn from(error: DbError) -> Self {
+        io::Error::new(error.kind(), error)
+    }
+}
@@ -0,0 +1,448 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{Error, ErrorKind, Result};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use super::compression::format::{check_version, BLOCK_FORMAT_V1};
+use super::SeriesData;
+
+// SSTable文件格式
+//   v1: 无文件头，整个文件是 bincode(Vec<SeriesData>)
+//   v2: "TSST" + 版本号(u16 LE) + bincode(Vec<Vec<u8>>)，每个元素是一个 bincode(SeriesData) 块
+//   v3: 同 v2，块的 compressed_data 以块编码版本字节开头。读取 v1/v2 文件时补上 v2 版本字节，内存中的块总是带版本字节
+//   v4: 块末尾追加写入时间流 ingest_data。读取更早版本的文件时写入时间流为空
+// v2起每个块单独带长度，以后的版本只在块末尾追加字段：旧的读取器解码已知字段、忽略块内多余的字节，
+// 新的读取器按文件版本号选择块的结构
+//   整体压缩: "TSSC" + 版本号(u16 LE) + 压缩算法(u8) + 压缩后的 bincode(Vec<Vec<u8>>)，解压后与同版本的未压缩文件相同
+//   带字段编号的块: "TSSF" + 版本号(u16 LE) + 压缩算法(u8) + （压缩后的）bincode(Vec<Vec<u8>>)，每个元素是一个
+//   TaggedCodec 编码的块。版本号的含义与上面相同，块结构的变化由字段编号表示
+pub const SSTABLE_MAGIC: &[u8; 4] = b"TSST";
+pub const SSTABLE_COMPRESSED_MAGIC: &[u8; 4] = b"TSSC";
+pub const SSTABLE_TAGGED_MAGIC: &[u8; 4] = b"TSSF";
+pub const SSTABLE_FORMAT_VERSION: u16 = 4;
+
+// 块开始带版本字节的SSTable格式版本
+const VERSIONED_BLOCKS_SINCE: u16 = 3;
+// 块带写入时间流的SSTable格式版本
+const INGEST_DATA_SINCE: u16 = 4;
+
+const HEADER_LEN: usize = SSTABLE_MAGIC.len() + 2;
+const COMPRESSED_HEADER_LEN: usize = HEADER_LEN + 1;
+const ZSTD_LEVEL: i32 = 3;
+// LZ4每个字节的压缩数据最多解压出约255字节
+const LZ4_MAX_RATIO: usize = 255;
+
+// 在Gorilla编码之外对整个文件的块数据再做一次通用压缩，适合归档的冷数据文件
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BlockCompression {
+    #[default]
+    None,
+    Lz4,
+    Zstd,
+}
+
+impl BlockCompression {
+    fn code(self) -> u8 {
+        match self {
+            Self::None => 0,
+            Self::Lz4 => 1,
+            Self::Zstd => 2,
+        }
+    }
+
+    fn from_code(code: u8) -> Result<Self> {
+        match code {
+            0 => Ok(Self::None),
+            1 => Ok(Self::Lz4),
+            2 => Ok(Self::Zstd),
+            _ => Err(Error::new(ErrorKind::InvalidData, format!("未知的SSTable压缩算法: {}", code))),
+        }
+    }
+
+    fn compress(self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Self::None => Ok(data.to_vec()),
+            Self::Lz4 => Ok(lz4_flex::compress_prepend_size(data)),
+            Self::Zstd => zstd::encode_all(data, ZSTD_LEVEL),
+        }
+    }
+
+    fn decompress(self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Self::None => Ok(data.to_vec()),
+            Self::Lz4 => {
+                // 解压前按文件头声明的大小分配内存，损坏的文件头可能声明一个远超压缩比上限的大小
+                let declared = data.get(..4).map_or(0, |len| u32::from_le_bytes(len.try_into().unwrap()) as usize);
+                if declared > data.len().saturating_mul(LZ4_MAX_RATIO) {
+                    return Err(Error::new(ErrorKind::InvalidData, format!("LZ4解压后的大小 {} 超出压缩比上限", declared)));
+                }
+                lz4_flex::decompress_size_prepended(data).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+            }
+            Self::Zstd => zstd::decode_all(data).map_err(|e| Error::new(ErrorKind::InvalidData, e)),
+        }
+    }
+}
+
+impl FromStr for BlockCompression {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(Self::None),
+            "lz4" => Ok(Self::Lz4),
+            "zstd" => Ok(Self::Zstd),
+            other => Err(format!("不支持的压缩算法: {}，可选 none/lz4/zstd", other)),
+        }
+    }
+}
+
+impl fmt::Display for BlockCompression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Self::None => "none",
+            Self::Lz4 => "lz4",
+            Self::Zstd => "zstd",
+        };
+        f.write_str(name)
+    }
+}
+
+// 文件使用的通用压缩算法，未压缩或无法识别的文件头视为 None
+pub fn block_compression(data: &[u8]) -> BlockCompression {
+    if data.len() >= COMPRESSED_HEADER_LEN && has_compression_byte(data) {
+        BlockCompression::from_code(data[HEADER_LEN]).unwrap_or_default()
+    } else {
+        BlockCompression::None
+    }
+}
+
+// 文件的格式版本，空文件视为当前版本
+pub fn format_version(data: &[u8]) -> u16 {
+    if data.len() >= HEADER_LEN && [SSTABLE_MAGIC, SSTABLE_COMPRESSED_MAGIC, SSTABLE_TAGGED_MAGIC].iter().any(|magic| data.starts_with(*magic)) {
+        u16::from_le_bytes([data[4], data[5]])
+    } else if data.is_empty() {
+        SSTABLE_FORMAT_VERSION
+    } else {
+        1
+    }
+}
+
+// 块的序列化方式。bincode 按字段顺序编码，块结构只能在末尾追加字段，且需要按文件版本号选择结构；
+// tagged 的每个字段都带编号和长度，读取时跳过不认识的字段，缺少的可选字段取默认值
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SSTableEncoding {
+    #[default]
+    Bincode,
+    Tagged,
+}
+
+impl SSTableEncoding {
+    pub fn codec(self) -> &'static dyn SeriesCodec {
+        match self {
+            Self::Bincode => &BincodeCodec,
+            Self::Tagged => &TaggedCodec,
+        }
+    }
+}
+
+impl FromStr for SSTableEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "bincode" => Ok(Self::Bincode),
+            "tagged" => Ok(Self::Tagged),
+            other => Err(format!("不支持的SSTable序列化方式: {}，可选 bincode/tagged", other)),
+        }
+    }
+}
+
+impl fmt::Display for SSTableEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Self::Bincode => "bincode",
+            Self::Tagged => "tagged",
+        };
+        f.write_str(name)
+    }
+}
+
+// 单个系列块的序列化，version 为所在文件的格式版本
+pub trait SeriesCodec: Send + Sync {
+    fn encode(&self, series: &SeriesData) -> Result<Vec<u8>>;
+    fn decode(&self, block: &[u8], version: u16) -> Result<SeriesData>;
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BincodeCodec;
+
+impl SeriesCodec for BincodeCodec {
+    fn encode(&self, series: &SeriesData) -> Result<Vec<u8>> {
+        bincode::serialize(series).map_err(Error::other)
+    }
+
+    fn decode(&self, block: &[u8], version: u16) -> Result<SeriesData> {
+        if version < INGEST_DATA_SINCE {
+            bincode::deserialize::<LegacySeriesData>(block).map(SeriesData::from)
+        } else {
+            bincode::deserialize(block)
+        }
+        .map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+}
+
+// 带字段编号的块：每个字段为 编号(u8) + 长度(u32 LE) + 内容，整数为 u64 LE，
+// 标签为若干个 键长度(u32 LE) + 键 + 值长度(u32 LE) + 值。新增字段使用新的编号，旧的读取器直接跳过
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TaggedCodec;
+
+const FIELD_SERIES_KEY: u8 = 1;
+const FIELD_COMPRESSED_DATA: u8 = 2;
+const FIELD_TAGS: u8 = 3;
+const FIELD_MIN_TIMESTAMP: u8 = 4;
+const FIELD_MAX_TIMESTAMP: u8 = 5;
+const FIELD_COUNT: u8 = 6;
+const FIELD_INGEST_DATA: u8 = 7;
+
+impl TaggedCodec {
+    // 追加一个字段，供写入新字段和测试构造其他版本的块使用
+    pub fn push_field(block: &mut Vec<u8>, field: u8, value: &[u8]) {
+        block.push(field);
+        block.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        block.extend_from_slice(value);
+    }
+}
+
+impl SeriesCodec for TaggedCodec {
+    fn encode(&self, series: &SeriesData) -> Result<Vec<u8>> {
+        let mut tags = Vec::new();
+        for (key, value) in &series.tags {
+            for part in [key, value] {
+                tags.extend_from_slice(&(part.len() as u32).to_le_bytes());
+                tags.extend_from_slice(part.as_bytes());
+            }
+        }
+        let mut block = Vec::with_capacity(series.compressed_data.len() + series.ingest_data.len() + 64);
+        Self::push_field(&mut block, FIELD_SERIES_KEY, series.series_key.as_bytes());
+        Self::push_field(&mut block, FIELD_COMPRESSED_DATA, &series.compressed_data);
+        if !tags.is_empty() {
+            Self::push_field(&mut block, FIELD_TAGS, &tags);
+        }
+        Self::push_field(&mut block, FIELD_MIN_TIMESTAMP, &series.min_timestamp.to_le_bytes());
+        Self::push_field(&mut block, FIELD_MAX_TIMESTAMP, &series.max_timestamp.to_le_bytes());
+        Self::push_field(&mut block, FIELD_COUNT, &(series.count as u64).to_le_bytes());
+        if !series.ingest_data.is_empty() {
+            Self::push_field(&mut block, FIELD_INGEST_DATA, &series.ingest_data);
+        }
+        Ok(block)
+    }
+
+    fn decode(&self, block: &[u8], _version: u16) -> Result<SeriesData> {
+        let invalid = |message: String| Error::new(ErrorKind::InvalidData, message);
+        let mut series_key = None;
+        let mut compressed_data = None;
+        let mut tags = BTreeMap::new();
+        let mut min_timestamp = None;
+        let mut max_timestamp = None;
+        let mut count = None;
+        let mut ingest_data = Vec::new();
+
+        let mut rest = block;
+        while !rest.is_empty() {
+            let (field, value, remaining) = split_field(rest).ok_or_else(|| invalid("块字段不完整".to_string()))?;
+            rest = remaining;
+            match field {
+                FIELD_SERIES_KEY => {
+                    let key = std::str::from_utf8(value).map_err(|e| invalid(format!("系列键不是有效的UTF-8: {}", e)))?;
+                    series_key = Some(key.to_string());
+                }
+                FIELD_COMPRESSED_DATA => compressed_data = Some(value.to_vec()),
+                FIELD_TAGS => tags = decode_tags(value).ok_or_else(|| invalid("块标签字段无效".to_string()))?,
+                FIELD_MIN_TIMESTAMP => min_timestamp = Some(decode_u64(field, value)?),
+                FIELD_MAX_TIMESTAMP => max_timestamp = Some(decode_u64(field, value)?),
+                FIELD_COUNT => count = Some(decode_u64(field, value)? as usize),
+                FIELD_INGEST_DATA => ingest_data = value.to_vec(),
+                // 更新的版本写入的字段
+                _ => {}
+            }
+        }
+
+        let missing = |name: &str| invalid(format!("块缺少字段 {}", name));
+        Ok(SeriesData {
+            series_key: series_key.ok_or_else(|| missing("series_key"))?,
+            compressed_data: compressed_data.ok_or_else(|| missing("compressed_data"))?,
+            tags,
+            min_timestamp: min_timestamp.ok_or_else(|| missing("min_timestamp"))?,
+            max_timestamp: max_timestamp.ok_or_else(|| missing("max_timestamp"))?,
+            count: count.ok_or_else(|| missing("count"))?,
+            ingest_data,
+        })
+    }
+}
+
+// 拆出一个 编号 + 长度 + 内容 的字段
+fn split_field(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&field, rest) = data.split_first()?;
+    let (value, rest) = split_len_prefixed(rest)?;
+    Some((field, value, rest))
+}
+
+fn split_len_prefixed(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    let len = u32::from_le_bytes(data.get(..4)?.try_into().ok()?) as usize;
+    let rest = &data[4..];
+    (rest.len() >= len).then(|| rest.split_at(len))
+}
+
+fn decode_tags(mut data: &[u8]) -> Option<BTreeMap<String, String>> {
+    let mut tags = BTreeMap::new();
+    while !data.is_empty() {
+        let (key, rest) = split_len_prefixed(data)?;
+        let (value, rest) = split_len_prefixed(rest)?;
+        tags.insert(String::from_utf8(key.to_vec()).ok()?, String::from_utf8(value.to_vec()).ok()?);
+        data = rest;
+    }
+    Some(tags)
+}
+
+fn decode_u64(field: u8, value: &[u8]) -> Result<u64> {
+    let bytes: [u8; 8] = value
+        .try_into()
+        .map_err(|_| Error::new(ErrorKind::InvalidData, format!("块字段 {} 的长度 {} 无效", field, value.len())))?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+// 文件头在版本号之后带压缩算法字节
+fn has_compression_byte(data: &[u8]) -> bool {
+    data.starts_with(SSTABLE_COMPRESSED_MAGIC) || data.starts_with(SSTABLE_TAGGED_MAGIC)
+}
+
+// 文件中块的序列化方式，无法识别的文件头视为 bincode
+pub fn sstable_encoding(data: &[u8]) -> SSTableEncoding {
+    if data.starts_with(SSTABLE_TAGGED_MAGIC) {
+        SSTableEncoding::Tagged
+    } else {
+        SSTableEncoding::Bincode
+    }
+}
+
+pub fn encode_series_list(series_list: &[SeriesData], compression: BlockCompression) -> Result<Vec<u8>> {
+    encode_series_list_with(series_list, compression, SSTableEncoding::Bincode)
+}
+
+pub fn encode_series_list_with(
+    series_list: &[SeriesData],
+    compression: BlockCompression,
+    encoding: SSTableEncoding,
+) -> Result<Vec<u8>> {
+    let codec = encoding.codec();
+    let blocks = series_list.iter().map(|series| codec.encode(series)).collect::<Result<Vec<_>>>()?;
+
+    let mut data = Vec::with_capacity(COMPRESSED_HEADER_LEN);
+    let magic = match (encoding, compression) {
+        (SSTableEncoding::Tagged, _) => SSTABLE_TAGGED_MAGIC,
+        (SSTableEncoding::Bincode, BlockCompression::None) => SSTABLE_MAGIC,
+        (SSTableEncoding::Bincode, _) => SSTABLE_COMPRESSED_MAGIC,
+    };
+    data.extend_from_slice(magic);
+    data.extend_from_slice(&SSTABLE_FORMAT_VERSION.to_le_bytes());
+    if has_compression_byte(&data) {
+        data.push(compression.code());
+    }
+
+    if compression == BlockCompression::None {
+        bincode::serialize_into(&mut data, &blocks).map_err(Error::other)?;
+    } else {
+        let payload = bincode::serialize(&blocks).map_err(Error::other)?;
+        data.extend_from_slice(&compression.compress(&payload)?);
+    }
+    Ok(data)
+}
+
+pub fn decode_series_list(data: &[u8]) -> Result<Vec<SeriesData>> {
+    if data.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let version = format_version(data);
+    if version == 1 {
+        let series_list: Vec<LegacySeriesData> =
+            bincode::deserialize(data).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        return Ok(add_block_versions(series_list.into_iter().map(SeriesData::from).collect()));
+    }
+    if version > SSTABLE_FORMAT_VERSION {
+        tracing::warn!("SSTable格式版本 {} 高于当前支持的 {}，忽略块内未知字段", version, SSTABLE_FORMAT_VERSION);
+    }
+
+    let payload = if has_compression_byte(data) {
+        if data.len() < COMPRESSED_HEADER_LEN {
+            return Err(Error::new(ErrorKind::InvalidData, "SSTable文件头不完整"));
+        }
+        match BlockCompression::from_code(data[HEADER_LEN])? {
+            BlockCompression::None => std::borrow::Cow::Borrowed(&data[COMPRESSED_HEADER_LEN..]),
+            compression => std::borrow::Cow::Owned(compression.decompress(&data[COMPRESSED_HEADER_LEN..])?),
+        }
+    } else {
+        std::borrow::Cow::Borrowed(&data[HEADER_LEN..])
+    };
+    let encoding = sstable_encoding(data);
+    let blocks: Vec<Vec<u8>> =
+        bincode::deserialize(&payload).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+    let codec = encoding.codec();
+    let series_list: Vec<SeriesData> = blocks
+        .iter()
+        .map(|block| codec.decode(block, version))
+        .collect::<Result<_>>()?;
+    if version < VERSIONED_BLOCKS_SINCE {
+        return Ok(add_block_versions(series_list));
+    }
+
+    // 读取时就拒绝无法解码的块，之后的流式解压不会遇到未知版本。
+    // 原样复制的旧块保留原来的版本字节，因此当前格式的文件中也可能有 v1 块
+    if let Some(series) = series_list
+        .iter()
+        .find(|series| series.compressed_data.first().is_none_or(|&version| check_version(version).is_err()))
+    {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("系列 {} 的块编码版本 {:?} 不受支持", series.series_key, series.compressed_data.first()),
+        ));
+    }
+    Ok(series_list)
+}
+
+// 旧格式文件的块不带版本字节，写入时还没有转义编码，是 v1 位流
+fn add_block_versions(mut series_list: Vec<SeriesData>) -> Vec<SeriesData> {
+    for series in &mut series_list {
+        series.compressed_data.insert(0, BLOCK_FORMAT_V1);
+    }
+    series_list
+}
+
+// v4 之前的块结构，没有写入时间流
+#[derive(Deserialize)]
+struct LegacySeriesData {
+    series_key: String,
+    compressed_data: Vec<u8>,
+    tags: BTreeMap<String, String>,
+    min_timestamp: u64,
+    max_timestamp: u64,
+    count: usize,
+}
+
+impl From<LegacySeriesData> for SeriesData {
+    fn from(legacy: LegacySeriesData) -> Self {
+        Self {
+            series_key: legacy.series_key,
+            compressed_data: legacy.compressed_data,
+            tags: legacy.tags,
+            min_timestamp: legacy.min_timestamp,
+            max_timestamp: legacy.max_timestamp,
+            count: legacy.count,
+            ingest_data: Vec::new(),
+        }
+    }
+}
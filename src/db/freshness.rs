@@ -0,0 +1,84 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+
+const SHARD_COUNT: usize = 16;
+
+// 每个系列的最新时间戳，按key分片加锁，写入路径只锁一个分片
+#[derive(Debug)]
+pub struct FreshnessMap {
+    shards: Vec<RwLock<HashMap<String, u64>>>,
+}
+
+impl Default for FreshnessMap {
+    fn default() -> Self {
+        Self {
+            shards: (0..SHARD_COUNT).map(|_| RwLock::new(HashMap::new())).collect(),
+        }
+    }
+}
+
+impl FreshnessMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn shard(&self, series_key: &str) -> &RwLock<HashMap<String, u64>> {
+        let mut hasher = DefaultHasher::new();
+        series_key.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize % SHARD_COUNT]
+    }
+
+    // 记录一个时间戳，只在更新时前进
+    pub fn observe(&self, series_key: &str, timestamp: u64) {
+        if self.get(series_key).is_some_and(|last| last >= timestamp) {
+            return;
+        }
+        let mut shard = self.shard(series_key).write();
+        let last = shard.entry(series_key.to_string()).or_insert(timestamp);
+        *last = (*last).max(timestamp);
+    }
+
+    pub fn get(&self, series_key: &str) -> Option<u64> {
+        self.shard(series_key).read().get(series_key).copied()
+    }
+
+    // 删除后重新计算的结果，None表示系列已没有数据
+    pub fn set(&self, series_key: &str, timestamp: Option<u64>) {
+        let mut shard = self.shard(series_key).write();
+        match timestamp {
+            Some(timestamp) => {
+                shard.insert(series_key.to_string(), timestamp);
+            }
+            None => {
+                shard.remove(series_key);
+            }
+        }
+    }
+
+    pub fn clear(&self) {
+        for shard in &self.shards {
+            shard.write().clear();
+        }
+    }
+
+    pub fn snapshot(&self) -> HashMap<String, u64> {
+        let mut all = HashMap::new();
+        for shard in &self.shards {
+            all.extend(shard.read().iter().map(|(k, v)| (k.clone(), *v)));
+        }
+        all
+    }
+}
+
+// 停止上报的系列
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StaleSeries {
+    pub series_key: String,
+    pub last_timestamp: u64,
+    // 只在请求时读取，需要解压包含最新点的块
+    pub last_value: Option<f64>,
+}
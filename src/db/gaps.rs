@@ -0,0 +1,37 @@
+use serde::{Deserialize, Serialize};
+
+// 缺失数据的时间窗口 [gap_start, gap_end)：gap_start 为上一个点之后本应出现下一个点的时间，
+// gap_end 为实际出现的下一个点
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Gap {
+    pub gap_start: u64,
+    pub gap_end: u64,
+}
+
+// 逐点检测相邻两点间隔超过预期采样间隔的位置，只保存上一个时间戳
+#[derive(Debug, Clone)]
+pub struct GapDetector {
+    expected_interval: u64,
+    previous: Option<u64>,
+}
+
+impl GapDetector {
+    pub fn new(expected_interval: u64) -> Self {
+        Self {
+            expected_interval,
+            previous: None,
+        }
+    }
+
+    pub fn push(&mut self, timestamp: u64) -> Option<Gap> {
+        let previous = self.previous.replace(timestamp)?;
+        if timestamp.saturating_sub(previous) > self.expected_interval {
+            Some(Gap {
+                gap_start: previous + self.expected_interval,
+                gap_end: timestamp,
+            })
+        } else {
+            None
+        }
+    }
+}
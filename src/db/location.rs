@@ -0,0 +1,171 @@
+use std::collections::{BTreeSet, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use parking_lot::RwLock;
+
+use super::SSTable;
+
+#[derive(Debug, Default)]
+struct LocationState {
+    // 索引是否完整；不完整时退回全量扫描
+    warm: bool,
+    series: HashMap<String, BTreeSet<String>>,
+}
+
+// 系列 -> 包含该系列的SSTable文件名。索引只会多记不会漏记：
+// 文件中的系列被删除后不必立即移除，查找时多打开一个文件即可
+#[derive(Debug, Default)]
+pub struct SeriesLocationIndex {
+    state: RwLock<LocationState>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    files_visited: AtomicU64,
+}
+
+impl SeriesLocationIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_warm(&self) -> bool {
+        self.state.read().warm
+    }
+
+    pub fn mark_warm(&self) {
+        self.state.write().warm = true;
+    }
+
+    // 新的SSTable写入后登记其中的系列
+    pub fn add_file<'a>(&self, file_name: &str, series_keys: impl IntoIterator<Item = &'a str>) {
+        let mut state = self.state.write();
+        for series_key in series_keys {
+            state
+                .series
+                .entry(series_key.to_string())
+                .or_default()
+                .insert(file_name.to_string());
+        }
+    }
+
+    // SSTable文件被删除
+    pub fn remove_file(&self, file_name: &str) {
+        let mut state = self.state.write();
+        state.series.retain(|_, files| {
+            files.remove(file_name);
+            !files.is_empty()
+        });
+    }
+
+    // 系列已从所有SSTable中删除
+    pub fn remove_series(&self, series_key: &str) {
+        self.state.write().series.remove(series_key);
+    }
+
+    pub fn clear(&self) {
+        let mut state = self.state.write();
+        state.series.clear();
+        state.warm = true;
+    }
+
+    // 包含该系列的文件名，索引不完整时返回None
+    pub fn lookup(&self, series_key: &str) -> Option<BTreeSet<String>> {
+        let state = self.state.read();
+        if !state.warm {
+            return None;
+        }
+        Some(state.series.get(series_key).cloned().unwrap_or_default())
+    }
+
+    // 从所有文件的元数据重建索引，全部读取成功才标记为完整
+    pub fn rebuild(&self, sstables: &mut [SSTable]) -> bool {
+        let mut series: HashMap<String, BTreeSet<String>> = HashMap::new();
+        let mut complete = true;
+        for sstable in sstables.iter_mut() {
+            match sstable.load_series_list() {
+                Ok(blocks) => {
+                    let file_name = sstable.file_name();
+                    for block in blocks {
+                        series.entry(block.series_key).or_default().insert(file_name.clone());
+                    }
+                }
+                Err(e) => {
+                    tracing::warn!("重建系列位置索引失败 {:?}: {}", sstable.file_path(), e);
+                    complete = false;
+                }
+            }
+        }
+
+        let mut state = self.state.write();
+        state.series = series;
+        state.warm = complete;
+        complete
+    }
+
+    // 选出需要访问的SSTable。索引不完整时先尝试重建，仍失败则返回全部文件
+    pub fn locate<'a>(&self, sstables: &'a mut [SSTable], series_key: &str) -> Vec<&'a mut SSTable> {
+        if !self.is_warm() {
+            self.rebuild(sstables);
+        }
+
+        let located: Vec<&'a mut SSTable> = match self.lookup(series_key) {
+            Some(files) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                sstables
+                    .iter_mut()
+                    .filter(|sstable| files.contains(&sstable.file_name()))
+                    .collect()
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                sstables.iter_mut().collect()
+            }
+        };
+        self.files_visited.fetch_add(located.len() as u64, Ordering::Relaxed);
+        located
+    }
+
+    // 包含其中任意一个系列的SSTable，保持列表顺序，每个文件只出现一次
+    pub fn locate_any<'a, 'k>(
+        &self,
+        sstables: &'a mut [SSTable],
+        series_keys: impl IntoIterator<Item = &'k str>,
+    ) -> Vec<&'a mut SSTable> {
+        if !self.is_warm() {
+            self.rebuild(sstables);
+        }
+
+        let mut files = BTreeSet::new();
+        for series_key in series_keys {
+            match self.lookup(series_key) {
+                Some(found) => files.extend(found),
+                None => {
+                    self.misses.fetch_add(1, Ordering::Relaxed);
+                    self.files_visited.fetch_add(sstables.len() as u64, Ordering::Relaxed);
+                    return sstables.iter_mut().collect();
+                }
+            }
+        }
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        let located: Vec<&'a mut SSTable> = sstables
+            .iter_mut()
+            .filter(|sstable| files.contains(&sstable.file_name()))
+            .collect();
+        self.files_visited.fetch_add(located.len() as u64, Ordering::Relaxed);
+        located
+    }
+
+    // 通过索引定位的次数
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    // 索引不完整、退回全量扫描的次数
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    // 按系列定位后实际访问的SSTable文件总数
+    pub fn files_visited(&self) -> u64 {
+        self.files_visited.load(Ordering::Relaxed)
+    }
+}
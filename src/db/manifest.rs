@@ -0,0 +1,88 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Result, Write};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use super::{DownsamplePolicy, QuotaPolicy, ResolutionSegment, RetentionPolicy, TimePrecision};
+
+pub const MANIFEST_FILE: &str = "MANIFEST.json";
+
+// 数据目录中持久化的元数据（保留策略等运行时配置）
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    #[serde(default)]
+    pub retention: RetentionPolicy,
+    // 数据目录使用的时间戳精度，首次打开时写入
+    #[serde(default)]
+    pub timestamp_precision: Option<TimePrecision>,
+    // 每个系列最早的数据点时间戳（内部精度）
+    #[serde(default)]
+    pub series_first_seen: BTreeMap<String, u64>,
+    // 每个系列的标签。标签不再随压缩数据写入SSTable，修改标签只需重写manifest
+    #[serde(default)]
+    pub series_tags: BTreeMap<String, BTreeMap<String, String>>,
+    // 启动时预热的系列，以 '*' 结尾的表示前缀
+    #[serde(default)]
+    pub warmup_series: Vec<String>,
+    // 按系列键前缀的存储配额
+    #[serde(default)]
+    pub quotas: QuotaPolicy,
+    // 受删除保护的系列，以 '*' 结尾的表示前缀
+    #[serde(default)]
+    pub protected_series: Vec<String>,
+    // 每个系列额外标记为敏感的标签键，HTTP响应中对没有 unredacted 权限的调用方隐藏
+    #[serde(default)]
+    pub sensitive_tags: BTreeMap<String, Vec<String>>,
+    // 按年龄降低分辨率的策略，在compaction时执行
+    #[serde(default)]
+    pub downsampling: DownsamplePolicy,
+    // 每个系列已降低分辨率的时间范围
+    #[serde(default)]
+    pub series_resolution: BTreeMap<String, Vec<ResolutionSegment>>,
+    // 已提交但可能没有完成的系列替换，启动时据此完成
+    #[serde(default)]
+    pub pending_replace: Option<PendingReplace>,
+    // 每个SSTable文件因删除被移除的点数，文件名到点数，没有删除的文件不记录
+    #[serde(default)]
+    pub sstable_deleted_points: BTreeMap<String, usize>,
+    // 每个SSTable文件最后写入的时间（Unix秒），文件名到时间
+    #[serde(default)]
+    pub sstable_created_at: BTreeMap<String, u64>,
+}
+
+// 系列替换的提交记录。新内容先写入暂存文件，记录保存后替换才算提交：
+// staged 中的文件由暂存文件改名而来（可能覆盖同名的旧文件），removed 中的旧文件删除
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingReplace {
+    pub series_key: String,
+    pub staged: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+impl Manifest {
+    pub fn load(data_dir: &Path) -> Result<Self> {
+        let path = data_dir.join(MANIFEST_FILE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = fs::read(&path)?;
+        serde_json::from_slice(&content)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+
+    // 先写临时文件再重命名，避免写到一半的manifest
+    pub fn save(&self, data_dir: &Path) -> Result<()> {
+        let path = data_dir.join(MANIFEST_FILE);
+        let tmp_path = data_dir.join(format!("{}.tmp", MANIFEST_FILE));
+        let content = serde_json::to_vec_pretty(self).map_err(std::io::Error::other)?;
+
+        let mut file = fs::File::create(&tmp_path)?;
+        file.write_all(&content)?;
+        file.sync_all()?;
+        drop(file);
+
+        fs::rename(&tmp_path, &path)
+    }
+}
@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Result;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use memmap2::Mmap;
+use parking_lot::Mutex;
+
+// 一次读取得到的文件内容。持有期间映射不会被淘汰后释放，空文件没有映射
+#[derive(Debug, Clone, Default)]
+pub struct MappedFile(Option<Arc<Mmap>>);
+
+impl Deref for MappedFile {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.0.as_deref().map_or(&[], |mmap| &mmap[..])
+    }
+}
+
+#[derive(Debug, Default)]
+struct MappingState {
+    tick: u64,
+    // 文件路径 -> (最近使用的序号, 映射)
+    entries: HashMap<PathBuf, (u64, Arc<Mmap>)>,
+}
+
+// 所有SSTable共用的内存映射缓存，同时保持的映射数不超过 capacity，超出时按最近最少使用淘汰，
+// 被淘汰的文件下次访问时重新映射
+#[derive(Debug)]
+pub struct MappingCache {
+    capacity: usize,
+    state: Mutex<MappingState>,
+    maps: AtomicU64,
+    evictions: AtomicU64,
+}
+
+// 映射缓存的容量和累计的映射、淘汰次数
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct MappingStats {
+    pub capacity: usize,
+    pub open: usize,
+    pub maps: u64,
+    pub evictions: u64,
+}
+
+impl MappingCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            state: Mutex::new(MappingState::default()),
+            maps: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
+    }
+
+    // 不限制映射数量，供单独打开的SSTable使用
+    pub fn unbounded() -> Self {
+        Self::new(usize::MAX)
+    }
+
+    pub fn get(&self, path: &Path) -> Result<MappedFile> {
+        let mut state = self.state.lock();
+        state.tick += 1;
+        let tick = state.tick;
+
+        if let Some((last_used, mmap)) = state.entries.get_mut(path) {
+            // 文件已被删除时映射失效
+            if path.exists() {
+                *last_used = tick;
+                return Ok(MappedFile(Some(Arc::clone(mmap))));
+            }
+            state.entries.remove(path);
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "SSTable file was deleted"
+            ));
+        }
+
+        // 检查文件是否存在且不为空
+        let metadata = std::fs::metadata(path)?;
+        if metadata.len() == 0 {
+            return Ok(MappedFile(None));
+        }
+
+        let file = File::open(path)?;
+        // 安全地创建内存映射
+        let mmap = unsafe {
+            match Mmap::map(&file) {
+                Ok(mmap) => Arc::new(mmap),
+                Err(e) => {
+                    tracing::error!("Failed to create mmap for {:?}: {}", path, e);
+                    return Err(e);
+                }
+            }
+        };
+        self.maps.fetch_add(1, Ordering::Relaxed);
+        state.entries.insert(path.to_path_buf(), (tick, Arc::clone(&mmap)));
+
+        // 只淘汰没有读取方持有的映射，全部在用时暂时超出容量，之后的访问再继续淘汰
+        while state.entries.len() > self.capacity {
+            let Some(oldest) = state
+                .entries
+                .iter()
+                .filter(|(_, (_, mmap))| Arc::strong_count(mmap) == 1)
+                .min_by_key(|(_, (last_used, _))| *last_used)
+                .map(|(path, _)| path.clone())
+            else {
+                break;
+            };
+            state.entries.remove(&oldest);
+            self.evictions.fetch_add(1, Ordering::Relaxed);
+        }
+
+        Ok(MappedFile(Some(mmap)))
+    }
+
+    // 文件被重写或删除，丢弃旧的映射
+    pub fn invalidate(&self, path: &Path) {
+        self.state.lock().entries.remove(path);
+    }
+
+    pub fn stats(&self) -> MappingStats {
+        MappingStats {
+            capacity: self.capacity,
+            open: self.state.lock().entries.len(),
+            maps: self.maps.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+        }
+    }
+}
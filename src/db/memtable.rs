@@ -1,25 +1,61 @@
 use std::collections::BTreeMap;
-use super::DataPoint;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use parking_lot::RwLockReadGuard;
+
+use super::{DataPoint, TagInterner, TagSet};
+
+// 内存表中的数据点，标签集合由内容相同的点共享
+#[derive(Debug, Clone)]
+pub struct MemtablePoint {
+    pub timestamp: u64,
+    pub value: f64,
+    pub tags: TagSet,
+}
+
+impl MemtablePoint {
+    pub fn to_datapoint(&self) -> DataPoint {
+        DataPoint {
+            timestamp: self.timestamp,
+            value: self.value,
+            tags: (*self.tags).clone(),
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct Memtable {
-    data: BTreeMap<String, Vec<DataPoint>>,
+    data: BTreeMap<String, Vec<MemtablePoint>>,
+    // 写入的标签集合在这里驻留，刷新后清空
+    tags: TagInterner,
     size: usize,
     threshold: usize,
+    // 当前内存表中最早和最近一次写入的时间
+    oldest_insert: Option<SystemTime>,
+    newest_insert: Option<SystemTime>,
+    // 开启写入时间记录时，每个系列按事件时间戳保存写入时间戳
+    ingest_times: BTreeMap<String, BTreeMap<u64, u64>>,
 }
 
 impl Memtable {
     pub fn new(threshold: usize) -> Self {
         Self {
             data: BTreeMap::new(),
+            tags: TagInterner::new(),
             size: 0,
             threshold,
+            oldest_insert: None,
+            newest_insert: None,
+            ingest_times: BTreeMap::new(),
         }
     }
 
-    pub fn insert(&mut self, series_key: String, datapoint: DataPoint) {
+    // 标签按引用驻留，内容相同的集合已驻留时不再为这个点分配标签
+    pub fn insert(&mut self, series_key: String, timestamp: u64, value: f64, tags: &BTreeMap<String, String>) {
+        let point = MemtablePoint { timestamp, value, tags: self.tags.intern(tags) };
         let entry = self.data.entry(series_key).or_default();
-        entry.push(datapoint);
+        entry.push(point);
         self.size += 1;
     }
 
@@ -39,12 +75,16 @@ impl Memtable {
                 if let Some(datapoints) = self.data.get_mut(series_key) {
                     let original_len = datapoints.len();
                     datapoints.retain(|dp| dp.timestamp != ts);
+                    if let Some(times) = self.ingest_times.get_mut(series_key) {
+                        times.remove(&ts);
+                    }
                     let removed = original_len > datapoints.len();
                     if removed {
                         self.size -= 1;
                     }
                     if datapoints.is_empty() {
                         self.data.remove(series_key);
+                        self.ingest_times.remove(series_key);
                     }
                     removed
                 } else {
@@ -52,8 +92,11 @@ impl Memtable {
                 }
             }
             None => {
+                self.ingest_times.remove(series_key);
                 if let Some(datapoints) = self.data.remove(series_key) {
                     self.size -= datapoints.len();
+                    drop(datapoints);
+                    self.tags.purge();
                     true
                 } else {
                     false
@@ -62,20 +105,123 @@ impl Memtable {
         }
     }
 
+    // 删除 [start_time, end_time] 范围内的数据点，返回删除数量
+    pub fn delete_range(&mut self, series_key: &str, start_time: Option<u64>, end_time: Option<u64>) -> usize {
+        let Some(datapoints) = self.data.get_mut(series_key) else {
+            return 0;
+        };
+        let in_range = |ts: u64| start_time.is_none_or(|start| ts >= start) && end_time.is_none_or(|end| ts <= end);
+        let original_len = datapoints.len();
+        datapoints.retain(|dp| !in_range(dp.timestamp));
+        let removed = original_len - datapoints.len();
+        self.size -= removed;
+        if datapoints.is_empty() {
+            self.data.remove(series_key);
+        }
+        if let Some(times) = self.ingest_times.get_mut(series_key) {
+            times.retain(|ts, _| !in_range(*ts));
+            if times.is_empty() {
+                self.ingest_times.remove(series_key);
+            }
+        }
+        removed
+    }
+
+    // 数据点数量
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
     pub fn is_full(&self) -> bool {
         self.size >= self.threshold
     }
 
     pub fn clear(&mut self) {
         self.data.clear();
+        self.tags.clear();
         self.size = 0;
+        self.ingest_times.clear();
+        self.oldest_insert = None;
+        self.newest_insert = None;
+    }
+
+    // 记录写入时间
+    pub fn record_insert_time(&mut self, now: SystemTime) {
+        if self.oldest_insert.is_none() {
+            self.oldest_insert = Some(now);
+        }
+        self.newest_insert = Some(now);
+    }
+
+    pub fn oldest_insert_time(&self) -> Option<SystemTime> {
+        if self.size == 0 {
+            return None;
+        }
+        self.oldest_insert
+    }
+
+    pub fn newest_insert_time(&self) -> Option<SystemTime> {
+        if self.size == 0 {
+            return None;
+        }
+        self.newest_insert
+    }
+
+    // 记录数据点的写入时间。查询时重复的时间戳保留先写入的值，写入时间同样保留第一次的
+    pub fn record_ingest_time(&mut self, series_key: &str, timestamp: u64, ingest_timestamp: u64) {
+        self.ingest_times.entry(series_key.to_string()).or_default().entry(timestamp).or_insert(ingest_timestamp);
+    }
+
+    pub fn ingest_times(&self) -> &BTreeMap<String, BTreeMap<u64, u64>> {
+        &self.ingest_times
     }
 
-    pub fn get_data(&self) -> &BTreeMap<String, Vec<DataPoint>> {
+    pub fn get_data(&self) -> &BTreeMap<String, Vec<MemtablePoint>> {
         &self.data
     }
 
-    pub fn query(&self, series_key: &str, start_time: Option<u64>, end_time: Option<u64>) -> Vec<DataPoint> {
+    // 取走整个内存表用于刷新，换上一个空的内存表
+    pub fn freeze(&mut self) -> Memtable {
+        let threshold = self.threshold;
+        std::mem::replace(self, Memtable::new(threshold))
+    }
+
+    // 刷新失败时放回取走的数据。这些点先于当前的点写入，放在前面，重复的时间戳仍保留先写入的值
+    pub fn restore(&mut self, earlier: &Memtable) {
+        for (series_key, points) in &earlier.data {
+            let restored: Vec<MemtablePoint> = points
+                .iter()
+                .map(|point| MemtablePoint { tags: self.tags.intern(&point.tags), ..point.clone() })
+                .collect();
+            self.size += restored.len();
+            self.data.entry(series_key.clone()).or_default().splice(0..0, restored);
+        }
+        for (series_key, times) in &earlier.ingest_times {
+            let current = self.ingest_times.entry(series_key.clone()).or_default();
+            for (&timestamp, &ingest_timestamp) in times {
+                current.insert(timestamp, ingest_timestamp);
+            }
+        }
+        self.oldest_insert = earlier.oldest_insert.or(self.oldest_insert);
+        self.newest_insert = self.newest_insert.or(earlier.newest_insert);
+    }
+
+    // 不同标签集合数
+    pub fn tag_sets(&self) -> usize {
+        self.tags.len()
+    }
+
+    // 内存表占用的内存估算：系列键、数据点数组和驻留的标签集合，共享的标签集合只计一次
+    pub fn estimated_bytes(&self) -> usize {
+        let points: usize = self
+            .data
+            .iter()
+            .map(|(series_key, points)| series_key.capacity() + points.capacity() * std::mem::size_of::<MemtablePoint>())
+            .sum();
+        points + self.tags.estimated_bytes()
+    }
+
+    pub fn query(&self, series_key: &str, start_time: Option<u64>, end_time: Option<u64>) -> Vec<MemtablePoint> {
         if let Some(datapoints) = self.data.get(series_key) {
             datapoints.iter()
                 .filter(|dp| {
@@ -99,3 +245,51 @@ impl Memtable {
     }
 }
 
+// 读取时看到的内存表：正在刷新的内存表在对应的SSTable加入之前仍然可见，排在当前内存表之前。
+// 新SSTable加入之后、刷新的内存表移除之前，两边的数据短暂同时可见，查询按时间戳去重
+pub struct MemtableView<'a> {
+    flushing: Option<Arc<Memtable>>,
+    active: RwLockReadGuard<'a, Memtable>,
+}
+
+impl<'a> MemtableView<'a> {
+    pub fn new(flushing: Option<Arc<Memtable>>, active: RwLockReadGuard<'a, Memtable>) -> Self {
+        Self { flushing, active }
+    }
+
+    // 按写入先后排列的内存表
+    pub fn tables(&self) -> impl Iterator<Item = &Memtable> {
+        self.flushing.as_deref().into_iter().chain(std::iter::once(&*self.active))
+    }
+
+    pub fn query(&self, series_key: &str, start_time: Option<u64>, end_time: Option<u64>) -> Vec<MemtablePoint> {
+        self.tables().flat_map(|memtable| memtable.query(series_key, start_time, end_time)).collect()
+    }
+
+    // 系列在内存表中的数据点，先写入的在前
+    pub fn series_points<'b>(&'b self, series_key: &'b str) -> impl Iterator<Item = &'b MemtablePoint> + 'b {
+        self.tables().flat_map(move |memtable| memtable.get_data().get(series_key).into_iter().flatten())
+    }
+
+    // 所有系列及其数据点，每个系列内先写入的在前
+    pub fn series(&self) -> BTreeMap<&String, Vec<&MemtablePoint>> {
+        let mut series: BTreeMap<&String, Vec<&MemtablePoint>> = BTreeMap::new();
+        for memtable in self.tables() {
+            for (series_key, points) in memtable.get_data() {
+                series.entry(series_key).or_default().extend(points);
+            }
+        }
+        series
+    }
+
+    // 系列按事件时间戳的写入时间，重复的时间戳保留先写入的
+    pub fn ingest_times(&self, series_key: &str) -> BTreeMap<u64, u64> {
+        let mut merged = BTreeMap::new();
+        for times in self.tables().filter_map(|memtable| memtable.ingest_times().get(series_key)) {
+            for (&timestamp, &ingest_timestamp) in times {
+                merged.entry(timestamp).or_insert(ingest_timestamp);
+            }
+        }
+        merged
+    }
+}
@@ -2,9 +2,101 @@ pub mod compression;
 pub mod sstable;
 pub mod memtable;
 pub mod engine;
+pub mod config;
+pub mod clock;
+pub mod aggregation;
+pub mod error;
+pub mod disk;
+pub mod retention;
+pub mod manifest;
+pub mod compaction;
+pub mod precision;
+pub mod tag_drift;
+pub mod scan;
+pub mod series_walk;
+pub mod crossing;
+pub mod gaps;
+pub mod singleflight;
+pub mod freshness;
+pub mod location;
+pub mod format;
+pub mod observer;
+pub mod warmup;
+pub mod blocking;
+pub mod bounds;
+pub mod archive;
+pub mod sstable_info;
+pub mod mapping;
+pub mod series_file;
+pub mod quota;
+pub mod operations;
+pub mod overlap;
+pub mod data_dirs;
+pub mod query_memory;
+pub mod chunks;
+pub mod summary;
+pub mod downsampling;
+pub mod time_range;
+pub mod tag_interner;
+pub mod supervisor;
+pub mod windows;
+pub mod namespace;
+pub mod validation;
+pub mod cancellation;
+pub mod annotations;
+pub mod verify;
+pub mod changes;
+pub mod tag_filter;
+pub mod replace;
 
 pub use compression::*;
 pub use sstable::*;
 pub use memtable::*;
 pub use engine::*;
+pub use config::*;
+pub use clock::*;
+pub use aggregation::*;
+pub use error::*;
+pub use disk::*;
+pub use retention::*;
+pub use manifest::*;
+pub use compaction::*;
+pub use precision::*;
+pub use tag_drift::*;
+pub use scan::*;
+pub use series_walk::*;
+pub use crossing::*;
+pub use gaps::*;
+pub use singleflight::*;
+pub use freshness::*;
+pub use location::*;
+pub use format::*;
+pub use observer::*;
+pub use warmup::*;
+pub use blocking::*;
+pub use bounds::*;
+pub use archive::*;
+pub use sstable_info::*;
+pub use mapping::*;
+pub use series_file::*;
+pub use quota::*;
+pub use operations::*;
+pub use overlap::*;
+pub use data_dirs::*;
+pub use query_memory::*;
+pub use chunks::*;
+pub use summary::*;
+pub use downsampling::*;
+pub use time_range::*;
+pub use tag_interner::*;
+pub use supervisor::*;
+pub use windows::*;
+pub use namespace::*;
+pub use validation::*;
+pub use cancellation::*;
+pub use annotations::*;
+pub use verify::*;
+pub use changes::*;
+pub use tag_filter::*;
+pub use replace::*;
 
@@ -0,0 +1,36 @@
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+use serde::{Deserialize, Serialize};
+
+use super::SeriesPointCount;
+
+// 有序映射中键以 prefix 开头的项。以前缀为起点做范围查找，遇到第一个不匹配的键即停止，
+// 只访问前缀范围内的键
+pub fn prefix_range<'a, V>(map: &'a BTreeMap<String, V>, prefix: &'a str) -> impl Iterator<Item = (&'a String, &'a V)> + 'a {
+    map.range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+        .take_while(move |(key, _)| key.starts_with(prefix))
+}
+
+// 某个前缀下系列的统计，与 stats_summary 一样只读取内存表和SSTable元数据中的计数
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrefixStats {
+    pub prefix: String,
+    pub series: usize,
+    // SSTable块元数据中的点数加上内存表中的点数，compaction之前重复的时间戳可能被重复计数
+    pub total_points: u64,
+    pub memtable_points: usize,
+    // 包含这些系列的SSTable文件数
+    pub sstable_count: usize,
+    // 点数最多的系列
+    pub top_series: Vec<SeriesPointCount>,
+}
+
+// 按前缀删除系列的结果
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrefixDeleteReport {
+    pub prefix: String,
+    pub deleted: Vec<String>,
+    // 受删除保护而保留的系列
+    pub protected: Vec<String>,
+}
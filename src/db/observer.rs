@@ -0,0 +1,161 @@
+use std::fmt::Debug;
+use std::io::{Error, ErrorKind};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use super::{CompactionOptions, CompactionReport};
+
+// 内存表刷新完成
+#[derive(Debug, Clone)]
+pub struct FlushEvent {
+    pub sstable_path: PathBuf,
+    pub series_blocks: usize,
+    pub points: usize,
+}
+
+// 读取SSTable时发现数据无法解析
+#[derive(Debug, Clone)]
+pub struct CorruptionEvent {
+    pub sstable_path: PathBuf,
+    pub error: String,
+}
+
+// 引擎生命周期事件的回调，所有方法默认不做任何事。
+//
+// 回调在触发事件的线程上同步执行，同一线程内按事件发生的顺序调用，多个观察者按注册顺序调用；
+// 不同线程上的事件（如后台compaction与写入触发的刷新）之间没有顺序保证。
+// 回调时可能持有引擎内部的锁，不要在回调中调用 TimeSeriesDB 的方法，耗时的处理请自行转交给其他任务。
+// 回调中的panic会被捕获并记录日志，不影响引擎操作。
+pub trait EngineObserver: Send + Sync + Debug {
+    // 新的SSTable已写入磁盘并对查询可见
+    fn on_flush_completed(&self, _event: &FlushEvent) {}
+
+    // 内存表已取走，新的SSTable开始写入，在写文件的线程上调用。此时取走的点仍对查询可见
+    fn on_flush_writing(&self, _points: usize) {}
+
+    // 开始一次compaction，之后必然有对应的 on_compaction_finished（执行出错时除外）
+    fn on_compaction_started(&self, _options: &CompactionOptions) {}
+
+    // compaction结束，跳过的执行带有 skipped_reason
+    fn on_compaction_finished(&self, _report: &CompactionReport) {}
+
+    // 同一个损坏的文件每次读取失败都会通知
+    fn on_corruption_detected(&self, _event: &CorruptionEvent) {}
+
+    // 写入后内存表达到阈值，随后会触发刷新
+    fn on_memtable_threshold_crossed(&self, _points: usize, _threshold: usize) {}
+
+    // 范围查询开始解压系列的一个SSTable块，在解压线程上调用
+    fn on_query_block(&self, _series_key: &str) {}
+}
+
+// 引擎事件计数，由内置的 MetricsObserver 统计
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct EngineMetrics {
+    pub flushes: u64,
+    pub flushed_points: u64,
+    pub compactions_started: u64,
+    pub compactions_finished: u64,
+    pub compactions_skipped: u64,
+    pub corruptions_detected: u64,
+    pub memtable_threshold_crossings: u64,
+}
+
+// 内置观察者，把事件累加为 /stats 中的计数
+#[derive(Debug, Default)]
+pub struct MetricsObserver {
+    flushes: AtomicU64,
+    flushed_points: AtomicU64,
+    compactions_started: AtomicU64,
+    compactions_finished: AtomicU64,
+    compactions_skipped: AtomicU64,
+    corruptions_detected: AtomicU64,
+    memtable_threshold_crossings: AtomicU64,
+}
+
+impl MetricsObserver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn snapshot(&self) -> EngineMetrics {
+        EngineMetrics {
+            flushes: self.flushes.load(Ordering::Relaxed),
+            flushed_points: self.flushed_points.load(Ordering::Relaxed),
+            compactions_started: self.compactions_started.load(Ordering::Relaxed),
+            compactions_finished: self.compactions_finished.load(Ordering::Relaxed),
+            compactions_skipped: self.compactions_skipped.load(Ordering::Relaxed),
+            corruptions_detected: self.corruptions_detected.load(Ordering::Relaxed),
+            memtable_threshold_crossings: self.memtable_threshold_crossings.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl EngineObserver for MetricsObserver {
+    fn on_flush_completed(&self, event: &FlushEvent) {
+        self.flushes.fetch_add(1, Ordering::Relaxed);
+        self.flushed_points.fetch_add(event.points as u64, Ordering::Relaxed);
+    }
+
+    fn on_compaction_started(&self, _options: &CompactionOptions) {
+        self.compactions_started.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_compaction_finished(&self, report: &CompactionReport) {
+        self.compactions_finished.fetch_add(1, Ordering::Relaxed);
+        if report.skipped_reason.is_some() {
+            self.compactions_skipped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn on_corruption_detected(&self, _event: &CorruptionEvent) {
+        self.corruptions_detected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_memtable_threshold_crossed(&self, _points: usize, _threshold: usize) {
+        self.memtable_threshold_crossings.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+// 引擎持有的观察者列表，内置的 MetricsObserver 总在最前面
+#[derive(Debug, Clone)]
+pub struct ObserverSet {
+    metrics: Arc<MetricsObserver>,
+    observers: Vec<Arc<dyn EngineObserver>>,
+}
+
+impl ObserverSet {
+    pub fn new(extra: Vec<Arc<dyn EngineObserver>>) -> Self {
+        let metrics = Arc::new(MetricsObserver::new());
+        let mut observers: Vec<Arc<dyn EngineObserver>> = vec![metrics.clone()];
+        observers.extend(extra);
+        Self { metrics, observers }
+    }
+
+    pub fn metrics(&self) -> EngineMetrics {
+        self.metrics.snapshot()
+    }
+
+    // 依次通知所有观察者，单个观察者panic不影响其他观察者和调用方
+    pub fn notify(&self, event: &str, callback: impl Fn(&dyn EngineObserver)) {
+        for observer in &self.observers {
+            if catch_unwind(AssertUnwindSafe(|| callback(observer.as_ref()))).is_err() {
+                tracing::error!("观察者 {:?} 处理 {} 事件时panic，已忽略", observer, event);
+            }
+        }
+    }
+
+    // 读取SSTable失败时，数据无法解析的情况作为损坏事件通知
+    pub fn sstable_read_failed(&self, sstable_path: &Path, error: &Error) {
+        if error.kind() != ErrorKind::InvalidData {
+            return;
+        }
+        let event = CorruptionEvent {
+            sstable_path: sstable_path.to_path_buf(),
+            error: error.to_string(),
+        };
+        self.notify("corruption_detected", |observer| observer.on_corruption_detected(&event));
+    }
+}
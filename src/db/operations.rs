@@ -0,0 +1,232 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{Error, ErrorKind, Result};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use serde::Serialize;
+use tokio_util::sync::CancellationToken;
+
+// 可以查看和取消的后台管理操作
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OperationKind {
+    Compact,
+    Snapshot,
+    Verify,
+}
+
+// 登记表保留的已结束操作数，超过时丢弃最早结束的
+pub const FINISHED_OPERATIONS_LIMIT: usize = 32;
+
+#[derive(Debug)]
+struct OperationState {
+    kind: OperationKind,
+    started_at: u64,
+    stage: Mutex<&'static str>,
+    files_total: AtomicUsize,
+    files_done: AtomicUsize,
+    // 执行过程中发现的问题（如损坏的文件），结束前就可以查看
+    findings: Mutex<Vec<String>>,
+    cancel: CancellationToken,
+}
+
+// 正在执行的操作及其进度，进度按当前阶段处理过的文件数计算
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct OperationInfo {
+    pub id: u64,
+    pub kind: OperationKind,
+    pub started_at: u64,
+    pub stage: String,
+    pub files_done: usize,
+    pub files_total: usize,
+    pub cancel_requested: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+// 按编号查询的操作状态：进行中的操作的进度，或已结束操作的结果。只保存在进程内存中
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct JobInfo {
+    pub id: u64,
+    pub kind: OperationKind,
+    pub status: JobStatus,
+    pub started_at: u64,
+    pub finished_at: Option<u64>,
+    pub stage: String,
+    pub files_done: usize,
+    pub files_total: usize,
+    pub findings: Vec<String>,
+    // 成功结束时操作的报告
+    pub result: Option<serde_json::Value>,
+    pub error: Option<String>,
+}
+
+// 进行中的管理操作登记表。取消是协作式的：操作在处理相邻两个文件之间检查取消标记，
+// 已经开始修改文件的阶段不再响应取消
+#[derive(Debug, Default)]
+pub struct OperationRegistry {
+    next_id: AtomicU64,
+    running: Mutex<BTreeMap<u64, Arc<OperationState>>>,
+    // 通过 OperationHandle::finish 结束的操作，按结束顺序
+    finished: Mutex<VecDeque<JobInfo>>,
+}
+
+impl OperationRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn start(self: &Arc<Self>, kind: OperationKind, started_at: u64) -> OperationHandle {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let state = Arc::new(OperationState {
+            kind,
+            started_at,
+            stage: Mutex::new("starting"),
+            files_total: AtomicUsize::new(0),
+            files_done: AtomicUsize::new(0),
+            findings: Mutex::new(Vec::new()),
+            cancel: CancellationToken::new(),
+        });
+        self.running.lock().insert(id, Arc::clone(&state));
+        OperationHandle {
+            id,
+            state,
+            registry: Arc::clone(self),
+        }
+    }
+
+    // 按开始顺序列出
+    pub fn list(&self) -> Vec<OperationInfo> {
+        self.running
+            .lock()
+            .iter()
+            .map(|(id, state)| OperationInfo {
+                id: *id,
+                kind: state.kind,
+                started_at: state.started_at,
+                stage: state.stage.lock().to_string(),
+                files_done: state.files_done.load(Ordering::Relaxed),
+                files_total: state.files_total.load(Ordering::Relaxed),
+                cancel_requested: state.cancel.is_cancelled(),
+            })
+            .collect()
+    }
+
+    // 进行中或最近结束的操作
+    pub fn job(&self, id: u64) -> Option<JobInfo> {
+        if let Some(state) = self.running.lock().get(&id) {
+            return Some(state.job_info(id, JobStatus::Running));
+        }
+        self.finished.lock().iter().find(|job| job.id == id).cloned()
+    }
+
+    // 请求取消，操作不存在（已结束）时返回false
+    pub fn cancel(&self, id: u64) -> bool {
+        match self.running.lock().get(&id) {
+            Some(state) => {
+                state.cancel.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl OperationState {
+    fn job_info(&self, id: u64, status: JobStatus) -> JobInfo {
+        JobInfo {
+            id,
+            kind: self.kind,
+            status,
+            started_at: self.started_at,
+            finished_at: None,
+            stage: self.stage.lock().to_string(),
+            files_done: self.files_done.load(Ordering::Relaxed),
+            files_total: self.files_total.load(Ordering::Relaxed),
+            findings: self.findings.lock().clone(),
+            result: None,
+            error: None,
+        }
+    }
+}
+
+// 一个操作在登记表中的条目，丢弃时移除
+#[derive(Debug)]
+pub struct OperationHandle {
+    id: u64,
+    state: Arc<OperationState>,
+    registry: Arc<OperationRegistry>,
+}
+
+impl OperationHandle {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    // 进入新的阶段，文件计数从0开始
+    pub fn begin_stage(&self, stage: &'static str, files_total: usize) {
+        *self.state.stage.lock() = stage;
+        self.state.files_total.store(files_total, Ordering::Relaxed);
+        self.state.files_done.store(0, Ordering::Relaxed);
+    }
+
+    pub fn file_done(&self) {
+        self.state.files_done.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_finding(&self, finding: String) {
+        self.state.findings.lock().push(finding);
+    }
+
+    // 交给阻塞任务检查的取消标记
+    pub fn cancel_token(&self) -> CancellationToken {
+        self.state.cancel.clone()
+    }
+
+    // 结束操作并保留结果，之后仍可以按编号查询。直接丢弃的操作不保留
+    pub fn finish(self, finished_at: u64, outcome: std::result::Result<serde_json::Value, String>) {
+        let status = match &outcome {
+            Ok(_) => JobStatus::Completed,
+            Err(_) if self.is_cancelled() => JobStatus::Cancelled,
+            Err(_) => JobStatus::Failed,
+        };
+        let mut job = self.state.job_info(self.id, status);
+        job.finished_at = Some(finished_at);
+        match outcome {
+            Ok(result) => job.result = Some(result),
+            Err(error) => job.error = Some(error),
+        }
+        let mut finished = self.registry.finished.lock();
+        if finished.len() >= FINISHED_OPERATIONS_LIMIT {
+            finished.pop_front();
+        }
+        finished.push_back(job);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.state.cancel.is_cancelled()
+    }
+
+    // 已请求取消时返回 Interrupted 错误
+    pub fn check_cancelled(&self) -> Result<()> {
+        if self.is_cancelled() {
+            Err(Error::new(ErrorKind::Interrupted, format!("操作 {} 已取消", self.id)))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Drop for OperationHandle {
+    fn drop(&mut self) {
+        self.registry.running.lock().remove(&self.id);
+    }
+}
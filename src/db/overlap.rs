@@ -0,0 +1,192 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use serde::Serialize;
+
+use super::SSTableSeriesInfo;
+
+// 报告中列出的重叠最严重的系列数量
+pub const OVERLAP_WORST_LIMIT: usize = 10;
+
+// 系列在一组时间范围相交的SSTable中的数据
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct OverlapGroup {
+    pub start_time: u64,
+    pub end_time: u64,
+    // 按文件顺序
+    pub files: Vec<String>,
+}
+
+// 一个系列在各SSTable之间的时间范围重叠情况
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SeriesOverlap {
+    pub series_key: String,
+    // 包含该系列的SSTable数量
+    pub files: usize,
+    // 时间范围与其他文件相交的文件数量
+    pub overlapping_files: usize,
+    // 同一时间点最多需要读取的文件数
+    pub max_files_per_query: usize,
+    // 在系列覆盖的时间范围内，时间点查询平均需要读取的文件数
+    pub avg_files_per_query: f64,
+    // 文件最多的一组相交文件，没有重叠时为None
+    pub worst_group: Option<OverlapGroup>,
+}
+
+// SSTable之间时间范围重叠的汇总，只根据块元数据计算，不解压数据
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct OverlapReport {
+    pub sstable_count: usize,
+    pub series_count: usize,
+    // 至少有两个文件时间范围相交的系列数量
+    pub overlapping_series: usize,
+    // 所有系列的平均值
+    pub avg_files_per_query: f64,
+    pub max_files_per_query: usize,
+    // 按 max_files_per_query、avg_files_per_query 从大到小，最多 OVERLAP_WORST_LIMIT 个有重叠的系列
+    pub worst: Vec<SeriesOverlap>,
+}
+
+// 计算结果：汇总报告和按重叠程度排序的全部系列
+#[derive(Debug, Clone, Default)]
+pub struct OverlapAnalysis {
+    pub report: OverlapReport,
+    pub series: Vec<SeriesOverlap>,
+}
+
+impl OverlapAnalysis {
+    // files 按文件顺序给出每个文件中各系列的块统计
+    pub fn compute(files: &[(String, Vec<SSTableSeriesInfo>)]) -> Self {
+        let mut ranges: BTreeMap<&str, Vec<(u64, u64, usize)>> = BTreeMap::new();
+        for (file_index, (_, series)) in files.iter().enumerate() {
+            for info in series.iter().filter(|info| info.points > 0) {
+                ranges
+                    .entry(info.series_key.as_str())
+                    .or_default()
+                    .push((info.min_timestamp, info.max_timestamp, file_index));
+            }
+        }
+
+        let mut series: Vec<SeriesOverlap> = ranges
+            .into_iter()
+            .map(|(series_key, ranges)| series_overlap(series_key, ranges, files))
+            .collect();
+        series.sort_by(|a, b| {
+            b.max_files_per_query
+                .cmp(&a.max_files_per_query)
+                .then(b.avg_files_per_query.total_cmp(&a.avg_files_per_query))
+                .then_with(|| a.series_key.cmp(&b.series_key))
+        });
+
+        let series_count = series.len();
+        let avg_files_per_query = if series_count == 0 {
+            0.0
+        } else {
+            series.iter().map(|s| s.avg_files_per_query).sum::<f64>() / series_count as f64
+        };
+        let overlapping: Vec<&SeriesOverlap> = series.iter().filter(|s| s.worst_group.is_some()).collect();
+        let report = OverlapReport {
+            sstable_count: files.len(),
+            series_count,
+            overlapping_series: overlapping.len(),
+            avg_files_per_query,
+            max_files_per_query: series.first().map_or(0, |s| s.max_files_per_query),
+            worst: overlapping.into_iter().take(OVERLAP_WORST_LIMIT).cloned().collect(),
+        };
+        Self { report, series }
+    }
+}
+
+fn series_overlap(
+    series_key: &str,
+    mut ranges: Vec<(u64, u64, usize)>,
+    files: &[(String, Vec<SSTableSeriesInfo>)],
+) -> SeriesOverlap {
+    let file_count = ranges.len();
+
+    // 扫描线求同一时间点最多相交的文件数，区间两端都包含在内
+    let mut events: Vec<(u64, u8)> = ranges.iter().flat_map(|&(min, max, _)| [(min, 0), (max, 1)]).collect();
+    events.sort_unstable();
+    let (mut depth, mut max_depth) = (0usize, 0usize);
+    for (_, kind) in events {
+        if kind == 0 {
+            depth += 1;
+            max_depth = max_depth.max(depth);
+        } else {
+            depth -= 1;
+        }
+    }
+
+    // 把相交的区间合并成组
+    ranges.sort_by_key(|&(min, max, _)| (min, max));
+    let mut groups: Vec<(u64, u64, Vec<usize>)> = Vec::new();
+    for (min, max, file_index) in ranges.iter().copied() {
+        match groups.last_mut() {
+            Some(group) if min <= group.1 => {
+                group.1 = group.1.max(max);
+                group.2.push(file_index);
+            }
+            _ => groups.push((min, max, vec![file_index])),
+        }
+    }
+
+    let covered: u128 = ranges.iter().map(|&(min, max, _)| (max - min) as u128 + 1).sum();
+    let union: u128 = groups.iter().map(|(min, max, _)| (max - min) as u128 + 1).sum();
+    let overlapping_files = groups.iter().filter(|g| g.2.len() > 1).map(|g| g.2.len()).sum();
+    let worst_group = groups.into_iter().filter(|g| g.2.len() > 1).max_by_key(|g| g.2.len()).map(
+        |(start_time, end_time, mut indices)| {
+            indices.sort_unstable();
+            let files = indices.into_iter().map(|index| files[index].0.clone()).collect();
+            OverlapGroup { start_time, end_time, files }
+        },
+    );
+
+    SeriesOverlap {
+        series_key: series_key.to_string(),
+        files: file_count,
+        overlapping_files,
+        max_files_per_query: max_depth,
+        avg_files_per_query: if union == 0 { 0.0 } else { covered as f64 / union as f64 },
+        worst_group,
+    }
+}
+
+// 缓存最近一次的重叠分析，刷新、compaction等改变SSTable的操作后失效。
+// 计算期间发生失效时丢弃计算结果
+#[derive(Debug, Default)]
+pub struct OverlapCache {
+    analysis: Mutex<Option<Arc<OverlapAnalysis>>>,
+    generation: AtomicU64,
+}
+
+impl OverlapCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self) -> Option<Arc<OverlapAnalysis>> {
+        self.analysis.lock().clone()
+    }
+
+    // 开始计算前取得的代数，传给 store
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    pub fn store(&self, generation: u64, analysis: OverlapAnalysis) -> Arc<OverlapAnalysis> {
+        let analysis = Arc::new(analysis);
+        let mut cached = self.analysis.lock();
+        if self.generation() == generation {
+            *cached = Some(Arc::clone(&analysis));
+        }
+        analysis
+    }
+
+    pub fn invalidate(&self) {
+        let mut cached = self.analysis.lock();
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        *cached = None;
+    }
+}
@@ -0,0 +1,86 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+// 时间戳精度
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum TimePrecision {
+    #[default]
+    #[serde(rename = "s")]
+    Seconds,
+    #[serde(rename = "ms")]
+    Milliseconds,
+    #[serde(rename = "us")]
+    Microseconds,
+    #[serde(rename = "ns")]
+    Nanoseconds,
+}
+
+impl TimePrecision {
+    // 每秒包含的单位数
+    pub fn units_per_second(&self) -> u64 {
+        match self {
+            Self::Seconds => 1,
+            Self::Milliseconds => 1_000,
+            Self::Microseconds => 1_000_000,
+            Self::Nanoseconds => 1_000_000_000,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Seconds => "s",
+            Self::Milliseconds => "ms",
+            Self::Microseconds => "us",
+            Self::Nanoseconds => "ns",
+        }
+    }
+
+    // 将本精度的时间戳转换为目标精度。转为更粗的精度时向下取整，
+    // 转为更细的精度溢出u64时返回None
+    pub fn convert(&self, timestamp: u64, target: TimePrecision) -> Option<u64> {
+        let from = self.units_per_second();
+        let to = target.units_per_second();
+        if from >= to {
+            Some(timestamp / (from / to))
+        } else {
+            timestamp.checked_mul(to / from)
+        }
+    }
+
+    // 秒数转换为本精度的单位数
+    pub fn from_secs(&self, secs: u64) -> u64 {
+        secs.saturating_mul(self.units_per_second())
+    }
+
+    pub fn from_duration(&self, duration: Duration) -> u64 {
+        let units = duration.as_nanos() / (1_000_000_000 / self.units_per_second()) as u128;
+        units.min(u64::MAX as u128) as u64
+    }
+
+    pub fn timestamp_of(&self, time: SystemTime) -> u64 {
+        self.from_duration(time.duration_since(UNIX_EPOCH).unwrap_or_default())
+    }
+}
+
+impl FromStr for TimePrecision {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "s" => Ok(Self::Seconds),
+            "ms" => Ok(Self::Milliseconds),
+            "us" => Ok(Self::Microseconds),
+            "ns" => Ok(Self::Nanoseconds),
+            other => Err(format!("不支持的时间精度: {}，可选 s/ms/us/ns", other)),
+        }
+    }
+}
+
+impl fmt::Display for TimePrecision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
@@ -0,0 +1,143 @@
+use std::collections::BTreeMap;
+use std::io::Result;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use super::{DataPoint, DbError};
+
+// 全局预算按KiB计数，信号量一次最多申请 u32::MAX 个许可
+const BUDGET_UNIT_BYTES: usize = 1024;
+// BTreeMap 每个标签项的节点开销估算
+const TAG_ENTRY_OVERHEAD: usize = 48;
+
+// 查询结果的内存记账。每个数据点按结构体大小加标签的堆内存估算，
+// 单次查询的结果超过 max_query_bytes 时失败；所有查询同时持有的预估内存不超过 budget_bytes，
+// 超出时后来的查询排队等待，而不是一起耗尽内存
+#[derive(Debug)]
+pub struct QueryMemory {
+    max_query_bytes: Option<usize>,
+    budget_bytes: Option<usize>,
+    budget: Option<Arc<Semaphore>>,
+    in_use: Arc<AtomicUsize>,
+    waiting: AtomicUsize,
+    rejected: AtomicU64,
+}
+
+// 查询内存的配置和当前用量
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct QueryMemoryStats {
+    pub max_query_bytes: Option<usize>,
+    pub budget_bytes: Option<usize>,
+    // 正在执行的查询预留的字节数
+    pub in_use_bytes: usize,
+    // 等待全局预算的查询数
+    pub waiting_queries: usize,
+    // 因结果超过单次查询上限而失败的查询数
+    pub rejected_queries: u64,
+}
+
+// 查询从全局预算中预留的内存，释放时归还
+#[derive(Debug)]
+pub struct QueryReservation {
+    bytes: usize,
+    in_use: Arc<AtomicUsize>,
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl QueryReservation {
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+}
+
+impl Drop for QueryReservation {
+    fn drop(&mut self) {
+        self.in_use.fetch_sub(self.bytes, Ordering::Relaxed);
+    }
+}
+
+impl QueryMemory {
+    pub fn new(max_query_bytes: Option<usize>, budget_bytes: Option<usize>) -> Self {
+        let budget = budget_bytes.map(|bytes| Arc::new(Semaphore::new(budget_units(bytes).min(Semaphore::MAX_PERMITS))));
+        Self {
+            max_query_bytes,
+            budget_bytes,
+            budget,
+            in_use: Arc::new(AtomicUsize::new(0)),
+            waiting: AtomicUsize::new(0),
+            rejected: AtomicU64::new(0),
+        }
+    }
+
+    // 结果中一个带指定标签的数据点的预估内存
+    pub fn point_bytes(tags: &BTreeMap<String, String>) -> usize {
+        std::mem::size_of::<DataPoint>()
+            + tags.iter().map(|(key, value)| key.len() + value.len() + TAG_ENTRY_OVERHEAD).sum::<usize>()
+    }
+
+    // 按结果大小的上界预留内存，不超过单次查询上限和全局预算；预算不足时等待其他查询释放。
+    // 每个查询只预留一次，不会出现多个查询各持有一部分预算互相等待；排队按先来先得，大查询不会被小查询饿死
+    pub async fn reserve(&self, estimated_bytes: usize) -> Result<QueryReservation> {
+        let bytes = self
+            .max_query_bytes
+            .map_or(estimated_bytes, |max| estimated_bytes.min(max))
+            .min(self.budget_bytes.unwrap_or(usize::MAX));
+        let permit = match &self.budget {
+            Some(budget) => {
+                let units = u32::try_from(budget_units(bytes)).unwrap_or(u32::MAX);
+                let _waiting = Waiting::enter(&self.waiting);
+                Some(Arc::clone(budget).acquire_many_owned(units).await.map_err(std::io::Error::other)?)
+            }
+            None => None,
+        };
+        self.in_use.fetch_add(bytes, Ordering::Relaxed);
+        Ok(QueryReservation { bytes, in_use: Arc::clone(&self.in_use), _permit: permit })
+    }
+
+    // 已收集的结果是否超过单次查询上限
+    pub fn exceeds(&self, bytes: usize) -> bool {
+        self.max_query_bytes.is_some_and(|max| bytes > max)
+    }
+
+    // 结果超过单次查询上限时的错误
+    pub fn too_large(&self, bytes: usize) -> std::io::Error {
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+        DbError::ResultTooLarge {
+            estimated_bytes: bytes,
+            limit_bytes: self.max_query_bytes.unwrap_or_default(),
+        }
+        .into()
+    }
+
+    pub fn stats(&self) -> QueryMemoryStats {
+        QueryMemoryStats {
+            max_query_bytes: self.max_query_bytes,
+            budget_bytes: self.budget_bytes,
+            in_use_bytes: self.in_use.load(Ordering::Relaxed),
+            waiting_queries: self.waiting.load(Ordering::Relaxed),
+            rejected_queries: self.rejected.load(Ordering::Relaxed),
+        }
+    }
+}
+
+// 排队中的查询计数，请求被取消时同样减去
+struct Waiting<'a>(&'a AtomicUsize);
+
+impl<'a> Waiting<'a> {
+    fn enter(count: &'a AtomicUsize) -> Self {
+        count.fetch_add(1, Ordering::Relaxed);
+        Self(count)
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+fn budget_units(bytes: usize) -> usize {
+    bytes.div_ceil(BUDGET_UNIT_BYTES)
+}
@@ -0,0 +1,199 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use super::DbError;
+
+// 超出配额时的处理方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QuotaAction {
+    // 拒绝写入，返回 QuotaExceeded
+    #[default]
+    Reject,
+    // 照常写入，刷新后从该前缀最旧的数据开始淘汰
+    Evict,
+}
+
+// 一个系列键前缀下所有系列的存储上限。点数包含内存表中尚未刷新的点，字节数只统计SSTable中的压缩块
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QuotaRule {
+    // 空字符串匹配所有系列
+    pub prefix: String,
+    #[serde(default)]
+    pub max_points: Option<u64>,
+    #[serde(default)]
+    pub max_bytes: Option<u64>,
+    #[serde(default)]
+    pub action: QuotaAction,
+}
+
+impl QuotaRule {
+    pub fn matches(&self, series_key: &str) -> bool {
+        series_key.starts_with(self.prefix.as_str())
+    }
+
+    fn exceeded_by(&self, usage: QuotaUsage) -> bool {
+        self.max_points.is_some_and(|max| usage.points > max) || self.max_bytes.is_some_and(|max| usage.bytes > max)
+    }
+}
+
+// 配额策略：系列匹配多条规则时每条都要满足
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct QuotaPolicy {
+    #[serde(default)]
+    pub rules: Vec<QuotaRule>,
+}
+
+impl QuotaPolicy {
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuotaUsage {
+    pub points: u64,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct QuotaStatus {
+    #[serde(flatten)]
+    pub rule: QuotaRule,
+    pub usage: QuotaUsage,
+    pub exceeded: bool,
+}
+
+#[derive(Debug, Default)]
+struct QuotaState {
+    policy: QuotaPolicy,
+    // 与 policy.rules 一一对应：SSTable中的用量，以及内存表中尚未刷新的点数
+    stored: Vec<QuotaUsage>,
+    pending: Vec<u64>,
+}
+
+impl QuotaState {
+    fn usage(&self, index: usize) -> QuotaUsage {
+        QuotaUsage {
+            points: self.stored[index].points + self.pending[index],
+            bytes: self.stored[index].bytes,
+        }
+    }
+}
+
+// 缓存的配额用量。写入时只检查和累加计数；刷新时按写出的块累加，
+// 删除后的用量标记为过期，在下次刷新或compaction时从SSTable重新统计
+#[derive(Debug, Default)]
+pub struct QuotaTracker {
+    state: Mutex<QuotaState>,
+    stale: AtomicBool,
+}
+
+impl QuotaTracker {
+    pub fn policy(&self) -> QuotaPolicy {
+        self.state.lock().policy.clone()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.state.lock().policy.is_empty()
+    }
+
+    // 替换策略和全部用量，用量按 series_usage 中每个系列的 (系列键, SSTable用量, 内存表点数) 统计
+    pub fn reset<'a>(&self, policy: Option<QuotaPolicy>, series_usage: impl Iterator<Item = (&'a str, QuotaUsage, u64)>) {
+        let mut state = self.state.lock();
+        if let Some(policy) = policy {
+            state.policy = policy;
+        }
+        let rules = state.policy.rules.len();
+        let mut stored = vec![QuotaUsage::default(); rules];
+        let mut pending = vec![0; rules];
+        for (series_key, usage, memtable_points) in series_usage {
+            for (index, rule) in state.policy.rules.iter().enumerate() {
+                if rule.matches(series_key) {
+                    stored[index].points += usage.points;
+                    stored[index].bytes += usage.bytes;
+                    pending[index] += memtable_points;
+                }
+            }
+        }
+        state.stored = stored;
+        state.pending = pending;
+        self.stale.store(false, Ordering::Relaxed);
+    }
+
+    // 写入前检查 reject 规则，通过后为所有匹配的规则累加点数
+    pub fn reserve(&self, series_key: &str, points: u64) -> Result<(), DbError> {
+        let mut state = self.state.lock();
+        for (index, rule) in state.policy.rules.iter().enumerate() {
+            if rule.action != QuotaAction::Reject || !rule.matches(series_key) {
+                continue;
+            }
+            let usage = state.usage(index);
+            let requested = QuotaUsage { points: usage.points + points, ..usage };
+            if rule.exceeded_by(requested) {
+                return Err(DbError::QuotaExceeded {
+                    prefix: rule.prefix.clone(),
+                    usage,
+                    max_points: rule.max_points,
+                    max_bytes: rule.max_bytes,
+                });
+            }
+        }
+        let QuotaState { policy, pending, .. } = &mut *state;
+        for (index, rule) in policy.rules.iter().enumerate() {
+            if rule.matches(series_key) {
+                pending[index] += points;
+            }
+        }
+        Ok(())
+    }
+
+    // 释放 reserve 占用的点数，用于占用之后写入失败的情况
+    pub fn release(&self, series_key: &str, points: u64) {
+        self.record_flushed(series_key, points, QuotaUsage::default());
+    }
+
+    // 内存表中的 memtable_points 个点刷新为占用 usage 的块；不经过内存表的写入 memtable_points 为0
+    pub fn record_flushed(&self, series_key: &str, memtable_points: u64, usage: QuotaUsage) {
+        let mut state = self.state.lock();
+        let QuotaState { policy, stored, pending } = &mut *state;
+        for (index, rule) in policy.rules.iter().enumerate() {
+            if rule.matches(series_key) {
+                pending[index] = pending[index].saturating_sub(memtable_points);
+                stored[index].points += usage.points;
+                stored[index].bytes += usage.bytes;
+            }
+        }
+    }
+
+    pub fn mark_stale(&self) {
+        self.stale.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_stale(&self) -> bool {
+        self.stale.load(Ordering::Relaxed)
+    }
+
+    pub fn status(&self) -> Vec<QuotaStatus> {
+        let state = self.state.lock();
+        state
+            .policy
+            .rules
+            .iter()
+            .enumerate()
+            .map(|(index, rule)| {
+                let usage = state.usage(index);
+                QuotaStatus { rule: rule.clone(), usage, exceeded: rule.exceeded_by(usage) }
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct QuotaReport {
+    // 用量是否可能偏高：删除后尚未刷新或compaction
+    pub stale: bool,
+    pub quotas: Vec<QuotaStatus>,
+}
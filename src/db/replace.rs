@@ -0,0 +1,117 @@
+use std::io::{ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use super::{decode_series_list, staged_path, PendingReplace, SSTable, SeriesData};
+
+// 包含被替换系列的一个旧文件。去掉该系列后的内容已写入暂存文件，没有其他系列时直接删除
+#[derive(Debug)]
+pub struct StagedRewrite {
+    pub path: PathBuf,
+    // 读取时文件的大小和修改时间，安装前据此确认期间没有被改写或移动
+    len: u64,
+    modified: Option<SystemTime>,
+    // 改写后的内容，为空时删除该文件
+    pub retained: Vec<SeriesData>,
+    pub removed_points: usize,
+}
+
+impl StagedRewrite {
+    pub fn file_name(&self) -> String {
+        file_name(&self.path)
+    }
+
+    pub fn is_unchanged(&self, sstable: &SSTable) -> bool {
+        sstable.file_path() == self.path
+            && std::fs::metadata(&self.path).is_ok_and(|now| now.len() == self.len && now.modified().ok() == self.modified)
+    }
+}
+
+// 系列替换暂存的全部文件：新系列数据写入 sstable 的暂存文件，旧文件的改写见 rewrites
+#[derive(Debug)]
+pub struct ReplacePlan {
+    pub series_key: String,
+    pub sstable: SSTable,
+    pub blocks: Vec<SeriesData>,
+    pub rewrites: Vec<StagedRewrite>,
+}
+
+impl ReplacePlan {
+    // 写出所有暂存文件，existing 为可能包含该系列的旧文件。失败时删除已写出的暂存文件
+    pub fn stage(series_key: String, sstable: SSTable, blocks: Vec<SeriesData>, existing: Vec<PathBuf>) -> Result<Self> {
+        let mut plan = Self { series_key, sstable, blocks, rewrites: Vec::new() };
+        match plan.write_files(existing) {
+            Ok(()) => Ok(plan),
+            Err(e) => {
+                plan.discard();
+                Err(e)
+            }
+        }
+    }
+
+    fn write_files(&mut self, existing: Vec<PathBuf>) -> Result<()> {
+        self.sstable.write_staged(&self.blocks)?;
+        for path in existing {
+            let metadata = std::fs::metadata(&path)?;
+            let series_list = decode_series_list(&std::fs::read(&path)?)?;
+            let (removed, retained): (Vec<SeriesData>, Vec<SeriesData>) =
+                series_list.into_iter().partition(|series| series.series_key == self.series_key);
+            if removed.is_empty() {
+                continue;
+            }
+            let rewrite = StagedRewrite {
+                path,
+                len: metadata.len(),
+                modified: metadata.modified().ok(),
+                retained,
+                removed_points: removed.iter().map(|series| series.count).sum(),
+            };
+            // 先登记再写，写到一半失败时 discard 同样会删除
+            self.rewrites.push(rewrite);
+            let rewrite = &self.rewrites[self.rewrites.len() - 1];
+            if !rewrite.retained.is_empty() {
+                SSTable::new(rewrite.path.clone())?.write_staged(&rewrite.retained)?;
+            }
+        }
+        Ok(())
+    }
+
+    // 提交记录。新系列的文件排在最前，启动时以它是否存在判断替换是否已经放弃
+    pub fn pending(&self) -> PendingReplace {
+        let rewritten = self.rewrites.iter().filter(|rewrite| !rewrite.retained.is_empty());
+        let removed = self.rewrites.iter().filter(|rewrite| rewrite.retained.is_empty());
+        PendingReplace {
+            series_key: self.series_key.clone(),
+            staged: std::iter::once(self.sstable.file_name()).chain(rewritten.map(StagedRewrite::file_name)).collect(),
+            removed: removed.map(StagedRewrite::file_name).collect(),
+        }
+    }
+
+    // 第一个读取之后被改写或移动的旧文件，sstables 为当前的SSTable列表
+    pub fn modified_file(&self, sstables: &[SSTable]) -> Option<String> {
+        self.rewrites
+            .iter()
+            .find(|rewrite| {
+                let current = sstables.iter().find(|sstable| sstable.file_name() == rewrite.file_name());
+                !current.is_some_and(|sstable| rewrite.is_unchanged(sstable))
+            })
+            .map(StagedRewrite::file_name)
+    }
+
+    // 放弃替换，删除暂存文件。新系列的暂存文件最先删除，中途中断时启动过程同样视为放弃
+    pub fn discard(&self) {
+        let rewritten = self.rewrites.iter().filter(|rewrite| !rewrite.retained.is_empty()).map(|rewrite| rewrite.path.as_path());
+        for path in std::iter::once(self.sstable.file_path()).chain(rewritten) {
+            let staged = staged_path(path);
+            if let Err(e) = std::fs::remove_file(&staged) {
+                if e.kind() != ErrorKind::NotFound {
+                    tracing::warn!("删除暂存文件 {:?} 失败: {}", staged, e);
+                }
+            }
+        }
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default()
+}
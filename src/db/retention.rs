@@ -0,0 +1,60 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+// 系列匹配条件
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SeriesMatcher {
+    // 系列键前缀
+    Prefix(String),
+    // 标签需全部匹配
+    Tags(BTreeMap<String, String>),
+}
+
+impl SeriesMatcher {
+    pub fn matches(&self, series_key: &str, tags: &BTreeMap<String, String>) -> bool {
+        match self {
+            Self::Prefix(prefix) => series_key.starts_with(prefix.as_str()),
+            Self::Tags(expected) => expected.iter().all(|(k, v)| tags.get(k) == Some(v)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RetentionRule {
+    #[serde(rename = "match")]
+    pub matcher: SeriesMatcher,
+    pub max_age_secs: u64,
+}
+
+// 保留策略：规则按顺序匹配，第一个命中的规则生效，未命中时使用全局默认值
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RetentionPolicy {
+    #[serde(default)]
+    pub rules: Vec<RetentionRule>,
+    #[serde(default)]
+    pub default_max_age_secs: Option<u64>,
+}
+
+impl RetentionPolicy {
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty() && self.default_max_age_secs.is_none()
+    }
+
+    pub fn max_age_for(&self, series_key: &str, tags: &BTreeMap<String, String>) -> Option<u64> {
+        self.rules
+            .iter()
+            .find(|rule| rule.matcher.matches(series_key, tags))
+            .map(|rule| rule.max_age_secs)
+            .or(self.default_max_age_secs)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RetentionReport {
+    pub series_trimmed: usize,
+    pub points_removed: usize,
+    // 受删除保护而跳过的系列
+    pub protected_skipped: Vec<String>,
+}
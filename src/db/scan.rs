@@ -0,0 +1,117 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use super::compression::format::block_decoder;
+use super::SeriesData;
+
+type PointIter = Box<dyn Iterator<Item = (u64, f64)> + Send>;
+
+// 按时间戳归并多个有序数据源（内存表、各SSTable块），逐点解压，内存占用与数据量无关。
+// 每个数据源的下一个点放在小顶堆中，每输出一个点的开销为 O(log k)，k 为数据源个数。
+// 时间戳相同时保留靠前数据源的值，与 query_range 的去重规则一致。
+pub struct MergedPoints {
+    sources: Vec<PointIter>,
+    // 每个数据源尚未输出的下一个点
+    heads: Vec<f64>,
+    heap: BinaryHeap<Reverse<(u64, usize)>>,
+    estimated_len: usize,
+    duplicates_resolved: usize,
+}
+
+impl MergedPoints {
+    pub fn new() -> Self {
+        Self {
+            sources: Vec::new(),
+            heads: Vec::new(),
+            heap: BinaryHeap::new(),
+            estimated_len: 0,
+            duplicates_resolved: 0,
+        }
+    }
+
+    pub fn push_source<I>(&mut self, points: I)
+    where
+        I: Iterator<Item = (u64, f64)> + Send + 'static,
+    {
+        self.estimated_len += points.size_hint().0;
+        self.add(Box::new(points));
+    }
+
+    // 添加一个压缩块，只输出 [start_time, end_time] 内的点
+    pub fn push_block(&mut self, block: SeriesData, start_time: Option<u64>, end_time: Option<u64>) {
+        let count = block.count;
+        let decoder = match block_decoder(block.compressed_data) {
+            Ok(decoder) => decoder,
+            Err(e) => {
+                tracing::warn!("跳过无法解码的块 {}: {}", block.series_key, e);
+                return;
+            }
+        };
+        self.estimated_len += count;
+        let points = decoder
+            .take(count)
+            .filter(move |(ts, _)| {
+                start_time.is_none_or(|start| *ts >= start) && end_time.is_none_or(|end| *ts <= end)
+            });
+        self.add(Box::new(points));
+    }
+
+    // 空的数据源不参与归并
+    fn add(&mut self, mut points: PointIter) {
+        if let Some((timestamp, value)) = points.next() {
+            self.heap.push(Reverse((timestamp, self.sources.len())));
+            self.sources.push(points);
+            self.heads.push(value);
+        }
+    }
+
+    // 取出 index 数据源的下一个点放回堆中
+    fn advance(&mut self, index: usize) {
+        if let Some((timestamp, value)) = self.sources[index].next() {
+            self.heads[index] = value;
+            self.heap.push(Reverse((timestamp, index)));
+        }
+    }
+
+    // 各数据源的点数之和（块按记录的点数计），用于估算扫描的开销；未去重、未按时间范围过滤
+    pub fn estimated_len(&self) -> usize {
+        self.estimated_len
+    }
+
+    // 参与归并的非空数据源个数
+    pub fn sources_merged(&self) -> usize {
+        self.sources.len()
+    }
+
+    // 到目前为止因时间戳重复而丢弃的点数
+    pub fn duplicates_resolved(&self) -> usize {
+        self.duplicates_resolved
+    }
+}
+
+impl Default for MergedPoints {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Iterator for MergedPoints {
+    type Item = (u64, f64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Reverse((timestamp, index)) = self.heap.pop()?;
+        let value = self.heads[index];
+        self.advance(index);
+
+        // 丢弃所有数据源（包括同一数据源）中相同时间戳的点
+        while let Some(&Reverse((other, other_index))) = self.heap.peek() {
+            if other != timestamp {
+                break;
+            }
+            self.heap.pop();
+            self.duplicates_resolved += 1;
+            self.advance(other_index);
+        }
+        Some((timestamp, value))
+    }
+}
@@ -0,0 +1,86 @@
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+// 由系列键派生文件名。数据目录中的文件名目前都不包含系列键，以后按系列建文件的功能（单系列文件、部分刷新等）
+// 必须通过这里生成文件名，任何键都不会逃出数据目录。
+// 字母、数字、'-' 和 '_' 原样保留，其余字节（包括 '.'、'/'、'\\'、'%' 和 NUL）编码为 %XX，结果只含安全字符且可以还原；
+// 空键编码为单独的 "%"。编码后超过 MAX_FILE_STEM_BYTES 的键改用截断的前缀加 '~' 和键的哈希，
+// 这种文件名无法还原，使用方需要另外保存键到文件的映射
+pub const MAX_FILE_STEM_BYTES: usize = 200;
+
+const HASHED_MARKER: char = '~';
+const EMPTY_KEY_STEM: &str = "%";
+
+pub fn encode_series_file_stem(series_key: &str) -> String {
+    if series_key.is_empty() {
+        return EMPTY_KEY_STEM.to_string();
+    }
+
+    let mut stem = String::with_capacity(series_key.len());
+    for byte in series_key.bytes() {
+        if byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_' {
+            stem.push(byte as char);
+        } else {
+            let _ = write!(stem, "%{:02X}", byte);
+        }
+    }
+    if stem.len() <= MAX_FILE_STEM_BYTES {
+        return stem;
+    }
+
+    // 截断时不拆开 %XX
+    let mut cut = MAX_FILE_STEM_BYTES - 17;
+    while stem[..cut].rfind('%').is_some_and(|pos| pos + 3 > cut) {
+        cut -= 1;
+    }
+    stem.truncate(cut);
+    let _ = write!(stem, "{}{:016x}", HASHED_MARKER, fnv1a64(series_key.as_bytes()));
+    stem
+}
+
+// 还原系列键。哈希形式或不是由 encode_series_file_stem 生成的文件名返回 None
+pub fn decode_series_file_stem(stem: &str) -> Option<String> {
+    if stem == EMPTY_KEY_STEM {
+        return Some(String::new());
+    }
+
+    let bytes = stem.as_bytes();
+    let mut key = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let hex = stem.get(i + 1..i + 3)?;
+                key.push(u8::from_str_radix(hex, 16).ok()?);
+                i += 3;
+            }
+            byte if byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_' => {
+                key.push(byte);
+                i += 1;
+            }
+            _ => return None,
+        }
+    }
+    // 只接受编码时会产生的形式，保证一个键只对应一个文件名
+    String::from_utf8(key).ok().filter(|key| encode_series_file_stem(key) == stem)
+}
+
+// 数据目录中属于该系列的文件路径，总是数据目录的直接子项
+pub fn series_file_path(data_dir: &Path, series_key: &str, extension: &str) -> PathBuf {
+    let mut file_name = encode_series_file_stem(series_key);
+    if !extension.is_empty() {
+        file_name.push('.');
+        file_name.push_str(&encode_series_file_stem(extension));
+    }
+    data_dir.join(file_name)
+}
+
+// 文件名中使用的哈希需要跨版本稳定，不能用标准库的 DefaultHasher
+fn fnv1a64(data: &[u8]) -> u64 {
+    let mut hash = 0xcbf29ce484222325u64;
+    for &byte in data {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
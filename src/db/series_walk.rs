@@ -0,0 +1,137 @@
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind, Result};
+use std::path::PathBuf;
+
+use super::compression::format::{block_decoder, decode_ingest_times};
+use super::{MergedPoints, SSTable, SeriesData};
+
+// for_each_series 交给回调的系列信息
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SeriesMetadata {
+    pub tags: BTreeMap<String, String>,
+    pub first_seen: Option<u64>,
+    pub last_timestamp: Option<u64>,
+    // 系列在所有SSTable中的压缩块数量（不含内存表中的点）
+    pub blocks: usize,
+}
+
+// 遍历中的一个系列：内存表中的点和各SSTable中尚未解压的块
+pub struct SeriesEntry {
+    pub series_key: String,
+    memtable: Vec<(u64, f64)>,
+    blocks: Vec<SeriesData>,
+}
+
+impl SeriesEntry {
+    pub fn block_count(&self) -> usize {
+        self.blocks.len()
+    }
+
+    // 各SSTable中的块（未解压），按加入顺序排列
+    pub fn blocks(&self) -> &[SeriesData] {
+        &self.blocks
+    }
+
+    // 逐块解压，检查时间戳在块记录的范围内、点数与记录一致、写入时间流可以解码，
+    // 返回检查的点数。不检查内存表中的点
+    pub fn verify(&self) -> Result<usize> {
+        let invalid = |message: String| Error::new(ErrorKind::InvalidData, message);
+        let mut points = 0;
+        for block in &self.blocks {
+            let mut decoder = block_decoder(block.compressed_data.clone())?;
+            let mut count = 0;
+            for (ts, _) in decoder.by_ref() {
+                if ts < block.min_timestamp || ts > block.max_timestamp {
+                    return Err(invalid(format!(
+                        "系列 {} 的块中时间戳 {} 超出记录的范围 [{}, {}]",
+                        self.series_key, ts, block.min_timestamp, block.max_timestamp
+                    )));
+                }
+                count += 1;
+            }
+            decoder.check().map_err(|e| invalid(format!("系列 {} 的{}", self.series_key, e)))?;
+            if count != block.count {
+                return Err(invalid(format!(
+                    "系列 {} 的块记录 {} 个数据点，解压得到 {} 个",
+                    self.series_key, block.count, count
+                )));
+            }
+            decode_ingest_times(&block.ingest_data)
+                .map_err(|e| invalid(format!("系列 {} 的写入时间流损坏: {}", self.series_key, e)))?;
+            points += count;
+        }
+        Ok(points)
+    }
+
+    // 按时间戳归并全部数据，逐块解压；去重规则与 query_range 一致（内存表优先，其次按文件顺序）
+    pub fn points(self) -> MergedPoints {
+        let mut merged = MergedPoints::new();
+        merged.push_source(self.memtable.into_iter());
+        for block in self.blocks {
+            merged.push_block(block, None, None);
+        }
+        merged
+    }
+}
+
+// 按系列键顺序遍历所有系列。每个SSTable的块列表只读取一次并按系列键归组，
+// 块在取出对应系列时才解压，全库遍历不再对每个系列重新读取所有文件。
+#[derive(Default)]
+pub struct SeriesWalk {
+    series: BTreeMap<String, SeriesEntry>,
+}
+
+impl SeriesWalk {
+    // memtable 为内存表快照，每个系列的点已按时间戳排序
+    pub fn new(memtable: BTreeMap<String, Vec<(u64, f64)>>) -> Self {
+        let series = memtable
+            .into_iter()
+            .map(|(series_key, points)| {
+                let entry = SeriesEntry { series_key: series_key.clone(), memtable: points, blocks: Vec::new() };
+                (series_key, entry)
+            })
+            .collect();
+        Self { series }
+    }
+
+    // 只包含给定SSTable文件中的块，不经过数据库的SSTable列表（导出快照、完整性校验）
+    pub fn from_files(files: &[PathBuf]) -> Result<Self> {
+        let mut walk = Self::default();
+        for path in files {
+            walk.add_blocks(SSTable::new(path.clone())?.load_series_list()?);
+        }
+        Ok(walk)
+    }
+
+    // 加入一个SSTable的全部块，先加入的文件在时间戳重复时优先
+    pub fn add_blocks(&mut self, blocks: Vec<SeriesData>) {
+        for block in blocks {
+            self.series
+                .entry(block.series_key.clone())
+                .or_insert_with(|| SeriesEntry {
+                    series_key: block.series_key.clone(),
+                    memtable: Vec::new(),
+                    blocks: Vec::new(),
+                })
+                .blocks
+                .push(block);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.series.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.series.is_empty()
+    }
+}
+
+impl IntoIterator for SeriesWalk {
+    type Item = SeriesEntry;
+    type IntoIter = std::collections::btree_map::IntoValues<String, SeriesEntry>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.series.into_values()
+    }
+}
@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use tokio::sync::OnceCell;
+
+// 合并并发的相同请求：同一个key同时只执行一次，其余调用者等待并共享结果。
+// 执行完成后立即移除，不缓存结果。
+#[derive(Debug)]
+pub struct SingleFlight<K, V> {
+    inflight: Mutex<HashMap<K, Arc<OnceCell<V>>>>,
+    shared: AtomicU64,
+}
+
+impl<K, V> Default for SingleFlight<K, V> {
+    fn default() -> Self {
+        Self {
+            inflight: Mutex::new(HashMap::new()),
+            shared: AtomicU64::new(0),
+        }
+    }
+}
+
+// 领头的调用结束（包括被取消）时移除key，避免残留的结果被后续请求当作缓存
+struct InflightGuard<'a, K: Hash + Eq, V> {
+    flight: &'a SingleFlight<K, V>,
+    key: &'a K,
+    cell: &'a Arc<OnceCell<V>>,
+}
+
+impl<K: Hash + Eq, V> Drop for InflightGuard<'_, K, V> {
+    fn drop(&mut self) {
+        let mut inflight = self.flight.inflight.lock();
+        if inflight.get(self.key).is_some_and(|cell| Arc::ptr_eq(cell, self.cell)) {
+            inflight.remove(self.key);
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> SingleFlight<K, V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub async fn run<F, Fut>(&self, key: K, f: F) -> V
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = V>,
+    {
+        let (cell, leader) = {
+            let mut inflight = self.inflight.lock();
+            match inflight.get(&key) {
+                Some(cell) => (Arc::clone(cell), false),
+                None => {
+                    let cell = Arc::new(OnceCell::new());
+                    inflight.insert(key.clone(), Arc::clone(&cell));
+                    (cell, true)
+                }
+            }
+        };
+
+        if leader {
+            let _guard = InflightGuard { flight: self, key: &key, cell: &cell };
+            return cell.get_or_init(f).await.clone();
+        }
+
+        // 领头的调用被取消时由等待者之一接手执行
+        let mut executed = false;
+        let value = cell
+            .get_or_init(|| {
+                executed = true;
+                f()
+            })
+            .await
+            .clone();
+        if !executed {
+            self.shared.fetch_add(1, Ordering::Relaxed);
+        }
+        value
+    }
+
+    // 通过共享结果省掉的执行次数
+    pub fn shared_count(&self) -> u64 {
+        self.shared.load(Ordering::Relaxed)
+    }
+}
@@ -1,37 +1,201 @@
 use std::fs::{File, OpenOptions};
-use std::io::{Result, Write};
-use std::path::PathBuf;
-use memmap2::Mmap;
+use std::collections::BTreeMap;
+use std::io::{Read, Result, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-use super::{DataPoint, GorillaDecompressor, GorillaCompressor, SeriesData};
+use super::{block_compression, decode_series_list, encode_series_list_with, sstable_encoding, BlockCompression, SSTableEncoding, BoundsMismatch, MappedFile, MappingCache, SSTableInfo, DataPoint, SeriesData};
+use super::compression::format::{block_decoder, decode_ingest_times, decode_versioned, encode_block_v2, encode_ingest_times};
+
+// 单个系列块的最大数据点数，超过时拆分为多个块
+pub const MAX_POINTS_PER_BLOCK: usize = 100_000;
+
+// 暂存文件的后缀。暂存文件不会在启动时加载，改名去掉后缀后才成为正式的SSTable
+pub const STAGED_SUFFIX: &str = ".staged";
+
+// SSTable文件对应的暂存文件路径
+pub fn staged_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(STAGED_SUFFIX);
+    PathBuf::from(name)
+}
 
 #[derive(Debug)]
 pub struct SSTable {
     file_path: PathBuf,
-    mmap: Option<Mmap>,
+    // 内存映射由缓存统一管理，数据库中的所有SSTable共用一个缓存
+    mappings: Arc<MappingCache>,
+    // 写入时使用的通用压缩算法，未指定时沿用文件原有的算法
+    compression: Option<BlockCompression>,
+    // 写入时使用的块序列化方式，未指定时沿用文件原有的方式
+    encoding: Option<SSTableEncoding>,
+    info: SSTableInfo,
 }
 
 impl SSTable {
     pub fn new(file_path: PathBuf) -> Result<Self> {
-        Ok(Self {
+        let mut sstable = Self {
             file_path,
-            mmap: None,
-        })
+            mappings: Arc::new(MappingCache::unbounded()),
+            compression: None,
+            encoding: None,
+            info: SSTableInfo::default(),
+        };
+        sstable.info.file_name = sstable.file_name();
+        sstable.info.sequence = sstable.sequence();
+        Ok(sstable)
+    }
+
+    pub fn with_compression(mut self, compression: BlockCompression) -> Self {
+        self.compression = Some(compression);
+        self
+    }
+
+    pub fn with_encoding(mut self, encoding: SSTableEncoding) -> Self {
+        self.encoding = Some(encoding);
+        self
+    }
+
+    pub fn with_mapping_cache(mut self, mappings: Arc<MappingCache>) -> Self {
+        self.mappings = mappings;
+        self
+    }
+
+    // 缓存的元数据摘要
+    pub fn info(&self) -> &SSTableInfo {
+        &self.info
+    }
+
+    // 用已读出的块元数据更新摘要，文件大小和压缩方式从文件读取。写入时间不变，
+    // 由写入文件时记录，启动时从manifest恢复
+    pub fn record_blocks(&mut self, blocks: &[SeriesData]) {
+        self.info.size_bytes = self.file_size();
+        self.info.compression = self.compression.unwrap_or_else(|| self.compression());
+        self.info.encoding = self.encoding.unwrap_or_else(|| self.encoding());
+        self.info.summarize(blocks);
+    }
+
+    // 启动或导入时设置写入时间，没有记录时（旧版本写出的文件）按文件的修改时间
+    pub fn set_created_at(&mut self, created_at: Option<u64>) {
+        self.info.created_at = created_at.or_else(|| {
+            std::fs::metadata(&self.file_path)
+                .and_then(|m| m.modified())
+                .ok()
+                .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+                .map(|age| age.as_secs())
+        });
+    }
+
+    // 写入文件后更新摘要并记录写入时间
+    fn record_written(&mut self, blocks: &[SeriesData]) {
+        self.record_blocks(blocks);
+        self.info.created_at = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .ok()
+            .map(|age| age.as_secs());
+    }
+
+    // 启动时恢复manifest中记录的已删除点数
+    pub fn set_deleted_points(&mut self, deleted_points: usize) {
+        self.info.deleted_points = deleted_points;
+    }
+
+    // compaction改写文件后不再保留已删除的点数
+    pub fn reset_deleted_points(&mut self) {
+        self.info.deleted_points = 0;
+    }
+
+    pub fn record_read_error(&mut self, error: &std::io::Error) {
+        self.info.size_bytes = self.file_size();
+        self.info.read_error = Some(error.to_string());
+    }
+
+    // 文件当前使用的压缩算法，从文件头读取
+    pub fn compression(&self) -> BlockCompression {
+        self.read_header().map_or(BlockCompression::None, |header| block_compression(&header))
+    }
+
+    // 文件当前使用的块序列化方式，从文件头读取
+    pub fn encoding(&self) -> SSTableEncoding {
+        self.read_header().map_or(SSTableEncoding::Bincode, |header| sstable_encoding(&header))
+    }
+
+    fn read_header(&self) -> Result<Vec<u8>> {
+        let mut header = Vec::new();
+        File::open(&self.file_path).and_then(|file| file.take(16).read_to_end(&mut header))?;
+        Ok(header)
+    }
+
+    pub fn file_path(&self) -> &std::path::Path {
+        &self.file_path
+    }
+
+    // 文件已被移动到新的数据目录，之后的读写使用新路径
+    pub fn set_file_path(&mut self, file_path: PathBuf) {
+        self.mappings.invalidate(&self.file_path);
+        self.file_path = file_path;
+    }
+
+    pub fn file_name(&self) -> String {
+        self.file_path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+
+    // 文件名中的序号（"前缀_时间戳.序号.data"），按创建顺序递增；旧命名的文件没有序号
+    pub fn sequence(&self) -> Option<u64> {
+        let stem = self.file_path.file_stem()?.to_str()?;
+        stem.rsplit_once('.')?.1.parse().ok()
+    }
+
+    // SSTable列表的排序键：没有序号的旧文件在前并按文件名排序，其余按序号
+    pub fn order_key(&self) -> (Option<u64>, String) {
+        (self.sequence(), self.file_name())
+    }
+
+    pub fn file_size(&self) -> u64 {
+        std::fs::metadata(&self.file_path).map(|m| m.len()).unwrap_or(0)
+    }
+
+    // 读取全部系列块（仅反序列化，不解压）
+    pub fn load_series_list(&mut self) -> Result<Vec<SeriesData>> {
+        let data = self.read_with_mmap()?;
+        decode_series_list(&data)
+    }
+
+    // 取出与时间范围相交的系列块（不解压），供流式扫描使用
+    pub fn series_blocks(&mut self, series_key: &str, start_time: Option<u64>, end_time: Option<u64>) -> Result<Vec<SeriesData>> {
+        Ok(self
+            .load_series_list()?
+            .into_iter()
+            .filter(|series| series.series_key == series_key)
+            .filter(|series| start_time.is_none_or(|start| series.max_timestamp >= start))
+            .filter(|series| end_time.is_none_or(|end| series.min_timestamp <= end))
+            .collect())
     }
 
     pub fn write_data(&mut self, series_data: &[SeriesData]) -> Result<()> {
         // 清除现有的内存映射
-        self.mmap = None;
-        
+        self.mappings.invalidate(&self.file_path);
+
+        // 重写已有文件（删除、更新等）时保持原来的压缩和序列化方式
+        let compression = self.compression.unwrap_or_else(|| self.compression());
+        self.compression = Some(compression);
+        let encoding = self.encoding.unwrap_or_else(|| self.encoding());
+        self.encoding = Some(encoding);
+
+        // 先写临时文件再重命名：重写不会留下写到一半的文件，导出快照中硬链接的旧文件也保持不变
+        let mut tmp_name = self.file_path.as_os_str().to_owned();
+        tmp_name.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_name);
         let mut file = OpenOptions::new()
             .create(true)
             .write(true)
             .truncate(true)
-            .open(&self.file_path)?;
+            .open(&tmp_path)?;
         
-        let serialized = bincode::serialize(series_data)
-            // .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-            .map_err(std::io::Error::other)?;
+        let serialized = encode_series_list_with(series_data, compression, encoding)?;
         
         file.write_all(&serialized)?;
         file.sync_all()?;
@@ -39,48 +203,51 @@ impl SSTable {
         // 确保文件被完全写入并关闭
         drop(file);
         
+        std::fs::rename(&tmp_path, &self.file_path)?;
+        self.record_written(series_data);
         Ok(())
     }
 
-    pub fn read_with_mmap(&mut self) -> Result<&[u8]> {
-        // 如果已有映射，先检查文件是否仍然有效
-        if self.mmap.is_some() && !self.file_path.exists() {
-        self.mmap = None;
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::NotFound,
-            "SSTable file was deleted"
-    ));
-}
+    // 把数据写到暂存文件并fsync，当前文件保持不变，查询照常读取；install_staged 改名后才替换当前文件。
+    // 压缩和序列化方式与 write_data 相同
+    pub fn write_staged(&self, series_data: &[SeriesData]) -> Result<()> {
+        let compression = self.compression.unwrap_or_else(|| self.compression());
+        let encoding = self.encoding.unwrap_or_else(|| self.encoding());
+        let serialized = encode_series_list_with(series_data, compression, encoding)?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(staged_path(&self.file_path))?;
+        file.write_all(&serialized)?;
+        file.sync_all()
+    }
 
-        
-        if self.mmap.is_none() {
-            // 检查文件是否存在且不为空
-            let metadata = std::fs::metadata(&self.file_path)?;
-            if metadata.len() == 0 {
-                return Ok(&[]);
-            }
-            
-            let file = File::open(&self.file_path)?;
-            
-            // 安全地创建内存映射
-            let mmap = unsafe { 
-                match Mmap::map(&file) {
-                    Ok(mmap) => mmap,
-                    Err(e) => {
-                        tracing::error!("Failed to create mmap for {:?}: {}", self.file_path, e);
-                        return Err(e);
-                    }
-                }
-            };
-            
-            self.mmap = Some(mmap);
-        }
-        
-        Ok(self.mmap.as_ref().unwrap())
+    // 把 write_staged 写好的暂存文件改名为当前文件，series_data 为暂存文件的内容，
+    // removed_points 为改写时删除的点数
+    pub fn install_staged(&mut self, series_data: &[SeriesData], removed_points: usize) -> Result<()> {
+        self.mappings.invalidate(&self.file_path);
+        std::fs::rename(staged_path(&self.file_path), &self.file_path)?;
+        self.record_written(series_data);
+        self.info.deleted_points += removed_points;
+        Ok(())
+    }
+
+    pub fn read_with_mmap(&mut self) -> Result<MappedFile> {
+        self.mappings.get(&self.file_path)
+    }
+
+    // 建立内存映射并逐页读取一遍，把文件内容载入页缓存，返回文件字节数
+    pub fn prefetch(&mut self) -> Result<u64> {
+        let data = self.read_with_mmap()?;
+        let checksum = data.iter().step_by(4096).fold(0u8, |acc, byte| acc ^ byte);
+        std::hint::black_box(checksum);
+        Ok(data.len() as u64)
     }
 
     pub fn delete_file(&self) -> Result<()> {
         // 在删除文件前清除内存映射
+        self.mappings.invalidate(&self.file_path);
         if self.file_path.exists() {
             std::fs::remove_file(&self.file_path)?;
         }
@@ -90,7 +257,7 @@ impl SSTable {
     // 安全的删除数据点方法
     pub fn delete_datapoint(&mut self, series_key: &str, timestamp: Option<u64>) -> Result<bool> {
         // 首先释放内存映射
-        self.mmap = None;
+        self.mappings.invalidate(&self.file_path);
         
         // 检查文件是否存在
         if !self.file_path.exists() {
@@ -102,42 +269,45 @@ impl SSTable {
             return Ok(false);
         }
         
-        let mut series_list: Vec<SeriesData> = bincode::deserialize(&data)
-            // .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-            .map_err(std::io::Error::other)?;
+        let mut series_list = decode_series_list(&data)?;
 
         let mut deleted = false;
+        let mut removed_points = 0;
 
         match timestamp {
             Some(ts) => {
+                // 覆盖写入的同一时间戳在compaction前可能出现在多个块中，全部删除并按实际移除的点数计数
                 for series in series_list.iter_mut() {
-                    if series.series_key == series_key {
-                        let decompressor = GorillaDecompressor::new(series.compressed_data.clone());
-                        let mut decompressed_points = decompressor.decompress_all();
-                        let original_len = decompressed_points.len();
-
-                        decompressed_points.retain(|(timestamp, _)| *timestamp != ts);
-
-                        if decompressed_points.len() < original_len {
-                            deleted = true;
-                            
-                            if decompressed_points.is_empty() {
-                                series_list.retain(|s| s.series_key != series_key);
-                            } else {
-                                let mut compressor = GorillaCompressor::new();
-                                for (timestamp, value) in decompressed_points {
-                                    compressor.compress_datapoint(timestamp, value);
-                                }
-                                series.compressed_data = compressor.finish();
-                                series.count -= 1;
-                            }
-                            break;
-                        }
+                    if series.series_key != series_key || ts < series.min_timestamp || ts > series.max_timestamp {
+                        continue;
+                    }
+
+                    let mut decompressed_points = decode_versioned(&series.compressed_data)?;
+                    let original_len = decompressed_points.len();
+
+                    decompressed_points.retain(|(timestamp, _)| *timestamp != ts);
+
+                    if decompressed_points.len() < original_len {
+                        deleted = true;
+                        removed_points += original_len - decompressed_points.len();
+
+                        series.compressed_data = encode_block_v2(&decompressed_points);
+                        retain_ingest_times(series, &decompressed_points)?;
+                        series.count = decompressed_points.len();
+                        series.min_timestamp = decompressed_points.iter().map(|(t, _)| *t).min().unwrap_or(0);
+                        series.max_timestamp = decompressed_points.iter().map(|(t, _)| *t).max().unwrap_or(0);
                     }
                 }
+                // 只移除被删空的块，同一系列的其他块保留
+                series_list.retain(|s| s.count > 0);
             }
             None => {
                 let original_len = series_list.len();
+                removed_points = series_list
+                    .iter()
+                    .filter(|s| s.series_key == series_key)
+                    .map(|s| s.count)
+                    .sum();
                 series_list.retain(|s| s.series_key != series_key);
                 deleted = series_list.len() < original_len;
             }
@@ -150,74 +320,145 @@ impl SSTable {
             } else {
                 // 重写文件
                 self.write_data(&series_list)?;
+                self.info.deleted_points += removed_points;
             }
         }
 
         Ok(deleted)
     }
 
+    // 删除系列在 [start_time, end_time] 范围内的数据点，返回删除数量
+    pub fn delete_range(&mut self, series_key: &str, start_time: Option<u64>, end_time: Option<u64>) -> Result<usize> {
+        self.mappings.invalidate(&self.file_path);
+
+        if !self.file_path.exists() {
+            return Ok(0);
+        }
+
+        let data = std::fs::read(&self.file_path)?;
+        if data.is_empty() {
+            return Ok(0);
+        }
+
+        let mut series_list = decode_series_list(&data)?;
+
+        let in_range = |ts: u64| {
+            start_time.is_none_or(|start| ts >= start) && end_time.is_none_or(|end| ts <= end)
+        };
+
+        let mut removed = 0;
+        for series in series_list.iter_mut() {
+            if series.series_key != series_key {
+                continue;
+            }
+            // 元数据范围不相交时无需解压
+            if start_time.is_some_and(|start| series.max_timestamp < start)
+                || end_time.is_some_and(|end| series.min_timestamp > end)
+            {
+                continue;
+            }
+
+            let mut points = decode_versioned(&series.compressed_data)?;
+            let original_len = points.len();
+            points.retain(|(ts, _)| !in_range(*ts));
+            if points.len() == original_len {
+                continue;
+            }
+            removed += original_len - points.len();
+
+            series.compressed_data = encode_block_v2(&points);
+            retain_ingest_times(series, &points)?;
+            series.count = points.len();
+            series.min_timestamp = points.iter().map(|(ts, _)| *ts).min().unwrap_or(0);
+            series.max_timestamp = points.iter().map(|(ts, _)| *ts).max().unwrap_or(0);
+        }
+
+        if removed > 0 {
+            series_list.retain(|s| s.count > 0);
+            if series_list.is_empty() {
+                self.delete_file()?;
+            } else {
+                self.write_data(&series_list)?;
+                self.info.deleted_points += removed;
+            }
+        }
+
+        Ok(removed)
+    }
+
     // 其他方法保持不变，但添加错误处理...
+    // 同一系列可能有多个块，只解压与查询范围相交的块
     pub fn query_series(&mut self, series_key: &str, start_time: Option<u64>, end_time: Option<u64>) -> Result<Vec<DataPoint>> {
-        let data = match self.read_with_mmap() {
-            Ok(data) => data,
+        let blocks = match self.series_blocks(series_key, start_time, end_time) {
+            Ok(blocks) => blocks,
             Err(e) => {
                 tracing::warn!("Failed to read SSTable {:?}: {}", self.file_path, e);
                 return Ok(Vec::new());
             }
         };
-        
-        if data.is_empty() {
-            return Ok(Vec::new());
-        }
-        
-        let series_list: Vec<SeriesData> = match bincode::deserialize(data) {
-            Ok(list) => list,
-            Err(e) => {
-                tracing::error!("Failed to deserialize SSTable data: {}", e);
-                return Ok(Vec::new());
-            }
-        };
 
         let mut results = Vec::new();
 
-        for series in series_list {
-            if series.series_key == series_key {
+        for series in blocks {
+            let decompressor = block_decoder(series.compressed_data)?;
+            for (timestamp, value) in decompressor.take(series.count) {
                 if let Some(start) = start_time {
-                    if series.max_timestamp < start {
+                    if timestamp < start {
                         continue;
                     }
                 }
                 if let Some(end) = end_time {
-                    if series.min_timestamp > end {
+                    if timestamp > end {
                         continue;
                     }
                 }
 
-                let decompressor = GorillaDecompressor::new(series.compressed_data);
-                let decompressed_points = decompressor.decompress_all();
+                results.push(DataPoint {
+                    timestamp,
+                    value,
+                    tags: series.tags.clone(),
+                });
+            }
+        }
 
-                for (timestamp, value) in decompressed_points {
-                    if let Some(start) = start_time {
-                        if timestamp < start {
-                            continue;
-                        }
-                    }
-                    if let Some(end) = end_time {
-                        if timestamp > end {
-                            continue;
-                        }
-                    }
+        Ok(results)
+    }
 
-                    results.push(DataPoint {
-                        timestamp,
-                        value,
-                        tags: series.tags.clone(),
-                    });
-                }
+    // 解压每个块重新计算时间范围，与记录的不一致时修正并重写文件。
+    // 返回检查的块数和修正的块
+    pub fn repair_series_bounds(&mut self) -> Result<(usize, Vec<BoundsMismatch>)> {
+        let mut series_list = self.load_series_list()?;
+        let file_name = self.file_name();
+
+        let mut mismatches = Vec::new();
+        for series in series_list.iter_mut() {
+            let mut bounds: Option<(u64, u64)> = None;
+            for (ts, _) in block_decoder(series.compressed_data.clone())?.take(series.count) {
+                bounds = Some(bounds.map_or((ts, ts), |(min, max)| (min.min(ts), max.max(ts))));
+            }
+            let Some((min_timestamp, max_timestamp)) = bounds else {
+                continue;
+            };
+            if (min_timestamp, max_timestamp) == (series.min_timestamp, series.max_timestamp) {
+                continue;
             }
+
+            mismatches.push(BoundsMismatch {
+                file_name: file_name.clone(),
+                series_key: series.series_key.clone(),
+                stored_min_timestamp: series.min_timestamp,
+                stored_max_timestamp: series.max_timestamp,
+                actual_min_timestamp: min_timestamp,
+                actual_max_timestamp: max_timestamp,
+            });
+            series.min_timestamp = min_timestamp;
+            series.max_timestamp = max_timestamp;
         }
 
-        Ok(results)
+        if !mismatches.is_empty() {
+            self.write_data(&series_list)?;
+        }
+        Ok((series_list.len(), mismatches))
     }
 
     // 安全的系列键获取方法
@@ -231,7 +472,7 @@ impl SSTable {
             return Ok(Vec::new());
         }
         
-        match bincode::deserialize::<Vec<SeriesData>>(data) {
+        match decode_series_list(&data) {
             Ok(series_list) => {
                 Ok(series_list.into_iter().map(|s| s.series_key).collect())
             }
@@ -242,42 +483,71 @@ impl SSTable {
         }
     }
 
+    // 读取每个系列的标签，只解析元数据不解压数据
+    pub fn get_series_tags(&mut self) -> Result<Vec<(String, std::collections::BTreeMap<String, String>)>> {
+        let data = match self.read_with_mmap() {
+            Ok(data) => data,
+            Err(_) => return Ok(Vec::new()),
+        };
+
+        if data.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        match decode_series_list(&data) {
+            Ok(series_list) => {
+                Ok(series_list.into_iter().map(|s| (s.series_key, s.tags)).collect())
+            }
+            Err(e) => {
+                tracing::warn!("Failed to deserialize series tags: {}", e);
+                Ok(Vec::new())
+            }
+        }
+    }
+
     pub fn update_datapoint(&mut self, series_key: &str, timestamp: u64, new_value: f64) -> Result<bool> {
+        let mut pending = BTreeMap::from([(series_key.to_string(), BTreeMap::from([(timestamp, new_value)]))]);
+        Ok(self.update_datapoints(&mut pending)? > 0)
+    }
+
+    // 批量更新已有的数据点：每个块最多解压重压缩一次，整个文件最多重写一次。
+    // 已应用的更新从 pending 中移除，剩下的是本文件中不存在的点，返回更新的点数
+    pub fn update_datapoints(&mut self, pending: &mut BTreeMap<String, BTreeMap<u64, f64>>) -> Result<usize> {
         // 释放内存映射
-        self.mmap = None;
+        self.mappings.invalidate(&self.file_path);
         
         let data = std::fs::read(&self.file_path)?;
-        let mut series_list: Vec<SeriesData> = bincode::deserialize(&data)
-            // .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-            .map_err(std::io::Error::other)?;
+        let mut series_list = decode_series_list(&data)?;
 
-        let mut updated = false;
+        let mut updated = 0;
 
         for series in series_list.iter_mut() {
-            if series.series_key == series_key {
-                let decompressor = GorillaDecompressor::new(series.compressed_data.clone());
-                let mut decompressed_points = decompressor.decompress_all();
-
-                for (ts, value) in decompressed_points.iter_mut() {
-                    if *ts == timestamp {
-                        *value = new_value;
-                        updated = true;
-                        break;
-                    }
-                }
+            let Some(updates) = pending.get_mut(&series.series_key) else {
+                continue;
+            };
+            if updates.range(series.min_timestamp..=series.max_timestamp).next().is_none() {
+                continue;
+            }
 
-                if updated {
-                    let mut compressor = GorillaCompressor::new();
-                    for (ts, val) in decompressed_points {
-                        compressor.compress_datapoint(ts, val);
-                    }
-                    series.compressed_data = compressor.finish();
-                    break;
+            let mut decompressed_points = decode_versioned(&series.compressed_data)?;
+            let mut changed = false;
+            for (ts, value) in decompressed_points.iter_mut() {
+                if let Some(new_value) = updates.remove(ts) {
+                    *value = new_value;
+                    changed = true;
+                    updated += 1;
                 }
             }
+            if updates.is_empty() {
+                pending.remove(&series.series_key);
+            }
+
+            if changed {
+                series.compressed_data = encode_block_v2(&decompressed_points);
+            }
         }
 
-        if updated {
+        if updated > 0 {
             self.write_data(&series_list)?;
         }
 
@@ -285,10 +555,22 @@ impl SSTable {
     }
 }
 
+// 删除数据点后，写入时间流只保留块内剩余的时间戳
+fn retain_ingest_times(series: &mut SeriesData, points: &[(u64, f64)]) -> Result<()> {
+    if series.ingest_data.is_empty() {
+        return Ok(());
+    }
+    let remaining: std::collections::BTreeSet<u64> = points.iter().map(|(ts, _)| *ts).collect();
+    let mut times = decode_ingest_times(&series.ingest_data)?;
+    times.retain(|(ts, _)| remaining.contains(ts));
+    series.ingest_data = encode_ingest_times(&times);
+    Ok(())
+}
+
 // 确保Drop时清理资源
 impl Drop for SSTable {
     fn drop(&mut self) {
-        self.mmap = None;
+        self.mappings.invalidate(&self.file_path);
     }
 }
 
@@ -0,0 +1,139 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use super::{BlockCompression, SSTableEncoding, SeriesData};
+
+// SSTable文件的元数据摘要，启动加载和每次写入文件时更新，列出文件时不需要重新读取
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct SSTableInfo {
+    pub file_name: String,
+    pub size_bytes: u64,
+    // 最后写入文件的时间（Unix秒），写入时记录，随manifest保存，重启后恢复
+    pub created_at: Option<u64>,
+    pub sequence: Option<u64>,
+    pub compression: BlockCompression,
+    pub encoding: SSTableEncoding,
+    pub series_count: usize,
+    pub block_count: usize,
+    pub total_points: usize,
+    // 因删除被移除的点数，文件被compaction改写后清零；随manifest保存，重启后恢复
+    pub deleted_points: usize,
+    pub min_timestamp: Option<u64>,
+    pub max_timestamp: Option<u64>,
+    // 启动时无法读取元数据的文件，查询会跳过该文件
+    pub read_error: Option<String>,
+    // 每个系列的点数，用于不读文件的统计摘要，不在文件列表中返回
+    #[serde(skip)]
+    pub series_points: BTreeMap<String, usize>,
+}
+
+impl SSTableInfo {
+    // 按块元数据更新系列数、点数和时间范围
+    pub fn summarize(&mut self, blocks: &[SeriesData]) {
+        let mut series = std::collections::BTreeSet::new();
+        self.block_count = blocks.len();
+        self.total_points = 0;
+        self.min_timestamp = None;
+        self.max_timestamp = None;
+        self.series_points.clear();
+        for block in blocks {
+            series.insert(block.series_key.as_str());
+            self.total_points += block.count;
+            *self.series_points.entry(block.series_key.clone()).or_default() += block.count;
+            if block.count == 0 {
+                continue;
+            }
+            self.min_timestamp = Some(self.min_timestamp.map_or(block.min_timestamp, |ts| ts.min(block.min_timestamp)));
+            self.max_timestamp = Some(self.max_timestamp.map_or(block.max_timestamp, |ts| ts.max(block.max_timestamp)));
+        }
+        self.series_count = series.len();
+        self.read_error = None;
+    }
+
+    // 已删除的点数占删除前点数的比例，没有删除时为0
+    pub fn deleted_ratio(&self) -> f64 {
+        let before = self.deleted_points + self.total_points;
+        if before == 0 {
+            0.0
+        } else {
+            self.deleted_points as f64 / before as f64
+        }
+    }
+}
+
+// 文件中一个系列的块统计
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SSTableSeriesInfo {
+    pub series_key: String,
+    pub blocks: usize,
+    pub points: usize,
+    pub min_timestamp: u64,
+    pub max_timestamp: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SSTableDetail {
+    #[serde(flatten)]
+    pub info: SSTableInfo,
+    // 按系列键排序
+    pub series: Vec<SSTableSeriesInfo>,
+}
+
+// 按系列汇总块元数据，不解压数据
+pub fn series_summaries(blocks: &[SeriesData]) -> Vec<SSTableSeriesInfo> {
+    let mut series: BTreeMap<&str, SSTableSeriesInfo> = BTreeMap::new();
+    for block in blocks {
+        series
+            .entry(block.series_key.as_str())
+            .and_modify(|info| {
+                info.blocks += 1;
+                info.points += block.count;
+                info.min_timestamp = info.min_timestamp.min(block.min_timestamp);
+                info.max_timestamp = info.max_timestamp.max(block.max_timestamp);
+            })
+            .or_insert_with(|| SSTableSeriesInfo {
+                series_key: block.series_key.clone(),
+                blocks: 1,
+                points: block.count,
+                min_timestamp: block.min_timestamp,
+                max_timestamp: block.max_timestamp,
+            });
+    }
+    series.into_values().collect()
+}
+
+// 一个系列在SSTable中的压缩效果，只统计系列块本身（Gorilla编码后的字节），不含文件级压缩和内存表中的点
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct CompressionReport {
+    pub series_key: String,
+    pub blocks: usize,
+    pub points: usize,
+    // 未压缩时每个点占 RAW_POINT_BYTES 字节（时间戳和数值各8字节）
+    pub raw_bytes: u64,
+    pub compressed_bytes: u64,
+    // raw_bytes / compressed_bytes，没有块时为0
+    pub ratio: f64,
+    pub bytes_per_point: f64,
+}
+
+pub const RAW_POINT_BYTES: u64 = 16;
+
+impl CompressionReport {
+    pub fn new(series_key: &str) -> Self {
+        Self { series_key: series_key.to_string(), ..Self::default() }
+    }
+
+    pub fn add_block(&mut self, block: &SeriesData) {
+        self.blocks += 1;
+        self.points += block.count;
+        self.raw_bytes += block.count as u64 * RAW_POINT_BYTES;
+        self.compressed_bytes += block.compressed_data.len() as u64;
+        if self.compressed_bytes > 0 {
+            self.ratio = self.raw_bytes as f64 / self.compressed_bytes as f64;
+        }
+        if self.points > 0 {
+            self.bytes_per_point = self.compressed_bytes as f64 / self.points as f64;
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde::{Deserialize, Serialize};
+
+// 写入速率保留最近一小时，每秒一个桶
+const RATE_WINDOW_SECS: u64 = 3600;
+// 摘要中按点数列出的系列数
+pub const SUMMARY_TOP_SERIES: usize = 10;
+
+// 最近一小时每秒写入的点数，桶按秒循环复用。写入路径只做原子操作，
+// 并发写入恰好切换到新的一秒时可能少计几个点
+#[derive(Debug)]
+pub struct IngestRate {
+    buckets: Vec<IngestBucket>,
+}
+
+#[derive(Debug, Default)]
+struct IngestBucket {
+    second: AtomicU64,
+    points: AtomicU64,
+}
+
+impl Default for IngestRate {
+    fn default() -> Self {
+        Self {
+            buckets: (0..RATE_WINDOW_SECS).map(|_| IngestBucket::default()).collect(),
+        }
+    }
+}
+
+impl IngestRate {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&self, now_secs: u64, points: u64) {
+        let bucket = &self.buckets[(now_secs % RATE_WINDOW_SECS) as usize];
+        let second = bucket.second.load(Ordering::Relaxed);
+        if second != now_secs
+            && bucket.second.compare_exchange(second, now_secs, Ordering::Relaxed, Ordering::Relaxed).is_ok()
+        {
+            bucket.points.store(0, Ordering::Relaxed);
+        }
+        bucket.points.fetch_add(points, Ordering::Relaxed);
+    }
+
+    // (now - window_secs, now] 内写入的点数，window_secs 不超过一小时
+    pub fn points_since(&self, now_secs: u64, window_secs: u64) -> u64 {
+        let window = window_secs.min(RATE_WINDOW_SECS);
+        self.buckets
+            .iter()
+            .filter(|bucket| {
+                let second = bucket.second.load(Ordering::Relaxed);
+                second <= now_secs && now_secs - second < window
+            })
+            .map(|bucket| bucket.points.load(Ordering::Relaxed))
+            .sum()
+    }
+}
+
+// 最近1分钟、5分钟和1小时写入的点数
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IngestedPoints {
+    pub last_1m: u64,
+    pub last_5m: u64,
+    pub last_1h: u64,
+}
+
+impl IngestedPoints {
+    pub fn from_rate(rate: &IngestRate, now_secs: u64) -> Self {
+        Self {
+            last_1m: rate.points_since(now_secs, 60),
+            last_5m: rate.points_since(now_secs, 300),
+            last_1h: rate.points_since(now_secs, 3600),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SeriesPointCount {
+    pub series_key: String,
+    pub points: u64,
+}
+
+// 只根据内存中的计数和SSTable元数据摘要生成的统计，不读取、不解压任何数据块
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StatsSummary {
+    // 生成时间（Unix秒）
+    pub computed_at: u64,
+    pub total_series: usize,
+    // SSTable块元数据中的点数加上内存表中的点数
+    pub total_points: u64,
+    pub memtable_points: usize,
+    // SSTable文件总字节数
+    pub total_compressed_bytes: u64,
+    pub sstable_count: usize,
+    pub ingested_points: IngestedPoints,
+    // 首次写入时间在当天（UTC）的系列数
+    pub new_series_today: usize,
+    // 点数最多的系列，点数相同时按键排序
+    pub top_series: Vec<SeriesPointCount>,
+    // 最近一次写入SSTable（刷新、compaction或改写）的时间（Unix秒）
+    pub sstables_updated_at: Option<u64>,
+    // 计数可能不准确的原因
+    pub notes: Vec<String>,
+}
+
+// 点数最多的 n 个系列
+pub fn top_series(counts: BTreeMap<String, u64>, n: usize) -> Vec<SeriesPointCount> {
+    let mut series: Vec<SeriesPointCount> = counts
+        .into_iter()
+        .map(|(series_key, points)| SeriesPointCount { series_key, points })
+        .collect();
+    series.sort_by(|a, b| b.points.cmp(&a.points).then_with(|| a.series_key.cmp(&b.series_key)));
+    series.truncate(n);
+    series
+}
@@ -0,0 +1,152 @@
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_util::FutureExt;
+use parking_lot::Mutex;
+use serde::Serialize;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+use super::Clock;
+
+// 任务失败后等待多久重新执行，连续失败时翻倍直到上限
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+// 一个受监督的后台任务的运行情况
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct TaskStatus {
+    // 执行次数和最近一次执行结束的时间（Unix秒）
+    pub runs: u64,
+    pub last_run: Option<u64>,
+    // 最近一次失败（返回错误或panic）的原因和时间
+    pub last_error: Option<String>,
+    pub last_error_at: Option<u64>,
+    // 失败后退避重启的次数，其中因panic重启的次数
+    pub restarts: u64,
+    pub panics: u64,
+    // 连续失败的次数，成功执行一次后清零
+    pub consecutive_failures: u32,
+}
+
+// 引擎的后台任务。定期任务每次执行都在监督下进行：返回错误或panic时记录原因，
+// 退避一段时间后重新执行，任务不会因为一次失败永久停止
+#[derive(Debug)]
+pub struct BackgroundTasks {
+    clock: Arc<dyn Clock>,
+    handles: Mutex<Vec<JoinHandle<()>>>,
+    statuses: Arc<Mutex<BTreeMap<String, TaskStatus>>>,
+}
+
+impl BackgroundTasks {
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            clock,
+            handles: Mutex::new(Vec::new()),
+            statuses: Arc::new(Mutex::new(BTreeMap::new())),
+        }
+    }
+
+    // 登记一个不需要监督的一次性任务，关闭时同样等待其退出
+    pub fn push(&self, handle: JoinHandle<()>) {
+        self.handles.lock().push(handle);
+    }
+
+    // 每隔 period 执行一次 run，第一次立即执行，shutdown 取消后停止
+    pub fn spawn_periodic<F, Fut>(&self, name: &str, period: Duration, shutdown: CancellationToken, mut run: F)
+    where
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = std::io::Result<()>> + Send,
+    {
+        let name = name.to_string();
+        let clock = Arc::clone(&self.clock);
+        let statuses = Arc::clone(&self.statuses);
+        statuses.lock().entry(name.clone()).or_default();
+        let handle = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            let mut backoff = INITIAL_BACKOFF;
+            let mut retry = false;
+            loop {
+                if !retry {
+                    tokio::select! {
+                        _ = shutdown.cancelled() => break,
+                        _ = interval.tick() => {}
+                    }
+                }
+                // run() 在 catch_unwind 内调用，同步部分的panic同样被捕获
+                let outcome = AssertUnwindSafe(async { run().await }).catch_unwind().await;
+                let failure = match outcome {
+                    Ok(Ok(())) => None,
+                    Ok(Err(e)) => Some((e.to_string(), false)),
+                    Err(panic) => Some((panic_message(&*panic), true)),
+                };
+
+                let now = clock.now_secs();
+                {
+                    let mut statuses = statuses.lock();
+                    let status = statuses.entry(name.clone()).or_default();
+                    status.runs += 1;
+                    status.last_run = Some(now);
+                    match &failure {
+                        None => status.consecutive_failures = 0,
+                        Some((message, panicked)) => {
+                            status.last_error = Some(message.clone());
+                            status.last_error_at = Some(now);
+                            status.restarts += 1;
+                            status.panics += u64::from(*panicked);
+                            status.consecutive_failures += 1;
+                        }
+                    }
+                }
+
+                retry = false;
+                let Some((message, panicked)) = failure else {
+                    backoff = INITIAL_BACKOFF;
+                    continue;
+                };
+                if panicked {
+                    tracing::error!("后台任务 {} panic: {}，{:?} 后重启", name, message, backoff);
+                } else {
+                    tracing::error!("后台任务 {} 失败: {}，{:?} 后重试", name, message, backoff);
+                }
+                tokio::select! {
+                    _ = shutdown.cancelled() => break,
+                    _ = tokio::time::sleep(backoff) => {}
+                }
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+                interval.reset();
+                retry = true;
+            }
+        });
+        self.push(handle);
+    }
+
+    // 每个定期任务的运行情况，按任务名排序
+    pub fn statuses(&self) -> BTreeMap<String, TaskStatus> {
+        self.statuses.lock().clone()
+    }
+
+    // 仍在运行的任务数量
+    pub fn running(&self) -> usize {
+        self.handles.lock().iter().filter(|handle| !handle.is_finished()).count()
+    }
+
+    // 取出所有任务的句柄，关闭时等待它们退出
+    pub fn take_handles(&self) -> Vec<JoinHandle<()>> {
+        std::mem::take(&mut *self.handles.lock())
+    }
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "未知的panic".to_string()
+    }
+}
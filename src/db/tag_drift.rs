@@ -0,0 +1,146 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+
+use super::DbError;
+
+// 标签漂移检测模式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TagDriftMode {
+    #[default]
+    Off,
+    // 记录告警日志并累加计数，写入照常进行
+    Warn,
+    // 拒绝标签与已有标签不一致的写入
+    Reject,
+}
+
+impl FromStr for TagDriftMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "off" => Ok(Self::Off),
+            "warn" => Ok(Self::Warn),
+            "reject" => Ok(Self::Reject),
+            other => Err(format!("不支持的标签漂移模式: {}，可选 off/warn/reject", other)),
+        }
+    }
+}
+
+pub fn tag_hash(tags: &BTreeMap<String, String>) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    tags.hash(&mut hasher);
+    hasher.finish()
+}
+
+// 已确立标签的哈希，以及原始标签（仅用于日志和错误信息）
+type EstablishedTags = (u64, BTreeMap<String, String>);
+
+// 缓存每个系列已确立的标签集合，写入时只比较哈希
+#[derive(Debug, Default)]
+pub struct TagDriftDetector {
+    mode: TagDriftMode,
+    established: RwLock<HashMap<String, EstablishedTags>>,
+    drift_counts: Mutex<BTreeMap<String, u64>>,
+}
+
+impl TagDriftDetector {
+    pub fn new(mode: TagDriftMode) -> Self {
+        Self {
+            mode,
+            ..Self::default()
+        }
+    }
+
+    pub fn mode(&self) -> TagDriftMode {
+        self.mode
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.mode != TagDriftMode::Off
+    }
+
+    // 记录系列已有的标签（启动时从SSTable元数据加载）
+    pub fn seed(&self, series_key: &str, tags: &BTreeMap<String, String>) {
+        if !self.enabled() {
+            return;
+        }
+        self.established
+            .write()
+            .entry(series_key.to_string())
+            .or_insert_with(|| (tag_hash(tags), tags.clone()));
+    }
+
+    pub fn forget(&self, series_key: &str) {
+        self.established.write().remove(series_key);
+    }
+
+    pub fn clear(&self) {
+        self.established.write().clear();
+        self.drift_counts.lock().clear();
+    }
+
+    // reject模式下标签与已确立的标签不一致时返回已有的标签，不记录计数、不确立新系列的标签
+    pub fn rejected(&self, series_key: &str, tags: &BTreeMap<String, String>) -> Option<BTreeMap<String, String>> {
+        if self.mode != TagDriftMode::Reject {
+            return None;
+        }
+        match self.established.read().get(series_key) {
+            Some((known, expected)) if *known != tag_hash(tags) => Some(expected.clone()),
+            _ => None,
+        }
+    }
+
+    // 检查写入的标签是否与已确立的标签一致，新系列以本次标签为准
+    pub fn check(&self, series_key: &str, tags: &BTreeMap<String, String>) -> Result<(), DbError> {
+        if !self.enabled() {
+            return Ok(());
+        }
+
+        let hash = tag_hash(tags);
+        let expected = {
+            let established = self.established.read();
+            match established.get(series_key) {
+                Some((known, _)) if *known == hash => return Ok(()),
+                Some((_, expected)) => Some(expected.clone()),
+                None => None,
+            }
+        };
+
+        let Some(expected) = expected else {
+            self.seed(series_key, tags);
+            return Ok(());
+        };
+
+        *self
+            .drift_counts
+            .lock()
+            .entry(series_key.to_string())
+            .or_default() += 1;
+        tracing::warn!(
+            series_key = series_key,
+            expected = ?expected,
+            actual = ?tags,
+            "检测到标签漂移"
+        );
+
+        match self.mode {
+            TagDriftMode::Reject => Err(DbError::TagDrift {
+                series_key: series_key.to_string(),
+                expected,
+                actual: tags.clone(),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn drift_counts(&self) -> BTreeMap<String, u64> {
+        self.drift_counts.lock().clone()
+    }
+}
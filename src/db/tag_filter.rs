@@ -0,0 +1,51 @@
+use std::collections::BTreeMap;
+
+// 按标签过滤数据点或事件，要求每个条件的标签都存在且值相等，条件之间为与，没有条件时全部匹配
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TagFilter {
+    conditions: BTreeMap<String, String>,
+}
+
+impl TagFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.conditions.insert(key.into(), value.into());
+        self
+    }
+
+    // 添加 key:value 形式的条件，同一个键的后一个条件覆盖前一个
+    pub fn add_condition(&mut self, condition: &str) -> std::result::Result<(), String> {
+        let (key, value) = parse_tag_condition(condition)?;
+        self.conditions.insert(key, value);
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.conditions.is_empty()
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.conditions.keys().map(String::as_str)
+    }
+
+    pub fn matches(&self, tags: &BTreeMap<String, String>) -> bool {
+        self.conditions.iter().all(|(key, value)| tags.get(key) == Some(value))
+    }
+}
+
+// 解析 tag=key:value 查询参数中的一个条件，键不能为空
+pub fn parse_tag_condition(condition: &str) -> std::result::Result<(String, String), String> {
+    match condition.split_once(':') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("无效的标签条件: {}，格式为 key:value", condition)),
+    }
+}
+
+impl From<BTreeMap<String, String>> for TagFilter {
+    fn from(conditions: BTreeMap<String, String>) -> Self {
+        Self { conditions }
+    }
+}
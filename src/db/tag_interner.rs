@@ -0,0 +1,61 @@
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
+
+// 内存表中共享的标签集合
+pub type TagSet = Arc<BTreeMap<String, String>>;
+
+// 标签集合驻留表。同一系列的数据点标签通常完全相同，内存表中的点共用一份驻留的集合，
+// 不再每个点各自持有一份。BTreeMap按键排序，内容相同的集合哈希和比较的结果都相同
+#[derive(Debug, Default)]
+pub struct TagInterner {
+    sets: HashSet<TagSet>,
+}
+
+impl TagInterner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // 返回内容相同的已驻留集合，没有时复制一份 tags 驻留。按引用查找，已驻留时不分配内存
+    pub fn intern(&mut self, tags: &BTreeMap<String, String>) -> TagSet {
+        if let Some(set) = self.sets.get(tags) {
+            return Arc::clone(set);
+        }
+        let set = Arc::new(tags.clone());
+        self.sets.insert(Arc::clone(&set));
+        set
+    }
+
+    // 释放只被驻留表自己引用的集合
+    pub fn purge(&mut self) {
+        self.sets.retain(|set| Arc::strong_count(set) > 1);
+    }
+
+    pub fn clear(&mut self) {
+        self.sets.clear();
+    }
+
+    // 驻留的不同标签集合数
+    pub fn len(&self) -> usize {
+        self.sets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sets.is_empty()
+    }
+
+    // 驻留的集合占用的内存估算
+    pub fn estimated_bytes(&self) -> usize {
+        self.sets
+            .iter()
+            .map(|set| std::mem::size_of::<BTreeMap<String, String>>() + tags_heap_bytes(set))
+            .sum()
+    }
+}
+
+// 一个标签集合在堆上占用的内存估算：每个键值对两个String加上字符串内容，不计BTreeMap节点的额外开销
+pub fn tags_heap_bytes(tags: &BTreeMap<String, String>) -> usize {
+    tags.iter()
+        .map(|(key, value)| 2 * std::mem::size_of::<String>() + key.capacity() + value.capacity())
+        .sum()
+}
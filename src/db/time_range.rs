@@ -0,0 +1,175 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use super::TimePrecision;
+
+// 时间范围的一端：绝对时间戳，或相对于当前时间的偏移
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeBound {
+    At(u64),
+    // 当前时间之前，Duration::ZERO 即当前时间
+    Ago(Duration),
+    // 当前时间之后
+    Ahead(Duration),
+}
+
+impl TimeBound {
+    pub fn now() -> Self {
+        Self::Ago(Duration::ZERO)
+    }
+
+    pub fn is_relative(&self) -> bool {
+        !matches!(self, Self::At(_))
+    }
+
+    // now 为内部精度的当前时间戳
+    pub fn resolve(&self, now: u64, precision: TimePrecision) -> u64 {
+        match *self {
+            Self::At(timestamp) => timestamp,
+            Self::Ago(duration) => now.saturating_sub(precision.from_duration(duration)),
+            Self::Ahead(duration) => now.saturating_add(precision.from_duration(duration)),
+        }
+    }
+}
+
+impl FromStr for TimeBound {
+    type Err = TimeExprError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_time_bound(s)
+    }
+}
+
+// 查询的时间范围，两端都可以是相对时间，查询时按当前时间换算为时间戳。None 表示不限制
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TimeRange {
+    pub start: Option<TimeBound>,
+    pub end: Option<TimeBound>,
+}
+
+impl TimeRange {
+    pub fn new(start: Option<TimeBound>, end: Option<TimeBound>) -> Self {
+        Self { start, end }
+    }
+
+    pub fn between(start_time: Option<u64>, end_time: Option<u64>) -> Self {
+        Self::new(start_time.map(TimeBound::At), end_time.map(TimeBound::At))
+    }
+
+    // 截止到当前时间的最近一段时间，如 TimeRange::last(Duration::from_secs(900)) 为最近15分钟
+    pub fn last(duration: Duration) -> Self {
+        Self::new(Some(TimeBound::Ago(duration)), Some(TimeBound::now()))
+    }
+
+    pub fn is_relative(&self) -> bool {
+        self.start.iter().chain(&self.end).any(TimeBound::is_relative)
+    }
+
+    pub fn resolve(&self, now: u64, precision: TimePrecision) -> ResolvedRange {
+        ResolvedRange {
+            start_time: self.start.map(|bound| bound.resolve(now, precision)),
+            end_time: self.end.map(|bound| bound.resolve(now, precision)),
+        }
+    }
+}
+
+// 换算后的绝对时间范围（内部精度）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct ResolvedRange {
+    pub start_time: Option<u64>,
+    pub end_time: Option<u64>,
+}
+
+// 时间表达式解析失败，position 为出错处在表达式中的字节位置（从0开始）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimeExprError {
+    pub expr: String,
+    pub position: usize,
+    pub message: String,
+}
+
+impl TimeExprError {
+    fn new(expr: &str, position: usize, message: impl Into<String>) -> Self {
+        Self { expr: expr.to_string(), position, message: message.into() }
+    }
+}
+
+impl fmt::Display for TimeExprError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "无效的时间表达式 {:?}，位置 {}: {}", self.expr, self.position, self.message)
+    }
+}
+
+impl std::error::Error for TimeExprError {}
+
+// 解析时间长度，由一个或多个数字加单位 s/m/h/d/w 组成，如 "15m"、"1h30m"、"2w"
+pub fn parse_duration_expr(expr: &str) -> Result<Duration, TimeExprError> {
+    duration_at(expr, 0)
+}
+
+// 从 expr 的 offset 处解析到末尾
+fn duration_at(expr: &str, offset: usize) -> Result<Duration, TimeExprError> {
+    let mut position = offset;
+    let mut total: u64 = 0;
+    if position == expr.len() {
+        return Err(TimeExprError::new(expr, position, "缺少时间长度，格式为数字加单位 s/m/h/d/w，如 15m"));
+    }
+    while position < expr.len() {
+        let rest = &expr[position..];
+        let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+        if digits == 0 {
+            return Err(TimeExprError::new(expr, position, "应为数字"));
+        }
+        let amount: u64 = rest[..digits]
+            .parse()
+            .map_err(|_| TimeExprError::new(expr, position, "数字溢出"))?;
+        let unit_start = position + digits;
+        let unit_rest = &expr[unit_start..];
+        let unit_len = unit_rest.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(unit_rest.len());
+        let unit_secs = match &unit_rest[..unit_len] {
+            "s" => 1,
+            "m" => 60,
+            "h" => 3600,
+            "d" => 86400,
+            "w" => 7 * 86400,
+            "" => return Err(TimeExprError::new(expr, unit_start, "缺少时间单位，可选 s/m/h/d/w")),
+            unit => return Err(TimeExprError::new(expr, unit_start, format!("无效的时间单位 {:?}，可选 s/m/h/d/w", unit))),
+        };
+        total = amount
+            .checked_mul(unit_secs)
+            .and_then(|secs| total.checked_add(secs))
+            .ok_or_else(|| TimeExprError::new(expr, position, "时间长度溢出"))?;
+        position = unit_start + unit_len;
+    }
+    if total == 0 {
+        return Err(TimeExprError::new(expr, offset, "时间长度必须大于0"));
+    }
+    Ok(Duration::from_secs(total))
+}
+
+// 解析时间范围的一端：时间戳，或 now、now-1h、now+5m 这样相对当前时间的表达式
+pub fn parse_time_bound(expr: &str) -> Result<TimeBound, TimeExprError> {
+    if !expr.is_empty() && expr.bytes().all(|b| b.is_ascii_digit()) {
+        return expr
+            .parse()
+            .map(TimeBound::At)
+            .map_err(|_| TimeExprError::new(expr, 0, "时间戳溢出"));
+    }
+    let Some(rest) = expr.strip_prefix("now") else {
+        return Err(TimeExprError::new(expr, 0, "应为时间戳，或 now、now-1h 这样的相对时间"));
+    };
+    match rest.chars().next() {
+        None => Ok(TimeBound::now()),
+        Some('-') => duration_at(expr, 4).map(TimeBound::Ago),
+        Some('+') => duration_at(expr, 4).map(TimeBound::Ahead),
+        Some(_) => Err(TimeExprError::new(expr, 3, "now 之后应为 '-' 或 '+'")),
+    }
+}
+
+// 解析相对时间长度，如 "30s"、"15m"、"1h"、"7d"、"2w"
+pub fn parse_relative_duration(s: &str) -> Result<Duration, String> {
+    parse_duration_expr(s.trim()).map_err(|e| e.to_string())
+}
@@ -0,0 +1,126 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use super::{DbError, NewAnnotation, MAX_ANNOTATION_TEXT_BYTES};
+
+// 系列键的最大长度（字节）
+pub const MAX_SERIES_KEY_BYTES: usize = 1024;
+
+// 数据点或注释不满足写入规则的具体原因，由 TimeSeriesDB::validate_datapoint 和 validate_annotation 返回
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValidationError {
+    SeriesKeyTooLong { length: usize, max_length: usize },
+    EmptyTagKey,
+    // 标签键或值中包含控制字符
+    TagControlChar { tag_key: String },
+    // 值为 NaN 或无穷大
+    NonFiniteValue { value: f64 },
+    // 时间戳超过当前时间加上允许的偏差（max_future_skew_secs）
+    TimestampTooFarAhead { timestamp: u64, max_timestamp: u64 },
+    // 标签与系列已有标签不一致（reject模式）
+    TagDrift {
+        expected: BTreeMap<String, String>,
+        actual: BTreeMap<String, String>,
+    },
+    // 注释文本为空或只有空白
+    EmptyAnnotationText,
+    AnnotationTextTooLong { length: usize, max_length: usize },
+    // 时间段注释的结束时间戳早于开始时间戳
+    AnnotationEndsBeforeStart { timestamp: u64, end_timestamp: u64 },
+}
+
+impl ValidationError {
+    // 转换为写入接口返回的引擎错误，标签漂移沿用原有的错误类型
+    pub fn into_db_error(self, series_key: &str) -> DbError {
+        match self {
+            Self::TagDrift { expected, actual } => DbError::TagDrift {
+                series_key: series_key.to_string(),
+                expected,
+                actual,
+            },
+            error => DbError::InvalidDataPoint {
+                series_key: series_key.to_string(),
+                error,
+            },
+        }
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::SeriesKeyTooLong { length, max_length } => {
+                write!(f, "系列键长度 {} 字节，超过上限 {} 字节", length, max_length)
+            }
+            Self::EmptyTagKey => write!(f, "标签键不能为空"),
+            Self::TagControlChar { tag_key } => write!(f, "标签 {:?} 的键或值包含控制字符", tag_key),
+            Self::NonFiniteValue { value } => write!(f, "值 {} 不是有限的数", value),
+            Self::TimestampTooFarAhead { timestamp, max_timestamp } => write!(
+                f,
+                "时间戳 {} 超过当前时间允许的偏差，最大为 {}",
+                timestamp, max_timestamp
+            ),
+            Self::TagDrift { expected, actual } => {
+                write!(f, "标签与已有标签不一致: 已有 {:?}，写入 {:?}", expected, actual)
+            }
+            Self::EmptyAnnotationText => write!(f, "注释文本不能为空"),
+            Self::AnnotationTextTooLong { length, max_length } => {
+                write!(f, "注释文本长度 {} 字节，超过上限 {} 字节", length, max_length)
+            }
+            Self::AnnotationEndsBeforeStart { timestamp, end_timestamp } => {
+                write!(f, "注释的结束时间戳 {} 早于开始时间戳 {}", end_timestamp, timestamp)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+// 系列键的规则：不超过 MAX_SERIES_KEY_BYTES。任意字符都可以出现在键中，
+// 数据文件名由键编码得到，不会超出数据目录
+pub fn validate_series_key(series_key: &str) -> Result<(), ValidationError> {
+    if series_key.len() > MAX_SERIES_KEY_BYTES {
+        return Err(ValidationError::SeriesKeyTooLong {
+            length: series_key.len(),
+            max_length: MAX_SERIES_KEY_BYTES,
+        });
+    }
+    Ok(())
+}
+
+// 标签的规则：键非空，键和值不含控制字符
+pub fn validate_tags(tags: &BTreeMap<String, String>) -> Result<(), ValidationError> {
+    for (key, value) in tags {
+        if key.is_empty() {
+            return Err(ValidationError::EmptyTagKey);
+        }
+        if key.chars().chain(value.chars()).any(char::is_control) {
+            return Err(ValidationError::TagControlChar { tag_key: key.clone() });
+        }
+    }
+    Ok(())
+}
+
+// 注释的规则：系列键和标签与数据点相同，文本非空且不超过 MAX_ANNOTATION_TEXT_BYTES，
+// 时间段注释的结束时间戳不早于开始时间戳
+pub fn validate_annotation(annotation: &NewAnnotation) -> Result<(), ValidationError> {
+    if let Some(series_key) = &annotation.series_key {
+        validate_series_key(series_key)?;
+    }
+    validate_tags(&annotation.tags)?;
+    if annotation.text.trim().is_empty() {
+        return Err(ValidationError::EmptyAnnotationText);
+    }
+    if annotation.text.len() > MAX_ANNOTATION_TEXT_BYTES {
+        return Err(ValidationError::AnnotationTextTooLong {
+            length: annotation.text.len(),
+            max_length: MAX_ANNOTATION_TEXT_BYTES,
+        });
+    }
+    if let Some(end_timestamp) = annotation.end_timestamp {
+        if end_timestamp < annotation.timestamp {
+            return Err(ValidationError::AnnotationEndsBeforeStart { timestamp: annotation.timestamp, end_timestamp });
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,119 @@
+use std::path::PathBuf;
+
+use serde::Serialize;
+use tokio_util::sync::CancellationToken;
+
+use super::SeriesWalk;
+
+// 每次交给阻塞线程池校验的文件数，两批之间更新进度并检查取消和关闭
+pub const VERIFY_CHUNK_FILES: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CorruptFile {
+    pub file_name: String,
+    pub error: String,
+}
+
+// 一次完整性校验的结果
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct VerifyReport {
+    pub files_checked: usize,
+    // 校验期间被compaction、删除等移除的文件，不计入检查
+    pub files_skipped: usize,
+    pub blocks_checked: usize,
+    pub points_checked: usize,
+    pub corrupt: Vec<CorruptFile>,
+}
+
+impl VerifyReport {
+    pub fn record(&mut self, check: &FileCheck) {
+        match &check.outcome {
+            FileOutcome::Verified { blocks, points } => {
+                self.files_checked += 1;
+                self.blocks_checked += blocks;
+                self.points_checked += points;
+            }
+            FileOutcome::Missing => self.files_skipped += 1,
+            FileOutcome::Corrupt(error) => {
+                self.files_checked += 1;
+                self.corrupt.push(CorruptFile { file_name: check.file_name.clone(), error: error.clone() });
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileOutcome {
+    Verified { blocks: usize, points: usize },
+    // 文件在开始校验后被移除
+    Missing,
+    Corrupt(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileCheck {
+    pub file_name: String,
+    pub outcome: FileOutcome,
+}
+
+// 按开始时的文件列表逐个校验SSTable，每次迭代校验一个文件。取消后停止迭代，
+// position 记录下一个要校验的文件，之后可以用 resume 从这里继续。
+// 每个文件单独打开，重写文件先写临时文件再重命名，读到的总是完整的某个版本
+#[derive(Debug)]
+pub struct SSTableVerifier {
+    files: Vec<PathBuf>,
+    position: usize,
+    cancel: CancellationToken,
+}
+
+impl SSTableVerifier {
+    pub fn new(files: Vec<PathBuf>, cancel: CancellationToken) -> Self {
+        Self::resume(files, 0, cancel)
+    }
+
+    pub fn resume(files: Vec<PathBuf>, position: usize, cancel: CancellationToken) -> Self {
+        let position = position.min(files.len());
+        Self { files, position, cancel }
+    }
+
+    pub fn files_total(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.position == self.files.len()
+    }
+
+    // 用只包含该文件的 SeriesWalk 逐个系列解压校验
+    fn check(path: PathBuf) -> FileCheck {
+        let file_name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        let verified = SeriesWalk::from_files(std::slice::from_ref(&path)).and_then(|walk| {
+            walk.into_iter().try_fold((0, 0), |(blocks, points), entry| {
+                Ok((blocks + entry.block_count(), points + entry.verify()?))
+            })
+        });
+        let outcome = match verified {
+            Ok((blocks, points)) => FileOutcome::Verified { blocks, points },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => FileOutcome::Missing,
+            Err(e) => FileOutcome::Corrupt(e.to_string()),
+        };
+        FileCheck { file_name, outcome }
+    }
+}
+
+impl Iterator for SSTableVerifier {
+    type Item = FileCheck;
+
+    fn next(&mut self) -> Option<FileCheck> {
+        if self.is_done() || self.cancel.is_cancelled() {
+            return None;
+        }
+        let path = self.files[self.position].clone();
+        self.position += 1;
+        Some(Self::check(path))
+    }
+}
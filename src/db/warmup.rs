@@ -0,0 +1,89 @@
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+
+use serde::Serialize;
+
+// 预热列表中的一项是否匹配系列键：以 '*' 结尾表示前缀，否则需完全相同
+pub fn warmup_matches(pattern: &str, series_key: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => series_key.starts_with(prefix),
+        None => pattern == series_key,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WarmupState {
+    // 未执行预热
+    Idle,
+    Running,
+    Done,
+    Failed,
+}
+
+impl WarmupState {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            1 => Self::Running,
+            2 => Self::Done,
+            3 => Self::Failed,
+            _ => Self::Idle,
+        }
+    }
+}
+
+// 启动预热的进度
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct WarmupStatus {
+    pub state: WarmupState,
+    pub series_matched: u64,
+    pub files_total: u64,
+    pub files_loaded: u64,
+    pub bytes_loaded: u64,
+    pub duration_ms: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct WarmupProgress {
+    state: AtomicU8,
+    series_matched: AtomicU64,
+    files_total: AtomicU64,
+    files_loaded: AtomicU64,
+    bytes_loaded: AtomicU64,
+    duration_ms: AtomicU64,
+}
+
+impl WarmupProgress {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn start(&self, series_matched: u64, files_total: u64) {
+        self.series_matched.store(series_matched, Ordering::Relaxed);
+        self.files_total.store(files_total, Ordering::Relaxed);
+        self.files_loaded.store(0, Ordering::Relaxed);
+        self.bytes_loaded.store(0, Ordering::Relaxed);
+        self.duration_ms.store(0, Ordering::Relaxed);
+        self.state.store(WarmupState::Running as u8, Ordering::Release);
+    }
+
+    pub fn file_loaded(&self, bytes: u64) {
+        self.files_loaded.fetch_add(1, Ordering::Relaxed);
+        self.bytes_loaded.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn finish(&self, state: WarmupState, duration_ms: u64) {
+        self.duration_ms.store(duration_ms, Ordering::Relaxed);
+        self.state.store(state as u8, Ordering::Release);
+    }
+
+    pub fn status(&self) -> WarmupStatus {
+        WarmupStatus {
+            state: WarmupState::from_u8(self.state.load(Ordering::Acquire)),
+            series_matched: self.series_matched.load(Ordering::Relaxed),
+            files_total: self.files_total.load(Ordering::Relaxed),
+            files_loaded: self.files_loaded.load(Ordering::Relaxed),
+            bytes_loaded: self.bytes_loaded.load(Ordering::Relaxed),
+            duration_ms: self.duration_ms.load(Ordering::Relaxed),
+        }
+    }
+}
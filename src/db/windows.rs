@@ -0,0 +1,112 @@
+use serde::{Deserialize, Serialize};
+
+use super::{AggregateFn, BucketAccumulator};
+
+// 单次多窗口查询最多的窗口数
+pub const MAX_QUERY_WINDOWS: usize = 1000;
+
+// 多窗口查询中的一个时间窗口 [start_time, end_time]，两端都包含
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimeWindow {
+    pub start_time: u64,
+    pub end_time: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct WindowPoint {
+    pub timestamp: u64,
+    pub value: f64,
+}
+
+// 一个窗口的查询结果。指定聚合函数时 value 为聚合值（窗口内没有点时为null）、不返回 points，
+// 否则返回窗口内的全部点
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WindowResult {
+    pub start_time: u64,
+    pub end_time: u64,
+    pub count: usize,
+    pub value: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub points: Option<Vec<WindowPoint>>,
+}
+
+// 按时间顺序逐点分配到包含它的窗口。窗口可以重叠，按起始时间依次打开，
+// 结束时间早于当前点的窗口不会再收到点，每个点只需检查仍然打开的窗口
+#[derive(Debug)]
+pub struct WindowCollector {
+    windows: Vec<TimeWindow>,
+    // 按起始时间排序的窗口下标，以及下一个待打开的位置
+    order: Vec<usize>,
+    next: usize,
+    active: Vec<usize>,
+    accumulators: Vec<Option<BucketAccumulator>>,
+    points: Vec<Vec<WindowPoint>>,
+    collected: usize,
+}
+
+impl WindowCollector {
+    pub fn new(windows: Vec<TimeWindow>, function: Option<AggregateFn>) -> Self {
+        let mut order: Vec<usize> = (0..windows.len()).collect();
+        order.sort_by_key(|&index| windows[index].start_time);
+        Self {
+            accumulators: windows.iter().map(|_| function.map(BucketAccumulator::new)).collect(),
+            points: vec![Vec::new(); windows.len()],
+            windows,
+            order,
+            next: 0,
+            active: Vec::new(),
+            collected: 0,
+        }
+    }
+
+    pub fn push(&mut self, timestamp: u64, value: f64) {
+        while let Some(&index) = self.order.get(self.next) {
+            if self.windows[index].start_time > timestamp {
+                break;
+            }
+            self.active.push(index);
+            self.next += 1;
+        }
+        let windows = &self.windows;
+        self.active.retain(|&index| windows[index].end_time >= timestamp);
+        for &index in &self.active {
+            match &mut self.accumulators[index] {
+                Some(accumulator) => accumulator.push(value),
+                None => {
+                    self.points[index].push(WindowPoint { timestamp, value });
+                    self.collected += 1;
+                }
+            }
+        }
+    }
+
+    // 已保存的原始点数，重叠窗口中的点按窗口重复计数
+    pub fn collected(&self) -> usize {
+        self.collected
+    }
+
+    // 按请求中窗口的顺序返回结果
+    pub fn finish(self) -> Vec<WindowResult> {
+        self.windows
+            .into_iter()
+            .zip(self.accumulators)
+            .zip(self.points)
+            .map(|((window, accumulator), points)| match accumulator {
+                Some(accumulator) => WindowResult {
+                    start_time: window.start_time,
+                    end_time: window.end_time,
+                    count: accumulator.count(),
+                    value: accumulator.finish(),
+                    points: None,
+                },
+                None => WindowResult {
+                    start_time: window.start_time,
+                    end_time: window.end_time,
+                    count: points.len(),
+                    value: None,
+                    points: Some(points),
+                },
+            })
+            .collect()
+    }
+}
@@ -2,9 +2,13 @@
 
 pub mod db;
 pub mod api;
+pub mod server_config;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 
 pub use db::*;
 pub use api::*;
+pub use server_config::*;
 
 #[cfg(test)]
 mod tests {
@@ -12,63 +16,5329 @@ mod tests {
     use tempfile::TempDir;
     use std::collections::BTreeMap;
 
+    // 按线程统计当前仍在使用的堆内存，用于测量某段代码构建的数据结构实际占用的内存
+    struct CountingAllocator;
+
+    thread_local! {
+        static LIVE_BYTES: std::cell::Cell<isize> = const { std::cell::Cell::new(0) };
+    }
+
+    unsafe impl std::alloc::GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
+            let _ = LIVE_BYTES.try_with(|live| live.set(live.get() + layout.size() as isize));
+            unsafe { std::alloc::System.alloc(layout) }
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: std::alloc::Layout) {
+            let _ = LIVE_BYTES.try_with(|live| live.set(live.get() - layout.size() as isize));
+            unsafe { std::alloc::System.dealloc(ptr, layout) }
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    // build 返回的值在当前线程上占用的堆内存
+    fn measure_heap<T>(build: impl FnOnce() -> T) -> (T, usize) {
+        let before = LIVE_BYTES.with(std::cell::Cell::get);
+        let value = build();
+        let after = LIVE_BYTES.with(std::cell::Cell::get);
+        (value, (after - before).max(0) as usize)
+    }
+
+    #[tokio::test]
+    async fn test_full_crud_operations() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let db = TimeSeriesDB::new(temp_dir.path(), 1000)?; // 增大阈值
+
+        let mut tags = BTreeMap::new();
+        tags.insert("sensor".to_string(), "temperature".to_string());
+        tags.insert("location".to_string(), "room1".to_string());
+
+        // 测试插入
+        println!("🔧 插入10个数据点...");
+        for i in 0..10 {
+            let dp = DataPoint {
+                timestamp: 1609459200 + i * 60,
+                value: 20.0 + (i as f64) * 0.5,
+                tags: tags.clone(),
+            };
+            db.insert("temp_sensor_1".to_string(), dp).await?;
+        }
+
+        // 验证插入 - 查询所有数据
+        let all_results = db.query_range("temp_sensor_1", None, None).await?;
+        println!("📊 查询到 {} 个数据点", all_results.len());
+        assert_eq!(all_results.len(), 10, "应该有10个数据点");
+
+        // 测试更新
+        println!("✏️ 测试更新操作...");
+        let updated = db.update("temp_sensor_1", 1609459200, 25.0).await?;
+        println!("更新结果: {}", updated);
+
+        // 验证更新 - 查询所有数据并检查第一个点的值
+        let updated_results = db.query_range("temp_sensor_1", None, None).await?;
+        let first_point = updated_results.iter().find(|dp| dp.timestamp == 1609459200);
+        assert!(first_point.is_some(), "应该找到时间戳为1609459200的数据点");
+        if let Some(point) = first_point {
+            assert_eq!(point.value, 25.0, "更新后的值应该是25.0");
+        }
+
+        // 测试删除
+        println!("🗑️ 测试删除操作...");
+        let deleted = db.delete("temp_sensor_1", Some(1609459200), false).await?;
+        println!("删除结果: {}", deleted);
+
+        // 验证删除
+        let after_delete = db.query_range("temp_sensor_1", None, None).await?;
+        let deleted_point = after_delete.iter().find(|dp| dp.timestamp == 1609459200);
+        assert!(deleted_point.is_none(), "删除的数据点不应该存在");
+        assert_eq!(after_delete.len(), 9, "删除后应该剩余9个数据点");
+
+        // 测试系列列表
+        let series = db.get_all_series().await?;
+        assert!(series.contains(&"temp_sensor_1".to_string()));
+
+        // 测试删除整个系列
+        let series_deleted = db.delete("temp_sensor_1", None, false).await?;
+        assert!(series_deleted);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_close_flushes_and_stops_tasks() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let db = TimeSeriesDB::new(temp_dir.path(), 1000)?;
+        db.spawn_compaction_task(std::time::Duration::from_secs(3600));
+
+        for i in 0..5 {
+            let dp = DataPoint {
+                timestamp: 1609459200 + i * 60,
+                value: i as f64,
+                tags: BTreeMap::new(),
+            };
+            db.insert("close_series".to_string(), dp).await?;
+        }
+
+        let handle = db.clone();
+        assert_eq!(handle.background_task_count(), 1);
+        db.close().await?;
+        assert_eq!(handle.background_task_count(), 0, "后台任务应已退出");
+
+        let data_files = std::fs::read_dir(temp_dir.path())?
+            .flatten()
+            .filter(|e| e.path().extension().and_then(|s| s.to_str()) == Some("data"))
+            .count();
+        assert_eq!(data_files, 1, "close后内存表应已刷新到磁盘");
+
+        // 数据目录锁已释放，可以重新打开
+        drop(handle);
+        let reopened = TimeSeriesDB::new(temp_dir.path(), 1000)?;
+        let results = reopened.query_range("close_series", None, None).await?;
+        assert_eq!(results.len(), 5);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_memtable_flush_by_age() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let clock = std::sync::Arc::new(MockClock::from_secs(1609459200));
+        let db = TimeSeriesDB::builder(temp_dir.path())
+            .memtable_threshold(1000)
+            .max_memtable_age(std::time::Duration::from_secs(60))
+            .clock(clock.clone())
+            .build()?;
+
+        let dp = |ts: u64| DataPoint { timestamp: ts, value: 1.0, tags: BTreeMap::new() };
+        db.insert("slow_series".to_string(), dp(1609459200)).await?;
+
+        clock.advance(std::time::Duration::from_secs(30));
+        let stats = db.get_stats().await?;
+        assert_eq!(stats.oldest_memtable_age_secs, Some(30));
+        assert_eq!(stats.sstable_count, 0);
+
+        // 超过驻留上限后，下一次写入触发刷新
+        clock.advance(std::time::Duration::from_secs(31));
+        db.insert("slow_series".to_string(), dp(1609459260)).await?;
+        let stats = db.get_stats().await?;
+        assert_eq!(stats.sstable_count, 1);
+        assert_eq!(stats.memtable_size, 0);
+        assert_eq!(stats.oldest_memtable_age_secs, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_aggregate_across_percentile() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let db = TimeSeriesDB::new(temp_dir.path(), 1000)?;
+
+        // 10台主机，每台每分钟2个点，另有一个不匹配的系列
+        for host in 0..10u64 {
+            let mut tags = BTreeMap::new();
+            tags.insert("metric".to_string(), "cpu".to_string());
+            tags.insert("host".to_string(), format!("host{}", host));
+            for minute in 0..3u64 {
+                for j in 0..2u64 {
+                    let dp = DataPoint {
+                        timestamp: 1609459200 + minute * 60 + j * 30,
+                        value: (host * 10 + minute + j) as f64,
+                        tags: tags.clone(),
+                    };
+                    db.insert(format!("cpu_host{}", host), dp).await?;
+                }
+            }
+        }
+        let mut mem_tags = BTreeMap::new();
+        mem_tags.insert("metric".to_string(), "mem".to_string());
+        db.insert("mem_host0".to_string(), DataPoint { timestamp: 1609459200, value: 1e6, tags: mem_tags }).await?;
+
+        let mut matchers = BTreeMap::new();
+        matchers.insert("metric".to_string(), "cpu".to_string());
+        let (series, buckets) = db
+            .aggregate_across(&matchers, None, None, 60, "p95".parse().unwrap())
+            .await?;
+
+        assert_eq!(series.len(), 10);
+        assert_eq!(buckets.len(), 3);
+        for (minute, bucket) in buckets.iter().enumerate() {
+            let mut values: Vec<f64> = (0..10u64)
+                .flat_map(|host| (0..2u64).map(move |j| (host * 10 + minute as u64 + j) as f64))
+                .collect();
+            let expected = percentile(&mut values, 95.0).unwrap();
+            assert_eq!(bucket.timestamp, 1609459200 + minute as u64 * 60);
+            assert_eq!(bucket.series_count, 10);
+            assert!((bucket.value - expected).abs() < 1e-9);
+        }
+        // 20个值 (host*10+m+j)，p95 对应排序后下标18.05，落在90+m与91+m之间
+        assert!((buckets[0].value - 90.05).abs() < 1e-9);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_disk_watermark_rejects_writes() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        {
+            let db = TimeSeriesDB::new(temp_dir.path(), 1000)?;
+            let dp = DataPoint { timestamp: 1609459200, value: 1.0, tags: BTreeMap::new() };
+            db.insert("existing".to_string(), dp).await?;
+            db.close().await?;
+        }
+
+        // 水位线设到任何磁盘都达不到的值
+        let db = TimeSeriesDB::builder(temp_dir.path())
+            .disk_watermarks(u64::MAX - 1, 0)
+            .build()?;
+        assert_eq!(db.disk_level(), DiskLevel::Low);
+
+        let dp = DataPoint { timestamp: 1609459260, value: 2.0, tags: BTreeMap::new() };
+        let err = db.insert("existing".to_string(), dp).await.unwrap_err();
+        assert!(matches!(DbError::from_io(&err), Some(DbError::DiskFull { .. })));
+
+        // 删除仍然允许，用于释放空间
+        assert!(db.delete("existing", None, false).await?);
+        assert!(db.get_all_series().await?.is_empty());
+
+        let stats = db.get_stats().await?;
+        assert!(stats.disk_free_bytes.is_some());
+        assert_eq!(stats.disk_level, DiskLevel::Low);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_per_series_retention_rules() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let now = 1_700_000_000u64;
+        let clock = std::sync::Arc::new(MockClock::from_secs(now));
+        let db = TimeSeriesDB::builder(temp_dir.path())
+            .memtable_threshold(6)
+            .clock(clock)
+            .build()?;
+
+        const DAY: u64 = 86400;
+        let ages = [400 * DAY, 30 * DAY, 2 * DAY, 3600];
+        for series in ["debug_trace", "billing"] {
+            for age in ages {
+                let dp = DataPoint { timestamp: now - age, value: 1.0, tags: BTreeMap::new() };
+                db.insert(series.to_string(), dp).await?;
+            }
+        }
+        // 前6个点已刷新到SSTable，其余仍在内存表
+        assert_eq!(db.get_stats().await?.sstable_count, 1);
+
+        db.set_retention_policy(RetentionPolicy {
+            rules: vec![RetentionRule {
+                matcher: SeriesMatcher::Prefix("debug_".to_string()),
+                max_age_secs: DAY,
+            }],
+            default_max_age_secs: Some(365 * DAY),
+        })?;
+
+        let report = db.enforce_retention().await?;
+        assert_eq!(report.series_trimmed, 2);
+        assert_eq!(report.points_removed, 3 + 1);
+
+        let debug: Vec<u64> = db.query_range("debug_trace", None, None).await?.iter().map(|dp| dp.timestamp).collect();
+        assert_eq!(debug, vec![now - 3600]);
+        let billing: Vec<u64> = db.query_range("billing", None, None).await?.iter().map(|dp| dp.timestamp).collect();
+        assert_eq!(billing, vec![now - 30 * DAY, now - 2 * DAY, now - 3600]);
+
+        // 规则持久化在manifest中
+        let manifest = Manifest::load(temp_dir.path())?;
+        assert_eq!(manifest.retention.rules.len(), 1);
+        assert_eq!(manifest.retention.default_max_age_secs, Some(365 * DAY));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_time_bounded_compaction() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let db = TimeSeriesDB::new(temp_dir.path(), 4)?;
+        let dp = |ts: u64, value: f64| DataPoint { timestamp: ts, value, tags: BTreeMap::new() };
+
+        const JAN: u64 = 1609459200; // 2021-01-01
+        const FEB: u64 = 1612137600; // 2021-02-01
+        const MAR: u64 = 1614556800; // 2021-03-01
+
+        // 文件1: 一月回填数据 + 二月的其他系列
+        db.insert("backfill".to_string(), dp(JAN, 1.0)).await?;
+        db.insert("backfill".to_string(), dp(JAN + 60, 2.0)).await?;
+        db.insert("other".to_string(), dp(FEB + 10, 3.0)).await?;
+        db.insert("other".to_string(), dp(FEB + 20, 4.0)).await?;
+        // 文件2: 一月回填数据
+        for i in 2..6 {
+            db.insert("backfill".to_string(), dp(JAN + i * 60, i as f64)).await?;
+        }
+        // 文件3: 二月数据，不在窗口内
+        for i in 0..4 {
+            db.insert("backfill".to_string(), dp(FEB + i * 60, i as f64)).await?;
+        }
+        assert_eq!(db.get_stats().await?.sstable_count, 3);
+
+        let report = db
+            .compact_with(&CompactionOptions::window(Some(JAN), Some(FEB - 1)))
+            .await?;
+        assert_eq!(report.inputs.len(), 2);
+        assert_eq!(report.series_merged, 1);
+        assert!(report.output.is_some());
+        assert!(report.bytes_rewritten > 0);
+
+        // 文件2被合并删除，文件1只保留other，文件3不变，新增一个合并文件
+        assert_eq!(db.get_stats().await?.sstable_count, 3);
+        let backfill = db.query_range("backfill", None, None).await?;
+        assert_eq!(backfill.len(), 10);
+        let other = db.query_range("other", None, None).await?;
+        assert_eq!(other.len(), 2);
+
+        // 窗口内只有一个文件时无需合并
+        let report = db
+            .compact_with(&CompactionOptions::window(Some(MAR), None))
+            .await?;
+        assert!(report.skipped_reason.is_some());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_millisecond_precision_inserts() -> anyhow::Result<()> {
+        let server = testing::spawn_test_server_with(|b| b.memtable_threshold(3).timestamp_precision(TimePrecision::Milliseconds)).await?;
+
+        let request = |timestamp: u64, value: f64| serde_json::json!({ "series_key": "ms_series", "timestamp": timestamp, "value": value });
+
+        // 同一秒内的多个点，以不同精度写入
+        const BASE_MS: u64 = 1609459200 * 1000;
+        let writes = [
+            ("?precision=ms", request(BASE_MS + 250, 2.0)),
+            ("?precision=ns", request((BASE_MS + 100) * 1_000_000, 1.0)),
+            ("?precision=s", request(1609459200, 0.0)),
+            ("", request(BASE_MS + 900, 3.0)),
+        ];
+        for (params, body) in writes {
+            let response = server.post_json(&format!("/api/v1/datapoints{}", params), &body).await?;
+            assert_eq!(response.status, axum::http::StatusCode::OK);
+        }
+
+        let results = server.db_handle.query_range("ms_series", None, None).await?;
+        let timestamps: Vec<u64> = results.iter().map(|dp| dp.timestamp).collect();
+        assert_eq!(timestamps, vec![BASE_MS, BASE_MS + 100, BASE_MS + 250, BASE_MS + 900]);
+        let values: Vec<f64> = results.iter().map(|dp| dp.value).collect();
+        assert_eq!(values, vec![0.0, 1.0, 2.0, 3.0]);
+
+        // 精度写入manifest，以其他精度重新打开会被拒绝
+        let temp_dir = server.stop_keeping_data().await?;
+        let reopened = TimeSeriesDB::builder(temp_dir.path())
+            .timestamp_precision(TimePrecision::Seconds)
+            .build();
+        assert!(reopened.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_tag_drift_detection() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+
+        let mut tags = BTreeMap::new();
+        tags.insert("location".to_string(), "room1".to_string());
+        let mut drifted = BTreeMap::new();
+        drifted.insert("location".to_string(), "Room1".to_string());
+
+        // warn模式：写入成功，但计数
+        let db = TimeSeriesDB::builder(temp_dir.path())
+            .tag_drift_mode(TagDriftMode::Warn)
+            .build()?;
+        db.insert("temp".to_string(), DataPoint { timestamp: 1, value: 1.0, tags: tags.clone() }).await?;
+        db.insert("temp".to_string(), DataPoint { timestamp: 2, value: 2.0, tags: drifted.clone() }).await?;
+        db.insert("temp".to_string(), DataPoint { timestamp: 3, value: 3.0, tags: tags.clone() }).await?;
+        let stats = db.get_stats().await?;
+        assert_eq!(stats.tag_drift_counts.get("temp"), Some(&1));
+        db.close().await?;
+
+        // reject模式：重新打开后从SSTable元数据恢复已有标签
+        let db = TimeSeriesDB::builder(temp_dir.path())
+            .tag_drift_mode(TagDriftMode::Reject)
+            .build()?;
+        let err = db
+            .insert("temp".to_string(), DataPoint { timestamp: 4, value: 4.0, tags: drifted.clone() })
+            .await
+            .unwrap_err();
+        assert!(matches!(DbError::from_io(&err), Some(DbError::TagDrift { .. })));
+        db.insert("temp".to_string(), DataPoint { timestamp: 5, value: 5.0, tags: tags.clone() }).await?;
+        assert_eq!(db.get_stats().await?.tag_drift_counts.get("temp"), Some(&1));
+
+        // 删除整个系列后可以用新标签重新建立
+        db.delete("temp", None, false).await?;
+        db.insert("temp".to_string(), DataPoint { timestamp: 6, value: 6.0, tags: drifted }).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_truncate_removes_all_data() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let db = TimeSeriesDB::new(temp_dir.path(), 5)?;
+
+        for i in 0..12u64 {
+            let series_key = format!("series_{}", i % 3);
+            db.insert(series_key, DataPoint { timestamp: 1000 + i, value: i as f64, tags: BTreeMap::new() }).await?;
+        }
+        assert!(db.get_stats().await?.sstable_count > 0);
+
+        db.truncate().await?;
+
+        assert!(db.get_all_series().await?.is_empty());
+        let data_files = std::fs::read_dir(temp_dir.path())?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().extension().and_then(|s| s.to_str()) == Some("data"))
+            .count();
+        assert_eq!(data_files, 0);
+
+        // 清空后仍可正常写入
+        db.insert("series_0".to_string(), DataPoint { timestamp: 2000, value: 1.0, tags: BTreeMap::new() }).await?;
+        assert_eq!(db.query_range("series_0", None, None).await?.len(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_threshold_crossings() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let db = TimeSeriesDB::new(temp_dir.path(), 4)?;
+
+        // 乱序写入，部分刷新到SSTable，部分留在内存表
+        let values = [(5, 90.0), (1, 85.0), (2, 70.0), (3, 80.0), (4, 60.0), (6, 95.0), (7, 50.0)];
+        for (timestamp, value) in values {
+            db.insert("cpu".to_string(), DataPoint { timestamp, value, tags: BTreeMap::new() }).await?;
+        }
+        assert!(db.get_stats().await?.sstable_count > 0);
+
+        let above = db.find_crossings("cpu", 80.0, CrossingDirection::Above, None, None).await?;
+        let above_ts: Vec<u64> = above.iter().map(|c| c.timestamp).collect();
+        assert_eq!(above_ts, vec![3, 5]);
+        assert_eq!((above[0].previous_timestamp, above[0].previous_value), (2, 70.0));
+
+        let both = db.find_crossings("cpu", 80.0, CrossingDirection::Both, None, None).await?;
+        let both_ts: Vec<u64> = both.iter().map(|c| c.timestamp).collect();
+        assert_eq!(both_ts, vec![2, 3, 4, 5, 7]);
+
+        // 范围内第一个点（时间戳3，已高于阈值）没有前一个点，不算穿越
+        let ranged = db.find_crossings("cpu", 80.0, CrossingDirection::Above, Some(3), Some(6)).await?;
+        let ranged_ts: Vec<u64> = ranged.iter().map(|c| c.timestamp).collect();
+        assert_eq!(ranged_ts, vec![5]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_ndjson_stream_ingest() -> anyhow::Result<()> {
+        const TOTAL: u64 = 100_000;
+        let server = testing::spawn_test_server_with(|b| b.memtable_threshold(1000)).await?;
+        let db = &server.db_handle;
+
+        let mut ndjson = String::new();
+        for i in 0..TOTAL {
+            ndjson.push_str(&format!(
+                "{{\"series_key\":\"stream_{}\",\"timestamp\":{},\"value\":{}}}\n",
+                i % 10, 1_000_000 + i, i
+            ));
+        }
+        // 按固定大小分块传输，行会跨越块边界
+        let response = server
+            .request_chunked("POST", "/api/v1/datapoints/stream", &[("Content-Type", "application/x-ndjson")], ndjson.as_bytes(), 8192)
+            .await?;
+        assert_eq!(response.status, axum::http::StatusCode::OK);
+        let report = response.json()?;
+        assert_eq!(report["data"]["inserted"], TOTAL);
+        assert_eq!(report["data"]["failed"], 0);
+
+        // 写入过程中内存表按阈值刷新，驻留数据不超过阈值
+        let stats = db.get_stats().await?;
+        assert!(stats.memtable_size <= 1000);
+        assert!(stats.sstable_count >= (TOTAL / 1000 - 1) as usize);
+
+        let mut total = 0;
+        for series in 0..10 {
+            total += db.query_range(&format!("stream_{}", series), None, None).await?.len();
+        }
+        assert_eq!(total, TOTAL as usize);
+
+        // 没有换行的超长请求体被拒绝
+        let response = server.request("POST", "/api/v1/datapoints/stream", &[], &vec![b'x'; 128 * 1024]).await?;
+        assert_eq!(response.status, axum::http::StatusCode::PAYLOAD_TOO_LARGE);
+
+        server.stop().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_envelope_downsampling() -> anyhow::Result<()> {
+        let server = testing::spawn_test_server_with(|b| b.memtable_threshold(4)).await?;
+        let db = &server.db_handle;
+
+        // 桶 [0,10) 含尖峰，桶 [10,20) 为空，桶 [20,30) 两个点
+        let points = [(1, 10.0), (3, 99.0), (5, 11.0), (8, 9.0), (21, 5.0), (25, 7.0)];
+        for (timestamp, value) in points {
+            db.insert("vibration".to_string(), DataPoint { timestamp, value, tags: BTreeMap::new() }).await?;
+        }
+
+        let envelope = db.downsample_envelope("vibration", None, None, 10, FillPolicy::None).await?;
+        assert_eq!(envelope.len(), 2);
+        assert_eq!(
+            envelope[0],
+            EnvelopeBucket { bucket_ts: 0, min: Some(9.0), max: Some(99.0), avg: Some(32.25), first: Some(10.0), last: Some(9.0), count: 4 }
+        );
+
+        // null填充：空桶保留 bucket_ts，包络的各个值为null、count为0，范围两端也按start/end补齐
+        let filled = db.downsample_envelope("vibration", Some(0), Some(39), 10, FillPolicy::Null).await?;
+        let bucket_ts: Vec<u64> = filled.iter().map(|b| b.bucket_ts).collect();
+        assert_eq!(bucket_ts, vec![0, 10, 20, 30]);
+        assert_eq!(filled[1], EnvelopeBucket::empty(10));
+        assert_eq!(filled[3].max, None);
+        assert_eq!((filled[2].first, filled[2].last), (Some(5.0), Some(7.0)));
+
+        // HTTP响应中空桶是字段为null的对象而不是null，客户端仍能按 bucket_ts 放到时间轴上
+        let body = server.get("/api/v1/series/vibration/downsample?interval=10&fn=envelope&fill=null&start_time=0&end_time=39").await?.json()?;
+        let buckets = body["data"]["buckets"].as_array().unwrap();
+        assert_eq!(buckets.len(), 4);
+        assert_eq!(
+            buckets[1],
+            serde_json::json!({"bucket_ts": 10, "min": null, "max": null, "avg": null, "first": null, "last": null, "count": 0})
+        );
+        assert_eq!(buckets[2]["min"], 5.0);
+
+        // 平均值降采样会抹掉尖峰
+        let avg = db.downsample("vibration", None, None, 10, AggregateFn::Avg, FillPolicy::Null).await?;
+        assert_eq!(avg.len(), 3);
+        assert_eq!(avg[0].value, Some(32.25));
+        assert_eq!(avg[1].value, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reject_inexact_integer_values() {
+        use api::models::{CreateDataPointRequest, UpdateDataPointRequest};
+
+        // 2^53 + 1 无法用f64精确表示
+        let err = serde_json::from_str::<CreateDataPointRequest>(
+            r#"{"series_key": "counter", "timestamp": 1, "value": 9007199254740993}"#,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("9007199254740993"));
+        assert!(serde_json::from_str::<UpdateDataPointRequest>(r#"{"value": -9007199254740993}"#).is_err());
+
+        // 精确范围内的整数和普通浮点数不受影响
+        let request = serde_json::from_str::<CreateDataPointRequest>(
+            r#"{"series_key": "counter", "timestamp": 1, "value": 9007199254740992}"#,
+        )
+        .unwrap();
+        assert_eq!(request.value, 9007199254740992.0);
+        let request = serde_json::from_str::<UpdateDataPointRequest>(r#"{"value": 23.5}"#).unwrap();
+        assert_eq!(request.value, 23.5);
+    }
+
+    #[tokio::test]
+    async fn test_large_series_split_into_blocks() -> anyhow::Result<()> {
+        const TOTAL: u64 = 1_000_000;
+        let temp_dir = TempDir::new()?;
+        let db = TimeSeriesDB::new(temp_dir.path(), TOTAL as usize)?;
+
+        for i in 0..TOTAL {
+            db.insert("big".to_string(), DataPoint { timestamp: i, value: (i % 100) as f64, tags: BTreeMap::new() }).await?;
+        }
+        assert_eq!(db.get_stats().await?.sstable_count, 1);
+
+        let path = std::fs::read_dir(temp_dir.path())?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .find(|path| path.extension().and_then(|s| s.to_str()) == Some("data"))
+            .unwrap();
+        let mut sstable = SSTable::new(path)?;
+        let blocks = sstable.load_series_list()?;
+        assert_eq!(blocks.len(), (TOTAL as usize).div_ceil(MAX_POINTS_PER_BLOCK));
+        assert!(blocks.iter().all(|block| block.count <= MAX_POINTS_PER_BLOCK));
+
+        // 窄范围查询只涉及一个块
+        let narrow = sstable.series_blocks("big", Some(450_000), Some(450_100))?;
+        assert_eq!(narrow.len(), 1);
+        assert!(narrow[0].count <= MAX_POINTS_PER_BLOCK);
+
+        let results = db.query_range("big", Some(450_000), Some(450_100)).await?;
+        assert_eq!(results.len(), 101);
+        assert_eq!(results[0].timestamp, 450_000);
+
+        // 删除一个块中的单点不影响同系列的其他块
+        assert!(db.delete("big", Some(0), false).await?);
+        assert_eq!(db.query_range("big", Some(999_999), None).await?.len(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_queries_coalesced() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        // 内联执行的查询不会让出，800个点的查询需要转交线程池才会同时等待
+        let db = TimeSeriesDB::builder(temp_dir.path())
+            .memtable_threshold(100)
+            .inline_query_points(799)
+            .build()?;
+
+        for i in 0..1000u64 {
+            db.insert("cold".to_string(), DataPoint { timestamp: i, value: i as f64, tags: BTreeMap::new() }).await?;
+        }
+
+        // join_all在第一次poll时依次进入所有查询，保证它们同时处于等待状态
+        let queries = (0..50).map(|_| db.query_range("cold", Some(100), Some(899)));
+        for result in futures_util::future::join_all(queries).await {
+            assert_eq!(result?.len(), 800);
+        }
+
+        let stats = db.get_stats().await?;
+        assert_eq!(stats.sstable_scans, 1);
+        assert_eq!(stats.coalesced_queries, 49);
+
+        // 执行完成后不缓存结果，后续查询重新读取
+        db.query_range("cold", Some(100), Some(899)).await?;
+        assert_eq!(db.get_stats().await?.sstable_scans, 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_last_timestamp_tracking() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let db = TimeSeriesDB::new(temp_dir.path(), 3)?;
+
+        for timestamp in [10, 30, 20, 40, 50] {
+            db.insert("fresh".to_string(), DataPoint { timestamp, value: 1.0, tags: BTreeMap::new() }).await?;
+        }
+        assert_eq!(db.last_timestamp("fresh"), Some(50));
+        assert_eq!(db.last_timestamp("missing"), None);
+
+        // 重新打开后从SSTable元数据重建（内存表中的点在close时刷新）
+        db.close().await?;
+        let db = TimeSeriesDB::new(temp_dir.path(), 3)?;
+        assert_eq!(db.last_timestamp("fresh"), Some(50));
+
+        // 删除最新的点后回退到次新的点
+        db.delete("fresh", Some(50), false).await?;
+        assert_eq!(db.last_timestamp("fresh"), Some(40));
+        db.delete("fresh", Some(10), false).await?;
+        assert_eq!(db.last_timestamp("fresh"), Some(40));
+
+        db.delete_range("fresh", Some(35), None, false).await?;
+        assert_eq!(db.last_timestamp("fresh"), Some(30));
+
+        db.delete("fresh", None, false).await?;
+        assert_eq!(db.last_timestamp("fresh"), None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_series_created_since() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let clock = std::sync::Arc::new(MockClock::from_secs(1_000));
+        let db = TimeSeriesDB::builder(temp_dir.path())
+            .memtable_threshold(2)
+            .clock(clock.clone())
+            .build()?;
+
+        // 系列按时钟先后开始上报，首个数据点的时间戳与写入时的时钟无关
+        let point = |timestamp| DataPoint { timestamp, value: 1.0, tags: BTreeMap::new() };
+        db.insert("old_metric".to_string(), point(1_000)).await?;
+        clock.advance(std::time::Duration::from_secs(3600));
+        db.insert("new_metric".to_string(), point(4_600)).await?;
+        // 已有系列的后续写入不改变首个数据点
+        db.insert("old_metric".to_string(), point(4_600)).await?;
+        clock.advance(std::time::Duration::from_secs(60));
+        db.insert("newest_metric".to_string(), point(4_660)).await?;
+        // 晚到的历史数据按数据点的时间戳计算，不算新系列
+        db.insert("backfilled".to_string(), point(500)).await?;
+
+        let recent = db.series_created_since(1_000 + 1800);
+        assert_eq!(
+            recent,
+            vec![("new_metric".to_string(), 4_600), ("newest_metric".to_string(), 4_660)]
+        );
+
+        // 写入更早的点后首个数据点随之提前
+        db.insert("new_metric".to_string(), point(2_000)).await?;
+        assert_eq!(db.first_seen("new_metric"), Some(2_000));
+        assert_eq!(db.series_created_since(1_000 + 1800), vec![("newest_metric".to_string(), 4_660)]);
+
+        // 首个数据点持久化在manifest中，重启后保留；删除的系列不再出现
+        db.delete("newest_metric", None, false).await?;
+        db.close().await?;
+        let db = TimeSeriesDB::builder(temp_dir.path()).clock(clock.clone()).build()?;
+        assert_eq!(db.first_seen("old_metric"), Some(1_000));
+        assert_eq!(db.first_seen("backfilled"), Some(500));
+        assert_eq!(db.series_created_since(1_000), vec![("new_metric".to_string(), 2_000)]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_stale_series() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let clock = std::sync::Arc::new(MockClock::from_secs(10_000));
+        let db = TimeSeriesDB::builder(temp_dir.path())
+            .memtable_threshold(3)
+            .clock(clock.clone())
+            .build()?;
+
+        let mut team_a = BTreeMap::new();
+        team_a.insert("team".to_string(), "a".to_string());
+        let mut team_b = BTreeMap::new();
+        team_b.insert("team".to_string(), "b".to_string());
+
+        let points = [
+            ("sensor_1", 100, 1.5, &team_a),
+            ("sensor_1", 200, 2.5, &team_a),
+            ("sensor_2", 9_900, 3.5, &team_a),
+            ("sensor_3", 300, 4.5, &team_b),
+            ("pump_1", 50, 5.5, &team_b),
+        ];
+        for (key, timestamp, value, tags) in points {
+            db.insert(key.to_string(), DataPoint { timestamp, value, tags: tags.clone() }).await?;
+        }
+
+        // 阈值300秒：最新时间戳早于9700的系列
+        let stale = db.stale_series(300, None, &BTreeMap::new(), false).await?;
+        let keys: Vec<&str> = stale.iter().map(|s| s.series_key.as_str()).collect();
+        assert_eq!(keys, vec!["pump_1", "sensor_1", "sensor_3"]);
+        assert!(stale.iter().all(|s| s.last_value.is_none()));
+
+        let stale = db.stale_series(300, Some("sensor_"), &team_a, true).await?;
+        assert_eq!(
+            stale,
+            vec![StaleSeries { series_key: "sensor_1".to_string(), last_timestamp: 200, last_value: Some(2.5) }]
+        );
+
+        // 时间推进后新上报的系列也会变陈旧
+        clock.advance(std::time::Duration::from_secs(1_000));
+        let stale = db.stale_series(300, Some("sensor_"), &BTreeMap::new(), true).await?;
+        let values: Vec<Option<f64>> = stale.iter().map(|s| s.last_value).collect();
+        assert_eq!(values, vec![Some(2.5), Some(4.5), Some(3.5)]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cumulative_histogram() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let db = TimeSeriesDB::new(temp_dir.path(), 4)?;
+
+        // 值 1..=10，每个值出现一次
+        for i in 1..=10u64 {
+            db.insert("latency".to_string(), DataPoint { timestamp: i, value: i as f64, tags: BTreeMap::new() }).await?;
+        }
+
+        let histogram = db.histogram("latency", None, None, vec![2.0, 5.0, 7.5]).await?;
+        let buckets: Vec<(String, u64)> = histogram.buckets().into_iter().map(|b| (b.le, b.count)).collect();
+        assert_eq!(
+            buckets,
+            vec![("2".to_string(), 2), ("5".to_string(), 5), ("7.5".to_string(), 7), ("+Inf".to_string(), 10)]
+        );
+        assert_eq!(histogram.count(), 10);
+        assert_eq!(histogram.sum(), 55.0);
+
+        // 时间范围过滤，边界值计入 le 等于它的桶
+        let ranged = db.histogram("latency", Some(5), Some(8), vec![5.0, 6.0]).await?;
+        let counts: Vec<u64> = ranged.buckets().iter().map(|b| b.count).collect();
+        assert_eq!(counts, vec![1, 2, 4]);
+
+        assert!(db.histogram("latency", None, None, vec![5.0, 2.0]).await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_location_index_limits_fan_out() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        // 阈值为1时每次写入都刷新为一个SSTable
+        let db = TimeSeriesDB::new(temp_dir.path(), 1)?;
+
+        for timestamp in 0..48 {
+            db.insert(format!("other_{}", timestamp % 4), DataPoint { timestamp, value: 1.0, tags: BTreeMap::new() }).await?;
+        }
+        for timestamp in [10, 20] {
+            db.insert("target".to_string(), DataPoint { timestamp, value: 2.0, tags: BTreeMap::new() }).await?;
+        }
+        let stats = db.get_stats().await?;
+        assert_eq!(stats.sstable_count, 50);
+        assert!(stats.location_index_warm);
+
+        let visited = stats.sstable_files_visited;
+        assert!(db.update("target", 20, 3.0).await?);
+        assert_eq!(db.get_stats().await?.sstable_files_visited - visited, 2);
+
+        let visited = db.get_stats().await?.sstable_files_visited;
+        let points = db.query_range("target", None, None).await?;
+        assert_eq!(points.iter().map(|dp| dp.value).collect::<Vec<_>>(), vec![2.0, 3.0]);
+        assert_eq!(db.get_stats().await?.sstable_files_visited - visited, 2);
+
+        // 重新打开后从元数据重建索引
+        db.close().await?;
+        let db = TimeSeriesDB::new(temp_dir.path(), 1)?;
+        let visited = db.get_stats().await?.sstable_files_visited;
+        assert!(db.delete("target", Some(10), false).await?);
+        let stats = db.get_stats().await?;
+        assert_eq!(stats.sstable_files_visited - visited, 2);
+        assert_eq!(stats.sstable_count, 49);
+        assert_eq!(stats.location_index_misses, 0);
+
+        // compaction后索引指向新文件
+        db.compact().await?;
+        assert_eq!(db.query_range("target", None, None).await?.len(), 1);
+        assert_eq!(db.query_range("other_1", None, None).await?.len(), 12);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_source_stats() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let db = TimeSeriesDB::new(temp_dir.path(), 4)?;
+
+        // 前4个点刷新到SSTable，之后的点留在内存表
+        for timestamp in 1..=6 {
+            db.insert("mixed".to_string(), DataPoint { timestamp, value: 1.0, tags: BTreeMap::new() }).await?;
+        }
+        let (points, stats) = db.query_range_with_stats("mixed", None, None).await?;
+        assert_eq!(points.len(), 6);
+        assert_eq!(stats, QueryStats { memtable_points: 2, sstable_points: 4, files_scanned: 1, sources_merged: 2, duplicates_resolved: 0 });
+
+        // 内存表中覆盖SSTable已有的时间戳，去重后计入内存表
+        db.insert("mixed".to_string(), DataPoint { timestamp: 3, value: 9.0, tags: BTreeMap::new() }).await?;
+        let (points, stats) = db.query_range_with_stats("mixed", None, None).await?;
+        assert_eq!(points.len(), 6);
+        assert_eq!(points[2].value, 9.0);
+        assert_eq!(stats.memtable_points + stats.sstable_points, points.len());
+        assert_eq!(stats, QueryStats { memtable_points: 3, sstable_points: 3, files_scanned: 1, sources_merged: 2, duplicates_resolved: 1 });
+
+        let (points, stats) = db.query_range_with_stats("mixed", Some(5), None).await?;
+        assert_eq!(points.len(), 2);
+        assert_eq!(stats, QueryStats { memtable_points: 2, sstable_points: 0, files_scanned: 1, sources_merged: 1, duplicates_resolved: 0 });
+
+        // 同一段时间在多个文件中重复写入时，按块归并并统计丢弃的重复点
+        let db = TimeSeriesDB::new(temp_dir.path().join("overlap"), 4)?;
+        for round in 0..5u64 {
+            for timestamp in [40, 10, 30, 20] {
+                db.insert("overlap".to_string(), DataPoint { timestamp, value: round as f64, tags: BTreeMap::new() }).await?;
+            }
+        }
+        db.insert("overlap".to_string(), DataPoint { timestamp: 25, value: 9.0, tags: BTreeMap::new() }).await?;
+        let (points, stats) = db.query_range_with_stats("overlap", None, None).await?;
+        let timestamps: Vec<u64> = points.iter().map(|dp| dp.timestamp).collect();
+        assert_eq!(timestamps, vec![10, 20, 25, 30, 40]);
+        // 重复的时间戳保留最早的文件中的值
+        assert!(points.iter().filter(|dp| dp.timestamp != 25).all(|dp| dp.value == 0.0));
+        assert_eq!(stats.sources_merged, 6);
+        assert_eq!(stats.duplicates_resolved, 16);
+        assert_eq!(stats.files_scanned, 5);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_compaction_report_history() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let db = TimeSeriesDB::new(temp_dir.path(), 4)?;
+        let dp = |ts: u64| DataPoint { timestamp: ts, value: ts as f64, tags: BTreeMap::new() };
+
+        // 文件1: a 1..=4；文件2: a 3..=6（与文件1重复2个）；文件3: a 7 和 b 1..=3
+        for ts in 1..=4 {
+            db.insert("a".to_string(), dp(ts)).await?;
+        }
+        for ts in 3..=6 {
+            db.insert("a".to_string(), dp(ts)).await?;
+        }
+        db.insert("a".to_string(), dp(7)).await?;
+        for ts in 1..=3 {
+            db.insert("b".to_string(), dp(ts)).await?;
+        }
+        assert_eq!(db.get_stats().await?.sstable_count, 3);
+        assert!(db.compaction_history().is_empty());
+
+        let mut sizes = BTreeMap::new();
+        for entry in std::fs::read_dir(temp_dir.path())? {
+            let entry = entry?;
+            if entry.path().extension().is_some_and(|ext| ext == "data") {
+                sizes.insert(entry.file_name().to_string_lossy().into_owned(), entry.metadata()?.len());
+            }
+        }
+
+        let report = db.compact().await?;
+        assert!(report.skipped_reason.is_none());
+        assert_eq!(report.inputs.len(), 3);
+        for input in &report.inputs {
+            assert_eq!(Some(&input.size_bytes), sizes.get(&input.file_name));
+        }
+        let mut series_counts: Vec<usize> = report.inputs.iter().map(|input| input.series_count).collect();
+        series_counts.sort();
+        assert_eq!(series_counts, vec![1, 1, 2]);
+        assert_eq!(report.series_merged, 2);
+        assert_eq!(report.points_merged, 12);
+        assert_eq!(report.duplicates_removed, 2);
+
+        let output = report.output.as_ref().unwrap();
+        assert_eq!(output.size_bytes, std::fs::metadata(temp_dir.path().join(&output.file_name))?.len());
+        assert_eq!(report.bytes_rewritten, output.size_bytes);
+        assert_eq!(db.query_range("a", None, None).await?.len() + db.query_range("b", None, None).await?.len(), 10);
+
+        // 只剩一个文件时跳过，不记录到历史
+        let skipped = db.compact().await?;
+        assert!(skipped.skipped_reason.is_some());
+        let history = db.compaction_history();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].output.as_ref().unwrap().file_name, output.file_name);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_compaction_preserves_hot_tier() -> anyhow::Result<()> {
+        const NOW: u64 = 1609459200;
+        let temp_dir = TempDir::new()?;
+        let db = TimeSeriesDB::builder(temp_dir.path())
+            .memtable_threshold(2)
+            .hot_window_secs(3600)
+            .clock(std::sync::Arc::new(MockClock::from_secs(NOW)))
+            .build()?;
+        let dp = |ts: u64| DataPoint { timestamp: ts, value: 1.0, tags: BTreeMap::new() };
+
+        // 两个冷文件、两个热文件，以及一个同时包含新旧数据的文件（按最新数据点算作热数据）
+        for (first, second) in [(7200, 7100), (5000, 4900), (100, 50), (10, 5), (9000, 20)] {
+            db.insert("cpu".to_string(), dp(NOW - first)).await?;
+            db.insert("cpu".to_string(), dp(NOW - second)).await?;
+        }
+        let mut files_before: Vec<String> = std::fs::read_dir(temp_dir.path())?
+            .map(|entry| entry.map(|e| e.file_name().to_string_lossy().into_owned()))
+            .collect::<std::io::Result<_>>()?;
+        files_before.retain(|name| name.ends_with(".data"));
+        assert_eq!(files_before.len(), 5);
+
+        let report = db.compact().await?;
+        assert_eq!(report.inputs.len(), 2);
+        assert_eq!(report.hot_files_skipped, 3);
+        assert_eq!(report.points_merged, 4);
+
+        // 热文件保持原样，两个冷文件合并为一个
+        let mut files_after: Vec<String> = std::fs::read_dir(temp_dir.path())?
+            .map(|entry| entry.map(|e| e.file_name().to_string_lossy().into_owned()))
+            .collect::<std::io::Result<_>>()?;
+        files_after.retain(|name| name.ends_with(".data"));
+        assert_eq!(files_after.len(), 4);
+        for input in &report.inputs {
+            assert!(!files_after.contains(&input.file_name));
+        }
+        assert_eq!(files_before.iter().filter(|name| files_after.contains(name)).count(), 3);
+        assert_eq!(db.query_range("cpu", None, None).await?.len(), 10);
+
+        // 冷数据只剩一个文件，无需再次合并
+        let report = db.compact().await?;
+        assert!(report.skipped_reason.is_some());
+        assert_eq!(report.hot_files_skipped, 3);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_partial_results_within_budget() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let db = TimeSeriesDB::new(temp_dir.path(), 1000)?;
+        for timestamp in 0..4500 {
+            db.insert("big".to_string(), DataPoint { timestamp, value: timestamp as f64, tags: BTreeMap::new() }).await?;
+        }
+        let full = db.query_range("big", None, None).await?;
+
+        // 预算为0时每次只返回一批，按 next_cursor 继续直到读完
+        let mut collected = Vec::new();
+        let mut cursor = None;
+        let mut rounds = 0;
+        loop {
+            let result = db.query_range_within("big", cursor, None, std::time::Duration::ZERO).await?;
+            collected.extend(result.datapoints);
+            rounds += 1;
+            match result.next_cursor {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+        assert!(rounds > 1);
+        assert_eq!(collected.len(), full.len());
+        assert!(collected.iter().zip(&full).all(|(a, b)| a.timestamp == b.timestamp && a.value == b.value));
+
+        // 预算充足时一次返回全部
+        let result = db.query_range_within("big", Some(100), Some(199), std::time::Duration::from_secs(10)).await?;
+        assert_eq!(result.datapoints.len(), 100);
+        assert_eq!(result.next_cursor, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_evict_oldest_over_size_limit() -> anyhow::Result<()> {
+        const MAX_TOTAL_BYTES: u64 = 4096;
+        let temp_dir = TempDir::new()?;
+        let db = TimeSeriesDB::builder(temp_dir.path())
+            .memtable_threshold(100)
+            .max_total_bytes(MAX_TOTAL_BYTES)
+            .build()?;
+
+        for timestamp in 0..2000u64 {
+            let series_key = format!("host_{}", timestamp % 4);
+            let value = (timestamp as f64 * 0.37).sin();
+            db.insert(series_key, DataPoint { timestamp, value, tags: BTreeMap::new() }).await?;
+        }
+
+        let stats = db.get_stats().await?;
+        assert!(stats.total_bytes <= MAX_TOTAL_BYTES, "total_bytes={}", stats.total_bytes);
+        let on_disk: u64 = std::fs::read_dir(temp_dir.path())?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "data"))
+            .map(|entry| entry.metadata().map(|m| m.len()).unwrap_or(0))
+            .sum();
+        assert_eq!(on_disk, stats.total_bytes);
+
+        // 最旧的数据被淘汰，每个系列最新的数据保留
+        for host in 0..4u64 {
+            let points = db.query_range(&format!("host_{}", host), None, None).await?;
+            assert!((25..500).contains(&points.len()));
+            assert_eq!(points.last().map(|dp| dp.timestamp), Some(1996 + host));
+            assert!(points.first().unwrap().timestamp > host);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_update_series_tags_without_rewriting_data() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let tags = |location: &str| BTreeMap::from([("location".to_string(), location.to_string())]);
+
+        // 旧格式文件：标签保存在块内
+        let legacy = SeriesData {
+            series_key: "legacy".to_string(),
+            compressed_data: compression::format::encode_block_v2(&[(1, 1.0), (2, 2.0)]),
+            tags: tags("attic"),
+            min_timestamp: 1,
+            max_timestamp: 2,
+            count: 2,
+            ingest_data: Vec::new(),
+        };
+        SSTable::new(temp_dir.path().join("sstable_legacy.data"))?.write_data(&[legacy])?;
+
+        let db = TimeSeriesDB::builder(temp_dir.path())
+            .memtable_threshold(2)
+            .tag_drift_mode(TagDriftMode::Reject)
+            .build()?;
+        assert_eq!(db.series_tags("legacy"), Some(tags("attic")));
+
+        db.insert("temp".to_string(), DataPoint { timestamp: 1, value: 1.0, tags: tags("room1") }).await?;
+        db.insert("temp".to_string(), DataPoint { timestamp: 2, value: 2.0, tags: tags("room1") }).await?;
+        assert_eq!(db.get_stats().await?.sstable_count, 2);
+
+        let snapshot = |dir: &std::path::Path| -> std::io::Result<BTreeMap<String, Vec<u8>>> {
+            let mut files = BTreeMap::new();
+            for entry in std::fs::read_dir(dir)? {
+                let path = entry?.path();
+                if path.extension().is_some_and(|ext| ext == "data") {
+                    files.insert(path.display().to_string(), std::fs::read(&path)?);
+                }
+            }
+            Ok(files)
+        };
+        let before = snapshot(temp_dir.path())?;
+
+        // 修改标签只重写manifest，数据文件保持不变
+        assert!(db.update_series_tags("temp", tags("room2"))?);
+        assert!(db.update_series_tags("legacy", tags("cellar"))?);
+        assert!(!db.update_series_tags("missing", tags("room2"))?);
+        assert_eq!(snapshot(temp_dir.path())?, before);
+
+        let points = db.query_range("temp", None, None).await?;
+        assert!(points.iter().all(|dp| dp.tags == tags("room2")));
+        let points = db.query_range("legacy", None, None).await?;
+        assert!(points.iter().all(|dp| dp.tags == tags("cellar")));
+        assert_eq!(db.get_series_tags().await?.get("temp"), Some(&tags("room2")));
+
+        // 新标签成为漂移检测的基准
+        assert!(db.insert("temp".to_string(), DataPoint { timestamp: 3, value: 3.0, tags: tags("room1") }).await.is_err());
+        db.insert("temp".to_string(), DataPoint { timestamp: 3, value: 3.0, tags: tags("room2") }).await?;
+        db.close().await?;
+
+        // 重新打开后标签来自manifest；compaction把旧格式块迁移为不带标签的块
+        let db = TimeSeriesDB::builder(temp_dir.path())
+            .memtable_threshold(2)
+            .build()?;
+        assert_eq!(db.series_tags("temp"), Some(tags("room2")));
+        assert_eq!(db.series_tags("legacy"), Some(tags("cellar")));
+        db.compact().await?;
+        let mut files = std::fs::read_dir(temp_dir.path())?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        files.retain(|path| path.extension().is_some_and(|ext| ext == "data"));
+        assert_eq!(files.len(), 1);
+        let blocks = SSTable::new(files.remove(0))?.load_series_list()?;
+        assert!(blocks.iter().all(|block| block.tags.is_empty()));
+        let points = db.query_range("legacy", None, None).await?;
+        assert_eq!(points.len(), 2);
+        assert!(points.iter().all(|dp| dp.tags == tags("cellar")));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_v1_and_v2_sstables() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let block = |series_key: &str, start: u64| {
+            let points: Vec<(u64, f64)> = (start..start + 3).map(|ts| (ts, ts as f64)).collect();
+            SeriesData {
+                series_key: series_key.to_string(),
+                compressed_data: compression::format::encode_block_v2(&points),
+                tags: BTreeMap::new(),
+                min_timestamp: start,
+                max_timestamp: start + 2,
+                count: 3,
+                ingest_data: Vec::new(),
+            }
+        };
+        // v3之前的块没有版本字节
+        let legacy_block = |series_key: &str, start: u64| {
+            let mut series = block(series_key, start);
+            series.compressed_data.remove(0);
+            series
+        };
+        let headed = |version: u16, blocks: Vec<Vec<u8>>| -> anyhow::Result<Vec<u8>> {
+            let mut data = SSTABLE_MAGIC.to_vec();
+            data.extend_from_slice(&version.to_le_bytes());
+            data.extend_from_slice(&bincode::serialize(&blocks)?);
+            Ok(data)
+        };
+
+        // v4之前的块没有写入时间流，字段顺序与 SeriesData 相同
+        let legacy_fields = |series: SeriesData| {
+            (series.series_key, series.compressed_data, series.tags, series.min_timestamp, series.max_timestamp, series.count)
+        };
+        // v1：无文件头的 bincode(Vec<SeriesData>)
+        let v1_path = temp_dir.path().join("sstable_v1.data");
+        std::fs::write(&v1_path, bincode::serialize(&vec![legacy_fields(legacy_block("cpu", 0))])?)?;
+        // v2：有文件头，块不带版本字节
+        let v2_path = temp_dir.path().join("sstable_v2.data");
+        std::fs::write(&v2_path, headed(2, vec![bincode::serialize(&legacy_fields(legacy_block("net", 0)))?])?)?;
+        // v3：块带版本字节，没有写入时间流
+        let v3_only_path = temp_dir.path().join("sstable_v3_only.data");
+        std::fs::write(&v3_only_path, headed(3, vec![bincode::serialize(&legacy_fields(block("swap", 0)))?])?)?;
+        // v4：当前写入格式
+        let v3_path = temp_dir.path().join("sstable_v3.data");
+        SSTable::new(v3_path.clone())?.write_data(&[block("cpu", 10), block("mem", 0)])?;
+        // 更高版本在块末尾追加了字段，当前读取器忽略多余字节
+        let mut extended = bincode::serialize(&block("disk", 0))?;
+        extended.extend_from_slice(&[7u8; 16]);
+        let v4_path = temp_dir.path().join("sstable_v4.data");
+        std::fs::write(&v4_path, headed(SSTABLE_FORMAT_VERSION + 1, vec![extended])?)?;
+
+        assert_eq!(format_version(&std::fs::read(&v1_path)?), 1);
+        assert_eq!(format_version(&std::fs::read(&v2_path)?), 2);
+        assert_eq!(format_version(&std::fs::read(&v3_path)?), SSTABLE_FORMAT_VERSION);
+        // 读取旧文件时补上版本字节：v3 之前写入的块都是最初编码的 v1 块
+        for (path, series_key) in [(&v1_path, "cpu"), (&v2_path, "net")] {
+            let blocks = SSTable::new(path.clone())?.load_series_list()?;
+            assert_eq!(blocks[0].compressed_data[0], compression::format::BLOCK_FORMAT_V1);
+            assert_eq!(blocks[0].compressed_data[1..], block(series_key, 0).compressed_data[1..]);
+        }
+        assert_eq!(SSTable::new(v3_only_path)?.load_series_list()?, vec![block("swap", 0)]);
+        assert_eq!(SSTable::new(v3_path)?.load_series_list()?.len(), 2);
+        assert_eq!(SSTable::new(v4_path)?.load_series_list()?[0].series_key, "disk");
+
+        let db = TimeSeriesDB::new(temp_dir.path(), 100)?;
+        let cpu = db.query_range("cpu", None, None).await?;
+        assert_eq!(cpu.iter().map(|dp| dp.timestamp).collect::<Vec<_>>(), vec![0, 1, 2, 10, 11, 12]);
+        assert_eq!(db.query_range("net", None, None).await?.len(), 3);
+        assert_eq!(db.query_range("disk", None, None).await?.len(), 3);
+
+        // 修改旧格式文件时按当前格式重写
+        assert!(db.update("cpu", 1, 9.0).await?);
+        assert_eq!(format_version(&std::fs::read(&v1_path)?), SSTABLE_FORMAT_VERSION);
+        assert_eq!(db.query_range("cpu", Some(1), Some(1)).await?[0].value, 9.0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_find_gaps() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let db = TimeSeriesDB::new(temp_dir.path(), 5)?;
+
+        // 每10秒一个点，缺少 40/50/60 和 150
+        for timestamp in (0..=200).step_by(10).filter(|ts| ![40, 50, 60, 150].contains(ts)) {
+            db.insert("heartbeat".to_string(), DataPoint { timestamp, value: 1.0, tags: BTreeMap::new() }).await?;
+        }
+
+        let gaps = db.find_gaps("heartbeat", 10, None, None).await?;
+        assert_eq!(
+            gaps,
+            vec![Gap { gap_start: 40, gap_end: 70 }, Gap { gap_start: 150, gap_end: 160 }]
+        );
+
+        // 只在查询范围内的点之间检测
+        assert_eq!(db.find_gaps("heartbeat", 10, Some(100), None).await?.len(), 1);
+        // 预期间隔更大时，缺一个点不算缺口
+        assert_eq!(db.find_gaps("heartbeat", 20, None, None).await?, vec![Gap { gap_start: 50, gap_end: 70 }]);
+        assert!(db.find_gaps("heartbeat", 0, None, None).await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_batch_body_size_limit() -> anyhow::Result<()> {
+        use axum::http::StatusCode;
+
+        let server = testing::spawn_test_server_with(|builder| builder.max_request_body_bytes(1024)).await?;
+        let db = &server.db_handle;
+        let json = [("Content-Type", "application/json")];
+
+        // 元素跨越块边界，字符串中的 ']' 和 ',' 不影响切分
+        let batch = r#"[{"series_key":"batch","timestamp":1,"value":1.0,"tags":{"note":"a],b"}},
+            {"series_key":"batch","timestamp":2,"value":2.0}, {"bad":true}]"#;
+        let response = server.request_chunked("POST", "/api/v1/datapoints/batch", &json, batch.as_bytes(), 7).await?;
+        assert_eq!(response.status, StatusCode::OK);
+        let points = db.query_range("batch", None, None).await?;
+        assert_eq!(points.len(), 2);
+        assert_eq!(points[0].tags.get("note").map(String::as_str), Some("a],b"));
+
+        // 分块传输的超大请求体在读取时被拒绝，超限前已解析的元素保留
+        let element = r#"{"series_key":"batch","timestamp":3,"value":3.0},"#;
+        let oversized = format!("[{}]", element.repeat(100));
+        let response = server.request_chunked("POST", "/api/v1/datapoints/batch", &json, oversized.as_bytes(), 256).await?;
+        assert_eq!(response.status, StatusCode::PAYLOAD_TOO_LARGE);
+        let body = response.json()?;
+        assert_eq!(body["success"], false);
+        assert_eq!(body["data"]["max_body_bytes"], 1024);
+        assert_eq!(body["data"]["stream_endpoint"], "/api/v1/datapoints/stream");
+        assert!(body["message"].as_str().unwrap().contains("/api/v1/datapoints/stream"));
+        assert_eq!(db.query_range("batch", None, None).await?.len(), 3);
+
+        // 声明了 Content-Length 的请求在读取请求体之前被中间件拒绝
+        let response = server
+            .request(
+                "POST",
+                "/api/v1/datapoints/batch",
+                &[("Content-Type", "application/json"), ("Content-Length", &(300 * 1024 * 1024).to_string())],
+                &[],
+            )
+            .await?;
+        assert_eq!(response.status, StatusCode::PAYLOAD_TOO_LARGE);
+        let body = response.json()?;
+        assert_eq!(body["success"], false);
+        assert_eq!(body["data"]["max_body_bytes"], 1024);
+        assert_eq!(db.query_range("batch", None, None).await?.len(), 3);
+
+        // 分块传输的请求体由提取器读取时超限，同样返回统一的响应结构
+        let point = format!(r#"{{"series_key":"single","timestamp":1,"value":1.0,"tags":{{"pad":"{}"}}}}"#, "x".repeat(2048));
+        let response = server.request_chunked("POST", "/api/v1/datapoints", &json, point.as_bytes(), 4096).await?;
+        assert_eq!(response.status, StatusCode::PAYLOAD_TOO_LARGE);
+        let body = response.json()?;
+        assert_eq!(body["success"], false);
+        assert_eq!(body["data"]["max_body_bytes"], 1024);
+        assert!(db.query_range("single", None, None).await?.is_empty());
+
+        server.stop().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_sort_by_value() -> anyhow::Result<()> {
+        let server = testing::spawn_test_server_with(|b| b.memtable_threshold(4)).await?;
+        let values = [3.0, 9.0, 1.0, 7.0, 9.0, 2.0, 8.0, 5.0];
+        for (i, value) in values.iter().enumerate() {
+            server.db_handle.insert("readings".to_string(), DataPoint {
+                timestamp: 100 + i as u64,
+                value: *value,
+                tags: BTreeMap::new(),
+            }).await?;
+        }
+
+        let query = |params: &str| {
+            let (server, path) = (&server, format!("/api/v1/series/readings/datapoints?{}", params));
+            async move { server.get(&path).await?.json() }
+        };
+        let points = |body: &serde_json::Value| -> Vec<(u64, f64)> {
+            body["data"].as_array().unwrap().iter().map(|dp| (dp["timestamp"].as_u64().unwrap(), dp["value"].as_f64().unwrap())).collect()
+        };
+
+        // 值相同的点按时间顺序排列
+        let body = query("sort=value_desc&limit=3").await?;
+        assert_eq!(points(&body), vec![(101, 9.0), (104, 9.0), (106, 8.0)]);
+
+        // 默认仍按时间戳排序，limit 取最早的点
+        let body = query("limit=3").await?;
+        assert_eq!(points(&body).iter().map(|(timestamp, _)| *timestamp).collect::<Vec<_>>(), vec![100, 101, 102]);
+
+        // 按值排序需要完整结果，忽略 max_wait_ms
+        let body = query("sort=value_desc&max_wait_ms=0").await?;
+        assert!(body.get("partial").is_none());
+        assert_eq!(points(&body).len(), values.len());
+
+        server.stop().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_engine_observer_events() -> anyhow::Result<()> {
+        use std::sync::Mutex;
+
+        #[derive(Debug, Default)]
+        struct Recorder {
+            events: Mutex<Vec<String>>,
+        }
+
+        impl EngineObserver for Recorder {
+            fn on_flush_completed(&self, event: &FlushEvent) {
+                self.events.lock().unwrap().push(format!("flush:{}", event.points));
+            }
+
+            fn on_compaction_started(&self, _options: &CompactionOptions) {
+                self.events.lock().unwrap().push("compaction_started".to_string());
+            }
+
+            fn on_compaction_finished(&self, report: &CompactionReport) {
+                self.events.lock().unwrap().push(format!("compaction_finished:{}", report.inputs.len()));
+            }
+
+            fn on_corruption_detected(&self, _event: &CorruptionEvent) {
+                self.events.lock().unwrap().push("corruption".to_string());
+            }
+
+            fn on_memtable_threshold_crossed(&self, points: usize, threshold: usize) {
+                self.events.lock().unwrap().push(format!("threshold:{}/{}", points, threshold));
+            }
+        }
+
+        #[derive(Debug)]
+        struct Panicking;
+
+        impl EngineObserver for Panicking {
+            fn on_flush_completed(&self, _event: &FlushEvent) {
+                panic!("观察者故障");
+            }
+        }
+
+        let temp_dir = TempDir::new()?;
+        std::fs::write(temp_dir.path().join("sstable_corrupt.data"), b"TSST\x02\x00garbage")?;
+
+        let recorder = std::sync::Arc::new(Recorder::default());
+        let db = TimeSeriesDB::builder(temp_dir.path())
+            .memtable_threshold(3)
+            .with_observer(std::sync::Arc::new(Panicking))
+            .with_observer(recorder.clone())
+            .build()?;
+        assert_eq!(recorder.events.lock().unwrap().as_slice(), ["corruption"]);
+
+        // panic的观察者不影响刷新，也不影响后注册的观察者
+        for ts in 0..6 {
+            db.insert("observed".to_string(), DataPoint {
+                timestamp: 100 + ts,
+                value: ts as f64,
+                tags: BTreeMap::new(),
+            }).await?;
+        }
+        assert_eq!(db.query_range("observed", None, None).await?.len(), 6);
+        db.compact().await?;
+
+        assert_eq!(
+            recorder.events.lock().unwrap().as_slice(),
+            [
+                "corruption",
+                "threshold:3/3",
+                "flush:3",
+                "threshold:3/3",
+                "flush:3",
+                "compaction_started",
+                // 损坏的文件每次被读取都会通知
+                "corruption",
+                "compaction_finished:2",
+            ]
+        );
+
+        // 内置的计数观察者与外部观察者收到同样的事件
+        let events = db.get_stats().await?.events;
+        assert_eq!(events.flushes, 2);
+        assert_eq!(events.flushed_points, 6);
+        assert_eq!(events.compactions_started, 1);
+        assert_eq!(events.compactions_finished, 1);
+        assert_eq!(events.compactions_skipped, 0);
+        assert_eq!(events.corruptions_detected, 2);
+        assert_eq!(events.memtable_threshold_crossings, 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_min_flush_interval_coalesces_flushes() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let clock = std::sync::Arc::new(MockClock::from_secs(1_000_000));
+        let db = TimeSeriesDB::builder(temp_dir.path())
+            .memtable_threshold(2)
+            .min_flush_interval(std::time::Duration::from_secs(60))
+            .memtable_hard_limit(10)
+            .clock(clock.clone())
+            .build()?;
+
+        let insert = |ts: u64| {
+            let db = db.clone();
+            async move {
+                db.insert("burst".to_string(), DataPoint {
+                    timestamp: ts,
+                    value: ts as f64,
+                    tags: BTreeMap::new(),
+                }).await
+            }
+        };
+
+        // 第一次达到阈值立即刷新，之后在间隔内只有达到硬上限才刷新
+        for ts in 0..22 {
+            insert(ts).await?;
+        }
+        let stats = db.get_stats().await?;
+        assert_eq!(stats.events.flushes, 3);
+        assert_eq!(stats.sstable_count, 3);
+        assert_eq!(db.query_range("burst", None, None).await?.len(), 22);
+
+        // 间隔过后恢复按阈值刷新
+        insert(100).await?;
+        assert_eq!(db.get_stats().await?.events.flushes, 3);
+        clock.advance(std::time::Duration::from_secs(61));
+        insert(101).await?;
+        assert_eq!(db.get_stats().await?.events.flushes, 4);
+        assert_eq!(db.query_range("burst", None, None).await?.len(), 24);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reject_inverted_time_range() -> anyhow::Result<()> {
+        use axum::http::StatusCode;
+
+        let server = testing::spawn_test_server_with(|b| b.memtable_threshold(1000)).await?;
+        let db = &server.db_handle;
+        for ts in [50, 75, 100] {
+            db.insert("range".to_string(), DataPoint {
+                timestamp: ts,
+                value: ts as f64,
+                tags: BTreeMap::new(),
+            }).await?;
+        }
+
+        let err = db.query_range("range", Some(100), Some(50)).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert_eq!(
+            DbError::from_io(&err),
+            Some(&DbError::InvalidTimeRange { start_time: 100, end_time: 50 })
+        );
+        assert!(db.delete_range("range", Some(100), Some(50), false).await.is_err());
+        assert!(db.find_gaps("range", 10, Some(100), Some(50)).await.is_err());
+        // 上下界相等是合法的单点查询
+        assert_eq!(db.query_range("range", Some(75), Some(75)).await?.len(), 1);
+        assert_eq!(db.query_range("range", None, None).await?.len(), 3);
+
+        let response = server.get("/api/v1/series/range/datapoints?start_time=100&end_time=50").await?;
+        assert_eq!(response.status, StatusCode::BAD_REQUEST);
+        let body = response.json()?;
+        assert_eq!(body["success"], false);
+        let message = body["message"].as_str().unwrap_or_default();
+        assert!(message.contains("100") && message.contains("50"));
+
+        let response = server.get("/api/v1/series/range/datapoints?limit=0").await?;
+        assert_eq!(response.status, StatusCode::BAD_REQUEST);
+
+        server.stop().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_batch_insert_result_per_series() -> anyhow::Result<()> {
+        use api::models::{ApiResponse, BatchInsertResult};
+        use axum::http::StatusCode;
+
+        let server = testing::spawn_test_server_with(|b| b.memtable_threshold(1000)).await?;
+
+        let batch = r#"[
+            {"series_key":"cpu","timestamp":1,"value":1.0},
+            {"series_key":"mem","timestamp":1,"value":2.0},
+            {"series_key":"cpu","timestamp":2},
+            {"series_key":"cpu","timestamp":3,"value":3.0},
+            "not an object",
+            {"series_key":"disk","timestamp":1,"value":9007199254740993},
+            {"series_key":"mem","timestamp":2,"value":4.0}
+        ]"#;
+        let response = server
+            .request("POST", "/api/v1/datapoints/batch", &[("Content-Type", "application/json")], batch.as_bytes())
+            .await?;
+        assert_eq!(response.status, StatusCode::OK);
+        let body: ApiResponse<BatchInsertResult> = serde_json::from_slice(&response.body)?;
+        assert!(body.success);
+        let result = body.data.unwrap();
+
+        assert_eq!((result.total, result.succeeded, result.failed), (7, 4, 3));
+        let per_series: Vec<(&str, usize)> = result
+            .per_series
+            .iter()
+            .map(|s| (s.series_key.as_str(), s.count))
+            .collect();
+        assert_eq!(per_series, vec![("cpu", 2), ("mem", 2)]);
+        let error_indices: Vec<usize> = result.errors.iter().map(|e| e.index).collect();
+        assert_eq!(error_indices, vec![2, 4, 5]);
+        assert!(result.errors.iter().all(|e| !e.message.is_empty()));
+
+        assert_eq!(server.db_handle.query_range("cpu", None, None).await?.len(), 2);
+        assert!(server.db_handle.query_range("disk", None, None).await?.is_empty());
+
+        server.stop().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_warmup_hot_series_at_startup() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        {
+            let db = TimeSeriesDB::new(temp_dir.path(), 2)?;
+            // 每个系列单独刷新到一个SSTable
+            for series in ["cpu.host1", "cpu.host2", "mem.host1"] {
+                for ts in 0..2 {
+                    db.insert(series.to_string(), DataPoint {
+                        timestamp: 100 + ts,
+                        value: ts as f64,
+                        tags: BTreeMap::new(),
+                    }).await?;
+                }
+            }
+            assert_eq!(db.get_stats().await?.sstable_count, 3);
+            assert_eq!(db.warmup_status().state, WarmupState::Idle);
+            db.set_warmup_series(vec!["cpu.*".to_string(), "disk.host9".to_string()])?;
+            db.close().await?;
+        }
+
+        // 预热列表保存在数据目录中，重启后只预热匹配系列所在的文件
+        let db = TimeSeriesDB::new(temp_dir.path(), 2)?;
+        assert_eq!(db.warmup_series(), vec!["cpu.*".to_string(), "disk.host9".to_string()]);
+        let status = db.warm_up().await?;
+        assert_eq!(status.state, WarmupState::Done);
+        assert_eq!(status.series_matched, 2);
+        assert_eq!((status.files_total, status.files_loaded), (2, 2));
+        assert!(status.bytes_loaded > 0);
+        assert_eq!(db.query_range("cpu.host1", None, None).await?.len(), 2);
+        db.close().await?;
+
+        // 配置中指定的列表替换保存的列表
+        let db = TimeSeriesDB::builder(temp_dir.path())
+            .warmup_series(vec!["mem.host1".to_string()])
+            .build()?;
+        assert_eq!(db.warmup_series(), vec!["mem.host1".to_string()]);
+        let status = db.warm_up().await?;
+        assert_eq!((status.series_matched, status.files_loaded), (1, 1));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_aggregate_measurement_per_series() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        // 只有一个执行线程时逐个系列计算，结果不变
+        let db = TimeSeriesDB::builder(temp_dir.path()).memtable_threshold(5).blocking_threads(1).build()?;
+
+        // 两个按前缀归属、一个按标签归属的系列，另有两个无关系列（cpu2_host 只是名字以 cpu 开头）
+        let mut tagged = BTreeMap::new();
+        tagged.insert(MEASUREMENT_TAG.to_string(), "cpu".to_string());
+        let series = [
+            ("cpu_host1", BTreeMap::new(), 0.0),
+            ("cpu_host2", BTreeMap::new(), 100.0),
+            ("edge_node", tagged, 1000.0),
+            ("mem_host1", BTreeMap::new(), 5e6),
+            ("cpu2_host", BTreeMap::new(), 7e6),
+        ];
+        for (key, tags, base) in &series {
+            for i in 0..6u64 {
+                let dp = DataPoint { timestamp: 1609459200 + i * 30, value: base + i as f64, tags: tags.clone() };
+                db.insert(key.to_string(), dp).await?;
+            }
+        }
+
+        let results = db
+            .aggregate_measurement("cpu", None, None, 60, "avg".parse().unwrap(), FillPolicy::None)
+            .await?;
+        assert_eq!(results.keys().collect::<Vec<_>>(), vec!["cpu_host1", "cpu_host2", "edge_node"]);
+        for (key, _, base) in &series[..3] {
+            let buckets = &results[*key];
+            assert_eq!(buckets.len(), 3);
+            for (i, bucket) in buckets.iter().enumerate() {
+                assert_eq!(bucket.timestamp, 1609459200 + i as u64 * 60);
+                assert_eq!(bucket.count, 2);
+                assert_eq!(bucket.value, Some(base + (i * 2) as f64 + 0.5));
+            }
+        }
+
+        let max = db
+            .aggregate_measurement("cpu_host2", None, None, 120, "max".parse().unwrap(), FillPolicy::None)
+            .await?;
+        assert_eq!(max.len(), 1);
+        let values: Vec<_> = max["cpu_host2"].iter().map(|b| b.value).collect();
+        assert_eq!(values, vec![Some(103.0), Some(105.0)]);
+
+        assert!(db.aggregate_measurement("disk", None, None, 60, AggregateFn::Avg, FillPolicy::None).await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_blocking_pool_offloads_large_reads() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let db = TimeSeriesDB::builder(temp_dir.path())
+            .memtable_threshold(5000)
+            .blocking_threads(2)
+            .inline_query_points(100)
+            .query_coalescing(false)
+            .build()?;
+
+        for ts in 0..20u64 {
+            db.insert("small".to_string(), DataPoint { timestamp: ts, value: ts as f64, tags: BTreeMap::new() }).await?;
+        }
+        for ts in 0..4980u64 {
+            db.insert("large".to_string(), DataPoint { timestamp: ts, value: ts as f64, tags: BTreeMap::new() }).await?;
+        }
+        // 达到阈值的刷新有5000个点，在线程池中压缩
+        let before = db.get_stats().await?;
+        assert_eq!(before.sstable_count, 1);
+        assert_eq!(before.blocking.threads, 2);
+        assert_eq!(before.blocking.offloaded, 1);
+
+        // 大查询和小查询混合并发执行
+        let mut large = tokio::task::JoinSet::new();
+        for _ in 0..4 {
+            let db = db.clone();
+            large.spawn(async move { db.downsample("large", None, None, 100, AggregateFn::Count, FillPolicy::None).await });
+        }
+        let mut small = tokio::task::JoinSet::new();
+        for _ in 0..16 {
+            let db = db.clone();
+            small.spawn(async move { db.query_range("small", None, None).await });
+        }
+        while let Some(result) = small.join_next().await {
+            assert_eq!(result??.len(), 20);
+        }
+        while let Some(result) = large.join_next().await {
+            let buckets = result??;
+            assert_eq!(buckets.len(), 50);
+            assert_eq!(buckets[49].value, Some(80.0));
+        }
+
+        // 小查询内联执行，大查询转交线程池
+        let after = db.get_stats().await?;
+        assert_eq!(after.blocking.offloaded - before.blocking.offloaded, 4);
+        assert_eq!(after.blocking.inlined - before.blocking.inlined, 16);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_verify_series_bounds_repairs_pruned_blocks() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+
+        // 实际数据为 0..100，记录的范围错误地写成了 500..600
+        let points: Vec<(u64, f64)> = (0..100u64).map(|ts| (ts, ts as f64)).collect();
+        let broken = SeriesData {
+            series_key: "broken".to_string(),
+            compressed_data: compression::format::encode_block_v2(&points),
+            tags: BTreeMap::new(),
+            min_timestamp: 500,
+            max_timestamp: 600,
+            count: 100,
+            ingest_data: Vec::new(),
+        };
+        let healthy = SeriesData {
+            series_key: "healthy".to_string(),
+            compressed_data: compression::format::encode_block_v2(&[(10, 1.0)]),
+            tags: BTreeMap::new(),
+            min_timestamp: 10,
+            max_timestamp: 10,
+            count: 1,
+            ingest_data: Vec::new(),
+        };
+        SSTable::new(temp_dir.path().join("sstable_broken.data"))?.write_data(&[broken, healthy])?;
+
+        let db = TimeSeriesDB::new(temp_dir.path(), 1000)?;
+        assert!(db.query_range("broken", Some(0), Some(99)).await?.is_empty());
+        assert_eq!(db.last_timestamp("broken"), Some(600));
+
+        let report = db.verify_series_bounds().await?;
+        assert_eq!(report.files_checked, 1);
+        assert_eq!(report.blocks_checked, 2);
+        assert_eq!(report.repaired, vec![BoundsMismatch {
+            file_name: "sstable_broken.data".to_string(),
+            series_key: "broken".to_string(),
+            stored_min_timestamp: 500,
+            stored_max_timestamp: 600,
+            actual_min_timestamp: 0,
+            actual_max_timestamp: 99,
+        }]);
+
+        let results = db.query_range("broken", Some(0), Some(99)).await?;
+        assert_eq!(results.len(), 100);
+        assert_eq!(db.last_timestamp("broken"), Some(99));
+        assert_eq!(db.query_range("healthy", None, None).await?.len(), 1);
+
+        // 修正已写回文件，重启后仍然有效，再次校验没有需要修正的块
+        db.close().await?;
+        let db = TimeSeriesDB::new(temp_dir.path(), 1000)?;
+        assert_eq!(db.query_range("broken", Some(50), Some(60)).await?.len(), 11);
+        assert!(db.verify_series_bounds().await?.repaired.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_deterministic_sstable_order_and_series_listing() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let block = |series_key: &str, points: &[(u64, f64)]| {
+            SeriesData {
+                series_key: series_key.to_string(),
+                compressed_data: compression::format::encode_block_v2(points),
+                tags: BTreeMap::new(),
+                min_timestamp: points[0].0,
+                max_timestamp: points[points.len() - 1].0,
+                count: points.len(),
+                ingest_data: Vec::new(),
+            }
+        };
+        // 文件名顺序与序号顺序相反，时间戳5在两个文件中都有
+        SSTable::new(temp_dir.path().join("sstable_2000.000001.data"))?.write_data(&[block("dup", &[(5, 1.0)])])?;
+        SSTable::new(temp_dir.path().join("sstable_1000.000002.data"))?.write_data(&[block("dup", &[(5, 2.0), (6, 2.0)])])?;
+
+        for _ in 0..3 {
+            let db = TimeSeriesDB::new(temp_dir.path(), 2)?;
+            let values: Vec<f64> = db.query_range("dup", None, None).await?.iter().map(|dp| dp.value).collect();
+            assert_eq!(values, vec![1.0, 2.0]);
+            db.close().await?;
+        }
+
+        let db = TimeSeriesDB::new(temp_dir.path(), 2)?;
+        let insert = |key: &str, ts: u64| {
+            let db = db.clone();
+            let key = key.to_string();
+            async move { db.insert(key, DataPoint { timestamp: ts, value: ts as f64, tags: BTreeMap::new() }).await }
+        };
+        insert("mem", 1).await?;
+        insert("cpu", 1).await?;
+        // 新文件的序号接着已有的最大序号
+        let flushed = std::fs::read_dir(temp_dir.path())?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_name().to_string_lossy().ends_with(".000003.data"))
+            .count();
+        assert_eq!(flushed, 1);
+
+        // 写入新系列与列表调用交替进行，每次都是有序的，已有系列的相对顺序不变
+        let mut previous: Vec<String> = Vec::new();
+        for key in ["zone", "disk", "net", "api", "mem_free"] {
+            let listed = db.get_all_series().await?;
+            assert_eq!(listed, db.get_all_series().await?);
+            assert!(listed.windows(2).all(|pair| pair[0] < pair[1]));
+            let retained: Vec<&String> = listed.iter().filter(|key| previous.contains(key)).collect();
+            assert_eq!(retained, previous.iter().collect::<Vec<_>>());
+            previous = listed;
+            insert(key, 2).await?;
+        }
+        assert_eq!(db.get_all_series().await?, vec!["api", "cpu", "disk", "dup", "mem", "mem_free", "net", "zone"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_block_compression_round_trip() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let series_list: Vec<SeriesData> = (0..20u64)
+            .map(|i| {
+                let points: Vec<(u64, f64)> = (0..500u64).map(|ts| (1609459200 + ts * 10, ((ts + i) % 7) as f64)).collect();
+                SeriesData {
+                    series_key: format!("sensor.building_a.floor_{}.temperature", i),
+                    compressed_data: compression::format::encode_block_v2(&points),
+                    tags: BTreeMap::from([("location".to_string(), "building_a".to_string())]),
+                    min_timestamp: 1609459200,
+                    max_timestamp: 1609459200 + 4990,
+                    count: 500,
+                    ingest_data: Vec::new(),
+                }
+            })
+            .collect();
+
+        let mut sizes = BTreeMap::new();
+        for compression in [BlockCompression::None, BlockCompression::Lz4, BlockCompression::Zstd] {
+            let path = temp_dir.path().join(format!("{}.bin", compression));
+            let mut sstable = SSTable::new(path.clone())?.with_compression(compression);
+            sstable.write_data(&series_list)?;
+            assert_eq!(block_compression(&std::fs::read(&path)?), compression);
+            assert_eq!(format_version(&std::fs::read(&path)?), SSTABLE_FORMAT_VERSION);
+            sizes.insert(compression.to_string(), std::fs::metadata(&path)?.len());
+
+            let loaded = SSTable::new(path)?.load_series_list()?;
+            assert_eq!(bincode::serialize(&loaded)?, bincode::serialize(&series_list)?);
+        }
+        assert!(sizes["zstd"] < sizes["none"], "{:?}", sizes);
+
+        // compaction输出使用zstd，重写后压缩方式不变
+        let db_dir = temp_dir.path().join("db");
+        let db = TimeSeriesDB::builder(&db_dir)
+            .memtable_threshold(10)
+            .compaction_compression(BlockCompression::Zstd)
+            .build()?;
+        for ts in 0..30u64 {
+            db.insert("cold".to_string(), DataPoint { timestamp: ts, value: ts as f64, tags: BTreeMap::new() }).await?;
+        }
+        let before = db.query_range("cold", None, None).await?;
+        let report = db.compact().await?;
+        let output = db_dir.join(&report.output.as_ref().unwrap().file_name);
+        assert_eq!(SSTable::new(output.clone())?.compression(), BlockCompression::Zstd);
+
+        let after = db.query_range("cold", None, None).await?;
+        assert_eq!(
+            after.iter().map(|dp| (dp.timestamp, dp.value)).collect::<Vec<_>>(),
+            before.iter().map(|dp| (dp.timestamp, dp.value)).collect::<Vec<_>>()
+        );
+        assert!(db.delete("cold", Some(5), false).await?);
+        assert_eq!(SSTable::new(output)?.compression(), BlockCompression::Zstd);
+        assert_eq!(db.query_range("cold", None, None).await?.len(), 29);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_replace_series() -> anyhow::Result<()> {
+        let server = testing::spawn_test_server_with(|builder| builder.memtable_threshold(4)).await?;
+        let db = &server.db_handle;
+        let old_tags = BTreeMap::from([("host".to_string(), "a".to_string())]);
+        // 前4个点刷新到SSTable，后2个留在内存表
+        for ts in 0..6u64 {
+            db.insert("cpu".to_string(), DataPoint { timestamp: 100 + ts, value: ts as f64, tags: old_tags.clone() }).await?;
+        }
+        db.insert("mem".to_string(), DataPoint { timestamp: 100, value: 1.0, tags: BTreeMap::new() }).await?;
+        assert!(!db.sstable_infos().is_empty());
+
+        let new_tags = BTreeMap::from([("host".to_string(), "b".to_string())]);
+        let points = [(300u64, 3.0), (200, 2.0), (300, 9.0)]
+            .into_iter()
+            .map(|(timestamp, value)| DataPoint { timestamp, value, tags: new_tags.clone() })
+            .collect();
+        db.replace_series("cpu", points).await?;
+        let values: Vec<(u64, f64)> = db.query_range("cpu", None, None).await?.iter().map(|dp| (dp.timestamp, dp.value)).collect();
+        assert_eq!(values, vec![(200, 2.0), (300, 3.0)]);
+        assert_eq!(db.series_tags("cpu"), Some(new_tags.clone()));
+        assert_eq!(db.query_range("mem", None, None).await?.len(), 1);
+        // 暂存文件都已改名，提交记录已清除
+        let staged = std::fs::read_dir(server.data_dir())?
+            .filter(|entry| entry.as_ref().is_ok_and(|entry| entry.file_name().to_string_lossy().ends_with(STAGED_SUFFIX)))
+            .count();
+        assert_eq!(staged, 0);
+        assert_eq!(Manifest::load(server.data_dir())?.pending_replace, None);
+
+        // 标签不一致时拒绝，数据保持不变
+        let mixed = vec![
+            DataPoint { timestamp: 1, value: 1.0, tags: new_tags.clone() },
+            DataPoint { timestamp: 2, value: 2.0, tags: BTreeMap::new() },
+        ];
+        assert!(db.replace_series("cpu", mixed).await.is_err());
+        assert_eq!(db.query_range("cpu", None, None).await?.len(), 2);
+
+        let request = serde_json::json!({"tags": {"host": "c"}, "datapoints": [{"timestamp": 500, "value": 5.0}]});
+        let response = server.put_json("/api/v1/series/cpu/datapoints", &request).await?;
+        assert_eq!(response.status, axum::http::StatusCode::OK);
+        assert_eq!(response.json()?["data"]["points"], 1);
+        let body = server.get("/api/v1/series/cpu/datapoints").await?.json()?;
+        assert_eq!(body["data"].as_array().unwrap().len(), 1);
+        assert_eq!(body["data"][0]["timestamp"], 500);
+
+        // 空数据等同删除系列
+        let response = server.put_json("/api/v1/series/cpu/datapoints", &serde_json::json!({"datapoints": []})).await?;
+        assert_eq!(response.status, axum::http::StatusCode::OK);
+        assert!(db.query_range("cpu", None, None).await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_replace_series_recovers_after_interruption() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let dp = |timestamp: u64| DataPoint { timestamp, value: timestamp as f64, tags: BTreeMap::new() };
+        let data_files = || -> anyhow::Result<Vec<String>> {
+            let mut names: Vec<String> =
+                std::fs::read_dir(temp_dir.path())?.map(|entry| Ok(entry?.file_name().to_string_lossy().into_owned())).collect::<anyhow::Result<_>>()?;
+            names.retain(|name| name.contains(".data"));
+            names.sort();
+            Ok(names)
+        };
+        {
+            let db = TimeSeriesDB::new(temp_dir.path(), 4)?;
+            for ts in 0..4 {
+                db.insert("cpu".to_string(), dp(ts)).await?;
+            }
+            for ts in 0..4 {
+                db.insert("mem".to_string(), dp(ts)).await?;
+            }
+        }
+        let files = data_files()?;
+        assert_eq!(files.len(), 2);
+        let (cpu_file, mem_file) = (files[0].clone(), files[1].clone());
+
+        // 另一个库中写出替换后的数据，作为中断时留下的暂存文件
+        let scratch = TempDir::new()?;
+        {
+            let db = TimeSeriesDB::new(scratch.path(), 1)?;
+            db.insert("cpu".to_string(), dp(500)).await?;
+        }
+        let scratch_file = std::fs::read_dir(scratch.path())?
+            .flatten()
+            .map(|entry| entry.path())
+            .find(|path| path.extension().is_some_and(|ext| ext == "data"))
+            .unwrap();
+        let stage = |file_name: &str| std::fs::copy(&scratch_file, staged_path(&temp_dir.path().join(file_name)));
+        let record = |pending: PendingReplace| -> anyhow::Result<()> {
+            let mut manifest = Manifest::load(temp_dir.path())?;
+            manifest.pending_replace = Some(pending);
+            Ok(manifest.save(temp_dir.path())?)
+        };
+
+        // 新系列的文件不存在：替换在生效前被放弃，旧文件保留
+        stage("sstable_0.000800.data")?;
+        record(PendingReplace { series_key: "cpu".to_string(), staged: vec!["sstable_0.000900.data".to_string()], removed: vec![cpu_file.clone()] })?;
+        {
+            let db = TimeSeriesDB::new(temp_dir.path(), 4)?;
+            assert_eq!(db.query_range("cpu", None, None).await?.len(), 4);
+            assert_eq!(Manifest::load(temp_dir.path())?.pending_replace, None);
+        }
+        // 没有记录的暂存文件被清理
+        assert_eq!(data_files()?, vec![cpu_file.clone(), mem_file.clone()]);
+
+        // 已提交的替换在启动时完成：暂存文件改名生效，旧文件删除
+        stage("sstable_0.000900.data")?;
+        record(PendingReplace { series_key: "cpu".to_string(), staged: vec!["sstable_0.000900.data".to_string()], removed: vec![cpu_file.clone()] })?;
+        let db = TimeSeriesDB::new(temp_dir.path(), 4)?;
+        let values: Vec<u64> = db.query_range("cpu", None, None).await?.iter().map(|dp| dp.timestamp).collect();
+        assert_eq!(values, vec![500]);
+        assert_eq!(db.query_range("mem", None, None).await?.len(), 4);
+        assert_eq!(data_files()?, vec!["sstable_0.000900.data".to_string(), mem_file]);
+        assert_eq!(Manifest::load(temp_dir.path())?.pending_replace, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_engine_serves_after_panic_holding_lock() -> anyhow::Result<()> {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        // 损坏事件在持有SSTable列表锁时通知，观察者在其中panic
+        #[derive(Debug, Default)]
+        struct PanicOnCorruption {
+            calls: AtomicUsize,
+        }
+        impl EngineObserver for PanicOnCorruption {
+            fn on_corruption_detected(&self, _event: &CorruptionEvent) {
+                self.calls.fetch_add(1, Ordering::SeqCst);
+                panic!("观察者故障");
+            }
+        }
+
+        let temp_dir = TempDir::new()?;
+        let observer = std::sync::Arc::new(PanicOnCorruption::default());
+        let db = TimeSeriesDB::builder(temp_dir.path()).memtable_threshold(4).with_observer(observer.clone()).build()?;
+        let dp = |ts: u64| DataPoint { timestamp: ts, value: ts as f64, tags: BTreeMap::new() };
+        for ts in 0..4 {
+            db.insert("cpu".to_string(), dp(ts)).await?;
+        }
+        let infos = db.sstable_infos();
+        assert_eq!(infos.len(), 1);
+        std::fs::write(temp_dir.path().join(&infos[0].file_name), b"not an sstable")?;
+
+        for n in 0..4u64 {
+            let before = observer.calls.load(Ordering::SeqCst);
+            assert_eq!(db.series_point_count("cpu"), 0);
+            assert_eq!(observer.calls.load(Ordering::SeqCst), before + 1);
+
+            // 锁不会因为panic而中毒，之后的写入和查询照常进行
+            db.insert("mem".to_string(), dp(n)).await?;
+            assert_eq!(db.query_range("mem", None, None).await?.len(), n as usize + 1);
+            assert!(db.get_stats().await.is_ok());
+        }
+
+        // 时钟早于Unix纪元时，需要写入时间的写入返回类型化的错误
+        let temp_dir = TempDir::new()?;
+        let clock = std::sync::Arc::new(MockClock::from_secs(0));
+        clock.set(std::time::UNIX_EPOCH - std::time::Duration::from_secs(5));
+        let db = TimeSeriesDB::builder(temp_dir.path()).record_ingest_time(true).clock(clock.clone()).build()?;
+        let rule = QuotaRule { prefix: String::new(), max_points: Some(10), max_bytes: None, action: QuotaAction::Reject };
+        db.set_quota_policy(QuotaPolicy { rules: vec![rule] }).await?;
+        let error = db.insert("cpu".to_string(), dp(1)).await.unwrap_err();
+        assert!(matches!(DbError::from_io(&error), Some(DbError::ClockBeforeEpoch { behind_secs: 5 })));
+        // 失败的写入不占用配额
+        assert_eq!(db.quota_report().quotas[0].usage.points, 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_windows() -> anyhow::Result<()> {
+        let server = testing::spawn_test_server_with(|builder| builder.memtable_threshold(50)).await?;
+        let db = &server.db_handle;
+        // 三天的数据，前两天刷新到SSTable，最后一天部分留在内存表
+        for ts in (0..3 * 86400u64).step_by(1800) {
+            db.insert("cpu".to_string(), DataPoint { timestamp: ts, value: (ts % 7200) as f64, tags: BTreeMap::new() }).await?;
+        }
+        assert!(!db.sstable_infos().is_empty());
+
+        let windows: Vec<TimeWindow> = (0..3u64)
+            .map(|day| TimeWindow { start_time: day * 86400 + 9 * 3600, end_time: day * 86400 + 11 * 3600 })
+            .collect();
+        let results = db.query_windows("cpu", windows.clone(), None).await?;
+        assert_eq!(results.len(), 3);
+        for (window, result) in windows.iter().zip(&results) {
+            let expected: Vec<(u64, f64)> = db
+                .query_range("cpu", Some(window.start_time), Some(window.end_time))
+                .await?
+                .iter()
+                .map(|dp| (dp.timestamp, dp.value))
+                .collect();
+            let actual: Vec<(u64, f64)> = result.points.as_ref().unwrap().iter().map(|p| (p.timestamp, p.value)).collect();
+            assert_eq!(actual, expected);
+            assert_eq!(result.count, 5);
+        }
+
+        let averages = db.query_windows("cpu", windows.clone(), Some(AggregateFn::Avg)).await?;
+        for (window, result) in windows.iter().zip(&averages) {
+            let points = db.query_range("cpu", Some(window.start_time), Some(window.end_time)).await?;
+            let values: Vec<f64> = points.iter().map(|dp| dp.value).collect();
+            assert_eq!(result.value, AggregateFn::Avg.apply(&values));
+            assert!(result.points.is_none());
+        }
+
+        // 颠倒的窗口和空的窗口列表返回400
+        let request = serde_json::json!({"windows": [[0, 3600], [7200, 3600]]});
+        assert_eq!(server.post_json("/api/v1/series/cpu/windows", &request).await?.status, 400);
+        assert_eq!(server.post_json("/api/v1/series/cpu/windows", &serde_json::json!({"windows": []})).await?.status, 400);
+
+        let request = serde_json::json!({"windows": [[86400, 90000], [0, 3600]], "fn": "count"});
+        let response = server.post_json("/api/v1/series/cpu/windows", &request).await?;
+        assert_eq!(response.status, axum::http::StatusCode::OK);
+        let body = response.json()?;
+        assert_eq!(body["data"]["fn"], "count");
+        assert_eq!(body["data"]["windows"][0]["start_time"], 86400);
+        assert_eq!(body["data"]["windows"][0]["value"], 3.0);
+        assert_eq!(body["data"]["windows"][1]["value"], 3.0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_response_field_selection() -> anyhow::Result<()> {
+        let server = testing::spawn_test_server_with(|builder| builder.memtable_threshold(100)).await?;
+        let tags = BTreeMap::from([("host".to_string(), "a".to_string())]);
+        for ts in 0..3u64 {
+            server.db_handle.insert("cpu".to_string(), DataPoint { timestamp: 100 + ts, value: ts as f64, tags: tags.clone() }).await?;
+        }
+
+        let body = server.get("/api/v1/series/cpu?fields=count,last_timestamp").await?.json()?;
+        assert_eq!(body["data"], serde_json::json!({"count": 3, "last_timestamp": 102}));
+        let full = server.get("/api/v1/series/cpu").await?.json()?;
+        assert_eq!(full["data"]["tags"]["host"], "a");
+
+        // 未知字段返回400并列出可选字段
+        let response = server.get("/api/v1/series/cpu?fields=count,colour").await?;
+        assert_eq!(response.status, axum::http::StatusCode::BAD_REQUEST);
+        let message = response.json()?["message"].as_str().unwrap_or_default().to_string();
+        assert!(message.contains("colour") && message.contains("last_timestamp"), "{}", message);
+
+        let body = server.get("/api/v1/series/new?since=0&fields=series_key").await?.json()?;
+        assert_eq!(body["data"]["series"], serde_json::json!([{"series_key": "cpu"}]));
+        let body = server.get("/api/v1/series/stale?threshold_secs=0&fields=series_key").await?.json()?;
+        assert_eq!(body["data"]["series"], serde_json::json!([{"series_key": "cpu"}]));
+        assert_eq!(server.get("/api/v1/series/stale?threshold_secs=0&fields=tags").await?.status, 400);
+
+        let body = server.get("/stats?fields=total_series,sstable_count").await?.json()?;
+        assert_eq!(body["data"], serde_json::json!({"total_series": 1, "sstable_count": 0}));
+        assert_eq!(server.get("/stats?fields=nope").await?.status, 400);
+        let body = server.get("/api/v1/stats/summary?fields=total_series").await?.json()?;
+        assert_eq!(body["data"], serde_json::json!({"total_series": 1}));
+        let body = server.get("/api/v1/series?fields=count").await?.json()?;
+        assert_eq!(body["data"], serde_json::json!({"count": 1}));
+        assert_eq!(server.get("/api/v1/series?fields=tags").await?.status, 400);
+
+        // 每个模型列出的可选字段与序列化结果的键一致
+        fn keys<T: api::ResponseFields>(value: &T) -> (Vec<String>, Vec<String>) {
+            let mut serialized: Vec<String> = serde_json::to_value(value).unwrap().as_object().unwrap().keys().cloned().collect();
+            let mut fields: Vec<String> = T::FIELDS.iter().map(|field| field.to_string()).collect();
+            serialized.sort();
+            fields.sort();
+            (serialized, fields)
+        }
+        let (serialized, fields) = keys(&server.db_handle.stats_summary());
+        assert_eq!(serialized, fields);
+        let stale = StaleSeries { series_key: "cpu".to_string(), last_timestamp: 102, last_value: None };
+        let (serialized, fields) = keys(&stale);
+        assert_eq!(serialized, fields);
+        let (serialized, fields) = keys(&api::models::SeriesFirstSeen { series_key: "cpu".to_string(), first_seen: 100 });
+        assert_eq!(serialized, fields);
+        let (serialized, fields) = keys(&api::models::SeriesListResponse::new(vec!["cpu".to_string()]));
+        assert_eq!(serialized, fields);
+        let info: serde_json::Value = full["data"].clone();
+        let mut serialized: Vec<&str> = info.as_object().unwrap().keys().map(String::as_str).collect();
+        let mut fields = <api::models::SeriesInfo as api::ResponseFields>::FIELDS.to_vec();
+        serialized.sort();
+        fields.sort();
+        assert_eq!(serialized, fields);
+
+        // 序列化时跳过没有选择的字段，rename 和 flatten 后的字段名同样可选
+        #[derive(serde::Serialize)]
+        struct Inner {
+            #[serde(rename = "b")]
+            renamed: u32,
+            c: u32,
+        }
+        #[derive(serde::Serialize)]
+        struct Outer {
+            a: u32,
+            #[serde(flatten)]
+            inner: Inner,
+        }
+        let fields = api::FieldSelection::parse(Some("b,a"), &["a", "b", "c"]).map_err(anyhow::Error::msg)?;
+        let selected = fields.apply(Outer { a: 1, inner: Inner { renamed: 2, c: 3 } });
+        assert_eq!(serde_json::to_string(&selected)?, r#"{"a":1,"b":2}"#);
+        let selected = fields.apply(serde_json::json!({"a": 1, "b": {"a": 5}, "c": 3}));
+        assert_eq!(serde_json::to_value(&selected)?, serde_json::json!({"a": 1, "b": {"a": 5}}));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_decode_scratch_reuse() -> anyhow::Result<()> {
+        use compression::scratch::{with_decode_scratch, DecodeScratch, DecodeScratchCounters};
+
+        let points: Vec<(u64, f64)> = (0..500u64).map(|ts| (1000 + ts, (ts % 17) as f64)).collect();
+        let block = compression::format::encode_block_v2(&points);
+        let counters = DecodeScratchCounters::new();
+        let mut scratch = DecodeScratch::new();
+        let all = |_: &(u64, f64)| true;
+        let first = scratch.decode(block.clone(), usize::MAX, &counters, all)?.clone();
+        assert_eq!(first, points);
+        let buffer = scratch.decode(block.clone(), usize::MAX, &counters, all)?.as_ptr();
+        let shorter = compression::format::encode_block_v2(&points[..250]);
+        assert_eq!(scratch.decode(shorter, usize::MAX, &counters, all)?.as_ptr(), buffer);
+        // 截断的块缺少结束标记，解码失败
+        assert!(scratch.decode(block[..block.len() / 2].to_vec(), usize::MAX, &counters, all).is_err());
+        assert_eq!(counters.snapshot().allocated, 1);
+        assert_eq!(counters.snapshot().reused, 2);
+        // 解码时只保留前 limit 个点中满足条件的点
+        let kept = scratch.decode(block.clone(), 100, &counters, |(ts, _)| ts % 2 == 0)?;
+        assert_eq!(*kept, points[..100].iter().copied().filter(|(ts, _)| ts % 2 == 0).collect::<Vec<_>>());
+
+        // 线程的缓冲区在多次调用间保留，超过上限的容量在调用结束后释放
+        with_decode_scratch(1000, |scratch| scratch.decode(block.clone(), usize::MAX, &counters, all).map(|_| ()))?;
+        assert!(with_decode_scratch(1000, |scratch| scratch.capacity()) >= points.len());
+        assert!(with_decode_scratch(100, |scratch| scratch.capacity()) >= points.len());
+        assert!(with_decode_scratch(100, |scratch| scratch.capacity()) <= 100);
+
+        let temp_dir = TempDir::new()?;
+        let db = TimeSeriesDB::builder(temp_dir.path()).memtable_threshold(200).build()?;
+        for ts in 0..1000u64 {
+            db.insert("cpu".to_string(), DataPoint { timestamp: ts, value: ts as f64 * 0.5, tags: BTreeMap::new() }).await?;
+        }
+        assert!(db.sstable_infos().len() >= 4);
+
+        let expected = db.query_range("cpu", Some(100), Some(899)).await?;
+        assert_eq!(expected.len(), 800);
+        let warm = db.get_stats().await?.decode_buffers;
+        assert!(warm.reused + warm.allocated >= 4);
+        for _ in 0..5 {
+            let results = db.query_range("cpu", Some(100), Some(899)).await?;
+            assert_eq!(
+                results.iter().map(|dp| (dp.timestamp, dp.value)).collect::<Vec<_>>(),
+                expected.iter().map(|dp| (dp.timestamp, dp.value)).collect::<Vec<_>>()
+            );
+        }
+        // 之后的查询全部复用缓冲区，不再重新分配
+        let stats = db.get_stats().await?.decode_buffers;
+        assert_eq!(stats.allocated, warm.allocated);
+        assert!(stats.reused >= warm.reused + 5 * 4);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_tenant_api_keys_isolate_prefixes() -> anyhow::Result<()> {
+        let tenants: BTreeMap<String, String> =
+            [("key-a", "tenant42."), ("key-b", "tenant7.")].into_iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        let server = testing::spawn_test_server_with(|b| b.memtable_threshold(3).tenant_api_keys(tenants.clone())).await?;
+        let a = ("X-API-Key", "key-a");
+        let b = ("X-API-Key", "key-b");
+        let json = ("Content-Type", "application/json");
+        let write = |key: &'static str, ts: u64, value: f64| serde_json::json!({"series_key": key, "timestamp": ts, "value": value});
+
+        // 两个租户写入同名系列，引擎中的键带各自的前缀，响应中不带
+        let body = server.request("POST", "/api/v1/datapoints", &[a, json], write("cpu", 1, 1.0).to_string().as_bytes()).await?.json()?;
+        assert_eq!(body["data"], "数据点已添加到系列: cpu (时间戳: 1)");
+        let batch = serde_json::json!([write("cpu", 2, 2.0), write("mem", 1, 5.0)]);
+        let body = server.request("POST", "/api/v1/datapoints/batch", &[a, json], batch.to_string().as_bytes()).await?.json()?;
+        assert_eq!(body["data"]["per_series"][0]["series_key"], "cpu");
+        server.request("POST", "/api/v1/datapoints", &[b, json], write("cpu", 1, 70.0).to_string().as_bytes()).await?;
+        server.db_handle.insert("other".to_string(), DataPoint { timestamp: 1, value: 0.0, tags: BTreeMap::new() }).await?;
+        assert_eq!(server.db_handle.get_all_series().await?, vec!["other", "tenant42.cpu", "tenant42.mem", "tenant7.cpu"]);
+
+        // 列表只返回租户自己的系列并去掉前缀，其他调用方看到全部系列
+        let body = server.request("GET", "/api/v1/series", &[a], b"").await?.json()?;
+        assert_eq!(body["data"]["series"], serde_json::json!(["cpu", "mem"]));
+        let body = server.request("GET", "/api/v1/series/new?since=0", &[b], b"").await?.json()?;
+        assert_eq!(body["data"]["series"], serde_json::json!([{"series_key": "cpu", "first_seen": body["data"]["series"][0]["first_seen"]}]));
+        assert_eq!(server.get("/api/v1/series").await?.json()?["data"]["count"], 4);
+        assert_eq!(server.get("/api/v1/series?prefix=tenant4").await?.json()?["data"]["series"], serde_json::json!(["tenant42.cpu", "tenant42.mem"]));
+
+        // 查询只能读到自己的数据，带其他租户前缀的键仍在自己的前缀之下
+        let body = server.request("GET", "/api/v1/series/cpu/datapoints", &[a], b"").await?.json()?;
+        let values: Vec<f64> = body["data"].as_array().unwrap().iter().map(|dp| dp["value"].as_f64().unwrap()).collect();
+        assert_eq!(values, vec![1.0, 2.0]);
+        let body = server.request("GET", "/api/v1/series/tenant7.cpu/datapoints", &[a], b"").await?.json()?;
+        assert_eq!(body["data"], serde_json::json!([]));
+        let body = server.request("GET", "/api/v1/series/cpu", &[b], b"").await?.json()?;
+        assert_eq!(body["data"]["series_key"], "cpu");
+        assert_eq!(body["data"]["count"], 1);
+
+        // 删除同样限定在租户的前缀内
+        let response = server.request("DELETE", "/api/v1/series/tenant7.cpu", &[a], b"").await?;
+        assert_eq!(response.json()?["success"], false);
+        assert_eq!(server.db_handle.query_range("tenant7.cpu", None, None).await?.len(), 1);
+        let body = server.request("DELETE", "/api/v1/series?prefix=", &[b], b"").await?.json()?;
+        assert_eq!(body["data"]["deleted"], serde_json::json!(["cpu"]));
+        assert_eq!(server.db_handle.get_all_series().await?, vec!["other", "tenant42.cpu", "tenant42.mem"]);
+
+        // 管理和跨系列接口对租户不可用
+        for (method, path) in [
+            ("GET", "/stats"),
+            ("GET", "/api/v1/stats/summary"),
+            ("POST", "/api/v1/admin/compact"),
+            ("POST", "/api/v1/admin/truncate"),
+            ("GET", "/api/v1/query/aggregate_across?series=cpu&fn=avg"),
+            ("GET", "/api/v1/series/"),
+        ] {
+            let response = server.request(method, path, &[a, json], b"{}").await?;
+            assert_eq!(response.status, 403, "{} {}", method, path);
+        }
+        assert_eq!(server.db_handle.get_all_series().await?.len(), 3);
+        assert_eq!(server.request("GET", "/health", &[a], b"").await?.status, 200);
+
+        // 前缀统计只读取计数，不包含前缀之外的系列
+        let body = server.request("GET", "/api/v1/stats/prefix", &[a], b"").await?.json()?;
+        assert_eq!(body["data"]["prefix"], "");
+        assert_eq!(body["data"]["series"], 2);
+        assert_eq!(body["data"]["total_points"], 3);
+        assert_eq!(body["data"]["top_series"][0]["series_key"], "cpu");
+        let stats = server.db_handle.prefix_stats("tenant42.");
+        assert_eq!((stats.series, stats.total_points), (2, 3));
+        assert_eq!(server.db_handle.series_with_prefix("tenant42.m"), vec!["tenant42.mem"]);
+        assert!(server.db_handle.delete_prefix("").await.is_err());
+
+        // 只有系列键字段去掉前缀，标签值和注释文本中相同的字符串保持原样
+        let tagged = serde_json::json!({"series_key": "net", "timestamp": 3, "value": 3.0, "tags": {"source": "tenant42.agent"}});
+        server.request("POST", "/api/v1/datapoints", &[a, json], tagged.to_string().as_bytes()).await?;
+        let body = server.request("GET", "/api/v1/series/net/datapoints", &[a], b"").await?.json()?;
+        assert_eq!(body["data"][0]["tags"]["source"], "tenant42.agent");
+        let annotation = serde_json::json!({"series_key": "cpu", "timestamp": 3, "text": "tenant42.cpu 扩容"});
+        let body = server.request("POST", "/api/v1/annotations", &[a, json], annotation.to_string().as_bytes()).await?.json()?;
+        assert_eq!((&body["data"]["series_key"], &body["data"]["text"]), (&serde_json::json!("cpu"), &serde_json::json!("tenant42.cpu 扩容")));
+        server.stop().await?;
+
+        // 配置接口对租户不可用，也不返回租户的API key
+        let config = Config { tenant_api_keys: tenants, ..Config::default() };
+        let server = testing::spawn_test_server_with_config(config).await?;
+        assert_eq!(server.request("GET", "/api/v1/admin/config", &[a], b"").await?.status, 403);
+        let body = server.get("/api/v1/admin/config").await?.json()?;
+        assert_eq!(body["data"]["tenant_api_keys"], serde_json::json!({"[redacted]#1": "tenant42.", "[redacted]#2": "tenant7."}));
+        server.stop().await?;
+
+        // 一个前缀是另一个前缀的开头时配置无效，同一前缀可以绑定多个key
+        let env = |keys: &'static str| move |name: &str| (name == "TENANT_API_KEYS").then(|| keys.to_string());
+        let error = Config::load(None, env("k1=t1,k2=t10,k3=t2")).unwrap_err();
+        assert_eq!(error.errors, ["tenant_api_keys: 前缀 t1 是前缀 t10 的开头，租户之间的系列不能隔离"]);
+        assert!(Config::load(None, env("k1=t1.,k2=t10.,k3=t1.")).is_ok());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_validate_datapoint() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let clock = std::sync::Arc::new(MockClock::from_secs(1_000));
+        let db = TimeSeriesDB::builder(temp_dir.path())
+            .clock(clock)
+            .tag_drift_mode(TagDriftMode::Reject)
+            .max_future_skew_secs(60)
+            .strict_validation(true)
+            .build()?;
+        let tags = |pairs: &[(&str, &str)]| -> BTreeMap<String, String> {
+            pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+        };
+        let point = |timestamp: u64, value: f64, tags: BTreeMap<String, String>| DataPoint { timestamp, value, tags };
+        let valid = point(1_060, 1.0, tags(&[("room", "r1")]));
+
+        // 合法的点通过校验，校验本身不写入、不确立标签
+        assert_eq!(db.validate_datapoint("temp", &valid), Ok(()));
+        assert_eq!(db.validate_datapoint("temp", &point(1, 1.0, tags(&[("room", "r2")]))), Ok(()));
+        assert!(db.get_all_series().await?.is_empty());
+
+        let long_key = "k".repeat(MAX_SERIES_KEY_BYTES + 1);
+        let cases = [
+            (long_key.as_str(), valid.clone(), ValidationError::SeriesKeyTooLong { length: MAX_SERIES_KEY_BYTES + 1, max_length: MAX_SERIES_KEY_BYTES }),
+            ("temp", point(1, 1.0, tags(&[("", "x")])), ValidationError::EmptyTagKey),
+            ("temp", point(1, 1.0, tags(&[("room", "r\t1")])), ValidationError::TagControlChar { tag_key: "room".to_string() }),
+            ("temp", point(1, f64::INFINITY, BTreeMap::new()), ValidationError::NonFiniteValue { value: f64::INFINITY }),
+            ("temp", point(1_061, 1.0, BTreeMap::new()), ValidationError::TimestampTooFarAhead { timestamp: 1_061, max_timestamp: 1_060 }),
+        ];
+        for (series_key, datapoint, expected) in cases {
+            assert_eq!(db.validate_datapoint(series_key, &datapoint), Err(expected.clone()));
+            // insert 按相同的规则拒绝，返回带系列键的错误
+            let error = db.insert(series_key.to_string(), datapoint).await.unwrap_err();
+            assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+            assert_eq!(
+                DbError::from_io(&error),
+                Some(&DbError::InvalidDataPoint { series_key: series_key.to_string(), error: expected })
+            );
+        }
+        // NaN 不等于自身，单独检查
+        let nan = db.validate_datapoint("temp", &point(1, f64::NAN, BTreeMap::new())).unwrap_err();
+        assert!(matches!(nan, ValidationError::NonFiniteValue { value } if value.is_nan()));
+        assert!(db.get_all_series().await?.is_empty());
+
+        // 系列确立标签后，reject模式下不同的标签无法通过校验，insert 仍返回标签漂移错误
+        db.insert("temp".to_string(), valid).await?;
+        let drifted = point(2, 2.0, tags(&[("room", "r2")]));
+        assert_eq!(
+            db.validate_datapoint("temp", &drifted),
+            Err(ValidationError::TagDrift { expected: tags(&[("room", "r1")]), actual: tags(&[("room", "r2")]) })
+        );
+        let error = db.insert("temp".to_string(), drifted).await.unwrap_err();
+        assert!(matches!(DbError::from_io(&error), Some(DbError::TagDrift { .. })));
+        assert_eq!(db.get_stats().await?.tag_drift_counts.get("temp"), Some(&1));
+
+        // 其他写入路径使用同一套规则，更新只检查值和时间戳
+        fn invalid<T>(result: std::io::Result<T>) -> bool {
+            result.err().is_some_and(|error| matches!(DbError::from_io(&error), Some(DbError::InvalidDataPoint { .. })))
+        }
+        let room = tags(&[("room", "r1")]);
+        assert!(invalid(db.update("temp", 1_060, f64::NAN).await));
+        assert!(invalid(db.update_batch(vec![("temp".to_string(), 1_060, 2.0), ("temp".to_string(), 1_060, f64::INFINITY)]).await));
+        assert!(invalid(db.replace_series("temp", vec![point(5, f64::NAN, room.clone())]).await));
+        assert!(invalid(db.replace_series("temp", vec![point(1_061, 1.0, room.clone())]).await));
+        assert!(invalid(db.ingest_block("temp".to_string(), EncodedBlock::encode(&[(5, 1.0), (6, f64::NAN)]), room.clone()).await));
+        assert!(invalid(db.ingest_block("other".to_string(), EncodedBlock::encode(&[(5, 1.0)]), tags(&[("", "x")])).await));
+        let values: Vec<(u64, f64)> = db.query_range("temp", None, None).await?.iter().map(|dp| (dp.timestamp, dp.value)).collect();
+        assert_eq!(values, vec![(1_060, 1.0)]);
+
+        // 默认不检查新增的规则，时间戳偏差只在配置后检查
+        let lenient = TimeSeriesDB::new(temp_dir.path().join("lenient"), 100)?;
+        let long_key = "k".repeat(MAX_SERIES_KEY_BYTES + 1);
+        assert_eq!(lenient.validate_datapoint(&long_key, &point(u64::MAX, f64::NAN, tags(&[("", "\n")]))), Ok(()));
+        lenient.insert("nan".to_string(), point(1, f64::NAN, BTreeMap::new())).await?;
+        assert!(lenient.update("nan", 1, f64::INFINITY).await?);
+
+        // 导入时任一数据点不满足规则，整个归档被拒绝
+        let (archive, _) = lenient.export_archive(Vec::new()).await?;
+        let strict = TimeSeriesDB::builder(temp_dir.path().join("strict")).strict_validation(true).build()?;
+        assert!(invalid(strict.import_archive(std::io::Cursor::new(archive.clone()), false).await));
+        assert!(strict.get_all_series().await?.is_empty());
+        TimeSeriesDB::new(temp_dir.path().join("copy"), 100)?.import_archive(std::io::Cursor::new(archive), false).await?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_gorilla_fuzz_harness() {
+        use compression::fuzz::{check_decoder, check_round_trip, points_from_bytes};
+        use compression::format::{decode_versioned, encode_block_v2};
+
+        // 全是1或全是0的位流、只有结束标记的位流
+        for len in 0..=64 {
+            check_decoder(&vec![0xFF; len]);
+            check_decoder(&vec![0x00; len]);
+        }
+        check_decoder(&[0xFF]);
+        check_round_trip(&[]);
+        check_round_trip(&[(u64::MAX, f64::NAN), (0, -0.0), (u64::MAX, f64::INFINITY), (1 << 63, 0.0)]);
+
+        // 截断的块缺少结束标记，整块解码返回错误
+        let block = encode_block_v2(&[(10, 1.0), (20, 2.5), (30, 2.5)]);
+        assert!(decode_versioned(&block[..block.len() - 1]).is_err());
+        let valid = block[1..].to_vec();
+
+        let (mut rng, seed, iterations) = fuzz_rng(2000);
+        for iteration in 0..iterations {
+            let len = rng.usize(..256);
+            let random: Vec<u8> = std::iter::repeat_with(|| rng.u8(..)).take(len).collect();
+
+            // 有效位流中翻转一位或截断
+            let mut mutated = valid.clone();
+            let bit = rng.usize(..mutated.len() * 8);
+            mutated[bit / 8] ^= 1 << (bit % 8);
+            mutated.truncate(rng.usize(..=mutated.len()));
+
+            let outcome = std::panic::catch_unwind(|| {
+                check_decoder(&random);
+                check_decoder(&mutated);
+                check_round_trip(&points_from_bytes(&random));
+            });
+            assert!(outcome.is_ok(), "seed {} 第 {} 轮失败，输入: {:02x?} {:02x?}", seed, iteration, random, mutated);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_query_stops_when_caller_disconnects() -> anyhow::Result<()> {
+        // 第一个块开始解压时通知测试，并阻塞到测试放行
+        let (gate, mut control) = testing::QueryBlockGate::new();
+        let temp_dir = TempDir::new()?;
+        let db = TimeSeriesDB::builder(temp_dir.path())
+            .memtable_threshold(10)
+            .inline_query_points(0)
+            .with_observer(gate)
+            .build()?;
+        for ts in 0..100u64 {
+            db.insert("cpu".to_string(), DataPoint { timestamp: ts, value: ts as f64, tags: BTreeMap::new() }).await?;
+        }
+        assert_eq!(db.sstable_infos().len(), 10);
+
+        // 解压第一个块时丢弃查询的future，与客户端断开连接时axum丢弃处理请求的future相同
+        let query = tokio::spawn({
+            let db = db.clone();
+            async move { db.query_range("cpu", None, None).await }
+        });
+        control.entered(std::time::Duration::from_secs(10)).await?;
+        query.abort();
+        assert!(query.await.unwrap_err().is_cancelled());
+        control.release()?;
+
+        // 解压线程在下一个块之前发现查询已取消，剩余的9个块不再解压
+        let progress = testing::wait_until(std::time::Duration::from_secs(10), || async {
+            let progress = db.get_stats().await?.query_progress;
+            Ok((progress.cancelled > 0).then_some(progress))
+        })
+        .await?;
+        assert_eq!(progress, QueryProgressStats { blocks_decoded: 1, cancelled: 1 });
+
+        // 没有被取消的查询解压全部块
+        assert_eq!(db.query_range("cpu", None, None).await?.len(), 100);
+        assert_eq!(db.get_stats().await?.query_progress, QueryProgressStats { blocks_decoded: 11, cancelled: 1 });
+        let partial = db.query_range_within("cpu", None, None, std::time::Duration::from_secs(5)).await?;
+        assert_eq!(partial.datapoints.len(), 100);
+        assert_eq!(db.get_stats().await?.query_progress.cancelled, 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_annotations() -> anyhow::Result<()> {
+        const NOW: u64 = 1_700_000_000;
+        let clock = std::sync::Arc::new(MockClock::from_secs(NOW));
+        let tenants: BTreeMap<String, String> = [("key-a".to_string(), "tenant42.".to_string())].into_iter().collect();
+        let server = testing::spawn_test_server_with(|b| b.clock(clock.clone()).tenant_api_keys(tenants.clone())).await?;
+        let db = &server.db_handle;
+        for ts in [NOW - 600, NOW - 300, NOW] {
+            db.insert("cpu".to_string(), DataPoint { timestamp: ts, value: 1.0, tags: BTreeMap::new() }).await?;
+        }
+
+        // 全局的时间点注释，系列的时间段注释，其他系列的注释
+        let body = server.post_json("/api/v1/annotations", &serde_json::json!({"timestamp": NOW - 1200, "text": "部署 v1.2"})).await?.json()?;
+        assert_eq!(body["data"], serde_json::json!({"id": 1, "timestamp": NOW - 1200, "text": "部署 v1.2", "tags": {}}));
+        let incident = serde_json::json!({"series_key": "cpu", "timestamp": NOW - 400, "end_timestamp": NOW - 200, "text": "故障", "tags": {"severity": "high"}});
+        let body = server.post_json("/api/v1/annotations", &incident).await?.json()?;
+        assert_eq!(body["data"]["id"], 2);
+        let body = server.post_json("/api/v1/annotations?precision=ms", &serde_json::json!({"series_key": "mem", "timestamp": (NOW - 100) * 1000, "text": "扩容"})).await?.json()?;
+        assert_eq!(body["data"]["timestamp"], NOW - 100);
+
+        for (request, message) in [
+            (serde_json::json!({"timestamp": 1, "text": "  "}), "注释文本不能为空"),
+            (serde_json::json!({"timestamp": 10, "end_timestamp": 5, "text": "x"}), "注释的结束时间戳 5 早于开始时间戳 10"),
+            (serde_json::json!({"timestamp": 1, "text": "x", "tags": {"a": "b\n"}}), "标签 \"a\" 的键或值包含控制字符"),
+        ] {
+            let response = server.post_json("/api/v1/annotations", &request).await?;
+            assert_eq!(response.status, 400);
+            assert_eq!(response.json()?["message"], format!("添加注释失败: 注释无效: {}", message));
+        }
+
+        // 指定系列时包括全局注释，时间段注释与范围相交即返回
+        let ids = |body: &serde_json::Value| -> Vec<u64> { body["data"].as_array().unwrap().iter().map(|a| a["id"].as_u64().unwrap()).collect() };
+        assert_eq!(ids(&server.get("/api/v1/annotations").await?.json()?), vec![1, 2, 3]);
+        assert_eq!(ids(&server.get("/api/v1/annotations?series_key=cpu").await?.json()?), vec![1, 2]);
+        let body = server.get(&format!("/api/v1/annotations?series_key=cpu&start_time={}&end_time={}", NOW - 250, NOW)).await?.json()?;
+        assert_eq!(ids(&body), vec![2]);
+        let body = server.get("/api/v1/annotations?last=5m").await?.json()?;
+        assert_eq!(ids(&body), vec![2, 3]);
+        assert_eq!(body["range"], serde_json::json!({"start_time": NOW - 300, "end_time": NOW}));
+
+        // 数据点查询附带注释，不指定时不出现
+        let body = server.get("/api/v1/series/cpu/datapoints?last=10m&include_annotations=true").await?.json()?;
+        assert_eq!(body["data"].as_array().unwrap().len(), 3);
+        let mut expected = incident.clone();
+        expected["id"] = serde_json::json!(2);
+        assert_eq!(body["annotations"], serde_json::json!([expected]));
+        assert!(server.get("/api/v1/series/cpu/datapoints").await?.json()?.get("annotations").is_none());
+
+        // 租户只能为自己的系列添加注释，看不到全局注释和其他系列的注释
+        let tenant = ("X-API-Key", "key-a");
+        let json = ("Content-Type", "application/json");
+        let response = server.request("POST", "/api/v1/annotations", &[tenant, json], br#"{"timestamp": 1, "text": "x"}"#).await?;
+        assert_eq!(response.status, 403);
+        let body = server.request("POST", "/api/v1/annotations", &[tenant, json], br#"{"series_key": "cpu", "timestamp": 1, "text": "x"}"#).await?.json()?;
+        assert_eq!(body["data"]["series_key"], "cpu");
+        assert_eq!(db.annotation(4).unwrap().series_key.as_deref(), Some("tenant42.cpu"));
+        let body = server.request("GET", "/api/v1/annotations", &[tenant], b"").await?.json()?;
+        assert_eq!(ids(&body), vec![4]);
+        assert_eq!(server.request("DELETE", "/api/v1/annotations/1", &[tenant], b"").await?.status, 404);
+
+        // 按编号删除
+        assert_eq!(server.delete("/api/v1/annotations/3").await?.status, 200);
+        assert_eq!(server.delete("/api/v1/annotations/3").await?.status, 404);
+
+        // 删除系列时可以同时删除注释
+        let body = server.delete("/api/v1/series/cpu?delete_annotations=true").await?.json()?;
+        assert_eq!(body["data"], "系列已删除: cpu (同时删除 1 条注释)");
+        assert_eq!(ids(&server.get("/api/v1/annotations").await?.json()?), vec![4, 1]);
+
+        // 注释保存在数据目录中，重新打开后仍在，编号继续递增
+        let dir = TempDir::new()?;
+        let db = TimeSeriesDB::builder(dir.path()).build()?;
+        let first = db.add_annotation(NewAnnotation { timestamp: 5, text: "a".to_string(), ..NewAnnotation::default() }).await?;
+        db.close().await?;
+        assert!(dir.path().join(ANNOTATIONS_FILE).exists());
+        let db = TimeSeriesDB::builder(dir.path()).build()?;
+        assert_eq!(db.annotations(None, &TimeRange::between(None, None))?, vec![first]);
+        let second = db.add_annotation(NewAnnotation { timestamp: 1, text: "b".to_string(), ..NewAnnotation::default() }).await?;
+        assert_eq!(second.id, 2);
+        assert!(db.delete_annotation(1).await?);
+
+        // 写文件失败时撤销修改：新注释不保留、编号不前进，删除的注释按原来的顺序放回
+        let third = db.add_annotation(NewAnnotation { timestamp: 9, text: "c".to_string(), ..NewAnnotation::default() }).await?;
+        let blocker = dir.path().join(ANNOTATIONS_FILE).with_extension("json.tmp");
+        std::fs::create_dir(&blocker)?;
+        assert!(db.add_annotation(NewAnnotation { timestamp: 3, text: "d".to_string(), ..NewAnnotation::default() }).await.is_err());
+        assert!(db.delete_annotation(second.id).await.is_err());
+        assert_eq!(db.annotations(None, &TimeRange::between(None, None))?, vec![second, third]);
+        std::fs::remove_dir(&blocker)?;
+        assert_eq!(db.add_annotation(NewAnnotation { timestamp: 3, text: "d".to_string(), ..NewAnnotation::default() }).await?.id, 4);
+        db.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_default_query_range() -> anyhow::Result<()> {
+        const NOW: u64 = 1_700_000_000;
+        let clock = std::sync::Arc::new(MockClock::from_secs(NOW));
+        let server = testing::spawn_test_server_with(|b| b.clock(clock.clone()).default_query_range_secs(600)).await?;
+        let db = &server.db_handle;
+        // 最近一小时每分钟一个点
+        for minute in 0..=60u64 {
+            db.insert("load".to_string(), DataPoint { timestamp: NOW - minute * 60, value: 1.0, tags: BTreeMap::new() }).await?;
+        }
+        let timestamps = |body: &serde_json::Value| -> Vec<u64> {
+            body["data"].as_array().unwrap().iter().map(|dp| dp["timestamp"].as_u64().unwrap()).collect()
+        };
+
+        // 不指定时间范围时只返回最近10分钟，并给出换算后的范围
+        let body = server.get("/api/v1/series/load/datapoints").await?.json()?;
+        assert_eq!(timestamps(&body), (0..=10).rev().map(|minute| NOW - minute * 60).collect::<Vec<_>>());
+        assert_eq!(body["range"], serde_json::json!({"start_time": NOW - 600, "end_time": NOW}));
+        let body = server.get("/api/v1/series/load/downsample?interval=3600&fn=count").await?.json()?;
+        assert_eq!(body["data"]["buckets"][0]["count"], 11);
+
+        // all=true 扫描整个系列，指定了一端时不使用默认范围
+        let body = server.get("/api/v1/series/load/datapoints?all=true").await?.json()?;
+        assert_eq!(timestamps(&body).len(), 61);
+        assert!(body.get("range").is_none());
+        let body = server.get(&format!("/api/v1/series/load/datapoints?start_time={}", NOW - 1800)).await?.json()?;
+        assert_eq!(timestamps(&body).len(), 31);
+        let body = server.get("/api/v1/query/aggregate_across?interval=7200&fn=count&all=true").await?.json()?;
+        let counts: f64 = body["data"]["buckets"].as_array().unwrap().iter().map(|bucket| bucket["value"].as_f64().unwrap()).sum();
+        assert_eq!(counts, 61.0);
+
+        // 系列信息的统计范围相同
+        let body = server.get("/api/v1/series/load").await?.json()?;
+        assert_eq!((body["data"]["count"].as_u64(), body["data"]["min_timestamp"].as_u64()), (Some(11), Some(NOW - 600)));
+        assert_eq!(body["range"], serde_json::json!({"start_time": NOW - 600, "end_time": NOW}));
+        let body = server.get("/api/v1/series/load?all=true").await?.json()?;
+        assert_eq!(body["data"]["count"], 61);
+        assert!(body.get("range").is_none());
+
+        // 嵌入使用时 query_range 不受影响
+        assert_eq!(db.query_range("load", None, None).await?.len(), 61);
+        assert_eq!(db.default_time_range(TimeRange::default()), TimeRange::last(std::time::Duration::from_secs(600)));
+        let explicit = TimeRange::between(Some(5), None);
+        assert_eq!(db.default_time_range(explicit), explicit);
+
+        // 默认范围为0时所有没有指定范围的查询都为空，配置时拒绝
+        let config = Config { default_query_range_secs: Some(0), ..Config::default() };
+        assert_eq!(config.validate(), ["default_query_range_secs: 必须大于0"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_verify_job() -> anyhow::Result<()> {
+        // 第一个块开始解压时阻塞到测试放行，期间唯一的阻塞线程被占用
+        let (gate, mut control) = testing::QueryBlockGate::new();
+        let server = testing::spawn_test_server_with(|b| {
+            b.memtable_threshold(10).inline_query_points(0).blocking_threads(1).with_observer(gate.clone())
+        })
+        .await?;
+        let db = &server.db_handle;
+        for ts in 0..30u64 {
+            db.insert("cpu".to_string(), DataPoint { timestamp: ts, value: ts as f64, tags: BTreeMap::new() }).await?;
+        }
+        assert_eq!(db.sstable_infos().len(), 3);
+        let server = &server;
+        let wait_finished = |id: u64| {
+            testing::wait_until(std::time::Duration::from_secs(10), move || async move {
+                let job = server.get(&format!("/api/v1/admin/verify/{}", id)).await?.json()?;
+                Ok((job["data"]["status"] != "running").then(|| job["data"].clone()))
+            })
+        };
+
+        // 查询占住阻塞线程时开始的校验停在第一批之前，第二个校验返回409
+        let query = tokio::spawn({
+            let db = db.clone();
+            async move { db.query_range("cpu", None, None).await }
+        });
+        control.entered(std::time::Duration::from_secs(10)).await?;
+        let response = server.post_json("/api/v1/admin/verify", &serde_json::json!({})).await?;
+        assert_eq!(response.status, 202);
+        let job = response.json()?["data"].clone();
+        let id = job["id"].as_u64().unwrap();
+        assert_eq!((job["kind"].clone(), job["status"].clone()), (serde_json::json!("verify"), serde_json::json!("running")));
+        assert_eq!((job["files_done"].as_u64(), job["files_total"].as_u64()), (Some(0), Some(3)));
+        let response = server.post_json("/api/v1/admin/verify", &serde_json::json!({})).await?;
+        assert_eq!(response.status, 409);
+        assert_eq!(response.json()?["message"], format!("开始完整性校验失败: 已有维护任务在执行: 操作 {}", id));
+        // compaction和导出等待校验结束后才开始，不登记操作
+        let compaction = tokio::spawn({
+            let db = db.clone();
+            async move { db.compact().await }
+        });
+        let export = tokio::spawn({
+            let db = db.clone();
+            async move { db.export_snapshot().await.map(drop) }
+        });
+        tokio::task::yield_now().await;
+        let operations = server.get("/api/v1/admin/operations").await?.json()?;
+        assert_eq!(operations["data"].as_array().map(Vec::len), Some(1));
+        assert_eq!(operations["data"][0]["kind"], "verify");
+
+        // 取消后在下一批之前停止，结果保留
+        assert_eq!(server.delete(&format!("/api/v1/admin/operations/{}", id)).await?.status, 202);
+        control.release()?;
+        assert_eq!(query.await??.len(), 30);
+        let job = wait_finished(id).await?;
+        assert_eq!(job["status"], "cancelled");
+        assert_eq!(job["files_done"], 0);
+        assert_eq!(job["error"], format!("操作 {} 已取消", id));
+        assert!(job["finished_at"].is_u64());
+        compaction.await??;
+        export.await??;
+        assert_eq!(db.sstable_infos().len(), 1);
+
+        // 完整执行的校验给出报告
+        let id = server.post_json("/api/v1/admin/verify", &serde_json::json!({})).await?.json()?["data"]["id"].as_u64().unwrap();
+        let job = wait_finished(id).await?;
+        assert_eq!(job["status"], "completed");
+        assert_eq!(job["files_done"], 1);
+        assert_eq!(job["findings"], serde_json::json!([]));
+        assert_eq!(
+            job["result"],
+            serde_json::json!({"files_checked": 1, "files_skipped": 0, "blocks_checked": 1, "points_checked": 30, "corrupt": []})
+        );
+        assert_eq!(server.get("/api/v1/admin/verify/999").await?.status, 404);
+        assert!(server.get("/api/v1/admin/operations").await?.json()?["data"].as_array().unwrap().is_empty());
+
+        // 损坏的文件出现在报告中，其他文件照常检查
+        let temp_dir = TempDir::new()?;
+        let db = TimeSeriesDB::builder(temp_dir.path()).memtable_threshold(10).build()?;
+        for ts in 0..30u64 {
+            db.insert("cpu".to_string(), DataPoint { timestamp: ts, value: ts as f64, tags: BTreeMap::new() }).await?;
+        }
+        let file_name = db.sstable_infos()[1].file_name.clone();
+        let path = temp_dir.path().join(&file_name);
+        let bytes = std::fs::read(&path)?;
+        let tmp_path = temp_dir.path().join("corrupt.tmp");
+        std::fs::write(&tmp_path, &bytes[..bytes.len() / 2])?;
+        std::fs::rename(&tmp_path, &path)?;
+        let report = db.verify().await?;
+        assert_eq!((report.files_checked, report.blocks_checked, report.points_checked), (3, 2, 20));
+        assert_eq!(report.corrupt.len(), 1);
+        assert_eq!(report.corrupt[0].file_name, file_name);
+
+        // 校验迭代器可以从中间继续，取消后不再校验
+        let files: Vec<std::path::PathBuf> = db.sstable_infos().iter().map(|info| temp_dir.path().join(&info.file_name)).collect();
+        let token = tokio_util::sync::CancellationToken::new();
+        let mut verifier = SSTableVerifier::resume(files.clone(), 2, token.clone());
+        assert_eq!(verifier.next().map(|check| check.outcome), Some(FileOutcome::Verified { blocks: 1, points: 10 }));
+        assert!(verifier.is_done());
+        let mut verifier = SSTableVerifier::new(files, token.clone());
+        assert!(matches!(verifier.next().map(|check| check.outcome), Some(FileOutcome::Verified { .. })));
+        token.cancel();
+        assert!(verifier.next().is_none());
+        assert_eq!(verifier.position(), 1);
+        db.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_change_feed() -> anyhow::Result<()> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let tenants: BTreeMap<String, String> = [("key-a".to_string(), "tenant42.".to_string())].into_iter().collect();
+        let server = testing::spawn_test_server_with(|b| b.tenant_api_keys(tenants.clone())).await?;
+        let db = &server.db_handle;
+        let tags: BTreeMap<String, String> = [("host".to_string(), "a".to_string())].into_iter().collect();
+
+        // 没有订阅方时不产生事件，订阅之后的变更按顺序推送
+        db.insert("cpu".to_string(), DataPoint { timestamp: 50, value: 0.5, tags: tags.clone() }).await?;
+        let mut receiver = db.subscribe();
+        db.insert("cpu".to_string(), DataPoint { timestamp: 100, value: 1.5, tags: tags.clone() }).await?;
+        assert!(db.update("cpu", 100, 2.5).await?);
+        assert!(!db.update("cpu", 999, 1.0).await?);
+        assert!(db.delete("cpu", Some(100), false).await?);
+        assert_eq!(
+            receiver.recv().await?,
+            ChangeEvent::Insert { series_key: "cpu".to_string(), timestamp: 100, value: 1.5, tags: tags.clone() }
+        );
+        assert_eq!(receiver.recv().await?, ChangeEvent::Update { series_key: "cpu".to_string(), timestamp: 100, value: 2.5 });
+        let delete = receiver.recv().await?;
+        assert_eq!(delete, ChangeEvent::Delete { series_key: "cpu".to_string(), start_time: Some(100), end_time: Some(100) });
+        assert!(receiver.try_recv().is_err());
+        assert_eq!(
+            serde_json::to_value(&delete)?,
+            serde_json::json!({"type": "delete", "series_key": "cpu", "start_time": 100, "end_time": 100})
+        );
+
+        let filter = ChangeFilter { prefix: Some("cp".to_string()), tags: tags.clone(), ..Default::default() };
+        assert!(filter.matches("cpu", &tags));
+        assert!(!filter.matches("mem", &tags));
+        assert!(!filter.matches("cpu", &BTreeMap::new()));
+
+        // 格式不正确的标签条件，租户密钥不能订阅
+        let response = server.get("/api/v1/stream?tag=host").await?;
+        assert_eq!(response.status, 400);
+        assert_eq!(response.json()?["message"], "无效的标签条件: host，格式为 key:value");
+        let response = server.request("GET", "/api/v1/stream", &[("X-API-Key", "key-a")], b"").await?;
+        assert_eq!(response.status, 403);
+
+        // SSE连接只收到过滤后的事件
+        let mut stream = tokio::net::TcpStream::connect(server.base_url.trim_start_matches("http://")).await?;
+        stream.write_all(b"GET /api/v1/stream?series_key=cpu&tag=host:a HTTP/1.1\r\nHost: localhost\r\n\r\n").await?;
+        async fn read_until(stream: &mut tokio::net::TcpStream, received: &mut Vec<u8>, pattern: &str) -> anyhow::Result<()> {
+            let mut buf = [0u8; 4096];
+            while !String::from_utf8_lossy(received).contains(pattern) {
+                let n = tokio::time::timeout(std::time::Duration::from_secs(5), stream.read(&mut buf)).await??;
+                anyhow::ensure!(n > 0, "连接提前关闭");
+                received.extend_from_slice(&buf[..n]);
+            }
+            Ok(())
+        }
+        let mut received = Vec::new();
+        read_until(&mut stream, &mut received, "\r\n\r\n").await?;
+        assert!(String::from_utf8_lossy(&received).contains("text/event-stream"));
+        db.insert("mem".to_string(), DataPoint { timestamp: 200, value: 9.0, tags: tags.clone() }).await?;
+        db.insert("cpu".to_string(), DataPoint { timestamp: 200, value: 3.0, tags: BTreeMap::new() }).await?;
+        db.insert("cpu".to_string(), DataPoint { timestamp: 300, value: 4.0, tags: tags.clone() }).await?;
+        read_until(&mut stream, &mut received, "\n\n").await?;
+        let text = String::from_utf8_lossy(&received).to_string();
+        let body = &text[text.find("\r\n\r\n").unwrap()..];
+        assert!(body.contains("event: insert"), "{}", body);
+        assert!(body.contains(r#""series_key":"cpu","timestamp":300,"value":4.0"#), "{}", body);
+        assert!(!body.contains("mem") && !body.contains(r#""timestamp":200"#), "{}", body);
+
+        // 关闭后已有的订阅方收完缓冲的事件后结束，之后的订阅立即结束
+        db.close_change_feed();
+        let mut buffered = 0;
+        while let Ok(event) = receiver.recv().await {
+            assert_eq!(event.kind(), "insert");
+            buffered += 1;
+        }
+        assert_eq!(buffered, 3);
+        assert!(matches!(db.subscribe().recv().await, Err(tokio::sync::broadcast::error::RecvError::Closed)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_datapoints_tag_filter() -> anyhow::Result<()> {
+        let server = testing::spawn_test_server_with(|b| b.memtable_threshold(3).sensitive_tag_keys(vec!["serial".to_string()])).await?;
+        let db = &server.db_handle;
+        let tags: BTreeMap<String, String> =
+            [("quality", "estimated"), ("host", "a"), ("serial", "SN-1")].into_iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        // 内存表阈值为3，数据点分批刷新到SSTable
+        for timestamp in 1..=5 {
+            db.insert("cpu".to_string(), DataPoint { timestamp, value: timestamp as f64, tags: tags.clone() }).await?;
+        }
+        db.insert("mem".to_string(), DataPoint { timestamp: 1, value: 1.0, tags: BTreeMap::new() }).await?;
+
+        let timestamps = |body: serde_json::Value| -> Vec<u64> {
+            body["data"].as_array().unwrap().iter().map(|dp| dp["timestamp"].as_u64().unwrap()).collect()
+        };
+        // 数据点只有系列级的标签：标签满足所有条件时返回整个范围，否则一个点也不返回
+        for (query, expected) in [
+            ("tag=quality:estimated", vec![1, 2, 3, 4, 5]),
+            ("tag=quality:estimated&tag=host:a&start_time=2&end_time=4", vec![2, 3, 4]),
+            ("tag=quality:estimated&tag=host:b", vec![]),
+            ("tag=quality:measured", vec![]),
+            ("tag=region:", vec![]),
+            ("tag=quality:estimated&max_wait_ms=5000", vec![1, 2, 3, 4, 5]),
+            ("tag=host:b&max_wait_ms=5000", vec![]),
+        ] {
+            let response = server.get(&format!("/api/v1/series/cpu/datapoints?{}", query)).await?;
+            assert_eq!(response.status, 200, "{}", query);
+            assert_eq!(timestamps(response.json()?), expected, "{}", query);
+        }
+        // 没有标签的系列不满足任何条件
+        assert_eq!(timestamps(server.get("/api/v1/series/mem/datapoints?tag=quality:estimated").await?.json()?), Vec::<u64>::new());
+        assert_eq!(timestamps(server.get("/api/v1/series/mem/datapoints").await?.json()?), vec![1]);
+
+        let filter = TagFilter::new().with("quality", "estimated");
+        let result = db.query_range_filtered("cpu", None, None, &filter).await?;
+        assert_eq!(result.datapoints.len(), 5);
+        let result = db.query_range_filtered("cpu", None, None, &filter.clone().with("host", "b")).await?;
+        assert!(result.datapoints.is_empty());
+        assert!(TagFilter::new().matches(&BTreeMap::new()));
+
+        for condition in ["quality", ":estimated"] {
+            let response = server.get(&format!("/api/v1/series/cpu/datapoints?tag={}", condition)).await?;
+            assert_eq!(response.status, 400);
+            assert_eq!(response.json()?["message"], format!("无效的标签条件: {}，格式为 key:value", condition));
+        }
+        // 敏感标签的值对调用方隐藏，不能用于过滤
+        let response = server.get("/api/v1/series/cpu/datapoints?tag=serial:SN-1").await?;
+        assert_eq!(response.status, 403);
+        assert_eq!(response.json()?["message"], "标签 serial 为敏感标签，不能用于过滤");
+
+        server.stop().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sstable_encoding_round_trip() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let series_list: Vec<SeriesData> = (0..5u64)
+            .map(|i| {
+                let points: Vec<(u64, f64)> = (0..100u64).map(|ts| (1000 + ts, (ts * i) as f64)).collect();
+                let ingest: Vec<(u64, u64)> = points.iter().map(|(ts, _)| (*ts, ts + 7)).collect();
+                SeriesData {
+                    series_key: format!("机房.rack_{}", i),
+                    compressed_data: compression::format::encode_block_v2(&points),
+                    tags: if i == 0 { BTreeMap::from([("host".to_string(), "a".to_string())]) } else { BTreeMap::new() },
+                    min_timestamp: 1000,
+                    max_timestamp: 1099,
+                    count: 100,
+                    ingest_data: if i % 2 == 0 { compression::format::encode_ingest_times(&ingest) } else { Vec::new() },
+                }
+            })
+            .collect();
+
+        for encoding in [SSTableEncoding::Bincode, SSTableEncoding::Tagged] {
+            for compression in [BlockCompression::None, BlockCompression::Zstd] {
+                let path = temp_dir.path().join(format!("{}_{}.bin", encoding, compression));
+                let mut sstable = SSTable::new(path.clone())?.with_compression(compression).with_encoding(encoding);
+                sstable.write_data(&series_list)?;
+                let data = std::fs::read(&path)?;
+                assert_eq!((sstable_encoding(&data), block_compression(&data)), (encoding, compression));
+                assert_eq!(format_version(&data), SSTABLE_FORMAT_VERSION);
+                assert_eq!(sstable.info().encoding, encoding);
+
+                let mut reopened = SSTable::new(path)?;
+                assert_eq!(reopened.load_series_list()?, series_list);
+                // 改写已有文件时保持原来的序列化方式
+                assert!(reopened.delete_datapoint("机房.rack_1", Some(1000))?);
+                assert_eq!(reopened.encoding(), encoding);
+                assert_eq!(reopened.load_series_list()?.len(), series_list.len());
+            }
+        }
+
+        // 旧版本写入的块缺少后来增加的可选字段，更新的版本写入了不认识的字段
+        let codec = SSTableEncoding::Tagged.codec();
+        let series = &series_list[0];
+        let mut older = Vec::new();
+        TaggedCodec::push_field(&mut older, 1, series.series_key.as_bytes());
+        TaggedCodec::push_field(&mut older, 2, &series.compressed_data);
+        TaggedCodec::push_field(&mut older, 4, &series.min_timestamp.to_le_bytes());
+        TaggedCodec::push_field(&mut older, 5, &series.max_timestamp.to_le_bytes());
+        TaggedCodec::push_field(&mut older, 6, &(series.count as u64).to_le_bytes());
+        let decoded = codec.decode(&older, SSTABLE_FORMAT_VERSION)?;
+        assert_eq!((decoded.tags.len(), decoded.ingest_data.len()), (0, 0));
+        assert_eq!(decoded.compressed_data, series.compressed_data);
+
+        let mut newer = codec.encode(series)?;
+        TaggedCodec::push_field(&mut newer, 200, b"checksum");
+        assert_eq!(&codec.decode(&newer, SSTABLE_FORMAT_VERSION)?, series);
+
+        // 缺少必需字段或字段被截断
+        assert!(codec.decode(&older[..older.len() - 3], SSTABLE_FORMAT_VERSION).is_err());
+        let mut no_count = Vec::new();
+        TaggedCodec::push_field(&mut no_count, 1, b"cpu");
+        assert!(codec.decode(&no_count, SSTABLE_FORMAT_VERSION).is_err());
+
+        // 配置为tagged后刷新和compaction写出的文件都使用tagged，已有的bincode文件照常读取
+        let db_dir = temp_dir.path().join("db");
+        let db = TimeSeriesDB::builder(&db_dir).memtable_threshold(10).build()?;
+        for ts in 0..10u64 {
+            db.insert("mixed".to_string(), DataPoint { timestamp: ts, value: ts as f64, tags: BTreeMap::new() }).await?;
+        }
+        db.close().await?;
+        let db = TimeSeriesDB::builder(&db_dir).memtable_threshold(10).sstable_encoding(SSTableEncoding::Tagged).build()?;
+        for ts in 10..20u64 {
+            db.insert("mixed".to_string(), DataPoint { timestamp: ts, value: ts as f64, tags: BTreeMap::new() }).await?;
+        }
+        let mut encodings: Vec<SSTableEncoding> = db.sstable_infos().iter().map(|info| info.encoding).collect();
+        encodings.sort_by_key(|encoding| encoding.to_string());
+        assert_eq!(encodings, vec![SSTableEncoding::Bincode, SSTableEncoding::Tagged]);
+        assert_eq!(db.query_range("mixed", None, None).await?.len(), 20);
+        let report = db.compact().await?;
+        let output = db_dir.join(&report.output.as_ref().unwrap().file_name);
+        assert_eq!(SSTable::new(output)?.encoding(), SSTableEncoding::Tagged);
+        assert_eq!(db.query_range("mixed", None, None).await?.len(), 20);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_export_import_round_trip() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let source = TimeSeriesDB::new(temp_dir.path().join("source"), 50)?;
+        let tags = BTreeMap::from([("host".to_string(), "web-1".to_string())]);
+        for ts in 0..120u64 {
+            for series in ["cpu", "mem"] {
+                source
+                    .insert(series.to_string(), DataPoint { timestamp: ts, value: (ts * 3) as f64, tags: tags.clone() })
+                    .await?;
+            }
+        }
+        // 一部分数据仍在内存表中，导出前会被刷新
+        source.insert("disk".to_string(), DataPoint { timestamp: 7, value: 1.5, tags: BTreeMap::new() }).await?;
+
+        let (archive, export) = source.export_archive(Vec::new()).await?;
+        assert_eq!(export.series_count, 3);
+        assert_eq!((export.min_timestamp, export.max_timestamp), (Some(0), Some(119)));
+        assert!(!export.files.is_empty());
+        // 导出的快照目录已清理
+        assert!(std::fs::read_dir(temp_dir.path().join("source"))?
+            .flatten()
+            .all(|entry| !entry.file_name().to_string_lossy().starts_with("export_")));
+
+        let target = TimeSeriesDB::new(temp_dir.path().join("target"), 50)?;
+        let report = target.import_archive(std::io::Cursor::new(archive.clone()), false).await?;
+        assert_eq!(report.files_imported, export.files.len());
+        assert_eq!(report.series_count, 3);
+
+        assert_eq!(target.get_all_series().await?, source.get_all_series().await?);
+        for series in ["cpu", "mem", "disk"] {
+            let expected = source.query_range(series, None, None).await?;
+            let actual = target.query_range(series, None, None).await?;
+            assert_eq!(
+                actual.iter().map(|dp| (dp.timestamp, dp.value)).collect::<Vec<_>>(),
+                expected.iter().map(|dp| (dp.timestamp, dp.value)).collect::<Vec<_>>()
+            );
+            assert_eq!(target.series_tags(series), source.series_tags(series));
+            assert_eq!(target.last_timestamp(series), source.last_timestamp(series));
+        }
+
+        // 已有数据时拒绝导入，force 覆盖
+        let err = target.import_archive(std::io::Cursor::new(archive.clone()), false).await.unwrap_err();
+        assert!(matches!(DbError::from_io(&err), Some(DbError::DatabaseNotEmpty { series_count: 3, .. })));
+        target.insert("extra".to_string(), DataPoint { timestamp: 1, value: 1.0, tags: BTreeMap::new() }).await?;
+        target.import_archive(std::io::Cursor::new(archive.clone()), true).await?;
+        assert_eq!(target.get_all_series().await?, vec!["cpu", "disk", "mem"]);
+
+        // 损坏的归档在校验时被拒绝，数据不变
+        let empty = TimeSeriesDB::new(temp_dir.path().join("empty"), 50)?;
+        let mut corrupted = archive;
+        corrupted[600] ^= 0xff;
+        let err = empty.import_archive(std::io::Cursor::new(corrupted.clone()), false).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert!(empty.get_all_series().await?.is_empty());
+        // 覆盖导入损坏的归档时，旧数据在校验失败后仍然保留
+        let err = target.import_archive(std::io::Cursor::new(corrupted), true).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(target.get_all_series().await?, vec!["cpu", "disk", "mem"]);
+        assert_eq!(target.query_range("cpu", None, None).await?.len(), 120);
+        assert!(std::fs::read_dir(temp_dir.path().join("target"))?
+            .flatten()
+            .all(|entry| !entry.file_name().to_string_lossy().starts_with("import_")));
+
+        // 重启后导入的数据仍然完整
+        source.close().await?;
+        drop(target);
+        let reopened = TimeSeriesDB::new(temp_dir.path().join("target"), 50)?;
+        assert_eq!(reopened.query_range("cpu", None, None).await?.len(), 120);
+        assert_eq!(reopened.series_tags("cpu"), Some(tags));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_series_point_count_across_flush() -> anyhow::Result<()> {
+        let server = testing::spawn_test_server_with(|b| b.memtable_threshold(5)).await?;
+        let db = &server.db_handle;
+        assert_eq!(db.series_point_count("cpu"), 0);
+
+        for ts in 0..12u64 {
+            let request = serde_json::json!({ "series_key": "cpu", "timestamp": ts, "value": ts as f64 });
+            let response = server.post_json("/api/v1/datapoints?return_count=true", &request).await?;
+            assert_eq!(response.status, axum::http::StatusCode::OK);
+            // 每写入5个点刷新一次，计数在刷新前后保持连续
+            assert_eq!(response.json()?["point_count"], ts + 1);
+            db.insert("mem".to_string(), DataPoint { timestamp: ts, value: 1.0, tags: BTreeMap::new() }).await?;
+        }
+        assert!(db.get_stats().await?.sstable_count > 0);
+
+        assert!(db.delete("cpu", Some(3), false).await?);
+        assert_eq!(db.series_point_count("cpu"), 11);
+        db.compact().await?;
+        assert_eq!(db.series_point_count("cpu"), 11);
+        assert_eq!(db.series_point_count("mem"), 12);
+        server.stop().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sstable_metadata_listing() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let db = TimeSeriesDB::new(temp_dir.path(), 10)?;
+        for ts in 0..30u64 {
+            let series = if ts % 3 == 0 { "mem" } else { "cpu" };
+            db.insert(series.to_string(), DataPoint { timestamp: 100 + ts, value: ts as f64, tags: BTreeMap::new() }).await?;
+        }
+
+        let infos = db.sstable_infos();
+        assert_eq!(infos.len(), 3);
+        assert_eq!(infos.iter().map(|info| info.total_points).sum::<usize>(), 30);
+        assert!(infos.windows(2).all(|pair| pair[0].sequence < pair[1].sequence));
+        let first = &infos[0];
+        assert_eq!((first.series_count, first.min_timestamp, first.max_timestamp), (2, Some(100), Some(109)));
+        assert_eq!(first.size_bytes, std::fs::metadata(temp_dir.path().join(&first.file_name))?.len());
+        assert!(first.created_at.is_some() && first.read_error.is_none());
+
+        let detail = db.sstable_detail(&first.file_name)?.unwrap();
+        assert_eq!(detail.info, *first);
+        assert_eq!(
+            detail.series.iter().map(|series| (series.series_key.as_str(), series.points)).collect::<Vec<_>>(),
+            vec![("cpu", 6), ("mem", 4)]
+        );
+        assert!(db.sstable_detail("missing.data")?.is_none());
+
+        // 删除重写文件后摘要随之更新
+        assert!(db.delete("mem", Some(100), false).await?);
+        let after_delete = db.sstable_infos();
+        assert_eq!(after_delete[0].total_points, 9);
+        assert_eq!(after_delete[0].size_bytes, std::fs::metadata(temp_dir.path().join(&first.file_name))?.len());
+
+        // compaction后只剩输出文件
+        db.compact().await?;
+        let compacted = db.sstable_infos();
+        assert_eq!(compacted.len(), 1);
+        assert_eq!((compacted[0].total_points, compacted[0].series_count), (29, 2));
+
+        // 重启后从文件重新建立相同的摘要，写入时间从manifest恢复，不受文件修改时间影响
+        db.close().await?;
+        std::fs::File::options()
+            .write(true)
+            .open(temp_dir.path().join(&compacted[0].file_name))?
+            .set_modified(std::time::UNIX_EPOCH + std::time::Duration::from_secs(1000))?;
+        let reopened = TimeSeriesDB::new(temp_dir.path(), 10)?;
+        assert_eq!(reopened.sstable_infos(), compacted);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_relative_range() -> anyhow::Result<()> {
+        const NOW: u64 = 1_700_000_000;
+        let clock = std::sync::Arc::new(MockClock::from_secs(NOW));
+        let server = testing::spawn_test_server_with(|b| b.memtable_threshold(4).clock(clock.clone())).await?;
+        // 每10分钟一个点，覆盖最近3小时
+        for i in 0..=18u64 {
+            server.db_handle.insert("load".to_string(), DataPoint { timestamp: NOW - i * 600, value: i as f64, tags: BTreeMap::new() }).await?;
+        }
+
+        let query = |params: &str| {
+            let (server, path) = (&server, format!("/api/v1/series/load/datapoints?{}", params));
+            async move { server.get(&path).await }
+        };
+        let timestamps = |response: testing::TestResponse| -> anyhow::Result<Vec<u64>> {
+            let body = response.json()?;
+            Ok(body["data"].as_array().unwrap().iter().map(|dp| dp["timestamp"].as_u64().unwrap()).collect())
+        };
+
+        let last_hour = timestamps(query("range=1h").await?)?;
+        assert_eq!(last_hour, (0..=6u64).rev().map(|i| NOW - i * 600).collect::<Vec<_>>());
+        let last_30m = timestamps(query("range=30m").await?)?;
+        assert_eq!(last_30m.len(), 4);
+
+        // 范围随服务端时间推进
+        clock.advance(std::time::Duration::from_secs(1800));
+        let last_hour = timestamps(query("range=1h").await?)?;
+        assert_eq!(last_hour.first(), Some(&(NOW - 1800)));
+        assert_eq!(last_hour.len(), 4);
+
+        for params in ["range=1h&start_time=0", "range=1h&end_time=0", "range=abc", "range=10y", "range=0h"] {
+            let response = query(params).await?;
+            assert_eq!(response.status, axum::http::StatusCode::BAD_REQUEST, "{}", params);
+            assert_eq!(response.json()?["success"], false);
+        }
+        assert_eq!(parse_relative_duration("7d"), Ok(std::time::Duration::from_secs(7 * 86400)));
+        assert_eq!(parse_relative_duration("2w"), Ok(std::time::Duration::from_secs(14 * 86400)));
+        server.stop().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_update_batch_rewrites_each_file_once() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let db = TimeSeriesDB::new(temp_dir.path(), 100)?;
+        // 三个SSTable: cpu 在前两个文件中，mem 只在第三个文件中，另有一部分 cpu 在内存表中
+        for ts in 0..200u64 {
+            db.insert("cpu".to_string(), DataPoint { timestamp: ts, value: 0.0, tags: BTreeMap::new() }).await?;
+        }
+        for ts in 0..100u64 {
+            db.insert("mem".to_string(), DataPoint { timestamp: ts, value: 0.0, tags: BTreeMap::new() }).await?;
+        }
+        for ts in 200..250u64 {
+            db.insert("cpu".to_string(), DataPoint { timestamp: ts, value: 0.0, tags: BTreeMap::new() }).await?;
+        }
+        assert_eq!(db.sstable_infos().len(), 3);
+        let untouched = db.sstable_infos()[2].clone();
+
+        let mut updates: Vec<(String, u64, f64)> = (0..250u64).map(|ts| ("cpu".to_string(), ts, ts as f64)).collect();
+        updates.push(("cpu".to_string(), 7, -1.0));
+        updates.push(("cpu".to_string(), 9999, 1.0));
+        updates.push(("missing".to_string(), 1, 1.0));
+        let scans_before = db.get_stats().await?.sstable_files_visited;
+        let report = db.update_batch(updates).await?;
+        assert_eq!(
+            report,
+            BatchUpdateReport { total: 253, updated: 250, not_found: 2, files_rewritten: 2 }
+        );
+        // 通过位置索引只打开包含 cpu 的两个文件
+        assert_eq!(db.get_stats().await?.sstable_files_visited - scans_before, 2);
+        assert_eq!(db.sstable_infos()[2], untouched);
+
+        let points = db.query_range("cpu", None, None).await?;
+        assert_eq!(points.len(), 250);
+        assert!(points.iter().all(|dp| dp.value == if dp.timestamp == 7 { -1.0 } else { dp.timestamp as f64 }));
+        assert!(db.query_range("mem", None, None).await?.iter().all(|dp| dp.value == 0.0));
+
+        // 单点更新沿用同一实现
+        assert!(db.update("mem", 5, 2.5).await?);
+        assert!(!db.update("mem", 500, 2.5).await?);
+        assert_eq!(db.query_range("mem", Some(5), Some(5)).await?[0].value, 2.5);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_mapping_cap_evicts_and_queries_stay_correct() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let db = TimeSeriesDB::builder(temp_dir.path())
+            .memtable_threshold(10)
+            .max_mapped_sstables(2)
+            .build()?;
+        for ts in 0..100u64 {
+            let series = format!("s{}", ts % 4);
+            db.insert(series, DataPoint { timestamp: 1000 + ts, value: ts as f64, tags: BTreeMap::new() }).await?;
+        }
+        assert_eq!(db.get_stats().await?.sstable_count, 10);
+
+        // 每个系列分布在全部10个文件中，映射上限为2时查询结果仍然完整
+        for round in 0..2 {
+            for i in 0..4u64 {
+                let points = db.query_range(&format!("s{}", i), None, None).await?;
+                assert_eq!(points.len(), 25, "round {} series s{}", round, i);
+                assert!(points.iter().enumerate().all(|(n, dp)| dp.timestamp == 1000 + i + 4 * n as u64));
+            }
+        }
+
+        let mappings = db.get_stats().await?.mappings;
+        assert_eq!(mappings.capacity, 2);
+        assert!(mappings.open <= 2);
+        assert!(mappings.evictions > 0);
+        assert!(mappings.maps > 10);
+
+        // 重写的文件丢弃旧映射后读到新内容
+        assert!(db.delete("s0", Some(1000), false).await?);
+        assert_eq!(db.query_range("s0", None, None).await?.len(), 24);
+        assert!(db.get_stats().await?.mappings.open <= 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_upload_precompressed_block() -> anyhow::Result<()> {
+        let server = testing::spawn_test_server_with(|b| b.memtable_threshold(1000)).await?;
+        let db = &server.db_handle;
+        let points: Vec<(u64, f64)> = (0..500u64).map(|i| (1_000 + i * 10, (i % 7) as f64 * 1.5)).collect();
+        let block = EncodedBlock::encode(&points);
+
+        let headers_for = |block: &EncodedBlock| -> Vec<(&'static str, String)> {
+            vec![
+                ("x-block-count", block.count.to_string()),
+                ("x-block-min-timestamp", block.min_timestamp.to_string()),
+                ("x-block-max-timestamp", block.max_timestamp.to_string()),
+                ("x-block-tags", "{\"host\":\"edge-1\"}".to_string()),
+            ]
+        };
+        let upload = |headers: Vec<(&'static str, String)>, data: Vec<u8>| {
+            let server = &server;
+            async move {
+                let headers: Vec<(&str, &str)> = headers.iter().map(|(name, value)| (*name, value.as_str())).collect();
+                server.request("POST", "/api/v1/series/edge/blocks", &headers, &data).await
+            }
+        };
+
+        // 校验失败的上传全部拒绝，不写入任何文件
+        let mut wrong_count = block.clone();
+        wrong_count.count += 1;
+        let mut wrong_range = block.clone();
+        wrong_range.max_timestamp += 1;
+        let truncated = block.data[..block.data.len() / 2].to_vec();
+        let unordered = EncodedBlock::encode(&[(20, 1.0), (10, 2.0)]);
+        let mut unknown_version = block.data.clone();
+        unknown_version[0] = 9;
+        for (headers, data) in [
+            (headers_for(&wrong_count), block.data.clone()),
+            (headers_for(&wrong_range), block.data.clone()),
+            (headers_for(&block), truncated),
+            (headers_for(&block), unknown_version),
+            (headers_for(&unordered), unordered.data.clone()),
+            (Vec::new(), block.data.clone()),
+        ] {
+            let response = upload(headers, data).await?;
+            assert_eq!(response.status, axum::http::StatusCode::BAD_REQUEST);
+            assert_eq!(response.json()?["success"], false);
+        }
+        assert_eq!(db.get_stats().await?.sstable_count, 0);
+        assert!(db.query_range("edge", None, None).await?.is_empty());
+
+        let response = upload(headers_for(&block), block.data.clone()).await?;
+        assert_eq!(response.status, axum::http::StatusCode::CREATED);
+        let report = response.json()?["data"].clone();
+        assert_eq!(
+            (report["points"].as_u64(), report["min_timestamp"].as_u64(), report["max_timestamp"].as_u64()),
+            (Some(500), Some(1_000), Some(5_990))
+        );
+
+        // 块原样写入新的SSTable，查询结果与原始数据一致
+        let stats = db.get_stats().await?;
+        assert_eq!((stats.sstable_count, stats.memtable_size), (1, 0));
+        let file_name = report["sstable"].as_str().unwrap_or_default();
+        let blocks = SSTable::new(server.data_dir().join(file_name))?.load_series_list()?;
+        assert_eq!(blocks[0].compressed_data, block.data);
+        let stored = db.query_range("edge", None, None).await?;
+        assert_eq!(stored.iter().map(|dp| (dp.timestamp, dp.value)).collect::<Vec<_>>(), points);
+        assert_eq!(db.series_point_count("edge"), 500);
+        server.stop().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_derivative_of_linear_ramp() -> anyhow::Result<()> {
+        let server = testing::spawn_test_server_with(|b| b.memtable_threshold(20)).await?;
+        let db = &server.db_handle;
+        // 一部分点已刷新到SSTable，其余在内存表中
+        for i in 0..30u64 {
+            db.insert("ramp".to_string(), DataPoint { timestamp: 100 + i * 10, value: 5.0 + i as f64 * 25.0, tags: BTreeMap::new() }).await?;
+        }
+
+        let rates = db.derivative("ramp", None, None).await?;
+        assert_eq!(rates.len(), 29);
+        assert_eq!(rates[0].0, 110);
+        assert!(rates.iter().all(|&(_, rate)| (rate - 2.5).abs() < 1e-12));
+
+        // 范围内的第一个点不产生结果，单点范围为空
+        assert_eq!(db.derivative("ramp", Some(200), Some(250)).await?.len(), 5);
+        assert!(db.derivative("ramp", Some(200), Some(200)).await?.is_empty());
+        assert!(db.derivative("missing", None, None).await?.is_empty());
+
+        let response = server.get("/api/v1/series/ramp/derivative?start=280&end=300").await?;
+        assert_eq!(response.status, axum::http::StatusCode::OK);
+        let body = response.json()?;
+        let points: Vec<(u64, f64)> = body["data"]["points"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|p| (p["timestamp"].as_u64().unwrap(), p["rate"].as_f64().unwrap()))
+            .collect();
+        assert_eq!(points, vec![(290, 2.5), (300, 2.5)]);
+        server.stop().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_block_format_v2_golden_bytes() -> anyhow::Result<()> {
+        use compression::format::{block_decoder, decode_block, decode_versioned, encode_block_v2, BLOCK_FORMAT_V2};
+
+        // 固定输入的编码结果。v2 已发布，这两段字节不能改变；编码变化时必须使用新的版本号
+        let regular = [(1609459200, 23.5), (1609459260, 23.6), (1609459320, 23.6), (1609459385, 24.0)];
+        let regular_v2: &[u8] = &[
+            2, 0, 0, 0, 0, 95, 238, 102, 0, 64, 55, 128, 0, 0, 0, 0, 0, 128, 60, 167, 102, 102, 102, 102, 102, 102,
+            144, 89, 153, 252, 204, 204, 204, 204, 204, 223, 240,
+        ];
+        // 首个delta超出14位、delta-of-delta超出12位的转义路径
+        let wide = [(0, 0.0), (100000, -1.5), (100001, -1.5), (300001, 1e300)];
+        let wide_v2: &[u8] = &[
+            2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 192, 0, 0, 0, 0, 0, 195, 80, 32, 27, 127, 254, 0, 63,
+            255, 255, 255, 255, 255, 158, 88, 92, 0, 0, 0, 0, 0, 0, 1, 134, 159, 192, 251, 7, 63, 144, 242, 32, 1,
+            214, 127, 240,
+        ];
+
+        for (points, golden) in [(&regular[..], regular_v2), (&wide[..], wide_v2)] {
+            assert_eq!(encode_block_v2(points), golden);
+            assert_eq!(golden[0], BLOCK_FORMAT_V2);
+            assert_eq!(decode_versioned(golden)?, points);
+            assert_eq!(decode_block(BLOCK_FORMAT_V2, &golden[1..])?, points);
+            assert_eq!(block_decoder(golden.to_vec())?.collect::<Vec<_>>(), points);
+        }
+
+        // 未知版本和空块直接报错，不尝试解码
+        assert!(decode_block(3, &regular_v2[1..]).is_err());
+        assert!(block_decoder(vec![3, 0, 0]).is_err());
+        assert!(decode_versioned(&[]).is_err());
+
+        // 刷新写出的块与直接编码的字节相同
+        let temp_dir = TempDir::new()?;
+        let db = TimeSeriesDB::new(temp_dir.path(), 4)?;
+        for &(timestamp, value) in &regular {
+            db.insert("golden".to_string(), DataPoint { timestamp, value, tags: BTreeMap::new() }).await?;
+        }
+        let file_name = db.sstable_infos()[0].file_name.clone();
+        let blocks = SSTable::new(temp_dir.path().join(file_name))?.load_series_list()?;
+        assert_eq!(blocks[0].compressed_data, regular_v2);
+
+        // 块带未知版本的文件在读取元数据时就被拒绝
+        let mut future = blocks[0].clone();
+        future.compressed_data[0] = 9;
+        let future_path = temp_dir.path().join("future.bin");
+        SSTable::new(future_path.clone())?.write_data(&[future])?;
+        let err = SSTable::new(future_path)?.load_series_list().unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_pre_escape_blocks() -> anyhow::Result<()> {
+        use compression::format::{decode_block, BLOCK_FORMAT_V1, BLOCK_FORMAT_V2};
+
+        // 按最初的编码手工写出位流：第二个delta-of-delta为 -2048，写作 '11' + 0x800
+        let points = vec![(1_000_000u64, 1.0f64), (1_003_000, 1.0), (1_003_952, 1.0), (1_004_904, 1.0)];
+        let mut writer = GorillaBitWriter::new();
+        writer.write_bits(points[0].0, 64);
+        writer.write_bits(points[0].1.to_bits(), 64);
+        writer.write_bits(0b10, 2);
+        writer.write_bits(3000, 14);
+        writer.write_bits(0b0, 1);
+        writer.write_bits(0b11, 2);
+        writer.write_bits(0x800, 12);
+        writer.write_bits(0b0, 1);
+        writer.write_bits(0b0, 1);
+        writer.write_bits(0b0, 1);
+        writer.write_bits(0b11111111, 8);
+        let stream = writer.get_bytes().to_vec();
+
+        // 同一段位流按 v2 解码时 0x800 是转义，无法得到原来的时间戳
+        assert_eq!(decode_block(BLOCK_FORMAT_V1, &stream)?, points);
+        assert_ne!(decode_block(BLOCK_FORMAT_V2, &stream).ok(), Some(points.clone()));
+
+        // 无文件头的旧文件中的块按 v1 解码
+        let temp_dir = TempDir::new()?;
+        let path = temp_dir.path().join("sstable_legacy.data");
+        let legacy = ("cpu".to_string(), stream.clone(), BTreeMap::<String, String>::new(), 1_000_000u64, 1_004_904u64, 4usize);
+        std::fs::write(&path, bincode::serialize(&vec![legacy])?)?;
+        let blocks = SSTable::new(path.clone())?.load_series_list()?;
+        assert_eq!(blocks[0].compressed_data[0], BLOCK_FORMAT_V1);
+        assert_eq!(blocks[0].compressed_data[1..], stream[..]);
+
+        let timestamps = |points: Vec<DataPoint>| points.iter().map(|dp| dp.timestamp).collect::<Vec<_>>();
+        let expected: Vec<u64> = points.iter().map(|(ts, _)| *ts).collect();
+        let db = TimeSeriesDB::new(temp_dir.path(), 100)?;
+        assert_eq!(timestamps(db.query_range("cpu", None, None).await?), expected);
+
+        // 改写后按当前编码写回，重新打开后读出相同的数据
+        assert!(db.update("cpu", 1_003_952, 2.0).await?);
+        let blocks = SSTable::new(path.clone())?.load_series_list()?;
+        assert_eq!(blocks[0].compressed_data[0], BLOCK_FORMAT_V2);
+        db.close().await?;
+        let db = TimeSeriesDB::new(temp_dir.path(), 100)?;
+        let reopened = db.query_range("cpu", None, None).await?;
+        assert_eq!(timestamps(reopened.clone()), expected);
+        assert_eq!(reopened.iter().map(|dp| dp.value).collect::<Vec<_>>(), vec![1.0, 1.0, 2.0, 1.0]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unwrapped_query_response() -> anyhow::Result<()> {
+        let server = testing::spawn_test_server_with(|builder| builder.memtable_threshold(100)).await?;
+        for ts in 0..3u64 {
+            server.db_handle.insert("cpu".to_string(), DataPoint { timestamp: 100 + ts, value: ts as f64, tags: BTreeMap::new() }).await?;
+        }
+        async fn get(server: &testing::TestServer, uri: &str) -> anyhow::Result<(axum::http::StatusCode, serde_json::Value)> {
+            let response = server.get(uri).await?;
+            Ok((response.status, response.json().unwrap_or_default()))
+        }
+
+        let (status, body) = get(&server, "/api/v1/series/cpu/datapoints?envelope=false").await?;
+        assert_eq!(status, axum::http::StatusCode::OK);
+        let points = body.as_array().expect("应为不带外层结构的数组");
+        assert_eq!(points.len(), 3);
+        assert_eq!(points[0]["timestamp"], 100);
+
+        // 默认仍带外层结构
+        let (_, body) = get(&server, "/api/v1/series/cpu/datapoints").await?;
+        assert_eq!(body["success"], true);
+        assert_eq!(body["data"].as_array().unwrap().len(), 3);
+
+        // 失败时保留错误结构和状态码，原本以200返回的失败改为400
+        let (status, body) = get(&server, "/api/v1/series/cpu/datapoints?limit=0&envelope=false").await?;
+        assert_eq!(status, axum::http::StatusCode::BAD_REQUEST);
+        assert_eq!(body["success"], false);
+        let (status, body) = get(&server, "/api/v1/series/cpu/gaps?expected_interval=0&envelope=false").await?;
+        assert_eq!(status, axum::http::StatusCode::BAD_REQUEST);
+        assert_eq!(body["success"], false);
+        assert!(body["message"].as_str().unwrap().contains("expected_interval"));
+        let (status, _) = get(&server, "/api/v1/series/cpu/datapoints?envelope=maybe").await?;
+        assert_eq!(status, axum::http::StatusCode::BAD_REQUEST);
+        server.stop().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_series_keys_cannot_escape_data_dir() -> anyhow::Result<()> {
+        let root = TempDir::new()?;
+        let data_dir = root.path().join("data");
+        let keys = ["../evil", "/etc/evil", "..", ".", "a\0b", "dir\\..\\evil", "cpu.usage%", ""];
+
+        // 编码只含安全字符，可以还原，且路径总是数据目录的直接子项
+        for key in keys {
+            let stem = encode_series_file_stem(key);
+            assert!(stem.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_' || b == b'%'), "{:?}", stem);
+            assert_eq!(decode_series_file_stem(&stem).as_deref(), Some(key));
+            assert_eq!(series_file_path(&data_dir, key, "data").parent(), Some(data_dir.as_path()));
+        }
+        assert_eq!(encode_series_file_stem("cpu_usage-1"), "cpu_usage-1");
+        assert_eq!(encode_series_file_stem("../evil"), "%2E%2E%2Fevil");
+        assert!(decode_series_file_stem("%2e%2E%2Fevil").is_none());
+        assert!(decode_series_file_stem("evil.data").is_none());
+
+        // 过长的键使用前缀加哈希，长度受限且不同的键不会得到相同的文件名
+        let long_a = "/".repeat(300);
+        let long_b = format!("{}x", long_a);
+        let (stem_a, stem_b) = (encode_series_file_stem(&long_a), encode_series_file_stem(&long_b));
+        assert!(stem_a.len() <= MAX_FILE_STEM_BYTES && stem_b.len() <= MAX_FILE_STEM_BYTES);
+        assert_ne!(stem_a, stem_b);
+        assert!(decode_series_file_stem(&stem_a).is_none());
+
+        // 写入、刷新、compaction和导出都不会在数据目录之外产生文件
+        let db = TimeSeriesDB::new(&data_dir, 2)?;
+        for (i, key) in keys.iter().enumerate() {
+            for ts in 0..3u64 {
+                db.insert(key.to_string(), DataPoint { timestamp: ts, value: i as f64, tags: BTreeMap::new() }).await?;
+            }
+        }
+        db.compact().await?;
+        let (archive, _) = db.export_archive(Vec::new()).await?;
+        assert!(!archive.is_empty());
+        assert_eq!(db.query_range("../evil", None, None).await?.len(), 3);
+        db.close().await?;
+
+        let entries: Vec<_> = std::fs::read_dir(root.path())?.map(|entry| entry.map(|e| e.file_name())).collect::<Result<_, _>>()?;
+        assert_eq!(entries, vec![std::ffi::OsString::from("data")]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_quota_reject_and_evict() -> anyhow::Result<()> {
+        let server = testing::spawn_test_server_with(|b| b.memtable_threshold(4)).await?;
+        let db = &server.db_handle;
+        db.set_quota_policy(QuotaPolicy {
+            rules: vec![
+                QuotaRule { prefix: "tenant_a.".to_string(), max_points: Some(6), max_bytes: None, action: QuotaAction::Reject },
+                QuotaRule { prefix: "debug.".to_string(), max_points: Some(5), max_bytes: None, action: QuotaAction::Evict },
+            ],
+        })
+        .await?;
+
+        let point = |timestamp: u64| DataPoint { timestamp, value: timestamp as f64, tags: BTreeMap::new() };
+        for ts in 1..=6 {
+            let series = if ts % 2 == 0 { "tenant_a.cpu" } else { "tenant_a.mem" };
+            db.insert(series.to_string(), point(ts)).await?;
+        }
+        // 第7个点超出配额被拒绝，其他前缀不受影响
+        let err = db.insert("tenant_a.cpu".to_string(), point(7)).await.unwrap_err();
+        assert!(matches!(DbError::from_io(&err), Some(DbError::QuotaExceeded { prefix, .. }) if prefix == "tenant_a."));
+        db.insert("tenant_b.cpu".to_string(), point(7)).await?;
+
+        let report = db.quota_report();
+        assert_eq!(report.quotas[0].usage.points, 6);
+        assert!(!report.quotas[0].exceeded);
+        // 已刷新的点计入字节数
+        assert!(report.quotas[0].usage.bytes > 0);
+
+        // 删除后腾出的配额在下次刷新时生效
+        db.delete_range("tenant_a.cpu", None, None, false).await?;
+        assert!(db.quota_report().stale);
+        db.insert("debug.trace".to_string(), point(100)).await?;
+        db.insert("debug.trace".to_string(), point(101)).await?;
+        assert!(!db.quota_report().stale);
+        assert_eq!(db.quota_report().quotas[0].usage.points, 3);
+        db.insert("tenant_a.cpu".to_string(), point(8)).await?;
+
+        // evict 规则在刷新后删除该前缀最旧的点
+        for ts in 102..=109 {
+            db.insert("debug.trace".to_string(), point(ts)).await?;
+        }
+        db.insert("debug.log".to_string(), point(110)).await?;
+        let debug: Vec<u64> = db.query_range("debug.trace", None, None).await?.iter().map(|dp| dp.timestamp).collect();
+        let remaining = debug.len() + db.query_range("debug.log", None, None).await?.len();
+        // 最后一次刷新后淘汰到上限的5个点，之后写入的 109 和 debug.log 的 110 还在内存表中，下次刷新时再淘汰
+        assert_eq!(debug, (104..=109).collect::<Vec<_>>());
+        assert_eq!(remaining, 5 + 2);
+        assert_eq!(db.quota_report().quotas[1].usage.points, remaining as u64);
+
+        // 通过HTTP重新设置相同的配额，超出配额的写入返回429
+        let response = server.put_json("/api/v1/admin/quotas", &serde_json::to_value(db.quota_policy())?).await?;
+        assert_eq!(response.status, axum::http::StatusCode::OK);
+        db.insert("tenant_a.cpu".to_string(), point(9)).await?;
+        db.insert("tenant_a.cpu".to_string(), point(10)).await?;
+        let response = server.post_json("/api/v1/datapoints", &serde_json::json!({ "series_key": "tenant_a.cpu", "timestamp": 1, "value": 1.0 })).await?;
+        assert_eq!(response.status, axum::http::StatusCode::TOO_MANY_REQUESTS);
+
+        // 配额持久化在manifest中，重新打开后按SSTable重新统计用量
+        let before = db.quota_report();
+        let temp_dir = server.stop_keeping_data().await?;
+        let db = TimeSeriesDB::new(temp_dir.path(), 4)?;
+        let after = db.quota_report();
+        assert_eq!(after.quotas.len(), 2);
+        assert_eq!(after.quotas[0].usage.points, before.quotas[0].usage.points);
+        assert_eq!(after.quotas[0].usage.points, 6);
+        let err = db.insert("tenant_a.cpu".to_string(), point(11)).await.unwrap_err();
+        assert!(matches!(DbError::from_io(&err), Some(DbError::QuotaExceeded { .. })));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_streaming_aggregation_of_million_points() -> anyhow::Result<()> {
+        const POINTS: u64 = 1_000_000;
+
+        // 累加器不保留原始值，结果与 AggregateFn::apply 一致
+        for function in [AggregateFn::Avg, AggregateFn::Sum, AggregateFn::Min, AggregateFn::Max, AggregateFn::Count, AggregateFn::First, AggregateFn::Last] {
+            let mut accumulator = BucketAccumulator::new(function);
+            for i in 0..POINTS {
+                accumulator.push((i % 1000) as f64);
+            }
+            assert_eq!(accumulator.buffered(), 0);
+            assert_eq!(accumulator.count(), POINTS as usize);
+
+            let values = [3.5, -1.0, 7.25, 0.0];
+            let mut small = BucketAccumulator::new(function);
+            values.iter().for_each(|v| small.push(*v));
+            assert_eq!(small.finish(), function.apply(&values));
+        }
+        assert_eq!(BucketAccumulator::new(AggregateFn::Avg).finish(), None);
+
+        let temp_dir = TempDir::new()?;
+        let db = TimeSeriesDB::new(temp_dir.path(), 1000)?;
+        let points: Vec<(u64, f64)> = (0..POINTS).map(|i| (i, (i % 1000) as f64)).collect();
+        for chunk in points.chunks(MAX_POINTS_PER_BLOCK) {
+            db.ingest_block("big".to_string(), EncodedBlock::encode(chunk), BTreeMap::new()).await?;
+        }
+        drop(points);
+
+        assert_eq!(db.aggregate_range("big", None, None, AggregateFn::Count).await?, Some(POINTS as f64));
+        assert_eq!(db.aggregate_range("big", None, None, AggregateFn::Avg).await?, Some(499.5));
+        assert_eq!(db.aggregate_range("big", None, None, AggregateFn::Max).await?, Some(999.0));
+        assert_eq!(db.aggregate_range("big", Some(10), Some(19), AggregateFn::Sum).await?, Some(145.0));
+        assert_eq!(db.aggregate_range("big", Some(POINTS), None, AggregateFn::Sum).await?, None);
+
+        // 降采样和跨系列聚合同样逐桶累计
+        let buckets = db.downsample("big", None, None, 250_000, AggregateFn::Avg, FillPolicy::None).await?;
+        assert_eq!(buckets.len(), 4);
+        assert!(buckets.iter().all(|bucket| bucket.count == 250_000 && bucket.value == Some(499.5)));
+        let (series, buckets) = db.aggregate_across(&BTreeMap::new(), None, None, 500_000, AggregateFn::Min).await?;
+        assert_eq!(series, vec!["big".to_string()]);
+        assert_eq!(buckets.len(), 2);
+        assert!(buckets.iter().all(|bucket| bucket.value == 0.0 && bucket.series_count == 1));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_testing_harness_round_trip() -> anyhow::Result<()> {
+        let server = testing::spawn_test_server().await?;
+        assert!(server.base_url.starts_with("http://127.0.0.1:"));
+
+        let sensor = &testing::default_sensors()[0];
+        let points = sensor.datapoints(1_700_000_000, 5);
+        let values = |points: &[DataPoint]| points.iter().map(|dp| (dp.timestamp, dp.value)).collect::<Vec<_>>();
+        assert_eq!(values(&points), values(&sensor.datapoints(1_700_000_000, 5)));
+        for dp in &points {
+            let response = server
+                .post_json(
+                    "/api/v1/datapoints",
+                    &serde_json::json!({ "series_key": sensor.series_key(), "timestamp": dp.timestamp, "value": dp.value, "tags": dp.tags }),
+                )
+                .await?;
+            assert_eq!(response.status, axum::http::StatusCode::OK, "{}", response.text());
+        }
+
+        // HTTP写入的数据可以直接从引擎读到，反之亦然
+        let stored = server.db_handle.query_range(&sensor.series_key(), None, None).await?;
+        assert_eq!(values(&stored), values(&points));
+        let series = testing::deterministic_series(7, 0, 10, 20);
+        assert_eq!(values(&series), values(&testing::deterministic_series(7, 0, 10, 20)));
+        assert_ne!(values(&series), values(&testing::deterministic_series(8, 0, 10, 20)));
+        for dp in series {
+            server.db_handle.insert("walk".to_string(), dp).await?;
+        }
+        let body = server.get("/api/v1/series/walk/datapoints?envelope=false").await?.json()?;
+        assert_eq!(body.as_array().unwrap().len(), 20);
+
+        let export = server.get("/api/v1/admin/export").await?;
+        assert_eq!(export.status, axum::http::StatusCode::OK);
+        assert_eq!(export.header("content-length"), Some(export.body.len().to_string().as_str()));
+        assert_eq!(export.body.len() % 512, 0);
+
+        server.stop().await?;
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_cancel_running_compaction() -> anyhow::Result<()> {
+        use std::sync::Mutex;
+
+        // 第一次compaction开始时阻塞，直到测试放行
+        #[derive(Debug)]
+        struct Gate {
+            release: Mutex<Option<std::sync::mpsc::Receiver<()>>>,
+        }
+
+        impl EngineObserver for Gate {
+            fn on_compaction_started(&self, _options: &CompactionOptions) {
+                let release = self.release.lock().unwrap().take();
+                if let Some(release) = release {
+                    let _ = release.recv();
+                }
+            }
+        }
+
+        let (release, receiver) = std::sync::mpsc::channel();
+        let gate = std::sync::Arc::new(Gate { release: Mutex::new(Some(receiver)) });
+        let server = testing::spawn_test_server_with(|builder| builder.memtable_threshold(4).with_observer(gate)).await?;
+        let db = server.db_handle.clone();
+        for ts in 0..12u64 {
+            db.insert("ops".to_string(), DataPoint { timestamp: ts, value: ts as f64, tags: BTreeMap::new() }).await?;
+        }
+        let files_before: Vec<String> = db.sstable_infos().into_iter().map(|info| info.file_name).collect();
+        assert_eq!(files_before.len(), 3);
+
+        let compaction = tokio::spawn({
+            let db = db.clone();
+            async move { db.compact().await }
+        });
+        let operation = loop {
+            let body = server.get("/api/v1/admin/operations").await?.json()?;
+            if let Some(operation) = body["data"].as_array().and_then(|ops| ops.first()).cloned() {
+                break operation;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        };
+        assert_eq!(operation["kind"], "compact");
+        assert_eq!(operation["cancel_requested"], false);
+        let id = operation["id"].as_u64().unwrap();
+        // 校验与compaction共用维护许可
+        assert_eq!(db.start_verify().unwrap_err().to_string(), format!("已有维护任务在执行: 操作 {}", id));
+
+        let response = server.delete(&format!("/api/v1/admin/operations/{}", id)).await?;
+        assert_eq!(response.status, axum::http::StatusCode::ACCEPTED);
+        assert!(db.operations()[0].cancel_requested);
+        release.send(())?;
+
+        // 在写入前停止，文件和数据保持不变
+        let report = compaction.await??;
+        assert!(report.skipped_reason.as_deref().is_some_and(|reason| reason.contains("已取消")), "{:?}", report);
+        assert!(db.operations().is_empty());
+        let files_after: Vec<String> = db.sstable_infos().into_iter().map(|info| info.file_name).collect();
+        assert_eq!(files_after, files_before);
+        assert_eq!(db.query_range("ops", None, None).await?.len(), 12);
+        assert!(db.compaction_history().is_empty());
+        let response = server.delete(&format!("/api/v1/admin/operations/{}", id)).await?;
+        assert_eq!(response.status, axum::http::StatusCode::NOT_FOUND);
+
+        // 之后的compaction正常执行
+        let report = db.compact().await?;
+        assert_eq!(report.skipped_reason, None);
+        assert_eq!(report.inputs.len(), 3);
+        let points: Vec<u64> = db.query_range("ops", None, None).await?.iter().map(|dp| dp.timestamp).collect();
+        assert_eq!(points, (0..12).collect::<Vec<_>>());
+
+        server.stop().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_envelope_keeps_spikes_per_bucket() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let db = TimeSeriesDB::new(temp_dir.path(), 64)?;
+
+        // 基线在 [0, 1) 内缓慢变化，每个60秒的桶里有一个向上和一个向下的尖峰
+        let mut points = Vec::new();
+        for timestamp in 0..600u64 {
+            let value = match timestamp % 60 {
+                17 => 500.0 + timestamp as f64,
+                43 => -500.0 - timestamp as f64,
+                offset => offset as f64 / 60.0,
+            };
+            points.push((timestamp, value));
+        }
+        // 内存表容量为64个点，大部分数据已刷新到SSTable，合并扫描同样要保留极值
+        for &(timestamp, value) in &points {
+            db.insert("spiky".to_string(), DataPoint { timestamp, value, tags: BTreeMap::new() }).await?;
+        }
+
+        let envelope = db.downsample_envelope("spiky", None, None, 60, FillPolicy::None).await?;
+        assert_eq!(envelope.len(), 10);
+        for bucket in &envelope {
+            let values: Vec<f64> = points
+                .iter()
+                .filter(|(timestamp, _)| timestamp / 60 * 60 == bucket.bucket_ts)
+                .map(|(_, value)| *value)
+                .collect();
+            assert_eq!(bucket.count, 60);
+            assert_eq!(bucket.max, Some(500.0 + (bucket.bucket_ts + 17) as f64));
+            assert_eq!(bucket.min, Some(-500.0 - (bucket.bucket_ts + 43) as f64));
+            let expected_avg = values.iter().sum::<f64>() / values.len() as f64;
+            assert!((bucket.avg.unwrap() - expected_avg).abs() < 1e-9);
+        }
+
+        // 同样的桶用平均值降采样时尖峰被抹平
+        let avg = db.downsample("spiky", None, None, 60, AggregateFn::Avg, FillPolicy::None).await?;
+        assert!(avg.iter().all(|bucket| bucket.value.unwrap().abs() < 10.0));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_export_is_byte_for_byte_stable() -> anyhow::Result<()> {
+        let server = testing::spawn_test_server_with(|builder| builder.memtable_threshold(16)).await?;
+        for sensor in testing::default_sensors().iter().take(3) {
+            for dp in sensor.datapoints(1609459200, 40) {
+                server.db_handle.insert(sensor.series_key(), dp).await?;
+            }
+        }
+
+        // 同样的数据导出两次，字节、长度和校验和都相同
+        let first = server.get("/api/v1/admin/export").await?;
+        let second = server.get("/api/v1/admin/export").await?;
+        assert_eq!(first.status, axum::http::StatusCode::OK);
+        assert!(first.body.len() > 1024);
+        assert!(first.body == second.body, "两次导出的内容不同");
+
+        let checksum = first.header("x-export-checksum").expect("缺少 X-Export-Checksum");
+        assert_eq!(checksum, format!("{:08x}", crc32fast::hash(&first.body)));
+        assert_eq!(second.header("x-export-checksum"), Some(checksum));
+        assert_eq!(first.header("etag"), Some(format!("\"{}\"", checksum).as_str()));
+
+        // 数据变化后校验和随之变化
+        server.db_handle.insert("temperature.temp_001".to_string(), DataPoint { timestamp: 1609559200, value: 1.0, tags: BTreeMap::new() }).await?;
+        let third = server.get("/api/v1/admin/export").await?;
+        assert_ne!(third.header("x-export-checksum"), Some(checksum));
+
+        server.stop().await?;
+        Ok(())
+    }
+
+    // 暂停tokio时间：后台检查的定时器按虚拟时间触发，测试不需要真实的等待
+    #[tokio::test(start_paused = true)]
+    async fn test_memtable_flush_on_idle() -> anyhow::Result<()> {
+        #[derive(Debug)]
+        struct FlushWatcher(tokio::sync::mpsc::UnboundedSender<usize>);
+
+        impl EngineObserver for FlushWatcher {
+            fn on_flush_completed(&self, event: &FlushEvent) {
+                let _ = self.0.send(event.points);
+            }
+        }
+
+        let temp_dir = TempDir::new()?;
+        let clock = std::sync::Arc::new(MockClock::from_secs(1609459200));
+        let (sender, mut flushed) = tokio::sync::mpsc::unbounded_channel();
+        let db = TimeSeriesDB::builder(temp_dir.path())
+            .memtable_threshold(1000)
+            .idle_flush_secs(5)
+            .clock(clock.clone())
+            .with_observer(std::sync::Arc::new(FlushWatcher(sender)))
+            .build()?;
+
+        for i in 0..3u64 {
+            db.insert("quiet".to_string(), DataPoint { timestamp: 1609459200 + i, value: i as f64, tags: BTreeMap::new() }).await?;
+        }
+        assert_eq!(db.memtable_idle_time(), Some(std::time::Duration::ZERO));
+
+        // 后台任务每1.25秒检查一次，空闲时间未达到阈值时不刷新
+        clock.advance(std::time::Duration::from_secs(4));
+        tokio::time::sleep(std::time::Duration::from_millis(1500)).await;
+        assert!(db.background_task_status()["timed_flush"].runs >= 1);
+        assert_eq!(db.get_stats().await?.sstable_count, 0);
+
+        clock.advance(std::time::Duration::from_secs(2));
+        let points = tokio::time::timeout(std::time::Duration::from_secs(60), flushed.recv()).await?.expect("空闲刷新未执行");
+        assert_eq!(points, 3);
+        let stats = db.get_stats().await?;
+        assert_eq!(stats.sstable_count, 1);
+        assert_eq!(stats.memtable_size, 0);
+        assert_eq!(db.memtable_idle_time(), None);
+        assert_eq!(db.query_range("quiet", None, None).await?.len(), 3);
+
+        db.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_protected_series_require_confirmation() -> anyhow::Result<()> {
+        let clock = std::sync::Arc::new(MockClock::from_secs(1609459200 + 7200));
+        let server = testing::spawn_test_server_with(|builder| builder.clock(clock.clone())).await?;
+        let db = &server.db_handle;
+        for series_key in ["billing_invoices", "billing_refunds", "cpu"] {
+            for timestamp in [1609459200, 1609466400] {
+                db.insert(series_key.to_string(), DataPoint { timestamp, value: 1.0, tags: BTreeMap::new() }).await?;
+            }
+        }
+
+        let response = server.put_json("/api/v1/admin/protected-series", &serde_json::json!({ "series": ["billing_*"] })).await?;
+        assert_eq!(response.status, axum::http::StatusCode::OK);
+        assert_eq!(server.get("/api/v1/admin/protected-series").await?.json()?["data"], serde_json::json!(["billing_*"]));
+
+        // 未确认或确认的键不一致时拒绝，数据保持不变
+        for path in ["/api/v1/series/billing_invoices", "/api/v1/series/billing_invoices?confirm=billing_refunds", "/api/v1/series/billing_invoices/datapoints/1609459200"] {
+            let response = server.delete(path).await?;
+            assert_eq!(response.status, axum::http::StatusCode::FORBIDDEN, "{}", path);
+            assert_eq!(response.json()?["success"], false);
+        }
+        assert_eq!(db.series_point_count("billing_invoices"), 2);
+        let error = db.delete("billing_invoices", None, false).await.unwrap_err();
+        assert!(matches!(DbError::from_io(&error), Some(DbError::SeriesProtected { .. })));
+
+        // 不受保护的系列照常删除；确认后删除受保护的系列
+        assert_eq!(server.delete("/api/v1/series/cpu/datapoints/1609466400").await?.status, axum::http::StatusCode::OK);
+        assert_eq!(db.series_point_count("cpu"), 1);
+        let response = server.delete("/api/v1/series/billing_invoices?confirm=billing_invoices").await?;
+        assert_eq!(response.status, axum::http::StatusCode::OK);
+        assert_eq!(response.json()?["success"], true);
+        assert_eq!(db.series_point_count("billing_invoices"), 0);
+
+        // 保留策略跳过受保护的系列并在报告中列出
+        db.set_retention_policy(RetentionPolicy { rules: Vec::new(), default_max_age_secs: Some(3600) })?;
+        let report = db.enforce_retention().await?;
+        assert_eq!(report.points_removed, 1);
+        assert_eq!(report.protected_skipped, vec!["billing_refunds".to_string()]);
+        assert_eq!(db.series_point_count("billing_refunds"), 2);
+
+        // 清空数据时保留受保护的系列；引擎层可以用 force 删除
+        db.insert("mem".to_string(), DataPoint { timestamp: 1609466400, value: 1.0, tags: BTreeMap::new() }).await?;
+        let response = server.post_json("/api/v1/admin/truncate", &serde_json::json!({ "confirm": true })).await?;
+        assert_eq!(response.json()?["data"]["protected_skipped"], serde_json::json!(["billing_refunds"]));
+        assert_eq!(db.get_all_series().await?, vec!["billing_refunds".to_string()]);
+        assert!(db.import_archive(std::io::empty(), true).await.is_err());
+        assert!(db.delete("billing_refunds", None, true).await?);
+
+        server.stop().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_series_compression_report() -> anyhow::Result<()> {
+        let server = testing::spawn_test_server_with(|builder| builder.memtable_threshold(100)).await?;
+        let db = &server.db_handle;
+        // 规则间隔、缓慢变化的数值，Gorilla编码后远小于原始大小
+        for i in 0..500u64 {
+            db.insert("regular".to_string(), DataPoint { timestamp: 1609459200 + i * 10, value: (i / 50) as f64, tags: BTreeMap::new() }).await?;
+        }
+        db.insert("other".to_string(), DataPoint { timestamp: 1609459200, value: 1.0, tags: BTreeMap::new() }).await?;
+
+        let report = db.series_compression_report("regular")?.expect("系列存在");
+        assert_eq!(report.points, db.series_point_count("regular"));
+        assert_eq!(report.points, 500);
+        assert_eq!(report.blocks, 5);
+        assert_eq!(report.raw_bytes, 500 * 16);
+        assert!(report.compressed_bytes > 0 && report.compressed_bytes < report.raw_bytes / 4);
+        assert_eq!(report.ratio, report.raw_bytes as f64 / report.compressed_bytes as f64);
+        assert_eq!(report.bytes_per_point, report.compressed_bytes as f64 / 500.0);
+        assert!(db.series_compression_report("missing")?.is_none());
+
+        let response = server.get("/api/v1/series/regular/compression").await?;
+        assert_eq!(response.status, axum::http::StatusCode::OK);
+        assert_eq!(response.json()?["data"]["compressed_bytes"], report.compressed_bytes);
+        assert_eq!(server.get("/api/v1/series/missing/compression").await?.status, axum::http::StatusCode::NOT_FOUND);
+
+        server.stop().await?;
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_queries_see_points_during_flush() -> anyhow::Result<()> {
+        use std::sync::Mutex;
+
+        // 第一次刷新开始写文件时通知测试并阻塞，直到测试放行
+        #[derive(Debug)]
+        struct Gate {
+            entered: Mutex<Option<tokio::sync::oneshot::Sender<usize>>>,
+            release: Mutex<Option<std::sync::mpsc::Receiver<()>>>,
+        }
+
+        impl EngineObserver for Gate {
+            fn on_flush_writing(&self, points: usize) {
+                if let Some(entered) = self.entered.lock().unwrap().take() {
+                    let _ = entered.send(points);
+                }
+                let release = self.release.lock().unwrap().take();
+                if let Some(release) = release {
+                    let _ = release.recv();
+                }
+            }
+        }
+
+        let (entered, entered_rx) = tokio::sync::oneshot::channel();
+        let (release, release_rx) = std::sync::mpsc::channel();
+        let gate = std::sync::Arc::new(Gate { entered: Mutex::new(Some(entered)), release: Mutex::new(Some(release_rx)) });
+        let temp_dir = TempDir::new()?;
+        let db = TimeSeriesDB::builder(temp_dir.path()).memtable_threshold(4).with_observer(gate).build()?;
+        let dp = |ts: u64| DataPoint { timestamp: ts, value: ts as f64, tags: BTreeMap::new() };
+        for ts in 0..3u64 {
+            db.insert("flushing".to_string(), dp(ts)).await?;
+        }
+
+        // 第4个点达到阈值，刷新在写文件时被挡住
+        let insert = tokio::spawn({
+            let db = db.clone();
+            async move { db.insert("flushing".to_string(), dp(3)).await }
+        });
+        let points = tokio::time::timeout(std::time::Duration::from_secs(5), entered_rx).await??;
+        assert_eq!(points, 4);
+
+        // 取走的点尚未写入SSTable，查询、聚合和系列列表都仍能看到
+        let values: Vec<f64> = db.query_range("flushing", None, None).await?.iter().map(|point| point.value).collect();
+        assert_eq!(values, vec![0.0, 1.0, 2.0, 3.0]);
+        assert_eq!(db.get_all_series().await?, vec!["flushing".to_string()]);
+        let stats = db.get_stats().await?;
+        assert_eq!((stats.sstable_count, stats.memtable_size, stats.total_series), (0, 1, 1));
+
+        // 刷新期间的新写入进入新的内存表，与正在刷新的点一起返回
+        db.insert("flushing".to_string(), dp(4)).await?;
+        assert_eq!(db.query_range("flushing", None, None).await?.len(), 5);
+
+        release.send(())?;
+        insert.await??;
+        let stats = db.get_stats().await?;
+        assert_eq!((stats.sstable_count, stats.memtable_size), (1, 1));
+        assert_eq!(db.query_range("flushing", None, None).await?.len(), 5);
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_write_ack_flushed_waits_for_flush() -> anyhow::Result<()> {
+        // 刷新完成时阻塞一段时间，模拟慢盘
+        #[derive(Debug)]
+        struct SlowFlush;
+
+        impl EngineObserver for SlowFlush {
+            fn on_flush_completed(&self, _event: &FlushEvent) {
+                std::thread::sleep(std::time::Duration::from_millis(300));
+            }
+        }
+
+        let server = testing::spawn_test_server_with(|builder| builder.with_observer(std::sync::Arc::new(SlowFlush))).await?;
+        let db = &server.db_handle;
+        let point = |timestamp: u64| serde_json::json!({ "series_key": "acked", "timestamp": timestamp, "value": 1.0 });
+
+        // 默认 received：写入内存表后立即响应
+        let started = std::time::Instant::now();
+        let response = server.post_json("/api/v1/datapoints", &point(1)).await?;
+        let received_latency = started.elapsed();
+        assert_eq!(response.status, axum::http::StatusCode::OK);
+        assert_eq!(db.write_sequence(), 1);
+        assert_eq!(db.durable_sequence(), 0);
+        assert_eq!(db.get_stats().await?.sstable_count, 0);
+
+        // flushed：响应时内存表已刷新，覆盖之前的写入
+        let mut durable = db.subscribe_durable();
+        let started = std::time::Instant::now();
+        let response = server.post_json("/api/v1/datapoints?ack=flushed", &point(2)).await?;
+        let flushed_latency = started.elapsed();
+        assert_eq!(response.status, axum::http::StatusCode::OK, "{}", response.text());
+        assert!(durable.has_changed()?);
+        assert_eq!(*durable.borrow_and_update(), 2);
+        let stats = db.get_stats().await?;
+        assert_eq!((stats.sstable_count, stats.memtable_size), (1, 0));
+        assert!(received_latency < std::time::Duration::from_millis(300), "{:?}", received_latency);
+        assert!(flushed_latency >= std::time::Duration::from_millis(300), "{:?}", flushed_latency);
+
+        // 已经落盘的写入不再触发刷新；批量写入同样支持
+        db.wait_durable(1).await?;
+        assert_eq!(db.get_stats().await?.sstable_count, 1);
+        let batch = serde_json::json!([point(3), point(4)]);
+        let response = server.post_json("/api/v1/datapoints/batch?ack=flushed", &batch).await?;
+        assert_eq!(response.status, axum::http::StatusCode::OK);
+        assert_eq!(db.durable_sequence(), 4);
+        assert_eq!(db.get_stats().await?.sstable_count, 2);
+
+        server.stop().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_patch_series_tags_on_flushed_series() -> anyhow::Result<()> {
+        let server = testing::spawn_test_server_with(|b| b.memtable_threshold(2)).await?;
+        let matchers = |pairs: &[(&str, &str)]| {
+            pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect::<BTreeMap<_, _>>()
+        };
+
+        for ts in 1..=2 {
+            let response = server.post_json("/api/v1/datapoints", &serde_json::json!({
+                "series_key": "temp",
+                "timestamp": ts,
+                "value": ts as f64,
+                "tags": {"location": "room1", "sensor_type": "temperature"}
+            })).await?;
+            assert_eq!(response.status, 200);
+        }
+        assert_eq!(server.db_handle.get_stats().await?.sstable_count, 1);
+
+        // 覆盖已有标签并新增一个标签
+        let response = server.patch_json("/api/v1/series/temp/tags", &serde_json::json!({
+            "set": {"location": "room2", "floor": "3"}
+        })).await?;
+        assert_eq!(response.status, 200);
+        assert_eq!(response.json()?["data"], serde_json::json!({
+            "floor": "3", "location": "room2", "sensor_type": "temperature"
+        }));
+
+        let body = server.get("/api/v1/series/temp/datapoints").await?.json()?;
+        let points = body["data"].as_array().unwrap();
+        assert_eq!(points.len(), 2);
+        assert!(points.iter().all(|dp| dp["tags"]["location"] == "room2" && dp["tags"]["floor"] == "3"));
+        let db = &server.db_handle;
+        assert_eq!(db.find_series_by_tags(&matchers(&[("location", "room2")])).await?, vec!["temp".to_string()]);
+        assert!(db.find_series_by_tags(&matchers(&[("location", "room1")])).await?.is_empty());
+
+        // 删除标签，其余标签保持不变
+        let response = server.patch_json("/api/v1/series/temp/tags", &serde_json::json!({
+            "remove": ["sensor_type", "not_there"]
+        })).await?;
+        assert_eq!(response.status, 200);
+        assert_eq!(db.series_tags("temp"), Some(matchers(&[("floor", "3"), ("location", "room2")])));
+        assert!(db.find_series_by_tags(&matchers(&[("sensor_type", "temperature")])).await?.is_empty());
+        let body = server.get("/api/v1/series/temp/datapoints").await?.json()?;
+        assert!(body["data"].as_array().unwrap().iter().all(|dp| dp["tags"].get("sensor_type").is_none()));
+
+        let conflict = serde_json::json!({"set": {"floor": "4"}, "remove": ["floor"]});
+        assert_eq!(server.patch_json("/api/v1/series/temp/tags", &conflict).await?.status, 400);
+        let missing = serde_json::json!({"set": {"floor": "4"}});
+        assert_eq!(server.patch_json("/api/v1/series/missing/tags", &missing).await?.status, 404);
+        assert_eq!(db.series_tags("temp").unwrap()["floor"], "3");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_overlap_report_tracks_flush_and_compaction() -> anyhow::Result<()> {
+        let server = testing::spawn_test_server_with(|b| b.memtable_threshold(2)).await?;
+        let db = &server.db_handle;
+        let point = |timestamp| DataPoint { timestamp, value: 1.0, tags: BTreeMap::new() };
+
+        // cpu: [1,10] 和 [5,20] 相交，[100,110] 单独；mem 只在一个文件中
+        for (series_key, timestamps) in [("cpu", [1, 10]), ("cpu", [5, 20]), ("cpu", [100, 110]), ("mem", [1, 2])] {
+            for ts in timestamps {
+                db.insert(series_key.to_string(), point(ts)).await?;
+            }
+        }
+        let files: Vec<String> = db.sstable_infos().into_iter().map(|info| info.file_name).collect();
+        assert_eq!(files.len(), 4);
+
+        let report = db.overlap_report();
+        assert_eq!((report.sstable_count, report.series_count, report.overlapping_series), (4, 2, 1));
+        assert_eq!(report.max_files_per_query, 2);
+        let cpu = &report.worst[0];
+        assert_eq!((cpu.series_key.as_str(), cpu.files, cpu.overlapping_files), ("cpu", 3, 2));
+        assert!((cpu.avg_files_per_query - 37.0 / 31.0).abs() < 1e-9);
+        let group = cpu.worst_group.as_ref().unwrap();
+        assert_eq!((group.start_time, group.end_time), (1, 20));
+        assert_eq!(group.files, files[..2].to_vec());
+        assert!((report.avg_files_per_query - (37.0 / 31.0 + 1.0) / 2.0).abs() < 1e-9);
+
+        let body = server.get("/api/v1/admin/overlap_report").await?.json()?;
+        assert_eq!(body["data"]["worst"][0]["worst_group"]["end_time"], 20);
+        assert_eq!(body["data"]["max_files_per_query"], 2);
+
+        // 刷新使缓存失效
+        db.insert("cpu".to_string(), point(8)).await?;
+        db.insert("cpu".to_string(), point(9)).await?;
+        assert_eq!(db.overlap_report().max_files_per_query, 3);
+
+        // compaction策略优先合并重叠最多的一组文件
+        let window = db.overlap_compaction_window().unwrap();
+        assert_eq!((window.start_time, window.end_time), (Some(1), Some(20)));
+        let compacted = db.compact_with(&window).await?;
+        assert!(compacted.skipped_reason.is_none());
+        let report = db.overlap_report();
+        assert_eq!((report.overlapping_series, report.max_files_per_query), (0, 1));
+        assert!(report.worst.is_empty());
+        assert!(db.overlap_compaction_window().is_none());
+        assert_eq!(db.query_range("cpu", None, None).await?.len(), 8);
+
+        Ok(())
+    }
+
+    // 随机测试的种子和轮数，可用 TSDB_FUZZ_SEED/TSDB_FUZZ_ITERATIONS 重现失败或跑更多轮
+    fn fuzz_rng(default_iterations: usize) -> (fastrand::Rng, u64, usize) {
+        let env = |name: &str| std::env::var(name).ok().and_then(|value| value.parse().ok());
+        let seed = env("TSDB_FUZZ_SEED").unwrap_or(0x7453_4442);
+        let iterations = env("TSDB_FUZZ_ITERATIONS").map_or(default_iterations, |n: u64| n as usize);
+        (fastrand::Rng::with_seed(seed), seed, iterations)
+    }
+
+    #[test]
+    fn test_sstable_reader_rejects_malformed_input() -> anyhow::Result<()> {
+        let (mut rng, seed, iterations) = fuzz_rng(2000);
+        let temp_dir = TempDir::new()?;
+        let path = temp_dir.path().join("sstable_fuzz.data");
+
+        let block = |key: &str, points: &[(u64, f64)]| SeriesData {
+            series_key: key.to_string(),
+            compressed_data: compression::format::encode_block_v2(points),
+            tags: BTreeMap::from([("host".to_string(), "a".to_string())]),
+            min_timestamp: points.iter().map(|p| p.0).min().unwrap_or(0),
+            max_timestamp: points.iter().map(|p| p.0).max().unwrap_or(0),
+            count: points.len(),
+            ingest_data: Vec::new(),
+        };
+        let series = vec![
+            block("cpu", &[(1, 1.0), (2, 2.5), (70, -3.0), (5000, 4.0)]),
+            block("mem", &[(10, 0.5)]),
+        ];
+        let valid: Vec<Vec<u8>> = [BlockCompression::None, BlockCompression::Lz4, BlockCompression::Zstd]
+            .into_iter()
+            .map(|compression| encode_series_list(&series, compression))
+            .collect::<std::io::Result<_>>()?;
+
+        for iteration in 0..iterations {
+            let mut bytes = match rng.u8(0..4) {
+                // 完全随机的字节，有时带上合法的文件头
+                0 => {
+                    let len = rng.usize(0..256);
+                    let mut bytes: Vec<u8> = std::iter::repeat_with(|| rng.u8(..)).take(len).collect();
+                    let header: &[u8] = match rng.u8(0..3) {
+                        0 => b"TSST\x03\x00",
+                        1 => &[b'T', b'S', b'S', b'C', 3, 0, rng.u8(0..3)],
+                        _ => b"",
+                    };
+                    bytes.splice(0..0, header.iter().copied());
+                    bytes
+                }
+                // 截断合法文件
+                1 => {
+                    let mut bytes = valid[rng.usize(..valid.len())].clone();
+                    bytes.truncate(rng.usize(0..bytes.len()));
+                    bytes
+                }
+                // 修改合法文件中的若干字节
+                _ => {
+                    let mut bytes = valid[rng.usize(..valid.len())].clone();
+                    for _ in 0..rng.usize(1..8) {
+                        let index = rng.usize(..bytes.len());
+                        bytes[index] = rng.u8(..);
+                    }
+                    bytes
+                }
+            };
+            if rng.bool() && !bytes.is_empty() {
+                // 随机插入一段字节
+                let index = rng.usize(..bytes.len());
+                let len = rng.usize(1..16);
+                let extra: Vec<u8> = std::iter::repeat_with(|| rng.u8(..)).take(len).collect();
+                bytes.splice(index..index, extra);
+            }
+
+            // 解码失败必须返回错误，不能panic；解码成功的块逐点解压也不能panic
+            let outcome = std::panic::catch_unwind(|| {
+                let Ok(series_list) = decode_series_list(&bytes) else { return };
+                for series in series_list {
+                    let _ = compression::format::decode_versioned(&series.compressed_data);
+                    if let Ok(decoder) = compression::format::block_decoder(series.compressed_data) {
+                        decoder.take(series.count).for_each(drop);
+                    }
+                }
+            });
+            assert!(outcome.is_ok(), "seed {} 第 {} 轮解码panic，输入: {:02x?}", seed, iteration, bytes);
+
+            // 通过内存映射读取同样的字节
+            if iteration % 16 == 0 {
+                std::fs::write(&path, &bytes)?;
+                let outcome = std::panic::catch_unwind(|| {
+                    let mut sstable = SSTable::new(path.clone()).unwrap();
+                    let _ = sstable.load_series_list();
+                    let _ = sstable.get_all_series_keys();
+                    let _ = sstable.query_series("cpu", None, None);
+                    let _ = sstable.query_series("mem", Some(5), Some(20));
+                });
+                assert!(outcome.is_ok(), "seed {} 第 {} 轮读取文件panic，输入: {:02x?}", seed, iteration, bytes);
+            }
+        }
+
+        // LZ4文件头声明的解压大小远超数据量时直接拒绝，不按声明的大小分配内存
+        let mut bomb = b"TSSC\x03\x00\x01".to_vec();
+        bomb.extend_from_slice(&u32::MAX.to_le_bytes());
+        bomb.extend_from_slice(&[0; 16]);
+        assert_eq!(decode_series_list(&bomb).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sstable_roundtrip_random_series() -> anyhow::Result<()> {
+        let (mut rng, seed, iterations) = fuzz_rng(300);
+        let temp_dir = TempDir::new()?;
+
+        // 第一个delta接近2^63时zigzag编码以8个1开头，不能被当作结束标记
+        let points = [(0, 1.0), (1 << 63, 2.0), (1 << 63, 3.0)];
+        let restored = compression::format::decode_versioned(&compression::format::encode_block_v2(&points))?;
+        assert_eq!(restored, points);
+
+        for iteration in 0..iterations {
+            let mut series_list = Vec::new();
+            let mut expected_points = Vec::new();
+            for _ in 0..rng.usize(0..6) {
+                let len = rng.usize(0..12);
+                let series_key: String = std::iter::repeat_with(|| rng.choice(['a', 'z', '_', '.', '温', '度']).unwrap())
+                    .take(len)
+                    .collect();
+                let tags = (0..rng.usize(0..3))
+                    .map(|i| (format!("k{}", i), rng.u32(..).to_string()))
+                    .collect();
+
+                // 时间差覆盖各个编码区间：不变、7位、12位、转义以及乱序和溢出
+                let mut timestamp = rng.u64(..);
+                let mut points = Vec::new();
+                for _ in 0..rng.usize(1..200) {
+                    points.push((timestamp, f64::from_bits(match rng.u8(0..4) {
+                        0 => rng.u64(..),
+                        1 => points.last().map_or(0, |p: &(u64, f64)| p.1.to_bits()),
+                        2 => (rng.f64() * 100.0).to_bits(),
+                        _ => f64::NAN.to_bits() ^ rng.u64(0..16),
+                    })));
+                    let step = match rng.u8(0..5) {
+                        0 => 60,
+                        1 => rng.u64(0..128),
+                        2 => rng.u64(0..8192),
+                        3 => rng.u64(..),
+                        _ => 60u64.wrapping_neg().wrapping_mul(rng.u64(0..3)),
+                    };
+                    timestamp = timestamp.wrapping_add(step);
+                }
+                series_list.push(SeriesData {
+                    series_key,
+                    compressed_data: compression::format::encode_block_v2(&points),
+                    tags,
+                    min_timestamp: points.iter().map(|p| p.0).min().unwrap(),
+                    max_timestamp: points.iter().map(|p| p.0).max().unwrap(),
+                    count: points.len(),
+                    ingest_data: Vec::new(),
+                });
+                expected_points.push(points);
+            }
+
+            let compression = [BlockCompression::None, BlockCompression::Lz4, BlockCompression::Zstd][iteration % 3];
+            let encoded = encode_series_list(&series_list, compression)?;
+            let decoded = decode_series_list(&encoded)?;
+            assert_eq!(decoded, series_list, "seed {} 第 {} 轮往返结果不一致", seed, iteration);
+
+            for (series, points) in decoded.iter().zip(&expected_points) {
+                let restored = compression::format::decode_versioned(&series.compressed_data)?;
+                let bits = |points: &[(u64, f64)]| points.iter().map(|&(ts, v)| (ts, v.to_bits())).collect::<Vec<_>>();
+                assert_eq!(bits(&restored), bits(points), "seed {} 第 {} 轮系列 {:?} 解压结果不一致", seed, iteration, series.series_key);
+            }
+
+            if iteration % 10 == 0 {
+                let mut sstable = SSTable::new(temp_dir.path().join(format!("sstable_{}.data", iteration)))?
+                    .with_compression(compression);
+                sstable.write_data(&series_list)?;
+                assert_eq!(sstable.load_series_list()?, series_list);
+            }
+        }
+
+        Ok(())
+    }
+
     #[tokio::test]
-    async fn test_full_crud_operations() -> anyhow::Result<()> {
+    async fn test_config_file_merged_with_env() -> anyhow::Result<()> {
         let temp_dir = TempDir::new()?;
-        let db = TimeSeriesDB::new(temp_dir.path(), 1000)?; // 增大阈值
+        let path = temp_dir.path().join("tsdb.toml");
+        std::fs::write(&path, r#"
+            port = 7000
+            memtable_threshold = 50
+            flush_compression = "lz4"
+            warmup_series = ["cpu.*", "mem"]
+            disk_low_watermark_bytes = 1000
+        "#)?;
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| vars.iter().find(|(key, _)| *key == name).map(|(_, value)| value.to_string())
+        };
 
-        let mut tags = BTreeMap::new();
-        tags.insert("sensor".to_string(), "temperature".to_string());
-        tags.insert("location".to_string(), "room1".to_string());
+        // 环境变量覆盖文件，都未指定的项使用默认值
+        let config = Config::load(Some(&path), env(&[("PORT", "7100"), ("TAG_DRIFT_MODE", "warn")]))?;
+        assert_eq!(config.port, 7100);
+        assert_eq!(config.memtable_threshold, 50);
+        assert_eq!(config.flush_compression, BlockCompression::Lz4);
+        assert_eq!(config.tag_drift_mode, TagDriftMode::Warn);
+        assert_eq!(config.warmup_series, Some(vec!["cpu.*".to_string(), "mem".to_string()]));
+        assert_eq!(config.retention_check_secs, Config::default().retention_check_secs);
+        let db = Config { data_dir: temp_dir.path().join("data"), ..config }.builder().build()?;
+        assert_eq!(db.config().disk_critical_watermark_bytes, Some(500));
+        assert_eq!(db.config().memtable_threshold, 50);
+        db.close().await?;
 
-        // 测试插入
-        println!("🔧 插入10个数据点...");
-        for i in 0..10 {
-            let dp = DataPoint {
-                timestamp: 1609459200 + i * 60,
-                value: 20.0 + (i as f64) * 0.5,
-                tags: tags.clone(),
-            };
-            db.insert("temp_sensor_1".to_string(), dp).await?;
+        let args = ["--config", "/etc/tsdb.toml"].map(String::from);
+        assert_eq!(Config::path_from_args(args, env(&[])), Some("/etc/tsdb.toml".into()));
+        assert_eq!(Config::path_from_args(Vec::new(), env(&[("TSDB_CONFIG", "a.toml")])), Some("a.toml".into()));
+
+        // 所有无效的项一起报告
+        std::fs::write(&path, "port = \"http\"\nmemtable_treshold = 10\nretention_check_secs = 0\n")?;
+        let error = Config::load(Some(&path), env(&[("FLUSH_COMPRESSION", "gzip"), ("DISK_CRITICAL_WATERMARK_BYTES", "1")])).unwrap_err();
+        let fields: Vec<&str> = error.errors.iter().map(|e| e.split(':').next().unwrap()).collect();
+        assert_eq!(fields, [
+            "配置文件 memtable_treshold",
+            "配置文件 port",
+            "环境变量 FLUSH_COMPRESSION",
+            "retention_check_secs",
+            "disk_critical_watermark_bytes",
+        ]);
+        assert!(error.to_string().starts_with("配置无效（5 项）"));
+        assert!(Config::load(Some(&temp_dir.path().join("missing.toml")), env(&[])).is_err());
+
+        // 服务提供生效的配置
+        let config = Config { memtable_threshold: 77, ..Config::default() };
+        let server = testing::spawn_test_server_with_config(config).await?;
+        assert_eq!(server.db_handle.config().memtable_threshold, 77);
+        let body = server.get("/api/v1/admin/config").await?.json()?;
+        assert_eq!(body["data"]["memtable_threshold"], 77);
+        assert_eq!(body["data"]["timestamp_precision"], "s");
+        assert_eq!(testing::spawn_test_server().await?.get("/api/v1/admin/config").await?.status, 404);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_ingest_time_of_late_points() -> anyhow::Result<()> {
+        const NOW: u64 = 1_700_000_000;
+        let clock = std::sync::Arc::new(MockClock::from_secs(NOW));
+        let server = testing::spawn_test_server_with(|b| b.memtable_threshold(3).record_ingest_time(true).clock(clock.clone())).await?;
+        let db = &server.db_handle;
+        let point = |timestamp| DataPoint { timestamp, value: timestamp as f64, tags: BTreeMap::new() };
+        let ingest_times = |body: serde_json::Value| -> Vec<Option<u64>> {
+            body["data"].as_array().unwrap().iter().map(|dp| dp["ingest_timestamp"].as_u64()).collect()
+        };
+        let path = "/api/v1/series/late/datapoints?include_ingest_time=true";
+
+        // 事件时间很早的点，写入时间为写入时的当前时间
+        db.insert("late".to_string(), point(1_000)).await?;
+        db.insert("late".to_string(), point(1_001)).await?;
+        assert_eq!(ingest_times(server.get(path).await?.json()?), vec![Some(NOW), Some(NOW)]);
+        let body = server.get("/api/v1/series/late/datapoints").await?.json()?;
+        assert!(body["data"][0].get("ingest_timestamp").is_none());
+
+        // 刷新和compaction之后写入时间保持不变
+        clock.advance(std::time::Duration::from_secs(60));
+        db.insert("late".to_string(), point(900)).await?;
+        assert_eq!(db.get_stats().await?.sstable_count, 1);
+        let body = server.get(path).await?.json()?;
+        assert_eq!(body["data"][0]["timestamp"], 900);
+        assert_eq!(ingest_times(body), vec![Some(NOW + 60), Some(NOW), Some(NOW)]);
+        for timestamp in [2_000, 2_001, 2_002] {
+            db.insert("late".to_string(), point(timestamp)).await?;
         }
+        db.compact().await?;
+        assert_eq!(db.get_stats().await?.sstable_count, 1);
+        let expected = vec![Some(NOW + 60), Some(NOW), Some(NOW), Some(NOW + 60), Some(NOW + 60), Some(NOW + 60)];
+        assert_eq!(ingest_times(server.get(path).await?.json()?), expected);
 
-        // 验证插入 - 查询所有数据
-        let all_results = db.query_range("temp_sensor_1", None, None).await?;
-        println!("📊 查询到 {} 个数据点", all_results.len());
-        assert_eq!(all_results.len(), 10, "应该有10个数据点");
+        // 删除数据点时一并删除写入时间；max_wait_ms 的限时查询同样返回写入时间
+        assert!(db.delete("late", Some(1_000), false).await?);
+        let body = server.get(&format!("{}&max_wait_ms=1000", path)).await?.json()?;
+        assert_eq!(ingest_times(body), expected[..1].iter().chain(&expected[2..]).copied().collect::<Vec<_>>());
 
-        // 测试更新
-        println!("✏️ 测试更新操作...");
-        let updated = db.update("temp_sensor_1", 1609459200, 25.0).await?;
-        println!("更新结果: {}", updated);
+        server.stop().await?;
 
-        // 验证更新 - 查询所有数据并检查第一个点的值
-        let updated_results = db.query_range("temp_sensor_1", None, None).await?;
-        let first_point = updated_results.iter().find(|dp| dp.timestamp == 1609459200);
-        assert!(first_point.is_some(), "应该找到时间戳为1609459200的数据点");
-        if let Some(point) = first_point {
-            assert_eq!(point.value, 25.0, "更新后的值应该是25.0");
+        // 未开启时不记录写入时间
+        let server = testing::spawn_test_server().await?;
+        server.db_handle.insert("late".to_string(), point(1_000)).await?;
+        assert_eq!(ingest_times(server.get(path).await?.json()?), vec![None]);
+        server.stop().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_relocate_sstable_between_data_dirs() -> anyhow::Result<()> {
+        let extra = TempDir::new()?;
+        let extra_dir = extra.path().to_path_buf();
+        let server = testing::spawn_test_server_with(|b| b.memtable_threshold(2).extra_data_dirs(vec![extra_dir.clone()])).await?;
+        let db = &server.db_handle;
+        for timestamp in [1, 2] {
+            db.insert("cpu".to_string(), DataPoint { timestamp, value: timestamp as f64, tags: BTreeMap::new() }).await?;
         }
+        let usage = db.data_dir_usage();
+        assert_eq!(usage.len(), 2);
+        assert!(usage[0].primary && !usage[1].primary);
+        assert_eq!(usage.iter().map(|dir| dir.sstable_count).sum::<usize>(), 1);
+        let stats = server.get("/stats").await?.json()?;
+        assert_eq!(stats["data"]["data_dirs"].as_array().map(Vec::len), Some(2));
 
-        // 测试删除
-        println!("🗑️ 测试删除操作...");
-        let deleted = db.delete("temp_sensor_1", Some(1609459200)).await?;
-        println!("删除结果: {}", deleted);
+        // 移动到另一个目录，再移回原目录
+        let file_name = db.sstable_infos()[0].file_name.clone();
+        let (source, target) = if usage[0].sstable_count == 1 { (&usage[0].path, &usage[1].path) } else { (&usage[1].path, &usage[0].path) };
+        for (from, to) in [(source, target), (target, source)] {
+            let body = serde_json::json!({ "file_name": file_name, "target_dir": to });
+            let response = server.post_json("/api/v1/admin/relocate", &body).await?;
+            assert_eq!(response.status, axum::http::StatusCode::OK);
+            assert_eq!(response.json()?["data"]["moved"], true);
+            assert!(to.join(&file_name).exists() && !from.join(&file_name).exists());
+            assert_eq!(db.query_range("cpu", None, None).await?.len(), 2);
+            assert!(db.update("cpu", 1, 10.0).await?);
+        }
+        let body = serde_json::json!({ "file_name": file_name, "target_dir": source });
+        assert_eq!(server.post_json("/api/v1/admin/relocate", &body).await?.json()?["data"]["moved"], false);
 
-        // 验证删除
-        let after_delete = db.query_range("temp_sensor_1", None, None).await?;
-        let deleted_point = after_delete.iter().find(|dp| dp.timestamp == 1609459200);
-        assert!(deleted_point.is_none(), "删除的数据点不应该存在");
-        assert_eq!(after_delete.len(), 9, "删除后应该剩余9个数据点");
+        let unknown_dir = TempDir::new()?;
+        let body = serde_json::json!({ "file_name": file_name, "target_dir": unknown_dir.path() });
+        assert_eq!(server.post_json("/api/v1/admin/relocate", &body).await?.status, axum::http::StatusCode::BAD_REQUEST);
+        let body = serde_json::json!({ "file_name": "missing.data", "target_dir": target });
+        assert_eq!(server.post_json("/api/v1/admin/relocate", &body).await?.status, axum::http::StatusCode::NOT_FOUND);
+        server.stop().await?;
 
-        // 测试系列列表
-        let series = db.get_all_series().await?;
-        assert!(series.contains(&"temp_sensor_1".to_string()));
+        // 启动时扫描所有目录；删除原文件前中断留下的同名文件被清理
+        let primary = TempDir::new()?;
+        let open = || TimeSeriesDB::builder(primary.path()).memtable_threshold(2).extra_data_dirs(vec![extra_dir.clone()]).build();
+        let db = open()?;
+        for timestamp in [1, 2] {
+            db.insert("mem".to_string(), DataPoint { timestamp, value: 1.0, tags: BTreeMap::new() }).await?;
+        }
+        let file_name = db.sstable_infos()[0].file_name.clone();
+        db.relocate_sstable(&file_name, &extra_dir).await?;
+        assert!(db.relocate_sstable(&file_name, primary.path().join("missing").as_path()).await.is_err());
+        db.close().await?;
+        std::fs::copy(extra_dir.join(&file_name), primary.path().join(&file_name))?;
+        let db = open()?;
+        assert_eq!(db.get_stats().await?.sstable_count, 1);
+        assert_eq!(db.query_range("mem", None, None).await?.len(), 2);
+        assert_eq!(primary.path().join(&file_name).exists() as u8 + extra_dir.join(&file_name).exists() as u8, 1);
+        db.close().await?;
 
-        // 测试删除整个系列
-        let series_deleted = db.delete("temp_sensor_1", None).await?;
-        assert!(series_deleted);
+        // 同一目录不能重复配置
+        assert!(TimeSeriesDB::builder(primary.path()).extra_data_dirs(vec![primary.path().to_path_buf()]).build().is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_compact_single_series() -> anyhow::Result<()> {
+        let server = testing::spawn_test_server_with(|b| b.memtable_threshold(2)).await?;
+        let db = &server.db_handle;
+        let point = |timestamp| DataPoint { timestamp, value: timestamp as f64, tags: BTreeMap::new() };
+        // hot 分散在三个文件中，其中两个文件还包含其他系列；disk 单独在一个文件中
+        for (series_key, timestamp) in [("hot", 1), ("mem", 1), ("hot", 2), ("hot", 3), ("hot", 4), ("cpu", 1), ("disk", 1), ("disk", 2)] {
+            db.insert(series_key.to_string(), point(timestamp)).await?;
+        }
+        let files_with = |series_key: &str| -> anyhow::Result<Vec<String>> {
+            let mut files = Vec::new();
+            for info in db.sstable_infos() {
+                let detail = db.sstable_detail(&info.file_name)?.unwrap();
+                if detail.series.iter().any(|series| series.series_key == series_key) {
+                    files.push(info.file_name);
+                }
+            }
+            Ok(files)
+        };
+        assert_eq!(files_with("hot")?.len(), 3);
+        let disk_file = files_with("disk")?.remove(0);
+        let disk_path = server.db_handle.data_dir_usage()[0].path.join(&disk_file);
+        let disk_bytes = std::fs::read(&disk_path)?;
+        let disk_modified = std::fs::metadata(&disk_path)?.modified()?;
+
+        let response = server.post_json("/api/v1/admin/series/hot/compact", &serde_json::json!({})).await?;
+        assert_eq!(response.status, axum::http::StatusCode::OK);
+        let report = &response.json()?["data"];
+        assert_eq!(report["inputs"].as_array().map(Vec::len), Some(3));
+        assert_eq!(report["series_merged"], 1);
+        assert_eq!(report["points_merged"], 4);
+
+        // hot 合并为一个块，其他系列留在原来的文件中
+        let hot_files = files_with("hot")?;
+        assert_eq!(hot_files.len(), 1);
+        assert!(hot_files[0].starts_with("compacted_series"));
+        let detail = db.sstable_detail(&hot_files[0])?.unwrap();
+        assert_eq!(detail.series.len(), 1);
+        assert_eq!((detail.series[0].blocks, detail.series[0].points), (1, 4));
+        assert_eq!(db.query_range("hot", None, None).await?.len(), 4);
+        for series_key in ["mem", "cpu"] {
+            let files = files_with(series_key)?;
+            assert_eq!(files.len(), 1);
+            assert!(files[0].starts_with("sstable_"), "{}", files[0]);
+            assert_eq!(db.query_range(series_key, None, None).await?.len(), 1);
+        }
+        assert_eq!(files_with("disk")?, vec![disk_file]);
+        assert_eq!(std::fs::read(&disk_path)?, disk_bytes);
+        assert_eq!(std::fs::metadata(&disk_path)?.modified()?, disk_modified);
+        assert_eq!(db.sstable_infos().len(), 4);
+
+        // 只在一个文件中的系列无需合并
+        let report = db.compact_series("hot").await?;
+        assert!(report.skipped_reason.is_some());
+
+        server.stop().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_memory_limit_counts_tags() -> anyhow::Result<()> {
+        let heavy_tags: BTreeMap<String, String> = [("host".to_string(), "h".repeat(200))].into_iter().collect();
+        let heavy_point = QueryMemory::point_bytes(&heavy_tags);
+        let limit = heavy_point * 1200;
+        let server = testing::spawn_test_server_with(|b| b.max_query_memory_bytes(limit).query_memory_budget_bytes(limit * 2)).await?;
+        // 点数相同时，标签多的系列超过上限，无标签的系列不超过
+        for timestamp in 1..=2500 {
+            let point = |tags: &BTreeMap<String, String>| DataPoint { timestamp, value: 1.0, tags: tags.clone() };
+            server.db_handle.insert("heavy".to_string(), point(&heavy_tags)).await?;
+            server.db_handle.insert("light".to_string(), point(&BTreeMap::new())).await?;
+        }
+
+        let response = server.get("/api/v1/series/light/datapoints").await?;
+        assert_eq!(response.status, axum::http::StatusCode::OK);
+        assert_eq!(response.json()?["data"].as_array().map(Vec::len), Some(2500));
+
+        let response = server.get("/api/v1/series/heavy/datapoints").await?;
+        assert_eq!(response.status, axum::http::StatusCode::PAYLOAD_TOO_LARGE);
+        let message = response.json()?["message"].as_str().unwrap_or_default().to_string();
+        assert!(message.contains("max_wait_ms"), "{}", message);
+        let error = server.db_handle.query_range("heavy", None, None).await.unwrap_err();
+        assert!(matches!(DbError::from_io(&error), Some(DbError::ResultTooLarge { limit_bytes, .. }) if *limit_bytes == limit));
+
+        // 限时分页查询读满上限时返回部分结果，按 next_cursor 可以读完
+        let mut path = "/api/v1/series/heavy/datapoints?max_wait_ms=60000".to_string();
+        let mut pages = Vec::new();
+        loop {
+            let body = server.get(&path).await?.json()?;
+            pages.push(body["data"].as_array().map_or(0, Vec::len));
+            match body["next_cursor"].as_u64() {
+                Some(cursor) => path = format!("/api/v1/series/heavy/datapoints?max_wait_ms=60000&start_time={}", cursor),
+                None => break,
+            }
+        }
+        assert_eq!(pages, vec![1200, 1200, 100]);
+
+        // 读满 limit 时结果完整，不返回 next_cursor
+        let body = server.get("/api/v1/series/heavy/datapoints?max_wait_ms=60000&limit=1000").await?.json()?;
+        assert_eq!(body["data"].as_array().map(Vec::len), Some(1000));
+        assert_eq!((body["partial"].as_bool(), body["next_cursor"].as_u64()), (Some(false), None));
+        let body = server.get("/api/v1/series/heavy/datapoints?max_wait_ms=60000&limit=2000").await?.json()?;
+        assert_eq!(body["data"].as_array().map(Vec::len), Some(1200));
+        assert_eq!(body["partial"].as_bool(), Some(true));
+
+        let stats = server.get("/stats").await?.json()?;
+        let query_memory = &stats["data"]["query_memory"];
+        assert_eq!(query_memory["rejected_queries"], 2);
+        assert_eq!(query_memory["in_use_bytes"], 0);
+        assert_eq!(query_memory["max_query_bytes"], limit);
+
+        server.stop().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_memory_budget_queues_queries() -> anyhow::Result<()> {
+        let memory = std::sync::Arc::new(QueryMemory::new(Some(64 * 1024), Some(100 * 1024)));
+        let first = memory.reserve(80 * 1024).await?;
+        // 超过单次上限的预留按上限计算
+        assert_eq!(first.bytes(), 64 * 1024);
+
+        let waiting = tokio::spawn({
+            let memory = std::sync::Arc::clone(&memory);
+            async move { memory.reserve(50 * 1024).await.map(|reservation| reservation.bytes()) }
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert!(!waiting.is_finished());
+        let stats = memory.stats();
+        assert_eq!((stats.in_use_bytes, stats.waiting_queries), (64 * 1024, 1));
+
+        // 释放后排队的查询继续
+        drop(first);
+        assert_eq!(waiting.await??, 50 * 1024);
+        let stats = memory.stats();
+        assert_eq!((stats.in_use_bytes, stats.waiting_queries, stats.rejected_queries), (0, 0, 0));
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_query_memory_reserved_until_response_written() -> anyhow::Result<()> {
+        use api::handlers::query_datapoints;
+        use axum::extract::{Path, Query, State};
+
+        let (gate, mut control) = testing::QueryBlockGate::new();
+        let temp_dir = TempDir::new()?;
+        let db = TimeSeriesDB::builder(temp_dir.path())
+            .memtable_threshold(10)
+            .inline_query_points(0)
+            .query_memory_budget_bytes(1024 * 1024)
+            .with_observer(gate)
+            .build()?;
+        for ts in 0..20u64 {
+            db.insert("cpu".to_string(), DataPoint { timestamp: ts, value: ts as f64, tags: BTreeMap::new() }).await?;
+        }
+        let point_bytes = QueryMemory::point_bytes(&BTreeMap::new());
+
+        // 第一个块开始解压之前已按两个块的点数预留
+        let query = tokio::spawn({
+            let db = db.clone();
+            async move { db.query_range("cpu", None, None).await }
+        });
+        control.entered(std::time::Duration::from_secs(10)).await?;
+        assert_eq!(db.get_stats().await?.query_memory.in_use_bytes, 20 * point_bytes);
+        control.release()?;
+        assert_eq!(query.await??.len(), 20);
+        assert_eq!(db.get_stats().await?.query_memory.in_use_bytes, 0);
+
+        // 处理函数返回的响应带着预留，序列化之后才释放
+        for uri in ["/", "/?max_wait_ms=5000"] {
+            let response =
+                query_datapoints(State(db.clone()), Path("cpu".to_string()), Query::try_from_uri(&uri.parse()?)?, axum::http::HeaderMap::new()).await;
+            assert_eq!(db.get_stats().await?.query_memory.in_use_bytes, 20 * point_bytes, "{}", uri);
+            let body = axum::body::to_bytes(axum::response::IntoResponse::into_response(response).into_body(), usize::MAX).await?;
+            assert_eq!(db.get_stats().await?.query_memory.in_use_bytes, 0, "{}", uri);
+            let body: serde_json::Value = serde_json::from_slice(&body)?;
+            assert_eq!(body["data"].as_array().map(Vec::len), Some(20));
+            assert!(body.get("reservation").is_none());
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_series_chunks_cover_range() -> anyhow::Result<()> {
+        let server = testing::spawn_test_server_with(|b| b.memtable_threshold(4)).await?;
+        // 每4个点刷新为一个块：第一个块完整落在第一个窗口内，第二个块跨越三个窗口，
+        // 第三、四个块在同一窗口内且时间范围重叠（210重复），最后两个点留在内存表中
+        let timestamps = [0, 10, 20, 30, 50, 70, 130, 140, 200, 210, 220, 230, 210, 215, 225, 235, 300, 305];
+        for timestamp in timestamps {
+            let point = DataPoint { timestamp, value: timestamp as f64, tags: BTreeMap::new() };
+            server.db_handle.insert("cpu".to_string(), point).await?;
+        }
+        assert_eq!(server.db_handle.sstable_infos().len(), 4);
+
+        let body = server.get("/api/v1/series/cpu/chunks?start=0&end=400&chunk=1m").await?.json()?;
+        assert_eq!(body["data"]["chunk"], 60);
+        let chunks: Vec<ChunkSummary> = serde_json::from_value(body["data"]["chunks"].clone())?;
+        let summary = |chunk: &ChunkSummary| (chunk.start, chunk.end, chunk.count, chunk.min_timestamp, chunk.max_timestamp);
+        assert_eq!(
+            chunks.iter().map(summary).collect::<Vec<_>>(),
+            vec![
+                (0, 59, 5, Some(0), Some(50)),
+                (60, 119, 1, Some(70), Some(70)),
+                (120, 179, 2, Some(130), Some(140)),
+                (180, 239, 7, Some(200), Some(235)),
+                (240, 299, 0, None, None),
+                (300, 359, 2, Some(300), Some(305)),
+                (360, 400, 0, None, None),
+            ]
+        );
+        let total: usize = chunks.iter().map(|chunk| chunk.count).sum();
+        assert_eq!(total, server.db_handle.query_range("cpu", Some(0), Some(400)).await?.len());
+
+        // 范围边界切开的块只统计范围内的点
+        let chunks = server.db_handle.series_chunks("cpu", 5, 65, 60).await?;
+        assert_eq!(chunks.iter().map(summary).collect::<Vec<_>>(), vec![(5, 64, 4, Some(10), Some(50)), (65, 65, 0, None, None)]);
+
+        for path in ["chunk=0s&start=0&end=400", "chunk=1x&start=0&end=400", "chunk=1m&start=400&end=0", "chunk=1s&start=0&end=1000000"] {
+            let response = server.get(&format!("/api/v1/series/cpu/chunks?{}", path)).await?;
+            assert_eq!(response.status, axum::http::StatusCode::BAD_REQUEST, "{}", path);
+        }
+
+        server.stop().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sensitive_tags_redacted_without_unredacted_key() -> anyhow::Result<()> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let server = testing::spawn_test_server_with(|b| {
+            b.tag_drift_mode(TagDriftMode::Reject)
+                .sensitive_tag_keys(vec!["serial".to_string()])
+                .unredacted_api_keys(vec!["ops-key".to_string()])
+        })
+        .await?;
+        let tags: BTreeMap<String, String> =
+            [("serial", "SN-1"), ("owner", "alice"), ("room", "r1")].into_iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        server.db_handle.insert("temp".to_string(), DataPoint { timestamp: 1, value: 1.0, tags: tags.clone() }).await?;
+        let ops = ("X-API-Key", "ops-key");
+
+        // 全局列表中的标签被替换，有权限的key看到原始值
+        let body = server.get("/api/v1/series/temp/datapoints").await?.json()?;
+        assert_eq!(body["data"][0]["tags"]["serial"], "[redacted]");
+        assert_eq!(body["data"][0]["tags"]["owner"], "alice");
+        let body = server.request("GET", "/api/v1/series/temp/datapoints", &[ops], b"").await?.json()?;
+        assert_eq!(body["data"][0]["tags"]["serial"], "SN-1");
+        let body = server.request("GET", "/api/v1/series/temp/datapoints", &[("X-API-Key", "other")], b"").await?.json()?;
+        assert_eq!(body["data"][0]["tags"]["serial"], "[redacted]");
+
+        // 单独标记系列的标签只能由有权限的key修改
+        let request = serde_json::json!({"tag_keys": ["owner", "owner"]});
+        assert_eq!(server.put_json("/api/v1/series/temp/sensitive-tags", &request).await?.status, 403);
+        let response = server
+            .request("PUT", "/api/v1/series/temp/sensitive-tags", &[ops, ("Content-Type", "application/json")], request.to_string().as_bytes())
+            .await?;
+        assert_eq!(response.status, 200);
+        let body = server.get("/api/v1/series/temp/sensitive-tags").await?.json()?;
+        assert_eq!(body["data"]["tag_keys"], serde_json::json!(["owner"]));
+        assert_eq!(body["data"]["global_tag_keys"], serde_json::json!(["serial"]));
+        let missing = server
+            .request("PUT", "/api/v1/series/missing/sensitive-tags", &[ops, ("Content-Type", "application/json")], request.to_string().as_bytes())
+            .await?;
+        assert_eq!(missing.status, 404);
+
+        let body = server.get("/api/v1/series/temp").await?.json()?;
+        assert_eq!(body["data"]["tags"]["owner"], "[redacted]");
+        assert_eq!(body["data"]["tags"]["serial"], "[redacted]");
+        assert_eq!(body["data"]["tags"]["room"], "r1");
+        let body = server.patch_json("/api/v1/series/temp/tags", &serde_json::json!({"set": {"room": "r2"}})).await?.json()?;
+        assert_eq!(body["data"]["owner"], "[redacted]");
+        assert_eq!(body["data"]["room"], "r2");
+        let body = server.put_json("/api/v1/series/temp/tags", &serde_json::json!({"tags": tags})).await?.json()?;
+        assert_eq!(body["data"], serde_json::json!({"owner": "[redacted]", "room": "r1", "serial": "[redacted]"}));
+        let body = server.get("/api/v1/series/temp?fields=tags").await?.json()?;
+        assert_eq!(body["data"], serde_json::json!({"tags": {"owner": "[redacted]", "room": "r1", "serial": "[redacted]"}}));
+        let body = server.get("/api/v1/series/temp/datapoints?max_wait_ms=5000").await?.json()?;
+        assert_eq!((body["partial"].as_bool(), &body["data"][0]["tags"]["serial"]), (Some(false), &serde_json::json!("[redacted]")));
+
+        // 注释的标签按所属系列的敏感标签隐藏，全局注释只按全局列表
+        let annotation = serde_json::json!({"series_key": "temp", "timestamp": 1, "text": "换传感器", "tags": {"serial": "SN-2", "owner": "bob"}});
+        let body = server.post_json("/api/v1/annotations", &annotation).await?.json()?;
+        assert_eq!(body["data"]["tags"], serde_json::json!({"owner": "[redacted]", "serial": "[redacted]"}));
+        let global = serde_json::json!({"timestamp": 1, "text": "机房巡检", "tags": {"serial": "SN-3", "owner": "carol"}});
+        let body = server.post_json("/api/v1/annotations", &global).await?.json()?;
+        assert_eq!(body["data"]["tags"], serde_json::json!({"owner": "carol", "serial": "[redacted]"}));
+        let body = server.get("/api/v1/annotations?series_key=temp").await?.json()?;
+        assert_eq!(body["data"][0]["tags"]["serial"], "[redacted]");
+        assert_eq!(body["data"][1]["tags"]["serial"], "[redacted]");
+        let body = server.get("/api/v1/series/temp/datapoints?include_annotations=true").await?.json()?;
+        assert_eq!(body["annotations"][0]["tags"]["owner"], "[redacted]");
+        assert_eq!(body["annotations"][1]["tags"]["owner"], "carol");
+        let body = server.request("GET", "/api/v1/annotations?series_key=temp", &[ops], b"").await?.json()?;
+        assert_eq!(body["data"][0]["tags"], serde_json::json!({"owner": "bob", "serial": "SN-2"}));
+
+        // 变更流中插入事件的标签
+        let mut stream = tokio::net::TcpStream::connect(server.base_url.trim_start_matches("http://")).await?;
+        stream.write_all(b"GET /api/v1/stream?series_key=temp HTTP/1.1\r\nHost: localhost\r\n\r\n").await?;
+        let mut received = Vec::new();
+        let mut buf = [0u8; 4096];
+        let mut inserted = false;
+        while !String::from_utf8_lossy(&received).contains("event: insert") {
+            if !inserted && String::from_utf8_lossy(&received).contains("\r\n\r\n") {
+                server.db_handle.insert("temp".to_string(), DataPoint { timestamp: 5, value: 5.0, tags: tags.clone() }).await?;
+                inserted = true;
+            }
+            let n = tokio::time::timeout(std::time::Duration::from_secs(5), stream.read(&mut buf)).await??;
+            anyhow::ensure!(n > 0, "连接提前关闭");
+            received.extend_from_slice(&buf[..n]);
+        }
+        let text = String::from_utf8_lossy(&received).to_string();
+        assert!(text.contains(r#""serial":"[redacted]""#) && !text.contains("SN-1"), "{}", text);
+        drop(stream);
+
+        // 标签漂移的错误信息同样隐藏，包括预压缩块的上传
+        let drifted = serde_json::json!({"series_key": "temp", "timestamp": 2, "value": 2.0, "tags": {"room": "r3"}});
+        let response = server.post_json("/api/v1/datapoints", &drifted).await?;
+        assert_eq!(response.status, 409);
+        let text = response.text();
+        assert!(!text.contains("alice") && !text.contains("SN-1"), "{}", text);
+        assert!(text.contains("[redacted]"), "{}", text);
+        let block = EncodedBlock::encode(&[(10, 1.0)]);
+        let headers = [("x-block-count", "1"), ("x-block-min-timestamp", "10"), ("x-block-max-timestamp", "10"), ("x-block-tags", r#"{"room":"r3"}"#)];
+        let response = server.request("POST", "/api/v1/series/temp/blocks", &headers, &block.data).await?;
+        assert_eq!(response.status, 409);
+        let text = response.text();
+        assert!(!text.contains("alice") && !text.contains("SN-1") && text.contains("[redacted]"), "{}", text);
 
+        // 带敏感标签的数据只有有权限的key可以导出
+        assert_eq!(server.get("/api/v1/admin/export").await?.status, 403);
+        assert_eq!(server.request("GET", "/api/v1/admin/export", &[ops], b"").await?.status, 200);
+
+        // 引擎接口不受影响
+        assert_eq!(server.db_handle.query_range("temp", None, None).await?[0].tags["owner"], "alice");
+        server.stop().await?;
+
+        // 配置接口不返回API key
+        let config = Config { unredacted_api_keys: vec!["ops-key".to_string()], ..Config::default() };
+        let server = testing::spawn_test_server_with_config(config).await?;
+        let body = server.get("/api/v1/admin/config").await?.json()?;
+        assert_eq!(body["data"]["unredacted_api_keys"], serde_json::json!(["[redacted]"]));
+        server.stop().await?;
+
+        // 旧格式文件在块内保存标签，manifest 中的标签改掉之后导出仍然需要权限
+        let temp_dir = TempDir::new()?;
+        let legacy = SeriesData {
+            series_key: "legacy".to_string(),
+            compressed_data: compression::format::encode_block_v2(&[(1, 1.0)]),
+            tags: tags.clone(),
+            min_timestamp: 1,
+            max_timestamp: 1,
+            count: 1,
+            ingest_data: Vec::new(),
+        };
+        SSTable::new(temp_dir.path().join("sstable_legacy.data"))?.write_data(&[legacy])?;
+        let db = TimeSeriesDB::builder(temp_dir.path()).sensitive_tag_keys(vec!["serial".to_string()]).build()?;
+        assert!(db.update_series_tags("legacy", BTreeMap::from([("room".to_string(), "r1".to_string())]))?);
+        let snapshot = db.export_snapshot().await?;
+        assert!(!api::TagView::from_headers(&db, &axum::http::HeaderMap::new()).can_export(&db, &snapshot)?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_low_disk_space_rejects_http_writes() -> anyhow::Result<()> {
+        let space = std::sync::Arc::new(MockDiskSpace::new(10_000));
+        let server = testing::spawn_test_server_with(|b| b.disk_watermarks(1_000, 500).disk_space(space.clone())).await?;
+        let point = |timestamp: u64| serde_json::json!({"series_key": "cpu", "timestamp": timestamp, "value": 1.0, "tags": {}});
+        assert_eq!(server.post_json("/api/v1/datapoints", &point(1)).await?.status, 200);
+
+        // 可用空间低于水位线后写入返回507，统计接口会立即重新检查空间
+        space.set(800);
+        let body = server.get("/stats").await?.json()?;
+        assert_eq!(body["data"]["disk_free_bytes"], 800);
+        assert_eq!(server.db_handle.disk_level(), DiskLevel::Low);
+        let response = server.post_json("/api/v1/datapoints", &point(2)).await?;
+        assert_eq!(response.status, axum::http::StatusCode::INSUFFICIENT_STORAGE);
+        let batch = serde_json::json!([point(3)]);
+        assert_eq!(server.post_json("/api/v1/datapoints/batch", &batch).await?.status, 507);
+        let stream = server.request("POST", "/api/v1/datapoints/stream", &[], point(4).to_string().as_bytes()).await?;
+        assert_eq!(stream.status, 507);
+        let err = server.db_handle.insert("cpu".to_string(), DataPoint { timestamp: 5, value: 1.0, tags: BTreeMap::new() }).await.unwrap_err();
+        assert!(matches!(DbError::from_io(&err), Some(DbError::DiskFull { free_bytes: 800, watermark_bytes: 1_000 })));
+        assert_eq!(server.db_handle.query_range("cpu", None, None).await?.len(), 1);
+
+        // 空间恢复后重新接受写入
+        space.set(10_000);
+        server.get("/stats").await?;
+        assert_eq!(server.post_json("/api/v1/datapoints", &point(6)).await?.status, 200);
+
+        server.stop().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_stats_summary_from_metadata() -> anyhow::Result<()> {
+        // 1_700_000_000 为当天（UTC）第80000秒
+        const NOW: u64 = 1_700_000_000;
+        let clock = std::sync::Arc::new(MockClock::from_secs(NOW));
+        let server =
+            testing::spawn_test_server_with(|b| b.memtable_threshold(4).max_mapped_sstables(1).clock(clock.clone())).await?;
+        let insert = |series_key: &'static str, timestamp: u64| {
+            server.db_handle.insert(series_key.to_string(), DataPoint { timestamp, value: 1.0, tags: BTreeMap::new() })
+        };
+        for (series_key, count) in [("a", 6), ("b", 3), ("c", 1)] {
+            for timestamp in 0..count {
+                insert(series_key, NOW + timestamp).await?;
+            }
+        }
+        // 新系列按最早的数据点计算，补写的前一天的数据不算当天的新系列
+        clock.advance(std::time::Duration::from_secs(120));
+        insert("d", NOW - 86_400).await?;
+        insert("d", NOW - 86_399).await?;
+
+        let body = server.get("/api/v1/stats/summary").await?.json()?;
+        let summary: StatsSummary = serde_json::from_value(body["data"].clone())?;
+        assert_eq!(summary.computed_at, 1_700_000_120);
+        assert_eq!((summary.total_series, summary.total_points, summary.sstable_count), (4, 12, 3));
+        assert_eq!(summary.total_compressed_bytes, server.db_handle.get_stats().await?.total_bytes);
+        assert_eq!(summary.ingested_points, IngestedPoints { last_1m: 2, last_5m: 12, last_1h: 12 });
+        assert_eq!(summary.new_series_today, 3);
+        let top: Vec<(&str, u64)> = summary.top_series.iter().map(|s| (s.series_key.as_str(), s.points)).collect();
+        assert_eq!(top, vec![("a", 6), ("b", 3), ("d", 2), ("c", 1)]);
+        assert!(summary.sstables_updated_at.is_some());
+        assert_eq!(summary.notes.len(), 1);
+
+        // 不读取任何SSTable：映射缓存只能容纳一个文件，读取文件必然重新映射
+        let maps = || async { anyhow::Ok(server.db_handle.get_stats().await?.mappings.maps) };
+        let (first, second) = (maps().await?, maps().await?);
+        for _ in 0..3 {
+            server.db_handle.stats_summary();
+        }
+        let third = maps().await?;
+        assert!(second > first);
+        assert_eq!(third - second, second - first);
+
+        // 写入计数按时间窗口滚动，跨天后新系列重新计数；同一时间戳重复写入在compaction之前重复计数
+        clock.advance(std::time::Duration::from_secs(7000));
+        insert("a", NOW).await?;
+        let summary = server.db_handle.stats_summary();
+        assert_eq!(summary.ingested_points, IngestedPoints { last_1m: 1, last_5m: 1, last_1h: 1 });
+        assert_eq!((summary.new_series_today, summary.total_points, summary.memtable_points), (0, 13, 1));
+        assert_eq!(summary.top_series[0], SeriesPointCount { series_key: "a".to_string(), points: 7 });
+
+        server.stop().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_for_each_point_matches_query_range() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let db = TimeSeriesDB::builder(temp_dir.path()).memtable_threshold(50).build()?;
+        // 数据分布在多个SSTable和内存表中，部分时间戳被后写入的值覆盖
+        for timestamp in 0..230u64 {
+            db.insert("load".to_string(), DataPoint { timestamp, value: (timestamp % 17) as f64 * 0.5, tags: BTreeMap::new() }).await?;
+        }
+        for timestamp in (0..230u64).step_by(7) {
+            db.insert("load".to_string(), DataPoint { timestamp, value: -1.0, tags: BTreeMap::new() }).await?;
+        }
+        assert!(db.sstable_infos().len() > 1);
+
+        for (start, end) in [(None, None), (Some(40), Some(180)), (Some(500), None)] {
+            let expected = db.query_range("load", start, end).await?;
+            let (mut sum, mut last) = (0.0, None);
+            let points = db.for_each_point("load", start, end, |timestamp, value| {
+                assert!(last < Some(timestamp));
+                last = Some(timestamp);
+                sum += value;
+            })?;
+            assert_eq!(points, expected.len());
+            assert_eq!(sum, expected.iter().map(|dp| dp.value).sum::<f64>());
+        }
+        assert_eq!(db.for_each_point("missing", None, None, |_, _| unreachable!())?, 0);
+        assert!(db.for_each_point("load", Some(10), Some(5), |_, _| {}).is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_downsample_old_data_on_compaction() -> anyhow::Result<()> {
+        const NOW: u64 = 1_000_000;
+        let clock = std::sync::Arc::new(MockClock::from_secs(NOW));
+        let server = testing::spawn_test_server_with(|b| b.memtable_threshold(240).clock(clock.clone())).await?;
+        let db = &server.db_handle;
+        let value = |timestamp: u64| (timestamp / 10 % 7) as f64;
+        // 最近两小时每10秒一个点，两个系列的数据相同
+        for series_key in ["sensor.a", "other"] {
+            for timestamp in (NOW - 7200..NOW).step_by(10) {
+                db.insert(series_key.to_string(), DataPoint { timestamp, value: value(timestamp), tags: BTreeMap::new() }).await?;
+            }
+        }
+        let raw = db.query_range("sensor.a", None, None).await?;
+
+        let invalid = serde_json::json!({"rules": [{"match": {"prefix": "sensor."}, "after_secs": 3600, "interval_secs": 0, "function": "avg"}]});
+        assert_eq!(server.put_json("/api/v1/admin/downsampling", &invalid).await?.status, 400);
+        let policy = serde_json::json!({"rules": [{"match": {"prefix": "sensor."}, "after_secs": 3600, "interval_secs": 300, "function": "avg"}]});
+        assert_eq!(server.put_json("/api/v1/admin/downsampling", &policy).await?.status, 200);
+        let body = server.get("/api/v1/admin/downsampling").await?.json()?;
+        assert_eq!(body["data"]["rules"][0]["function"], "avg");
+
+        // 早于 NOW-3600 按300秒对齐之前的点每桶聚合为一个点，桶起点为时间戳
+        let expected = |raw: &[DataPoint], before: u64| -> Vec<(u64, f64)> {
+            let mut buckets: BTreeMap<u64, Vec<f64>> = BTreeMap::new();
+            let mut points = Vec::new();
+            for dp in raw {
+                if dp.timestamp < before {
+                    buckets.entry(dp.timestamp / 300 * 300).or_default().push(dp.value);
+                } else {
+                    points.push((dp.timestamp, dp.value));
+                }
+            }
+            let mut aggregated: Vec<(u64, f64)> =
+                buckets.into_iter().map(|(ts, values)| (ts, values.iter().sum::<f64>() / values.len() as f64)).collect();
+            aggregated.extend(points);
+            aggregated
+        };
+        let points = |series: &[DataPoint]| series.iter().map(|dp| (dp.timestamp, dp.value)).collect::<Vec<_>>();
+        let before = 996_300;
+        let report = db.compact().await?;
+        assert_eq!(report.points_downsampled, 350 - 12);
+        let downsampled = db.query_range("sensor.a", None, None).await?;
+        assert_eq!(points(&downsampled), expected(&raw, before));
+        assert_eq!(db.query_range("other", None, None).await?.len(), 720);
+        let segment = ResolutionSegment { start: 0, end: before, interval: 300, function: AggregateFn::Avg };
+        assert_eq!(db.series_resolution("sensor.a"), vec![segment.clone()]);
+        assert!(db.series_resolution("other").is_empty());
+        let body = server.get("/api/v1/series/sensor.a").await?.json()?;
+        assert_eq!(body["data"]["resolution"][0]["end"], before);
+
+        // 旧数据占用的块字节数按点数同比例减少
+        let bytes = |series_key: &str| db.series_compression_report(series_key).map(|report| report.unwrap().compressed_bytes);
+        assert!(bytes("sensor.a")? * 4 < bytes("other")? * 3);
+
+        // 重复compaction不会再次聚合
+        for timestamp in NOW..NOW + 240 {
+            db.insert("sensor.a".to_string(), DataPoint { timestamp, value: 1.0, tags: BTreeMap::new() }).await?;
+        }
+        let report = db.compact().await?;
+        assert!(report.skipped_reason.is_none());
+        assert_eq!(report.points_downsampled, 0);
+        let again = db.query_range("sensor.a", None, Some(NOW - 1)).await?;
+        assert_eq!(points(&again), points(&downsampled));
+        assert_eq!(db.series_resolution("sensor.a"), vec![segment]);
+
+        // 时间推进后只聚合新变旧的数据，范围与上一段合并
+        clock.advance(std::time::Duration::from_secs(600));
+        for timestamp in NOW + 240..NOW + 480 {
+            db.insert("sensor.a".to_string(), DataPoint { timestamp, value: 1.0, tags: BTreeMap::new() }).await?;
+        }
+        let report = db.compact().await?;
+        assert_eq!(report.points_downsampled, 60 - 2);
+        let later = db.query_range("sensor.a", None, Some(NOW - 1)).await?;
+        assert_eq!(points(&later), expected(&raw, 996_900));
+        assert_eq!(db.series_resolution("sensor.a"), vec![ResolutionSegment { start: 0, end: 996_900, interval: 300, function: AggregateFn::Avg }]);
+
+        server.stop().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_downsample_compaction_fails_when_resolution_not_saved() -> anyhow::Result<()> {
+        const NOW: u64 = 1_000_000;
+        let temp_dir = TempDir::new()?;
+        let open = || {
+            TimeSeriesDB::builder(temp_dir.path())
+                .memtable_threshold(240)
+                .clock(std::sync::Arc::new(MockClock::from_secs(NOW)))
+                .build()
+        };
+        let db = open()?;
+        for timestamp in (NOW - 7200..NOW).step_by(10) {
+            db.insert("sensor.a".to_string(), DataPoint { timestamp, value: 1.0, tags: BTreeMap::new() }).await?;
+        }
+        let policy = serde_json::json!({"rules": [{"match": {"prefix": "sensor."}, "after_secs": 3600, "interval_secs": 300, "function": "avg"}]});
+        db.set_downsample_policy(serde_json::from_value(policy)?)?;
+        let files_before: Vec<String> = db.sstable_infos().into_iter().map(|info| info.file_name).collect();
+        assert_eq!(files_before.len(), 3);
+
+        // manifest的临时文件位置被目录占住，保存失败
+        let blocker = temp_dir.path().join(format!("{}.tmp", MANIFEST_FILE));
+        std::fs::create_dir(&blocker)?;
+        assert!(db.compact().await.is_err());
+        let files: Vec<String> = db.sstable_infos().into_iter().map(|info| info.file_name).collect();
+        assert_eq!(files, files_before);
+        assert_eq!(db.query_range("sensor.a", None, None).await?.len(), 720);
+        assert!(db.series_resolution("sensor.a").is_empty());
+        let data_files = std::fs::read_dir(temp_dir.path())?
+            .filter(|entry| entry.as_ref().is_ok_and(|entry| entry.path().extension().is_some_and(|ext| ext == "data")))
+            .count();
+        assert_eq!(data_files, 3);
+
+        // 重启后数据保持原始分辨率，也没有分辨率记录
+        std::fs::remove_dir(&blocker)?;
+        drop(db);
+        let db = open()?;
+        assert_eq!(db.query_range("sensor.a", None, None).await?.len(), 720);
+        assert!(db.series_resolution("sensor.a").is_empty());
+
+        // 可以保存后compaction正常降采样，分辨率记录随之保存
+        let report = db.compact().await?;
+        assert_eq!(report.points_downsampled, 350 - 12);
+        drop(db);
+        let db = open()?;
+        assert_eq!(db.series_resolution("sensor.a"), vec![ResolutionSegment { start: 0, end: 996_300, interval: 300, function: AggregateFn::Avg }]);
+        assert_eq!(db.query_range("sensor.a", None, None).await?.len(), 720 - 338);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_deleted_ratio_triggers_compaction() -> anyhow::Result<()> {
+        #[derive(Debug)]
+        struct CompactionWatcher(tokio::sync::mpsc::UnboundedSender<CompactionReport>);
+
+        impl EngineObserver for CompactionWatcher {
+            fn on_compaction_finished(&self, report: &CompactionReport) {
+                let _ = self.0.send(report.clone());
+            }
+        }
+
+        let temp_dir = TempDir::new()?;
+        let (sender, mut finished) = tokio::sync::mpsc::unbounded_channel();
+        let db = TimeSeriesDB::builder(temp_dir.path())
+            .memtable_threshold(100)
+            .compaction_deleted_ratio(0.5)
+            .with_observer(std::sync::Arc::new(CompactionWatcher(sender)))
+            .build()?;
+        // 每个SSTable中 a 占70个点，b 占30个点
+        for batch in 0..4u64 {
+            for timestamp in batch * 70..(batch + 1) * 70 {
+                db.insert("a".to_string(), DataPoint { timestamp, value: timestamp as f64 * 0.25, tags: BTreeMap::new() }).await?;
+            }
+            for timestamp in batch * 30..(batch + 1) * 30 {
+                db.insert("b".to_string(), DataPoint { timestamp, value: timestamp as f64, tags: BTreeMap::new() }).await?;
+            }
+        }
+        let infos = db.sstable_infos();
+        assert_eq!(infos.len(), 4);
+        let untouched = infos[3].file_name.clone();
+        let size_before: u64 = infos[..3].iter().map(|info| info.size_bytes).sum();
+
+        // 占比未超过阈值时不触发
+        assert!(db.delete("a", Some(0), false).await?);
+        assert_eq!(db.sstable_infos().iter().map(|info| info.deleted_points).collect::<Vec<_>>(), vec![1, 0, 0, 0]);
+        tokio::task::yield_now().await;
+        assert!(db.compaction_history().is_empty());
+
+        // 前三个文件删除了70%的点，最后一个文件删除了40个，只合并前三个文件
+        assert_eq!(db.delete_range("a", Some(0), Some(249), false).await?, 249);
+        let report = tokio::time::timeout(std::time::Duration::from_secs(10), finished.recv()).await?.expect("compaction完成");
+        assert_eq!(report.inputs.len(), 3);
+        assert!(!report.inputs.iter().any(|input| input.file_name == untouched));
+        assert_eq!(db.compaction_history().len(), 1);
+
+        let infos = db.sstable_infos();
+        assert_eq!(infos.len(), 2);
+        let merged = infos.iter().find(|info| info.file_name != untouched).expect("合并后的文件");
+        assert_eq!((merged.total_points, merged.deleted_points), (90, 0));
+        assert!(merged.size_bytes * 2 < size_before);
+        let kept = infos.iter().find(|info| info.file_name == untouched).expect("未合并的文件");
+        assert_eq!((kept.total_points, kept.deleted_points), (60, 40));
+        assert_eq!(db.query_range("a", None, None).await?.len(), 30);
+        let b = db.query_range("b", None, None).await?;
+        assert_eq!(b.iter().map(|dp| dp.timestamp).collect::<Vec<_>>(), (0..120).collect::<Vec<_>>());
+
+        // 已删除的点数随manifest保存，重启后恢复
+        db.close().await?;
+        let db = TimeSeriesDB::builder(temp_dir.path()).compaction_deleted_ratio(0.5).build()?;
+        let infos = db.sstable_infos();
+        let kept = infos.iter().find(|info| info.file_name == untouched).expect("未合并的文件");
+        assert_eq!(kept.deleted_points, 40);
+        assert_eq!(infos.iter().map(|info| info.deleted_points).sum::<usize>(), 40);
+        db.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_relative_time_expressions() -> anyhow::Result<()> {
+        const NOW: u64 = 1_700_000_000;
+        let clock = std::sync::Arc::new(MockClock::from_secs(NOW));
+        let server = testing::spawn_test_server_with(|b| b.clock(clock.clone())).await?;
+        let db = &server.db_handle;
+        // 最近两小时每分钟一个点
+        for minute in 0..=120u64 {
+            db.insert("load".to_string(), DataPoint { timestamp: NOW - minute * 60, value: minute as f64, tags: BTreeMap::new() }).await?;
+        }
+        let timestamps = |body: &serde_json::Value| -> Vec<u64> {
+            body["data"].as_array().unwrap().iter().map(|dp| dp["timestamp"].as_u64().unwrap()).collect()
+        };
+        let minutes_ago = |range: std::ops::RangeInclusive<u64>| -> Vec<u64> { range.rev().map(|minute| NOW - minute * 60).collect() };
+
+        // 引擎按时钟换算范围
+        let last = TimeRange::last(std::time::Duration::from_secs(900));
+        assert_eq!(db.resolve_time_range(&last), ResolvedRange { start_time: Some(NOW - 900), end_time: Some(NOW) });
+        let points = db.query_time_range("load", &last).await?;
+        assert_eq!(points.iter().map(|dp| dp.timestamp).collect::<Vec<_>>(), minutes_ago(0..=15));
+
+        let response = server.get("/api/v1/series/load/datapoints?last=15m").await?;
+        assert_eq!(response.status, 200);
+        let body = response.json()?;
+        assert_eq!(timestamps(&body), minutes_ago(0..=15));
+        assert_eq!(body["range"], serde_json::json!({"start_time": NOW - 900, "end_time": NOW}));
+
+        let body = server.get("/api/v1/series/load/datapoints?start_time=now-1h&end_time=now-30m").await?.json()?;
+        assert_eq!(timestamps(&body), minutes_ago(30..=60));
+        assert_eq!(body["range"]["end_time"], NOW - 1800);
+        // '+' 在查询参数中需要编码
+        let body = server.get("/api/v1/series/load/datapoints?start_time=now-1h30m&end_time=now%2B5m").await?.json()?;
+        assert_eq!(body["range"], serde_json::json!({"start_time": NOW - 5400, "end_time": NOW + 300}));
+        assert_eq!(timestamps(&body), minutes_ago(0..=90));
+        // 只用绝对时间戳时不返回 range
+        let body = server.get(&format!("/api/v1/series/load/datapoints?start_time={}", NOW - 120)).await?.json()?;
+        assert_eq!(timestamps(&body), minutes_ago(0..=2));
+        assert!(body.get("range").is_none());
+
+        // 范围随服务端时间推进
+        clock.advance(std::time::Duration::from_secs(600));
+        let body = server.get("/api/v1/series/load/gaps?expected_interval=60&last=15m").await?.json()?;
+        assert_eq!(body["range"], serde_json::json!({"start_time": NOW - 300, "end_time": NOW + 600}));
+        assert_eq!(body["data"]["gaps"], serde_json::json!([]));
+        let response = server.get("/api/v1/series/load/chunks?chunk=5m&last=1h&envelope=false").await?;
+        assert_eq!(response.status, 200);
+        assert_eq!(response.header("x-range-start"), Some((NOW - 3000).to_string().as_str()));
+        assert_eq!(response.json()?["chunks"].as_array().unwrap().len(), 13);
+
+        // 无效的表达式返回400和出错位置
+        for (uri, expected) in [
+            ("/api/v1/series/load/datapoints?start_time=now-1x", "start_time: 无效的时间表达式 \"now-1x\"，位置 5"),
+            ("/api/v1/series/load/datapoints?end_time=nowish", "位置 3"),
+            ("/api/v1/series/load/downsample?interval=60&start_time=yesterday", "位置 0"),
+            ("/api/v1/series/load/derivative?last=15", "last: 无效的时间表达式 \"15\"，位置 2: 缺少时间单位"),
+            ("/api/v1/series/load/histogram?last=15m&start_time=now", "last 不能与 start_time/end_time 同时指定"),
+            ("/api/v1/series/load/chunks?chunk=5m&start_time=now-1h", "需要指定 start_time 和 end_time"),
+        ] {
+            let response = server.get(uri).await?;
+            assert_eq!(response.status, 400, "{}", uri);
+            let message = response.json()?["message"].as_str().unwrap().to_string();
+            assert!(message.contains(expected), "{}: {}", uri, message);
+        }
+
+        assert_eq!(parse_time_bound("1700000000"), Ok(TimeBound::At(1_700_000_000)));
+        assert_eq!(parse_time_bound("now"), Ok(TimeBound::now()));
+        assert_eq!(parse_time_bound("now-1h30m"), Ok(TimeBound::Ago(std::time::Duration::from_secs(5400))));
+        assert_eq!(parse_time_bound("now-").unwrap_err().position, 4);
+        assert_eq!(parse_duration_expr("2w1d").unwrap(), std::time::Duration::from_secs(15 * 86400));
+        assert_eq!(parse_duration_expr("1h0s"), Ok(std::time::Duration::from_secs(3600)));
+        assert_eq!(parse_duration_expr("0m").unwrap_err().message, "时间长度必须大于0");
+
+        server.stop().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_integral_of_ramp() -> anyhow::Result<()> {
+        let server = testing::spawn_test_server_with(|b| b.memtable_threshold(4)).await?;
+        let db = &server.db_handle;
+        // v = 2t，[a, b] 上的面积为 b² - a²，梯形法对线性函数是精确的；数据分布在多个SSTable和内存表中
+        for timestamp in (0..=100u64).step_by(10) {
+            db.insert("ramp".to_string(), DataPoint { timestamp, value: 2.0 * timestamp as f64, tags: BTreeMap::new() }).await?;
+        }
+        assert!(db.sstable_infos().len() > 1);
+
+        assert_eq!(db.integral("ramp", None, None).await?, 10_000.0);
+        assert_eq!(db.integral("ramp", Some(20), Some(50)).await?, 2_500.0 - 400.0);
+        // 范围两端不插值，只计算 30 到 50 之间
+        assert_eq!(db.integral("ramp", Some(25), Some(55)).await?, 2_500.0 - 900.0);
+        assert_eq!(db.integral("ramp", Some(30), Some(30)).await?, 0.0);
+        assert_eq!(db.integral("missing", None, None).await?, 0.0);
+        assert!(db.integral("ramp", Some(50), Some(20)).await.is_err());
+
+        let response = server.get("/api/v1/series/ramp/integral?start=0&end=60").await?;
+        assert_eq!(response.status, 200);
+        assert_eq!(response.json()?["data"]["integral"], 3_600.0);
+
+        server.stop().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_memtable_shares_interned_tag_sets() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let points = 2_000;
+        let db = TimeSeriesDB::builder(temp_dir.path()).memtable_threshold(points + 2).build()?;
+        let tags: BTreeMap<String, String> = [("host", "server-01"), ("region", "us-east-1"), ("rack", "r12"), ("unit", "celsius")]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        for i in 0..points as u64 {
+            db.insert("temp".to_string(), DataPoint { timestamp: 1000 + i, value: i as f64, tags: tags.clone() }).await?;
+        }
+        let other = BTreeMap::from([("host".to_string(), "server-02".to_string())]);
+        db.insert("load".to_string(), DataPoint { timestamp: 1000, value: 1.0, tags: other }).await?;
+
+        // 每个不同的标签集合只保存一份
+        let stats = db.get_stats().await?;
+        assert_eq!(stats.memtable_tag_sets, 2);
+        // 实际占用的内存：每个点各自持有一份标签（驻留之前的内存表）与共享驻留的标签相比
+        let (owned, owned_bytes) = measure_heap(|| {
+            let points: Vec<DataPoint> = (0..points as u64)
+                .map(|i| DataPoint { timestamp: 1000 + i, value: i as f64, tags: tags.clone() })
+                .collect();
+            BTreeMap::from([("temp".to_string(), points)])
+        });
+        let (memtable, shared_bytes) = measure_heap(|| {
+            let mut memtable = Memtable::new(points + 2);
+            for i in 0..points as u64 {
+                memtable.insert("temp".to_string(), 1000 + i, i as f64, &tags);
+            }
+            memtable
+        });
+        assert!(shared_bytes * 5 < owned_bytes, "共享后 {} 字节，独立保存 {} 字节", shared_bytes, owned_bytes);
+        // 估算不计BTreeMap节点等额外开销，与实际占用相差不大
+        let estimated = memtable.estimated_bytes();
+        assert!(estimated <= shared_bytes && shared_bytes < estimated * 2, "估算 {} 字节，实际 {} 字节", estimated, shared_bytes);
+        drop((owned, memtable));
+
+        // 查询结果仍然带完整的标签
+        let results = db.query_range("temp", Some(1000), Some(1002)).await?;
+        assert_eq!(results.len(), 3);
+        assert!(results.iter().all(|dp| dp.tags == tags));
+
+        // 达到阈值刷新后驻留表清空，刷新的数据标签不变
+        db.insert("temp".to_string(), DataPoint { timestamp: 5000, value: 0.0, tags: tags.clone() }).await?;
+        let stats = db.get_stats().await?;
+        assert_eq!((stats.sstable_count, stats.memtable_size, stats.memtable_tag_sets), (1, 0, 0));
+        let results = db.query_range("temp", None, None).await?;
+        assert_eq!(results.len(), points + 1);
+        assert!(results.iter().all(|dp| dp.tags == tags));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_background_task_restarts_after_failure() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let db = TimeSeriesDB::builder(temp_dir.path()).memtable_threshold(100).build()?;
+        db.spawn_compaction_task(std::time::Duration::from_secs(3600));
+
+        // 第一次panic，第三次返回错误，其余成功
+        let attempts = std::sync::Arc::new(std::sync::atomic::AtomicU64::new(0));
+        let counter = attempts.clone();
+        db.spawn_periodic_task("flaky", std::time::Duration::from_millis(20), move || {
+            let attempt = counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            async move {
+                match attempt {
+                    0 => panic!("第一次执行panic"),
+                    2 => Err(std::io::Error::other("第三次执行失败")),
+                    _ => Ok(()),
+                }
+            }
+        });
+
+        let status = testing::wait_until(std::time::Duration::from_secs(15), || async {
+            let status = db.background_task_status()["flaky"].clone();
+            Ok((status.runs >= 4).then_some(status))
+        })
+        .await?;
+        assert_eq!((status.restarts, status.panics, status.consecutive_failures), (2, 1, 0));
+        assert_eq!(status.last_error.as_deref(), Some("第三次执行失败"));
+        assert!(status.last_run.is_some() && status.last_error_at.is_some());
+
+        // panic之后引擎仍然可用，其他任务不受影响
+        db.insert("after_panic".to_string(), DataPoint { timestamp: 1, value: 1.0, tags: BTreeMap::new() }).await?;
+        assert_eq!(db.query_range("after_panic", None, None).await?.len(), 1);
+        let stats = db.get_stats().await?;
+        assert_eq!(stats.background_tasks["compaction"].runs, 1);
+        assert_eq!(stats.background_tasks["compaction"].restarts, 0);
+
+        db.close().await?;
         Ok(())
     }
 
@@ -95,6 +5365,36 @@ mod tests {
         assert_eq!(decompressed[3], (1609459380, 23.5));
     }
 
+    #[test]
+    fn test_gorilla_irregular_roundtrip() {
+        // 大跨度、乱序的时间差以及符号翻转的值
+        let points = vec![
+            (1_600_000_000u64, 1.0f64),
+            (1_600_900_000, -1.000_000_000_000_000_2),
+            (1_600_900_064, 3.5),
+            (1_600_900_064, 3.5),
+            (1_600_899_000, f64::MIN_POSITIVE),
+            (1_700_000_000, -0.0),
+            (1_500_000_000, 42.0),
+            (1_500_000_060, 42.0),
+            (1_500_000_120, -42.000_000_000_000_01),
+            (1_500_000_120 + 2048, 1e300),
+            (1_500_000_120 + 2048 + 2048 - 64, -1e-300),
+        ];
+
+        let mut compressor = GorillaCompressor::new();
+        for (ts, value) in &points {
+            compressor.compress_datapoint(*ts, *value);
+        }
+        let decompressed = GorillaDecompressor::new(compressor.finish()).decompress_all();
+
+        assert_eq!(decompressed.len(), points.len());
+        for ((ts, value), (expected_ts, expected_value)) in decompressed.iter().zip(&points) {
+            assert_eq!(ts, expected_ts);
+            assert_eq!(value.to_bits(), expected_value.to_bits());
+        }
+    }
+
     #[tokio::test]
     async fn test_compaction() -> anyhow::Result<()> {
         let temp_dir = TempDir::new()?;
@@ -136,5 +5436,63 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_for_each_series() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let db = TimeSeriesDB::new(temp_dir.path(), 4)?;
+        let point = |timestamp: u64, value: f64| DataPoint { timestamp, value, tags: BTreeMap::new() };
+
+        // cpu 与 mem 各写满一次内存表，落盘为两个SSTable；之后的点留在内存表
+        for ts in 0..4 {
+            db.insert("cpu".to_string(), point(ts, ts as f64)).await?;
+        }
+        for ts in 0..4 {
+            db.insert("mem".to_string(), point(ts, 1.0)).await?;
+        }
+        db.insert("cpu".to_string(), point(2, 99.0)).await?;
+        db.insert("cpu".to_string(), point(10, 10.0)).await?;
+        let mut tags = BTreeMap::new();
+        tags.insert("host".to_string(), "a".to_string());
+        db.insert("disk".to_string(), DataPoint { timestamp: 5, value: 5.0, tags: tags.clone() }).await?;
+
+        let mut seen = Vec::new();
+        let visited = db.for_each_series(|series_key, metadata, points| {
+            seen.push((series_key.to_string(), metadata.clone(), points.collect::<Vec<_>>()));
+            std::ops::ControlFlow::Continue(())
+        })?;
+        assert_eq!(visited, 3);
+
+        let keys: Vec<&str> = seen.iter().map(|(key, _, _)| key.as_str()).collect();
+        assert_eq!(keys, vec!["cpu", "disk", "mem"]);
+
+        // 合并结果与逐系列查询一致
+        for (series_key, metadata, points) in &seen {
+            let expected: Vec<(u64, f64)> = db
+                .query_range(series_key, None, None)
+                .await?
+                .into_iter()
+                .map(|dp| (dp.timestamp, dp.value))
+                .collect();
+            assert_eq!(points, &expected, "{}", series_key);
+            assert_eq!(metadata.last_timestamp, expected.last().map(|(ts, _)| *ts));
+        }
+        assert_eq!(seen[0].2.len(), 5);
+        assert_eq!(seen[0].1.blocks, 1);
+        assert_eq!(seen[1].1.blocks, 0);
+        assert_eq!(seen[1].1.tags, tags);
+        assert!(seen[1].1.first_seen.is_some());
+
+        // 提前停止
+        let mut first = Vec::new();
+        let visited = db.for_each_series(|series_key, _, _| {
+            first.push(series_key.to_string());
+            std::ops::ControlFlow::Break(())
+        })?;
+        assert_eq!(visited, 1);
+        assert_eq!(first, vec!["cpu"]);
+
+        Ok(())
+    }
 }
 
@@ -1,8 +1,4 @@
-use axum::{
-    routing::{get, post, put, delete},
-    Router,
-    middleware::from_fn,
-};
+use axum::middleware::from_fn;
 use std::net::SocketAddr;
 use std::time::Duration;
 use tower::ServiceBuilder;
@@ -13,14 +9,7 @@ use tower_http::{
 };
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
-use timeseries_db::{
-    TimeSeriesDB,
-    api::handlers::{
-        create_datapoint, create_datapoints_batch, query_datapoints, 
-        update_datapoint, delete_datapoint, delete_series, list_series,
-        health_check, db_stats, get_series_info, trigger_compaction
-    }
-};
+use timeseries_db::{Config, api::router_with_config};
 
 // 中间件：请求日志
 async fn logging_middleware(
@@ -59,53 +48,22 @@ async fn main() -> anyhow::Result<()> {
     // 打印启动横幅
     print_banner();
     
-    // 初始化数据库
-    let memtable_threshold = std::env::var("MEMTABLE_THRESHOLD")
-        .unwrap_or_else(|_| "1000".to_string())
-        .parse()
-        .unwrap_or(1000);
-    
-    let data_dir = std::env::var("DATA_DIR")
-        .unwrap_or_else(|_| "./tsdb_data".to_string());
+    // 加载配置：配置文件（--config 或 TSDB_CONFIG）、环境变量、默认值依次合并
+    let config = Config::from_env()?;
+    tracing::info!("初始化数据库，数据目录: {}, 内存表阈值: {}", config.data_dir.display(), config.memtable_threshold);
+    let db = config.builder().build()?;
     
-    tracing::info!("初始化数据库，数据目录: {}, 内存表阈值: {}", data_dir, memtable_threshold);
-    let db = TimeSeriesDB::new(&data_dir, memtable_threshold)?;
+    // 在监听端口前开始预热，进度见 /health
+    db.spawn_warmup();
     
     // 启动定期compaction任务
-    let db_for_compaction = db.clone();
-    tokio::spawn(async move {
-        let mut interval = tokio::time::interval(Duration::from_secs(300)); // 5分钟
-        loop {
-            interval.tick().await;
-            if let Err(e) = db_for_compaction.compact().await {
-                tracing::error!("定期compaction失败: {}", e);
-            } else {
-                tracing::info!("定期compaction执行完成");
-            }
-        }
-    });
+    db.spawn_compaction_task(Duration::from_secs(300)); // 5分钟
+    
+    // 启动定期保留策略任务
+    db.spawn_retention_task(Duration::from_secs(config.retention_check_secs));
     
     // 构建路由
-    let app = Router::new()
-        // 健康检查和统计
-        .route("/health", get(health_check))
-        .route("/stats", get(db_stats))
-        
-        // 数据点CRUD操作
-        .route("/api/v1/datapoints", post(create_datapoint))
-        .route("/api/v1/datapoints/batch", post(create_datapoints_batch))
-        .route("/api/v1/series/:series_key/datapoints", get(query_datapoints))
-        .route("/api/v1/series/:series_key/datapoints/:timestamp", put(update_datapoint))
-        .route("/api/v1/series/:series_key/datapoints/:timestamp", delete(delete_datapoint))
-        
-        // 系列管理
-        .route("/api/v1/series", get(list_series))
-        .route("/api/v1/series/:series_key", get(get_series_info))
-        .route("/api/v1/series/:series_key", delete(delete_series))
-        
-        // 数据库管理
-        .route("/api/v1/admin/compact", post(trigger_compaction))
-        
+    let app = router_with_config(db.clone(), config.clone())
         // 添加中间件
         .layer(
             ServiceBuilder::new()
@@ -113,15 +71,10 @@ async fn main() -> anyhow::Result<()> {
                 .layer(TimeoutLayer::new(Duration::from_secs(30)))
                 .layer(CorsLayer::permissive())
                 .layer(from_fn(logging_middleware))
-        )
-        .with_state(db);
+        );
 
     // 获取监听地址
-    let port = std::env::var("PORT")
-        .unwrap_or_else(|_| "6364".to_string())
-        .parse::<u16>()
-        .unwrap_or(6364);
-    
+    let port = config.port;
     let addr = SocketAddr::from(([0, 0, 0, 0], port));
     
     // 打印API信息
@@ -131,11 +84,28 @@ async fn main() -> anyhow::Result<()> {
     tracing::info!("🚀 时序数据库服务启动完成，监听地址: http://{}", addr);
     
     let listener = tokio::net::TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
+    let feed = db.clone();
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async move {
+            shutdown_signal().await;
+            // 结束变更订阅流，否则打开的SSE连接会让服务一直等待
+            feed.close_change_feed();
+        })
+        .await?;
+    
+    // 停止后台任务并刷新内存表
+    db.close().await?;
     
     Ok(())
 }
 
+async fn shutdown_signal() {
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        tracing::error!("监听退出信号失败: {}", e);
+    }
+    tracing::info!("收到退出信号，开始关闭服务");
+}
+
 fn print_banner() {
     println!(r#"
 ╔══════════════════════════════════════════════════════════════╗
@@ -156,27 +126,120 @@ fn print_api_info(port: u16) {
     tracing::info!("│  健康检查与统计                                                                │");
     tracing::info!("│  GET  /health                                    - 健康检查                   │");
     tracing::info!("│  GET  /stats                                     - 数据库统计                 │");
+    tracing::info!("│  GET  /api/v1/stats/summary                      - 只读元数据的统计摘要       │");
+    tracing::info!("│  GET  /api/v1/stats/prefix?prefix=               - 按系列键前缀统计           │");
     tracing::info!("├─────────────────────────────────────────────────────────────────────────────────┤");
     tracing::info!("│  数据点操作                                                                    │");
     tracing::info!("│  POST /api/v1/datapoints                         - 创建数据点                 │");
     tracing::info!("│  POST /api/v1/datapoints/batch                   - 批量创建数据点             │");
+    tracing::info!("│  POST /api/v1/datapoints/stream                  - NDJSON流式写入             │");
     tracing::info!("│  GET  /api/v1/series/{{series_key}}/datapoints     - 查询数据点                 │");
+    tracing::info!("│  GET  /api/v1/series/{{series_key}}/crossings      - 查询阈值穿越点             │");
+    tracing::info!("│  GET  /api/v1/series/{{series_key}}/downsample     - 降采样查询                 │");
+    tracing::info!("│  GET  /api/v1/series/{{series_key}}/histogram      - 累积直方图                 │");
+    tracing::info!("│  GET  /api/v1/series/{{series_key}}/gaps           - 数据缺口                   │");
+    tracing::info!("│  GET  /api/v1/series/{{series_key}}/derivative     - 逐点变化率                 │");
+    tracing::info!("│  GET  /api/v1/series/{{series_key}}/integral       - 曲线下面积                 │");
+    tracing::info!("│  POST /api/v1/series/{{series_key}}/windows        - 多窗口查询                 │");
+    tracing::info!("│  GET  /api/v1/series/{{series_key}}/chunks         - 按时间窗口汇总点数         │");
+    tracing::info!("│  PUT  /api/v1/series/{{series_key}}/datapoints     - 替换系列数据               │");
     tracing::info!("│  PUT  /api/v1/series/{{series_key}}/datapoints/{{ts}} - 更新数据点                 │");
+    tracing::info!("│  POST /api/v1/datapoints/update_batch            - 批量更新数据点             │");
     tracing::info!("│  DEL  /api/v1/series/{{series_key}}/datapoints/{{ts}} - 删除数据点                 │");
+    tracing::info!("│  POST /api/v1/series/{{series_key}}/blocks        - 上传预压缩块               │");
+    tracing::info!("├─────────────────────────────────────────────────────────────────────────────────┤");
+    tracing::info!("│  查询                                                                          │");
+    tracing::info!("│  GET  /api/v1/query/aggregate_across             - 跨系列聚合                 │");
+    tracing::info!("│  GET  /api/v1/measurements/{{m}}/aggregate         - 按度量逐系列聚合           │");
+    tracing::info!("├─────────────────────────────────────────────────────────────────────────────────┤");
+    tracing::info!("│  注释                                                                          │");
+    tracing::info!("│  POST /api/v1/annotations                        - 添加注释                   │");
+    tracing::info!("│  GET  /api/v1/annotations                        - 查询注释                   │");
+    tracing::info!("│  DEL  /api/v1/annotations/{{id}}                   - 删除注释                   │");
+    tracing::info!("├─────────────────────────────────────────────────────────────────────────────────┤");
+    tracing::info!("│  变更订阅                                                                      │");
+    tracing::info!("│  GET  /api/v1/stream                             - 订阅变更事件(SSE)          │");
     tracing::info!("├─────────────────────────────────────────────────────────────────────────────────┤");
     tracing::info!("│  系列管理                                                                      │");
     tracing::info!("│  GET  /api/v1/series                             - 获取系列列表               │");
+    tracing::info!("│  DEL  /api/v1/series?prefix=                     - 按前缀删除系列             │");
+    tracing::info!("│  GET  /api/v1/series/new?since=                    - 新出现的系列               │");
+    tracing::info!("│  GET  /api/v1/series/stale?threshold_secs=         - 停止上报的系列             │");
     tracing::info!("│  GET  /api/v1/series/{{series_key}}               - 获取系列信息               │");
     tracing::info!("│  DEL  /api/v1/series/{{series_key}}               - 删除整个系列               │");
+    tracing::info!("│  PUT  /api/v1/series/{{series_key}}/tags          - 修改系列标签               │");
+    tracing::info!("│  PATCH /api/v1/series/{{series_key}}/tags         - 合并修改系列标签           │");
+    tracing::info!("│  GET  /api/v1/series/{{series_key}}/compression   - 查看系列压缩效果           │");
+    tracing::info!("│  GET  /api/v1/series/{{series_key}}/sensitive-tags - 查看系列的敏感标签         │");
+    tracing::info!("│  PUT  /api/v1/series/{{series_key}}/sensitive-tags - 标记系列的敏感标签         │");
     tracing::info!("├─────────────────────────────────────────────────────────────────────────────────┤");
     tracing::info!("│  数据库管理                                                                    │");
     tracing::info!("│  POST /api/v1/admin/compact                      - 手动触发compaction         │");
+    tracing::info!("│  GET  /api/v1/admin/compaction/history           - compaction历史             │");
+    tracing::info!("│  POST /api/v1/admin/series/{{series_key}}/compact  - 只合并单个系列             │");
+    tracing::info!("│  GET  /api/v1/admin/operations                   - 查看进行中的管理操作       │");
+    tracing::info!("│  DEL  /api/v1/admin/operations/{{id}}              - 取消管理操作               │");
+    tracing::info!("│  GET  /api/v1/admin/retention                    - 查看保留策略               │");
+    tracing::info!("│  PUT  /api/v1/admin/retention                    - 设置保留策略               │");
+    tracing::info!("│  POST /api/v1/admin/retention/enforce            - 立即执行保留策略           │");
+    tracing::info!("│  GET  /api/v1/admin/downsampling                 - 查看降采样策略             │");
+    tracing::info!("│  PUT  /api/v1/admin/downsampling                 - 设置降采样策略             │");
+    tracing::info!("│  GET  /api/v1/admin/quotas                       - 查看存储配额及用量         │");
+    tracing::info!("│  PUT  /api/v1/admin/quotas                       - 设置存储配额               │");
+    tracing::info!("│  GET  /api/v1/admin/warmup                       - 查看预热列表与进度         │");
+    tracing::info!("│  PUT  /api/v1/admin/warmup                       - 设置预热列表               │");
+    tracing::info!("│  GET  /api/v1/admin/protected-series             - 查看删除保护列表           │");
+    tracing::info!("│  PUT  /api/v1/admin/protected-series             - 设置删除保护列表           │");
+    tracing::info!("│  POST /api/v1/admin/verify                       - 后台完整性校验             │");
+    tracing::info!("│  GET  /api/v1/admin/verify/{{id}}                  - 校验进度和结果             │");
+    tracing::info!("│  POST /api/v1/admin/verify/bounds                - 校验并修正块时间范围       │");
+    tracing::info!("│  GET  /api/v1/admin/sstables                     - SSTable文件列表            │");
+    tracing::info!("│  GET  /api/v1/admin/sstables/:file_name          - SSTable文件中的系列        │");
+    tracing::info!("│  GET  /api/v1/admin/overlap_report               - SSTable时间范围重叠报告    │");
+    tracing::info!("│  POST /api/v1/admin/relocate                     - 在数据目录之间移动SSTable  │");
+    tracing::info!("│  GET  /api/v1/admin/config                       - 当前生效的服务配置         │");
+    tracing::info!("│  GET  /api/v1/admin/export                       - 导出全部数据(tar)          │");
+    tracing::info!("│  POST /api/v1/admin/import                       - 导入归档(?force=true覆盖)  │");
+    tracing::info!("│  POST /api/v1/admin/truncate                     - 清空全部数据(需确认)       │");
     tracing::info!("└─────────────────────────────────────────────────────────────────────────────────┘");
     tracing::info!("🌐 服务地址: http://localhost:{}", port);
     tracing::info!("🔧 环境变量:");
+    tracing::info!("   TSDB_CONFIG       - TOML配置文件路径，也可用 --config 指定；键名为下列环境变量的小写形式，环境变量覆盖文件中的值");
     tracing::info!("   PORT              - 服务端口 (默认: 6364)");
     tracing::info!("   DATA_DIR          - 数据目录 (默认: ./tsdb_data)");
     tracing::info!("   MEMTABLE_THRESHOLD - 内存表阈值 (默认: 1000)");
+    tracing::info!("   MAX_MEMTABLE_AGE_SECS - 内存表最长驻留秒数 (默认: 不限制)");
+    tracing::info!("   IDLE_FLUSH_SECS   - 超过该秒数没有写入时刷新内存表 (默认: 不刷新)");
+    tracing::info!("   MIN_FLUSH_INTERVAL_MS - 两次按阈值刷新的最短间隔毫秒数，期间内存表可暂时超过阈值 (默认: 不限制)");
+    tracing::info!("   MEMTABLE_HARD_LIMIT - 刷新被推迟时内存表数据点数量上限 (默认: 阈值的4倍)");
+    tracing::info!("   DISK_LOW_WATERMARK_BYTES - 磁盘低水位线，低于时拒绝写入 (默认: 不检查)");
+    tracing::info!("   DISK_CRITICAL_WATERMARK_BYTES - 磁盘临界水位线，低于时暂停compaction (默认: 低水位线的一半)");
+    tracing::info!("   TIMESTAMP_PRECISION - 内部时间戳精度 s/ms/us/ns (默认: s，数据目录创建后不可更改)");
+    tracing::info!("   TAG_DRIFT_MODE    - 标签漂移检测 off/warn/reject (默认: off)");
+    tracing::info!("   RETENTION_CHECK_SECS - 保留策略执行周期秒数 (默认: 3600)");
+    tracing::info!("   MAX_TOTAL_BYTES   - SSTable总大小上限，超过时淘汰最旧的数据 (默认: 不限制)");
+    tracing::info!("   HOT_WINDOW_SECS   - 热数据窗口秒数，含窗口内数据的SSTable不参与compaction (默认: 不限制)");
+    tracing::info!("   MAX_FUTURE_SKEW_SECS - 时间戳最多比当前时间晚多少秒，超过时拒绝写入 (默认: 不检查)");
+    tracing::info!("   STRICT_VALIDATION - 拒绝过长的系列键、空标签键、含控制字符的标签和非有限的值 true/false (默认: false)");
+    tracing::info!("   DEFAULT_QUERY_RANGE_SECS - 查询未指定时间范围时只查询最近这么多秒，all=true 时不限制 (默认: 不限制)");
+    tracing::info!("   COMPACTION_DELETED_RATIO - SSTable中已删除的点数占比超过该值时触发compaction (默认: 不触发)");
+    tracing::info!("   MAX_REQUEST_BODY_BYTES - 写入接口请求体大小上限，超过返回413 (默认: 16777216)");
+    tracing::info!("   WARMUP_SERIES     - 启动时预热的系列，逗号分隔，以*结尾表示前缀 (默认: 沿用数据目录中保存的列表)");
+    tracing::info!("   BLOCKING_THREADS  - 解压、压缩和compaction的线程数 (默认: CPU核数)");
+    tracing::info!("   INLINE_QUERY_POINTS - 点数不超过该值的查询不交给线程池 (默认: 4096)");
+    tracing::info!("   DECODE_SCRATCH_POINTS - 每个线程复用的解压缓冲区点数上限，0为不复用 (默认: 65536)");
+    tracing::info!("   CHANGE_FEED_CAPACITY - 变更事件通道容量，跟不上的订阅方丢失最早的事件 (默认: 1024)");
+    tracing::info!("   FLUSH_COMPRESSION - 刷新写出的SSTable的通用压缩 none/lz4/zstd (默认: none)");
+    tracing::info!("   COMPACTION_COMPRESSION - compaction输出的SSTable的通用压缩 none/lz4/zstd (默认: none)");
+    tracing::info!("   SSTABLE_ENCODING  - 新写入的SSTable中块的序列化方式 bincode/tagged (默认: bincode)");
+    tracing::info!("   MAX_MAPPED_SSTABLES - 同时保持内存映射的SSTable数量上限 (默认: 1024)");
+    tracing::info!("   RECORD_INGEST_TIME - 为每个数据点记录写入时间 true/false (默认: false)");
+    tracing::info!("   EXTRA_DATA_DIRS   - 其他数据目录，逗号分隔；新的SSTable写入可用空间最多的目录 (默认: 无)");
+    tracing::info!("   MAX_QUERY_MEMORY_BYTES - 单次查询结果的内存上限，超过返回413 (默认: 不限制)");
+    tracing::info!("   QUERY_MEMORY_BUDGET_BYTES - 并发查询的内存总预算，不足时排队 (默认: 不限制)");
+    tracing::info!("   SENSITIVE_TAG_KEYS - 响应中隐藏值的标签键，逗号分隔 (默认: 无)");
+    tracing::info!("   UNREDACTED_API_KEYS - 可以看到敏感标签原始值的API key，逗号分隔 (默认: 无)");
+    tracing::info!("   TENANT_API_KEYS - 绑定到系列键前缀的租户API key，如 key1=tenant42.,key2=tenant7. (默认: 无)");
     tracing::info!("   RUST_LOG          - 日志级别 (默认: timeseries_db=info)");
 }
 
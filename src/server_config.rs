@@ -0,0 +1,445 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::db::{BlockCompression, DbConfig, SSTableEncoding, TagDriftMode, TimePrecision, TimeSeriesDB, TimeSeriesDBBuilder};
+
+// 指定配置文件路径的环境变量，命令行参数 --config 优先
+pub const CONFIG_PATH_ENV: &str = "TSDB_CONFIG";
+
+// 配置项及对应的环境变量。配置文件中的键与配置项同名，环境变量覆盖配置文件中的值
+const CONFIG_KEYS: &[(&str, &str)] = &[
+    ("data_dir", "DATA_DIR"),
+    ("port", "PORT"),
+    ("memtable_threshold", "MEMTABLE_THRESHOLD"),
+    ("max_memtable_age_secs", "MAX_MEMTABLE_AGE_SECS"),
+    ("idle_flush_secs", "IDLE_FLUSH_SECS"),
+    ("min_flush_interval_ms", "MIN_FLUSH_INTERVAL_MS"),
+    ("memtable_hard_limit", "MEMTABLE_HARD_LIMIT"),
+    ("disk_low_watermark_bytes", "DISK_LOW_WATERMARK_BYTES"),
+    ("disk_critical_watermark_bytes", "DISK_CRITICAL_WATERMARK_BYTES"),
+    ("timestamp_precision", "TIMESTAMP_PRECISION"),
+    ("tag_drift_mode", "TAG_DRIFT_MODE"),
+    ("retention_check_secs", "RETENTION_CHECK_SECS"),
+    ("max_total_bytes", "MAX_TOTAL_BYTES"),
+    ("hot_window_secs", "HOT_WINDOW_SECS"),
+    ("max_future_skew_secs", "MAX_FUTURE_SKEW_SECS"),
+    ("strict_validation", "STRICT_VALIDATION"),
+    ("default_query_range_secs", "DEFAULT_QUERY_RANGE_SECS"),
+    ("compaction_deleted_ratio", "COMPACTION_DELETED_RATIO"),
+    ("max_request_body_bytes", "MAX_REQUEST_BODY_BYTES"),
+    ("warmup_series", "WARMUP_SERIES"),
+    ("blocking_threads", "BLOCKING_THREADS"),
+    ("inline_query_points", "INLINE_QUERY_POINTS"),
+    ("decode_scratch_points", "DECODE_SCRATCH_POINTS"),
+    ("change_feed_capacity", "CHANGE_FEED_CAPACITY"),
+    ("flush_compression", "FLUSH_COMPRESSION"),
+    ("compaction_compression", "COMPACTION_COMPRESSION"),
+    ("sstable_encoding", "SSTABLE_ENCODING"),
+    ("max_mapped_sstables", "MAX_MAPPED_SSTABLES"),
+    ("record_ingest_time", "RECORD_INGEST_TIME"),
+    ("extra_data_dirs", "EXTRA_DATA_DIRS"),
+    ("max_query_memory_bytes", "MAX_QUERY_MEMORY_BYTES"),
+    ("query_memory_budget_bytes", "QUERY_MEMORY_BUDGET_BYTES"),
+    ("sensitive_tag_keys", "SENSITIVE_TAG_KEYS"),
+    ("unredacted_api_keys", "UNREDACTED_API_KEYS"),
+    ("tenant_api_keys", "TENANT_API_KEYS"),
+];
+
+// 服务配置：配置文件（TOML）中的值被环境变量覆盖，都未指定的项使用引擎构建器的默认值
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Config {
+    pub data_dir: PathBuf,
+    pub port: u16,
+    pub memtable_threshold: usize,
+    pub max_memtable_age_secs: Option<u64>,
+    pub idle_flush_secs: Option<u64>,
+    pub min_flush_interval_ms: Option<u64>,
+    pub memtable_hard_limit: Option<usize>,
+    pub disk_low_watermark_bytes: Option<u64>,
+    // 只指定低水位线时为低水位线的一半
+    pub disk_critical_watermark_bytes: Option<u64>,
+    pub timestamp_precision: TimePrecision,
+    pub tag_drift_mode: TagDriftMode,
+    pub retention_check_secs: u64,
+    pub max_total_bytes: Option<u64>,
+    pub hot_window_secs: Option<u64>,
+    pub max_future_skew_secs: Option<u64>,
+    pub strict_validation: bool,
+    pub default_query_range_secs: Option<u64>,
+    pub compaction_deleted_ratio: Option<f64>,
+    pub max_request_body_bytes: usize,
+    // 未指定时沿用数据目录中保存的列表
+    pub warmup_series: Option<Vec<String>>,
+    pub blocking_threads: usize,
+    pub inline_query_points: usize,
+    pub decode_scratch_points: usize,
+    pub change_feed_capacity: usize,
+    pub flush_compression: BlockCompression,
+    pub compaction_compression: BlockCompression,
+    pub sstable_encoding: SSTableEncoding,
+    pub max_mapped_sstables: usize,
+    pub record_ingest_time: bool,
+    // 主数据目录之外的数据目录，新的SSTable写入可用空间最多的目录
+    pub extra_data_dirs: Vec<PathBuf>,
+    pub max_query_memory_bytes: Option<usize>,
+    pub query_memory_budget_bytes: Option<usize>,
+    pub sensitive_tag_keys: Vec<String>,
+    // 具有 unredacted 权限的API key，/api/v1/admin/config 中不显示
+    pub unredacted_api_keys: Vec<String>,
+    // 租户API key到系列键前缀，/api/v1/admin/config 中不显示key
+    pub tenant_api_keys: BTreeMap<String, String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let db = DbConfig::default();
+        Self {
+            data_dir: PathBuf::from("./tsdb_data"),
+            port: 6364,
+            memtable_threshold: db.memtable_threshold,
+            max_memtable_age_secs: db.max_memtable_age.map(|age| age.as_secs()),
+            idle_flush_secs: db.idle_flush_secs,
+            min_flush_interval_ms: db.min_flush_interval.map(|interval| interval.as_millis() as u64),
+            memtable_hard_limit: db.memtable_hard_limit,
+            disk_low_watermark_bytes: db.disk_low_watermark_bytes,
+            disk_critical_watermark_bytes: db.disk_critical_watermark_bytes,
+            timestamp_precision: db.timestamp_precision,
+            tag_drift_mode: db.tag_drift_mode,
+            retention_check_secs: 3600,
+            max_total_bytes: db.max_total_bytes,
+            hot_window_secs: db.hot_window_secs,
+            max_future_skew_secs: db.max_future_skew_secs,
+            strict_validation: db.strict_validation,
+            default_query_range_secs: db.default_query_range_secs,
+            compaction_deleted_ratio: db.compaction_deleted_ratio,
+            max_request_body_bytes: db.max_request_body_bytes,
+            warmup_series: db.warmup_series,
+            blocking_threads: db.blocking_threads,
+            inline_query_points: db.inline_query_points,
+            decode_scratch_points: db.decode_scratch_points,
+            change_feed_capacity: db.change_feed_capacity,
+            flush_compression: db.flush_compression,
+            compaction_compression: db.compaction_compression,
+            sstable_encoding: db.sstable_encoding,
+            max_mapped_sstables: db.max_mapped_sstables,
+            record_ingest_time: db.record_ingest_time,
+            extra_data_dirs: db.extra_data_dirs,
+            max_query_memory_bytes: db.max_query_memory_bytes,
+            query_memory_budget_bytes: db.query_memory_budget_bytes,
+            sensitive_tag_keys: db.sensitive_tag_keys,
+            unredacted_api_keys: db.unredacted_api_keys,
+            tenant_api_keys: db.tenant_api_keys,
+        }
+    }
+}
+
+// 配置加载或校验失败，列出所有有问题的配置项
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigError {
+    pub errors: Vec<String>,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "配置无效（{} 项）:", self.errors.len())?;
+        for error in &self.errors {
+            write!(f, "\n  - {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+// 配置项的原始值：配置文件中的TOML值或环境变量的字符串
+enum RawValue {
+    Toml(toml::Value),
+    Env(String),
+}
+
+impl RawValue {
+    fn parse<T>(self) -> Result<T, String>
+    where
+        T: FromStr + DeserializeOwned,
+        T::Err: fmt::Display,
+    {
+        match self {
+            Self::Toml(value) => T::deserialize(value).map_err(|e| e.to_string()),
+            Self::Env(value) => value.trim().parse().map_err(|e: T::Err| e.to_string()),
+        }
+    }
+
+    // 环境变量中的列表以逗号分隔
+    fn list(self) -> Result<Vec<String>, String> {
+        match self {
+            Self::Toml(value) => Vec::deserialize(value).map_err(|e| e.to_string()),
+            Self::Env(value) => Ok(value
+                .split(',')
+                .map(|item| item.trim().to_string())
+                .filter(|item| !item.is_empty())
+                .collect()),
+        }
+    }
+
+    // 环境变量中的映射为逗号分隔的 key=value
+    fn map(self) -> Result<BTreeMap<String, String>, String> {
+        match self {
+            Self::Toml(value) => BTreeMap::deserialize(value).map_err(|e| e.to_string()),
+            Self::Env(value) => value
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(|item| match item.split_once('=') {
+                    Some((key, value)) => Ok((key.trim().to_string(), value.trim().to_string())),
+                    None => Err(format!("{:?} 不是 key=value 格式", item)),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl Config {
+    // 配置文件路径：命令行参数 --config <path>（或 --config=<path>），其次是 TSDB_CONFIG 环境变量
+    pub fn path_from_args(args: impl IntoIterator<Item = String>, env: impl Fn(&str) -> Option<String>) -> Option<PathBuf> {
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if arg == "--config" {
+                return args.next().map(PathBuf::from);
+            }
+            if let Some(path) = arg.strip_prefix("--config=") {
+                return Some(PathBuf::from(path));
+            }
+        }
+        env(CONFIG_PATH_ENV).map(PathBuf::from)
+    }
+
+    // 从进程的命令行参数和环境变量加载配置
+    pub fn from_env() -> Result<Self, ConfigError> {
+        let env = |name: &str| std::env::var(name).ok();
+        let path = Self::path_from_args(std::env::args().skip(1), env);
+        Self::load(path.as_deref(), env)
+    }
+
+    // 依次合并配置文件和环境变量，最后整体校验。所有错误一起返回
+    pub fn load(path: Option<&Path>, env: impl Fn(&str) -> Option<String>) -> Result<Self, ConfigError> {
+        let mut config = Self::default();
+        let mut errors = Vec::new();
+
+        if let Some(path) = path {
+            match std::fs::read_to_string(path).map_err(|e| e.to_string()).and_then(|text| {
+                text.parse::<toml::Table>().map_err(|e| e.message().to_string())
+            }) {
+                Ok(table) => {
+                    for (key, value) in table {
+                        if let Err(e) = config.set(&key, RawValue::Toml(value)) {
+                            errors.push(format!("配置文件 {}: {}", key, e));
+                        }
+                    }
+                }
+                Err(e) => errors.push(format!("读取配置文件 {} 失败: {}", path.display(), e)),
+            }
+        }
+
+        for (key, name) in CONFIG_KEYS {
+            if let Some(value) = env(name) {
+                if let Err(e) = config.set(key, RawValue::Env(value)) {
+                    errors.push(format!("环境变量 {}: {}", name, e));
+                }
+            }
+        }
+
+        errors.extend(config.validate());
+        if errors.is_empty() {
+            Ok(config)
+        } else {
+            Err(ConfigError { errors })
+        }
+    }
+
+    fn set(&mut self, key: &str, value: RawValue) -> Result<(), String> {
+        match key {
+            "data_dir" => self.data_dir = value.parse()?,
+            "port" => self.port = value.parse()?,
+            "memtable_threshold" => self.memtable_threshold = value.parse()?,
+            "max_memtable_age_secs" => self.max_memtable_age_secs = Some(value.parse()?),
+            "idle_flush_secs" => self.idle_flush_secs = Some(value.parse()?),
+            "min_flush_interval_ms" => self.min_flush_interval_ms = Some(value.parse()?),
+            "memtable_hard_limit" => self.memtable_hard_limit = Some(value.parse()?),
+            "disk_low_watermark_bytes" => self.disk_low_watermark_bytes = Some(value.parse()?),
+            "disk_critical_watermark_bytes" => self.disk_critical_watermark_bytes = Some(value.parse()?),
+            "timestamp_precision" => self.timestamp_precision = value.parse()?,
+            "tag_drift_mode" => self.tag_drift_mode = value.parse()?,
+            "retention_check_secs" => self.retention_check_secs = value.parse()?,
+            "max_total_bytes" => self.max_total_bytes = Some(value.parse()?),
+            "hot_window_secs" => self.hot_window_secs = Some(value.parse()?),
+            "max_future_skew_secs" => self.max_future_skew_secs = Some(value.parse()?),
+            "strict_validation" => self.strict_validation = value.parse()?,
+            "default_query_range_secs" => self.default_query_range_secs = Some(value.parse()?),
+            "compaction_deleted_ratio" => self.compaction_deleted_ratio = Some(value.parse()?),
+            "max_request_body_bytes" => self.max_request_body_bytes = value.parse()?,
+            "warmup_series" => self.warmup_series = Some(value.list()?),
+            "blocking_threads" => self.blocking_threads = value.parse()?,
+            "inline_query_points" => self.inline_query_points = value.parse()?,
+            "decode_scratch_points" => self.decode_scratch_points = value.parse()?,
+            "change_feed_capacity" => self.change_feed_capacity = value.parse()?,
+            "flush_compression" => self.flush_compression = value.parse()?,
+            "compaction_compression" => self.compaction_compression = value.parse()?,
+            "sstable_encoding" => self.sstable_encoding = value.parse()?,
+            "max_mapped_sstables" => self.max_mapped_sstables = value.parse()?,
+            "record_ingest_time" => self.record_ingest_time = value.parse()?,
+            "extra_data_dirs" => self.extra_data_dirs = value.list()?.into_iter().map(PathBuf::from).collect(),
+            "max_query_memory_bytes" => self.max_query_memory_bytes = Some(value.parse()?),
+            "query_memory_budget_bytes" => self.query_memory_budget_bytes = Some(value.parse()?),
+            "sensitive_tag_keys" => self.sensitive_tag_keys = value.list()?,
+            "unredacted_api_keys" => self.unredacted_api_keys = value.list()?,
+            "tenant_api_keys" => self.tenant_api_keys = value.map()?,
+            _ => return Err("未知的配置项".to_string()),
+        }
+        Ok(())
+    }
+
+    // 检查取值之间的约束，返回所有不满足的项
+    pub fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        let positive = [
+            ("memtable_threshold", self.memtable_threshold as u64),
+            ("retention_check_secs", self.retention_check_secs),
+            ("max_request_body_bytes", self.max_request_body_bytes as u64),
+            ("blocking_threads", self.blocking_threads as u64),
+            ("change_feed_capacity", self.change_feed_capacity as u64),
+            ("max_mapped_sstables", self.max_mapped_sstables as u64),
+        ];
+        for (key, value) in positive {
+            if value == 0 {
+                errors.push(format!("{}: 必须大于0", key));
+            }
+        }
+        for (key, value) in [
+            ("max_memtable_age_secs", self.max_memtable_age_secs),
+            ("idle_flush_secs", self.idle_flush_secs),
+            ("max_query_memory_bytes", self.max_query_memory_bytes.map(|bytes| bytes as u64)),
+            ("query_memory_budget_bytes", self.query_memory_budget_bytes.map(|bytes| bytes as u64)),
+            ("default_query_range_secs", self.default_query_range_secs),
+        ] {
+            if value == Some(0) {
+                errors.push(format!("{}: 必须大于0", key));
+            }
+        }
+        if self.data_dir.as_os_str().is_empty() {
+            errors.push("data_dir: 不能为空".to_string());
+        }
+        for (index, dir) in self.extra_data_dirs.iter().enumerate() {
+            if dir.as_os_str().is_empty() || *dir == self.data_dir || self.extra_data_dirs[..index].contains(dir) {
+                errors.push(format!("extra_data_dirs: {:?} 为空或与其他数据目录重复", dir));
+            }
+        }
+        match (self.disk_low_watermark_bytes, self.disk_critical_watermark_bytes) {
+            (None, Some(_)) => errors.push("disk_critical_watermark_bytes: 需要同时指定 disk_low_watermark_bytes".to_string()),
+            (Some(low), Some(critical)) if critical > low => errors.push(format!(
+                "disk_critical_watermark_bytes: 临界水位线 {} 不能高于低水位线 {}",
+                critical, low
+            )),
+            _ => {}
+        }
+        if let Some(ratio) = self.compaction_deleted_ratio {
+            if !(ratio > 0.0 && ratio <= 1.0) {
+                errors.push(format!("compaction_deleted_ratio: {} 必须大于0且不超过1", ratio));
+            }
+        }
+        if let Some(limit) = self.memtable_hard_limit {
+            if limit < self.memtable_threshold {
+                errors.push(format!("memtable_hard_limit: {} 不能小于 memtable_threshold {}", limit, self.memtable_threshold));
+            }
+        }
+        for (key, prefix) in &self.tenant_api_keys {
+            if key.is_empty() || prefix.is_empty() {
+                errors.push("tenant_api_keys: API key和前缀都不能为空".to_string());
+                break;
+            }
+        }
+        // 一个前缀是另一个前缀的开头时（例如 t1 和 t10），前者的租户能访问后者的系列。
+        // 排序后这样的前缀对中间的前缀都以前者开头，只需检查相邻的前缀
+        let prefixes: BTreeSet<&str> = self.tenant_api_keys.values().map(String::as_str).collect();
+        let prefixes: Vec<&str> = prefixes.into_iter().collect();
+        for pair in prefixes.windows(2) {
+            if !pair[0].is_empty() && pair[1].starts_with(pair[0]) {
+                errors.push(format!("tenant_api_keys: 前缀 {} 是前缀 {} 的开头，租户之间的系列不能隔离", pair[0], pair[1]));
+            }
+        }
+        if let (Some(max), Some(budget)) = (self.max_query_memory_bytes, self.query_memory_budget_bytes) {
+            if max > budget {
+                errors.push(format!("max_query_memory_bytes: {} 不能大于 query_memory_budget_bytes {}", max, budget));
+            }
+        }
+        errors
+    }
+
+    // 按配置创建引擎构建器
+    pub fn builder(&self) -> TimeSeriesDBBuilder {
+        let mut builder = TimeSeriesDB::builder(&self.data_dir)
+            .memtable_threshold(self.memtable_threshold)
+            .timestamp_precision(self.timestamp_precision)
+            .tag_drift_mode(self.tag_drift_mode)
+            .max_request_body_bytes(self.max_request_body_bytes)
+            .blocking_threads(self.blocking_threads)
+            .inline_query_points(self.inline_query_points)
+            .decode_scratch_points(self.decode_scratch_points)
+            .change_feed_capacity(self.change_feed_capacity)
+            .flush_compression(self.flush_compression)
+            .compaction_compression(self.compaction_compression)
+            .sstable_encoding(self.sstable_encoding)
+            .max_mapped_sstables(self.max_mapped_sstables)
+            .record_ingest_time(self.record_ingest_time)
+            .strict_validation(self.strict_validation)
+            .extra_data_dirs(self.extra_data_dirs.clone())
+            .sensitive_tag_keys(self.sensitive_tag_keys.clone())
+            .unredacted_api_keys(self.unredacted_api_keys.clone())
+            .tenant_api_keys(self.tenant_api_keys.clone());
+        if let Some(secs) = self.max_memtable_age_secs {
+            builder = builder.max_memtable_age(Duration::from_secs(secs));
+        }
+        if let Some(secs) = self.idle_flush_secs {
+            builder = builder.idle_flush_secs(secs);
+        }
+        if let Some(ms) = self.min_flush_interval_ms {
+            builder = builder.min_flush_interval(Duration::from_millis(ms));
+        }
+        if let Some(points) = self.memtable_hard_limit {
+            builder = builder.memtable_hard_limit(points);
+        }
+        if let Some(low) = self.disk_low_watermark_bytes {
+            builder = builder.disk_watermarks(low, self.disk_critical_watermark_bytes.unwrap_or(low / 2));
+        }
+        if let Some(secs) = self.hot_window_secs {
+            builder = builder.hot_window_secs(secs);
+        }
+        if let Some(secs) = self.max_future_skew_secs {
+            builder = builder.max_future_skew_secs(secs);
+        }
+        if let Some(secs) = self.default_query_range_secs {
+            builder = builder.default_query_range_secs(secs);
+        }
+        if let Some(ratio) = self.compaction_deleted_ratio {
+            builder = builder.compaction_deleted_ratio(ratio);
+        }
+        if let Some(bytes) = self.max_total_bytes {
+            builder = builder.max_total_bytes(bytes);
+        }
+        if let Some(bytes) = self.max_query_memory_bytes {
+            builder = builder.max_query_memory_bytes(bytes);
+        }
+        if let Some(bytes) = self.query_memory_budget_bytes {
+            builder = builder.query_memory_budget_bytes(bytes);
+        }
+        if let Some(patterns) = &self.warmup_series {
+            builder = builder.warmup_series(patterns.clone());
+        }
+        builder
+    }
+}
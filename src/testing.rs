@@ -0,0 +1,414 @@
+//! 集成测试工具（testing 特性）：在进程内启动完整的HTTP服务，并提供确定性的测试数据
+
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::io::{Error, ErrorKind, Result};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
+
+use axum::http::StatusCode;
+use parking_lot::Mutex;
+use tempfile::TempDir;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+use crate::{api, Config, DataPoint, EngineObserver, TimeSeriesDB, TimeSeriesDBBuilder};
+
+// 运行在临时目录和随机端口上的测试服务，stop 或丢弃时临时目录被删除
+pub struct TestServer {
+    // 形如 http://127.0.0.1:12345，不带末尾的 '/'
+    pub base_url: String,
+    // 与服务共用的引擎，可以绕过HTTP直接读写
+    pub db_handle: TimeSeriesDB,
+    // 取消后服务停止接受新连接
+    pub shutdown: CancellationToken,
+    addr: SocketAddr,
+    server: JoinHandle<Result<()>>,
+    data_dir: TempDir,
+}
+
+// 以默认配置启动测试服务
+pub async fn spawn_test_server() -> Result<TestServer> {
+    spawn_test_server_with(|builder| builder).await
+}
+
+// 以自定义配置启动测试服务，configure 收到的构建器已指向临时数据目录
+pub async fn spawn_test_server_with<F>(configure: F) -> Result<TestServer>
+where
+    F: FnOnce(TimeSeriesDBBuilder) -> TimeSeriesDBBuilder,
+{
+    let data_dir = TempDir::new()?;
+    let db = configure(TimeSeriesDB::builder(data_dir.path())).build()?;
+    let app = api::router(db.clone());
+    serve(db, app, data_dir).await
+}
+
+// 按服务配置启动测试服务，与正式服务一样提供 /api/v1/admin/config。
+// 配置中的数据目录和端口被替换为临时目录和随机端口
+pub async fn spawn_test_server_with_config(config: Config) -> Result<TestServer> {
+    let data_dir = TempDir::new()?;
+    let config = Config {
+        data_dir: data_dir.path().to_path_buf(),
+        port: 0,
+        ..config
+    };
+    let db = config.builder().build()?;
+    let app = api::router_with_config(db.clone(), config);
+    serve(db, app, data_dir).await
+}
+
+async fn serve(db: TimeSeriesDB, app: axum::Router, data_dir: TempDir) -> Result<TestServer> {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+
+    let shutdown = CancellationToken::new();
+    let token = shutdown.clone();
+    let feed = db.clone();
+    let server = tokio::spawn(async move {
+        axum::serve(listener, app)
+            .with_graceful_shutdown(async move {
+                token.cancelled().await;
+                feed.close_change_feed();
+            })
+            .await
+    });
+
+    Ok(TestServer {
+        base_url: format!("http://{}", addr),
+        db_handle: db,
+        shutdown,
+        addr,
+        server,
+        data_dir,
+    })
+}
+
+impl TestServer {
+    // 服务使用的临时数据目录
+    pub fn data_dir(&self) -> &Path {
+        self.data_dir.path()
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    pub async fn get(&self, path: &str) -> Result<TestResponse> {
+        self.request("GET", path, &[], &[]).await
+    }
+
+    pub async fn delete(&self, path: &str) -> Result<TestResponse> {
+        self.request("DELETE", path, &[], &[]).await
+    }
+
+    pub async fn post_json(&self, path: &str, body: &serde_json::Value) -> Result<TestResponse> {
+        self.request("POST", path, &[("Content-Type", "application/json")], body.to_string().as_bytes()).await
+    }
+
+    pub async fn put_json(&self, path: &str, body: &serde_json::Value) -> Result<TestResponse> {
+        self.request("PUT", path, &[("Content-Type", "application/json")], body.to_string().as_bytes()).await
+    }
+
+    pub async fn patch_json(&self, path: &str, body: &serde_json::Value) -> Result<TestResponse> {
+        self.request("PATCH", path, &[("Content-Type", "application/json")], body.to_string().as_bytes()).await
+    }
+
+    // 发送一个HTTP/1.1请求并读取完整响应，path 需要自行做URL编码
+    pub async fn request(&self, method: &str, path: &str, headers: &[(&str, &str)], body: &[u8]) -> Result<TestResponse> {
+        let mut head = format!("{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n", method, path, self.addr);
+        for (name, value) in headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        // 指定 Transfer-Encoding 时 body 为已编码的分块内容
+        if !headers
+            .iter()
+            .any(|(name, _)| name.eq_ignore_ascii_case("content-length") || name.eq_ignore_ascii_case("transfer-encoding"))
+        {
+            head.push_str(&format!("Content-Length: {}\r\n", body.len()));
+        }
+        head.push_str("\r\n");
+
+        let mut stream = tokio::net::TcpStream::connect(self.addr).await?;
+        stream.write_all(head.as_bytes()).await?;
+        stream.write_all(body).await?;
+        let mut raw = Vec::new();
+        stream.read_to_end(&mut raw).await?;
+        TestResponse::parse(&raw)
+    }
+
+    // 按 chunk_size 分块传输请求体（Transfer-Encoding: chunked），不声明 Content-Length
+    pub async fn request_chunked(
+        &self,
+        method: &str,
+        path: &str,
+        headers: &[(&str, &str)],
+        body: &[u8],
+        chunk_size: usize,
+    ) -> Result<TestResponse> {
+        let mut encoded = Vec::new();
+        for chunk in body.chunks(chunk_size.max(1)) {
+            encoded.extend_from_slice(format!("{:x}\r\n", chunk.len()).as_bytes());
+            encoded.extend_from_slice(chunk);
+            encoded.extend_from_slice(b"\r\n");
+        }
+        encoded.extend_from_slice(b"0\r\n\r\n");
+        let headers: Vec<(&str, &str)> = headers.iter().copied().chain([("Transfer-Encoding", "chunked")]).collect();
+        self.request(method, path, &headers, &encoded).await
+    }
+
+    // 停止服务并关闭引擎（刷新内存表），然后删除临时目录
+    pub async fn stop(self) -> Result<()> {
+        self.stop_keeping_data().await.map(drop)
+    }
+
+    // 停止服务并关闭引擎，返回保留下来的临时目录，用于重新打开数据库检查持久化的内容
+    pub async fn stop_keeping_data(self) -> Result<TempDir> {
+        self.shutdown.cancel();
+        self.server.await.map_err(Error::other)??;
+        self.db_handle.close().await?;
+        Ok(self.data_dir)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TestResponse {
+    pub status: StatusCode,
+    // 响应头名称均为小写
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl TestResponse {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn json(&self) -> Result<serde_json::Value> {
+        serde_json::from_slice(&self.body).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+
+    fn parse(raw: &[u8]) -> Result<Self> {
+        let invalid = |message: &str| Error::new(ErrorKind::InvalidData, format!("无法解析HTTP响应: {}", message));
+        let split = raw
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .ok_or_else(|| invalid("缺少响应头结束标记"))?;
+        let head = std::str::from_utf8(&raw[..split]).map_err(|_| invalid("响应头不是UTF-8"))?;
+        let mut lines = head.split("\r\n");
+        let status = lines
+            .next()
+            .and_then(|line| line.split(' ').nth(1))
+            .and_then(|code| code.parse::<u16>().ok())
+            .and_then(|code| StatusCode::from_u16(code).ok())
+            .ok_or_else(|| invalid("状态行无效"))?;
+        let headers: Vec<(String, String)> = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_string()))
+            .collect();
+
+        let mut response = Self { status, headers, body: raw[split + 4..].to_vec() };
+        if response.header("transfer-encoding").is_some_and(|value| value.eq_ignore_ascii_case("chunked")) {
+            response.body = decode_chunked(&response.body).ok_or_else(|| invalid("分块编码无效"))?;
+        }
+        Ok(response)
+    }
+}
+
+fn decode_chunked(mut data: &[u8]) -> Option<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let line_end = data.windows(2).position(|window| window == b"\r\n")?;
+        let size_line = std::str::from_utf8(&data[..line_end]).ok()?;
+        let size = usize::from_str_radix(size_line.split(';').next()?.trim(), 16).ok()?;
+        data = &data[line_end + 2..];
+        if size == 0 {
+            return Some(body);
+        }
+        body.extend_from_slice(data.get(..size)?);
+        data = data.get(size + 2..)?;
+    }
+}
+
+// 等待后台任务的间隔
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+// 反复执行 check 直到返回 Some，用于等待后台任务推进。超过 timeout 时返回 TimedOut，
+// 条件永远不满足时测试失败而不是挂起
+pub async fn wait_until<T, F, Fut>(timeout: Duration, mut check: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<Option<T>>>,
+{
+    let poll = async {
+        loop {
+            if let Some(value) = check().await? {
+                return Ok(value);
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    };
+    tokio::time::timeout(timeout, poll)
+        .await
+        .map_err(|_| Error::new(ErrorKind::TimedOut, format!("等待 {:?} 后条件仍未满足", timeout)))?
+}
+
+// 第一个压缩块开始解压时通知测试，并阻塞解压线程直到测试放行，之后的块不再阻塞。
+// 用于在查询进行中（占住阻塞线程时）检查引擎的行为
+#[derive(Debug)]
+pub struct QueryBlockGate {
+    entered: Mutex<Option<oneshot::Sender<()>>>,
+    release: Mutex<Option<mpsc::Receiver<()>>>,
+}
+
+// 测试一侧的控制端
+#[derive(Debug)]
+pub struct GateControl {
+    entered: Option<oneshot::Receiver<()>>,
+    release: mpsc::Sender<()>,
+}
+
+impl QueryBlockGate {
+    // 返回注册到引擎的观察者和测试使用的控制端
+    pub fn new() -> (Arc<Self>, GateControl) {
+        let (entered, entered_rx) = oneshot::channel();
+        let (release, release_rx) = mpsc::channel();
+        let gate = Arc::new(Self { entered: Mutex::new(Some(entered)), release: Mutex::new(Some(release_rx)) });
+        (gate, GateControl { entered: Some(entered_rx), release })
+    }
+}
+
+impl EngineObserver for QueryBlockGate {
+    fn on_query_block(&self, _series_key: &str) {
+        if let Some(entered) = self.entered.lock().take() {
+            let _ = entered.send(());
+        }
+        let release = self.release.lock().take();
+        if let Some(release) = release {
+            let _ = release.recv();
+        }
+    }
+}
+
+impl GateControl {
+    // 等待第一个块开始解压，超过 timeout 时返回 TimedOut
+    pub async fn entered(&mut self, timeout: Duration) -> Result<()> {
+        let entered = self.entered.take().ok_or_else(|| Error::other("已经等待过第一个块"))?;
+        tokio::time::timeout(timeout, entered)
+            .await
+            .map_err(|_| Error::new(ErrorKind::TimedOut, format!("{:?} 内没有开始解压", timeout)))?
+            .map_err(Error::other)
+    }
+
+    // 放行被阻塞的解压线程
+    pub fn release(&self) -> Result<()> {
+        self.release.send(()).map_err(Error::other)
+    }
+}
+
+// 模拟传感器：按固定间隔产生带日周期和噪声的确定性数值
+#[derive(Debug, Clone)]
+pub struct SensorConfig {
+    pub name: String,
+    pub sensor_type: String,
+    pub location: String,
+    pub base_value: f64,
+    pub variation: f64,
+    pub interval_seconds: u64,
+}
+
+impl SensorConfig {
+    pub fn new(name: &str, sensor_type: &str, location: &str, base_value: f64, variation: f64, interval_seconds: u64) -> Self {
+        Self {
+            name: name.to_string(),
+            sensor_type: sensor_type.to_string(),
+            location: location.to_string(),
+            base_value,
+            variation,
+            interval_seconds,
+        }
+    }
+
+    // 系列键为 sensor_type.name
+    pub fn series_key(&self) -> String {
+        format!("{}.{}", self.sensor_type, self.name)
+    }
+
+    pub fn tags(&self) -> BTreeMap<String, String> {
+        let mut tags = BTreeMap::new();
+        tags.insert("sensor_type".to_string(), self.sensor_type.clone());
+        tags.insert("location".to_string(), self.location.clone());
+        tags.insert("device_name".to_string(), self.name.clone());
+        tags
+    }
+
+    // time_offset 为距起始时间的秒数
+    pub fn value_at(&self, time_offset: u64) -> f64 {
+        let time_factor = (time_offset as f64 / 86400.0) * std::f64::consts::PI * 2.0;
+        let noise = (time_offset as f64 * 0.1).sin() * 0.1;
+        self.base_value + time_factor.sin() * self.variation + noise * self.variation * 0.3
+    }
+
+    // 从 start_timestamp（秒）开始的 count 个数据点
+    pub fn datapoints(&self, start_timestamp: u64, count: usize) -> Vec<DataPoint> {
+        let tags = self.tags();
+        (0..count as u64)
+            .map(|i| {
+                let offset = i * self.interval_seconds;
+                DataPoint {
+                    timestamp: start_timestamp + offset,
+                    value: self.value_at(offset),
+                    tags: tags.clone(),
+                }
+            })
+            .collect()
+    }
+}
+
+// 与 examples/batch_insert.rs 相同的一组传感器
+pub fn default_sensors() -> Vec<SensorConfig> {
+    vec![
+        SensorConfig::new("temp_001", "temperature", "server_room_a", 22.0, 5.0, 60),
+        SensorConfig::new("temp_002", "temperature", "server_room_b", 24.0, 4.0, 60),
+        SensorConfig::new("temp_003", "temperature", "server_room_c", 23.0, 3.5, 60),
+        SensorConfig::new("humi_001", "humidity", "server_room_a", 45.0, 15.0, 120),
+        SensorConfig::new("humi_002", "humidity", "server_room_b", 50.0, 12.0, 120),
+        SensorConfig::new("pres_001", "pressure", "server_room_a", 1013.25, 50.0, 300),
+        SensorConfig::new("cpu_001", "cpu_usage", "server_001", 45.0, 30.0, 30),
+        SensorConfig::new("cpu_002", "cpu_usage", "server_002", 35.0, 25.0, 30),
+        SensorConfig::new("mem_001", "memory_usage", "server_001", 65.0, 20.0, 30),
+        SensorConfig::new("mem_002", "memory_usage", "server_002", 70.0, 18.0, 30),
+    ]
+}
+
+// 由种子决定的伪随机序列（随机游走），同一组参数每次生成相同的数据，不带标签
+pub fn deterministic_series(seed: u64, start_timestamp: u64, interval: u64, count: usize) -> Vec<DataPoint> {
+    let mut state = seed ^ 0x9e3779b97f4a7c15;
+    let mut value = 0.0;
+    (0..count as u64)
+        .map(|i| {
+            // splitmix64
+            state = state.wrapping_add(0x9e3779b97f4a7c15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+            z ^= z >> 31;
+            value += (z >> 11) as f64 / (1u64 << 53) as f64 - 0.5;
+            DataPoint {
+                timestamp: start_timestamp + i * interval,
+                value,
+                tags: BTreeMap::new(),
+            }
+        })
+        .collect()
+}